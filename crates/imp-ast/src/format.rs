@@ -0,0 +1,90 @@
+use crate::{Atom, Call, Comment, Program};
+use std::fmt::Write as _;
+
+/// `program` in canonical style: one call per line as
+/// `#call @anno target key=value;` with single spaces, arguments in source
+/// order, strings always quoted and numbers as `f64` displays them.
+/// Comments keep their place: on their own line, after the call they
+/// trailed, or, when written inside a call, on the lines before it. Runs of
+/// blank lines shrink to one; the text ends with a newline unless empty.
+#[must_use]
+pub fn format_program(program: &Program) -> String {
+    let mut out = String::new();
+    let mut comments = program.comments.iter().peekable();
+    let mut last_line = None;
+    for call in &program.calls {
+        while let Some(comment) = comments.next_if(|comment| comment.span.start < call.span.start) {
+            push_comment(&mut out, &mut last_line, comment);
+        }
+        blank_line(&mut out, last_line, call.span.line);
+        while let Some(comment) = comments.next_if(|comment| comment.span.start < call.span.end) {
+            let _ = writeln!(out, "//{}", comment.text);
+        }
+        write_call(&mut out, call);
+        out.push('\n');
+        last_line = Some(call.span.end_line);
+    }
+    for comment in comments {
+        push_comment(&mut out, &mut last_line, comment);
+    }
+    out
+}
+
+/// A comment between calls: on the end of the previous line when it shared
+/// that line, else on its own.
+fn push_comment(out: &mut String, last_line: &mut Option<usize>, comment: &Comment) {
+    if *last_line == Some(comment.span.line) {
+        out.pop();
+        let _ = writeln!(out, " //{}", comment.text);
+        return;
+    }
+    blank_line(out, *last_line, comment.span.line);
+    let _ = writeln!(out, "//{}", comment.text);
+    *last_line = Some(comment.span.line);
+}
+
+/// Keeps one blank line where the source had any between two items.
+fn blank_line(out: &mut String, last_line: Option<usize>, line: usize) {
+    if last_line.is_some_and(|last| line > last + 1) {
+        out.push('\n');
+    }
+}
+
+fn write_call(out: &mut String, call: &Call) {
+    out.push_str("#call");
+    for anno in &call.annos {
+        let _ = write!(out, " @{anno}");
+    }
+    let _ = write!(out, " {}", call.target);
+    for arg in &call.args {
+        let _ = write!(out, " {}=", arg.key);
+        write_atom(out, &arg.value);
+    }
+    out.push(';');
+}
+
+fn write_atom(out: &mut String, atom: &Atom) {
+    match atom {
+        Atom::Null => out.push_str("null"),
+        Atom::Bool(flag) => out.push_str(if *flag { "true" } else { "false" }),
+        Atom::Num(num) => {
+            let _ = write!(out, "{num}");
+        }
+        Atom::Str(text) => {
+            out.push('"');
+            for ch in text.chars() {
+                match ch {
+                    '"' => out.push_str("\\\""),
+                    '\\' => out.push_str("\\\\"),
+                    '\n' => out.push_str("\\n"),
+                    '\t' => out.push_str("\\t"),
+                    ch => out.push(ch),
+                }
+            }
+            out.push('"');
+        }
+        Atom::Ref(path) => {
+            let _ = write!(out, "{}::{}", path.namespace, path.name);
+        }
+    }
+}
@@ -1,8 +1,33 @@
+use std::cell::Cell;
 use std::fmt;
 
+mod format;
+mod upgrade;
+
+pub use format::format_program;
+pub use upgrade::upgrade_program;
+
+/// Most `@anno` markers one call may carry.
+pub const MAX_ANNOS: usize = 16;
+/// Most `key=value` arguments one call may carry.
+pub const MAX_ARGS: usize = 256;
+/// Longest token (target, anno or argument, strings included), in bytes.
+pub const MAX_TOKEN_BYTES: usize = 1 << 20;
+
 #[derive(Debug, Clone, PartialEq)]
 pub struct Program {
     pub calls: Vec<Call>,
+    /// Every comment, in source order, including those inside calls.
+    pub comments: Vec<Comment>,
+}
+
+/// A `//` comment. It starts where a token could (at the start of a line,
+/// or after whitespace or a `;`) and runs to the end of the line.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Comment {
+    /// Everything after the `//`, without trailing whitespace.
+    pub text: String,
+    pub span: Span,
 }
 
 #[derive(Debug, Clone, PartialEq)]
@@ -11,21 +36,69 @@ pub struct Call {
     pub target: String,
     pub args: Vec<Arg>,
     pub line: usize,
+    /// From `#call` through the last argument, without the `;`.
+    pub span: Span,
 }
 
 impl Call {
+    #[must_use]
     pub fn arg(&self, key: &str) -> Option<&Atom> {
         self.args
             .iter()
             .find(|arg| arg.key == key)
             .map(|arg| &arg.value)
     }
+
+    /// Span of the value of argument `key`, if the call has one.
+    #[must_use]
+    pub fn arg_span(&self, key: &str) -> Option<Span> {
+        self.args
+            .iter()
+            .find(|arg| arg.key == key)
+            .map(|arg| arg.value_span)
+    }
 }
 
 #[derive(Debug, Clone, PartialEq)]
 pub struct Arg {
     pub key: String,
     pub value: Atom,
+    /// The whole `key=value` token.
+    pub span: Span,
+    /// Just the value, including the quotes of a string.
+    pub value_span: Span,
+}
+
+/// A byte range of the source, with the 1-based line and column (in
+/// characters) where it starts and the line it ends on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+    pub line: usize,
+    pub column: usize,
+    pub end_line: usize,
+}
+
+impl Span {
+    /// The source line the span starts on, and below it `^` markers under
+    /// the span (up to the end of that line).
+    #[must_use]
+    pub fn underline(&self, src: &str) -> String {
+        let start = self.start.min(src.len());
+        let line_start = src[..start].rfind('\n').map_or(0, |at| at + 1);
+        let line_end = src[start..].find('\n').map_or(src.len(), |at| start + at);
+        let text = src[line_start..line_end].trim_end_matches('\r');
+        let pad = src[line_start..start]
+            .chars()
+            .map(|ch| if ch == '\t' { '\t' } else { ' ' })
+            .collect::<String>();
+        let width = src[start..self.end.clamp(start, line_end)]
+            .chars()
+            .count()
+            .max(1);
+        format!("{text}\n{pad}{}", "^".repeat(width))
+    }
 }
 
 #[derive(Debug, Clone, PartialEq)]
@@ -44,6 +117,7 @@ pub struct RefPath {
 }
 
 impl RefPath {
+    #[must_use]
     pub fn parse(raw: &str) -> Option<Self> {
         let (namespace, name) = raw.split_once("::")?;
         if namespace.is_empty() || name.is_empty() {
@@ -60,6 +134,8 @@ impl RefPath {
 pub struct ParseError {
     pub line: usize,
     pub message: String,
+    /// The offending text.
+    pub span: Span,
 }
 
 impl fmt::Display for ParseError {
@@ -70,190 +146,311 @@ impl fmt::Display for ParseError {
 
 impl std::error::Error for ParseError {}
 
+/// Parses `src` into its calls, keeping comments for the formatter.
+///
+/// # Errors
+///
+/// The first malformed statement, with its line and span.
 pub fn parse_program(src: &str) -> Result<Program, ParseError> {
+    let lines = LineIndex::new(src);
+    let (stmts, comments, unfinished) = split_statements(src, &lines);
+    if let Some(err) = unfinished {
+        return Err(err);
+    }
     let mut calls = Vec::new();
-    for (line, stmt) in split_statements(src)? {
-        if stmt.trim().is_empty() {
-            continue;
+    for (start, stmt) in stmts {
+        calls.push(parse_statement(stmt, start, &lines)?);
+    }
+    Ok(Program { calls, comments })
+}
+
+/// Like [`parse_program`], but skips statements that fail to parse and
+/// returns every error, in source order, alongside the calls that parsed.
+#[must_use]
+pub fn parse_program_recovering(src: &str) -> (Program, Vec<ParseError>) {
+    let lines = LineIndex::new(src);
+    let (stmts, comments, unfinished) = split_statements(src, &lines);
+    let mut calls = Vec::new();
+    let mut errors = Vec::new();
+    for (start, stmt) in stmts {
+        match parse_statement(stmt, start, &lines) {
+            Ok(call) => calls.push(call),
+            Err(err) => errors.push(err),
+        }
+    }
+    errors.extend(unfinished);
+    (Program { calls, comments }, errors)
+}
+
+/// Fuzzing entry point: parses `data`, when it is UTF-8, both failing fast
+/// and recovering, and underlines every span it reports. Any panic is a
+/// bug.
+pub fn fuzz_parse(data: &[u8]) {
+    let Ok(src) = std::str::from_utf8(data) else {
+        return;
+    };
+    let _ = parse_program(src);
+    let (program, errors) = parse_program_recovering(src);
+    for call in &program.calls {
+        let _ = call.span.underline(src);
+        for arg in &call.args {
+            let _ = arg.value_span.underline(src);
         }
-        calls.push(parse_statement(&stmt, line)?);
     }
-    Ok(Program { calls })
+    for comment in &program.comments {
+        let _ = comment.span.underline(src);
+    }
+    for err in &errors {
+        let _ = err.span.underline(src);
+    }
+    let _ = format_program(&program);
+}
+
+/// Byte offsets of line starts, for turning offsets into line/column.
+struct LineIndex<'a> {
+    src: &'a str,
+    starts: Vec<usize>,
+    /// Byte offset and column of the last span's start, so spans asked for
+    /// in order along one long line cost only the text between them.
+    cursor: Cell<(usize, usize)>,
 }
 
-fn split_statements(src: &str) -> Result<Vec<(usize, String)>, ParseError> {
+impl<'a> LineIndex<'a> {
+    fn new(src: &'a str) -> Self {
+        let starts = std::iter::once(0)
+            .chain(src.match_indices('\n').map(|(at, _)| at + 1))
+            .collect();
+        Self {
+            src,
+            starts,
+            cursor: Cell::new((0, 1)),
+        }
+    }
+
+    fn span(&self, start: usize, end: usize) -> Span {
+        let line = self
+            .starts
+            .partition_point(|line_start| *line_start <= start);
+        let line_start = self.starts[line - 1];
+        let (from, column) = match self.cursor.get() {
+            (at, column) if line_start <= at && at <= start => (at, column),
+            _ => (line_start, 1),
+        };
+        let column = column + self.src[from..start].chars().count();
+        self.cursor.set((start, column));
+        let last = end.max(start + 1) - 1;
+        let end_line = line + self.starts[line..].partition_point(|line_start| *line_start <= last);
+        Span {
+            start,
+            end,
+            line,
+            column,
+            end_line,
+        }
+    }
+
+    fn error(&self, start: usize, end: usize, message: impl Into<String>) -> ParseError {
+        let span = self.span(start, end);
+        ParseError {
+            line: span.line,
+            message: message.into(),
+            span,
+        }
+    }
+}
+
+/// Each `;`-terminated statement, trimmed, with its byte offset, every
+/// comment, and the error for an unterminated string or trailing statement,
+/// if any. Statements start at their first token, after any comments.
+fn split_statements<'a>(
+    src: &'a str,
+    lines: &LineIndex<'_>,
+) -> (Vec<(usize, &'a str)>, Vec<Comment>, Option<ParseError>) {
     let mut out = Vec::new();
-    let mut current = String::new();
-    let mut in_string = false;
+    let mut comments = Vec::new();
+    let mut stmt_start = None;
+    let mut string_start = None;
     let mut escaped = false;
-    let mut line = 1usize;
-    let mut stmt_line = 1usize;
+    let mut token_boundary = true;
+    let mut comment_end = 0;
 
-    for ch in src.chars() {
-        if ch == '\n' {
-            line += 1;
+    for (at, ch) in src.char_indices() {
+        if at < comment_end {
+            continue;
         }
-        if in_string {
-            current.push(ch);
+        if string_start.is_some() {
             if escaped {
                 escaped = false;
-                continue;
-            }
-            if ch == '\\' {
+            } else if ch == '\\' {
                 escaped = true;
-                continue;
-            }
-            if ch == '"' {
-                in_string = false;
+            } else if ch == '"' {
+                string_start = None;
             }
             continue;
         }
-
-        if ch == '"' {
-            in_string = true;
-            current.push(ch);
+        if token_boundary && src[at..].starts_with("//") {
+            comment_end = line_end(src, at);
+            comments.push(Comment {
+                text: src[at + 2..comment_end].trim_end().to_owned(),
+                span: lines.span(at, comment_end),
+            });
             continue;
         }
-
-        if ch == ';' {
-            let trimmed = current.trim();
-            if !trimmed.is_empty() {
-                out.push((stmt_line, trimmed.to_owned()));
+        token_boundary = ch.is_whitespace() || ch == ';';
+        match ch {
+            ';' => {
+                if let Some(start) = stmt_start.take() {
+                    out.push((start, src[start..at].trim_end()));
+                }
+            }
+            ch if ch.is_whitespace() => {}
+            ch => {
+                stmt_start.get_or_insert(at);
+                if ch == '"' {
+                    string_start = Some(at);
+                }
             }
-            current.clear();
-            stmt_line = line;
-            continue;
-        }
-
-        if current.is_empty() && ch == '\n' {
-            stmt_line = line;
         }
-        current.push(ch);
-    }
-
-    if in_string {
-        return Err(ParseError {
-            line: stmt_line,
-            message: "unterminated string literal".to_owned(),
-        });
     }
 
-    if !current.trim().is_empty() {
-        return Err(ParseError {
-            line: stmt_line,
-            message: "statement must end with ';'".to_owned(),
-        });
-    }
+    let unfinished = if let Some(start) = string_start {
+        Some(lines.error(start, src.len(), "unterminated string literal"))
+    } else {
+        stmt_start.map(|start| {
+            let rest = src[start..].trim_end();
+            lines.error(start, start + rest.len(), "statement must end with ';'")
+        })
+    };
+    (out, comments, unfinished)
+}
 
-    Ok(out)
+/// Offset of the end of the line `at` is on, before its `\n`.
+fn line_end(src: &str, at: usize) -> usize {
+    src[at..].find('\n').map_or(src.len(), |len| at + len)
 }
 
-fn parse_statement(stmt: &str, line: usize) -> Result<Call, ParseError> {
-    let tokens = tokenize(stmt, line)?;
-    if tokens.is_empty() {
-        return Err(ParseError {
-            line,
-            message: "empty statement".to_owned(),
-        });
+fn parse_statement(stmt: &str, offset: usize, lines: &LineIndex<'_>) -> Result<Call, ParseError> {
+    let tokens = tokenize(stmt, offset);
+    let stmt_end = offset + stmt.len();
+    let (first_start, first) = tokens[0];
+    if first != "#call" {
+        return Err(lines.error(
+            first_start,
+            first_start + first.len(),
+            "statement must start with #call",
+        ));
     }
 
-    if tokens[0] != "#call" {
-        return Err(ParseError {
-            line,
-            message: "statement must start with #call".to_owned(),
-        });
+    if let Some((start, token)) = tokens
+        .iter()
+        .find(|(_, token)| token.len() > MAX_TOKEN_BYTES)
+    {
+        return Err(lines.error(
+            *start,
+            start + token.len(),
+            format!("token is longer than {MAX_TOKEN_BYTES} bytes"),
+        ));
     }
 
+    // Before the argument spans, which lie after it on the line.
+    let span = lines.span(offset, stmt_end);
     let mut index = 1;
     let mut annos = Vec::new();
-    while index < tokens.len() && tokens[index].starts_with('@') {
-        annos.push(tokens[index][1..].to_owned());
+    while index < tokens.len() && tokens[index].1.starts_with('@') {
+        if annos.len() == MAX_ANNOS {
+            let (start, token) = tokens[index];
+            return Err(lines.error(
+                start,
+                start + token.len(),
+                format!("more than {MAX_ANNOS} annotations on one call"),
+            ));
+        }
+        annos.push(tokens[index].1[1..].to_owned());
         index += 1;
     }
 
-    let target = tokens.get(index).ok_or_else(|| ParseError {
-        line,
-        message: "missing target".to_owned(),
-    })?;
+    let Some((_, target)) = tokens.get(index) else {
+        return Err(lines.error(offset, stmt_end, "missing target"));
+    };
     index += 1;
 
     let mut args = Vec::new();
-    while index < tokens.len() {
-        let token = &tokens[index];
-        index += 1;
-        let (key, raw_value) = token.split_once('=').ok_or_else(|| ParseError {
-            line,
-            message: format!("invalid key=value argument: {token}"),
-        })?;
+    for (start, token) in &tokens[index..] {
+        let end = start + token.len();
+        if args.len() == MAX_ARGS {
+            return Err(lines.error(
+                *start,
+                end,
+                format!("more than {MAX_ARGS} arguments on one call"),
+            ));
+        }
+        let Some((key, raw_value)) = token.split_once('=') else {
+            return Err(lines.error(*start, end, format!("invalid key=value argument: {token}")));
+        };
         if key.is_empty() {
-            return Err(ParseError {
-                line,
-                message: "argument key cannot be empty".to_owned(),
-            });
+            return Err(lines.error(*start, end, "argument key cannot be empty"));
         }
 
         args.push(Arg {
             key: key.to_owned(),
             value: parse_atom(raw_value),
+            span: lines.span(*start, end),
+            value_span: lines.span(start + key.len() + 1, end),
         });
     }
 
     Ok(Call {
         annos,
-        target: target.to_owned(),
+        target: (*target).to_owned(),
         args,
-        line,
+        line: span.line,
+        span,
     })
 }
 
-fn tokenize(stmt: &str, line: usize) -> Result<Vec<String>, ParseError> {
+/// Whitespace-separated tokens of a statement (strings may hold spaces),
+/// with their byte offsets, skipping comments. The statement is non-empty
+/// and its strings are terminated, as `split_statements` guarantees.
+fn tokenize(stmt: &str, offset: usize) -> Vec<(usize, &str)> {
     let mut tokens = Vec::new();
-    let mut current = String::new();
+    let mut token_start = None;
     let mut in_string = false;
     let mut escaped = false;
+    let mut comment_end = 0;
 
-    for ch in stmt.chars() {
+    for (at, ch) in stmt.char_indices() {
+        if at < comment_end {
+            continue;
+        }
         if in_string {
-            current.push(ch);
             if escaped {
                 escaped = false;
-                continue;
-            }
-            if ch == '\\' {
+            } else if ch == '\\' {
                 escaped = true;
-                continue;
-            }
-            if ch == '"' {
+            } else if ch == '"' {
                 in_string = false;
             }
             continue;
         }
-
+        if token_start.is_none() && stmt[at..].starts_with("//") {
+            comment_end = line_end(stmt, at);
+            continue;
+        }
         if ch.is_whitespace() {
-            if !current.is_empty() {
-                tokens.push(std::mem::take(&mut current));
+            if let Some(start) = token_start.take() {
+                tokens.push((offset + start, &stmt[start..at]));
             }
             continue;
         }
-
-        current.push(ch);
+        token_start.get_or_insert(at);
         if ch == '"' {
             in_string = true;
         }
     }
-
-    if in_string {
-        return Err(ParseError {
-            line,
-            message: "unterminated string literal".to_owned(),
-        });
-    }
-
-    if !current.is_empty() {
-        tokens.push(current);
+    if let Some(start) = token_start {
+        tokens.push((offset + start, &stmt[start..]));
     }
-
-    Ok(tokens)
+    tokens
 }
 
 fn parse_atom(raw: &str) -> Atom {
@@ -319,6 +516,39 @@ mod tests {
         assert_eq!(call.args.len(), 3);
     }
 
+    #[test]
+    fn spans_locate_calls_args_and_errors() {
+        let src = "#call core::const out=local::x value=1;\n  #call core::host::print msg=\"hé\" value=local::x;";
+        let program = parse_program(src).expect("parse");
+        let call = &program.calls[1];
+        assert_eq!((call.span.line, call.span.column), (2, 3));
+        assert_eq!(
+            &src[call.span.start..call.span.end],
+            "#call core::host::print msg=\"hé\" value=local::x"
+        );
+        let value = call.arg_span("value").expect("value span");
+        assert_eq!(&src[value.start..value.end], "local::x");
+        assert_eq!((value.line, value.column), (2, 42));
+        assert_eq!(
+            value.underline(src),
+            format!(
+                "  #call core::host::print msg=\"hé\" value=local::x;\n{}^^^^^^^^",
+                " ".repeat(41)
+            )
+        );
+
+        let err = parse_program("#call core::exit;\n#call core::add a b=1;").expect_err("bad arg");
+        assert_eq!(err.to_string(), "line 2: invalid key=value argument: a");
+        assert_eq!(
+            (
+                err.span.line,
+                err.span.column,
+                err.span.end - err.span.start
+            ),
+            (2, 17, 1)
+        );
+    }
+
     #[test]
     fn parse_string_with_spaces() {
         let src = "#call core::host::print slot=local::x msg=\"hello world\";";
@@ -326,4 +556,100 @@ mod tests {
         let value = program.calls[0].arg("msg").expect("msg");
         assert_eq!(value, &Atom::Str("hello world".to_owned()));
     }
+
+    #[test]
+    fn comments_are_kept_beside_calls() {
+        let src = "// setup\n#call core::const out=local::url value=http://x; // trailing\n#call core::exit // inside\n;";
+        let program = parse_program(src).expect("parse");
+        assert_eq!(program.calls.len(), 2);
+        assert_eq!(
+            program.calls[0].arg("value"),
+            Some(&Atom::Str("http://x".to_owned()))
+        );
+        assert_eq!(program.calls[1].span.line, 3);
+        let texts = program
+            .comments
+            .iter()
+            .map(|comment| (comment.text.as_str(), comment.span.line))
+            .collect::<Vec<_>>();
+        assert_eq!(texts, [(" setup", 1), (" trailing", 2), (" inside", 3)]);
+        assert!(parse_program("#call core::exit; // no ; needed after this").is_ok());
+    }
+
+    #[test]
+    fn format_program_is_canonical_and_stable() {
+        let src = r#"
+
+// header
+#call   @safe core::const  out=local::x
+    value=1.50;   // one and a half
+
+
+
+#call core::host::print slot=local::x msg=hi
+  // about the label
+  label="say \"a\tb\"";
+#call core::exit;
+// end
+"#;
+        let formatted = format_program(&parse_program(src).expect("parse"));
+        assert_eq!(
+            formatted,
+            r#"// header
+#call @safe core::const out=local::x value=1.5; // one and a half
+
+// about the label
+#call core::host::print slot=local::x msg="hi" label="say \"a\tb\"";
+#call core::exit;
+// end
+"#
+        );
+        let reparsed = parse_program(&formatted).expect("reparse");
+        assert_eq!(format_program(&reparsed), formatted);
+        let original = parse_program(src).expect("parse");
+        for (before, after) in original.calls.iter().zip(&reparsed.calls) {
+            let values = |call: &Call| {
+                call.args
+                    .iter()
+                    .map(|arg| arg.value.clone())
+                    .collect::<Vec<_>>()
+            };
+            assert_eq!(values(before), values(after));
+        }
+        assert_eq!(format_program(&parse_program("").expect("empty")), "");
+    }
+
+    #[test]
+    fn upgrade_program_renames_old_argument_keys() {
+        let src = "#call core::host::print slot=local::x; // keep\n\
+                   #call core::host::eprint slot=local::x value=local::y;\n\
+                   #call core::const slot=1 out=local::z;\n";
+        let mut program = parse_program(src).expect("parse");
+        let notes = upgrade_program(&mut program);
+        assert_eq!(notes, ["line 1: core::host::print slot= -> value="]);
+        assert_eq!(
+            format_program(&program),
+            "#call core::host::print value=local::x; // keep\n\
+             #call core::host::eprint slot=local::x value=local::y;\n\
+             #call core::const slot=1 out=local::z;\n"
+        );
+        assert!(upgrade_program(&mut program).is_empty());
+    }
+
+    #[test]
+    fn oversized_calls_are_errors() {
+        let args = (0..=MAX_ARGS).fold(String::new(), |args, i| args + &format!(" a{i}=1"));
+        let err = parse_program(&format!("#call core::exit{args};")).expect_err("too many args");
+        assert_eq!(
+            err.to_string(),
+            format!("line 1: more than {MAX_ARGS} arguments on one call")
+        );
+        let annos = " @a".repeat(MAX_ANNOS + 1);
+        let err = parse_program(&format!("#call{annos} core::exit;")).expect_err("too many annos");
+        assert_eq!(
+            err.to_string(),
+            format!("line 1: more than {MAX_ANNOS} annotations on one call")
+        );
+        fuzz_parse(b"#call core::add a=\"unterminated b=;;\n#call @ ::");
+    }
 }
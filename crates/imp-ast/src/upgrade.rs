@@ -0,0 +1,32 @@
+use crate::Program;
+
+/// Argument keys a target still accepts under a deprecated name, as
+/// `(target, old key, current key)`. A call that already has the current key
+/// is left alone, so the compiler reads the same argument afterwards.
+const RENAMED_ARGS: &[(&str, &str, &str)] = &[
+    ("core::host::print", "slot", "value"),
+    ("core::host::eprint", "slot", "value"),
+];
+
+/// Rewrites older spellings in `program` to their current form and returns
+/// one note per rewrite, as `line N: what changed`. Only spellings with an
+/// exact current equivalent are touched, so the program compiles to the
+/// same code; print the result with [`crate::format_program`].
+///
+/// Converting `core::fn::begin`/`core::fn::end` pairs into blocks is not
+/// done: the language has no block syntax yet, so that rewrite waits for it.
+pub fn upgrade_program(program: &mut Program) -> Vec<String> {
+    let mut notes = Vec::new();
+    for call in &mut program.calls {
+        for &(target, old, new) in RENAMED_ARGS {
+            if call.target != target || call.arg(new).is_some() {
+                continue;
+            }
+            if let Some(arg) = call.args.iter_mut().find(|arg| arg.key == old) {
+                new.clone_into(&mut arg.key);
+                notes.push(format!("line {}: {target} {old}= -> {new}=", call.line));
+            }
+        }
+    }
+    notes
+}
@@ -0,0 +1,922 @@
+//! Text listing of a compiled module, for `imp disasm` / `imp asm`.
+//!
+//! A listing is one item per line, indented by nesting (indentation is
+//! ignored when reading; blank lines and lines starting with `;` are
+//! skipped):
+//!
+//! ```text
+//! module "main" init=0 globals=2
+//!   global 0 "main::double"
+//!   fnglobal global=0 fn=1
+//!   export "double" global=0
+//!   const "limit" int:3
+//!   test fn=1
+//!   import "dep.imp" alias="dep"
+//!     bind "value" global=1
+//!     module "dep" init=0 globals=0
+//!     end
+//!   fn 1 "main::double" locals=0 args=1 rets=1 errs=0 retshape=scalar params=["x"]
+//!     debug source="main.imp" lines=[2,3]
+//!     0000: Add a=A0 b=A0 out=R0
+//!     0001: Exit
+//!   end
+//! end
+//! ```
+//!
+//! Slots are written `L`ocal, `G`lobal, `A`rg, `R`et or `E`rr plus an index,
+//! missing optional slots `-`, and constants `null`, `true`, `false`,
+//! `num:1.5`, `int:3`, `str:"text"` or `bytes:` plus hex.
+
+use crate::BytecodeError;
+use imp_ir::{
+    CompiledFunction, CompiledModule, ConstValue, DebugInfo, FnMeta, HostClock, ImportBinding,
+    Instr, PrintLevel, PrintStream, RetShape, Slot, ValueKind,
+};
+use std::fmt::Write as _;
+use std::sync::Arc;
+
+/// The listing of `module` and, nested inside it, every import.
+#[must_use]
+pub fn disassemble(module: &CompiledModule) -> String {
+    let mut buf = String::new();
+    write_module(&mut buf, module, 0);
+    buf
+}
+
+/// Reads a listing written by [`disassemble`] (or by hand) back into a
+/// module. Errors name the 1-based line at fault.
+///
+/// # Errors
+///
+/// [`BytecodeError::Asm`] for a line that does not parse, naming it.
+pub fn assemble(text: &str) -> Result<CompiledModule, BytecodeError> {
+    let mut lines = Lines {
+        lines: text
+            .lines()
+            .enumerate()
+            .map(|(index, line)| (index + 1, line.trim()))
+            .filter(|(_, line)| !line.is_empty() && !line.starts_with(';'))
+            .collect(),
+        pos: 0,
+    };
+    let module = read_module(&mut lines)?;
+    if let Some((line, _)) = lines.lines.get(lines.pos) {
+        return Err(asm_error(*line, "text after the module's `end`"));
+    }
+    Ok(module)
+}
+
+fn asm_error(line: usize, message: impl Into<String>) -> BytecodeError {
+    BytecodeError::Asm {
+        line,
+        message: message.into(),
+    }
+}
+
+fn write_module(buf: &mut String, module: &CompiledModule, depth: usize) {
+    let pad = "  ".repeat(depth);
+    let _ = writeln!(
+        buf,
+        "{pad}module {:?} init={} globals={}",
+        module.name, module.init_func, module.global_count
+    );
+    for (slot, name) in module.global_names.iter().enumerate() {
+        let _ = writeln!(buf, "{pad}  global {slot} {name:?}");
+    }
+    for (slot, func) in &module.function_globals {
+        let _ = writeln!(buf, "{pad}  fnglobal global={slot} fn={func}");
+    }
+    for (name, slot) in &module.exports {
+        let _ = writeln!(buf, "{pad}  export {name:?} global={slot}");
+    }
+    for (name, value) in &module.consts {
+        let _ = write!(buf, "{pad}  const {name:?} ");
+        value.write(buf);
+        buf.push('\n');
+    }
+    for func in &module.tests {
+        let _ = writeln!(buf, "{pad}  test fn={func}");
+    }
+    for import in &module.imports {
+        let _ = writeln!(
+            buf,
+            "{pad}  import {:?} alias={:?}",
+            import.path, import.alias
+        );
+        for (name, slot) in &import.export_to_global {
+            let _ = writeln!(buf, "{pad}    bind {name:?} global={slot}");
+        }
+        write_module(buf, &import.module, depth + 2);
+    }
+    for function in &module.functions {
+        write_function(buf, function, &pad);
+    }
+    let _ = writeln!(buf, "{pad}end");
+}
+
+fn write_function(buf: &mut String, function: &CompiledFunction, pad: &str) {
+    let meta = &function.meta;
+    let _ = write!(
+        buf,
+        "{pad}  fn {} {:?} locals={} args={} rets={} errs={} retshape={}",
+        function.id,
+        meta.name,
+        function.local_count,
+        function.arg_count,
+        function.ret_count,
+        function.err_count,
+        meta.retshape
+    );
+    if !meta.params.is_empty() {
+        let params = meta.params.iter().map(|param| format!("{param:?}"));
+        let _ = write!(buf, " params=[{}]", params.collect::<Vec<_>>().join(","));
+    }
+    if meta.arg_count != function.arg_count {
+        let _ = write!(buf, " meta_args={}", meta.arg_count);
+    }
+    if meta.ret_count != function.ret_count {
+        let _ = write!(buf, " meta_rets={}", meta.ret_count);
+    }
+    if let Some(ms) = meta.timeout_ms {
+        let _ = write!(buf, " timeout={ms}");
+    }
+    buf.push('\n');
+    if let Some(debug) = &meta.debug {
+        let _ = write!(buf, "{pad}    debug");
+        if let Some(source) = &debug.source {
+            let _ = write!(buf, " source={source:?}");
+        }
+        let lines = debug.lines.iter().map(u32::to_string).collect::<Vec<_>>();
+        let _ = writeln!(buf, " lines=[{}]", lines.join(","));
+    }
+    for (pc, instr) in function.code.iter().enumerate() {
+        let _ = write!(buf, "{pad}    {pc:04}: ");
+        write_instr(buf, instr);
+        buf.push('\n');
+    }
+    let _ = writeln!(buf, "{pad}  end");
+}
+
+/// The meaningful lines of a listing, with their line numbers.
+struct Lines<'a> {
+    lines: Vec<(usize, &'a str)>,
+    pos: usize,
+}
+
+impl<'a> Lines<'a> {
+    fn next(&mut self, within: &str) -> Result<(usize, Line<'a>), BytecodeError> {
+        let Some(&(number, text)) = self.lines.get(self.pos) else {
+            let last = self.lines.last().map_or(1, |(line, _)| *line);
+            return Err(asm_error(last, format!("{within} is missing its `end`")));
+        };
+        self.pos += 1;
+        let line = Line::parse(text).map_err(|message| asm_error(number, message))?;
+        Ok((number, line))
+    }
+}
+
+/// One listing line split into its keyword, positional words and
+/// `key=value` fields.
+struct Line<'a> {
+    keyword: &'a str,
+    words: Vec<&'a str>,
+    fields: Vec<(&'a str, &'a str)>,
+}
+
+impl<'a> Line<'a> {
+    fn parse(text: &'a str) -> Result<Self, String> {
+        let mut tokens = tokens(text)?.into_iter();
+        let keyword = tokens.next().unwrap_or_default();
+        let mut words = Vec::new();
+        let mut fields = Vec::new();
+        for token in tokens {
+            match token.split_once('=') {
+                Some((key, value))
+                    if !key.is_empty()
+                        && key.bytes().all(|b| b.is_ascii_alphanumeric() || b == b'_') =>
+                {
+                    fields.push((key, value));
+                }
+                _ => words.push(token),
+            }
+        }
+        Ok(Self {
+            keyword,
+            words,
+            fields,
+        })
+    }
+
+    /// The `index`th positional word.
+    fn word(&self, index: usize, what: &str) -> Result<&'a str, String> {
+        self.words
+            .get(index)
+            .copied()
+            .ok_or_else(|| format!("`{}` needs {what}", self.keyword))
+    }
+
+    /// Removes and returns field `key`.
+    fn take(&mut self, key: &str) -> Result<&'a str, String> {
+        self.take_opt(key)
+            .ok_or_else(|| format!("`{}` is missing `{key}=`", self.keyword))
+    }
+
+    fn take_opt(&mut self, key: &str) -> Option<&'a str> {
+        let index = self.fields.iter().position(|(name, _)| *name == key)?;
+        Some(self.fields.remove(index).1)
+    }
+
+    /// Fails on fields nobody took, and on more than `words` positionals.
+    fn finish(&self, words: usize) -> Result<(), String> {
+        if let Some((key, _)) = self.fields.first() {
+            return Err(format!("unknown field `{key}=` on `{}`", self.keyword));
+        }
+        if self.words.len() > words {
+            return Err(format!(
+                "unexpected `{}` on `{}`",
+                self.words[words], self.keyword
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// Whitespace-separated tokens; quoted strings and `[...]` lists may hold
+/// spaces.
+fn tokens(text: &str) -> Result<Vec<&str>, String> {
+    let mut tokens = Vec::new();
+    let mut start = None;
+    let mut in_string = false;
+    let mut escaped = false;
+    let mut depth = 0usize;
+    for (at, ch) in text.char_indices() {
+        if in_string {
+            if escaped {
+                escaped = false;
+            } else if ch == '\\' {
+                escaped = true;
+            } else if ch == '"' {
+                in_string = false;
+            }
+            continue;
+        }
+        match ch {
+            ch if ch.is_whitespace() && depth == 0 => {
+                if let Some(from) = start.take() {
+                    tokens.push(&text[from..at]);
+                }
+                continue;
+            }
+            '"' => in_string = true,
+            '[' => depth += 1,
+            ']' => depth = depth.saturating_sub(1),
+            _ => {}
+        }
+        start.get_or_insert(at);
+    }
+    if in_string {
+        return Err("unterminated string".to_owned());
+    }
+    if let Some(from) = start {
+        tokens.push(&text[from..]);
+    }
+    Ok(tokens)
+}
+
+fn read_module(lines: &mut Lines<'_>) -> Result<CompiledModule, BytecodeError> {
+    let (number, mut header) = lines.next("the listing")?;
+    let fail = |message: String| asm_error(number, message);
+    if header.keyword != "module" {
+        return Err(fail(format!(
+            "expected `module`, found `{}`",
+            header.keyword
+        )));
+    }
+    let mut module = CompiledModule {
+        name: Arc::from(unquote(header.word(0, "a name").map_err(fail)?).map_err(fail)?),
+        init_func: parse_num(header.take("init").map_err(fail)?).map_err(fail)?,
+        functions: Vec::new(),
+        function_globals: Vec::new(),
+        exports: Vec::new(),
+        imports: Vec::new(),
+        consts: Vec::new(),
+        global_count: parse_num(header.take("globals").map_err(fail)?).map_err(fail)?,
+        tests: Vec::new(),
+        global_names: Vec::new(),
+    };
+    header.finish(1).map_err(fail)?;
+
+    loop {
+        let (number, mut line) = lines.next("a module")?;
+        let fail = |message: String| asm_error(number, message);
+        match line.keyword {
+            "end" => {
+                line.finish(0).map_err(fail)?;
+                return Ok(module);
+            }
+            "global" => {
+                let slot: usize = parse_num(line.word(0, "a slot").map_err(fail)?).map_err(fail)?;
+                if slot != module.global_names.len() {
+                    return Err(fail(format!(
+                        "global {slot} is out of order; expected {}",
+                        module.global_names.len()
+                    )));
+                }
+                let name = unquote(line.word(1, "a name").map_err(fail)?).map_err(fail)?;
+                line.finish(2).map_err(fail)?;
+                module.global_names.push(name);
+            }
+            "fnglobal" => {
+                let slot = parse_num(line.take("global").map_err(fail)?).map_err(fail)?;
+                let func = parse_num(line.take("fn").map_err(fail)?).map_err(fail)?;
+                line.finish(0).map_err(fail)?;
+                module.function_globals.push((slot, func));
+            }
+            "export" => {
+                let name = unquote(line.word(0, "a name").map_err(fail)?).map_err(fail)?;
+                let slot = parse_num(line.take("global").map_err(fail)?).map_err(fail)?;
+                line.finish(1).map_err(fail)?;
+                module.exports.push((name, slot));
+            }
+            "const" => {
+                let name = unquote(line.word(0, "a name").map_err(fail)?).map_err(fail)?;
+                let value =
+                    ConstValue::read(line.word(1, "a value").map_err(fail)?).map_err(fail)?;
+                line.finish(2).map_err(fail)?;
+                module.consts.push((name, value));
+            }
+            "test" => {
+                let func = parse_num(line.take("fn").map_err(fail)?).map_err(fail)?;
+                line.finish(0).map_err(fail)?;
+                module.tests.push(func);
+            }
+            "import" => {
+                let path = unquote(line.word(0, "a path").map_err(fail)?).map_err(fail)?;
+                let alias = unquote(line.take("alias").map_err(fail)?).map_err(fail)?;
+                line.finish(1).map_err(fail)?;
+                module.imports.push(read_import(lines, path, alias)?);
+            }
+            "fn" => module.functions.push(read_function(lines, line, number)?),
+            other => return Err(fail(format!("unknown module item `{other}`"))),
+        }
+    }
+}
+
+/// The `bind` lines and nested `module` after an `import` line.
+fn read_import(
+    lines: &mut Lines<'_>,
+    path: String,
+    alias: String,
+) -> Result<ImportBinding, BytecodeError> {
+    let mut export_to_global = Vec::new();
+    loop {
+        let Some(&(number, text)) = lines.lines.get(lines.pos) else {
+            return Err(asm_error(
+                lines.lines.last().map_or(1, |(line, _)| *line),
+                format!("import {path:?} is missing its module"),
+            ));
+        };
+        if !text.starts_with("bind ") {
+            break;
+        }
+        let (_, mut line) = lines.next("an import")?;
+        let fail = |message: String| asm_error(number, message);
+        let name = unquote(line.word(0, "an export name").map_err(fail)?).map_err(fail)?;
+        let slot = parse_num(line.take("global").map_err(fail)?).map_err(fail)?;
+        line.finish(1).map_err(fail)?;
+        export_to_global.push((name, slot));
+    }
+    Ok(ImportBinding {
+        path,
+        alias,
+        module: Arc::new(read_module(lines)?),
+        export_to_global,
+    })
+}
+
+fn read_function(
+    lines: &mut Lines<'_>,
+    mut header: Line<'_>,
+    number: usize,
+) -> Result<CompiledFunction, BytecodeError> {
+    let fail = |message: String| asm_error(number, message);
+    let id = parse_num(header.word(0, "an id").map_err(fail)?).map_err(fail)?;
+    let name: Arc<str> = Arc::from(unquote(header.word(1, "a name").map_err(fail)?).map_err(fail)?);
+    let local_count = parse_num(header.take("locals").map_err(fail)?).map_err(fail)?;
+    let arg_count = parse_num(header.take("args").map_err(fail)?).map_err(fail)?;
+    let ret_count = parse_num(header.take("rets").map_err(fail)?).map_err(fail)?;
+    let err_count = parse_num(header.take("errs").map_err(fail)?).map_err(fail)?;
+    let retshape = parse_retshape(header.take("retshape").map_err(fail)?).map_err(fail)?;
+    let params = match header.take_opt("params") {
+        Some(list) => list_items(list)
+            .map_err(fail)?
+            .into_iter()
+            .map(|item| unquote(item).map(Arc::from))
+            .collect::<Result<_, _>>()
+            .map_err(fail)?,
+        None => Vec::new(),
+    };
+    let meta_args = optional_num(header.take_opt("meta_args")).map_err(fail)?;
+    let meta_rets = optional_num(header.take_opt("meta_rets")).map_err(fail)?;
+    let timeout_ms = optional_num(header.take_opt("timeout")).map_err(fail)?;
+    header.finish(2).map_err(fail)?;
+
+    let mut debug = None;
+    let mut code = Vec::new();
+    loop {
+        let (number, mut line) = lines.next("a function")?;
+        let fail = |message: String| asm_error(number, message);
+        match line.keyword {
+            "end" => {
+                line.finish(0).map_err(fail)?;
+                break;
+            }
+            "debug" => {
+                let source = line
+                    .take_opt("source")
+                    .map(|source| unquote(source).map(Arc::from))
+                    .transpose()
+                    .map_err(fail)?;
+                let lines = list_items(line.take("lines").map_err(fail)?)
+                    .map_err(fail)?
+                    .into_iter()
+                    .map(parse_num)
+                    .collect::<Result<_, _>>()
+                    .map_err(fail)?;
+                line.finish(0).map_err(fail)?;
+                debug = Some(Arc::new(DebugInfo { source, lines }));
+            }
+            _ => code.push(read_code_line(line, code.len()).map_err(fail)?),
+        }
+    }
+    Ok(CompiledFunction {
+        id,
+        code: code.into(),
+        local_count,
+        arg_count,
+        ret_count,
+        err_count,
+        meta: FnMeta {
+            name,
+            params,
+            arg_count: meta_args.unwrap_or(arg_count),
+            ret_count: meta_rets.unwrap_or(ret_count),
+            retshape,
+            timeout_ms,
+            debug,
+        },
+    })
+}
+
+/// An instruction line, with or without its `0000:` pc label.
+fn read_code_line(mut line: Line<'_>, pc: usize) -> Result<Instr, String> {
+    let mut words = 0;
+    let name = match line.keyword.strip_suffix(':') {
+        Some(label) => {
+            let label: usize = parse_num(label)?;
+            if label != pc {
+                return Err(format!("instruction labelled {label} is at pc {pc}"));
+            }
+            words = 1;
+            line.word(0, "an instruction")?
+        }
+        None => line.keyword,
+    };
+    let instr = read_instr(name, &mut line)?;
+    line.finish(words)?;
+    Ok(instr)
+}
+
+fn optional_num<T: std::str::FromStr>(text: Option<&str>) -> Result<Option<T>, String> {
+    text.map(parse_num).transpose()
+}
+
+fn parse_num<T: std::str::FromStr>(text: &str) -> Result<T, String> {
+    text.parse().map_err(|_| format!("invalid number `{text}`"))
+}
+
+/// The items of a `[a,b,c]` list; commas inside quotes do not split.
+fn list_items(text: &str) -> Result<Vec<&str>, String> {
+    let inner = text
+        .strip_prefix('[')
+        .and_then(|rest| rest.strip_suffix(']'))
+        .ok_or_else(|| format!("expected a [..] list, found `{text}`"))?;
+    let mut items = Vec::new();
+    let mut start = 0;
+    let mut in_string = false;
+    let mut escaped = false;
+    for (at, ch) in inner.char_indices() {
+        if in_string {
+            if escaped {
+                escaped = false;
+            } else if ch == '\\' {
+                escaped = true;
+            } else if ch == '"' {
+                in_string = false;
+            }
+        } else if ch == '"' {
+            in_string = true;
+        } else if ch == ',' {
+            items.push(inner[start..at].trim());
+            start = at + 1;
+        }
+    }
+    let last = inner[start..].trim();
+    if !last.is_empty() || !items.is_empty() {
+        items.push(last);
+    }
+    Ok(items)
+}
+
+/// A string written as Rust's `{:?}` writes it.
+fn unquote(text: &str) -> Result<String, String> {
+    let inner = text
+        .strip_prefix('"')
+        .and_then(|rest| rest.strip_suffix('"'))
+        .filter(|_| text.len() >= 2)
+        .ok_or_else(|| format!("expected a quoted string, found `{text}`"))?;
+    let mut out = String::with_capacity(inner.len());
+    let mut chars = inner.chars();
+    while let Some(ch) = chars.next() {
+        if ch != '\\' {
+            out.push(ch);
+            continue;
+        }
+        out.push(match chars.next() {
+            Some('n') => '\n',
+            Some('r') => '\r',
+            Some('t') => '\t',
+            Some('0') => '\0',
+            Some(ch @ ('\\' | '"' | '\'')) => ch,
+            Some('u') => {
+                let code = chars
+                    .by_ref()
+                    .skip_while(|ch| *ch == '{')
+                    .take_while(|ch| *ch != '}')
+                    .collect::<String>();
+                u32::from_str_radix(&code, 16)
+                    .ok()
+                    .and_then(char::from_u32)
+                    .ok_or_else(|| format!("invalid \\u escape in {text}"))?
+            }
+            _ => return Err(format!("invalid escape in {text}")),
+        });
+    }
+    Ok(out)
+}
+
+fn parse_retshape(text: &str) -> Result<RetShape, String> {
+    let names = |inner: &str| {
+        inner
+            .split(',')
+            .filter(|name| !name.is_empty())
+            .map(str::to_owned)
+            .collect()
+    };
+    match text {
+        "scalar" => Ok(RetShape::Scalar),
+        "any" => Ok(RetShape::Any),
+        _ => {
+            if let Some(inner) = text
+                .strip_prefix("either(")
+                .and_then(|rest| rest.strip_suffix(')'))
+            {
+                Ok(RetShape::Either(names(inner)))
+            } else if let Some(inner) = text
+                .strip_prefix("record(")
+                .and_then(|rest| rest.strip_suffix(')'))
+            {
+                Ok(RetShape::Record(names(inner)))
+            } else {
+                Err(format!("invalid retshape `{text}`"))
+            }
+        }
+    }
+}
+
+/// An instruction field's text form.
+trait Operand: Sized {
+    fn write(&self, buf: &mut String);
+    fn read(text: &str) -> Result<Self, String>;
+}
+
+impl Operand for Slot {
+    fn write(&self, buf: &mut String) {
+        let (prefix, index) = match self {
+            Self::Local(index) => ('L', index),
+            Self::Global(index) => ('G', index),
+            Self::Arg(index) => ('A', index),
+            Self::Ret(index) => ('R', index),
+            Self::Err(index) => ('E', index),
+        };
+        let _ = write!(buf, "{prefix}{index}");
+    }
+
+    fn read(text: &str) -> Result<Self, String> {
+        let mut chars = text.chars();
+        let make = match chars.next() {
+            Some('L') => Self::Local,
+            Some('G') => Self::Global,
+            Some('A') => Self::Arg,
+            Some('R') => Self::Ret,
+            Some('E') => Self::Err,
+            _ => return Err(format!("invalid slot `{text}`")),
+        };
+        chars
+            .as_str()
+            .parse()
+            .map(make)
+            .map_err(|_| format!("invalid slot `{text}`"))
+    }
+}
+
+impl Operand for Option<Slot> {
+    fn write(&self, buf: &mut String) {
+        match self {
+            Some(slot) => slot.write(buf),
+            None => buf.push('-'),
+        }
+    }
+
+    fn read(text: &str) -> Result<Self, String> {
+        match text {
+            "-" => Ok(None),
+            slot => Slot::read(slot).map(Some),
+        }
+    }
+}
+
+impl Operand for Vec<Slot> {
+    fn write(&self, buf: &mut String) {
+        buf.push('[');
+        for (index, slot) in self.iter().enumerate() {
+            if index > 0 {
+                buf.push(',');
+            }
+            slot.write(buf);
+        }
+        buf.push(']');
+    }
+
+    fn read(text: &str) -> Result<Self, String> {
+        list_items(text)?.into_iter().map(Slot::read).collect()
+    }
+}
+
+macro_rules! number_operands {
+    ($($ty:ty),*) => {
+        $(impl Operand for $ty {
+            fn write(&self, buf: &mut String) {
+                let _ = write!(buf, "{self}");
+            }
+
+            fn read(text: &str) -> Result<Self, String> {
+                parse_num(text)
+            }
+        })*
+    };
+}
+
+number_operands!(usize, u32, u16);
+
+impl Operand for bool {
+    fn write(&self, buf: &mut String) {
+        let _ = write!(buf, "{self}");
+    }
+
+    fn read(text: &str) -> Result<Self, String> {
+        text.parse()
+            .map_err(|_| format!("expected true or false, found `{text}`"))
+    }
+}
+
+impl Operand for String {
+    fn write(&self, buf: &mut String) {
+        let _ = write!(buf, "{self:?}");
+    }
+
+    fn read(text: &str) -> Result<Self, String> {
+        unquote(text)
+    }
+}
+
+impl Operand for ConstValue {
+    fn write(&self, buf: &mut String) {
+        let _ = match self {
+            Self::Null => write!(buf, "null"),
+            Self::Bool(flag) => write!(buf, "{flag}"),
+            Self::Num(num) => write!(buf, "num:{num}"),
+            Self::Int(int) => write!(buf, "int:{int}"),
+            Self::Str(text) => write!(buf, "str:{text:?}"),
+            Self::Bytes(bytes) => {
+                buf.push_str("bytes:");
+                for byte in bytes.iter() {
+                    let _ = write!(buf, "{byte:02x}");
+                }
+                Ok(())
+            }
+        };
+    }
+
+    fn read(text: &str) -> Result<Self, String> {
+        match text {
+            "null" => return Ok(Self::Null),
+            "true" => return Ok(Self::Bool(true)),
+            "false" => return Ok(Self::Bool(false)),
+            _ => {}
+        }
+        let (kind, value) = text
+            .split_once(':')
+            .ok_or_else(|| format!("invalid constant `{text}`"))?;
+        match kind {
+            "num" => parse_num(value).map(Self::Num),
+            "int" => parse_num(value).map(Self::Int),
+            "str" => unquote(value).map(|text| Self::Str(Arc::from(text))),
+            "bytes" if value.len() % 2 == 0 => (0..value.len())
+                .step_by(2)
+                .map(|at| {
+                    value
+                        .get(at..at + 2)
+                        .and_then(|hex| u8::from_str_radix(hex, 16).ok())
+                })
+                .collect::<Option<Vec<u8>>>()
+                .map(|bytes| Self::Bytes(Arc::from(bytes)))
+                .ok_or_else(|| format!("invalid hex bytes `{value}`")),
+            _ => Err(format!("invalid constant `{text}`")),
+        }
+    }
+}
+
+impl Operand for ValueKind {
+    fn write(&self, buf: &mut String) {
+        buf.push_str(self.as_str());
+    }
+
+    fn read(text: &str) -> Result<Self, String> {
+        Self::parse(text).ok_or_else(|| format!("unknown value kind `{text}`"))
+    }
+}
+
+impl Operand for PrintStream {
+    fn write(&self, buf: &mut String) {
+        buf.push_str(match self {
+            Self::Stdout => "stdout",
+            Self::Stderr => "stderr",
+        });
+    }
+
+    fn read(text: &str) -> Result<Self, String> {
+        match text {
+            "stdout" => Ok(Self::Stdout),
+            "stderr" => Ok(Self::Stderr),
+            _ => Err(format!("unknown print stream `{text}`")),
+        }
+    }
+}
+
+impl Operand for PrintLevel {
+    fn write(&self, buf: &mut String) {
+        buf.push_str(self.as_str());
+    }
+
+    fn read(text: &str) -> Result<Self, String> {
+        Self::parse(text).ok_or_else(|| format!("unknown print level `{text}`"))
+    }
+}
+
+impl Operand for HostClock {
+    fn write(&self, buf: &mut String) {
+        buf.push_str(match self {
+            Self::Wall => "wall",
+            Self::Monotonic => "monotonic",
+        });
+    }
+
+    fn read(text: &str) -> Result<Self, String> {
+        match text {
+            "wall" => Ok(Self::Wall),
+            "monotonic" => Ok(Self::Monotonic),
+            _ => Err(format!("unknown clock `{text}`")),
+        }
+    }
+}
+
+/// Declares each instruction's listing form: its variant name, then its
+/// fields as `name=value` in declaration order.
+macro_rules! instr_forms {
+    ($($name:ident $({ $($field:ident),* })?),* $(,)?) => {
+        fn write_instr(buf: &mut String, instr: &Instr) {
+            match instr {
+                $(Instr::$name $({ $($field),* })? => {
+                    buf.push_str(stringify!($name));
+                    $($(
+                        buf.push_str(concat!(" ", stringify!($field), "="));
+                        Operand::write($field, buf);
+                    )*)?
+                })*
+            }
+        }
+
+        fn read_instr(name: &str, line: &mut Line<'_>) -> Result<Instr, String> {
+            Ok(match name {
+                $(stringify!($name) => Instr::$name $({
+                    $($field: Operand::read(line.take(stringify!($field))?)?),*
+                })?,)*
+                other => return Err(format!("unknown instruction `{other}`")),
+            })
+        }
+    };
+}
+
+instr_forms! {
+    StoreConst { slot, value },
+    Move { from, to },
+    Add { a, b, out },
+    Sub { a, b, out },
+    Mul { a, b, out },
+    Div { a, b, out },
+    Mod { a, b, out },
+    Pow { a, b, out },
+    Neg { value, out },
+    Eq { a, b, out },
+    Lt { a, b, out },
+    Ne { a, b, out },
+    Le { a, b, out },
+    Gt { a, b, out },
+    Ge { a, b, out },
+    Cmp { a, b, out },
+    Jump { target },
+    Branch { cond, then_pc, else_pc },
+    Invoke { fn_slot, args, out },
+    InvokeMulti { fn_slot, args, outs },
+    TailInvoke { fn_slot, args },
+    InvokeDynamic { fn_slot, arglist, out },
+    ReturnSet { slot_id, value },
+    Exit,
+    Throw { code, msg, cause, data },
+    Rethrow { err },
+    TryPush { handler_pc },
+    TryPop,
+    ObjNew { out },
+    ObjSet { obj, key, value, out },
+    ObjGet { obj, key, out },
+    ObjHas { obj, key, out },
+    ObjEntries { obj, out, len },
+    ObjKeys { obj, out },
+    ObjDel { obj, key, out },
+    ObjLen { obj, out },
+    ObjGetPath { obj, path, out, safe },
+    ObjSetPath { obj, path, value, out, safe },
+    ListNew { out },
+    ListPush { list, value, out },
+    ListGet { list, index, out },
+    ListSet { list, index, value, out },
+    ListLen { list, out },
+    StrConcat { a, b, out },
+    StrLen { value, out },
+    StrSub { value, start, end, out },
+    StrFind { value, pattern, out },
+    StrReplace { value, pattern, with, out },
+    StrContains { value, pattern, out },
+    StrUpper { value, out },
+    StrLower { value, out },
+    StrTrim { value, out },
+    StrTrimStart { value, out },
+    StrTrimEnd { value, out },
+    StrSplit { value, sep, out },
+    StrJoin { list, sep, out },
+    StrFormat { tmpl, args, out },
+    BytesFromStr { value, out },
+    BytesToStr { value, out },
+    BytesLen { value, out },
+    BytesConcat { a, b, out },
+    BytesSlice { value, start, end, out },
+    IntParse { value, out },
+    TypeOf { value, out },
+    IsType { value, kind, out },
+    NumParse { value, out },
+    NumFormat { value, precision, width, out },
+    FnMeta { func, out },
+    FnBind { func, args, out },
+    MakeClosure { func, captures, out },
+    ErrorWrap { err, code, msg, out },
+    ErrorCause { err, out },
+    ErrorCode { err, out },
+    ErrorMsg { err, out },
+    ErrorData { err, out },
+    Ext { op, operands },
+    HostCall { name, args, out },
+    HostPrint { slot, stream, level },
+    HostTime { clock, out },
+    HostRandom { out },
+    HostArgs { out },
+    HostReadLine { out },
+    HostReadAll { out },
+    HostEnvVar { key, out },
+    HostRandomInt { lo, hi, out },
+    HostFsRead { path, out },
+    HostFsWrite { path, data },
+    HostFsExists { path, out },
+    JsonParse { value, out },
+    JsonStringify { value, out },
+}
@@ -1,14 +1,67 @@
 use imp_ir::{
-    CompiledFunction, CompiledModule, ConstValue, FnMeta, ImportBinding, Instr, RetShape, Slot,
+    CompiledFunction, CompiledModule, ConstValue, DebugInfo, FnMeta, HostClock, ImportBinding,
+    Instr, PrintLevel, PrintStream, RetShape, Slot, ValueKind,
 };
+use imp_std::sha256;
+use std::collections::HashMap;
 use std::fmt;
 use std::fs;
 use std::io;
 use std::path::Path;
 use std::sync::Arc;
 
+mod asm;
+
+pub use asm::{assemble, disassemble};
+
 const MAGIC: [u8; 4] = *b"IMPC";
-const VERSION: u16 = 1;
+const VERSION: u16 = 10;
+/// Oldest format still decoded; v1 lacks function parameter names, v2
+/// lacks module constants, v3 lacks function timeouts, v4 lacks
+/// separately linked imports, v5 lacks debug info, v7 lacks the test
+/// list, v8 lacks global names and v9 lacks the hashes of linked
+/// imports. Up to v6, integers
+/// are fixed-width little-endian and strings are inline.
+const MIN_VERSION: u16 = 1;
+/// First format with LEB128 integers, packed slots and a string pool.
+const COMPACT_VERSION: u16 = 7;
+
+/// Import entry holding the imported module's bytecode inline.
+const IMPORT_EMBEDDED: u8 = 0;
+/// Import entry naming a module to fetch through a [`ModuleResolver`],
+/// followed (from v10) by the SHA-256 of its separately encoded bytecode.
+const IMPORT_LINKED: u8 = 1;
+/// Deepest chain of embedded imports decoded, so hostile input cannot
+/// exhaust the stack.
+const MAX_IMPORT_DEPTH: usize = 64;
+
+/// Supplies the encoded bytecode of separately compiled imports, the
+/// decode-side counterpart of the compiler's `ModuleLoader`. Embedders can
+/// serve modules from a database, embedded assets or an encrypted store.
+pub trait ModuleResolver {
+    /// Bytecode for the import recorded as `path` (`ImportBinding::path`).
+    ///
+    /// # Errors
+    ///
+    /// Whatever stops the bytecode from being found or read.
+    fn resolve(&self, path: &str) -> Result<Vec<u8>, BytecodeError>;
+}
+
+/// Reads each import from the `.impc` file beside its source path.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct FsModuleResolver;
+
+impl ModuleResolver for FsModuleResolver {
+    fn resolve(&self, path: &str) -> Result<Vec<u8>, BytecodeError> {
+        let compiled = Path::new(path).with_extension("impc");
+        fs::read(&compiled).map_err(|err| {
+            BytecodeError::Io(io::Error::new(
+                err.kind(),
+                format!("cannot read {}: {err}", compiled.display()),
+            ))
+        })
+    }
+}
 
 #[derive(Debug)]
 pub enum BytecodeError {
@@ -17,8 +70,31 @@ pub enum BytecodeError {
     InvalidMagic([u8; 4]),
     UnsupportedVersion(u16),
     InvalidUtf8(String),
-    InvalidTag { kind: &'static str, tag: u8 },
+    InvalidTag {
+        kind: &'static str,
+        tag: u8,
+    },
     Overflow(&'static str),
+    /// A separately linked import was decoded without a [`ModuleResolver`].
+    UnresolvedImport(String),
+    /// Linked imports that lead back to a module still being decoded.
+    ImportCycle(Vec<String>),
+    /// A linked import's bytecode differs from what was built alongside
+    /// the importing module.
+    ImportHashMismatch {
+        path: String,
+        expected: [u8; 32],
+        found: [u8; 32],
+    },
+    /// A string reference past the end of the string pool.
+    InvalidStringIndex(u32),
+    /// Embedded imports nested deeper than the decoder follows.
+    ImportTooDeep,
+    /// A listing [`assemble`] could not read.
+    Asm {
+        line: usize,
+        message: String,
+    },
 }
 
 impl fmt::Display for BytecodeError {
@@ -30,42 +106,154 @@ impl fmt::Display for BytecodeError {
             Self::UnsupportedVersion(version) => {
                 write!(
                     f,
-                    "unsupported bytecode version {version} (expected {VERSION})"
+                    "unsupported bytecode version {version} (expected {MIN_VERSION}..={VERSION})"
                 )
             }
             Self::InvalidUtf8(ctx) => write!(f, "invalid utf8 for {ctx}"),
             Self::InvalidTag { kind, tag } => write!(f, "invalid {kind} tag {tag}"),
             Self::Overflow(ctx) => write!(f, "value overflow while encoding/decoding {ctx}"),
+            Self::UnresolvedImport(path) => {
+                write!(
+                    f,
+                    "import {path} is linked separately; decode with a ModuleResolver"
+                )
+            }
+            Self::ImportCycle(chain) => write!(f, "import cycle: {}", chain.join(" -> ")),
+            Self::ImportHashMismatch {
+                path,
+                expected,
+                found,
+            } => write!(
+                f,
+                "import {path} does not match the bytecode it was linked against: expected sha256 {}, found {}",
+                hex(expected),
+                hex(found)
+            ),
+            Self::InvalidStringIndex(index) => {
+                write!(f, "string pool index {index} out of range")
+            }
+            Self::ImportTooDeep => {
+                write!(f, "embedded imports nest deeper than {MAX_IMPORT_DEPTH}")
+            }
+            Self::Asm { line, message } => write!(f, "line {line}: {message}"),
         }
     }
 }
 
 impl std::error::Error for BytecodeError {}
 
+fn hex(digest: &[u8]) -> String {
+    use fmt::Write;
+
+    digest
+        .iter()
+        .fold(String::with_capacity(digest.len() * 2), |mut hex, byte| {
+            let _ = write!(hex, "{byte:02x}");
+            hex
+        })
+}
+
 impl From<io::Error> for BytecodeError {
     fn from(value: io::Error) -> Self {
         Self::Io(value)
     }
 }
 
+/// Encodes `module` with its imports embedded.
+///
+/// # Errors
+///
+/// A module or import past a limit of the format.
 pub fn encode_module(module: &CompiledModule) -> Result<Vec<u8>, BytecodeError> {
     let mut w = Writer::default();
-    w.write_bytes(&MAGIC);
-    w.write_u16(VERSION);
     write_module(&mut w, module)?;
-    Ok(w.finish())
+    w.finish()
+}
+
+/// Encodes `module` with its imports recorded by path only, for separate
+/// compilation; each import is encoded on its own and found again at load
+/// time by [`decode_module_linked`].
+///
+/// # Errors
+///
+/// A module or import past a limit of the format.
+pub fn encode_module_separate(module: &CompiledModule) -> Result<Vec<u8>, BytecodeError> {
+    let mut w = Writer {
+        link_imports: true,
+        ..Writer::default()
+    };
+    write_module(&mut w, module)?;
+    w.finish()
 }
 
+/// Decodes a module encoded with its imports embedded.
+///
+/// # Errors
+///
+/// Bad magic or version, an unknown tag, truncated input, or imports
+/// recorded by path, which need [`decode_module_linked`].
 pub fn decode_module(bytes: &[u8]) -> Result<CompiledModule, BytecodeError> {
+    decode_with(bytes, None)
+}
+
+/// Fuzzing entry point: decodes `data` and, when it is a module, checks that
+/// encoding it and decoding the result gives the same bytes again.
+///
+/// # Panics
+///
+/// When the round trip is not stable, which is a bug.
+pub fn fuzz_roundtrip(data: &[u8]) {
+    let Ok(module) = decode_module(data) else {
+        return;
+    };
+    let Ok(encoded) = encode_module(&module) else {
+        return;
+    };
+    let decoded = decode_module(&encoded).expect("re-decode an encoded module");
+    let reencoded = encode_module(&decoded).expect("re-encode a decoded module");
+    assert_eq!(encoded, reencoded, "bytecode round trip is not stable");
+}
+
+/// Like [`decode_module`], fetching separately linked imports (at any depth)
+/// through `resolver`. Each path is resolved once and its module shared.
+/// From v10, each import's bytes must hash to the SHA-256 recorded when the
+/// importing module was encoded, so a rebuilt or tampered dependency fails
+/// with [`BytecodeError::ImportHashMismatch`].
+///
+/// # Errors
+///
+/// Malformed bytes, a resolver failure, an import cycle and a hash
+/// mismatch.
+pub fn decode_module_linked(
+    bytes: &[u8],
+    resolver: &dyn ModuleResolver,
+) -> Result<CompiledModule, BytecodeError> {
+    let mut linker = Linker {
+        resolver,
+        linked: HashMap::new(),
+        loading: Vec::new(),
+    };
+    decode_with(bytes, Some(&mut linker))
+}
+
+fn decode_with(
+    bytes: &[u8],
+    linker: Option<&mut Linker<'_>>,
+) -> Result<CompiledModule, BytecodeError> {
     let mut r = Reader::new(bytes);
+    r.linker = linker;
     let magic = r.read_fixed_4()?;
     if magic != MAGIC {
         return Err(BytecodeError::InvalidMagic(magic));
     }
     let version = r.read_u16()?;
-    if version != VERSION {
+    if !(MIN_VERSION..=VERSION).contains(&version) {
         return Err(BytecodeError::UnsupportedVersion(version));
     }
+    r.version = version;
+    if version >= COMPACT_VERSION {
+        r.read_string_pool()?;
+    }
     let module = read_module(&mut r)?;
     if !r.is_eof() {
         return Err(BytecodeError::InvalidTag {
@@ -76,17 +264,79 @@ pub fn decode_module(bytes: &[u8]) -> Result<CompiledModule, BytecodeError> {
     Ok(module)
 }
 
+/// [`encode_module`] written to `path`.
+///
+/// # Errors
+///
+/// As [`encode_module`], or the write failing.
 pub fn encode_to_path(path: &Path, module: &CompiledModule) -> Result<(), BytecodeError> {
     let encoded = encode_module(module)?;
     fs::write(path, encoded)?;
     Ok(())
 }
 
+/// [`decode_module`] of the file at `path`.
+///
+/// # Errors
+///
+/// As [`decode_module`], or the read failing.
 pub fn decode_from_path(path: &Path) -> Result<CompiledModule, BytecodeError> {
     let bytes = fs::read(path)?;
     decode_module(&bytes)
 }
 
+struct Linker<'r> {
+    resolver: &'r dyn ModuleResolver,
+    /// Modules already linked, with the SHA-256 of their bytes.
+    linked: HashMap<String, (Arc<CompiledModule>, [u8; 32])>,
+    /// Paths being decoded, outermost first.
+    loading: Vec<String>,
+}
+
+impl Linker<'_> {
+    /// The module at `path`, checked against `expected` when the importing
+    /// module recorded a hash.
+    fn link(
+        &mut self,
+        path: &str,
+        expected: Option<[u8; 32]>,
+    ) -> Result<Arc<CompiledModule>, BytecodeError> {
+        if let Some((module, found)) = self.linked.get(path) {
+            check_import_hash(path, expected, *found)?;
+            return Ok(Arc::clone(module));
+        }
+        if let Some(start) = self.loading.iter().position(|loading| loading == path) {
+            let mut chain = self.loading[start..].to_vec();
+            chain.push(path.to_owned());
+            return Err(BytecodeError::ImportCycle(chain));
+        }
+        self.loading.push(path.to_owned());
+        let bytes = self.resolver.resolve(path)?;
+        let found = sha256(&bytes);
+        check_import_hash(path, expected, found)?;
+        let module = Arc::new(decode_with(&bytes, Some(self))?);
+        self.loading.pop();
+        self.linked
+            .insert(path.to_owned(), (Arc::clone(&module), found));
+        Ok(module)
+    }
+}
+
+fn check_import_hash(
+    path: &str,
+    expected: Option<[u8; 32]>,
+    found: [u8; 32],
+) -> Result<(), BytecodeError> {
+    match expected {
+        Some(expected) if expected != found => Err(BytecodeError::ImportHashMismatch {
+            path: path.to_owned(),
+            expected,
+            found,
+        }),
+        _ => Ok(()),
+    }
+}
+
 fn write_module(w: &mut Writer, module: &CompiledModule) -> Result<(), BytecodeError> {
     w.write_string(module.name.as_ref())?;
     w.write_u32(module.init_func);
@@ -109,33 +359,70 @@ fn write_module(w: &mut Writer, module: &CompiledModule) -> Result<(), BytecodeE
         write_import(w, import)?;
     }
     w.write_u32(module.global_count);
+    w.write_len(module.consts.len(), "consts length")?;
+    for (name, value) in &module.consts {
+        w.write_string(name)?;
+        write_const(w, value)?;
+    }
+    w.write_len(module.tests.len(), "tests length")?;
+    for func in &module.tests {
+        w.write_u32(*func);
+    }
+    w.write_len(module.global_names.len(), "global names length")?;
+    for name in &module.global_names {
+        w.write_string(name)?;
+    }
     Ok(())
 }
 
-fn read_module(r: &mut Reader<'_>) -> Result<CompiledModule, BytecodeError> {
+fn read_module(r: &mut Reader<'_, '_>) -> Result<CompiledModule, BytecodeError> {
     let name = Arc::<str>::from(r.read_string("module.name")?.as_str());
     let init_func = r.read_u32()?;
     let function_count = r.read_len("functions length")?;
-    let mut functions = Vec::with_capacity(function_count);
+    let mut functions = Vec::with_capacity(r.capacity(function_count));
     for _ in 0..function_count {
         functions.push(read_function(r)?);
     }
     let function_global_count = r.read_len("function_globals length")?;
-    let mut function_globals = Vec::with_capacity(function_global_count);
+    let mut function_globals = Vec::with_capacity(r.capacity(function_global_count));
     for _ in 0..function_global_count {
         function_globals.push((r.read_u32()?, r.read_u32()?));
     }
     let export_count = r.read_len("exports length")?;
-    let mut exports = Vec::with_capacity(export_count);
+    let mut exports = Vec::with_capacity(r.capacity(export_count));
     for _ in 0..export_count {
         exports.push((r.read_string("export name")?, r.read_u32()?));
     }
     let import_count = r.read_len("imports length")?;
-    let mut imports = Vec::with_capacity(import_count);
+    let mut imports = Vec::with_capacity(r.capacity(import_count));
     for _ in 0..import_count {
         imports.push(read_import(r)?);
     }
     let global_count = r.read_u32()?;
+    let mut consts = Vec::new();
+    if r.version >= 3 {
+        let const_count = r.read_len("consts length")?;
+        consts.reserve(r.capacity(const_count));
+        for _ in 0..const_count {
+            consts.push((r.read_string("const name")?, read_const(r)?));
+        }
+    }
+    let mut tests = Vec::new();
+    if r.version >= 8 {
+        let test_count = r.read_len("tests length")?;
+        tests.reserve(r.capacity(test_count));
+        for _ in 0..test_count {
+            tests.push(r.read_u32()?);
+        }
+    }
+    let mut global_names = Vec::new();
+    if r.version >= 9 {
+        let name_count = r.read_len("global names length")?;
+        global_names.reserve(r.capacity(name_count));
+        for _ in 0..name_count {
+            global_names.push(r.read_string("global name")?);
+        }
+    }
 
     Ok(CompiledModule {
         name,
@@ -144,7 +431,10 @@ fn read_module(r: &mut Reader<'_>) -> Result<CompiledModule, BytecodeError> {
         function_globals,
         exports,
         imports,
+        consts,
         global_count,
+        tests,
+        global_names,
     })
 }
 
@@ -159,18 +449,56 @@ fn write_import(w: &mut Writer, import: &ImportBinding) -> Result<(), BytecodeEr
         w.write_string(name)?;
         w.write_u32(*destination);
     }
+    if w.link_imports {
+        w.write_u8(IMPORT_LINKED);
+        w.write_bytes(&sha256(&encode_module_separate(&import.module)?));
+        return Ok(());
+    }
+    w.write_u8(IMPORT_EMBEDDED);
     write_module(w, &import.module)
 }
 
-fn read_import(r: &mut Reader<'_>) -> Result<ImportBinding, BytecodeError> {
+fn read_import(r: &mut Reader<'_, '_>) -> Result<ImportBinding, BytecodeError> {
     let path = r.read_string("import.path")?;
     let alias = r.read_string("import.alias")?;
     let pair_count = r.read_len("import export_to_global length")?;
-    let mut export_to_global = Vec::with_capacity(pair_count);
+    let mut export_to_global = Vec::with_capacity(r.capacity(pair_count));
     for _ in 0..pair_count {
         export_to_global.push((r.read_string("import export name")?, r.read_u32()?));
     }
-    let module = Arc::new(read_module(r)?);
+    let kind = if r.version >= 5 {
+        r.read_u8()?
+    } else {
+        IMPORT_EMBEDDED
+    };
+    let module = match kind {
+        IMPORT_EMBEDDED => {
+            if r.depth == MAX_IMPORT_DEPTH {
+                return Err(BytecodeError::ImportTooDeep);
+            }
+            r.depth += 1;
+            let module = read_module(r);
+            r.depth -= 1;
+            Arc::new(module?)
+        }
+        IMPORT_LINKED => {
+            let expected = if r.version >= 10 {
+                Some(r.read_fixed_32()?)
+            } else {
+                None
+            };
+            match r.linker.as_deref_mut() {
+                Some(linker) => linker.link(&path, expected)?,
+                None => return Err(BytecodeError::UnresolvedImport(path)),
+            }
+        }
+        tag => {
+            return Err(BytecodeError::InvalidTag {
+                kind: "import",
+                tag,
+            });
+        }
+    };
     Ok(ImportBinding {
         path,
         alias,
@@ -193,7 +521,7 @@ fn write_function(w: &mut Writer, function: &CompiledFunction) -> Result<(), Byt
     Ok(())
 }
 
-fn read_function(r: &mut Reader<'_>) -> Result<CompiledFunction, BytecodeError> {
+fn read_function(r: &mut Reader<'_, '_>) -> Result<CompiledFunction, BytecodeError> {
     let id = r.read_u32()?;
     let local_count = r.read_u32()?;
     let arg_count = r.read_u32()?;
@@ -201,7 +529,7 @@ fn read_function(r: &mut Reader<'_>) -> Result<CompiledFunction, BytecodeError>
     let err_count = r.read_u32()?;
     let meta = read_fn_meta(r)?;
     let code_len = r.read_len("function code length")?;
-    let mut code = Vec::with_capacity(code_len);
+    let mut code = Vec::with_capacity(r.capacity(code_len));
     for _ in 0..code_len {
         code.push(read_instr(r)?);
     }
@@ -220,22 +548,101 @@ fn write_fn_meta(w: &mut Writer, meta: &FnMeta) -> Result<(), BytecodeError> {
     w.write_string(meta.name.as_ref())?;
     w.write_u32(meta.arg_count);
     w.write_u32(meta.ret_count);
-    write_retshape(w, &meta.retshape)
+    write_retshape(w, &meta.retshape)?;
+    w.write_len(meta.params.len(), "fn meta params length")?;
+    for param in &meta.params {
+        w.write_string(param)?;
+    }
+    match meta.timeout_ms {
+        Some(ms) => {
+            w.write_u8(1);
+            w.write_u64(ms);
+        }
+        None => w.write_u8(0),
+    }
+    let Some(debug) = &meta.debug else {
+        w.write_u8(0);
+        return Ok(());
+    };
+    w.write_u8(1);
+    match &debug.source {
+        Some(source) => {
+            w.write_u8(1);
+            w.write_string(source)?;
+        }
+        None => w.write_u8(0),
+    }
+    w.write_len(debug.lines.len(), "debug lines length")?;
+    for line in debug.lines.iter() {
+        w.write_u32(*line);
+    }
+    Ok(())
 }
 
-fn read_fn_meta(r: &mut Reader<'_>) -> Result<FnMeta, BytecodeError> {
+fn read_fn_meta(r: &mut Reader<'_, '_>) -> Result<FnMeta, BytecodeError> {
     let name = Arc::<str>::from(r.read_string("fn meta name")?.as_str());
     let arg_count = r.read_u32()?;
     let ret_count = r.read_u32()?;
     let retshape = read_retshape(r)?;
+    let mut params = Vec::new();
+    if r.version >= 2 {
+        let param_count = r.read_len("fn meta params length")?;
+        params.reserve(r.capacity(param_count));
+        for _ in 0..param_count {
+            params.push(Arc::<str>::from(r.read_string("fn meta param")?.as_str()));
+        }
+    }
+    let mut timeout_ms = None;
+    if r.version >= 4 {
+        match r.read_u8()? {
+            0 => {}
+            1 => timeout_ms = Some(r.read_u64()?),
+            tag => {
+                return Err(BytecodeError::InvalidTag {
+                    kind: "fn meta timeout",
+                    tag,
+                });
+            }
+        }
+    }
+    let debug = if r.version >= 6 && read_flag(r, "fn meta debug")? {
+        let source = if read_flag(r, "debug source")? {
+            Some(Arc::<str>::from(r.read_string("debug source")?.as_str()))
+        } else {
+            None
+        };
+        let line_count = r.read_len("debug lines length")?;
+        let mut lines = Vec::with_capacity(r.capacity(line_count));
+        for _ in 0..line_count {
+            lines.push(r.read_u32()?);
+        }
+        Some(Arc::new(DebugInfo {
+            source,
+            lines: lines.into(),
+        }))
+    } else {
+        None
+    };
     Ok(FnMeta {
         name,
+        params,
         arg_count,
         ret_count,
         retshape,
+        timeout_ms,
+        debug,
     })
 }
 
+/// A `0`/`1` presence byte.
+fn read_flag(r: &mut Reader<'_, '_>, kind: &'static str) -> Result<bool, BytecodeError> {
+    match r.read_u8()? {
+        0 => Ok(false),
+        1 => Ok(true),
+        tag => Err(BytecodeError::InvalidTag { kind, tag }),
+    }
+}
+
 fn write_retshape(w: &mut Writer, retshape: &RetShape) -> Result<(), BytecodeError> {
     match retshape {
         RetShape::Scalar => w.write_u8(0),
@@ -258,13 +665,13 @@ fn write_retshape(w: &mut Writer, retshape: &RetShape) -> Result<(), BytecodeErr
     Ok(())
 }
 
-fn read_retshape(r: &mut Reader<'_>) -> Result<RetShape, BytecodeError> {
+fn read_retshape(r: &mut Reader<'_, '_>) -> Result<RetShape, BytecodeError> {
     let tag = r.read_u8()?;
     match tag {
         0 => Ok(RetShape::Scalar),
         1 => {
             let len = r.read_len("retshape either length")?;
-            let mut values = Vec::with_capacity(len);
+            let mut values = Vec::with_capacity(r.capacity(len));
             for _ in 0..len {
                 values.push(r.read_string("retshape either value")?);
             }
@@ -272,7 +679,7 @@ fn read_retshape(r: &mut Reader<'_>) -> Result<RetShape, BytecodeError> {
         }
         2 => {
             let len = r.read_len("retshape record length")?;
-            let mut values = Vec::with_capacity(len);
+            let mut values = Vec::with_capacity(r.capacity(len));
             for _ in 0..len {
                 values.push(r.read_string("retshape record value")?);
             }
@@ -287,33 +694,24 @@ fn read_retshape(r: &mut Reader<'_>) -> Result<RetShape, BytecodeError> {
 }
 
 fn write_slot(w: &mut Writer, slot: Slot) {
-    match slot {
-        Slot::Local(v) => {
-            w.write_u8(0);
-            w.write_u32(v);
-        }
-        Slot::Global(v) => {
-            w.write_u8(1);
-            w.write_u32(v);
-        }
-        Slot::Arg(v) => {
-            w.write_u8(2);
-            w.write_u32(v);
-        }
-        Slot::Ret(v) => {
-            w.write_u8(3);
-            w.write_u32(v);
-        }
-        Slot::Err(v) => {
-            w.write_u8(4);
-            w.write_u32(v);
-        }
-    }
+    let (tag, index) = match slot {
+        Slot::Local(v) => (0, v),
+        Slot::Global(v) => (1, v),
+        Slot::Arg(v) => (2, v),
+        Slot::Ret(v) => (3, v),
+        Slot::Err(v) => (4, v),
+    };
+    w.write_varint((u64::from(index) << 3) | tag);
 }
 
-fn read_slot(r: &mut Reader<'_>) -> Result<Slot, BytecodeError> {
-    let tag = r.read_u8()?;
-    let value = r.read_u32()?;
+fn read_slot(r: &mut Reader<'_, '_>) -> Result<Slot, BytecodeError> {
+    let (tag, value) = if r.version >= COMPACT_VERSION {
+        let packed = r.read_varint()?;
+        let index = u32::try_from(packed >> 3).map_err(|_| BytecodeError::Overflow("slot"))?;
+        ((packed & 0b111) as u8, index)
+    } else {
+        (r.read_u8()?, r.read_u32()?)
+    };
     match tag {
         0 => Ok(Slot::Local(value)),
         1 => Ok(Slot::Global(value)),
@@ -329,21 +727,30 @@ fn write_const(w: &mut Writer, value: &ConstValue) -> Result<(), BytecodeError>
         ConstValue::Null => w.write_u8(0),
         ConstValue::Bool(v) => {
             w.write_u8(1);
-            w.write_u8(if *v { 1 } else { 0 });
+            w.write_u8(u8::from(*v));
         }
         ConstValue::Num(v) => {
             w.write_u8(2);
             w.write_f64(*v);
         }
+        ConstValue::Int(v) => {
+            w.write_u8(5);
+            w.write_bytes(&v.to_le_bytes());
+        }
         ConstValue::Str(v) => {
             w.write_u8(3);
             w.write_string(v)?;
         }
+        ConstValue::Bytes(v) => {
+            w.write_u8(4);
+            w.write_len(v.len(), "const bytes length")?;
+            w.write_bytes(v);
+        }
     }
     Ok(())
 }
 
-fn read_const(r: &mut Reader<'_>) -> Result<ConstValue, BytecodeError> {
+fn read_const(r: &mut Reader<'_, '_>) -> Result<ConstValue, BytecodeError> {
     let tag = r.read_u8()?;
     match tag {
         0 => Ok(ConstValue::Null),
@@ -352,10 +759,21 @@ fn read_const(r: &mut Reader<'_>) -> Result<ConstValue, BytecodeError> {
         3 => Ok(ConstValue::Str(Arc::<str>::from(
             r.read_string("const string")?.as_str(),
         ))),
+        4 => {
+            let len = r.read_len("const bytes length")?;
+            Ok(ConstValue::Bytes(Arc::from(r.read_exact(len)?)))
+        }
+        5 => {
+            let mut bytes = [0u8; 8];
+            bytes.copy_from_slice(r.read_exact(8)?);
+            Ok(ConstValue::Int(i64::from_le_bytes(bytes)))
+        }
         _ => Err(BytecodeError::InvalidTag { kind: "const", tag }),
     }
 }
 
+// One arm per instruction.
+#[allow(clippy::too_many_lines)]
 fn write_instr(w: &mut Writer, instr: &Instr) -> Result<(), BytecodeError> {
     match instr {
         Instr::StoreConst { slot, value } => {
@@ -404,6 +822,12 @@ fn write_instr(w: &mut Writer, instr: &Instr) -> Result<(), BytecodeError> {
             write_slot(w, *b);
             write_slot(w, *out);
         }
+        Instr::Cmp { a, b, out } => {
+            w.write_u8(36);
+            write_slot(w, *a);
+            write_slot(w, *b);
+            write_slot(w, *out);
+        }
         Instr::Jump { target } => {
             w.write_u8(8);
             w.write_usize_as_u32(*target, "jump target")?;
@@ -433,11 +857,164 @@ fn write_instr(w: &mut Writer, instr: &Instr) -> Result<(), BytecodeError> {
             write_slot(w, *value);
         }
         Instr::Exit => w.write_u8(12),
-        Instr::Throw { code, msg } => {
+        Instr::Throw {
+            code,
+            msg,
+            cause: None,
+            data: None,
+        } => {
             w.write_u8(13);
             w.write_string(code)?;
             w.write_string(msg)?;
         }
+        Instr::Throw {
+            code,
+            msg,
+            cause: Some(cause),
+            data: None,
+        } => {
+            w.write_u8(26);
+            w.write_string(code)?;
+            w.write_string(msg)?;
+            write_slot(w, *cause);
+        }
+        Instr::Throw {
+            code,
+            msg,
+            cause,
+            data: Some(data),
+        } => {
+            w.write_u8(76);
+            w.write_string(code)?;
+            w.write_string(msg)?;
+            match cause {
+                Some(cause) => {
+                    w.write_u8(1);
+                    write_slot(w, *cause);
+                }
+                None => w.write_u8(0),
+            }
+            write_slot(w, *data);
+        }
+        Instr::ErrorData { err, out } => {
+            w.write_u8(77);
+            write_slot(w, *err);
+            write_slot(w, *out);
+        }
+        Instr::Rethrow { err } => {
+            w.write_u8(78);
+            write_slot(w, *err);
+        }
+        Instr::InvokeMulti {
+            fn_slot,
+            args,
+            outs,
+        } => {
+            w.write_u8(79);
+            write_slot(w, *fn_slot);
+            w.write_len(args.len(), "invoke args length")?;
+            for slot in args {
+                write_slot(w, *slot);
+            }
+            w.write_len(outs.len(), "invoke outs length")?;
+            for slot in outs {
+                write_slot(w, *slot);
+            }
+        }
+        Instr::TailInvoke { fn_slot, args } => {
+            w.write_u8(80);
+            write_slot(w, *fn_slot);
+            w.write_len(args.len(), "invoke args length")?;
+            for slot in args {
+                write_slot(w, *slot);
+            }
+        }
+        Instr::ErrorWrap {
+            err,
+            code,
+            msg,
+            out,
+        } => {
+            w.write_u8(27);
+            write_slot(w, *err);
+            w.write_string(code)?;
+            w.write_string(msg)?;
+            write_slot(w, *out);
+        }
+        Instr::ErrorCause { err, out } => {
+            w.write_u8(28);
+            write_slot(w, *err);
+            write_slot(w, *out);
+        }
+        Instr::ErrorCode { err, out } => {
+            w.write_u8(45);
+            write_slot(w, *err);
+            write_slot(w, *out);
+        }
+        Instr::ErrorMsg { err, out } => {
+            w.write_u8(46);
+            write_slot(w, *err);
+            write_slot(w, *out);
+        }
+        Instr::MakeClosure {
+            func,
+            captures,
+            out,
+        } => {
+            w.write_u8(47);
+            write_slot(w, *func);
+            w.write_len(captures.len(), "closure captures length")?;
+            for capture in captures {
+                write_slot(w, *capture);
+            }
+            write_slot(w, *out);
+        }
+        Instr::IntParse { value, out } => {
+            w.write_u8(48);
+            write_slot(w, *value);
+            write_slot(w, *out);
+        }
+        Instr::Mod { a, b, out } => {
+            w.write_u8(49);
+            write_slot(w, *a);
+            write_slot(w, *b);
+            write_slot(w, *out);
+        }
+        Instr::Pow { a, b, out } => {
+            w.write_u8(50);
+            write_slot(w, *a);
+            write_slot(w, *b);
+            write_slot(w, *out);
+        }
+        Instr::Neg { value, out } => {
+            w.write_u8(51);
+            write_slot(w, *value);
+            write_slot(w, *out);
+        }
+        Instr::Ne { a, b, out } => {
+            w.write_u8(52);
+            write_slot(w, *a);
+            write_slot(w, *b);
+            write_slot(w, *out);
+        }
+        Instr::Le { a, b, out } => {
+            w.write_u8(53);
+            write_slot(w, *a);
+            write_slot(w, *b);
+            write_slot(w, *out);
+        }
+        Instr::Gt { a, b, out } => {
+            w.write_u8(54);
+            write_slot(w, *a);
+            write_slot(w, *b);
+            write_slot(w, *out);
+        }
+        Instr::Ge { a, b, out } => {
+            w.write_u8(55);
+            write_slot(w, *a);
+            write_slot(w, *b);
+            write_slot(w, *out);
+        }
         Instr::TryPush { handler_pc } => {
             w.write_u8(14);
             w.write_usize_as_u32(*handler_pc, "try handler pc")?;
@@ -471,6 +1048,109 @@ fn write_instr(w: &mut Writer, instr: &Instr) -> Result<(), BytecodeError> {
             write_slot(w, *key);
             write_slot(w, *out);
         }
+        Instr::ObjEntries { obj, out, len } => {
+            w.write_u8(35);
+            write_slot(w, *obj);
+            write_slot(w, *out);
+            match len {
+                Some(len) => {
+                    w.write_u8(1);
+                    write_slot(w, *len);
+                }
+                None => w.write_u8(0),
+            }
+        }
+        Instr::ObjKeys { obj, out } => {
+            w.write_u8(56);
+            write_slot(w, *obj);
+            write_slot(w, *out);
+        }
+        Instr::ObjDel { obj, key, out } => {
+            w.write_u8(57);
+            write_slot(w, *obj);
+            write_slot(w, *key);
+            write_slot(w, *out);
+        }
+        Instr::ObjLen { obj, out } => {
+            w.write_u8(58);
+            write_slot(w, *obj);
+            write_slot(w, *out);
+        }
+        Instr::ObjGetPath {
+            obj,
+            path,
+            out,
+            safe,
+        } => {
+            w.write_u8(59);
+            write_slot(w, *obj);
+            write_slot(w, *path);
+            write_slot(w, *out);
+            w.write_u8(u8::from(*safe));
+        }
+        Instr::ObjSetPath {
+            obj,
+            path,
+            value,
+            out,
+            safe,
+        } => {
+            w.write_u8(60);
+            write_slot(w, *obj);
+            write_slot(w, *path);
+            write_slot(w, *value);
+            write_slot(w, *out);
+            w.write_u8(u8::from(*safe));
+        }
+        Instr::ListNew { out } => {
+            w.write_u8(38);
+            write_slot(w, *out);
+        }
+        Instr::ListPush { list, value, out } => {
+            w.write_u8(39);
+            write_slot(w, *list);
+            write_slot(w, *value);
+            write_slot(w, *out);
+        }
+        Instr::ListGet { list, index, out } => {
+            w.write_u8(40);
+            write_slot(w, *list);
+            write_slot(w, *index);
+            write_slot(w, *out);
+        }
+        Instr::ListSet {
+            list,
+            index,
+            value,
+            out,
+        } => {
+            w.write_u8(41);
+            write_slot(w, *list);
+            write_slot(w, *index);
+            write_slot(w, *value);
+            write_slot(w, *out);
+        }
+        Instr::ListLen { list, out } => {
+            w.write_u8(42);
+            write_slot(w, *list);
+            write_slot(w, *out);
+        }
+        Instr::StrFormat { tmpl, args, out } => {
+            w.write_u8(43);
+            write_slot(w, *tmpl);
+            write_slot(w, *args);
+            write_slot(w, *out);
+        }
+        Instr::InvokeDynamic {
+            fn_slot,
+            arglist,
+            out,
+        } => {
+            w.write_u8(44);
+            write_slot(w, *fn_slot);
+            write_slot(w, *arglist);
+            write_slot(w, *out);
+        }
         Instr::StrConcat { a, b, out } => {
             w.write_u8(20);
             write_slot(w, *a);
@@ -482,37 +1162,313 @@ fn write_instr(w: &mut Writer, instr: &Instr) -> Result<(), BytecodeError> {
             write_slot(w, *value);
             write_slot(w, *out);
         }
-        Instr::HostPrint { slot } => {
-            w.write_u8(22);
-            write_slot(w, *slot);
+        Instr::StrSub {
+            value,
+            start,
+            end,
+            out,
+        } => {
+            w.write_u8(61);
+            write_slot(w, *value);
+            write_slot(w, *start);
+            match end {
+                Some(end) => {
+                    w.write_u8(1);
+                    write_slot(w, *end);
+                }
+                None => w.write_u8(0),
+            }
+            write_slot(w, *out);
         }
-    }
-    Ok(())
-}
-
-fn read_instr(r: &mut Reader<'_>) -> Result<Instr, BytecodeError> {
-    let tag = r.read_u8()?;
-    match tag {
-        0 => Ok(Instr::StoreConst {
-            slot: read_slot(r)?,
-            value: read_const(r)?,
-        }),
-        1 => Ok(Instr::Move {
-            from: read_slot(r)?,
-            to: read_slot(r)?,
-        }),
-        2 => Ok(Instr::Add {
-            a: read_slot(r)?,
-            b: read_slot(r)?,
-            out: read_slot(r)?,
-        }),
-        3 => Ok(Instr::Sub {
-            a: read_slot(r)?,
-            b: read_slot(r)?,
-            out: read_slot(r)?,
-        }),
-        4 => Ok(Instr::Mul {
-            a: read_slot(r)?,
+        Instr::StrFind {
+            value,
+            pattern,
+            out,
+        } => {
+            w.write_u8(62);
+            write_slot(w, *value);
+            write_slot(w, *pattern);
+            write_slot(w, *out);
+        }
+        Instr::StrReplace {
+            value,
+            pattern,
+            with,
+            out,
+        } => {
+            w.write_u8(63);
+            write_slot(w, *value);
+            write_slot(w, *pattern);
+            write_slot(w, *with);
+            write_slot(w, *out);
+        }
+        Instr::StrContains {
+            value,
+            pattern,
+            out,
+        } => {
+            w.write_u8(64);
+            write_slot(w, *value);
+            write_slot(w, *pattern);
+            write_slot(w, *out);
+        }
+        Instr::StrUpper { value, out } => {
+            w.write_u8(67);
+            write_slot(w, *value);
+            write_slot(w, *out);
+        }
+        Instr::StrLower { value, out } => {
+            w.write_u8(68);
+            write_slot(w, *value);
+            write_slot(w, *out);
+        }
+        Instr::StrTrim { value, out } => {
+            w.write_u8(69);
+            write_slot(w, *value);
+            write_slot(w, *out);
+        }
+        Instr::StrTrimStart { value, out } => {
+            w.write_u8(70);
+            write_slot(w, *value);
+            write_slot(w, *out);
+        }
+        Instr::StrTrimEnd { value, out } => {
+            w.write_u8(71);
+            write_slot(w, *value);
+            write_slot(w, *out);
+        }
+        Instr::TypeOf { value, out } => {
+            w.write_u8(74);
+            write_slot(w, *value);
+            write_slot(w, *out);
+        }
+        Instr::IsType { value, kind, out } => {
+            w.write_u8(75);
+            write_slot(w, *value);
+            w.write_u8(*kind as u8);
+            write_slot(w, *out);
+        }
+        Instr::NumParse { value, out } => {
+            w.write_u8(72);
+            write_slot(w, *value);
+            write_slot(w, *out);
+        }
+        Instr::NumFormat {
+            value,
+            precision,
+            width,
+            out,
+        } => {
+            w.write_u8(73);
+            write_slot(w, *value);
+            for arg in [precision, width] {
+                match arg {
+                    Some(slot) => {
+                        w.write_u8(1);
+                        write_slot(w, *slot);
+                    }
+                    None => w.write_u8(0),
+                }
+            }
+            write_slot(w, *out);
+        }
+        Instr::StrSplit { value, sep, out } => {
+            w.write_u8(65);
+            write_slot(w, *value);
+            write_slot(w, *sep);
+            write_slot(w, *out);
+        }
+        Instr::StrJoin { list, sep, out } => {
+            w.write_u8(66);
+            write_slot(w, *list);
+            write_slot(w, *sep);
+            write_slot(w, *out);
+        }
+        Instr::BytesFromStr { value, out } => {
+            w.write_u8(30);
+            write_slot(w, *value);
+            write_slot(w, *out);
+        }
+        Instr::BytesToStr { value, out } => {
+            w.write_u8(31);
+            write_slot(w, *value);
+            write_slot(w, *out);
+        }
+        Instr::BytesLen { value, out } => {
+            w.write_u8(32);
+            write_slot(w, *value);
+            write_slot(w, *out);
+        }
+        Instr::BytesConcat { a, b, out } => {
+            w.write_u8(33);
+            write_slot(w, *a);
+            write_slot(w, *b);
+            write_slot(w, *out);
+        }
+        Instr::BytesSlice {
+            value,
+            start,
+            end,
+            out,
+        } => {
+            w.write_u8(34);
+            write_slot(w, *value);
+            write_slot(w, *start);
+            match end {
+                Some(end) => {
+                    w.write_u8(1);
+                    write_slot(w, *end);
+                }
+                None => w.write_u8(0),
+            }
+            write_slot(w, *out);
+        }
+        Instr::FnMeta { func, out } => {
+            w.write_u8(24);
+            write_slot(w, *func);
+            write_slot(w, *out);
+        }
+        Instr::FnBind { func, args, out } => {
+            w.write_u8(25);
+            write_slot(w, *func);
+            w.write_len(args.len(), "fn bind args length")?;
+            for arg in args {
+                write_slot(w, *arg);
+            }
+            write_slot(w, *out);
+        }
+        Instr::Ext { op, operands } => {
+            w.write_u8(37);
+            w.write_u16(*op);
+            w.write_len(operands.len(), "ext operands length")?;
+            for operand in operands {
+                write_slot(w, *operand);
+            }
+        }
+        Instr::HostCall { name, args, out } => {
+            w.write_u8(29);
+            w.write_string(name)?;
+            w.write_len(args.len(), "host call args length")?;
+            for arg in args {
+                write_slot(w, *arg);
+            }
+            write_slot(w, *out);
+        }
+        Instr::HostPrint {
+            slot,
+            stream: PrintStream::Stdout,
+            level: PrintLevel::Info,
+        } => {
+            w.write_u8(22);
+            write_slot(w, *slot);
+        }
+        Instr::HostPrint {
+            slot,
+            stream,
+            level,
+        } => {
+            w.write_u8(23);
+            write_slot(w, *slot);
+            w.write_u8(match stream {
+                PrintStream::Stdout => 0,
+                PrintStream::Stderr => 1,
+            });
+            w.write_u8(match level {
+                PrintLevel::Debug => 0,
+                PrintLevel::Info => 1,
+                PrintLevel::Warn => 2,
+                PrintLevel::Error => 3,
+            });
+        }
+        Instr::HostTime { clock, out } => {
+            w.write_u8(81);
+            w.write_u8(match clock {
+                HostClock::Wall => 0,
+                HostClock::Monotonic => 1,
+            });
+            write_slot(w, *out);
+        }
+        Instr::HostArgs { out } => {
+            w.write_u8(89);
+            write_slot(w, *out);
+        }
+        Instr::HostReadLine { out } => {
+            w.write_u8(91);
+            write_slot(w, *out);
+        }
+        Instr::HostReadAll { out } => {
+            w.write_u8(92);
+            write_slot(w, *out);
+        }
+        Instr::HostEnvVar { key, out } => {
+            w.write_u8(90);
+            write_slot(w, *key);
+            write_slot(w, *out);
+        }
+        Instr::HostRandom { out } => {
+            w.write_u8(82);
+            write_slot(w, *out);
+        }
+        Instr::HostRandomInt { lo, hi, out } => {
+            w.write_u8(83);
+            write_slot(w, *lo);
+            write_slot(w, *hi);
+            write_slot(w, *out);
+        }
+        Instr::HostFsRead { path, out } => {
+            w.write_u8(84);
+            write_slot(w, *path);
+            write_slot(w, *out);
+        }
+        Instr::HostFsWrite { path, data } => {
+            w.write_u8(85);
+            write_slot(w, *path);
+            write_slot(w, *data);
+        }
+        Instr::HostFsExists { path, out } => {
+            w.write_u8(86);
+            write_slot(w, *path);
+            write_slot(w, *out);
+        }
+        Instr::JsonParse { value, out } => {
+            w.write_u8(87);
+            write_slot(w, *value);
+            write_slot(w, *out);
+        }
+        Instr::JsonStringify { value, out } => {
+            w.write_u8(88);
+            write_slot(w, *value);
+            write_slot(w, *out);
+        }
+    }
+    Ok(())
+}
+
+// One arm per instruction tag.
+#[allow(clippy::too_many_lines)]
+fn read_instr(r: &mut Reader<'_, '_>) -> Result<Instr, BytecodeError> {
+    let tag = r.read_u8()?;
+    match tag {
+        0 => Ok(Instr::StoreConst {
+            slot: read_slot(r)?,
+            value: read_const(r)?,
+        }),
+        1 => Ok(Instr::Move {
+            from: read_slot(r)?,
+            to: read_slot(r)?,
+        }),
+        2 => Ok(Instr::Add {
+            a: read_slot(r)?,
+            b: read_slot(r)?,
+            out: read_slot(r)?,
+        }),
+        3 => Ok(Instr::Sub {
+            a: read_slot(r)?,
+            b: read_slot(r)?,
+            out: read_slot(r)?,
+        }),
+        4 => Ok(Instr::Mul {
+            a: read_slot(r)?,
             b: read_slot(r)?,
             out: read_slot(r)?,
         }),
@@ -544,7 +1500,7 @@ fn read_instr(r: &mut Reader<'_>) -> Result<Instr, BytecodeError> {
         10 => {
             let fn_slot = read_slot(r)?;
             let arg_count = r.read_len("invoke args length")?;
-            let mut args = Vec::with_capacity(arg_count);
+            let mut args = Vec::with_capacity(r.capacity(arg_count));
             for _ in 0..arg_count {
                 args.push(read_slot(r)?);
             }
@@ -562,6 +1518,8 @@ fn read_instr(r: &mut Reader<'_>) -> Result<Instr, BytecodeError> {
         13 => Ok(Instr::Throw {
             code: r.read_string("throw.code")?,
             msg: r.read_string("throw.msg")?,
+            cause: None,
+            data: None,
         }),
         14 => Ok(Instr::TryPush {
             handler_pc: usize::try_from(r.read_u32()?)
@@ -596,19 +1554,472 @@ fn read_instr(r: &mut Reader<'_>) -> Result<Instr, BytecodeError> {
         }),
         22 => Ok(Instr::HostPrint {
             slot: read_slot(r)?,
+            stream: PrintStream::Stdout,
+            level: PrintLevel::Info,
+        }),
+        23 => {
+            let slot = read_slot(r)?;
+            let stream = match r.read_u8()? {
+                0 => PrintStream::Stdout,
+                1 => PrintStream::Stderr,
+                tag => {
+                    return Err(BytecodeError::InvalidTag {
+                        kind: "print stream",
+                        tag,
+                    });
+                }
+            };
+            let level = match r.read_u8()? {
+                0 => PrintLevel::Debug,
+                1 => PrintLevel::Info,
+                2 => PrintLevel::Warn,
+                3 => PrintLevel::Error,
+                tag => {
+                    return Err(BytecodeError::InvalidTag {
+                        kind: "print level",
+                        tag,
+                    });
+                }
+            };
+            Ok(Instr::HostPrint {
+                slot,
+                stream,
+                level,
+            })
+        }
+        81 => {
+            let clock = match r.read_u8()? {
+                0 => HostClock::Wall,
+                1 => HostClock::Monotonic,
+                tag => {
+                    return Err(BytecodeError::InvalidTag {
+                        kind: "host clock",
+                        tag,
+                    });
+                }
+            };
+            Ok(Instr::HostTime {
+                clock,
+                out: read_slot(r)?,
+            })
+        }
+        82 => Ok(Instr::HostRandom { out: read_slot(r)? }),
+        89 => Ok(Instr::HostArgs { out: read_slot(r)? }),
+        91 => Ok(Instr::HostReadLine { out: read_slot(r)? }),
+        92 => Ok(Instr::HostReadAll { out: read_slot(r)? }),
+        90 => Ok(Instr::HostEnvVar {
+            key: read_slot(r)?,
+            out: read_slot(r)?,
+        }),
+        83 => Ok(Instr::HostRandomInt {
+            lo: read_slot(r)?,
+            hi: read_slot(r)?,
+            out: read_slot(r)?,
+        }),
+        84 => Ok(Instr::HostFsRead {
+            path: read_slot(r)?,
+            out: read_slot(r)?,
+        }),
+        85 => Ok(Instr::HostFsWrite {
+            path: read_slot(r)?,
+            data: read_slot(r)?,
+        }),
+        86 => Ok(Instr::HostFsExists {
+            path: read_slot(r)?,
+            out: read_slot(r)?,
+        }),
+        87 => Ok(Instr::JsonParse {
+            value: read_slot(r)?,
+            out: read_slot(r)?,
+        }),
+        88 => Ok(Instr::JsonStringify {
+            value: read_slot(r)?,
+            out: read_slot(r)?,
+        }),
+        24 => Ok(Instr::FnMeta {
+            func: read_slot(r)?,
+            out: read_slot(r)?,
+        }),
+        25 => {
+            let func = read_slot(r)?;
+            let arg_count = r.read_len("fn bind args length")?;
+            let mut args = Vec::with_capacity(r.capacity(arg_count));
+            for _ in 0..arg_count {
+                args.push(read_slot(r)?);
+            }
+            Ok(Instr::FnBind {
+                func,
+                args,
+                out: read_slot(r)?,
+            })
+        }
+        26 => Ok(Instr::Throw {
+            code: r.read_string("throw.code")?,
+            msg: r.read_string("throw.msg")?,
+            cause: Some(read_slot(r)?),
+            data: None,
+        }),
+        76 => Ok(Instr::Throw {
+            code: r.read_string("throw.code")?,
+            msg: r.read_string("throw.msg")?,
+            cause: match r.read_u8()? {
+                0 => None,
+                _ => Some(read_slot(r)?),
+            },
+            data: Some(read_slot(r)?),
+        }),
+        77 => Ok(Instr::ErrorData {
+            err: read_slot(r)?,
+            out: read_slot(r)?,
+        }),
+        78 => Ok(Instr::Rethrow { err: read_slot(r)? }),
+        79 => {
+            let fn_slot = read_slot(r)?;
+            let mut slots = |what| -> Result<Vec<Slot>, BytecodeError> {
+                let count = r.read_len(what)?;
+                let mut slots = Vec::with_capacity(r.capacity(count));
+                for _ in 0..count {
+                    slots.push(read_slot(r)?);
+                }
+                Ok(slots)
+            };
+            let args = slots("invoke args length")?;
+            let outs = slots("invoke outs length")?;
+            Ok(Instr::InvokeMulti {
+                fn_slot,
+                args,
+                outs,
+            })
+        }
+        80 => {
+            let fn_slot = read_slot(r)?;
+            let count = r.read_len("invoke args length")?;
+            let mut args = Vec::with_capacity(r.capacity(count));
+            for _ in 0..count {
+                args.push(read_slot(r)?);
+            }
+            Ok(Instr::TailInvoke { fn_slot, args })
+        }
+        27 => Ok(Instr::ErrorWrap {
+            err: read_slot(r)?,
+            code: r.read_string("error_wrap.code")?,
+            msg: r.read_string("error_wrap.msg")?,
+            out: read_slot(r)?,
+        }),
+        28 => Ok(Instr::ErrorCause {
+            err: read_slot(r)?,
+            out: read_slot(r)?,
+        }),
+        29 => {
+            let name = r.read_string("host_call.name")?;
+            let arg_count = r.read_len("host call args length")?;
+            let mut args = Vec::with_capacity(r.capacity(arg_count));
+            for _ in 0..arg_count {
+                args.push(read_slot(r)?);
+            }
+            Ok(Instr::HostCall {
+                name,
+                args,
+                out: read_slot(r)?,
+            })
+        }
+        30 => Ok(Instr::BytesFromStr {
+            value: read_slot(r)?,
+            out: read_slot(r)?,
+        }),
+        31 => Ok(Instr::BytesToStr {
+            value: read_slot(r)?,
+            out: read_slot(r)?,
+        }),
+        32 => Ok(Instr::BytesLen {
+            value: read_slot(r)?,
+            out: read_slot(r)?,
+        }),
+        33 => Ok(Instr::BytesConcat {
+            a: read_slot(r)?,
+            b: read_slot(r)?,
+            out: read_slot(r)?,
+        }),
+        34 => {
+            let value = read_slot(r)?;
+            let start = read_slot(r)?;
+            let end = match r.read_u8()? {
+                0 => None,
+                _ => Some(read_slot(r)?),
+            };
+            Ok(Instr::BytesSlice {
+                value,
+                start,
+                end,
+                out: read_slot(r)?,
+            })
+        }
+        35 => Ok(Instr::ObjEntries {
+            obj: read_slot(r)?,
+            out: read_slot(r)?,
+            len: match r.read_u8()? {
+                0 => None,
+                _ => Some(read_slot(r)?),
+            },
+        }),
+        36 => Ok(Instr::Cmp {
+            a: read_slot(r)?,
+            b: read_slot(r)?,
+            out: read_slot(r)?,
+        }),
+        37 => {
+            let op = r.read_u16()?;
+            let count = r.read_len("ext operands length")?;
+            let mut operands = Vec::with_capacity(r.capacity(count));
+            for _ in 0..count {
+                operands.push(read_slot(r)?);
+            }
+            Ok(Instr::Ext { op, operands })
+        }
+        38 => Ok(Instr::ListNew { out: read_slot(r)? }),
+        39 => Ok(Instr::ListPush {
+            list: read_slot(r)?,
+            value: read_slot(r)?,
+            out: read_slot(r)?,
+        }),
+        40 => Ok(Instr::ListGet {
+            list: read_slot(r)?,
+            index: read_slot(r)?,
+            out: read_slot(r)?,
+        }),
+        41 => Ok(Instr::ListSet {
+            list: read_slot(r)?,
+            index: read_slot(r)?,
+            value: read_slot(r)?,
+            out: read_slot(r)?,
+        }),
+        42 => Ok(Instr::ListLen {
+            list: read_slot(r)?,
+            out: read_slot(r)?,
+        }),
+        43 => Ok(Instr::StrFormat {
+            tmpl: read_slot(r)?,
+            args: read_slot(r)?,
+            out: read_slot(r)?,
+        }),
+        44 => Ok(Instr::InvokeDynamic {
+            fn_slot: read_slot(r)?,
+            arglist: read_slot(r)?,
+            out: read_slot(r)?,
+        }),
+        45 => Ok(Instr::ErrorCode {
+            err: read_slot(r)?,
+            out: read_slot(r)?,
+        }),
+        46 => Ok(Instr::ErrorMsg {
+            err: read_slot(r)?,
+            out: read_slot(r)?,
+        }),
+        47 => {
+            let func = read_slot(r)?;
+            let capture_count = r.read_len("closure captures length")?;
+            let mut captures = Vec::with_capacity(r.capacity(capture_count));
+            for _ in 0..capture_count {
+                captures.push(read_slot(r)?);
+            }
+            Ok(Instr::MakeClosure {
+                func,
+                captures,
+                out: read_slot(r)?,
+            })
+        }
+        48 => Ok(Instr::IntParse {
+            value: read_slot(r)?,
+            out: read_slot(r)?,
+        }),
+        49 => Ok(Instr::Mod {
+            a: read_slot(r)?,
+            b: read_slot(r)?,
+            out: read_slot(r)?,
+        }),
+        50 => Ok(Instr::Pow {
+            a: read_slot(r)?,
+            b: read_slot(r)?,
+            out: read_slot(r)?,
+        }),
+        51 => Ok(Instr::Neg {
+            value: read_slot(r)?,
+            out: read_slot(r)?,
+        }),
+        52 => Ok(Instr::Ne {
+            a: read_slot(r)?,
+            b: read_slot(r)?,
+            out: read_slot(r)?,
+        }),
+        53 => Ok(Instr::Le {
+            a: read_slot(r)?,
+            b: read_slot(r)?,
+            out: read_slot(r)?,
+        }),
+        54 => Ok(Instr::Gt {
+            a: read_slot(r)?,
+            b: read_slot(r)?,
+            out: read_slot(r)?,
+        }),
+        55 => Ok(Instr::Ge {
+            a: read_slot(r)?,
+            b: read_slot(r)?,
+            out: read_slot(r)?,
+        }),
+        56 => Ok(Instr::ObjKeys {
+            obj: read_slot(r)?,
+            out: read_slot(r)?,
+        }),
+        57 => Ok(Instr::ObjDel {
+            obj: read_slot(r)?,
+            key: read_slot(r)?,
+            out: read_slot(r)?,
+        }),
+        58 => Ok(Instr::ObjLen {
+            obj: read_slot(r)?,
+            out: read_slot(r)?,
+        }),
+        59 => Ok(Instr::ObjGetPath {
+            obj: read_slot(r)?,
+            path: read_slot(r)?,
+            out: read_slot(r)?,
+            safe: r.read_u8()? != 0,
+        }),
+        60 => Ok(Instr::ObjSetPath {
+            obj: read_slot(r)?,
+            path: read_slot(r)?,
+            value: read_slot(r)?,
+            out: read_slot(r)?,
+            safe: r.read_u8()? != 0,
+        }),
+        61 => {
+            let value = read_slot(r)?;
+            let start = read_slot(r)?;
+            let end = match r.read_u8()? {
+                0 => None,
+                _ => Some(read_slot(r)?),
+            };
+            Ok(Instr::StrSub {
+                value,
+                start,
+                end,
+                out: read_slot(r)?,
+            })
+        }
+        62 => Ok(Instr::StrFind {
+            value: read_slot(r)?,
+            pattern: read_slot(r)?,
+            out: read_slot(r)?,
+        }),
+        63 => Ok(Instr::StrReplace {
+            value: read_slot(r)?,
+            pattern: read_slot(r)?,
+            with: read_slot(r)?,
+            out: read_slot(r)?,
+        }),
+        64 => Ok(Instr::StrContains {
+            value: read_slot(r)?,
+            pattern: read_slot(r)?,
+            out: read_slot(r)?,
+        }),
+        67 => Ok(Instr::StrUpper {
+            value: read_slot(r)?,
+            out: read_slot(r)?,
+        }),
+        68 => Ok(Instr::StrLower {
+            value: read_slot(r)?,
+            out: read_slot(r)?,
+        }),
+        69 => Ok(Instr::StrTrim {
+            value: read_slot(r)?,
+            out: read_slot(r)?,
+        }),
+        70 => Ok(Instr::StrTrimStart {
+            value: read_slot(r)?,
+            out: read_slot(r)?,
+        }),
+        71 => Ok(Instr::StrTrimEnd {
+            value: read_slot(r)?,
+            out: read_slot(r)?,
+        }),
+        74 => Ok(Instr::TypeOf {
+            value: read_slot(r)?,
+            out: read_slot(r)?,
+        }),
+        75 => {
+            let value = read_slot(r)?;
+            let tag = r.read_u8()?;
+            let kind = *ValueKind::ALL
+                .get(usize::from(tag))
+                .ok_or(BytecodeError::InvalidTag {
+                    kind: "value kind",
+                    tag,
+                })?;
+            Ok(Instr::IsType {
+                value,
+                kind,
+                out: read_slot(r)?,
+            })
+        }
+        72 => Ok(Instr::NumParse {
+            value: read_slot(r)?,
+            out: read_slot(r)?,
+        }),
+        73 => {
+            let value = read_slot(r)?;
+            let mut optional = || -> Result<Option<Slot>, BytecodeError> {
+                Ok(match r.read_u8()? {
+                    0 => None,
+                    _ => Some(read_slot(r)?),
+                })
+            };
+            let precision = optional()?;
+            let width = optional()?;
+            Ok(Instr::NumFormat {
+                value,
+                precision,
+                width,
+                out: read_slot(r)?,
+            })
+        }
+        65 => Ok(Instr::StrSplit {
+            value: read_slot(r)?,
+            sep: read_slot(r)?,
+            out: read_slot(r)?,
+        }),
+        66 => Ok(Instr::StrJoin {
+            list: read_slot(r)?,
+            sep: read_slot(r)?,
+            out: read_slot(r)?,
         }),
         _ => Err(BytecodeError::InvalidTag { kind: "instr", tag }),
     }
 }
 
+/// Encodes the module body; [`Writer::finish`] prepends the header and the
+/// string pool the body refers into.
 #[derive(Default)]
 struct Writer {
     bytes: Vec<u8>,
+    /// Write imports by path instead of embedding their modules.
+    link_imports: bool,
+    /// Distinct strings in first-use order, shared by embedded imports.
+    strings: Vec<String>,
+    string_ids: HashMap<String, u32>,
 }
 
 impl Writer {
-    fn finish(self) -> Vec<u8> {
-        self.bytes
+    fn finish(self) -> Result<Vec<u8>, BytecodeError> {
+        let mut out = Writer::default();
+        out.write_bytes(&MAGIC);
+        out.write_u16(VERSION);
+        out.write_len(self.strings.len(), "string pool length")?;
+        for value in &self.strings {
+            out.write_len(value.len(), "string length")?;
+            out.write_bytes(value.as_bytes());
+        }
+        out.write_bytes(&self.bytes);
+        Ok(out.bytes)
     }
 
     fn write_bytes(&mut self, bytes: &[u8]) {
@@ -624,7 +2035,21 @@ impl Writer {
     }
 
     fn write_u32(&mut self, value: u32) {
-        self.bytes.extend_from_slice(&value.to_le_bytes());
+        self.write_varint(u64::from(value));
+    }
+
+    fn write_u64(&mut self, value: u64) {
+        self.write_varint(value);
+    }
+
+    /// Unsigned LEB128: seven bits per byte, low groups first, high bit set
+    /// on every byte but the last.
+    fn write_varint(&mut self, mut value: u64) {
+        while value >= 0x80 {
+            self.bytes.push(value.to_le_bytes()[0] | 0x80);
+            value >>= 7;
+        }
+        self.bytes.push(value.to_le_bytes()[0]);
     }
 
     fn write_f64(&mut self, value: f64) {
@@ -643,21 +2068,43 @@ impl Writer {
         Ok(())
     }
 
+    /// Writes `value` as its index in the string pool.
     fn write_string(&mut self, value: &str) -> Result<(), BytecodeError> {
-        self.write_len(value.len(), "string length")?;
-        self.write_bytes(value.as_bytes());
+        let id = if let Some(id) = self.string_ids.get(value) {
+            *id
+        } else {
+            let id = u32::try_from(self.strings.len())
+                .map_err(|_| BytecodeError::Overflow("string pool length"))?;
+            self.strings.push(value.to_owned());
+            self.string_ids.insert(value.to_owned(), id);
+            id
+        };
+        self.write_u32(id);
         Ok(())
     }
 }
 
-struct Reader<'a> {
+struct Reader<'a, 'r> {
     bytes: &'a [u8],
     pos: usize,
+    version: u16,
+    linker: Option<&'a mut Linker<'r>>,
+    /// The string pool, from v7 on.
+    strings: Vec<String>,
+    /// Embedded imports currently being read.
+    depth: usize,
 }
 
-impl<'a> Reader<'a> {
+impl<'a> Reader<'a, '_> {
     fn new(bytes: &'a [u8]) -> Self {
-        Self { bytes, pos: 0 }
+        Self {
+            bytes,
+            pos: 0,
+            version: VERSION,
+            linker: None,
+            strings: Vec::new(),
+            depth: 0,
+        }
     }
 
     fn is_eof(&self) -> bool {
@@ -689,12 +2136,42 @@ impl<'a> Reader<'a> {
     }
 
     fn read_u32(&mut self) -> Result<u32, BytecodeError> {
+        if self.version >= COMPACT_VERSION {
+            return u32::try_from(self.read_varint()?)
+                .map_err(|_| BytecodeError::Overflow("varint u32"));
+        }
         let raw = self.read_exact(4)?;
         let mut bytes = [0u8; 4];
         bytes.copy_from_slice(raw);
         Ok(u32::from_le_bytes(bytes))
     }
 
+    fn read_u64(&mut self) -> Result<u64, BytecodeError> {
+        if self.version >= COMPACT_VERSION {
+            return self.read_varint();
+        }
+        let raw = self.read_exact(8)?;
+        let mut bytes = [0u8; 8];
+        bytes.copy_from_slice(raw);
+        Ok(u64::from_le_bytes(bytes))
+    }
+
+    fn read_varint(&mut self) -> Result<u64, BytecodeError> {
+        let mut value = 0u64;
+        for shift in (0..64).step_by(7) {
+            let byte = self.read_u8()?;
+            let bits = u64::from(byte & 0x7f);
+            if shift == 63 && bits > 1 {
+                return Err(BytecodeError::Overflow("varint"));
+            }
+            value |= bits << shift;
+            if byte & 0x80 == 0 {
+                return Ok(value);
+            }
+        }
+        Err(BytecodeError::Overflow("varint"))
+    }
+
     fn read_f64(&mut self) -> Result<f64, BytecodeError> {
         let raw = self.read_exact(8)?;
         let mut bytes = [0u8; 8];
@@ -709,22 +2186,57 @@ impl<'a> Reader<'a> {
         Ok(bytes)
     }
 
+    fn read_fixed_32(&mut self) -> Result<[u8; 32], BytecodeError> {
+        let raw = self.read_exact(32)?;
+        let mut bytes = [0u8; 32];
+        bytes.copy_from_slice(raw);
+        Ok(bytes)
+    }
+
+    /// Room to reserve for `count` decoded items: every item takes at least
+    /// one byte, so a count past the remaining input cannot be honest.
+    fn capacity(&self, count: usize) -> usize {
+        count.min(self.bytes.len() - self.pos)
+    }
+
     fn read_len(&mut self, ctx: &'static str) -> Result<usize, BytecodeError> {
         let raw = self.read_u32()?;
         usize::try_from(raw).map_err(|_| BytecodeError::Overflow(ctx))
     }
 
     fn read_string(&mut self, ctx: &'static str) -> Result<String, BytecodeError> {
+        if self.version >= COMPACT_VERSION {
+            let index = self.read_u32()?;
+            return usize::try_from(index)
+                .ok()
+                .and_then(|index| self.strings.get(index))
+                .cloned()
+                .ok_or(BytecodeError::InvalidStringIndex(index));
+        }
+        self.read_inline_string(ctx)
+    }
+
+    fn read_inline_string(&mut self, ctx: &'static str) -> Result<String, BytecodeError> {
         let len = self.read_len("string length")?;
         let bytes = self.read_exact(len)?;
         String::from_utf8(bytes.to_vec()).map_err(|_| BytecodeError::InvalidUtf8(ctx.to_owned()))
     }
+
+    fn read_string_pool(&mut self) -> Result<(), BytecodeError> {
+        let count = self.read_len("string pool length")?;
+        let mut strings = Vec::with_capacity(self.capacity(count));
+        for _ in 0..count {
+            strings.push(self.read_inline_string("string pool entry")?);
+        }
+        self.strings = strings;
+        Ok(())
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use imp_compiler::{FsModuleLoader, compile_module};
+    use imp_compiler::{CompileOpts, FsModuleLoader, compile_module, compile_program};
     use imp_vm::{Value, Vm, VmConfig};
     use std::path::PathBuf;
     use std::sync::Arc;
@@ -742,9 +2254,382 @@ mod tests {
 
         assert_eq!(decoded.name, module.name);
         assert_eq!(decoded.global_count, module.global_count);
+        assert_eq!(decoded.global_names, module.global_names);
         assert_eq!(decoded.functions.len(), module.functions.len());
         assert_eq!(decoded.exports, module.exports);
         assert_eq!(decoded.imports.len(), module.imports.len());
+        let debug = decoded.functions[0]
+            .meta
+            .debug
+            .as_deref()
+            .expect("debug info");
+        assert_eq!(debug.source.as_deref(), path.to_str());
+        assert_eq!(debug.lines.len(), decoded.functions[0].code.len());
+    }
+
+    #[test]
+    fn roundtrip_bytes_constants_and_ops() {
+        let module = imp_compiler::compile_program(
+            "#call core::bytes::from_str value=\"ÿab\" out=local::b;\n\
+             #call core::bytes::slice value=local::b start=1 out=local::s;\n\
+             #call core::exit;\n",
+            imp_compiler::CompileOpts::default(),
+        )
+        .expect("compile")
+        .module;
+        let decoded = decode_module(&encode_module(&module).expect("encode")).expect("decode");
+        assert_eq!(decoded.functions[0].code, module.functions[0].code);
+        assert_eq!(
+            decoded.functions[0].meta.debug,
+            module.functions[0].meta.debug
+        );
+    }
+
+    #[test]
+    fn roundtrip_int_constants_and_parse() {
+        let module = imp_compiler::compile_program(
+            "#call core::int::parse value=\"-9007199254740993\" out=local::big;\n\
+             #call core::int::parse value=local::big out=local::same;\n\
+             #call core::exit;\n",
+            imp_compiler::CompileOpts::default(),
+        )
+        .expect("compile")
+        .module;
+        assert!(matches!(
+            module.functions[0].code[0],
+            Instr::StoreConst {
+                value: ConstValue::Int(-9_007_199_254_740_993),
+                ..
+            }
+        ));
+        let decoded = decode_module(&encode_module(&module).expect("encode")).expect("decode");
+        assert_eq!(decoded.functions[0].code, module.functions[0].code);
+    }
+
+    #[test]
+    fn roundtrip_throw_data_and_error_data() {
+        let module = imp_compiler::compile_program(
+            "#call core::obj::new out=local::info;\n\
+             #call core::try::push handler=\"caught\";\n\
+             #call core::throw code=\"a\" msg=\"b\" data=local::info;\n\
+             #call core::label name=\"caught\";\n\
+             #call core::error::data err=err::0 out=local::data;\n\
+             #call core::throw code=\"c\" msg=\"d\" cause=err::0 data=local::data;\n",
+            imp_compiler::CompileOpts::default(),
+        )
+        .expect("compile")
+        .module;
+        let code = &module.functions[0].code;
+        assert!(code.iter().any(|instr| matches!(
+            instr,
+            Instr::Throw {
+                cause: Some(_),
+                data: Some(_),
+                ..
+            }
+        )));
+        let decoded = decode_module(&encode_module(&module).expect("encode")).expect("decode");
+        assert_eq!(&decoded.functions[0].code, code);
+    }
+
+    #[test]
+    fn roundtrip_invoke_multi() {
+        let module = imp_compiler::compile_program(
+            "#call core::fn::begin name=main::pair args=\"a\" retshape=\"any\" retcount=2;\n\
+             #call core::mov from=arg::a to=return::0;\n\
+             #call core::mov from=arg::a to=return::1;\n\
+             #call core::exit;\n\
+             #call core::fn::end;\n\
+             #call core::const out=local::x value=1;\n\
+             #call main::pair args=\"local::x\" outs=\"local::a,local::b\";\n",
+            imp_compiler::CompileOpts::default(),
+        )
+        .expect("compile")
+        .module;
+        let code = &module.functions[0].code;
+        assert!(
+            code.iter()
+                .any(|instr| matches!(instr, Instr::InvokeMulti { outs, .. } if outs.len() == 2))
+        );
+        let decoded = decode_module(&encode_module(&module).expect("encode")).expect("decode");
+        assert_eq!(&decoded.functions[0].code, code);
+    }
+
+    #[test]
+    fn roundtrip_tail_invoke() {
+        let module = imp_compiler::compile_program(
+            "#call core::fn::begin name=main::f args=\"n\" retshape=\"scalar\";\n\
+             #call main::f args=\"arg::n\" out=return::value;\n\
+             #call core::exit;\n\
+             #call core::fn::end;\n",
+            imp_compiler::CompileOpts::default(),
+        )
+        .expect("compile")
+        .module;
+        let code = &module.functions[1].code;
+        assert!(
+            code.iter()
+                .any(|instr| matches!(instr, Instr::TailInvoke { args, .. } if args.len() == 1))
+        );
+        let decoded = decode_module(&encode_module(&module).expect("encode")).expect("decode");
+        assert_eq!(&decoded.functions[1].code, code);
+    }
+
+    #[test]
+    fn roundtrip_host_time() {
+        let module = imp_compiler::compile_program(
+            "#call core::host::now out=local::now;\n\
+             #call core::host::monotonic out=return::value;\n\
+             #call core::exit;\n",
+            imp_compiler::CompileOpts::default(),
+        )
+        .expect("compile")
+        .module;
+        let code = &module.functions[0].code;
+        assert!(code.iter().any(|instr| matches!(
+            instr,
+            Instr::HostTime {
+                clock: HostClock::Monotonic,
+                ..
+            }
+        )));
+        let decoded = decode_module(&encode_module(&module).expect("encode")).expect("decode");
+        assert_eq!(&decoded.functions[0].code, code);
+    }
+
+    #[test]
+    fn roundtrip_host_args_and_env() {
+        let module = imp_compiler::compile_program(
+            "#call core::host::args out=local::args;\n\
+             #call core::host::env key=\"HOME\" out=return::value;\n\
+             #call core::exit;\n",
+            imp_compiler::CompileOpts::default(),
+        )
+        .expect("compile")
+        .module;
+        let code = &module.functions[0].code;
+        assert!(
+            code.iter()
+                .any(|instr| matches!(instr, Instr::HostArgs { .. }))
+        );
+        assert!(
+            code.iter()
+                .any(|instr| matches!(instr, Instr::HostEnvVar { .. }))
+        );
+        let decoded = decode_module(&encode_module(&module).expect("encode")).expect("decode");
+        assert_eq!(&decoded.functions[0].code, code);
+    }
+
+    #[test]
+    fn roundtrip_host_read() {
+        let module = imp_compiler::compile_program(
+            "#call core::host::read_line out=local::line;\n\
+             #call core::host::read_all out=return::value;\n\
+             #call core::exit;\n",
+            imp_compiler::CompileOpts::default(),
+        )
+        .expect("compile")
+        .module;
+        let code = &module.functions[0].code;
+        assert!(
+            code.iter()
+                .any(|instr| matches!(instr, Instr::HostReadAll { .. }))
+        );
+        let decoded = decode_module(&encode_module(&module).expect("encode")).expect("decode");
+        assert_eq!(&decoded.functions[0].code, code);
+    }
+
+    #[test]
+    fn roundtrip_host_random() {
+        let module = imp_compiler::compile_program(
+            "#call core::host::random out=local::x;\n\
+             #call core::host::random_int lo=1 hi=7 out=return::value;\n\
+             #call core::exit;\n",
+            imp_compiler::CompileOpts::default(),
+        )
+        .expect("compile")
+        .module;
+        let code = &module.functions[0].code;
+        assert!(
+            code.iter()
+                .any(|instr| matches!(instr, Instr::HostRandomInt { .. }))
+        );
+        let decoded = decode_module(&encode_module(&module).expect("encode")).expect("decode");
+        assert_eq!(&decoded.functions[0].code, code);
+    }
+
+    #[test]
+    fn roundtrip_host_fs() {
+        let module = imp_compiler::compile_program(
+            "#call core::host::fs::exists path=\"a.txt\" out=local::there;\n\
+             #call core::host::fs::write path=\"a.txt\" data=\"hi\";\n\
+             #call core::host::fs::read path=\"a.txt\" out=return::value;\n\
+             #call core::exit;\n",
+            imp_compiler::CompileOpts::default(),
+        )
+        .expect("compile")
+        .module;
+        let code = &module.functions[0].code;
+        assert!(
+            code.iter()
+                .any(|instr| matches!(instr, Instr::HostFsWrite { .. }))
+        );
+        let decoded = decode_module(&encode_module(&module).expect("encode")).expect("decode");
+        assert_eq!(&decoded.functions[0].code, code);
+    }
+
+    #[test]
+    fn roundtrip_json() {
+        let module = imp_compiler::compile_program(
+            "#call core::json::parse value=\"[1,2]\" out=local::list;\n\
+             #call core::json::stringify value=local::list out=return::value;\n\
+             #call core::exit;\n",
+            imp_compiler::CompileOpts::default(),
+        )
+        .expect("compile")
+        .module;
+        let code = &module.functions[0].code;
+        assert!(
+            code.iter()
+                .any(|instr| matches!(instr, Instr::JsonStringify { .. }))
+        );
+        let decoded = decode_module(&encode_module(&module).expect("encode")).expect("decode");
+        assert_eq!(&decoded.functions[0].code, code);
+    }
+
+    #[test]
+    fn compact_format_pools_strings_and_still_reads_fixed_width_files() {
+        let module = imp_compiler::compile_program(
+            "#call core::const out=local::a value=\"repeated text\";\n\
+             #call core::const out=local::b value=\"repeated text\";\n\
+             #call core::str::concat a=local::a b=local::b out=return::value;\n\
+             #call core::exit;\n",
+            imp_compiler::CompileOpts::default(),
+        )
+        .expect("compile")
+        .module;
+        let encoded = encode_module(&module).expect("encode");
+        let occurrences = encoded
+            .windows(b"repeated text".len())
+            .filter(|window| *window == b"repeated text")
+            .count();
+        assert_eq!(occurrences, 1);
+        let decoded = decode_module(&encoded).expect("decode");
+        assert_eq!(decoded.functions[0].code, module.functions[0].code);
+
+        // `return::value = 42; exit` in the fixed-width layout of v1 and v6.
+        let legacy = |version: u16| {
+            let string = |bytes: &mut Vec<u8>, text: &str| {
+                let len = u32::try_from(text.len()).expect("short test string");
+                bytes.extend_from_slice(&len.to_le_bytes());
+                bytes.extend_from_slice(text.as_bytes());
+            };
+            let mut bytes = MAGIC.to_vec();
+            bytes.extend_from_slice(&version.to_le_bytes());
+            string(&mut bytes, "legacy");
+            for word in [0u32, 1, 0, 0, 0, 1, 0] {
+                bytes.extend_from_slice(&word.to_le_bytes());
+            }
+            string(&mut bytes, "<init>");
+            bytes.extend_from_slice(&0u32.to_le_bytes());
+            bytes.extend_from_slice(&1u32.to_le_bytes());
+            bytes.push(3);
+            if version >= 2 {
+                bytes.extend_from_slice(&0u32.to_le_bytes());
+            }
+            if version >= 4 {
+                bytes.push(0);
+            }
+            if version >= 6 {
+                bytes.push(0);
+            }
+            bytes.extend_from_slice(&2u32.to_le_bytes());
+            bytes.extend_from_slice(&[0, 3, 0, 0, 0, 0, 2]);
+            bytes.extend_from_slice(&42f64.to_le_bytes());
+            bytes.push(12);
+            for _ in 0..4 {
+                bytes.extend_from_slice(&0u32.to_le_bytes());
+            }
+            if version >= 3 {
+                bytes.extend_from_slice(&0u32.to_le_bytes());
+            }
+            bytes
+        };
+        for version in [1, 6] {
+            let decoded = decode_module(&legacy(version)).expect("decode legacy");
+            assert_eq!(decoded.name.as_ref(), "legacy");
+            let result = Vm::new(VmConfig::default())
+                .run_main(&decoded)
+                .expect("run legacy");
+            assert_eq!(result.returns, vec![Value::Num(42.0)]);
+        }
+    }
+
+    #[test]
+    fn separate_imports_link_through_a_resolver() {
+        struct Assets(HashMap<String, Vec<u8>>);
+        impl ModuleResolver for Assets {
+            fn resolve(&self, path: &str) -> Result<Vec<u8>, BytecodeError> {
+                self.0.get(path).cloned().ok_or_else(|| {
+                    BytecodeError::Io(io::Error::new(io::ErrorKind::NotFound, path.to_owned()))
+                })
+            }
+        }
+
+        let dir = std::env::temp_dir().join("imp_bytecode_separate");
+        fs::create_dir_all(&dir).expect("temp dir");
+        let dep_path = dir.join("dep.imp");
+        fs::write(
+            &dep_path,
+            "#call core::const out=mod::base value=40;\n\
+             #call core::mod::export name=\"base\" value=mod::base;\n\
+             #call core::exit;\n",
+        )
+        .expect("write dep");
+        let main_path = dir.join("main.imp");
+        fs::write(
+            &main_path,
+            "#call core::import alias=\"dep\" path=\"dep.imp\";\n\
+             #call core::const out=local::two value=2;\n\
+             #call core::add a=dep::base b=local::two out=return::value;\n\
+             #call core::exit;\n",
+        )
+        .expect("write main");
+
+        let main = compile_module(&main_path, &FsModuleLoader).expect("compile main");
+        let dep = compile_module(&dep_path, &FsModuleLoader).expect("compile dep");
+        let bytes = encode_module_separate(&main).expect("encode main");
+        assert!(bytes.len() < encode_module(&main).expect("encode embedded").len());
+        assert!(matches!(
+            decode_module(&bytes),
+            Err(BytecodeError::UnresolvedImport(path)) if path == main.imports[0].path
+        ));
+
+        let assets = Assets(HashMap::from([(
+            main.imports[0].path.clone(),
+            encode_module(&dep).expect("encode dep"),
+        )]));
+        let linked = decode_module_linked(&bytes, &assets).expect("link");
+        let mut vm = Vm::new(VmConfig::default());
+        let result = vm.run_main(&linked).expect("run linked");
+        assert_eq!(result.returns, vec![Value::Num(42.0)]);
+
+        fs::write(
+            &dep_path,
+            "#call core::const out=mod::base value=1;\n\
+             #call core::mod::export name=\"base\" value=mod::base;\n\
+             #call core::exit;\n",
+        )
+        .expect("rewrite dep");
+        let drifted = compile_module(&dep_path, &FsModuleLoader).expect("compile drifted dep");
+        let assets = Assets(HashMap::from([(
+            main.imports[0].path.clone(),
+            encode_module(&drifted).expect("encode drifted dep"),
+        )]));
+        assert!(matches!(
+            decode_module_linked(&bytes, &assets),
+            Err(BytecodeError::ImportHashMismatch { path, .. }) if path == main.imports[0].path
+        ));
     }
 
     #[test]
@@ -758,14 +2643,95 @@ mod tests {
         let encoded = encode_module(&module).expect("encode");
         let decoded = decode_module(&encoded).expect("decode");
 
-        let mut vm = Vm::new(VmConfig {
-            enable_host_print: false,
-            enable_jit: true,
-        });
+        let mut cfg = VmConfig::default();
+        cfg.enable_host_print = false;
+        cfg.enable_jit = true;
+        let mut vm = Vm::new(cfg);
         let result = vm.run_main(&decoded).expect("run decoded");
         assert_eq!(
             result.returns,
             vec![Value::Str(Arc::from("ok=true name=Ada"))]
         );
     }
+
+    #[test]
+    fn disassembly_reassembles_to_the_same_bytes() {
+        let path = PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+            .join("../../examples")
+            .join("enum_custom_object_demo.imp")
+            .canonicalize()
+            .expect("canonicalize example");
+        let module = compile_module(&path, &FsModuleLoader).expect("compile module");
+        let listing = disassemble(&module);
+        assert!(listing.contains("enum.imp\" alias=\"std_enum\"\n"));
+
+        let assembled = assemble(&listing).expect("assemble");
+        assert_eq!(
+            encode_module(&assembled).expect("encode assembled"),
+            encode_module(&module).expect("encode")
+        );
+        assert_eq!(disassemble(&assembled), listing);
+    }
+
+    #[test]
+    fn handwritten_listing_assembles_and_runs() {
+        let module = assemble(
+            r#"
+            ; doubles its argument
+            module "fixture" init=0 globals=1
+              global 0 "fixture::double"
+              fnglobal global=0 fn=1
+              const "greeting" str:"hi \"there\""
+              fn 0 "fixture::init" locals=1 args=0 rets=1 errs=0 retshape=scalar
+                StoreConst slot=L0 value=num:21
+                Invoke fn_slot=G0 args=[L0] out=R0
+                Exit
+              end
+              fn 1 "fixture::double" locals=0 args=1 rets=1 errs=0 retshape=scalar params=["x"]
+                0000: Add a=A0 b=A0 out=R0
+                0001: Exit
+              end
+            end
+            "#,
+        )
+        .expect("assemble");
+        assert_eq!(
+            module.consts[0].1,
+            ConstValue::Str(Arc::from("hi \"there\""))
+        );
+        let result = Vm::new(VmConfig::default()).run_main(&module).expect("run");
+        assert_eq!(result.returns, vec![Value::Num(42.0)]);
+
+        let error = assemble("module \"m\" init=0 globals=0\n  fn 0 \"f\" locals=0\nend\n")
+            .expect_err("missing fields");
+        assert!(
+            matches!(error, BytecodeError::Asm { line: 2, .. }),
+            "{error}"
+        );
+        let error = assemble(
+            "module \"m\" init=0 globals=0\n\
+             fn 0 \"f\" locals=0 args=0 rets=0 errs=0 retshape=any\n\
+             Bogus\nend\nend\n",
+        )
+        .expect_err("unknown instruction");
+        assert_eq!(error.to_string(), "line 3: unknown instruction `Bogus`");
+    }
+
+    #[test]
+    fn truncated_bytecode_is_an_error() {
+        let module = compile_program(
+            "#call core::const out=return::x value=\"hi\";",
+            CompileOpts::default(),
+        )
+        .expect("compile");
+        let bytes = encode_module(&module.module).expect("encode");
+        fuzz_roundtrip(&bytes);
+        for end in 0..bytes.len() {
+            assert!(
+                decode_module(&bytes[..end]).is_err(),
+                "prefix of {end} bytes"
+            );
+            fuzz_roundtrip(&bytes[..end]);
+        }
+    }
 }
@@ -0,0 +1,983 @@
+//! `imp dap`: a Debug Adapter Protocol server on stdin/stdout, so editors
+//! such as VS Code can debug imp programs.
+//!
+//! Requests are read on their own thread. The program runs on the same VM
+//! breakpoints `imp debug` uses, in batches of [`BATCH_STEPS`]
+//! instructions with queued requests handled in between, so `pause` and
+//! `disconnect` reach a program stuck in a loop. `launch` takes `program`, `stopOnEntry` and `args`; steps move by source
+//! line. Each frame has `Locals`, `Arguments`, `Returns` and `Errors`
+//! scopes, and `evaluate` reads a slot (`L0`, `A1`, ...) or parameter.
+//! Program output arrives as `output` events, and program input is empty.
+
+use crate::debug::{parse_function_breakpoint, parse_slot, slot_name};
+use crate::{load_module, render_returns, traceback_lines, with_env_flags};
+use imp_ir::{CompiledModule, Instr, Slot};
+use imp_lsp::{read_message, write_message};
+use imp_std::json::{Json, obj};
+use imp_vm::{
+    Breakpoint, InputBuffer, OutputBuffer, PrintLevel, PrintStream, RunState, StepOutcome, Steps,
+    TraceFrame, Vm, VmConfig, VmError,
+};
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+use std::fs;
+use std::io::{self, BufRead, Write};
+use std::path::Path;
+use std::sync::Arc;
+use std::sync::mpsc::{self, Receiver, Sender, TryRecvError};
+use std::thread;
+
+const THREAD_ID: u32 = 1;
+
+/// Instructions a running program executes between looks at the request
+/// queue.
+const BATCH_STEPS: u64 = 10_000;
+
+/// Scopes of every frame; a scope's `variablesReference` is
+/// `depth * SCOPES.len() + index + 1`.
+const SCOPES: [&str; 4] = ["Locals", "Arguments", "Returns", "Errors"];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Resume {
+    Continue,
+    Next,
+    StepIn,
+    StepOut,
+}
+
+/// Serves one debug session until `disconnect` or EOF.
+pub fn dap() -> Result<(), Box<dyn std::error::Error>> {
+    let (sender, requests) = mpsc::channel();
+    thread::spawn(move || read_requests(&mut io::stdin().lock(), &sender));
+    serve(&requests, io::stdout().lock())?;
+    Ok(())
+}
+
+/// Forwards requests from `input` until EOF, a read error or the session
+/// ending.
+fn read_requests(input: &mut impl BufRead, requests: &Sender<Json>) {
+    loop {
+        let body = match read_message(input) {
+            Ok(Some(body)) => body,
+            Ok(None) => return,
+            Err(err) => {
+                eprintln!("error: reading DAP message: {err}");
+                return;
+            }
+        };
+        match Json::parse(&body) {
+            Ok(request) => {
+                if requests.send(request).is_err() {
+                    return;
+                }
+            }
+            Err(err) => eprintln!("error: bad DAP message: {err}"),
+        }
+    }
+}
+
+/// Handles `requests` until `disconnect` or the reader hangs up, running
+/// the program a batch at a time whenever none are waiting.
+fn serve(requests: &Receiver<Json>, out: impl Write) -> io::Result<()> {
+    let mut server = Server::new(out);
+    loop {
+        let request = if server.running.is_some() {
+            match requests.try_recv() {
+                Ok(request) => request,
+                Err(TryRecvError::Empty) => {
+                    server.run_batch()?;
+                    continue;
+                }
+                Err(TryRecvError::Disconnected) => return Ok(()),
+            }
+        } else {
+            match requests.recv() {
+                Ok(request) => request,
+                Err(_) => return Ok(()),
+            }
+        };
+        if !server.handle(&request)? {
+            return Ok(());
+        }
+    }
+}
+
+/// A `continue` or step that has not stopped yet.
+#[derive(Debug, Clone, Copy)]
+struct Running {
+    how: Resume,
+    /// Frames on the stack and the innermost one's line when it began.
+    depth: usize,
+    line: Option<u32>,
+}
+
+// One flag per debug adapter option the client sets.
+#[allow(clippy::struct_excessive_bools)]
+struct Server<W: Write> {
+    out: W,
+    seq: u32,
+    vm: Option<Vm>,
+    /// The launched program, which function breakpoints must name.
+    module: Option<CompiledModule>,
+    output: Arc<OutputBuffer>,
+    /// Lines of `output` already sent as events.
+    sent_output: usize,
+    /// Source lines by canonical path.
+    line_breakpoints: BTreeMap<String, Vec<u32>>,
+    /// Names as the client sent them; checked once a program is launched.
+    function_breakpoints: Vec<String>,
+    break_on_throw: bool,
+    break_on_uncaught: bool,
+    stop_on_entry: bool,
+    configured: bool,
+    /// The uncaught error the session is stopped on; the run is over.
+    failure: Option<VmError>,
+    running: Option<Running>,
+}
+
+impl<W: Write> Server<W> {
+    fn new(out: W) -> Self {
+        Self {
+            out,
+            seq: 0,
+            vm: None,
+            module: None,
+            output: Arc::new(OutputBuffer::default()),
+            sent_output: 0,
+            line_breakpoints: BTreeMap::new(),
+            function_breakpoints: Vec::new(),
+            break_on_throw: false,
+            break_on_uncaught: true,
+            stop_on_entry: false,
+            configured: false,
+            failure: None,
+            running: None,
+        }
+    }
+
+    /// Handles one request; `false` once the session is over.
+    fn handle(&mut self, request: &Json) -> io::Result<bool> {
+        let command = request.get("command").and_then(Json::as_str).unwrap_or("");
+        let args = request.get("arguments").unwrap_or(&Json::Null);
+        match command {
+            "initialize" => {
+                let body = capabilities();
+                self.respond(request, Ok(body))?;
+                self.event("initialized", obj([]))?;
+            }
+            "launch" => {
+                let launched = self.launch(args);
+                let started = launched.is_ok();
+                self.respond(request, launched.map(|()| Json::Null))?;
+                if started {
+                    for index in 0..self.function_breakpoints.len() {
+                        let breakpoint = self.function_breakpoint(index);
+                        let body = obj([("reason", "changed".into()), ("breakpoint", breakpoint)]);
+                        self.event("breakpoint", body)?;
+                    }
+                }
+                if started && self.configured {
+                    self.begin()?;
+                }
+            }
+            "setBreakpoints" => {
+                let body = self.set_breakpoints(args);
+                self.respond(request, Ok(body))?;
+            }
+            "setFunctionBreakpoints" => {
+                let body = self.set_function_breakpoints(args);
+                self.respond(request, Ok(body))?;
+            }
+            "setExceptionBreakpoints" => {
+                let filters = args.get("filters").map_or(&[][..], Json::as_array);
+                let enabled = |name| filters.iter().any(|filter| filter.as_str() == Some(name));
+                self.break_on_throw = enabled("throw");
+                self.break_on_uncaught = enabled("uncaught");
+                self.apply_breakpoints();
+                self.respond(request, Ok(Json::Null))?;
+            }
+            "configurationDone" => {
+                self.configured = true;
+                self.respond(request, Ok(Json::Null))?;
+                if self.vm.is_some() {
+                    self.begin()?;
+                }
+            }
+            "threads" => {
+                let thread = obj([("id", THREAD_ID.into()), ("name", "main".into())]);
+                let body = obj([("threads", vec![thread].into())]);
+                self.respond(request, Ok(body))?;
+            }
+            "stackTrace" => {
+                let body = self.stack_trace();
+                self.respond(request, Ok(body))?;
+            }
+            "scopes" => self.respond(request, Ok(scopes(args)))?,
+            "variables" => {
+                let body = self.variables(args);
+                self.respond(request, Ok(body))?;
+            }
+            "evaluate" => {
+                let body = self.evaluate(args);
+                self.respond(request, body)?;
+            }
+            "continue" => {
+                let body = obj([("allThreadsContinued", true.into())]);
+                self.respond(request, Ok(body))?;
+                self.resume(Resume::Continue)?;
+            }
+            "next" | "stepIn" | "stepOut" => {
+                self.respond(request, Ok(Json::Null))?;
+                let how = match command {
+                    "next" => Resume::Next,
+                    "stepIn" => Resume::StepIn,
+                    _ => Resume::StepOut,
+                };
+                self.resume(how)?;
+            }
+            "pause" => {
+                self.respond(request, Ok(Json::Null))?;
+                if self.running.take().is_some() {
+                    self.stopped("pause", None)?;
+                }
+            }
+            "disconnect" | "terminate" => {
+                self.respond(request, Ok(Json::Null))?;
+                return Ok(false);
+            }
+            other => self.respond(request, Err(format!("unsupported request '{other}'")))?,
+        }
+        Ok(true)
+    }
+
+    fn launch(&mut self, args: &Json) -> Result<(), String> {
+        let program = args
+            .get("program")
+            .and_then(Json::as_str)
+            .ok_or("launch expects a 'program' path")?;
+        let module = load_module(Path::new(program), false, 0).map_err(|err| err.to_string())?;
+        let program_args = args
+            .get("args")
+            .map_or(&[][..], Json::as_array)
+            .iter()
+            .filter_map(Json::as_str)
+            .map(str::to_owned)
+            .collect();
+        let mut cfg = VmConfig::default();
+        cfg.writer = Some(self.output.clone());
+        cfg.reader = Some(Arc::new(InputBuffer::new("")));
+        cfg.program_args = program_args;
+        let mut vm = Vm::new(with_env_flags(cfg));
+        vm.start(&module).map_err(|err| err.to_string())?;
+        self.stop_on_entry = args
+            .get("stopOnEntry")
+            .and_then(Json::as_bool)
+            .unwrap_or(false);
+        self.vm = Some(vm);
+        self.module = Some(module);
+        self.apply_breakpoints();
+        Ok(())
+    }
+
+    /// Starts the launched program once configuration is done.
+    fn begin(&mut self) -> io::Result<()> {
+        if self.stop_on_entry {
+            self.stopped("entry", None)
+        } else {
+            self.resume(Resume::Continue)
+        }
+    }
+
+    fn set_breakpoints(&mut self, args: &Json) -> Json {
+        let path = args
+            .get("source")
+            .and_then(|source| source.get("path"))
+            .and_then(Json::as_str)
+            .unwrap_or("");
+        let lines = args
+            .get("breakpoints")
+            .map_or(&[][..], Json::as_array)
+            .iter()
+            .filter_map(|breakpoint| breakpoint.get("line").and_then(Json::as_u32))
+            .collect::<Vec<_>>();
+        let verified = lines
+            .iter()
+            .map(|line| obj([("verified", true.into()), ("line", (*line).into())]))
+            .collect::<Vec<_>>();
+        self.line_breakpoints.insert(canonical(path), lines);
+        self.apply_breakpoints();
+        obj([("breakpoints", verified.into())])
+    }
+
+    fn set_function_breakpoints(&mut self, args: &Json) -> Json {
+        let names = args.get("breakpoints").map_or(&[][..], Json::as_array);
+        self.function_breakpoints = names
+            .iter()
+            .map(|name| name.get("name").and_then(Json::as_str).unwrap_or(""))
+            .map(str::to_owned)
+            .collect();
+        self.apply_breakpoints();
+        let breakpoints = (0..self.function_breakpoints.len())
+            .map(|index| self.function_breakpoint(index))
+            .collect::<Vec<_>>();
+        obj([("breakpoints", breakpoints.into())])
+    }
+
+    /// Function breakpoint `index` as the client sees it, with its position
+    /// as its id. Before launch there is no module to check it against.
+    fn function_breakpoint(&self, index: usize) -> Json {
+        let checked = match &self.module {
+            Some(module) => parse_function_breakpoint(&self.function_breakpoints[index], module),
+            None => Err("checked when the program launches".to_owned()),
+        };
+        let mut fields = vec![
+            ("id".to_owned(), Json::from(index + 1)),
+            ("verified".to_owned(), checked.is_ok().into()),
+        ];
+        if let Err(message) = checked {
+            fields.push(("message".to_owned(), message.into()));
+        }
+        Json::Obj(fields)
+    }
+
+    fn apply_breakpoints(&mut self) {
+        let Some(vm) = &mut self.vm else {
+            return;
+        };
+        let module = self.module.as_ref().expect("launched with the VM");
+        let mut breakpoints = self
+            .function_breakpoints
+            .iter()
+            .filter_map(|name| parse_function_breakpoint(name, module).ok())
+            .collect::<Vec<_>>();
+        for (path, lines) in &self.line_breakpoints {
+            let source: Arc<str> = Arc::from(path.as_str());
+            breakpoints.extend(lines.iter().map(|line| Breakpoint::Line {
+                source: Some(Arc::clone(&source)),
+                line: *line,
+            }));
+        }
+        if self.break_on_throw {
+            breakpoints.push(Breakpoint::Throw);
+        }
+        vm.set_breakpoints(breakpoints);
+    }
+
+    /// The paused frames, or those the uncaught error escaped.
+    fn frames(&self) -> Vec<TraceFrame> {
+        match (&self.failure, &self.vm) {
+            (Some(err), _) => err.traceback().to_vec(),
+            (None, Some(vm)) => vm
+                .paused_frames()
+                .into_iter()
+                .map(|info| info.frame)
+                .collect(),
+            (None, None) => Vec::new(),
+        }
+    }
+
+    fn stack_trace(&self) -> Json {
+        let frames = self
+            .frames()
+            .into_iter()
+            .enumerate()
+            .map(|(depth, frame)| {
+                let mut fields = vec![
+                    ("id".to_owned(), Json::from(depth + 1)),
+                    ("name".to_owned(), frame.function.as_ref().into()),
+                    ("line".to_owned(), frame.line.unwrap_or(0).into()),
+                    ("column".to_owned(), u32::from(frame.line.is_some()).into()),
+                ];
+                if let Some(source) = &frame.source {
+                    let source = obj([("path", source.as_ref().into())]);
+                    fields.push(("source".to_owned(), source));
+                }
+                Json::Obj(fields)
+            })
+            .collect::<Vec<_>>();
+        let total = frames.len();
+        obj([
+            ("stackFrames", frames.into()),
+            ("totalFrames", total.into()),
+        ])
+    }
+
+    fn variables(&self, args: &Json) -> Json {
+        let reference = args
+            .get("variablesReference")
+            .and_then(Json::as_u32)
+            .unwrap_or(0) as usize;
+        let Some(reference) = reference.checked_sub(1) else {
+            return obj([("variables", Json::Arr(Vec::new()))]);
+        };
+        let (depth, scope) = (reference / SCOPES.len(), reference % SCOPES.len());
+        let info = self
+            .vm
+            .as_ref()
+            .filter(|_| self.failure.is_none())
+            .and_then(|vm| vm.paused_frames().into_iter().nth(depth));
+        let variables = info
+            .iter()
+            .flat_map(|info| {
+                info.slots.iter().filter_map(move |(slot, value)| {
+                    let name = match (scope, slot) {
+                        (0, Slot::Local(_)) | (2, Slot::Ret(_)) | (3, Slot::Err(_)) => {
+                            slot_name(*slot)
+                        }
+                        (1, Slot::Arg(index)) => info
+                            .params
+                            .get(*index as usize)
+                            .map_or_else(|| slot_name(*slot), ToString::to_string),
+                        _ => return None,
+                    };
+                    Some(obj([
+                        ("name", name.into()),
+                        ("value", value.to_string().into()),
+                        ("variablesReference", 0u32.into()),
+                    ]))
+                })
+            })
+            .collect::<Vec<_>>();
+        obj([("variables", variables.into())])
+    }
+
+    fn evaluate(&self, args: &Json) -> Result<Json, String> {
+        let expression = args
+            .get("expression")
+            .and_then(Json::as_str)
+            .unwrap_or("")
+            .trim();
+        let depth = frame_depth(args);
+        let vm = self.vm.as_ref().ok_or("no program is running")?;
+        let param = vm.paused_frames().into_iter().nth(depth).and_then(|info| {
+            let index = info
+                .params
+                .iter()
+                .position(|param| param.as_ref() == expression)?;
+            Some(Slot::Arg(u32::try_from(index).ok()?))
+        });
+        let slot = parse_slot(expression)
+            .or(param)
+            .ok_or_else(|| format!("'{expression}' is not a slot or parameter"))?;
+        let value = vm
+            .paused_slot(depth, slot)
+            .ok_or_else(|| format!("no slot {expression} in this frame"))?;
+        Ok(obj([
+            ("result", value.to_string().into()),
+            ("variablesReference", 0u32.into()),
+        ]))
+    }
+
+    /// Sets the program running as `how` asks; [`Self::run_batch`] moves
+    /// it along.
+    fn resume(&mut self, how: Resume) -> io::Result<()> {
+        if self.failure.take().is_some() {
+            return self.exit(1);
+        }
+        let Some(vm) = &self.vm else {
+            return Ok(());
+        };
+        let start = vm.paused_frames();
+        self.running = Some(Running {
+            how,
+            depth: start.len(),
+            line: start.first().and_then(|info| info.frame.line),
+        });
+        Ok(())
+    }
+
+    /// Runs up to [`BATCH_STEPS`] instructions of the current `continue` or
+    /// step and reports where it stopped, if it did.
+    fn run_batch(&mut self) -> io::Result<()> {
+        let (Some(vm), Some(running)) = (&mut self.vm, self.running) else {
+            self.running = None;
+            return Ok(());
+        };
+        let outcome = match running.how {
+            Resume::Continue => match vm.run_for(Steps(BATCH_STEPS)) {
+                Ok(RunState::Done(result)) => Ok(Some(StepOutcome::Finished(result))),
+                Ok(RunState::Suspended) if vm.at_breakpoint() => {
+                    Ok(Some(StepOutcome::Paused { frames: Vec::new() }))
+                }
+                Ok(RunState::Suspended) => Ok(None),
+                Err(err) => Err(err),
+            },
+            _ => step_lines(vm, running, BATCH_STEPS),
+        };
+        self.flush_output()?;
+        match outcome {
+            Ok(None) => Ok(()),
+            Ok(Some(outcome)) => {
+                self.running = None;
+                self.report(Ok(outcome))
+            }
+            Err(err) => {
+                self.running = None;
+                self.report(Err(err))
+            }
+        }
+    }
+
+    /// Tells the client where a finished batch left the program.
+    fn report(&mut self, outcome: Result<StepOutcome, VmError>) -> io::Result<()> {
+        match outcome {
+            Ok(StepOutcome::Paused { .. }) => {
+                let vm = self.vm.as_ref().expect("paused run");
+                let throwing = self.break_on_throw
+                    && vm.paused_frames().first().is_some_and(|info| {
+                        matches!(
+                            info.instr,
+                            Some(Instr::Throw { .. } | Instr::Rethrow { .. })
+                        )
+                    });
+                match (throwing, vm.at_breakpoint()) {
+                    (true, _) => self.stopped("exception", Some("throw".to_owned())),
+                    (false, true) => self.stopped("breakpoint", None),
+                    (false, false) => self.stopped("step", None),
+                }
+            }
+            Ok(StepOutcome::Finished(result)) => {
+                let text = format!("returns: {}\n", render_returns(&result.returns));
+                self.output_event("console", text)?;
+                self.exit(0)
+            }
+            Err(err) if self.break_on_uncaught => {
+                let text = err.to_string();
+                self.failure = Some(err);
+                self.stopped("exception", Some(text))
+            }
+            Err(err) => {
+                let mut text = format!("error: {err}\n");
+                for line in traceback_lines(err.traceback()) {
+                    let _ = writeln!(text, "  {line}");
+                }
+                self.output_event("stderr", text)?;
+                self.exit(1)
+            }
+        }
+    }
+
+    fn stopped(&mut self, reason: &str, text: Option<String>) -> io::Result<()> {
+        let mut body = vec![
+            ("reason".to_owned(), Json::from(reason)),
+            ("threadId".to_owned(), THREAD_ID.into()),
+            ("allThreadsStopped".to_owned(), true.into()),
+        ];
+        if let Some(text) = text {
+            body.push(("text".to_owned(), text.into()));
+        }
+        self.event("stopped", Json::Obj(body))
+    }
+
+    fn exit(&mut self, code: u32) -> io::Result<()> {
+        self.vm = None;
+        self.event("exited", obj([("exitCode", code.into())]))?;
+        self.event("terminated", obj([]))
+    }
+
+    /// Sends the program's new output lines as `output` events.
+    fn flush_output(&mut self) -> io::Result<()> {
+        let lines = self.output.lines();
+        for line in lines.iter().skip(self.sent_output) {
+            let category = match line.stream {
+                PrintStream::Stdout => "stdout",
+                PrintStream::Stderr => "stderr",
+            };
+            let text = match line.level {
+                PrintLevel::Info => format!("{}\n", line.text),
+                level => format!("{}: {}\n", level.as_str(), line.text),
+            };
+            self.output_event(category, text)?;
+        }
+        self.sent_output = lines.len();
+        Ok(())
+    }
+
+    fn output_event(&mut self, category: &str, output: String) -> io::Result<()> {
+        let body = obj([("category", category.into()), ("output", output.into())]);
+        self.event("output", body)
+    }
+
+    fn respond(&mut self, request: &Json, body: Result<Json, String>) -> io::Result<()> {
+        let mut fields = vec![
+            (
+                "request_seq",
+                request.get("seq").cloned().unwrap_or(Json::Null),
+            ),
+            (
+                "command",
+                request.get("command").cloned().unwrap_or(Json::Null),
+            ),
+        ];
+        match body {
+            Ok(body) => {
+                fields.push(("success", true.into()));
+                if body != Json::Null {
+                    fields.push(("body", body));
+                }
+            }
+            Err(message) => {
+                fields.push(("success", false.into()));
+                fields.push(("message", message.into()));
+            }
+        }
+        self.send("response", fields)
+    }
+
+    fn event(&mut self, event: &str, body: Json) -> io::Result<()> {
+        self.send("event", vec![("event", event.into()), ("body", body)])
+    }
+
+    fn send(&mut self, kind: &str, fields: Vec<(&str, Json)>) -> io::Result<()> {
+        self.seq += 1;
+        let mut message = vec![
+            ("seq".to_owned(), Json::from(self.seq)),
+            ("type".to_owned(), Json::from(kind)),
+        ];
+        message.extend(
+            fields
+                .into_iter()
+                .map(|(name, value)| (name.to_owned(), value)),
+        );
+        write_message(&mut self.out, &Json::Obj(message))
+    }
+}
+
+fn capabilities() -> Json {
+    let filter = |name: &str, label: &str, default: bool| {
+        obj([
+            ("filter", name.into()),
+            ("label", label.into()),
+            ("default", default.into()),
+        ])
+    };
+    obj([
+        ("supportsConfigurationDoneRequest", true.into()),
+        ("supportsFunctionBreakpoints", true.into()),
+        ("supportsEvaluateForHovers", true.into()),
+        (
+            "exceptionBreakpointFilters",
+            vec![
+                filter("throw", "Thrown errors", false),
+                filter("uncaught", "Uncaught errors", true),
+            ]
+            .into(),
+        ),
+    ])
+}
+
+fn scopes(args: &Json) -> Json {
+    let depth = frame_depth(args);
+    let scopes = SCOPES
+        .iter()
+        .enumerate()
+        .map(|(index, name)| {
+            obj([
+                ("name", (*name).into()),
+                (
+                    "variablesReference",
+                    (depth * SCOPES.len() + index + 1).into(),
+                ),
+                ("expensive", false.into()),
+            ])
+        })
+        .collect::<Vec<_>>();
+    obj([("scopes", scopes.into())])
+}
+
+/// Frame ids are depths from the innermost frame, plus one.
+fn frame_depth(args: &Json) -> usize {
+    let id = args.get("frameId").and_then(Json::as_u32).unwrap_or(1) as usize;
+    id.saturating_sub(1)
+}
+
+/// Source paths are compared as the compiler records them: canonical.
+fn canonical(path: &str) -> String {
+    fs::canonicalize(path).map_or_else(
+        |_| path.to_owned(),
+        |path| path.to_string_lossy().into_owned(),
+    )
+}
+
+/// Steps one instruction at a time, up to `budget` of them, until
+/// `running.how` is satisfied: the line changes (`StepIn`), the line
+/// changes in the starting frame or a caller (`Next`), or that frame
+/// returns (`StepOut`). Breakpoints stop it early; `None` when the budget
+/// ran out first.
+fn step_lines(vm: &mut Vm, running: Running, budget: u64) -> Result<Option<StepOutcome>, VmError> {
+    let Running { how, depth, line } = running;
+    for _ in 0..budget {
+        let outcome = vm.step(1)?;
+        let StepOutcome::Paused { frames } = &outcome else {
+            return Ok(Some(outcome));
+        };
+        let here = frames.first().and_then(|frame| frame.line);
+        let moved = here.is_none() || here != line;
+        let done = match how {
+            Resume::StepIn => frames.len() != depth || moved,
+            Resume::Next => frames.len() < depth || (frames.len() == depth && moved),
+            Resume::StepOut | Resume::Continue => frames.len() < depth,
+        };
+        if done || vm.at_breakpoint() {
+            return Ok(Some(outcome));
+        }
+    }
+    Ok(None)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::io::Cursor;
+    use std::sync::Mutex;
+    use std::thread::JoinHandle;
+    use std::time::{Duration, Instant};
+
+    /// Output shared with the test while the server thread writes it.
+    #[derive(Clone, Default)]
+    struct Captured(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for Captured {
+        fn write(&mut self, bytes: &[u8]) -> io::Result<usize> {
+            self.0.lock().expect("output lock").extend_from_slice(bytes);
+            Ok(bytes.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl Captured {
+        fn messages(&self) -> Vec<Json> {
+            let bytes = self.0.lock().expect("output lock").clone();
+            let mut input = Cursor::new(bytes);
+            std::iter::from_fn(|| read_message(&mut input).expect("framed message"))
+                .map(|body| Json::parse(&body).expect("json message"))
+                .collect()
+        }
+    }
+
+    /// A server on its own thread, fed requests the way a client sends
+    /// them: each after the replies it depends on.
+    struct Client {
+        requests: Sender<Json>,
+        output: Captured,
+        seq: u32,
+        /// Events of each kind already waited for.
+        seen: BTreeMap<String, usize>,
+        server: JoinHandle<io::Result<()>>,
+    }
+
+    impl Client {
+        fn start() -> Self {
+            let (requests, received) = mpsc::channel();
+            let output = Captured::default();
+            let out = output.clone();
+            let server = thread::spawn(move || serve(&received, out));
+            Self {
+                requests,
+                output,
+                seq: 0,
+                seen: BTreeMap::new(),
+                server,
+            }
+        }
+
+        fn send(&mut self, command: &str, arguments: Json) {
+            self.seq += 1;
+            let request = obj([
+                ("seq", self.seq.into()),
+                ("type", "request".into()),
+                ("command", command.into()),
+                ("arguments", arguments),
+            ]);
+            self.requests.send(request).expect("server is up");
+        }
+
+        /// The `nth` message `matches` accepts, waiting for it to arrive.
+        fn wait_for(&self, what: &str, nth: usize, matches: impl Fn(&Json) -> bool) -> Json {
+            let deadline = Instant::now() + Duration::from_secs(10);
+            loop {
+                if let Some(message) = self.output.messages().into_iter().filter(&matches).nth(nth)
+                {
+                    return message;
+                }
+                assert!(
+                    Instant::now() < deadline,
+                    "no {what} in {:?}",
+                    self.output.messages()
+                );
+                thread::sleep(Duration::from_millis(5));
+            }
+        }
+
+        /// The next `event` after those already waited for.
+        fn event(&mut self, event: &str) -> Json {
+            let seen = self.seen.entry(event.to_owned()).or_default();
+            let nth = *seen;
+            *seen += 1;
+            self.wait_for(event, nth, |message| {
+                message.get("event").and_then(Json::as_str) == Some(event)
+            })
+        }
+
+        /// The response to the first `command` request.
+        fn response(&self, command: &str) -> Json {
+            self.wait_for(command, 0, |message| {
+                message.get("type").and_then(Json::as_str) == Some("response")
+                    && message.get("command").and_then(Json::as_str) == Some(command)
+            })
+        }
+
+        /// Disconnects and waits for the server to return.
+        fn finish(mut self) -> Vec<Json> {
+            self.send("disconnect", obj([]));
+            let Self { output, server, .. } = self;
+            server.join().expect("server thread").expect("server io");
+            output.messages()
+        }
+    }
+
+    fn program(name: &str, source: &str) -> String {
+        let dir = env::temp_dir().join(format!("imp_dap_{name}"));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).expect("scratch dir");
+        let path = dir.join("main.imp");
+        fs::write(&path, source).expect("write program");
+        canonical(&path.to_string_lossy())
+    }
+
+    fn launch(client: &mut Client, program: &str) {
+        client.send("initialize", obj([]));
+        client.send("launch", obj([("program", program.into())]));
+        client.response("launch");
+    }
+
+    fn body_str<'a>(message: &'a Json, field: &str) -> Option<&'a str> {
+        message.get("body")?.get(field)?.as_str()
+    }
+
+    #[test]
+    fn pause_and_disconnect_reach_a_looping_program() {
+        let program = program(
+            "spin",
+            "#call core::label name=\"spin\";\n#call core::jump target=\"spin\";\n",
+        );
+        let mut client = Client::start();
+        launch(&mut client, &program);
+        client.send("configurationDone", obj([]));
+        client.response("configurationDone");
+        client.send("pause", obj([]));
+        let stopped = client.event("stopped");
+        assert_eq!(body_str(&stopped, "reason"), Some("pause"));
+
+        client.send("continue", obj([]));
+        client.response("continue");
+        let messages = client.finish();
+        let last = messages.last().expect("disconnect response");
+        assert_eq!(
+            last.get("command").and_then(Json::as_str),
+            Some("disconnect")
+        );
+        assert!(
+            !messages
+                .iter()
+                .any(|message| message.get("event").and_then(Json::as_str) == Some("exited"))
+        );
+    }
+
+    #[test]
+    fn breakpoints_stop_the_run_and_continue_finishes_it() {
+        let program = program(
+            "break",
+            "#call core::const value=1 out=local::x;\n\
+             #call core::const value=2 out=local::y;\n\
+             #call core::add a=local::x b=local::y out=return::sum;\n\
+             #call core::exit;\n",
+        );
+        let mut client = Client::start();
+        launch(&mut client, &program);
+        let source = obj([("path", program.as_str().into())]);
+        let breakpoint = obj([("line", 3u32.into())]);
+        client.send(
+            "setBreakpoints",
+            obj([("source", source), ("breakpoints", vec![breakpoint].into())]),
+        );
+        client.send("configurationDone", obj([]));
+        let stopped = client.event("stopped");
+        assert_eq!(body_str(&stopped, "reason"), Some("breakpoint"));
+
+        client.send("stackTrace", obj([("threadId", THREAD_ID.into())]));
+        let trace = client.response("stackTrace");
+        let top = &trace
+            .get("body")
+            .and_then(|body| body.get("stackFrames"))
+            .map(Json::as_array)
+            .expect("frames")[0];
+        assert_eq!(top.get("line").and_then(Json::as_u32), Some(3));
+        client.send("evaluate", obj([("expression", "L1".into())]));
+        let value = client.response("evaluate");
+        assert_eq!(body_str(&value, "result"), Some("2"));
+
+        client.send("next", obj([("threadId", THREAD_ID.into())]));
+        let stopped = client.event("stopped");
+        assert_eq!(body_str(&stopped, "reason"), Some("step"));
+        client.send("continue", obj([]));
+        let exited = client.event("exited");
+        let code = exited.get("body").and_then(|body| body.get("exitCode"));
+        assert_eq!(code.and_then(Json::as_u32), Some(0));
+        let messages = client.finish();
+        assert!(messages.iter().any(|message| {
+            body_str(message, "output").is_some_and(|text| text == "returns: [3]\n")
+        }));
+    }
+
+    #[test]
+    fn function_breakpoints_must_name_a_function_of_the_program() {
+        let program = program(
+            "function",
+            "#call core::fn::begin name=main::double args=\"a\" retshape=\"scalar\";\n\
+             #call core::add a=arg::a b=arg::a out=return::value;\n\
+             #call core::exit;\n\
+             #call core::fn::end;\n\
+             #call core::const value=2 out=local::x;\n\
+             #call main::double args=\"local::x\" out=return::value;\n\
+             #call core::exit;\n",
+        );
+        let mut client = Client::start();
+        launch(&mut client, &program);
+        let names = ["main::double", "<init>:2", "main::double@99"]
+            .into_iter()
+            .map(|name| obj([("name", name.into())]))
+            .collect::<Vec<_>>();
+        client.send(
+            "setFunctionBreakpoints",
+            obj([("breakpoints", names.into())]),
+        );
+        let response = client.response("setFunctionBreakpoints");
+        let verified = response
+            .get("body")
+            .and_then(|body| body.get("breakpoints"))
+            .map(Json::as_array)
+            .expect("breakpoints")
+            .iter()
+            .map(|breakpoint| breakpoint.get("verified").and_then(Json::as_bool))
+            .collect::<Vec<_>>();
+        assert_eq!(verified, [Some(true), Some(false), Some(false)]);
+
+        client.send("configurationDone", obj([]));
+        let stopped = client.event("stopped");
+        assert_eq!(body_str(&stopped, "reason"), Some("breakpoint"));
+        client.send("stackTrace", obj([("threadId", THREAD_ID.into())]));
+        let trace = client.response("stackTrace");
+        let top = &trace
+            .get("body")
+            .and_then(|body| body.get("stackFrames"))
+            .map(Json::as_array)
+            .expect("frames")[0];
+        assert_eq!(top.get("name").and_then(Json::as_str), Some("main::double"));
+        client.finish();
+    }
+}
@@ -0,0 +1,308 @@
+//! `imp debug`: runs a module under a line-oriented debugger on stdin.
+//!
+//! The run starts paused before the init's first instruction. Breakpoints
+//! are `FUNC` (its pc 0), `FUNC@PC` or a source `LINE` of the debugged file;
+//! slots are named as in `imp run --trace` (`L0`, `A1`, `G2`, `R0`, `E0`) or
+//! by parameter name.
+
+use crate::{load_module, render_returns, with_env_flags, with_traceback};
+use imp_ir::{CompiledFunction, CompiledModule, Slot};
+use imp_vm::{Breakpoint, StepOutcome, TraceFrame, Vm, VmConfig, VmError};
+use std::io::{self, BufRead, Write};
+use std::path::Path;
+use std::sync::Arc;
+
+const COMMANDS: &str = "break FUNC[@PC]|LINE, delete, step, next, continue, bt, slots [DEPTH], \
+                        print SLOT [DEPTH], quit";
+
+/// Debugs `path` until the run finishes, fails, or `quit`.
+pub fn debug(path: &Path) -> Result<(), Box<dyn std::error::Error>> {
+    let module = load_module(path, false, 0)?;
+    let source = module
+        .function(module.init_func)
+        .and_then(|init| init.meta.debug.as_ref())
+        .and_then(|debug| debug.source.clone());
+    let mut vm = Vm::new(with_env_flags(VmConfig::default()));
+    vm.start(&module).map_err(|err| with_traceback(&err))?;
+    show_stop(&vm);
+
+    let mut input = io::stdin().lock();
+    loop {
+        print!("debug> ");
+        io::stdout().flush()?;
+        let mut line = String::new();
+        if input.read_line(&mut line)? == 0 {
+            println!();
+            return Ok(());
+        }
+        let line = line.trim();
+        let (command, arg) = line
+            .split_once(char::is_whitespace)
+            .map_or((line, ""), |(command, arg)| (command, arg.trim()));
+        let outcome = match command {
+            "" => continue,
+            "quit" | "q" => return Ok(()),
+            "break" | "b" => {
+                match parse_breakpoint(arg, &module, source.as_ref()) {
+                    Ok(breakpoint) => {
+                        let mut breakpoints = vm.breakpoints().to_vec();
+                        breakpoints.push(breakpoint);
+                        println!("breakpoint {}", breakpoints.len());
+                        vm.set_breakpoints(breakpoints);
+                    }
+                    Err(err) => eprintln!("error: {err}"),
+                }
+                continue;
+            }
+            "delete" => {
+                vm.set_breakpoints(Vec::new());
+                continue;
+            }
+            "bt" => {
+                for info in vm.paused_frames() {
+                    println!("  at {}", info.frame);
+                }
+                continue;
+            }
+            "slots" => {
+                show_slots(&vm, arg);
+                continue;
+            }
+            "print" | "p" => {
+                print_slot(&vm, arg);
+                continue;
+            }
+            "step" | "s" => vm.step(1),
+            "next" | "n" => next(&mut vm),
+            "continue" | "c" => vm.step(u64::MAX),
+            _ => {
+                eprintln!("error: unknown command '{line}', expected {COMMANDS}");
+                continue;
+            }
+        };
+        match outcome.map_err(|err| with_traceback(&err))? {
+            StepOutcome::Paused { .. } => show_stop(&vm),
+            StepOutcome::Finished(result) => {
+                println!("returns: {}", render_returns(&result.returns));
+                return Ok(());
+            }
+        }
+    }
+}
+
+/// Runs one instruction, then on until the current frame is back (or
+/// returned) or a breakpoint is reached.
+fn next(vm: &mut Vm) -> Result<StepOutcome, VmError> {
+    let depth = vm.paused_frames().len();
+    loop {
+        let outcome = vm.step(1)?;
+        match &outcome {
+            StepOutcome::Paused { frames } if frames.len() > depth && !vm.at_breakpoint() => {}
+            _ => return Ok(outcome),
+        }
+    }
+}
+
+fn parse_breakpoint(
+    arg: &str,
+    module: &CompiledModule,
+    source: Option<&Arc<str>>,
+) -> Result<Breakpoint, String> {
+    if arg.is_empty() {
+        return Err("break expects FUNC, FUNC@PC or LINE".to_owned());
+    }
+    if let Ok(line) = arg.parse() {
+        return Ok(Breakpoint::Line {
+            source: source.cloned(),
+            line,
+        });
+    }
+    parse_function_breakpoint(arg, module)
+}
+
+/// `FUNC` (its pc 0) or `FUNC@PC`, where `FUNC` is a function of `module`
+/// or of a module it imports and `PC` one of its instructions.
+pub(crate) fn parse_function_breakpoint(
+    arg: &str,
+    module: &CompiledModule,
+) -> Result<Breakpoint, String> {
+    let (function, pc) = match arg.rsplit_once('@') {
+        Some((function, pc)) => {
+            let pc = pc.parse().map_err(|_| format!("'{pc}' is not a pc"))?;
+            (function, pc)
+        }
+        None => (arg, 0),
+    };
+    let found =
+        find_function(module, function).ok_or_else(|| format!("no function named '{function}'"))?;
+    if pc >= found.code.len() {
+        return Err(format!(
+            "{function} has no pc {pc}; it has {} instructions",
+            found.code.len()
+        ));
+    }
+    Ok(Breakpoint::At {
+        function: Arc::from(function),
+        pc,
+    })
+}
+
+fn find_function<'a>(module: &'a CompiledModule, name: &str) -> Option<&'a CompiledFunction> {
+    module
+        .functions
+        .iter()
+        .find(|function| function.meta.name.as_ref() == name)
+        .or_else(|| {
+            module
+                .imports
+                .iter()
+                .find_map(|import| find_function(&import.module, name))
+        })
+}
+
+/// `L0`, `A1`, `G2`, `R0` or `E0`.
+pub(crate) fn parse_slot(text: &str) -> Option<Slot> {
+    let mut chars = text.chars();
+    let kind = chars.next()?;
+    let index = chars.as_str().parse().ok()?;
+    Some(match kind {
+        'L' => Slot::Local(index),
+        'A' => Slot::Arg(index),
+        'G' => Slot::Global(index),
+        'R' => Slot::Ret(index),
+        'E' => Slot::Err(index),
+        _ => return None,
+    })
+}
+
+pub(crate) fn slot_name(slot: Slot) -> String {
+    match slot {
+        Slot::Local(index) => format!("L{index}"),
+        Slot::Arg(index) => format!("A{index}"),
+        Slot::Global(index) => format!("G{index}"),
+        Slot::Ret(index) => format!("R{index}"),
+        Slot::Err(index) => format!("E{index}"),
+    }
+}
+
+fn parse_depth(arg: &str) -> Option<usize> {
+    if arg.is_empty() {
+        Some(0)
+    } else {
+        arg.parse().ok()
+    }
+}
+
+fn show_stop(vm: &Vm) {
+    let Some(info) = vm.paused_frames().into_iter().next() else {
+        return;
+    };
+    let TraceFrame { function, pc, .. } = &info.frame;
+    let what = if vm.at_breakpoint() {
+        "breakpoint"
+    } else {
+        "stopped"
+    };
+    println!("{what} at {}", info.frame);
+    match &info.instr {
+        Some(instr) => println!("  {function}@{pc:04}: {instr:?}"),
+        None => println!("  {function}@{pc:04}: <end>"),
+    }
+}
+
+fn show_slots(vm: &Vm, arg: &str) {
+    let Some(depth) = parse_depth(arg) else {
+        eprintln!("error: slots expects a frame depth");
+        return;
+    };
+    let Some(info) = vm.paused_frames().into_iter().nth(depth) else {
+        eprintln!("error: no frame at depth {depth}");
+        return;
+    };
+    for (slot, value) in &info.slots {
+        let param = match slot {
+            Slot::Arg(index) => info.params.get(*index as usize),
+            _ => None,
+        };
+        match param {
+            Some(name) => println!("{} ({name}) = {value}", slot_name(*slot)),
+            None => println!("{} = {value}", slot_name(*slot)),
+        }
+    }
+}
+
+fn print_slot(vm: &Vm, arg: &str) {
+    let (name, depth) = arg
+        .split_once(char::is_whitespace)
+        .map_or((arg, ""), |(name, depth)| (name, depth.trim()));
+    let Some(depth) = parse_depth(depth) else {
+        eprintln!("error: print expects SLOT [DEPTH]");
+        return;
+    };
+    let param = vm.paused_frames().into_iter().nth(depth).and_then(|info| {
+        let index = info
+            .params
+            .iter()
+            .position(|param| param.as_ref() == name)?;
+        Some(Slot::Arg(u32::try_from(index).ok()?))
+    });
+    let Some(slot) = parse_slot(name).or(param) else {
+        eprintln!("error: print expects a slot (L0, A0, G0, R0, E0) or parameter name");
+        return;
+    };
+    match vm.paused_slot(depth, slot) {
+        Some(value) => println!("{name} = {value}"),
+        None => eprintln!("error: no slot {name} at depth {depth}"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn function_breakpoints_must_name_a_function_and_pc() {
+        let module = imp_compiler::compile_program(
+            "#call core::fn::begin name=main::double args=\"a\" retshape=\"scalar\";\n\
+             #call core::add a=arg::a b=arg::a out=return::value;\n\
+             #call core::exit;\n\
+             #call core::fn::end;\n\
+             #call core::exit;\n",
+            imp_compiler::CompileOpts::default(),
+        )
+        .expect("compile")
+        .module;
+        let init = module.function(module.init_func).expect("init");
+        let init_name = init.meta.name.to_string();
+
+        let at = |function: &str, pc| Breakpoint::At {
+            function: Arc::from(function),
+            pc,
+        };
+        let parse = |arg: &str| parse_breakpoint(arg, &module, None);
+        assert_eq!(parse("main::double"), Ok(at("main::double", 0)));
+        assert_eq!(parse("main::double@1"), Ok(at("main::double", 1)));
+        assert_eq!(parse(&init_name), Ok(at(&init_name, 0)));
+        assert_eq!(
+            parse("3"),
+            Ok(Breakpoint::Line {
+                source: None,
+                line: 3
+            })
+        );
+
+        assert!(parse("").is_err());
+        assert_eq!(
+            parse(&format!("{init_name}:2")),
+            Err(format!("no function named '{init_name}:2'"))
+        );
+        assert_eq!(
+            parse("double"),
+            Err("no function named 'double'".to_owned())
+        );
+        assert!(parse("main::double@x").is_err());
+        assert!(
+            parse("main::double@99").is_err_and(|err| err.starts_with("main::double has no pc 99"))
+        );
+    }
+}
@@ -1,9 +1,30 @@
-use imp_bytecode::{decode_from_path, encode_to_path};
-use imp_compiler::{FsModuleLoader, compile_module};
-use imp_ir::CompiledModule;
-use imp_vm::{Vm, VmConfig};
+use imp_ast::{Program, format_program, parse_program, upgrade_program};
+use imp_bytecode::{
+    FsModuleResolver, assemble, decode_module_linked, disassemble, encode_module,
+    encode_module_separate,
+};
+use imp_compiler::{
+    CompileError, CompileOpts, FsModuleLoader, compile_module_diagnostics,
+    compile_module_with_opts, prune_exports,
+};
+use imp_ir::{CompiledFunction, CompiledModule, ImportBinding};
+use imp_vm::{
+    Coverage, RunResult, TraceFrame, TraceSink, TraceWriter, Value, Vm, VmConfig, VmError,
+};
+use std::collections::{HashMap, HashSet};
 use std::env;
+use std::fmt::Write as _;
+use std::fs;
+use std::io;
 use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+mod dap;
+mod debug;
+mod profile;
+mod repl;
+
+use profile::Profile;
 
 fn main() {
     if let Err(err) = run() {
@@ -14,71 +35,558 @@ fn main() {
 
 fn run() -> Result<(), Box<dyn std::error::Error>> {
     let mut args = env::args().skip(1).collect::<Vec<_>>();
+    if args.first().is_some_and(|command| command == "repl") {
+        let mut debug = false;
+        for arg in &args[1..] {
+            match arg.as_str() {
+                "--debug" => debug = true,
+                other => return Err(format!("unknown option '{other}'").into()),
+            }
+        }
+        return repl::repl(debug);
+    }
+    if args.first().is_some_and(|command| command == "dap") {
+        if let Some(other) = args.get(1) {
+            return Err(format!("unknown option '{other}'").into());
+        }
+        return dap::dap();
+    }
+    if args.first().is_some_and(|command| command == "lsp") {
+        if let Some(other) = args.get(1) {
+            return Err(format!("unknown option '{other}'").into());
+        }
+        return Ok(imp_lsp::serve(io::stdin().lock(), io::stdout().lock())?);
+    }
     if args.len() < 2 {
-        eprintln!("usage: imp <run|dump-ir|build> <file.(imp|impc)> [options]");
+        eprintln!("usage: imp <run|dump-ir|build|test> <file.(imp|impc)> [options]");
+        eprintln!("       imp check <file.imp>");
+        eprintln!("       imp debug <file.(imp|impc)>");
+        eprintln!("       imp dap");
+        eprintln!("       imp lsp");
+        eprintln!("       imp fmt <file.imp> [--check]");
+        eprintln!("       imp upgrade <file.imp> [--dry-run]");
+        eprintln!("       imp disasm <file.(imp|impc)>");
+        eprintln!("       imp asm <listing> [-o out.impc]");
+        eprintln!("       imp profile-diff <before.json> <after.json>");
+        eprintln!("       imp repl [--debug]");
         return Ok(());
     }
 
     let command = args.remove(0);
     match command.as_str() {
-        "run" => {
-            let path = args.remove(0);
-            let strict = parse_strict_flag(&args)?;
-            let module = load_module(Path::new(&path), strict)?;
-            let mut cfg = VmConfig::default();
-            if std::env::var("IMP_NO_JIT").is_ok() {
-                cfg.enable_jit = false;
-            }
-            let mut vm = Vm::new(cfg);
-            let result = vm.run_main(&module)?;
-            println!("returns: {:?}", result.returns);
-            if !result.exports.is_empty() {
-                println!("exports: {:?}", result.exports);
-            }
+        "run" => run_file(&args.remove(0), &args)?,
+        "debug" => debug::debug(Path::new(&args.remove(0)))?,
+        "profile-diff" => {
+            let [before, after] = args.as_slice() else {
+                return Err("profile-diff expects <before.json> <after.json>".into());
+            };
+            let read = |path: &String| {
+                let text = fs::read_to_string(path).map_err(|err| format!("{path}: {err}"))?;
+                Profile::from_json(&text).map_err(|err| format!("{path}: {err}"))
+            };
+            print!("{}", profile::diff(&read(before)?, &read(after)?));
         }
         "dump-ir" => {
             let path = args.remove(0);
             let strict = parse_strict_flag(&args)?;
-            let module = load_module(Path::new(&path), strict)?;
+            let module = load_module(Path::new(&path), strict, 0)?;
             for function in &module.functions {
-                println!("fn#{} {}", function.id, function.meta.name);
-                for (pc, instr) in function.code.iter().enumerate() {
-                    println!("  {:04}: {:?}", pc, instr);
-                }
+                dump_function(function);
             }
         }
-        "build" => {
-            let input = args.remove(0);
-            let (out_path, strict) = parse_build_flags(Path::new(&input), &args)?;
-            if strict {
-                eprintln!("warning: --strict-bytecode has no effect for build");
+        "build" => build(&args.remove(0), &args)?,
+        "disasm" => {
+            let path = args.remove(0);
+            if let Some(other) = args.first() {
+                return Err(format!("unknown option '{other}'").into());
             }
-            if has_impc_extension(Path::new(&input)) {
-                return Err("build expects a .imp source input".into());
+            print!("{}", disassemble(&load_module(Path::new(&path), false, 0)?));
+        }
+        "asm" => {
+            let input = args.remove(0);
+            let out = match args.as_slice() {
+                [] => Path::new(&input).with_extension("impc"),
+                [flag, out] if flag == "-o" || flag == "--out" => PathBuf::from(out),
+                [other, ..] => return Err(format!("unknown option '{other}'").into()),
+            };
+            let module =
+                assemble(&fs::read_to_string(&input)?).map_err(|err| format!("{input}: {err}"))?;
+            fs::write(&out, encode_module(&module)?)?;
+            println!("wrote {}", out.display());
+        }
+        "check" => {
+            let path = args.remove(0);
+            if let Some(other) = args.first() {
+                return Err(format!("unknown option '{other}'").into());
             }
-            let module = compile_module(Path::new(&input), &FsModuleLoader)?;
-            encode_to_path(&out_path, &module)?;
-            println!("wrote {}", out_path.display());
+            return check(Path::new(&path));
         }
+        "fmt" => fmt(&args.remove(0), &args)?,
+        "upgrade" => upgrade(&args.remove(0), &args)?,
+        "test" => test(&args.remove(0), &args)?,
         _ => {
-            eprintln!("unknown command '{command}', expected run, dump-ir, or build");
+            eprintln!(
+                "unknown command '{command}', expected run, dump-ir, build, disasm, asm, check, fmt, test, debug, dap, lsp, profile-diff, or repl"
+            );
+        }
+    }
+
+    Ok(())
+}
+
+/// `imp run`: runs the program and prints its returns, exports and any
+/// reports the flags ask for.
+fn run_file(path: &str, args: &[String]) -> Result<(), Box<dyn std::error::Error>> {
+    let flags = parse_run_flags(args)?;
+    let module = load_module(Path::new(path), flags.strict, flags.opt_level)?;
+    let mut cfg = VmConfig::default();
+    cfg.coverage = flags.coverage || flags.lcov.is_some() || flags.profile_out.is_some();
+    cfg.profile = flags.profile || flags.profile_out.is_some();
+    cfg.fs_roots.clone_from(&flags.fs_roots);
+    cfg.program_args.clone_from(&flags.program_args);
+    cfg.trace = flags
+        .trace
+        .then(|| Arc::new(TraceWriter::stderr()) as Arc<dyn TraceSink>);
+    let mut vm = Vm::new(with_env_flags(cfg));
+    let result = vm.run_main(&module).map_err(|err| with_traceback(&err))?;
+    if !flags.quiet {
+        println!("returns: {}", render_returns(&result.returns));
+        if !result.exports.is_empty() {
+            println!("exports: {}", render_exports(&result.exports));
         }
     }
+    if let Some(name) = &flags.export {
+        println!("{}", render_export(&result, name)?);
+    }
+    report_coverage(vm.coverage(), flags.coverage, flags.lcov.as_deref())?;
+    if flags.profile {
+        print!("{}", vm.stats().report());
+    }
+    if let Some(out) = &flags.profile_out {
+        let profile = Profile::collect(vm.coverage(), result.stats.as_ref());
+        fs::write(out, profile.to_json())?;
+    }
+    Ok(())
+}
+
+/// `imp build`: compiles `input` to bytecode, with its imports when
+/// `--separate` is given.
+fn build(input: &str, args: &[String]) -> Result<(), Box<dyn std::error::Error>> {
+    let flags = parse_build_flags(Path::new(input), args)?;
+    if flags.strict {
+        eprintln!("warning: --strict-bytecode has no effect for build");
+    }
+    if has_impc_extension(Path::new(input)) {
+        return Err("build expects a .imp source input".into());
+    }
+    let mut module = compile_optimized(Path::new(input), flags.opt_level)?;
+    if let Some(keep) = &flags.prune_exports {
+        module = prune_exports(&module, keep)?;
+    }
+    let bytes = if flags.separate {
+        encode_module_separate(&module)?
+    } else {
+        encode_module(&module)?
+    };
+    fs::write(&flags.out, bytes)?;
+    println!("wrote {}", flags.out.display());
+    if flags.separate {
+        for import in linked_imports(&module) {
+            let out = Path::new(&import.path).with_extension("impc");
+            fs::write(&out, encode_module_separate(&import.module)?)?;
+            println!("wrote {}", out.display());
+        }
+    }
+    Ok(())
+}
 
+/// `imp fmt`: rewrites the file in canonical style, or with `--check`
+/// only fails if it would change.
+fn fmt(path: &str, args: &[String]) -> Result<(), Box<dyn std::error::Error>> {
+    let mut check = false;
+    for arg in args {
+        match arg.as_str() {
+            "--check" => check = true,
+            other => return Err(format!("unknown option '{other}'").into()),
+        }
+    }
+    let src = fs::read_to_string(path)?;
+    let formatted = format_program(&parse_source(&src)?);
+    if formatted != src {
+        if check {
+            return Err(format!("{path} is not formatted").into());
+        }
+        fs::write(path, formatted)?;
+        println!("formatted {path}");
+    }
     Ok(())
 }
 
+/// `imp upgrade`: rewrites older spellings to their current form.
+fn upgrade(path: &str, args: &[String]) -> Result<(), Box<dyn std::error::Error>> {
+    let mut dry_run = false;
+    for arg in args {
+        match arg.as_str() {
+            "--dry-run" => dry_run = true,
+            other => return Err(format!("unknown option '{other}'").into()),
+        }
+    }
+    let src = fs::read_to_string(path)?;
+    let mut program = parse_source(&src)?;
+    let notes = upgrade_program(&mut program);
+    let upgraded = format_program(&program);
+    for note in &notes {
+        println!("{path}: {note}");
+    }
+    if upgraded == src {
+        println!("{path} is up to date");
+    } else if dry_run {
+        print!("{}", line_diff(&src, &upgraded));
+    } else {
+        fs::write(path, upgraded)?;
+        println!("upgraded {path}");
+    }
+    Ok(())
+}
+
+/// `imp test`: runs the `@test` functions, or compares the run with its
+/// snapshot when there are none.
+fn test(path: &str, args: &[String]) -> Result<(), Box<dyn std::error::Error>> {
+    let flags = parse_test_flags(args)?;
+    let module = load_module(Path::new(path), flags.strict, 0)?;
+    if !module.tests.is_empty() {
+        if flags.update {
+            eprintln!("warning: --update has no effect for @test functions");
+        }
+        return run_test_functions(&module, &flags);
+    }
+    let mut vm = Vm::new(test_vm_config(flags.collects_coverage()));
+    let actual = vm
+        .run_main(&module)
+        .map_err(|err| with_traceback(&err))?
+        .to_snapshot();
+    let checked = check_snapshot(&snapshot_path(Path::new(path)), &actual, flags.update);
+    report_coverage(vm.coverage(), flags.coverage, flags.lcov.as_deref())?;
+    checked
+}
+
+/// Parses and compiles `path` and its imports without running anything,
+/// printing each error as `path:line:column: error: message`.
+fn check(path: &Path) -> Result<(), Box<dyn std::error::Error>> {
+    if has_impc_extension(path) {
+        return Err("check expects a .imp source input".into());
+    }
+    let (lines, errors) = check_report(path);
+    for line in lines {
+        println!("{line}");
+    }
+    if errors > 0 {
+        return Err(format!("{} has {errors} error(s)", path.display()).into());
+    }
+    Ok(())
+}
+
+/// The lines `imp check` prints for `path`, and how many errors they hold.
+fn check_report(path: &Path) -> (Vec<String>, usize) {
+    let report = compile_module_diagnostics(path, &FsModuleLoader, &CompileOpts::default());
+    let src = fs::read_to_string(path).unwrap_or_default();
+    let mut lines = Vec::new();
+    for warning in &report.diagnostics.warnings {
+        lines.push(format!("warning: {warning}"));
+    }
+    for err in &report.diagnostics.errors {
+        let column = err
+            .span
+            .map(|span| format!(":{}", span.column))
+            .unwrap_or_default();
+        lines.push(format!(
+            "{}:{}{column}: error: {}",
+            path.display(),
+            err.line,
+            err.message
+        ));
+        for note in &err.notes {
+            lines.push(format!("  {note}"));
+        }
+        if let Some(span) = err.span.filter(|_| err.notes.is_empty()) {
+            for line in span.underline(&src).lines() {
+                lines.push(format!("  {line}"));
+            }
+        }
+    }
+    (lines, report.diagnostics.errors.len())
+}
+
+/// Runs each `@test` function on a fresh VM; a test passes unless it throws
+/// or fails at runtime.
+fn run_test_functions(
+    module: &CompiledModule,
+    flags: &TestFlags,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut failed = 0;
+    let mut merged = Coverage::default();
+    for func in &module.tests {
+        let name = module
+            .function(*func)
+            .map_or("<unknown>", |function| function.meta.name.as_ref());
+        let mut vm = Vm::new(test_vm_config(flags.collects_coverage()));
+        match vm.run_function(module, *func, &[]) {
+            Ok(_) => println!("test {name} ... ok"),
+            Err(err) => {
+                failed += 1;
+                println!("test {name} ... FAILED");
+                for line in with_traceback(&err).to_string().lines() {
+                    println!("    {line}");
+                }
+            }
+        }
+        merged.merge(vm.coverage());
+    }
+    report_coverage(&merged, flags.coverage, flags.lcov.as_deref())?;
+    let passed = module.tests.len() - failed;
+    println!("{passed} passed, {failed} failed");
+    if failed > 0 {
+        return Err(format!("{failed} of {} tests failed", module.tests.len()).into());
+    }
+    Ok(())
+}
+
+/// Prints the `--coverage` listing when `print` is set and writes the
+/// `--lcov` tracefile when asked for one.
+fn report_coverage(coverage: &Coverage, print: bool, lcov: Option<&Path>) -> io::Result<()> {
+    if print {
+        print!("{}", coverage.report());
+    }
+    match lcov {
+        Some(path) => fs::write(path, coverage.lcov()),
+        None => Ok(()),
+    }
+}
+
+/// The VM `imp test` runs snapshots and `@test` functions on: host output
+/// off, and the same environment flags as `imp run`.
+fn test_vm_config(coverage: bool) -> VmConfig {
+    let mut cfg = VmConfig::default();
+    cfg.enable_host_print = false;
+    cfg.coverage = coverage;
+    with_env_flags(cfg)
+}
+
+/// Applies `IMP_NO_JIT` and `IMP_VERIFY_JIT`.
+fn with_env_flags(mut cfg: VmConfig) -> VmConfig {
+    if env::var("IMP_NO_JIT").is_ok() {
+        cfg.enable_jit = false;
+    }
+    if env::var("IMP_VERIFY_JIT").is_ok() {
+        cfg.verify_jit = true;
+    }
+    cfg
+}
+
+/// Prints `function`'s instructions, one per line, with `; line N` and the
+/// source path when it carries debug info.
+fn dump_function(function: &CompiledFunction) {
+    let debug = function.meta.debug.as_deref();
+    match debug.and_then(|debug| debug.source.as_deref()) {
+        Some(source) => {
+            println!("fn#{} {} ({source})", function.id, function.meta.name);
+        }
+        None => println!("fn#{} {}", function.id, function.meta.name),
+    }
+    for (pc, instr) in function.code.iter().enumerate() {
+        match debug.and_then(|debug| debug.line(pc)) {
+            Some(line) => println!("  {pc:04}: {instr:?}  ; line {line}"),
+            None => println!("  {pc:04}: {instr:?}"),
+        }
+    }
+}
+
+/// The error message followed by one `at` line per frame it escaped.
+fn with_traceback(err: &VmError) -> Box<dyn std::error::Error> {
+    let mut text = err.to_string();
+    for line in traceback_lines(err.traceback()) {
+        let _ = write!(text, "\n  {line}");
+    }
+    text.into()
+}
+
+/// One `at` line per frame, with folded repeats and omitted frames each
+/// summed up on a line of their own.
+fn traceback_lines(frames: &[TraceFrame]) -> Vec<String> {
+    let mut lines = Vec::new();
+    for frame in frames {
+        lines.push(format!("at {frame}"));
+        if frame.repeats > 0 {
+            lines.push(format!(
+                "… {} more frames of {}",
+                frame.repeats, frame.function
+            ));
+        }
+        if frame.omitted > 0 {
+            lines.push(format!("… {} more frames omitted", frame.omitted));
+        }
+    }
+    lines
+}
+
+/// The error followed by its source line with the offending text
+/// underlined, when the error is in `src` rather than in an import.
+fn with_snippet(err: &CompileError, src: &str) -> Box<dyn std::error::Error> {
+    let mut text = err.to_string();
+    if let Some(span) = err.span.filter(|_| err.notes.is_empty()) {
+        for line in span.underline(src).lines() {
+            let _ = write!(text, "\n  {line}");
+        }
+    }
+    text.into()
+}
+
+/// `src` parsed, or the parse error with the offending text underlined.
+fn parse_source(src: &str) -> Result<Program, Box<dyn std::error::Error>> {
+    parse_program(src).map_err(|err| {
+        let mut text = err.to_string();
+        for line in err.span.underline(src).lines() {
+            let _ = write!(text, "\n  {line}");
+        }
+        text.into()
+    })
+}
+
+/// The lines that differ between `old` and `new`, as `@@ line N` hunks of
+/// `-old` and `+new` lines (line numbers are `old`'s). Lines common to both
+/// are matched by longest common subsequence, unless the changed middle is
+/// too big for that, when it is shown as one hunk.
+fn line_diff(old: &str, new: &str) -> String {
+    const MAX_CELLS: usize = 1 << 22;
+    let old = old.lines().collect::<Vec<_>>();
+    let new = new.lines().collect::<Vec<_>>();
+    let prefix = old.iter().zip(&new).take_while(|(a, b)| a == b).count();
+    let suffix = old[prefix..]
+        .iter()
+        .rev()
+        .zip(new[prefix..].iter().rev())
+        .take_while(|(a, b)| a == b)
+        .count();
+    let (old_mid, new_mid) = (
+        &old[prefix..old.len() - suffix],
+        &new[prefix..new.len() - suffix],
+    );
+    // `(old index, new index)` of each line kept, in order.
+    let mut kept = Vec::new();
+    if old_mid.len().saturating_mul(new_mid.len()) <= MAX_CELLS {
+        let width = new_mid.len() + 1;
+        let mut lcs = vec![0_u32; (old_mid.len() + 1) * width];
+        for i in (0..old_mid.len()).rev() {
+            for j in (0..new_mid.len()).rev() {
+                lcs[i * width + j] = if old_mid[i] == new_mid[j] {
+                    lcs[(i + 1) * width + j + 1] + 1
+                } else {
+                    lcs[(i + 1) * width + j].max(lcs[i * width + j + 1])
+                };
+            }
+        }
+        let (mut i, mut j) = (0, 0);
+        while i < old_mid.len() && j < new_mid.len() {
+            if old_mid[i] == new_mid[j] {
+                kept.push((i, j));
+                i += 1;
+                j += 1;
+            } else if lcs[(i + 1) * width + j] >= lcs[i * width + j + 1] {
+                i += 1;
+            } else {
+                j += 1;
+            }
+        }
+    }
+    kept.push((old_mid.len(), new_mid.len()));
+    let mut out = String::new();
+    let (mut i, mut j) = (0, 0);
+    for (next_i, next_j) in kept {
+        if next_i > i || next_j > j {
+            let _ = writeln!(out, "@@ line {}", prefix + i + 1);
+            for line in &old_mid[i..next_i] {
+                let _ = writeln!(out, "-{line}");
+            }
+            for line in &new_mid[j..next_j] {
+                let _ = writeln!(out, "+{line}");
+            }
+        }
+        (i, j) = (next_i + 1, next_j + 1);
+    }
+    out
+}
+
+fn render_returns(values: &[Value]) -> String {
+    let items = values.iter().map(ToString::to_string).collect::<Vec<_>>();
+    format!("[{}]", items.join(", "))
+}
+
+/// The `--export NAME` line, as a shell script wants it: strings unquoted,
+/// lists and objects as JSON (displayed if JSON has no form for them), the
+/// rest as displayed. A missing or error export fails the run.
+fn render_export(result: &RunResult, name: &str) -> Result<String, String> {
+    let value = result
+        .exports_path(name)
+        .ok_or_else(|| format!("export '{name}' not found"))?;
+    Ok(match value {
+        Value::Error { .. } => return Err(format!("export '{name}' is {value}")),
+        Value::Str(text) => text.to_string(),
+        Value::List(_) | Value::Obj(_) => value.to_json().unwrap_or_else(|_| value.to_string()),
+        other => other.to_string(),
+    })
+}
+
+fn render_exports(exports: &HashMap<String, Value>) -> String {
+    let mut names = exports.keys().collect::<Vec<_>>();
+    names.sort();
+    let items = names
+        .into_iter()
+        .map(|name| format!("{name}: {}", exports[name]))
+        .collect::<Vec<_>>();
+    format!("{{{}}}", items.join(", "))
+}
+
 fn load_module(
     path: &Path,
     strict_bytecode: bool,
+    opt_level: u8,
 ) -> Result<CompiledModule, Box<dyn std::error::Error>> {
     if has_impc_extension(path) {
-        return Ok(decode_from_path(path)?);
+        return Ok(decode_module_linked(&fs::read(path)?, &FsModuleResolver)?);
     }
     if strict_bytecode {
         return Err("strict bytecode mode requires .impc input".into());
     }
-    Ok(compile_module(path, &FsModuleLoader)?)
+    compile_optimized(path, opt_level)
+}
+
+fn compile_optimized(
+    path: &Path,
+    opt_level: u8,
+) -> Result<CompiledModule, Box<dyn std::error::Error>> {
+    let opts = CompileOpts {
+        opt_level,
+        ..CompileOpts::default()
+    };
+    let output = compile_module_with_opts(path, &FsModuleLoader, &opts).map_err(|err| {
+        let Ok(src) = fs::read_to_string(path) else {
+            return err.into();
+        };
+        // Recompile collecting every error; the last one becomes the
+        // returned error so the exit status and final line stay as before.
+        let mut errors = compile_module_diagnostics(path, &FsModuleLoader, &opts)
+            .diagnostics
+            .errors;
+        let last = errors.pop().unwrap_or(err);
+        for earlier in errors {
+            eprintln!("error: {}", with_snippet(&earlier, &src));
+        }
+        with_snippet(&last, &src)
+    })?;
+    for warning in &output.warnings {
+        eprintln!("warning: {warning}");
+    }
+    Ok(output.module)
 }
 
 fn parse_strict_flag(args: &[String]) -> Result<bool, Box<dyn std::error::Error>> {
@@ -94,11 +602,169 @@ fn parse_strict_flag(args: &[String]) -> Result<bool, Box<dyn std::error::Error>
     Ok(strict)
 }
 
+// One flag per `imp run` switch.
+#[allow(clippy::struct_excessive_bools)]
+#[derive(Debug, Default)]
+struct RunFlags {
+    strict: bool,
+    coverage: bool,
+    /// Skip the `returns:` / `exports:` lines.
+    quiet: bool,
+    /// Log every executed instruction to stderr.
+    trace: bool,
+    /// Print per-function calls, instructions and time after the run.
+    profile: bool,
+    /// Dotted export path whose value is printed on its own line.
+    export: Option<String>,
+    /// Where to write an lcov tracefile of the run's coverage.
+    lcov: Option<PathBuf>,
+    /// Where to write the run's profile for `imp profile-diff`.
+    profile_out: Option<PathBuf>,
+    /// `CompileOpts::opt_level` for `.imp` input; `-O` sets 1.
+    opt_level: u8,
+    /// `VmConfig::fs_roots`, one per `--allow-fs <dir>`.
+    fs_roots: Vec<PathBuf>,
+    /// `VmConfig::program_args`: everything after `--`.
+    program_args: Vec<String>,
+}
+
+fn parse_run_flags(args: &[String]) -> Result<RunFlags, Box<dyn std::error::Error>> {
+    let mut flags = RunFlags::default();
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--strict-bytecode" => flags.strict = true,
+            "--coverage" => flags.coverage = true,
+            "--quiet" => flags.quiet = true,
+            "--trace" => flags.trace = true,
+            "--profile" => flags.profile = true,
+            "-O" => flags.opt_level = 1,
+            "--export" => {
+                let name = args.next().ok_or("--export expects an export name")?;
+                flags.export = Some(name.clone());
+            }
+            "--lcov" => {
+                let out = args.next().ok_or("--lcov expects a file path")?;
+                flags.lcov = Some(PathBuf::from(out));
+            }
+            "--profile-out" => {
+                let out = args.next().ok_or("--profile-out expects a file path")?;
+                flags.profile_out = Some(PathBuf::from(out));
+            }
+            "--allow-fs" => {
+                let dir = args.next().ok_or("--allow-fs expects a directory")?;
+                flags.fs_roots.push(PathBuf::from(dir));
+            }
+            "--" => {
+                flags.program_args = args.cloned().collect();
+                break;
+            }
+            other => return Err(format!("unknown option '{other}'").into()),
+        }
+    }
+    Ok(flags)
+}
+
+#[derive(Debug, Default)]
+struct TestFlags {
+    strict: bool,
+    /// Rewrite the `.snap` file instead of comparing against it.
+    update: bool,
+    /// Print the annotated coverage listing.
+    coverage: bool,
+    /// Where to write an lcov tracefile of the tests' coverage.
+    lcov: Option<PathBuf>,
+}
+
+impl TestFlags {
+    fn collects_coverage(&self) -> bool {
+        self.coverage || self.lcov.is_some()
+    }
+}
+
+fn parse_test_flags(args: &[String]) -> Result<TestFlags, Box<dyn std::error::Error>> {
+    let mut flags = TestFlags::default();
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--strict-bytecode" => flags.strict = true,
+            "--update" => flags.update = true,
+            "--coverage" => flags.coverage = true,
+            "--lcov" => {
+                let out = args.next().ok_or("--lcov expects a file path")?;
+                flags.lcov = Some(PathBuf::from(out));
+            }
+            other => return Err(format!("unknown option '{other}'").into()),
+        }
+    }
+    Ok(flags)
+}
+
+fn snapshot_path(input: &Path) -> PathBuf {
+    let mut output = input.to_path_buf();
+    output.set_extension("snap");
+    output
+}
+
+fn check_snapshot(
+    path: &Path,
+    actual: &str,
+    update: bool,
+) -> Result<(), Box<dyn std::error::Error>> {
+    if update {
+        fs::write(path, actual)?;
+        println!("updated {}", path.display());
+        return Ok(());
+    }
+    let expected = fs::read_to_string(path).map_err(|err| {
+        format!(
+            "cannot read snapshot {}: {err} (run with --update to create it)",
+            path.display()
+        )
+    })?;
+    if expected == actual {
+        println!("ok {}", path.display());
+        return Ok(());
+    }
+
+    println!("snapshot mismatch: {}", path.display());
+    let expected_lines = expected.lines().collect::<Vec<_>>();
+    let actual_lines = actual.lines().collect::<Vec<_>>();
+    for index in 0..expected_lines.len().max(actual_lines.len()) {
+        match (expected_lines.get(index), actual_lines.get(index)) {
+            (Some(old), Some(new)) if old == new => println!("  {old}"),
+            (old, new) => {
+                if let Some(old) = old {
+                    println!("- {old}");
+                }
+                if let Some(new) = new {
+                    println!("+ {new}");
+                }
+            }
+        }
+    }
+    Err("snapshot mismatch (run with --update to accept)".into())
+}
+
+struct BuildFlags {
+    out: PathBuf,
+    strict: bool,
+    opt_level: u8,
+    /// Record imports by path and build each import to its own `.impc`
+    /// beside its source, where `FsModuleResolver` looks for it.
+    separate: bool,
+    /// Exports to keep; everything they and the inits cannot reach is dropped.
+    prune_exports: Option<Vec<String>>,
+}
+
 fn parse_build_flags(
     input: &Path,
     args: &[String],
-) -> Result<(PathBuf, bool), Box<dyn std::error::Error>> {
+) -> Result<BuildFlags, Box<dyn std::error::Error>> {
     let mut strict = false;
+    let mut opt_level = 0;
+    let mut separate = false;
+    let mut prune_exports = None;
     let mut out: Option<PathBuf> = None;
     let mut i = 0usize;
     while i < args.len() {
@@ -107,6 +773,30 @@ fn parse_build_flags(
                 strict = true;
                 i += 1;
             }
+            "-O" => {
+                opt_level = 1;
+                i += 1;
+            }
+            "--separate" => {
+                separate = true;
+                i += 1;
+            }
+            "--prune-exports" => {
+                let Some(next) = args.get(i + 1) else {
+                    return Err("missing export list after --prune-exports".into());
+                };
+                let names = next
+                    .split(',')
+                    .map(str::trim)
+                    .filter(|name| !name.is_empty())
+                    .map(str::to_owned)
+                    .collect::<Vec<_>>();
+                if names.is_empty() {
+                    return Err("--prune-exports needs at least one export name".into());
+                }
+                prune_exports = Some(names);
+                i += 2;
+            }
             "-o" | "--out" => {
                 let Some(next) = args.get(i + 1) else {
                     return Err("missing output path after -o/--out".into());
@@ -123,7 +813,27 @@ fn parse_build_flags(
     } else {
         default_impc_path(input)
     };
-    Ok((out, strict))
+    Ok(BuildFlags {
+        out,
+        strict,
+        opt_level,
+        separate,
+        prune_exports,
+    })
+}
+
+/// Every import reachable from `module`, once per path.
+fn linked_imports(module: &CompiledModule) -> Vec<&ImportBinding> {
+    let mut seen = HashSet::new();
+    let mut found = Vec::new();
+    let mut pending = module.imports.iter().collect::<Vec<_>>();
+    while let Some(import) = pending.pop() {
+        if seen.insert(import.path.as_str()) {
+            pending.extend(&import.module.imports);
+            found.push(import);
+        }
+    }
+    found
 }
 
 fn default_impc_path(input: &Path) -> PathBuf {
@@ -135,3 +845,178 @@ fn default_impc_path(input: &Path) -> PathBuf {
 fn has_impc_extension(path: &Path) -> bool {
     matches!(path.extension().and_then(|s| s.to_str()), Some("impc"))
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn line_diff_shows_only_changed_lines() {
+        let old = "a\nb\nc\nd\ne\n";
+        let new = "a\nB\nc\nd\ne\nf\n";
+        assert_eq!(line_diff(old, new), "@@ line 2\n-b\n+B\n@@ line 6\n+f\n");
+        assert_eq!(line_diff(old, old), "");
+    }
+
+    /// A fresh directory under the system temp dir.
+    fn scratch(name: &str) -> PathBuf {
+        let dir = env::temp_dir().join(format!("imp_cli_{name}"));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).expect("scratch dir");
+        dir
+    }
+
+    #[test]
+    fn snapshots_are_created_by_update_and_then_compared() {
+        let dir = scratch("snapshot");
+        let source = dir.join("answer.imp");
+        fs::write(
+            &source,
+            "#call core::const value=42 out=return::answer;\n#call core::exit;\n",
+        )
+        .expect("write source");
+        let module = load_module(&source, false, 0).expect("load");
+        let run = |module: &CompiledModule| {
+            Vm::new(test_vm_config(false))
+                .run_main(module)
+                .expect("run")
+                .to_snapshot()
+        };
+        let snap = snapshot_path(&source);
+        let actual = run(&module);
+        assert!(check_snapshot(&snap, &actual, false).is_err());
+        check_snapshot(&snap, &actual, true).expect("update");
+        check_snapshot(&snap, &actual, false).expect("unchanged run matches");
+
+        fs::write(
+            &source,
+            "#call core::const value=43 out=return::answer;\n#call core::exit;\n",
+        )
+        .expect("rewrite source");
+        let changed = run(&load_module(&source, false, 0).expect("reload"));
+        assert_ne!(changed, actual);
+        assert!(check_snapshot(&snap, &changed, false).is_err());
+        assert_eq!(fs::read_to_string(&snap).expect("snapshot"), actual);
+    }
+
+    #[test]
+    fn run_flags_parse_export_and_program_args() {
+        let args = |list: &[&str]| list.iter().map(|arg| (*arg).to_owned()).collect::<Vec<_>>();
+        let flags = parse_run_flags(&args(&[
+            "--quiet",
+            "--export",
+            "a.b",
+            "-O",
+            "--allow-fs",
+            "data",
+            "--",
+            "--quiet",
+            "x",
+        ]))
+        .expect("flags");
+        assert!(flags.quiet && !flags.coverage);
+        assert_eq!(flags.export.as_deref(), Some("a.b"));
+        assert_eq!(flags.opt_level, 1);
+        assert_eq!(flags.fs_roots, [PathBuf::from("data")]);
+        assert_eq!(flags.program_args, ["--quiet", "x"]);
+
+        assert!(parse_run_flags(&args(&["--export"])).is_err());
+        assert!(parse_run_flags(&args(&["--bogus"])).is_err());
+    }
+
+    #[test]
+    fn exports_render_strings_bare_and_containers_as_json() {
+        let dir = scratch("export");
+        let source = dir.join("exports.imp");
+        fs::write(
+            &source,
+            r#"#call core::const value="hi" out=main::name;
+#call core::const value=3 out=main::count;
+#call core::list::new out=main::items;
+#call core::list::push list=main::items value=main::name out=main::items;
+#call core::list::push list=main::items value=main::count out=main::items;
+#call core::obj::new out=main::config;
+#call core::obj::set obj=main::config key="items" value=main::items;
+#call core::error::wrap err=main::cause code="bad" msg="no" out=main::failed;
+#call core::mod::export name="name" value=main::name;
+#call core::mod::export name="count" value=main::count;
+#call core::mod::export name="config" value=main::config;
+#call core::mod::export name="failed" value=main::failed;
+#call core::exit;
+"#,
+        )
+        .expect("write source");
+        let module = load_module(&source, false, 0).expect("load");
+        let result = Vm::new(test_vm_config(false))
+            .run_main(&module)
+            .expect("run");
+        assert_eq!(render_export(&result, "name").as_deref(), Ok("hi"));
+        assert_eq!(render_export(&result, "count").as_deref(), Ok("3"));
+        assert_eq!(
+            render_export(&result, "config").as_deref(),
+            Ok(r#"{"items":["hi",3]}"#)
+        );
+        assert_eq!(
+            render_export(&result, "config.items").as_deref(),
+            Ok(r#"["hi",3]"#)
+        );
+        let failed = render_export(&result, "failed").expect_err("error export");
+        assert!(failed.starts_with("export 'failed' is "), "{failed}");
+        assert_eq!(
+            render_export(&result, "missing"),
+            Err("export 'missing' not found".to_owned())
+        );
+    }
+
+    #[test]
+    fn lcov_maps_test_hits_to_source_lines() {
+        let dir = scratch("lcov");
+        let source = dir.join("tested.imp");
+        fs::write(
+            &source,
+            "#call core::exit;\n#call @test core::fn::begin name=main::t args=\"\";\n\
+             #call core::exit;\n#call core::fn::end;\n",
+        )
+        .expect("write source");
+        let lcov = dir.join("out.lcov");
+        let flags =
+            parse_test_flags(&["--lcov".to_owned(), lcov.display().to_string()]).expect("flags");
+        assert!(flags.collects_coverage() && !flags.coverage);
+        let module = load_module(&source, false, 0).expect("load");
+        run_test_functions(&module, &flags).expect("tests pass");
+
+        let tracefile = fs::read_to_string(&lcov).expect("tracefile");
+        assert!(
+            tracefile.starts_with(&format!("TN:\nSF:{}\n", source.display())),
+            "{tracefile}"
+        );
+        assert!(tracefile.contains("FNDA:1,main::t\n"), "{tracefile}");
+        assert!(tracefile.contains("DA:3,1\n"), "{tracefile}");
+        assert!(tracefile.ends_with("end_of_record\n"));
+    }
+
+    #[test]
+    fn check_reports_every_error_with_its_position() {
+        let dir = scratch("check");
+        let path = dir.join("bad.imp");
+        fs::write(
+            &path,
+            "#call core::bogus;\n#call core::const value=1 out=local::x;\n#call core::nope;\n",
+        )
+        .expect("write source");
+        let (lines, errors) = check_report(&path);
+        assert_eq!(errors, 2);
+        let errors = lines
+            .iter()
+            .filter(|line| line.contains(": error: "))
+            .collect::<Vec<_>>();
+        assert_eq!(errors.len(), 2);
+        assert!(errors[0].starts_with(&format!("{}:1:", path.display())));
+        assert!(errors[1].starts_with(&format!("{}:3:", path.display())));
+
+        let good = dir.join("good.imp");
+        fs::write(&good, "#call core::exit;\n").expect("write source");
+        assert_eq!(check_report(&good), (Vec::new(), 0));
+        assert!(check(&dir.join("good.impc")).is_err());
+    }
+}
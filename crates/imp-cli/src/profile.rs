@@ -0,0 +1,235 @@
+//! `imp run --profile-out` profiles and `imp profile-diff`.
+//!
+//! A profile is a small JSON object:
+//!
+//! ```text
+//! {"version":1,"wall_ms":1.5,"instructions":120,
+//!  "opcodes":{"Add":10,...},"functions":{"main/main::f":40,...}}
+//! ```
+//!
+//! Counts come from coverage hits, so `functions` is each function's own
+//! executed instructions (`module/function`); the VM has no per-function
+//! clock, so time is only recorded for the whole run.
+
+use imp_std::json::{Json, quote};
+use imp_vm::{Coverage, RunStats};
+use std::collections::BTreeMap;
+use std::fmt::Write;
+
+const VERSION: f64 = 1.0;
+
+#[derive(Debug, Default)]
+pub struct Profile {
+    wall_ms: f64,
+    instructions: u64,
+    opcodes: BTreeMap<String, u64>,
+    functions: BTreeMap<String, u64>,
+}
+
+impl Profile {
+    pub fn collect(coverage: &Coverage, stats: Option<&RunStats>) -> Self {
+        let mut profile = Profile {
+            wall_ms: stats.map_or(0.0, |stats| stats.wall_time.as_secs_f64() * 1000.0),
+            ..Profile::default()
+        };
+        for function in coverage.functions() {
+            let mut own = 0;
+            for (instr, hits) in function.code.iter().zip(&function.hits) {
+                if *hits == 0 {
+                    continue;
+                }
+                *profile.opcodes.entry(opcode(instr)).or_default() += hits;
+                own += hits;
+            }
+            profile.instructions += own;
+            profile
+                .functions
+                .insert(format!("{}/{}", function.module, function.function), own);
+        }
+        profile
+    }
+
+    pub fn to_json(&self) -> String {
+        let counts = |map: &BTreeMap<String, u64>| {
+            let items = map
+                .iter()
+                .map(|(name, count)| format!("{}:{count}", quote(name)))
+                .collect::<Vec<_>>();
+            format!("{{{}}}", items.join(","))
+        };
+        format!(
+            "{{\"version\":{VERSION},\"wall_ms\":{},\"instructions\":{},\"opcodes\":{},\"functions\":{}}}\n",
+            self.wall_ms,
+            self.instructions,
+            counts(&self.opcodes),
+            counts(&self.functions)
+        )
+    }
+
+    pub fn from_json(text: &str) -> Result<Self, String> {
+        let Json::Obj(fields) = Json::parse(text)? else {
+            return Err("profile must be a JSON object".to_owned());
+        };
+        let field = |name: &str| fields.iter().find(|(key, _)| key == name).map(|(_, v)| v);
+        match field("version") {
+            Some(Json::Num(version)) if *version == VERSION => {}
+            _ => return Err("unsupported profile version".to_owned()),
+        }
+        let num = |name: &str| match field(name) {
+            Some(Json::Num(value)) => Ok(*value),
+            _ => Err(format!("profile is missing number '{name}'")),
+        };
+        let counts = |name: &str| match field(name) {
+            Some(Json::Obj(entries)) => entries
+                .iter()
+                .map(|(key, value)| {
+                    value
+                        .as_u64()
+                        .map(|count| (key.clone(), count))
+                        .ok_or_else(|| format!("'{name}.{key}' must be a whole number"))
+                })
+                .collect(),
+            _ => Err(format!("profile is missing object '{name}'")),
+        };
+        Ok(Profile {
+            wall_ms: num("wall_ms")?,
+            instructions: field("instructions")
+                .and_then(Json::as_u64)
+                .ok_or("profile is missing count 'instructions'")?,
+            opcodes: counts("opcodes")?,
+            functions: counts("functions")?,
+        })
+    }
+}
+
+/// A report of what changed from `before` to `after`: each section lists
+/// regressions (more instructions) then improvements, largest first.
+pub fn diff(before: &Profile, after: &Profile) -> String {
+    let mut out = String::new();
+    let _ = writeln!(
+        out,
+        "instructions: {} -> {} ({})",
+        before.instructions,
+        after.instructions,
+        change(before.instructions, after.instructions)
+    );
+    let _ = writeln!(
+        out,
+        "wall time: {:.3}ms -> {:.3}ms",
+        before.wall_ms, after.wall_ms
+    );
+    diff_section(&mut out, "opcodes", &before.opcodes, &after.opcodes);
+    diff_section(&mut out, "functions", &before.functions, &after.functions);
+    out
+}
+
+fn diff_section(
+    out: &mut String,
+    title: &str,
+    before: &BTreeMap<String, u64>,
+    after: &BTreeMap<String, u64>,
+) {
+    let mut changes = before
+        .keys()
+        .chain(after.keys())
+        .filter_map(|name| {
+            let old = before.get(name).copied().unwrap_or(0);
+            let new = after.get(name).copied().unwrap_or(0);
+            (old != new).then_some((name, old, new))
+        })
+        .collect::<Vec<_>>();
+    changes.sort();
+    changes.dedup();
+    changes.sort_by_key(|(_, old, new)| std::cmp::Reverse(old.abs_diff(*new)));
+    let _ = writeln!(out, "{title}:");
+    if changes.is_empty() {
+        let _ = writeln!(out, "  no changes");
+        return;
+    }
+    for (label, regressed) in [("regressions", true), ("improvements", false)] {
+        let rows = changes
+            .iter()
+            .filter(|(_, old, new)| (new > old) == regressed)
+            .collect::<Vec<_>>();
+        if rows.is_empty() {
+            continue;
+        }
+        let _ = writeln!(out, "  {label}:");
+        for (name, old, new) in rows {
+            let _ = writeln!(out, "    {name}: {old} -> {new} ({})", change(*old, *new));
+        }
+    }
+}
+
+/// Signed difference with a percentage when `old` is non-zero.
+// Rounding past 2^53 does not show in a percentage.
+#[allow(clippy::cast_precision_loss)]
+fn change(old: u64, new: u64) -> String {
+    let sign = if new >= old { "+" } else { "-" };
+    let delta = old.abs_diff(new);
+    if old == 0 {
+        return format!("{sign}{delta}");
+    }
+    format!(
+        "{sign}{delta}, {sign}{:.1}%",
+        delta as f64 * 100.0 / old as f64
+    )
+}
+
+/// The variant name of an instruction, e.g. `Add`.
+fn opcode(instr: &imp_ir::Instr) -> String {
+    let text = format!("{instr:?}");
+    let end = text
+        .find(|ch: char| !ch.is_ascii_alphanumeric())
+        .unwrap_or(text.len());
+    text[..end].to_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn profile(instructions: u64, opcodes: &[(&str, u64)], functions: &[(&str, u64)]) -> Profile {
+        let counts = |pairs: &[(&str, u64)]| {
+            pairs
+                .iter()
+                .map(|(name, count)| ((*name).to_owned(), *count))
+                .collect()
+        };
+        Profile {
+            wall_ms: 1.5,
+            instructions,
+            opcodes: counts(opcodes),
+            functions: counts(functions),
+        }
+    }
+
+    #[test]
+    fn profiles_round_trip_and_diff_by_size_of_change() {
+        let before = profile(100, &[("Add", 40), ("Jump", 60)], &[("main/<init>", 100)]);
+        let after = profile(
+            90,
+            &[("Add", 50), ("Jump", 30), ("Mov", 10)],
+            &[("main/<init>", 90)],
+        );
+        let text = after.to_json();
+        let parsed = Profile::from_json(&text).expect("parse profile");
+        assert_eq!(parsed.to_json(), text);
+        assert!(Profile::from_json(r#"{"version":2}"#).is_err());
+
+        assert_eq!(
+            diff(&before, &parsed),
+            "instructions: 100 -> 90 (-10, -10.0%)\n\
+             wall time: 1.500ms -> 1.500ms\n\
+             opcodes:\n\
+             \x20 regressions:\n\
+             \x20   Add: 40 -> 50 (+10, +25.0%)\n\
+             \x20   Mov: 0 -> 10 (+10)\n\
+             \x20 improvements:\n\
+             \x20   Jump: 60 -> 30 (-30, -50.0%)\n\
+             functions:\n\
+             \x20 improvements:\n\
+             \x20   main/<init>: 100 -> 90 (-10, -10.0%)\n"
+        );
+    }
+}
@@ -0,0 +1,230 @@
+//! `imp repl`: an incremental session over stdin.
+//!
+//! With `--debug` the prompt also takes inspection commands over the
+//! session so far: `:ir NAME`, `:disasm`, `:globals`, `:bt` and
+//! `:time STATEMENT`.
+
+use crate::{
+    dump_function, render_returns, traceback_lines, with_env_flags, with_snippet, with_traceback,
+};
+use imp_compiler::{CompileOpts, FsModuleLoader, IncrementalCompiler};
+use imp_ir::{CompiledFunction, CompiledModule};
+use imp_vm::{TraceFrame, Value, Vm, VmConfig};
+use std::io::{self, BufRead, Write};
+use std::time::Instant;
+
+const DEBUG_COMMANDS: &str = ":ir NAME, :disasm, :globals, :bt, :time STATEMENT";
+
+/// Reads statements from stdin until EOF or `:quit`, compiling and running
+/// each complete chunk (a `;`-terminated statement, or a whole
+/// `core::fn::begin`..`core::fn::end` block) in one session, then prints the
+/// slots the chunk wrote.
+pub fn repl(debug: bool) -> Result<(), Box<dyn std::error::Error>> {
+    let mut session = Session::new();
+    let mut input = io::stdin().lock();
+    let mut pending = String::new();
+    loop {
+        print!("{}", if pending.is_empty() { "imp> " } else { "...> " });
+        io::stdout().flush()?;
+        let mut line = String::new();
+        if input.read_line(&mut line)? == 0 {
+            println!();
+            return Ok(());
+        }
+        if pending.is_empty() {
+            match line.trim() {
+                "" => continue,
+                ":quit" | ":q" => return Ok(()),
+                command if command.starts_with(':') => {
+                    if debug {
+                        session.command(command);
+                    } else {
+                        eprintln!(
+                            "error: unknown command '{command}' (debug commands need --debug)"
+                        );
+                    }
+                    continue;
+                }
+                _ => {}
+            }
+        }
+        pending.push_str(&line);
+        if !is_complete_chunk(&pending) {
+            continue;
+        }
+
+        let src = std::mem::take(&mut pending);
+        session.eval(&src);
+    }
+}
+
+fn is_complete_chunk(src: &str) -> bool {
+    src.trim_end().ends_with(';')
+        && src.matches("core::fn::begin").count() <= src.matches("core::fn::end").count()
+}
+
+struct Session {
+    compiler: IncrementalCompiler,
+    vm: Vm,
+    /// Everything compiled so far, as of the last chunk that compiled.
+    module: Option<CompiledModule>,
+    /// Call stack the last runtime error escaped, innermost first.
+    last_trace: Vec<TraceFrame>,
+}
+
+impl Session {
+    fn new() -> Self {
+        Self {
+            compiler: IncrementalCompiler::new(CompileOpts::default()),
+            vm: Vm::new(with_env_flags(VmConfig::default())),
+            module: None,
+            last_trace: Vec::new(),
+        }
+    }
+
+    /// Compiles and runs one chunk, printing what it wrote or why it failed.
+    fn eval(&mut self, src: &str) {
+        let chunk = match self.compiler.compile_chunk(src, &FsModuleLoader) {
+            Ok(chunk) => chunk,
+            Err(err) => {
+                eprintln!("error: {}", with_snippet(&err, src));
+                return;
+            }
+        };
+        for warning in &chunk.warnings {
+            eprintln!("warning: {warning}");
+        }
+        let outcome = self.vm.run_incremental(&chunk.module);
+        self.module = Some(chunk.module);
+        match outcome {
+            Ok(result) => {
+                for (name, slot) in &chunk.bindings {
+                    let value = self.vm.session_globals().get(*slot as usize);
+                    println!("{name} = {}", value.unwrap_or(&Value::Null));
+                }
+                if !result.returns.is_empty() {
+                    println!("returns: {}", render_returns(&result.returns));
+                }
+            }
+            Err(err) => {
+                self.last_trace = err.traceback().to_vec();
+                eprintln!("error: {}", with_traceback(&err));
+            }
+        }
+    }
+
+    /// Runs one `--debug` inspection command.
+    fn command(&mut self, line: &str) {
+        let (name, arg) = line
+            .split_once(char::is_whitespace)
+            .map_or((line, ""), |(name, arg)| (name, arg.trim()));
+        match (name, arg) {
+            (":ir", "") => eprintln!("error: :ir expects a function name"),
+            (":ir", function) => self.show_function(function),
+            (":disasm", "") => {
+                for function in self.module.iter().flat_map(|module| &module.functions) {
+                    dump_function(function);
+                }
+            }
+            (":globals", "") => self.show_globals(),
+            (":bt", "") => {
+                if self.last_trace.is_empty() {
+                    println!("no error traceback");
+                }
+                for line in traceback_lines(&self.last_trace) {
+                    println!("  {line}");
+                }
+            }
+            (":time", "") => eprintln!("error: :time expects a statement"),
+            (":time", src) => {
+                let started = Instant::now();
+                self.eval(src);
+                let elapsed = started.elapsed();
+                println!("time: {:.3} ms", elapsed.as_secs_f64() * 1000.0);
+            }
+            _ => eprintln!("error: unknown command '{line}', expected {DEBUG_COMMANDS}"),
+        }
+    }
+
+    /// Dumps the function `find_function` finds for `name`.
+    fn show_function(&self, name: &str) {
+        match self.find_function(name) {
+            Some(function) => dump_function(function),
+            None => eprintln!("error: no function named '{name}'"),
+        }
+    }
+
+    /// The function named `name`, or `ns::name` for a bare name.
+    fn find_function(&self, name: &str) -> Option<&CompiledFunction> {
+        let mut functions = self.module.iter().flat_map(|module| &module.functions);
+        functions.find(|function| {
+            let full = function.meta.name.as_ref();
+            full == name
+                || full
+                    .rsplit_once("::")
+                    .is_some_and(|(_, short)| short == name)
+        })
+    }
+
+    fn show_globals(&self) {
+        for (name, value) in self.globals() {
+            println!("{name} = {value}");
+        }
+    }
+
+    /// Every named session global that is not a function binding, in slot
+    /// order.
+    fn globals(&self) -> Vec<(&str, &Value)> {
+        let Some(module) = &self.module else {
+            return Vec::new();
+        };
+        let globals = self.vm.session_globals();
+        module
+            .global_names
+            .iter()
+            .enumerate()
+            .filter(|(slot, name)| {
+                !name.is_empty()
+                    && !module
+                        .function_globals
+                        .iter()
+                        .any(|(bound, _)| *bound as usize == *slot)
+            })
+            .map(|(slot, name)| (name.as_str(), globals.get(slot).unwrap_or(&Value::Null)))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn debug_session_exposes_functions_globals_and_tracebacks() {
+        let mut session = Session::new();
+        session.eval("#call core::const value=3 out=local::x;\n");
+        session.eval(
+            "#call core::fn::begin name=main::double args=\"a\" retshape=\"scalar\";\n\
+             #call core::add a=arg::a b=arg::a out=return::value;\n\
+             #call core::exit;\n\
+             #call core::fn::end;\n",
+        );
+        let globals = session.globals();
+        assert_eq!(globals.len(), 1);
+        assert_eq!(globals[0].0, "local::x");
+        assert_eq!(globals[0].1.to_string(), "3");
+
+        let function = session.find_function("double").expect("short name");
+        assert_eq!(function.meta.name.as_ref(), "main::double");
+        assert!(session.find_function("main::double").is_some());
+        assert!(session.find_function("missing").is_none());
+
+        assert!(session.last_trace.is_empty());
+        session.eval("#call core::throw code=\"boom\" msg=\"no\";\n");
+        assert_eq!(session.last_trace.len(), 1);
+        assert_eq!(
+            traceback_lines(&session.last_trace),
+            ["at <init> (line 1, pc 0)"]
+        );
+    }
+}
@@ -0,0 +1,207 @@
+use crate::{
+    CompileCx, CompileError, CompileOpts, CompileWarning, ModuleBuilder, ModuleLoader, SlotEnv,
+    check_function_limit, check_import_aliases, collect_consts, collect_exports, compile_function,
+    compile_imports, compile_raw_function, expand_macros, filter_meta_calls, fold_const_calls,
+    get_string_arg, split_functions,
+};
+use imp_ast::{Atom, Call, parse_program};
+use imp_ir::{CompiledFunction, CompiledModule, ConstValue, FuncId, ImportBinding, RetShape};
+use std::collections::HashMap;
+use std::sync::Arc;
+
+/// Compiles source a chunk at a time into one growing module, as `imp repl`
+/// does.
+///
+/// Global slots, functions, imports, exports and constants carry over from
+/// earlier chunks. Each chunk's top-level statements become the module's
+/// init, with `local::` names stored as `local::NAME` globals so later chunks
+/// can read them; run each chunk with `Vm::run_incremental` to keep those
+/// globals alive between chunks.
+pub struct IncrementalCompiler {
+    opts: CompileOpts,
+    state: Session,
+    /// Imports compiled so far, shared by every chunk.
+    cx: CompileCx,
+}
+
+/// Everything a chunk may add to; cloned per chunk so a failed chunk leaves
+/// it untouched.
+#[derive(Debug, Clone, Default)]
+struct Session {
+    globals: HashMap<String, u32>,
+    next_global: u32,
+    fn_params: HashMap<String, Vec<String>>,
+    folded_consts: HashMap<String, ConstValue>,
+    /// Declared functions; function `id` is at index `id - 1`.
+    functions: Vec<CompiledFunction>,
+    function_globals: Vec<(u32, FuncId)>,
+    exports: Vec<(String, u32)>,
+    imports: Vec<ImportBinding>,
+    consts: Vec<(String, ConstValue)>,
+    tests: Vec<FuncId>,
+}
+
+/// One compiled chunk of an [`IncrementalCompiler`] session.
+#[derive(Debug, Clone)]
+pub struct CompiledChunk {
+    /// Everything compiled so far; the init runs this chunk's statements.
+    pub module: CompiledModule,
+    /// `namespace::name` and global slot of each ref the chunk's statements
+    /// write (`out=` / `to=`), in first-write order.
+    pub bindings: Vec<(String, u32)>,
+    pub warnings: Vec<CompileWarning>,
+}
+
+impl IncrementalCompiler {
+    #[must_use]
+    pub fn new(opts: CompileOpts) -> Self {
+        Self {
+            opts,
+            state: Session::default(),
+            cx: CompileCx::default(),
+        }
+    }
+
+    /// Compiles `src` on top of the earlier chunks. Redeclaring a function
+    /// rebinds its name to the new body; a failed chunk leaves the session
+    /// unchanged.
+    ///
+    /// # Errors
+    ///
+    /// The chunk's first compile error, with the session left as it was.
+    pub fn compile_chunk(
+        &mut self,
+        src: &str,
+        loader: &dyn ModuleLoader,
+    ) -> Result<CompiledChunk, CompileError> {
+        let program = parse_program(src)?;
+        // A failed chunk may have left its import chain behind.
+        self.cx.visiting.clear();
+        self.cx.warnings.clear();
+        let expanded = fold_const_calls(expand_macros(
+            &program.calls,
+            &self.opts.module_name,
+            &mut self.cx.warnings,
+        )?);
+        let (top_level, functions) = split_functions(&expanded)?;
+
+        let mut state = self.state.clone();
+        check_function_limit(&functions, state.functions.len(), self.opts.limits)?;
+        let mut builder = ModuleBuilder::new(self.opts.module_name.clone(), &self.opts);
+        builder.globals = std::mem::take(&mut state.globals);
+        builder.next_global = state.next_global;
+        builder.fn_params = std::mem::take(&mut state.fn_params);
+        builder.consts = std::mem::take(&mut state.folded_consts);
+
+        for (name, value) in collect_consts(&top_level)? {
+            if state.consts.iter().any(|(existing, _)| *existing == name) {
+                let line = top_level
+                    .iter()
+                    .find(|call| {
+                        call.target == "core::mod::const"
+                            && get_string_arg(call, "name").is_ok_and(|declared| declared == name)
+                    })
+                    .map_or(1, |call| call.line);
+                return Err(CompileError::new(
+                    line,
+                    format!("duplicate module constant '{name}'"),
+                ));
+            }
+            builder.consts.insert(format!("mod::{name}"), value.clone());
+            state.consts.push((name, value));
+        }
+
+        let first_id = FuncId::try_from(state.functions.len() + 1)
+            .map_err(|_| CompileError::new(1, "too many functions in this session"))?;
+        for (func_id, function_ast) in (first_id..).zip(&functions) {
+            let name = &function_ast.name;
+            let slot = builder.resolve_global(&name.namespace, &name.name);
+            state
+                .function_globals
+                .retain(|(existing, _)| *existing != slot);
+            state.function_globals.push((slot, func_id));
+            if function_ast.test {
+                state.tests.push(func_id);
+            }
+            let qualified = format!("{}::{}", name.namespace, name.name);
+            builder
+                .fn_params
+                .insert(qualified, function_ast.args.clone());
+        }
+
+        // Redeclared functions no longer run as tests under their old body.
+        let bound = &state.function_globals;
+        state
+            .tests
+            .retain(|test| bound.iter().any(|(_, func_id)| func_id == test));
+
+        check_import_aliases(&top_level, &functions, &state.imports)?;
+        let imports = compile_imports(&top_level, None, loader, &mut self.cx, &mut builder)?;
+        for (func_id, function_ast) in (first_id..).zip(&functions) {
+            state
+                .functions
+                .push(compile_function(function_ast, func_id, &mut builder));
+        }
+        builder.first_error()?;
+        for (name, slot) in collect_exports(&top_level, &mut builder)? {
+            state.exports.retain(|(existing, _)| *existing != name);
+            state.exports.push((name, slot));
+        }
+        state.imports.extend(imports);
+
+        let body = filter_meta_calls(&top_level);
+        let mut env = SlotEnv::new(Vec::new(), 0);
+        env.locals_as_globals = true;
+        let init = compile_raw_function(&body, 0, "<init>", env, RetShape::Any, 0, &mut builder, 1);
+        builder.first_error()?;
+        let bindings = written_globals(&body, &builder.globals);
+        let global_names = builder.global_names();
+
+        state.globals = builder.globals;
+        state.next_global = builder.next_global;
+        state.fn_params = builder.fn_params;
+        state.folded_consts = builder.consts;
+
+        let module = CompiledModule {
+            name: Arc::from(self.opts.module_name.as_str()),
+            init_func: 0,
+            functions: std::iter::once(init)
+                .chain(state.functions.iter().cloned())
+                .collect(),
+            function_globals: state.function_globals.clone(),
+            exports: state.exports.clone(),
+            imports: state.imports.clone(),
+            consts: state.consts.clone(),
+            global_count: state.next_global,
+            tests: state.tests.clone(),
+            global_names,
+        };
+        self.state = state;
+        Ok(CompiledChunk {
+            module,
+            bindings,
+            warnings: std::mem::take(&mut self.cx.warnings),
+        })
+    }
+}
+
+fn written_globals(calls: &[Call], globals: &HashMap<String, u32>) -> Vec<(String, u32)> {
+    let mut written: Vec<(String, u32)> = Vec::new();
+    for call in calls {
+        for arg in &call.args {
+            let Atom::Ref(path) = &arg.value else {
+                continue;
+            };
+            if arg.key != "out" && arg.key != "to" {
+                continue;
+            }
+            let name = format!("{}::{}", path.namespace, path.name);
+            if let Some(slot) = globals.get(&name)
+                && !written.iter().any(|(existing, _)| *existing == name)
+            {
+                written.push((name, *slot));
+            }
+        }
+    }
+    written
+}
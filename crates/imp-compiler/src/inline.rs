@@ -0,0 +1,351 @@
+//! `@inline`: splices the bodies of same-module functions into their callers
+//! in place of `Invoke`.
+
+use crate::{CompileOpts, optimize};
+use imp_ir::{CompiledFunction, ConstValue, DebugInfo, FuncId, Instr, RetShape, Slot};
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+
+/// Replaces each `Invoke`/`InvokeMulti`/`TailInvoke` that names a function in `marked`
+/// by its global slot with a copy of that function's body. A function whose
+/// global slot any instruction might write (`core::mov to=main::f`) is never
+/// inlined, since the slot may no longer hold it when the call runs.
+///
+/// The callee's locals, args, error and return slots become fresh locals of
+/// the caller, reset to null at every call site so each call starts from a
+/// blank frame. Calls stay `Invoke` when the callee is recursive (it can
+/// reach itself through calls by name), pushes try handlers, has a timeout
+/// or a retshape the VM would have to check, or when splicing would break
+/// `CompileOpts::limits`.
+pub(crate) fn inline_calls(
+    functions: &mut [CompiledFunction],
+    function_globals: &[(u32, FuncId)],
+    marked: &HashSet<FuncId>,
+    opts: &CompileOpts,
+) {
+    let index_of: HashMap<FuncId, usize> = functions
+        .iter()
+        .enumerate()
+        .map(|(index, function)| (function.id, index))
+        .collect();
+    let written = written_globals(functions);
+    let targets: HashMap<u32, usize> = function_globals
+        .iter()
+        .filter(|(global, _)| !written.contains(global))
+        .filter_map(|(global, func_id)| Some((*global, *index_of.get(func_id)?)))
+        .collect();
+    let callees: Vec<HashSet<usize>> = functions
+        .iter()
+        .map(|function| {
+            function
+                .code
+                .iter()
+                .filter_map(|instr| call_site(instr, &targets).map(|(callee, _, _)| callee))
+                .collect()
+        })
+        .collect();
+
+    let eligible: HashSet<usize> = marked
+        .iter()
+        .filter_map(|func_id| index_of.get(func_id).copied())
+        .filter(|index| splicable(&functions[*index]) && !reaches(&callees, *index, *index))
+        .collect();
+    if eligible.is_empty() {
+        return;
+    }
+
+    // Eligible functions call no chain leading back to themselves, so a
+    // post-order walk splices every callee before its callers copy it.
+    let mut done = HashSet::new();
+    for root in 0..functions.len() {
+        let mut stack = vec![(root, false)];
+        while let Some((index, expanded)) = stack.pop() {
+            if expanded {
+                if let Some(spliced) =
+                    splice(&functions[index], functions, &targets, &eligible, opts)
+                {
+                    functions[index] = spliced;
+                }
+                continue;
+            }
+            if !done.insert(index) {
+                continue;
+            }
+            stack.push((index, true));
+            for callee in &callees[index] {
+                if eligible.contains(callee) && !done.contains(callee) {
+                    stack.push((*callee, false));
+                }
+            }
+        }
+    }
+}
+
+/// The callee index, args and out slots of a call by global slot to one of
+/// the module's own functions.
+fn call_site<'i>(
+    instr: &'i Instr,
+    targets: &HashMap<u32, usize>,
+) -> Option<(usize, &'i [Slot], &'i [Slot])> {
+    let (fn_slot, args, outs) = match instr {
+        Instr::Invoke { fn_slot, args, out } => (fn_slot, args, std::slice::from_ref(out)),
+        Instr::InvokeMulti {
+            fn_slot,
+            args,
+            outs,
+        } => (fn_slot, args, outs.as_slice()),
+        Instr::TailInvoke { fn_slot, args } => (fn_slot, args, &[Slot::Ret(0)][..]),
+        _ => return None,
+    };
+    let Slot::Global(global) = fn_slot else {
+        return None;
+    };
+    Some((*targets.get(global)?, args, outs))
+}
+
+/// Global slots some instruction in `functions` might write: every global
+/// an instruction names, except the callee and args of a call and the
+/// source of a move, which are only read.
+fn written_globals(functions: &[CompiledFunction]) -> HashSet<u32> {
+    let mut written = HashSet::new();
+    for instr in functions.iter().flat_map(|function| function.code.iter()) {
+        let slots = match instr {
+            Instr::Invoke { out, .. } => vec![*out],
+            Instr::InvokeMulti { outs, .. } => outs.clone(),
+            Instr::TailInvoke { .. } => Vec::new(),
+            Instr::Move { to, .. } => vec![*to],
+            other => other.slots(),
+        };
+        written.extend(slots.into_iter().filter_map(|slot| match slot {
+            Slot::Global(global) => Some(global),
+            _ => None,
+        }));
+    }
+    written
+}
+
+/// Whether any chain of calls by name leads from `from` to `to`.
+fn reaches(callees: &[HashSet<usize>], from: usize, to: usize) -> bool {
+    let mut seen = HashSet::new();
+    let mut pending: Vec<usize> = callees[from].iter().copied().collect();
+    while let Some(index) = pending.pop() {
+        if index == to {
+            return true;
+        }
+        if seen.insert(index) {
+            pending.extend(&callees[index]);
+        }
+    }
+    false
+}
+
+/// Whether `function` behaves the same spliced into a caller's frame: no
+/// handlers that `core::exit` could leave on the caller's try stack, no
+/// deadline of its own, no return check beyond the slot count, and no slot
+/// outside its frame layout.
+fn splicable(function: &CompiledFunction) -> bool {
+    if function.meta.timeout_ms.is_some()
+        || function
+            .code
+            .iter()
+            .any(|instr| matches!(instr, Instr::TryPush { .. }))
+    {
+        return false;
+    }
+    let rets = ret_len(function);
+    let shape_holds = match function.meta.retshape {
+        RetShape::Any => true,
+        RetShape::Scalar => rets == 1,
+        RetShape::Either(_) | RetShape::Record(_) => false,
+    };
+    shape_holds
+        && function.code.iter().all(|instr| {
+            instr.slots().into_iter().all(|slot| match slot {
+                Slot::Local(index) => index < function.local_count,
+                Slot::Arg(index) => index < function.arg_count,
+                Slot::Err(index) => index < function.err_count.max(1),
+                Slot::Ret(_) | Slot::Global(_) => true,
+            })
+        })
+}
+
+/// How many return values `function` produces: `ret_count`, grown by any
+/// store past it.
+fn ret_len(function: &CompiledFunction) -> u32 {
+    function
+        .code
+        .iter()
+        .flat_map(|instr| match instr {
+            Instr::ReturnSet { slot_id, .. } => vec![*slot_id],
+            _ => instr
+                .slots()
+                .into_iter()
+                .filter_map(|slot| match slot {
+                    Slot::Ret(index) => Some(index),
+                    _ => None,
+                })
+                .collect(),
+        })
+        .map(|index| index + 1)
+        .fold(function.ret_count, u32::max)
+}
+
+/// `caller` with its calls to `eligible` functions spliced in, or `None`
+/// when it has none that fit.
+fn splice(
+    caller: &CompiledFunction,
+    functions: &[CompiledFunction],
+    targets: &HashMap<u32, usize>,
+    eligible: &HashSet<usize>,
+    opts: &CompileOpts,
+) -> Option<CompiledFunction> {
+    let old_lines = caller.meta.debug.as_ref().map(|debug| &debug.lines[..]);
+    let mut code = Vec::with_capacity(caller.code.len());
+    let mut lines = Vec::with_capacity(caller.code.len());
+    // `remap[pc]` is the new pc of the caller's instruction at `pc`.
+    let mut remap = Vec::with_capacity(caller.code.len() + 1);
+    let mut kept = Vec::new();
+    let mut local_count = caller.local_count;
+    for (pc, instr) in caller.code.iter().enumerate() {
+        remap.push(code.len());
+        let line = old_lines.and_then(|lines| lines.get(pc)).copied();
+        let site = call_site(instr, targets).filter(|(callee, _, _)| eligible.contains(callee));
+        if let Some((callee, args, outs)) = site {
+            let body = &functions[callee];
+            let frame = frame_len(body);
+            let fits_locals = opts
+                .limits
+                .max_locals
+                .is_none_or(|max| local_count + frame <= max);
+            let fits_code = opts.limits.max_instructions.is_none_or(|max| {
+                code.len() + frame as usize + body.code.len() + outs.len() + caller.code.len()
+                    - pc
+                    - 1
+                    <= max
+            });
+            if fits_locals && fits_code {
+                splice_body(body, args, outs, local_count, &mut code);
+                local_count += frame;
+                lines.resize(code.len(), line.unwrap_or(0));
+                continue;
+            }
+        }
+        kept.push(code.len());
+        code.push(instr.clone());
+        lines.push(line.unwrap_or(0));
+    }
+    if kept.len() == caller.code.len() {
+        return None;
+    }
+    remap.push(code.len());
+    for pc in kept {
+        retarget(&mut code[pc], |target| remap[target]);
+    }
+
+    if opts.opt_level > 0 {
+        optimize::remove_unreachable(&mut code, &mut lines);
+        optimize::fold_constants(&mut code);
+    }
+    let mut function = caller.clone();
+    function.code = code.into();
+    function.local_count = local_count;
+    function.meta.debug = caller.meta.debug.as_ref().map(|debug| {
+        Arc::new(DebugInfo {
+            source: debug.source.clone(),
+            lines: lines.into(),
+        })
+    });
+    Some(function)
+}
+
+/// Caller locals one spliced call of `callee` takes: its whole frame.
+fn frame_len(callee: &CompiledFunction) -> u32 {
+    callee.local_count + callee.arg_count + callee.err_count.max(1) + ret_len(callee)
+}
+
+/// Appends `callee`'s body, with its frame laid out from local `base`, after
+/// moves that fill its args, and ends with moves of its return values into
+/// `outs` (null past the last one, as the VM fills them).
+fn splice_body(
+    callee: &CompiledFunction,
+    args: &[Slot],
+    outs: &[Slot],
+    base: u32,
+    code: &mut Vec<Instr>,
+) {
+    let arg_base = base + callee.local_count;
+    let err_base = arg_base + callee.arg_count;
+    let ret_base = err_base + callee.err_count.max(1);
+    let rets = ret_len(callee);
+    let rename = |slot: Slot| match slot {
+        Slot::Local(index) => Slot::Local(base + index),
+        Slot::Arg(index) => Slot::Local(arg_base + index),
+        Slot::Err(index) => Slot::Local(err_base + index),
+        Slot::Ret(index) => Slot::Local(ret_base + index),
+        Slot::Global(index) => Slot::Global(index),
+    };
+    let null = |slot| Instr::StoreConst {
+        slot,
+        value: ConstValue::Null,
+    };
+
+    code.extend((base..arg_base).map(|index| null(Slot::Local(index))));
+    for index in 0..callee.arg_count {
+        let to = Slot::Local(arg_base + index);
+        code.push(match args.get(index as usize) {
+            Some(from) => Instr::Move { from: *from, to },
+            None => null(to),
+        });
+    }
+    code.extend((err_base..ret_base + rets).map(|index| null(Slot::Local(index))));
+
+    let start = code.len();
+    for instr in callee.code.iter() {
+        let mut instr = match instr {
+            Instr::Exit => Instr::Jump {
+                target: callee.code.len(),
+            },
+            Instr::ReturnSet { slot_id, value } => Instr::Move {
+                from: *value,
+                to: Slot::Ret(*slot_id),
+            },
+            // The caller's frame outlives the spliced body.
+            Instr::TailInvoke { fn_slot, args } => Instr::Invoke {
+                fn_slot: *fn_slot,
+                args: args.clone(),
+                out: Slot::Ret(0),
+            },
+            instr => instr.clone(),
+        };
+        retarget(&mut instr, |target| start + target);
+        for slot in instr.slots_mut() {
+            *slot = rename(*slot);
+        }
+        code.push(instr);
+    }
+
+    for (index, out) in (0..).zip(outs) {
+        code.push(if index < rets {
+            Instr::Move {
+                from: Slot::Local(ret_base + index),
+                to: *out,
+            }
+        } else {
+            null(*out)
+        });
+    }
+}
+
+fn retarget(instr: &mut Instr, map: impl Fn(usize) -> usize) {
+    match instr {
+        Instr::Jump { target } => *target = map(*target),
+        Instr::Branch {
+            then_pc, else_pc, ..
+        } => {
+            *then_pc = map(*then_pc);
+            *else_pc = map(*else_pc);
+        }
+        Instr::TryPush { handler_pc } => *handler_pc = map(*handler_pc),
+        _ => {}
+    }
+}
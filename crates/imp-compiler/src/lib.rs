@@ -1,32 +1,85 @@
-use imp_ast::{Atom, Call, Program, RefPath, parse_program};
+use imp_ast::{
+    Atom, Call, ParseError, Program, RefPath, Span, parse_program, parse_program_recovering,
+};
 use imp_ir::{
-    CompiledFunction, CompiledModule, ConstValue, FnMeta, FuncId, ImportBinding, Instr, RetShape,
-    Slot,
+    CompiledFunction, CompiledModule, ConstValue, DebugInfo, FnMeta, FuncId, HostClock,
+    ImportBinding, Instr, PrintLevel, PrintStream, RetShape, Slot, ValueKind,
+};
+use imp_std::{
+    ANNO_INLINE, ANNO_SAFE, ANNO_TEST, ANNO_TIMEOUT, RESERVED_NAMESPACES, is_core_target,
+    parse_csv, sha256_hex,
 };
-use imp_std::{ANNO_SAFE, is_core_target, parse_csv};
+use std::borrow::Cow;
 use std::collections::{HashMap, HashSet};
 use std::fmt;
 use std::fs;
 use std::path::{Path, PathBuf};
 use std::sync::Arc;
 
-#[derive(Debug, Clone)]
+mod incremental;
+mod inline;
+mod optimize;
+mod plugin;
+mod prune;
+
+pub use incremental::{CompiledChunk, IncrementalCompiler};
+pub use plugin::{LoweringCx, TargetLowering};
+pub use prune::prune_exports;
+
 pub struct CompileOpts {
     pub module_name: String,
+    /// Lowering for embedder call targets; also applied to imported modules.
+    pub target_plugins: Vec<Box<dyn TargetLowering>>,
+    /// `0` emits code as written; `1` also folds instructions whose operands
+    /// are known constants into `StoreConst`. Applies to imported modules.
+    pub opt_level: u8,
+    /// Size limits checked on every compiled module, imports included.
+    pub limits: CompileLimits,
+}
+
+/// Upper bounds on compiled output, for code generators that want errors at
+/// compile time rather than oversized modules; `None` leaves a size unbounded.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CompileLimits {
+    /// Instructions per function, counted after optimization.
+    pub max_instructions: Option<usize>,
+    /// Local slots per function, temporaries included.
+    pub max_locals: Option<u32>,
+    /// Declared functions per module, not counting the init function.
+    pub max_functions: Option<usize>,
 }
 
 impl Default for CompileOpts {
     fn default() -> Self {
         Self {
             module_name: "main".to_owned(),
+            target_plugins: Vec::new(),
+            opt_level: 0,
+            limits: CompileLimits::default(),
         }
     }
 }
 
+impl fmt::Debug for CompileOpts {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("CompileOpts")
+            .field("module_name", &self.module_name)
+            .field("target_plugins", &self.target_plugins.len())
+            .field("opt_level", &self.opt_level)
+            .field("limits", &self.limits)
+            .finish()
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct CompileError {
     pub line: usize,
     pub message: String,
+    /// Source text the error is about: the offending argument when there is
+    /// one, else the whole call. Spans index the failing module's source.
+    pub span: Option<Span>,
+    /// Import chain leading to the failing module, innermost first.
+    pub notes: Vec<String>,
 }
 
 impl CompileError {
@@ -34,20 +87,79 @@ impl CompileError {
         Self {
             line,
             message: message.into(),
+            span: None,
+            notes: Vec::new(),
         }
     }
+
+    /// Points the error at `span` unless it already points somewhere
+    /// narrower.
+    fn or_span(mut self, span: Span) -> Self {
+        self.span.get_or_insert(span);
+        self
+    }
+
+    fn with_note(mut self, note: String) -> Self {
+        self.notes.push(note);
+        self
+    }
 }
 
 impl fmt::Display for CompileError {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "line {}: {}", self.line, self.message)
+        write!(f, "line {}: {}", self.line, self.message)?;
+        for note in &self.notes {
+            write!(f, "\n  {note}")?;
+        }
+        Ok(())
+    }
+}
+
+/// Something that compiled but likely does not do what its author meant.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CompileWarning {
+    /// Name of the module the warning is in (imports use their file stem).
+    pub module: String,
+    pub line: usize,
+    pub message: String,
+}
+
+impl fmt::Display for CompileWarning {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} line {}: {}", self.module, self.line, self.message)
     }
 }
 
+/// State shared by every module compiled for one root: finished imports,
+/// the import chain being compiled, and warnings so far.
+#[derive(Debug, Default)]
+struct CompileCx {
+    cache: HashMap<PathBuf, CompiledModule>,
+    visiting: HashSet<PathBuf>,
+    warnings: Vec<CompileWarning>,
+}
+
 impl std::error::Error for CompileError {}
 
+impl From<ParseError> for CompileError {
+    fn from(err: ParseError) -> Self {
+        Self::new(err.line, err.message).or_span(err.span)
+    }
+}
+
 pub trait ModuleLoader {
+    /// The source text of the module at `path`.
+    ///
+    /// # Errors
+    ///
+    /// When the module cannot be read.
     fn load(&self, path: &Path) -> Result<String, CompileError>;
+    /// The path `path` is known by, so one module imported two ways is
+    /// compiled once.
+    ///
+    /// # Errors
+    ///
+    /// When the path cannot be resolved.
     fn normalize(&self, path: &Path) -> Result<PathBuf, CompileError>;
 }
 
@@ -73,72 +185,260 @@ impl ModuleLoader for FsModuleLoader {
 #[derive(Debug, Clone)]
 pub struct CompiledProgram {
     pub module: CompiledModule,
+    pub warnings: Vec<CompileWarning>,
 }
 
-pub fn compile_program(src: &str, opts: CompileOpts) -> Result<CompiledProgram, CompileError> {
-    let program = parse_program(src).map_err(|err| CompileError::new(err.line, err.message))?;
-    let mut cache = HashMap::new();
-    let mut visiting = HashSet::new();
+/// Compiles `src` as a module with no path; imports are not available.
+///
+/// # Errors
+///
+/// The first parse or compile error.
+pub fn compile_program(src: &str, mut opts: CompileOpts) -> Result<CompiledProgram, CompileError> {
+    let program = parse_program(src)?;
+    let mut cx = CompileCx::default();
     let module = compile_source_internal(
         &program,
-        opts.module_name,
+        std::mem::take(&mut opts.module_name),
         None,
         &NoopLoader,
-        &mut cache,
-        &mut visiting,
-    )?;
-    Ok(CompiledProgram { module })
+        &opts,
+        &mut cx,
+    )
+    .and_then(stop_at_first_error)?;
+    Ok(CompiledProgram {
+        module,
+        warnings: cx.warnings,
+    })
+}
+
+/// Every error and warning one compile found.
+#[derive(Debug, Clone, Default)]
+pub struct Diagnostics {
+    /// Sorted by line; parse errors come before others on the same line.
+    pub errors: Vec<CompileError>,
+    /// Warnings from the root module and every import, in compile order.
+    pub warnings: Vec<CompileWarning>,
+}
+
+impl Diagnostics {
+    #[must_use]
+    pub fn has_errors(&self) -> bool {
+        !self.errors.is_empty()
+    }
+}
+
+/// What [`compile_program_diagnostics`] and [`compile_module_diagnostics`]
+/// could build, and everything wrong with it.
+#[derive(Debug, Clone)]
+pub struct CompileReport {
+    /// `None` when the errors leave no module to build: a statement failed
+    /// to parse, or a declaration, import or constant is invalid. Otherwise
+    /// each function whose body failed to compile, and the init if the
+    /// top-level statements did, throws `compile_error` when called, so the
+    /// rest of the module still runs.
+    pub module: Option<CompiledModule>,
+    pub diagnostics: Diagnostics,
+}
+
+/// Like [`compile_program`], but carries on past errors in statements and
+/// function bodies and reports them all instead of stopping at the first.
+#[must_use]
+pub fn compile_program_diagnostics(src: &str, opts: &CompileOpts) -> CompileReport {
+    let mut cx = CompileCx::default();
+    let module_name = opts.module_name.clone();
+    diagnose(src, module_name, None, &NoopLoader, opts, &mut cx)
+}
+
+/// Fuzzing entry point: compiles `data`, when it is UTF-8, failing fast and
+/// then with diagnostics and constant folding. Imports are unavailable. Any
+/// panic is a bug.
+pub fn fuzz_compile(data: &[u8]) {
+    let Ok(src) = std::str::from_utf8(data) else {
+        return;
+    };
+    let _ = compile_program(src, CompileOpts::default());
+    let _ = compile_program_diagnostics(
+        src,
+        &CompileOpts {
+            opt_level: 1,
+            ..CompileOpts::default()
+        },
+    );
+}
+
+/// Like [`compile_module_with_opts`], but carries on past errors in the root
+/// module's statements and function bodies and reports them all. An import
+/// with errors is reported as one error at its `core::import`.
+pub fn compile_module_diagnostics(
+    path: &Path,
+    loader: &dyn ModuleLoader,
+    opts: &CompileOpts,
+) -> CompileReport {
+    let mut cx = CompileCx::default();
+    let source = loader
+        .normalize(path)
+        .and_then(|canonical| Ok((loader.load(&canonical)?, canonical)));
+    match source {
+        Ok((src, canonical)) => {
+            cx.visiting.insert(canonical.clone());
+            let module_name = module_name_of(&canonical);
+            diagnose(&src, module_name, Some(&canonical), loader, opts, &mut cx)
+        }
+        Err(err) => CompileReport {
+            module: None,
+            diagnostics: Diagnostics {
+                errors: vec![err],
+                warnings: Vec::new(),
+            },
+        },
+    }
+}
+
+fn diagnose(
+    src: &str,
+    module_name: String,
+    module_path: Option<&Path>,
+    loader: &dyn ModuleLoader,
+    opts: &CompileOpts,
+    cx: &mut CompileCx,
+) -> CompileReport {
+    let (program, parse_errors) = parse_program_recovering(src);
+    let parsed = parse_errors.is_empty();
+    let mut errors = parse_errors
+        .into_iter()
+        .map(CompileError::from)
+        .collect::<Vec<_>>();
+    let module = match compile_source_internal(&program, module_name, module_path, loader, opts, cx)
+    {
+        Ok((module, compile_errors)) => {
+            errors.extend(compile_errors);
+            parsed.then_some(module)
+        }
+        Err(err) => {
+            errors.push(err);
+            None
+        }
+    };
+    errors.sort_by_key(|err| err.line);
+    CompileReport {
+        module,
+        diagnostics: Diagnostics {
+            errors,
+            warnings: std::mem::take(&mut cx.warnings),
+        },
+    }
+}
+
+/// The module, unless compiling it recorded errors; then the first of them.
+fn stop_at_first_error(
+    (module, errors): (CompiledModule, Vec<CompileError>),
+) -> Result<CompiledModule, CompileError> {
+    errors.into_iter().next().map_or(Ok(module), Err)
 }
 
+/// Compiles the module at `path` and its imports through `loader`.
+///
+/// # Errors
+///
+/// The first load, parse or compile error, in this module or an import.
 pub fn compile_module(
     path: &Path,
     loader: &dyn ModuleLoader,
 ) -> Result<CompiledModule, CompileError> {
-    let mut cache = HashMap::new();
-    let mut visiting = HashSet::new();
-    compile_module_internal(path, loader, &mut cache, &mut visiting)
+    let mut cx = CompileCx::default();
+    compile_module_internal(path, loader, &CompileOpts::default(), &mut cx)
+}
+
+/// A compiled module plus every source that contributed to it.
+#[derive(Debug, Clone)]
+pub struct CompileOutput {
+    pub module: CompiledModule,
+    /// Canonical (loader-normalized) paths of the root module and all
+    /// transitive imports, sorted and deduplicated.
+    pub dependencies: Vec<PathBuf>,
+    /// Warnings from the root module and every import, in compile order.
+    pub warnings: Vec<CompileWarning>,
+}
+
+/// Like [`compile_module`], but also reports the source files read, so watch
+/// and caching tools need not re-walk imports themselves.
+///
+/// # Errors
+///
+/// As [`compile_module`].
+pub fn compile_module_with_deps(
+    path: &Path,
+    loader: &dyn ModuleLoader,
+) -> Result<CompileOutput, CompileError> {
+    compile_module_with_opts(path, loader, &CompileOpts::default())
+}
+
+/// Like [`compile_module_with_deps`], with plugins and optimization settings;
+/// `opts.module_name` is unused since file modules are named after the file.
+///
+/// # Errors
+///
+/// As [`compile_module`].
+pub fn compile_module_with_opts(
+    path: &Path,
+    loader: &dyn ModuleLoader,
+    opts: &CompileOpts,
+) -> Result<CompileOutput, CompileError> {
+    let mut cx = CompileCx::default();
+    let module = compile_module_internal(path, loader, opts, &mut cx)?;
+    let mut dependencies = cx.cache.into_keys().collect::<Vec<_>>();
+    dependencies.sort();
+    Ok(CompileOutput {
+        module,
+        dependencies,
+        warnings: cx.warnings,
+    })
 }
 
 fn compile_module_internal(
     path: &Path,
     loader: &dyn ModuleLoader,
-    cache: &mut HashMap<PathBuf, CompiledModule>,
-    visiting: &mut HashSet<PathBuf>,
+    opts: &CompileOpts,
+    cx: &mut CompileCx,
 ) -> Result<CompiledModule, CompileError> {
     let canonical = loader.normalize(path)?;
-    if let Some(module) = cache.get(&canonical) {
+    if let Some(module) = cx.cache.get(&canonical) {
         return Ok(module.clone());
     }
-    if visiting.contains(&canonical) {
+    if cx.visiting.contains(&canonical) {
         return Err(CompileError::new(
             1,
             format!("cyclic import detected at {}", canonical.display()),
         ));
     }
 
-    visiting.insert(canonical.clone());
+    cx.visiting.insert(canonical.clone());
     let src = loader.load(&canonical)?;
-    let program = parse_program(&src).map_err(|err| CompileError::new(err.line, err.message))?;
-    let module_name = canonical
-        .file_stem()
-        .and_then(|s| s.to_str())
-        .unwrap_or("module")
-        .to_owned();
-
+    let program = parse_program(&src)?;
     let module = compile_source_internal(
         &program,
-        module_name,
+        module_name_of(&canonical),
         Some(canonical.as_path()),
         loader,
-        cache,
-        visiting,
-    )?;
+        opts,
+        cx,
+    )
+    .and_then(stop_at_first_error)?;
 
-    visiting.remove(&canonical);
-    cache.insert(canonical, module.clone());
+    cx.visiting.remove(&canonical);
+    cx.cache.insert(canonical, module.clone());
     Ok(module)
 }
 
+/// File modules are named after their file stem.
+fn module_name_of(canonical: &Path) -> String {
+    canonical
+        .file_stem()
+        .and_then(|s| s.to_str())
+        .unwrap_or("module")
+        .to_owned()
+}
+
 struct NoopLoader;
 
 impl ModuleLoader for NoopLoader {
@@ -160,6 +460,13 @@ struct FunctionAst {
     args: Vec<String>,
     retshape: RetShape,
     ret_count: u32,
+    timeout_ms: Option<u64>,
+    /// Declared with `@test`.
+    test: bool,
+    /// Declared with `@inline`.
+    inline: bool,
+    /// Declared by `core::fn::lambda` rather than `core::fn::begin`.
+    lambda: bool,
     body: Vec<Call>,
     line: usize,
 }
@@ -169,63 +476,95 @@ fn compile_source_internal(
     module_name: String,
     module_path: Option<&Path>,
     loader: &dyn ModuleLoader,
-    cache: &mut HashMap<PathBuf, CompiledModule>,
-    visiting: &mut HashSet<PathBuf>,
-) -> Result<CompiledModule, CompileError> {
-    let expanded = expand_macros(&program.calls)?;
+    opts: &CompileOpts,
+    cx: &mut CompileCx,
+) -> Result<(CompiledModule, Vec<CompileError>), CompileError> {
+    let expanded = fold_const_calls(expand_macros(
+        &program.calls,
+        &module_name,
+        &mut cx.warnings,
+    )?);
     let (top_level, functions) = split_functions(&expanded)?;
 
-    let mut builder = ModuleBuilder::new(module_name);
+    let mut builder = ModuleBuilder::new(module_name, opts);
+    builder.source = module_path.map(|path| Arc::from(path.to_string_lossy().as_ref()));
+    let consts = collect_consts(&top_level)?;
+    for (name, value) in &consts {
+        builder.consts.insert(format!("mod::{name}"), value.clone());
+    }
 
+    check_function_limit(&functions, 0, opts.limits)?;
     let mut compiled_functions = Vec::new();
     let mut function_globals = Vec::new();
 
     // Reserve function IDs by compile order; init function is always id 0.
-    let mut next_func_id: FuncId = 1;
-    for function_ast in &functions {
+    for (func_id, function_ast) in (1..).zip(&functions) {
         let global_slot =
             builder.resolve_global(&function_ast.name.namespace, &function_ast.name.name);
-        let func_id = next_func_id;
-        next_func_id += 1;
         function_globals.push((global_slot, func_id));
-        compiled_functions.push(compile_function(function_ast, func_id, &mut builder)?);
+        builder.fn_params.insert(
+            format!(
+                "{}::{}",
+                function_ast.name.namespace, function_ast.name.name
+            ),
+            function_ast.args.clone(),
+        );
     }
 
-    let imports = compile_imports(
-        &top_level,
-        module_path,
-        loader,
-        cache,
-        visiting,
-        &mut builder,
-    )?;
-    let exports = collect_exports(&top_level, &mut builder)?;
+    // Imports are bound before bodies are lowered so call sites can use the
+    // imported functions' parameter names.
+    check_import_aliases(&top_level, &functions, &[])?;
+    let imports = compile_imports(&top_level, module_path, loader, cx, &mut builder)?;
+
+    for (func_id, function_ast) in (1..).zip(&functions) {
+        compiled_functions.push(compile_function(function_ast, func_id, &mut builder));
+    }
+    let exports = collect_exports(&top_level, &mut builder).unwrap_or_else(|err| {
+        builder.errors.push(err);
+        Vec::new()
+    });
     let init_body = filter_meta_calls(&top_level);
 
     let init_func = compile_raw_function(
         &init_body,
         0,
         "<init>",
-        Vec::new(),
+        SlotEnv::new(Vec::new(), 0),
         RetShape::Any,
         0,
         &mut builder,
         1,
-    )?;
+    );
 
     let mut functions_all = Vec::with_capacity(compiled_functions.len() + 1);
     functions_all.push(init_func);
     functions_all.extend(compiled_functions);
+    let inline: HashSet<FuncId> = (1..)
+        .zip(&functions)
+        .filter(|(_, function)| function.inline)
+        .map(|(func_id, _)| func_id)
+        .collect();
+    if !inline.is_empty() && builder.errors.is_empty() {
+        inline::inline_calls(&mut functions_all, &function_globals, &inline, opts);
+    }
 
-    Ok(CompiledModule {
+    let module = CompiledModule {
         name: Arc::from(builder.module_name.as_str()),
         init_func: 0,
         functions: functions_all,
         function_globals,
         exports,
         imports,
+        consts,
         global_count: builder.next_global,
-    })
+        tests: (1..)
+            .zip(&functions)
+            .filter(|(_, function)| function.test)
+            .map(|(func_id, _)| func_id)
+            .collect(),
+        global_names: builder.global_names(),
+    };
+    Ok((module, builder.errors))
 }
 
 fn filter_meta_calls(calls: &[Call]) -> Vec<Call> {
@@ -234,6 +573,7 @@ fn filter_meta_calls(calls: &[Call]) -> Vec<Call> {
         .filter(|call| {
             call.target != "core::import"
                 && call.target != "core::mod::export"
+                && call.target != "core::mod::const"
                 && call.target != "core::fn::begin"
                 && call.target != "core::fn::end"
         })
@@ -241,12 +581,104 @@ fn filter_meta_calls(calls: &[Call]) -> Vec<Call> {
         .collect()
 }
 
+/// Checks an import's source against its `sha256="<hex>"` pin.
+fn verify_import_hash(
+    call: &Call,
+    alias: &str,
+    import_path: &Path,
+    pinned: &Atom,
+    loader: &dyn ModuleLoader,
+) -> Result<(), CompileError> {
+    let expected = atom_as_str(pinned)
+        .filter(|hex| hex.len() == 64 && hex.bytes().all(|b| b.is_ascii_hexdigit()))
+        .ok_or_else(|| {
+            CompileError::new(
+                call.line,
+                format!("import '{alias}' sha256 must be a 64-digit hex string"),
+            )
+        })?;
+    let src = loader.load(&loader.normalize(import_path)?)?;
+    let actual = sha256_hex(src.as_bytes());
+    if !actual.eq_ignore_ascii_case(expected) {
+        return Err(CompileError::new(
+            call.line,
+            format!(
+                "import '{alias}' ({}) does not match its pinned sha256: expected {}, found {actual}",
+                import_path.display(),
+                expected.to_ascii_lowercase()
+            ),
+        ));
+    }
+    Ok(())
+}
+
+/// Rejects a module whose `existing` plus newly declared `functions` exceed
+/// `limits.max_functions`, pointing at the first function over the limit.
+fn check_function_limit(
+    functions: &[FunctionAst],
+    existing: usize,
+    limits: CompileLimits,
+) -> Result<(), CompileError> {
+    let Some(max) = limits.max_functions else {
+        return Ok(());
+    };
+    let total = existing + functions.len();
+    if total <= max {
+        return Ok(());
+    }
+    let line = functions
+        .get(max.saturating_sub(existing))
+        .map_or(1, |function| function.line);
+    Err(CompileError::new(
+        line,
+        format!("module declares {total} functions, over the limit of {max} (max_functions)"),
+    ))
+}
+
+/// Rejects import aliases that are reserved, bound twice (here or by an
+/// earlier chunk's `bound` imports), or equal to the namespace of a function
+/// the module declares; any of these would share global slots.
+fn check_import_aliases(
+    calls: &[Call],
+    functions: &[FunctionAst],
+    bound: &[ImportBinding],
+) -> Result<(), CompileError> {
+    let mut seen: HashMap<String, usize> = HashMap::new();
+    for call in calls {
+        if call.target != "core::import" {
+            continue;
+        }
+        let alias = get_string_arg(call, "alias")?;
+        let collision = if RESERVED_NAMESPACES.contains(&alias.as_str()) {
+            Some("is a reserved namespace".to_owned())
+        } else if let Some(line) = seen.get(&alias) {
+            Some(format!("is already used by the import at line {line}"))
+        } else if bound.iter().any(|import| import.alias == alias) {
+            Some("is already bound".to_owned())
+        } else if functions
+            .iter()
+            .any(|function| function.name.namespace == alias)
+        {
+            Some("collides with a function namespace".to_owned())
+        } else {
+            None
+        };
+        if let Some(reason) = collision {
+            return Err(CompileError::new(
+                call.line,
+                format!("import alias '{alias}' {reason}"),
+            ));
+        }
+        seen.insert(alias, call.line);
+    }
+    Ok(())
+}
+
 fn compile_imports(
     calls: &[Call],
     module_path: Option<&Path>,
     loader: &dyn ModuleLoader,
-    cache: &mut HashMap<PathBuf, CompiledModule>,
-    visiting: &mut HashSet<PathBuf>,
+    cx: &mut CompileCx,
     builder: &mut ModuleBuilder,
 ) -> Result<Vec<ImportBinding>, CompileError> {
     let mut imports = Vec::new();
@@ -259,12 +691,45 @@ fn compile_imports(
         let alias = get_string_arg(call, "alias")?;
         let path_raw = get_string_arg(call, "path")?;
         let import_path = resolve_import_path(module_path, Path::new(&path_raw));
-        let imported_module = compile_module_internal(&import_path, loader, cache, visiting)?;
+        if let Some(pinned) = call.arg("sha256") {
+            verify_import_hash(call, &alias, &import_path, pinned, loader)?;
+        }
+        let opts = builder.opts;
+        let imported_module =
+            compile_module_internal(&import_path, loader, opts, cx).map_err(|err| {
+                err.with_note(format!(
+                    "while compiling import '{alias}' ({}) at line {}",
+                    import_path.display(),
+                    call.line
+                ))
+            })?;
 
         let mut export_to_global = Vec::new();
-        for (name, _) in &imported_module.exports {
+        for (name, source) in &imported_module.exports {
             let destination = builder.resolve_global(&alias, name);
             export_to_global.push((name.clone(), destination));
+
+            let exported_fn = imported_module
+                .function_globals
+                .iter()
+                .find(|(slot, _)| slot == source)
+                .and_then(|(_, func_id)| imported_module.function(*func_id));
+            if let Some(function) = exported_fn {
+                builder.fn_params.insert(
+                    format!("{alias}::{name}"),
+                    function
+                        .meta
+                        .params
+                        .iter()
+                        .map(ToString::to_string)
+                        .collect(),
+                );
+            }
+        }
+        for (name, value) in &imported_module.consts {
+            builder
+                .consts
+                .insert(format!("{alias}::{name}"), value.clone());
         }
 
         imports.push(ImportBinding {
@@ -292,6 +757,117 @@ fn resolve_import_path(module_path: Option<&Path>, path: &Path) -> PathBuf {
     path.to_path_buf()
 }
 
+fn collect_consts(calls: &[Call]) -> Result<Vec<(String, ConstValue)>, CompileError> {
+    let mut consts: Vec<(String, ConstValue)> = Vec::new();
+    for call in calls {
+        if call.target != "core::mod::const" {
+            continue;
+        }
+        let name = get_string_arg(call, "name")?;
+        let value = match call.arg("value") {
+            Some(Atom::Ref(_)) => {
+                return Err(CompileError::new(
+                    call.line,
+                    "core::mod::const value must be a literal",
+                ));
+            }
+            Some(atom) => lower_const(atom, call.line)?,
+            None => {
+                return Err(CompileError::new(
+                    call.line,
+                    "core::mod::const missing value",
+                ));
+            }
+        };
+        if consts.iter().any(|(existing, _)| *existing == name) {
+            return Err(CompileError::new(
+                call.line,
+                format!("duplicate module constant '{name}'"),
+            ));
+        }
+        consts.push((name, value));
+    }
+    Ok(consts)
+}
+
+/// Replaces reads of module constants (`mod::NAME`, `alias::NAME`) with a
+/// local loaded by `StoreConst`, so constants never touch globals.
+fn fold_module_consts<'a>(
+    call: &'a Call,
+    env: &mut SlotEnv,
+    builder: &ModuleBuilder,
+    code: &mut Vec<Instr>,
+) -> Result<Cow<'a, Call>, CompileError> {
+    if builder.consts.is_empty() {
+        return Ok(Cow::Borrowed(call));
+    }
+    let mut folded = Cow::Borrowed(call);
+    for (index, arg) in call.args.iter().enumerate() {
+        let replacement = match &arg.value {
+            Atom::Ref(path) => {
+                let Some(local) = load_const_ref(path, env, builder, code) else {
+                    continue;
+                };
+                if arg.key == "out" || arg.key == "to" {
+                    return Err(CompileError::new(
+                        call.line,
+                        format!(
+                            "cannot assign to module constant '{}::{}'",
+                            path.namespace, path.name
+                        ),
+                    ));
+                }
+                Atom::Ref(local)
+            }
+            Atom::Str(csv) if arg.key == "args" => {
+                let mut changed = false;
+                let items = parse_csv(csv)
+                    .into_iter()
+                    .map(|item| {
+                        let local = RefPath::parse(&item)
+                            .and_then(|path| load_const_ref(&path, env, builder, code));
+                        match local {
+                            Some(local) => {
+                                changed = true;
+                                format!("local::{}", local.name)
+                            }
+                            None => item,
+                        }
+                    })
+                    .collect::<Vec<_>>();
+                if !changed {
+                    continue;
+                }
+                Atom::Str(items.join(","))
+            }
+            _ => continue,
+        };
+        folded.to_mut().args[index].value = replacement;
+    }
+    Ok(folded)
+}
+
+fn load_const_ref(
+    path: &RefPath,
+    env: &mut SlotEnv,
+    builder: &ModuleBuilder,
+    code: &mut Vec<Instr>,
+) -> Option<RefPath> {
+    let value = builder
+        .consts
+        .get(&format!("{}::{}", path.namespace, path.name))?;
+    let name = format!("__const_{}_{}", path.namespace, path.name);
+    let slot = env.resolve_local(&name);
+    code.push(Instr::StoreConst {
+        slot,
+        value: value.clone(),
+    });
+    Some(RefPath {
+        namespace: "local".to_owned(),
+        name,
+    })
+}
+
 fn collect_exports(
     calls: &[Call],
     builder: &mut ModuleBuilder,
@@ -309,109 +885,255 @@ fn collect_exports(
     Ok(exports)
 }
 
+/// Deepest nesting of `core::fn::lambda` blocks, counting the enclosing
+/// function.
+const MAX_BLOCK_DEPTH: usize = 64;
+
 fn split_functions(calls: &[Call]) -> Result<(Vec<Call>, Vec<FunctionAst>), CompileError> {
     let mut top_level = Vec::new();
     let mut functions = Vec::new();
 
-    let mut in_function = false;
-    let mut current: Option<FunctionAst> = None;
+    // Open blocks, innermost last: at most one declared function, plus the
+    // lambdas nested in it or at top level.
+    let mut open: Vec<FunctionAst> = Vec::new();
+    let mut lambda_count = 0;
 
     for call in calls {
         match call.target.as_str() {
             "core::fn::begin" => {
-                if in_function {
+                if !open.is_empty() {
                     return Err(CompileError::new(
                         call.line,
                         "nested functions are not allowed",
                     ));
                 }
-                in_function = true;
-                current = Some(FunctionAst {
-                    name: get_ref_arg(call, "name")?,
+                let name = get_ref_arg(call, "name")?;
+                if RESERVED_NAMESPACES.contains(&name.namespace.as_str()) {
+                    return Err(CompileError::new(
+                        call.line,
+                        format!(
+                            "function '{}::{}' uses reserved namespace '{}'",
+                            name.namespace, name.name, name.namespace
+                        ),
+                    ));
+                }
+                let test = parse_test_anno(call, &name)?;
+                open.push(FunctionAst {
+                    name,
                     args: parse_csv(&get_string_arg(call, "args").unwrap_or_default()),
                     retshape: parse_retshape(
                         call.arg("retshape").and_then(atom_as_str).unwrap_or("any"),
                     ),
-                    ret_count: call
-                        .arg("retcount")
-                        .and_then(atom_as_number)
-                        .map(|v| v as u32)
-                        .unwrap_or(1),
+                    ret_count: retcount_arg(call),
+                    timeout_ms: parse_timeout_anno(call)?,
+                    test,
+                    inline: call.annos.iter().any(|anno| anno == ANNO_INLINE),
+                    lambda: false,
                     body: Vec::new(),
                     line: call.line,
                 });
             }
-            "core::fn::end" => {
-                if !in_function {
+            "core::fn::lambda" => {
+                if open.len() == MAX_BLOCK_DEPTH {
                     return Err(CompileError::new(
                         call.line,
-                        "core::fn::end without core::fn::begin",
+                        format!("blocks nest deeper than {MAX_BLOCK_DEPTH}"),
                     ));
                 }
-                in_function = false;
-                if let Some(function) = current.take() {
-                    functions.push(function);
+                lambda_count += 1;
+                let (lambda, placeholder) = split_lambda(call, lambda_count)?;
+                match open.last_mut() {
+                    Some(function) => function.body.push(placeholder),
+                    None => top_level.push(placeholder),
                 }
+                open.push(lambda);
             }
-            _ => {
-                if in_function {
-                    if let Some(function) = current.as_mut() {
-                        function.body.push(call.clone());
-                    }
-                } else {
-                    top_level.push(call.clone());
-                }
+            "core::fn::end" => {
+                let Some(function) = open.pop() else {
+                    return Err(CompileError::new(
+                        call.line,
+                        "core::fn::end without core::fn::begin",
+                    ));
+                };
+                functions.push(function);
             }
+            _ => match open.last_mut() {
+                Some(function) => function.body.push(call.clone()),
+                None => top_level.push(call.clone()),
+            },
         }
     }
 
-    if in_function {
-        let line = current.as_ref().map_or(1, |f| f.line);
-        return Err(CompileError::new(line, "unclosed core::fn::begin block"));
+    if let Some(function) = open.last() {
+        let form = if function.lambda { "lambda" } else { "begin" };
+        return Err(CompileError::new(
+            function.line,
+            format!("unclosed core::fn::{form} block"),
+        ));
     }
 
     Ok((top_level, functions))
 }
 
+/// Turns a `core::fn::lambda` into the hidden function its block declares,
+/// `lambda::<index>`, and the call left in its place, which gains the
+/// function as `fn=`.
+///
+/// Captured refs become the function's leading parameters, named after the
+/// ref's name; its body starts by copying each into `local::NAME`.
+fn split_lambda(call: &Call, index: usize) -> Result<(FunctionAst, Call), CompileError> {
+    let name = RefPath {
+        namespace: "lambda".to_owned(),
+        name: format!("<{index}>"),
+    };
+    let mut params = Vec::new();
+    let mut body = Vec::new();
+    for item in parse_csv(&get_string_arg(call, "captures").unwrap_or_default()) {
+        let capture = RefPath::parse(&item).ok_or_else(|| {
+            let err = CompileError::new(call.line, format!("invalid capture ref '{item}'"));
+            arg_error(err, call, "captures")
+        })?;
+        if params.contains(&capture.name) {
+            let err = CompileError::new(
+                call.line,
+                format!("capture name '{}' is used twice", capture.name),
+            );
+            return Err(arg_error(err, call, "captures"));
+        }
+        let param = |namespace: &str| RefPath {
+            namespace: namespace.to_owned(),
+            name: capture.name.clone(),
+        };
+        body.push(Call {
+            annos: Vec::new(),
+            target: "core::mov".to_owned(),
+            args: vec![
+                generated_arg("from", Atom::Ref(param("arg")), call.span),
+                generated_arg("to", Atom::Ref(param("local")), call.span),
+            ],
+            line: call.line,
+            span: call.span,
+        });
+        params.push(capture.name);
+    }
+    for arg in parse_csv(&get_string_arg(call, "args").unwrap_or_default()) {
+        if params.contains(&arg) {
+            let err = CompileError::new(
+                call.line,
+                format!("lambda parameter '{arg}' shadows a capture"),
+            );
+            return Err(arg_error(err, call, "args"));
+        }
+        params.push(arg);
+    }
+
+    let mut placeholder = call.clone();
+    placeholder
+        .args
+        .push(generated_arg("fn", Atom::Ref(name.clone()), call.span));
+    let ret_count = retcount_arg(call);
+    let lambda = FunctionAst {
+        name,
+        args: params,
+        retshape: parse_retshape(call.arg("retshape").and_then(atom_as_str).unwrap_or("any")),
+        ret_count,
+        timeout_ms: parse_timeout_anno(call)?,
+        test: false,
+        inline: false,
+        lambda: true,
+        body,
+        line: call.line,
+    };
+    Ok((lambda, placeholder))
+}
+
+/// Whether a `core::fn::begin` carries `@test`; test functions take no
+/// arguments, since the runner calls them with none.
+fn parse_test_anno(call: &Call, name: &RefPath) -> Result<bool, CompileError> {
+    if !call.annos.iter().any(|anno| anno == ANNO_TEST) {
+        return Ok(false);
+    }
+    if !parse_csv(&get_string_arg(call, "args").unwrap_or_default()).is_empty() {
+        return Err(CompileError::new(
+            call.line,
+            format!(
+                "@test function '{}::{}' must take no arguments",
+                name.namespace, name.name
+            ),
+        ));
+    }
+    Ok(true)
+}
+
+/// Reads `@timeout(ms=N)` off a `core::fn::begin`.
+fn parse_timeout_anno(call: &Call) -> Result<Option<u64>, CompileError> {
+    let Some(anno) = call
+        .annos
+        .iter()
+        .find(|anno| anno.split('(').next() == Some(ANNO_TIMEOUT))
+    else {
+        return Ok(None);
+    };
+    anno.strip_prefix("timeout(ms=")
+        .and_then(|rest| rest.strip_suffix(')'))
+        .and_then(|ms| ms.parse::<u64>().ok())
+        .filter(|ms| *ms > 0)
+        .map(Some)
+        .ok_or_else(|| {
+            CompileError::new(
+                call.line,
+                format!("invalid @{anno}; expected @timeout(ms=<positive integer>)"),
+            )
+        })
+}
+
 fn compile_function(
     function_ast: &FunctionAst,
     func_id: FuncId,
     builder: &mut ModuleBuilder,
-) -> Result<CompiledFunction, CompileError> {
-    compile_raw_function(
+) -> CompiledFunction {
+    let mut function = compile_raw_function(
         &function_ast.body,
         func_id,
         &format!(
             "{}::{}",
             function_ast.name.namespace, function_ast.name.name
         ),
-        function_ast.args.clone(),
+        SlotEnv::new(function_ast.args.clone(), function_ast.ret_count),
         function_ast.retshape.clone(),
         function_ast.ret_count,
         builder,
         function_ast.line,
-    )
+    );
+    function.meta.timeout_ms = function_ast.timeout_ms;
+    function
 }
 
+/// Lowers `calls` into a function, recording each error in `builder.errors`
+/// and carrying on so one compile reports them all. A function with errors
+/// gets a body that throws `compile_error` with the first one's message.
+#[allow(clippy::too_many_arguments, clippy::too_many_lines)]
 fn compile_raw_function(
     calls: &[Call],
     func_id: FuncId,
     name: &str,
-    args: Vec<String>,
+    mut env: SlotEnv,
     retshape: RetShape,
     ret_count: u32,
     builder: &mut ModuleBuilder,
     default_line: usize,
-) -> Result<CompiledFunction, CompileError> {
-    let mut env = SlotEnv::new(args, ret_count);
+) -> CompiledFunction {
+    let params = env.params();
+    let first_error = builder.errors.len();
     let mut code = Vec::new();
     let mut labels: HashMap<String, usize> = HashMap::new();
     let mut pending_jumps = Vec::new();
     let mut pending_branches = Vec::new();
     let mut pending_try = Vec::new();
 
+    let mut lines = Vec::new();
     for call in calls {
-        lower_call(
+        if let Err(err) = lower_call(
             call,
             &mut env,
             builder,
@@ -420,38 +1142,48 @@ fn compile_raw_function(
             &mut pending_jumps,
             &mut pending_branches,
             &mut pending_try,
-        )?;
+        ) {
+            builder.errors.push(err.or_span(call.span));
+        }
+        lines.resize(code.len(), line_u32(call.line));
     }
 
     if !matches!(code.last(), Some(Instr::Exit)) {
         code.push(Instr::Exit);
+        lines.push(line_u32(
+            calls.last().map_or(default_line, |call| call.line),
+        ));
     }
 
+    let mut resolve = |label: &str| {
+        let target = labels.get(label).copied();
+        if target.is_none() {
+            builder.errors.push(CompileError::new(
+                default_line,
+                format!("unknown label '{label}'"),
+            ));
+        }
+        target
+    };
     for (pc, label) in pending_jumps {
-        let target = labels
-            .get(&label)
-            .copied()
-            .ok_or_else(|| CompileError::new(default_line, format!("unknown label '{label}'")))?;
-        if let Some(Instr::Jump {
-            target: jump_target,
-        }) = code.get_mut(pc)
+        if let Some(target) = resolve(&label)
+            && let Some(Instr::Jump {
+                target: jump_target,
+            }) = code.get_mut(pc)
         {
             *jump_target = target;
         }
     }
 
     for (pc, then_label, else_label) in pending_branches {
-        let then_pc = labels.get(&then_label).copied().ok_or_else(|| {
-            CompileError::new(default_line, format!("unknown label '{then_label}'"))
-        })?;
-        let else_pc = labels.get(&else_label).copied().ok_or_else(|| {
-            CompileError::new(default_line, format!("unknown label '{else_label}'"))
-        })?;
-        if let Some(Instr::Branch {
-            then_pc: branch_then,
-            else_pc: branch_else,
-            ..
-        }) = code.get_mut(pc)
+        let then_pc = resolve(&then_label);
+        let else_pc = resolve(&else_label);
+        if let (Some(then_pc), Some(else_pc)) = (then_pc, else_pc)
+            && let Some(Instr::Branch {
+                then_pc: branch_then,
+                else_pc: branch_else,
+                ..
+            }) = code.get_mut(pc)
         {
             *branch_then = then_pc;
             *branch_else = else_pc;
@@ -459,33 +1191,113 @@ fn compile_raw_function(
     }
 
     for (pc, label) in pending_try {
-        let handler_pc = labels
-            .get(&label)
-            .copied()
-            .ok_or_else(|| CompileError::new(default_line, format!("unknown label '{label}'")))?;
-        if let Some(Instr::TryPush { handler_pc: target }) = code.get_mut(pc) {
+        if let Some(handler_pc) = resolve(&label)
+            && let Some(Instr::TryPush { handler_pc: target }) = code.get_mut(pc)
+        {
             *target = handler_pc;
         }
     }
 
-    Ok(CompiledFunction {
+    mark_tail_calls(&mut code, &retshape, ret_count);
+    if builder.opts.opt_level > 0 {
+        optimize::remove_unreachable(&mut code, &mut lines);
+        optimize::fold_constants(&mut code);
+    }
+
+    let limits = builder.opts.limits;
+    if let Some(max) = limits.max_instructions
+        && code.len() > max
+    {
+        builder.errors.push(CompileError::new(
+            default_line,
+            format!(
+                "{name} compiles to {} instructions, over the limit of {max} (max_instructions)",
+                code.len()
+            ),
+        ));
+    }
+    if let Some(max) = limits.max_locals
+        && env.next_local > max
+    {
+        builder.errors.push(CompileError::new(
+            default_line,
+            format!(
+                "{name} uses {} local slots, over the limit of {max} (max_locals)",
+                env.next_local
+            ),
+        ));
+    }
+
+    if let Some(err) = builder.errors.get(first_error) {
+        code = vec![Instr::Throw {
+            code: "compile_error".to_owned(),
+            msg: err.message.clone(),
+            cause: None,
+            data: None,
+        }];
+        lines = vec![line_u32(err.line)];
+    }
+
+    CompiledFunction {
         id: func_id,
         code: code.into(),
         local_count: env.next_local,
-        arg_count: env.args.len() as u32,
+        arg_count: slot_u32(env.args.len()),
         ret_count,
         err_count: env.next_err,
         meta: FnMeta {
             name: Arc::from(name),
-            arg_count: env.args.len() as u32,
+            params,
+            arg_count: slot_u32(env.args.len()),
             ret_count,
             retshape,
+            timeout_ms: None,
+            debug: Some(Arc::new(DebugInfo {
+                source: builder.source.clone(),
+                lines: lines.into(),
+            })),
         },
-    })
+    }
 }
 
-#[allow(clippy::too_many_arguments)]
-fn lower_call(
+/// Turns each `Invoke` into `return::0` directly followed by `core::exit`
+/// into `TailInvoke`, when the function returns just that slot and checks no
+/// more than the count, so returning the callee's first value in its place
+/// changes nothing the caller's caller can see.
+fn mark_tail_calls(code: &mut [Instr], retshape: &RetShape, ret_count: u32) {
+    let single_return = ret_count == 1
+        && matches!(retshape, RetShape::Scalar | RetShape::Any)
+        && code.iter().all(|instr| match instr {
+            Instr::ReturnSet { slot_id, .. } => *slot_id == 0,
+            _ => instr
+                .slots()
+                .into_iter()
+                .all(|slot| !matches!(slot, Slot::Ret(index) if index > 0)),
+        });
+    if !single_return {
+        return;
+    }
+    for pc in 1..code.len() {
+        if !matches!(code[pc], Instr::Exit) {
+            continue;
+        }
+        if let Instr::Invoke {
+            fn_slot,
+            args,
+            out: Slot::Ret(0),
+        } = &mut code[pc - 1]
+        {
+            code[pc - 1] = Instr::TailInvoke {
+                fn_slot: *fn_slot,
+                args: std::mem::take(args),
+            };
+        }
+    }
+}
+
+// One arm per core target.
+#[allow(clippy::too_many_arguments, clippy::too_many_lines)]
+fn lower_call(
     call: &Call,
     env: &mut SlotEnv,
     builder: &mut ModuleBuilder,
@@ -495,9 +1307,36 @@ fn lower_call(
     pending_branches: &mut Vec<(usize, String, String)>,
     pending_try: &mut Vec<(usize, String)>,
 ) -> Result<(), CompileError> {
+    let folded = fold_module_consts(call, env, builder, code)?;
+    let call = folded.as_ref();
+    if !is_core_target(&call.target)
+        && let Some(plugin) = builder
+            .opts
+            .target_plugins
+            .iter()
+            .find(|plugin| plugin.handles(&call.target))
+    {
+        return plugin.lower(&mut LoweringCx {
+            call,
+            env,
+            builder,
+            code,
+        });
+    }
     if !is_core_target(&call.target) {
         let fn_slot = resolve_target_ref(call, env, builder)?;
-        let mut args = collect_invoke_args(call, env, builder)?;
+        let mut args = match bind_named_args(call, env, builder, code)? {
+            Some(args) => args,
+            None => collect_invoke_args(call, env, builder)?,
+        };
+        if let Some(outs) = collect_invoke_outs(call, env, builder)? {
+            code.push(Instr::InvokeMulti {
+                fn_slot,
+                args,
+                outs,
+            });
+            return Ok(());
+        }
         let out = call
             .arg("out")
             .map(|atom| resolve_ref_atom(atom, env, builder, call.line))
@@ -532,7 +1371,8 @@ fn lower_call(
             let to = resolve_named_ref(call, "to", env, builder)?;
             code.push(Instr::Move { from, to });
         }
-        "core::add" | "core::sub" | "core::mul" | "core::div" | "core::eq" | "core::lt" => {
+        "core::add" | "core::sub" | "core::mul" | "core::div" | "core::mod" | "core::pow"
+        | "core::eq" | "core::ne" | "core::lt" | "core::le" | "core::gt" | "core::ge" => {
             let a = resolve_named_ref(call, "a", env, builder)?;
             let b = resolve_named_ref(call, "b", env, builder)?;
             let out = resolve_named_ref(call, "out", env, builder)?;
@@ -541,11 +1381,28 @@ fn lower_call(
                 "core::sub" => Instr::Sub { a, b, out },
                 "core::mul" => Instr::Mul { a, b, out },
                 "core::div" => Instr::Div { a, b, out },
+                "core::mod" => Instr::Mod { a, b, out },
+                "core::pow" => Instr::Pow { a, b, out },
                 "core::eq" => Instr::Eq { a, b, out },
+                "core::ne" => Instr::Ne { a, b, out },
+                "core::le" => Instr::Le { a, b, out },
+                "core::gt" => Instr::Gt { a, b, out },
+                "core::ge" => Instr::Ge { a, b, out },
                 _ => Instr::Lt { a, b, out },
             };
             code.push(instr);
         }
+        "core::neg" => {
+            let value = resolve_named_ref(call, "value", env, builder)?;
+            let out = resolve_named_ref(call, "out", env, builder)?;
+            code.push(Instr::Neg { value, out });
+        }
+        "core::cmp" => {
+            let a = resolve_required_atom(call, "value_a", env, builder, code)?;
+            let b = resolve_required_atom(call, "value_b", env, builder, code)?;
+            let out = resolve_named_ref(call, "out", env, builder)?;
+            code.push(Instr::Cmp { a, b, out });
+        }
         "core::label" => {
             let name = get_string_arg(call, "name")?;
             labels.insert(name, code.len());
@@ -570,11 +1427,31 @@ fn lower_call(
         }
         "core::invoke" => {
             let fn_slot = resolve_named_ref(call, "fn", env, builder)?;
-            let out = resolve_named_ref(call, "out", env, builder)?;
             let args = collect_invoke_args(call, env, builder)?;
-            code.push(Instr::Invoke { fn_slot, args, out });
+            if let Some(outs) = collect_invoke_outs(call, env, builder)? {
+                code.push(Instr::InvokeMulti {
+                    fn_slot,
+                    args,
+                    outs,
+                });
+            } else {
+                let out = resolve_named_ref(call, "out", env, builder)?;
+                code.push(Instr::Invoke { fn_slot, args, out });
+            }
+        }
+        "core::invoke::dynamic" => {
+            let fn_slot = resolve_named_ref(call, "fn", env, builder)?;
+            let arglist = resolve_named_ref(call, "arglist", env, builder)?;
+            let out = resolve_named_ref(call, "out", env, builder)?;
+            code.push(Instr::InvokeDynamic {
+                fn_slot,
+                arglist,
+                out,
+            });
         }
         "core::ret::set" => {
+            // Out-of-range slots saturate.
+            #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
             let slot_id = call.arg("slot").and_then(atom_as_number).ok_or_else(|| {
                 CompileError::new(call.line, "core::ret::set requires numeric slot")
             })? as u32;
@@ -587,11 +1464,28 @@ fn lower_call(
         "core::throw" => {
             let code_text = get_string_arg(call, "code")?;
             let msg = get_string_arg(call, "msg")?;
+            let cause = call
+                .arg("cause")
+                .map(|atom| resolve_ref_atom(atom, env, builder, call.line))
+                .transpose()?;
+            let data = call
+                .arg("data")
+                .map(|atom| resolve_ref_atom(atom, env, builder, call.line))
+                .transpose()?;
             code.push(Instr::Throw {
                 code: code_text,
                 msg,
+                cause,
+                data,
             });
         }
+        "core::rethrow" => {
+            let err = match call.arg("err") {
+                Some(atom) => resolve_ref_atom(atom, env, builder, call.line)?,
+                None => Slot::Err(0),
+            };
+            code.push(Instr::Rethrow { err });
+        }
         "core::try::push" => {
             let handler_label = get_string_arg(call, "handler")?;
             let pc = code.len();
@@ -654,6 +1548,90 @@ fn lower_call(
             let out = resolve_named_ref(call, "out", env, builder)?;
             code.push(Instr::ObjHas { obj, key, out });
         }
+        "core::list::new" => {
+            let out = resolve_named_ref(call, "out", env, builder)?;
+            code.push(Instr::ListNew { out });
+        }
+        "core::list::push" => {
+            let list = resolve_required_atom(call, "list", env, builder, code)?;
+            let value = resolve_required_atom(call, "value", env, builder, code)?;
+            let out = resolve_named_ref(call, "out", env, builder)?;
+            code.push(Instr::ListPush { list, value, out });
+        }
+        "core::list::get" => {
+            let list = resolve_required_atom(call, "list", env, builder, code)?;
+            let index = resolve_required_atom(call, "index", env, builder, code)?;
+            let out = resolve_named_ref(call, "out", env, builder)?;
+            code.push(Instr::ListGet { list, index, out });
+        }
+        "core::list::set" => {
+            let list = resolve_required_atom(call, "list", env, builder, code)?;
+            let index = resolve_required_atom(call, "index", env, builder, code)?;
+            let value = resolve_required_atom(call, "value", env, builder, code)?;
+            let out = resolve_named_ref(call, "out", env, builder)?;
+            code.push(Instr::ListSet {
+                list,
+                index,
+                value,
+                out,
+            });
+        }
+        "core::list::len" => {
+            let list = resolve_required_atom(call, "list", env, builder, code)?;
+            let out = resolve_named_ref(call, "out", env, builder)?;
+            code.push(Instr::ListLen { list, out });
+        }
+        "core::obj::entries" => {
+            let obj = resolve_required_atom(call, "obj", env, builder, code)?;
+            let out = resolve_named_ref(call, "out", env, builder)?;
+            let len = match call.arg("len") {
+                Some(_) => Some(resolve_named_ref(call, "len", env, builder)?),
+                None => None,
+            };
+            code.push(Instr::ObjEntries { obj, out, len });
+        }
+        "core::obj::keys" => {
+            let obj = resolve_required_atom(call, "obj", env, builder, code)?;
+            let out = resolve_named_ref(call, "out", env, builder)?;
+            code.push(Instr::ObjKeys { obj, out });
+        }
+        "core::obj::del" => {
+            let obj = resolve_required_atom(call, "obj", env, builder, code)?;
+            let key = resolve_required_atom(call, "key", env, builder, code)?;
+            let out = resolve_named_ref(call, "out", env, builder)?;
+            code.push(Instr::ObjDel { obj, key, out });
+        }
+        "core::obj::len" => {
+            let obj = resolve_required_atom(call, "obj", env, builder, code)?;
+            let out = resolve_named_ref(call, "out", env, builder)?;
+            code.push(Instr::ObjLen { obj, out });
+        }
+        "core::obj::getpath" => {
+            let obj = resolve_required_atom(call, "obj", env, builder, code)?;
+            let path = resolve_required_atom(call, "path", env, builder, code)?;
+            let out = resolve_named_ref(call, "out", env, builder)?;
+            let safe = flag_arg(call, "safe")?;
+            code.push(Instr::ObjGetPath {
+                obj,
+                path,
+                out,
+                safe,
+            });
+        }
+        "core::obj::setpath" => {
+            let obj = resolve_required_atom(call, "obj", env, builder, code)?;
+            let path = resolve_required_atom(call, "path", env, builder, code)?;
+            let value = resolve_required_atom(call, "value", env, builder, code)?;
+            let out = resolve_named_ref(call, "out", env, builder)?;
+            let safe = flag_arg(call, "safe")?;
+            code.push(Instr::ObjSetPath {
+                obj,
+                path,
+                value,
+                out,
+                safe,
+            });
+        }
         "core::str::concat" => {
             let a = resolve_atom_to_slot(
                 call.arg("a")
@@ -674,6 +1652,12 @@ fn lower_call(
             let out = resolve_named_ref(call, "out", env, builder)?;
             code.push(Instr::StrConcat { a, b, out });
         }
+        "core::str::format" => {
+            let tmpl = resolve_required_atom(call, "tmpl", env, builder, code)?;
+            let args = resolve_required_atom(call, "args", env, builder, code)?;
+            let out = resolve_named_ref(call, "out", env, builder)?;
+            code.push(Instr::StrFormat { tmpl, args, out });
+        }
         "core::str::len" => {
             let value = resolve_atom_to_slot(
                 call.arg("value")
@@ -686,19 +1670,392 @@ fn lower_call(
             let out = resolve_named_ref(call, "out", env, builder)?;
             code.push(Instr::StrLen { value, out });
         }
-        "core::host::print" => {
+        "core::str::sub" => {
+            let value = resolve_required_atom(call, "value", env, builder, code)?;
+            let start = resolve_required_atom(call, "start", env, builder, code)?;
+            let end = match call.arg("end") {
+                Some(atom) => Some(resolve_atom_to_slot(atom, env, builder, code, call.line)?),
+                None => None,
+            };
+            let out = resolve_named_ref(call, "out", env, builder)?;
+            code.push(Instr::StrSub {
+                value,
+                start,
+                end,
+                out,
+            });
+        }
+        "core::str::find" => {
+            let value = resolve_required_atom(call, "value", env, builder, code)?;
+            let pattern = resolve_required_atom(call, "pattern", env, builder, code)?;
+            let out = resolve_named_ref(call, "out", env, builder)?;
+            code.push(Instr::StrFind {
+                value,
+                pattern,
+                out,
+            });
+        }
+        "core::str::replace" => {
+            let value = resolve_required_atom(call, "value", env, builder, code)?;
+            let pattern = resolve_required_atom(call, "pattern", env, builder, code)?;
+            let with = resolve_required_atom(call, "with", env, builder, code)?;
+            let out = resolve_named_ref(call, "out", env, builder)?;
+            code.push(Instr::StrReplace {
+                value,
+                pattern,
+                with,
+                out,
+            });
+        }
+        "core::str::contains" => {
+            let value = resolve_required_atom(call, "value", env, builder, code)?;
+            let pattern = resolve_required_atom(call, "pattern", env, builder, code)?;
+            let out = resolve_named_ref(call, "out", env, builder)?;
+            code.push(Instr::StrContains {
+                value,
+                pattern,
+                out,
+            });
+        }
+        "core::str::upper" => {
+            let value = resolve_required_atom(call, "value", env, builder, code)?;
+            let out = resolve_named_ref(call, "out", env, builder)?;
+            code.push(Instr::StrUpper { value, out });
+        }
+        "core::str::lower" => {
+            let value = resolve_required_atom(call, "value", env, builder, code)?;
+            let out = resolve_named_ref(call, "out", env, builder)?;
+            code.push(Instr::StrLower { value, out });
+        }
+        "core::str::trim" => {
+            let value = resolve_required_atom(call, "value", env, builder, code)?;
+            let out = resolve_named_ref(call, "out", env, builder)?;
+            code.push(Instr::StrTrim { value, out });
+        }
+        "core::str::trim_start" => {
+            let value = resolve_required_atom(call, "value", env, builder, code)?;
+            let out = resolve_named_ref(call, "out", env, builder)?;
+            code.push(Instr::StrTrimStart { value, out });
+        }
+        "core::str::trim_end" => {
+            let value = resolve_required_atom(call, "value", env, builder, code)?;
+            let out = resolve_named_ref(call, "out", env, builder)?;
+            code.push(Instr::StrTrimEnd { value, out });
+        }
+        "core::str::split" => {
+            let value = resolve_required_atom(call, "value", env, builder, code)?;
+            let sep = resolve_required_atom(call, "sep", env, builder, code)?;
+            let out = resolve_named_ref(call, "out", env, builder)?;
+            code.push(Instr::StrSplit { value, sep, out });
+        }
+        "core::str::join" => {
+            let list = resolve_required_atom(call, "list", env, builder, code)?;
+            let sep = resolve_required_atom(call, "sep", env, builder, code)?;
+            let out = resolve_named_ref(call, "out", env, builder)?;
+            code.push(Instr::StrJoin { list, sep, out });
+        }
+        "core::bytes::from_str" => {
+            let out = resolve_named_ref(call, "out", env, builder)?;
+            // Literal input is encoded once, at compile time.
+            if let Some(Atom::Str(text)) = call.arg("value") {
+                code.push(Instr::StoreConst {
+                    slot: out,
+                    value: ConstValue::Bytes(Arc::from(text.as_bytes())),
+                });
+            } else {
+                let value = resolve_required_atom(call, "value", env, builder, code)?;
+                code.push(Instr::BytesFromStr { value, out });
+            }
+        }
+        "core::bytes::to_str" => {
+            let value = resolve_required_atom(call, "value", env, builder, code)?;
+            let out = resolve_named_ref(call, "out", env, builder)?;
+            code.push(Instr::BytesToStr { value, out });
+        }
+        "core::int::parse" => {
+            let out = resolve_named_ref(call, "out", env, builder)?;
+            let literal = match call.arg("value") {
+                Some(Atom::Str(text)) => text.trim().parse().ok(),
+                Some(Atom::Num(num))
+                    if num.fract() == 0.0 && num.abs() <= 9_007_199_254_740_992.0 =>
+                {
+                    // Whole and within 2^53, so the cast is exact.
+                    #[allow(clippy::cast_possible_truncation)]
+                    let int = *num as i64;
+                    Some(int)
+                }
+                _ => None,
+            };
+            // Literal input is parsed once, at compile time.
+            if let Some(int) = literal {
+                code.push(Instr::StoreConst {
+                    slot: out,
+                    value: ConstValue::Int(int),
+                });
+            } else {
+                let value = resolve_required_atom(call, "value", env, builder, code)?;
+                code.push(Instr::IntParse { value, out });
+            }
+        }
+        "core::json::parse" | "core::json::stringify" => {
+            let value = resolve_required_atom(call, "value", env, builder, code)?;
+            let out = resolve_named_ref(call, "out", env, builder)?;
+            code.push(if call.target == "core::json::parse" {
+                Instr::JsonParse { value, out }
+            } else {
+                Instr::JsonStringify { value, out }
+            });
+        }
+        "core::type_of" => {
+            let value = resolve_required_atom(call, "value", env, builder, code)?;
+            let out = resolve_named_ref(call, "out", env, builder)?;
+            code.push(Instr::TypeOf { value, out });
+        }
+        target if target.starts_with("core::is::") => {
+            let kind = ValueKind::parse(&target["core::is::".len()..]).ok_or_else(|| {
+                CompileError::new(call.line, format!("unsupported core target '{target}'"))
+            })?;
+            let value = resolve_required_atom(call, "value", env, builder, code)?;
+            let out = resolve_named_ref(call, "out", env, builder)?;
+            code.push(Instr::IsType { value, kind, out });
+        }
+        "core::num::parse" => {
+            let out = resolve_named_ref(call, "out", env, builder)?;
+            let literal = match call.arg("value") {
+                Some(Atom::Str(text)) => text
+                    .trim()
+                    .parse::<f64>()
+                    .ok()
+                    .filter(|num| num.is_finite()),
+                Some(Atom::Num(num)) => Some(*num),
+                _ => None,
+            };
+            if let Some(num) = literal {
+                code.push(Instr::StoreConst {
+                    slot: out,
+                    value: ConstValue::Num(num),
+                });
+            } else {
+                let value = resolve_required_atom(call, "value", env, builder, code)?;
+                code.push(Instr::NumParse { value, out });
+            }
+        }
+        "core::num::format" => {
+            let value = resolve_required_atom(call, "value", env, builder, code)?;
+            let precision = match call.arg("precision") {
+                Some(atom) => Some(resolve_atom_to_slot(atom, env, builder, code, call.line)?),
+                None => None,
+            };
+            let width = match call.arg("width") {
+                Some(atom) => Some(resolve_atom_to_slot(atom, env, builder, code, call.line)?),
+                None => None,
+            };
+            let out = resolve_named_ref(call, "out", env, builder)?;
+            code.push(Instr::NumFormat {
+                value,
+                precision,
+                width,
+                out,
+            });
+        }
+        "core::bytes::len" => {
+            let value = resolve_required_atom(call, "value", env, builder, code)?;
+            let out = resolve_named_ref(call, "out", env, builder)?;
+            code.push(Instr::BytesLen { value, out });
+        }
+        "core::bytes::concat" => {
+            let a = resolve_required_atom(call, "a", env, builder, code)?;
+            let b = resolve_required_atom(call, "b", env, builder, code)?;
+            let out = resolve_named_ref(call, "out", env, builder)?;
+            code.push(Instr::BytesConcat { a, b, out });
+        }
+        "core::bytes::slice" => {
+            let value = resolve_required_atom(call, "value", env, builder, code)?;
+            let start = resolve_required_atom(call, "start", env, builder, code)?;
+            let end = match call.arg("end") {
+                Some(atom) => Some(resolve_atom_to_slot(atom, env, builder, code, call.line)?),
+                None => None,
+            };
+            let out = resolve_named_ref(call, "out", env, builder)?;
+            code.push(Instr::BytesSlice {
+                value,
+                start,
+                end,
+                out,
+            });
+        }
+        "core::fn::meta" => {
+            let func = resolve_atom_to_slot(
+                call.arg("fn")
+                    .ok_or_else(|| CompileError::new(call.line, "core::fn::meta missing fn"))?,
+                env,
+                builder,
+                code,
+                call.line,
+            )?;
+            let out = resolve_named_ref(call, "out", env, builder)?;
+            code.push(Instr::FnMeta { func, out });
+        }
+        "core::error::wrap" => {
+            let err = resolve_named_ref(call, "err", env, builder)?;
+            let code_text = get_string_arg(call, "code")?;
+            let msg = get_string_arg(call, "msg")?;
+            let out = resolve_named_ref(call, "out", env, builder)?;
+            code.push(Instr::ErrorWrap {
+                err,
+                code: code_text,
+                msg,
+                out,
+            });
+        }
+        "core::error::cause" => {
+            let err = resolve_named_ref(call, "err", env, builder)?;
+            let out = resolve_named_ref(call, "out", env, builder)?;
+            code.push(Instr::ErrorCause { err, out });
+        }
+        "core::error::code" | "core::err::code" => {
+            let err = resolve_named_ref(call, "err", env, builder)?;
+            let out = resolve_named_ref(call, "out", env, builder)?;
+            code.push(Instr::ErrorCode { err, out });
+        }
+        "core::error::msg" | "core::err::msg" => {
+            let err = resolve_named_ref(call, "err", env, builder)?;
+            let out = resolve_named_ref(call, "out", env, builder)?;
+            code.push(Instr::ErrorMsg { err, out });
+        }
+        "core::error::data" | "core::err::data" => {
+            let err = resolve_named_ref(call, "err", env, builder)?;
+            let out = resolve_named_ref(call, "out", env, builder)?;
+            code.push(Instr::ErrorData { err, out });
+        }
+        "core::fn::bind" => {
+            let func = resolve_atom_to_slot(
+                call.arg("fn")
+                    .ok_or_else(|| CompileError::new(call.line, "core::fn::bind missing fn"))?,
+                env,
+                builder,
+                code,
+                call.line,
+            )?;
+            let args = collect_invoke_args(call, env, builder)?;
+            let out = resolve_named_ref(call, "out", env, builder)?;
+            code.push(Instr::FnBind { func, args, out });
+        }
+        "core::fn::lambda" => {
+            let func = get_ref_arg(call, "fn")?;
+            let func = env.resolve_ref(&func, builder);
+            let mut captures = Vec::new();
+            for item in parse_csv(&get_string_arg(call, "captures").unwrap_or_default()) {
+                let path = RefPath::parse(&item).ok_or_else(|| {
+                    CompileError::new(call.line, format!("invalid capture ref '{item}'"))
+                })?;
+                captures.push(env.resolve_ref(&path, builder));
+            }
+            let out = resolve_named_ref(call, "out", env, builder)?;
+            code.push(Instr::MakeClosure {
+                func,
+                captures,
+                out,
+            });
+        }
+        "core::host::call" => {
+            let name = get_string_arg(call, "name")?;
+            let args = collect_invoke_args(call, env, builder)?;
+            let out = resolve_named_ref(call, "out", env, builder)?;
+            code.push(Instr::HostCall { name, args, out });
+        }
+        "core::host::print" | "core::host::eprint" => {
             let slot = call
                 .arg("slot")
                 .or_else(|| call.arg("value"))
                 .ok_or_else(|| {
-                    CompileError::new(call.line, "core::host::print missing slot/value")
+                    CompileError::new(call.line, format!("{} missing slot/value", call.target))
                 })?;
             let slot = resolve_ref_atom(slot, env, builder, call.line)?;
-            code.push(Instr::HostPrint { slot });
+            let stream = if call.target == "core::host::eprint" {
+                PrintStream::Stderr
+            } else {
+                PrintStream::Stdout
+            };
+            let level = match call.arg("level") {
+                Some(_) => {
+                    let name = get_string_arg(call, "level")?;
+                    PrintLevel::parse(&name).ok_or_else(|| {
+                        CompileError::new(
+                            call.line,
+                            format!(
+                                "unknown print level '{name}' (expected debug|info|warn|error)"
+                            ),
+                        )
+                    })?
+                }
+                None => PrintLevel::default(),
+            };
+            code.push(Instr::HostPrint {
+                slot,
+                stream,
+                level,
+            });
+        }
+        "core::host::now" | "core::host::monotonic" => {
+            let clock = if call.target == "core::host::now" {
+                HostClock::Wall
+            } else {
+                HostClock::Monotonic
+            };
+            let out = resolve_named_ref(call, "out", env, builder)?;
+            code.push(Instr::HostTime { clock, out });
+        }
+        "core::host::args" => {
+            let out = resolve_named_ref(call, "out", env, builder)?;
+            code.push(Instr::HostArgs { out });
+        }
+        "core::host::read_line" | "core::host::read_all" => {
+            let out = resolve_named_ref(call, "out", env, builder)?;
+            code.push(if call.target == "core::host::read_line" {
+                Instr::HostReadLine { out }
+            } else {
+                Instr::HostReadAll { out }
+            });
+        }
+        "core::host::env" => {
+            let key = resolve_required_atom(call, "key", env, builder, code)?;
+            let out = resolve_named_ref(call, "out", env, builder)?;
+            code.push(Instr::HostEnvVar { key, out });
+        }
+        "core::host::random" => {
+            let out = resolve_named_ref(call, "out", env, builder)?;
+            code.push(Instr::HostRandom { out });
+        }
+        "core::host::random_int" => {
+            let lo = resolve_required_atom(call, "lo", env, builder, code)?;
+            let hi = resolve_required_atom(call, "hi", env, builder, code)?;
+            let out = resolve_named_ref(call, "out", env, builder)?;
+            code.push(Instr::HostRandomInt { lo, hi, out });
+        }
+        "core::host::fs::read" | "core::host::fs::exists" => {
+            let path = resolve_required_atom(call, "path", env, builder, code)?;
+            let out = resolve_named_ref(call, "out", env, builder)?;
+            code.push(if call.target == "core::host::fs::read" {
+                Instr::HostFsRead { path, out }
+            } else {
+                Instr::HostFsExists { path, out }
+            });
+        }
+        "core::host::fs::write" => {
+            let path = resolve_required_atom(call, "path", env, builder, code)?;
+            let data = resolve_required_atom(call, "data", env, builder, code)?;
+            code.push(Instr::HostFsWrite { path, data });
         }
         "core::import" | "core::mod::export" => {
             // Handled in metadata pass.
         }
+        "core::mod::const" => {
+            return Err(CompileError::new(
+                call.line,
+                "core::mod::const is only valid at module top level",
+            ));
+        }
         "core::fn::begin" | "core::fn::end" => {
             return Err(CompileError::new(
                 call.line,
@@ -752,7 +2109,15 @@ fn resolve_named_ref(
     let atom = call
         .arg(key)
         .ok_or_else(|| CompileError::new(call.line, format!("{} missing {key}", call.target)))?;
-    resolve_ref_atom(atom, env, builder, call.line)
+    resolve_ref_atom(atom, env, builder, call.line).map_err(|err| arg_error(err, call, key))
+}
+
+/// Points `err` at argument `key` of `call`.
+fn arg_error(err: CompileError, call: &Call, key: &str) -> CompileError {
+    match call.arg_span(key) {
+        Some(span) => err.or_span(span),
+        None => err,
+    }
 }
 
 fn resolve_ref_atom(
@@ -786,6 +2151,36 @@ fn resolve_atom_to_slot(
     }
 }
 
+fn resolve_required_atom(
+    call: &Call,
+    key: &str,
+    env: &mut SlotEnv,
+    builder: &mut ModuleBuilder,
+    code: &mut Vec<Instr>,
+) -> Result<Slot, CompileError> {
+    let atom = call
+        .arg(key)
+        .ok_or_else(|| CompileError::new(call.line, format!("{} missing {key}", call.target)))?;
+    resolve_atom_to_slot(atom, env, builder, code, call.line)
+        .map_err(|err| arg_error(err, call, key))
+}
+
+/// An optional literal `true`/`false` argument, false when absent.
+fn flag_arg(call: &Call, key: &str) -> Result<bool, CompileError> {
+    match call.arg(key) {
+        None => Ok(false),
+        Some(Atom::Bool(value)) => Ok(*value),
+        Some(_) => Err(arg_error(
+            CompileError::new(
+                call.line,
+                format!("{} {key} must be true or false", call.target),
+            ),
+            call,
+            key,
+        )),
+    }
+}
+
 fn collect_invoke_args(
     call: &Call,
     env: &mut SlotEnv,
@@ -805,7 +2200,7 @@ fn collect_invoke_args(
     let mut arg_pairs = call
         .args
         .iter()
-        .filter(|arg| arg.key.starts_with("arg"))
+        .filter(|arg| is_positional_arg_key(&arg.key))
         .collect::<Vec<_>>();
     arg_pairs.sort_by(|a, b| a.key.cmp(&b.key));
 
@@ -816,17 +2211,127 @@ fn collect_invoke_args(
     Ok(out)
 }
 
-fn parse_retshape(raw: &str) -> RetShape {
-    if raw.eq_ignore_ascii_case("scalar") {
-        return RetShape::Scalar;
+/// The slots of an `outs="local::a,local::b"` argument, which takes the
+/// place of `out` to receive every return value of the call.
+fn collect_invoke_outs(
+    call: &Call,
+    env: &mut SlotEnv,
+    builder: &mut ModuleBuilder,
+) -> Result<Option<Vec<Slot>>, CompileError> {
+    let Some(outs) = call.arg("outs") else {
+        return Ok(None);
+    };
+    if call.arg("out").is_some() {
+        return Err(CompileError::new(
+            call.line,
+            format!("{} takes out or outs, not both", call.target),
+        ));
     }
-    if raw.eq_ignore_ascii_case("any") {
-        return RetShape::Any;
+    let items = atom_as_str(outs).map(parse_csv).unwrap_or_default();
+    if items.is_empty() {
+        return Err(CompileError::new(
+            call.line,
+            format!("{} outs must list at least one ref", call.target),
+        ));
     }
-    if let Some(inner) = raw
-        .strip_prefix("either(")
-        .and_then(|rest| rest.strip_suffix(')'))
-    {
+    items
+        .iter()
+        .map(|item| {
+            let path = RefPath::parse(item).ok_or_else(|| {
+                CompileError::new(call.line, format!("invalid invoke out ref '{item}'"))
+            })?;
+            if builder.consts.contains_key(item.as_str()) {
+                return Err(CompileError::new(
+                    call.line,
+                    format!("cannot assign to module constant '{item}'"),
+                ));
+            }
+            Ok(env.resolve_ref(&path, builder))
+        })
+        .collect::<Result<_, _>>()
+        .map(Some)
+}
+
+fn is_positional_arg_key(key: &str) -> bool {
+    key.strip_prefix("arg")
+        .is_some_and(|index| !index.is_empty() && index.bytes().all(|b| b.is_ascii_digit()))
+}
+
+/// Maps `param=value` call-site arguments onto the target's positional
+/// parameters. Returns `None` when the call uses no named arguments.
+fn bind_named_args(
+    call: &Call,
+    env: &mut SlotEnv,
+    builder: &mut ModuleBuilder,
+    code: &mut Vec<Instr>,
+) -> Result<Option<Vec<Slot>>, CompileError> {
+    let named = call
+        .args
+        .iter()
+        .filter(|arg| {
+            !matches!(arg.key.as_str(), "args" | "out" | "outs") && !is_positional_arg_key(&arg.key)
+        })
+        .collect::<Vec<_>>();
+    if named.is_empty() {
+        return Ok(None);
+    }
+    if call
+        .args
+        .iter()
+        .any(|arg| arg.key == "args" || is_positional_arg_key(&arg.key))
+    {
+        return Err(CompileError::new(
+            call.line,
+            format!("{} mixes named and positional arguments", call.target),
+        ));
+    }
+    let params = builder.fn_params.get(&call.target).cloned().ok_or_else(|| {
+        CompileError::new(
+            call.line,
+            format!(
+                "named arguments need a function declared in this module or imported; '{}' is neither",
+                call.target
+            ),
+        )
+    })?;
+
+    if let Some(unknown) = named.iter().find(|arg| !params.contains(&arg.key)) {
+        return Err(CompileError::new(
+            call.line,
+            format!(
+                "{} has no parameter '{}' (expected {})",
+                call.target,
+                unknown.key,
+                params.join(", ")
+            ),
+        ));
+    }
+    let mut slots = Vec::with_capacity(params.len());
+    for param in &params {
+        let arg = named.iter().find(|arg| &arg.key == param).ok_or_else(|| {
+            CompileError::new(
+                call.line,
+                format!("{} missing argument '{param}'", call.target),
+            )
+        })?;
+        slots.push(resolve_atom_to_slot(
+            &arg.value, env, builder, code, call.line,
+        )?);
+    }
+    Ok(Some(slots))
+}
+
+fn parse_retshape(raw: &str) -> RetShape {
+    if raw.eq_ignore_ascii_case("scalar") {
+        return RetShape::Scalar;
+    }
+    if raw.eq_ignore_ascii_case("any") {
+        return RetShape::Any;
+    }
+    if let Some(inner) = raw
+        .strip_prefix("either(")
+        .and_then(|rest| rest.strip_suffix(')'))
+    {
         return RetShape::Either(parse_csv(inner));
     }
     if let Some(inner) = raw
@@ -838,26 +2343,45 @@ fn parse_retshape(raw: &str) -> RetShape {
     RetShape::Any
 }
 
+/// The `retcount=` of a `core::fn::begin` or lambda, 1 when missing.
+/// Out-of-range counts saturate.
+#[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+fn retcount_arg(call: &Call) -> u32 {
+    call.arg("retcount")
+        .and_then(atom_as_number)
+        .map_or(1, |count| count as u32)
+}
+
+/// A slot count or index as IR stores it, saturating past `u32::MAX`.
+fn slot_u32(count: usize) -> u32 {
+    u32::try_from(count).unwrap_or(u32::MAX)
+}
+
+/// A source line as stored in a function's line table, which saturates past
+/// `u32::MAX`.
+fn line_u32(line: usize) -> u32 {
+    u32::try_from(line).unwrap_or(u32::MAX)
+}
+
 fn get_string_arg(call: &Call, key: &str) -> Result<String, CompileError> {
     call.arg(key)
         .and_then(atom_as_str)
         .map(ToOwned::to_owned)
         .ok_or_else(|| {
-            CompileError::new(
+            let err = CompileError::new(
                 call.line,
                 format!("{} missing string arg {key}", call.target),
-            )
+            );
+            arg_error(err, call, key)
         })
 }
 
 fn get_ref_arg(call: &Call, key: &str) -> Result<RefPath, CompileError> {
-    match call.arg(key) {
-        Some(Atom::Ref(path)) => Ok(path.clone()),
-        _ => Err(CompileError::new(
-            call.line,
-            format!("{} missing ref arg {key}", call.target),
-        )),
+    if let Some(Atom::Ref(path)) = call.arg(key) {
+        return Ok(path.clone());
     }
+    let err = CompileError::new(call.line, format!("{} missing ref arg {key}", call.target));
+    Err(arg_error(err, call, key))
 }
 
 fn atom_as_str(atom: &Atom) -> Option<&str> {
@@ -876,33 +2400,59 @@ fn atom_as_number(atom: &Atom) -> Option<f64> {
     }
 }
 
-fn expand_macros(calls: &[Call]) -> Result<Vec<Call>, CompileError> {
+/// Expands `@safe` calls into a try/fallback sequence and
+/// `core::try::begin`/`finally`/`end` blocks into try, label and rethrow
+/// calls; `@safe` on a call that cannot throw is dropped with a warning.
+fn expand_macros(
+    calls: &[Call],
+    module_name: &str,
+    warnings: &mut Vec<CompileWarning>,
+) -> Result<Vec<Call>, CompileError> {
     let mut output = Vec::new();
     let mut safe_counter = 0usize;
+    let mut finally = FinallyBlocks::default();
 
     for call in calls {
+        match call.target.as_str() {
+            "core::try::begin" | "core::try::finally" | "core::try::end" => {
+                finally.expand(call, &mut output)?;
+                continue;
+            }
+            "core::fn::begin" | "core::fn::lambda" => finally.open.push(Vec::new()),
+            "core::fn::end" if finally.open.len() > 1 => finally.close_function()?,
+            "core::exit" if finally.in_body() => {
+                finally.exit(call, &mut output);
+                continue;
+            }
+            _ => finally.note_jumps(call),
+        }
         if !call.annos.iter().any(|anno| anno == ANNO_SAFE) {
             output.push(call.clone());
             continue;
         }
 
-        if call.target != "core::div" {
-            let mut cloned = call.clone();
-            cloned.annos.clear();
-            output.push(cloned);
+        let mut unwrapped = call.clone();
+        unwrapped.annos.retain(|anno| anno != ANNO_SAFE);
+        if !may_throw(&call.target) {
+            warnings.push(CompileWarning {
+                module: module_name.to_owned(),
+                line: call.line,
+                message: format!("@safe has no effect on {}, which never throws", call.target),
+            });
+            output.push(unwrapped);
             continue;
         }
 
-        let out_ref = call
-            .arg("out")
-            .and_then(|atom| {
-                if let Atom::Ref(path) = atom {
-                    Some(path.clone())
-                } else {
-                    None
-                }
-            })
-            .ok_or_else(|| CompileError::new(call.line, "@safe core::div requires out=<ref>"))?;
+        let out_ref = match call.arg("out") {
+            None => None,
+            Some(Atom::Ref(path)) => Some(path.clone()),
+            Some(_) => {
+                return Err(CompileError::new(
+                    call.line,
+                    format!("@safe {} requires out=<ref>", call.target),
+                ));
+            }
+        };
 
         let handler = format!("__safe_handler_{safe_counter}");
         let end = format!("__safe_end_{safe_counter}");
@@ -911,61 +2461,52 @@ fn expand_macros(calls: &[Call]) -> Result<Vec<Call>, CompileError> {
         output.push(Call {
             annos: Vec::new(),
             target: "core::try::push".to_owned(),
-            args: vec![imp_ast::Arg {
-                key: "handler".to_owned(),
-                value: Atom::Str(handler.clone()),
-            }],
+            args: vec![generated_arg(
+                "handler",
+                Atom::Str(handler.clone()),
+                call.span,
+            )],
             line: call.line,
+            span: call.span,
         });
 
-        let mut div = call.clone();
-        div.annos.clear();
-        output.push(div);
+        output.push(unwrapped);
 
         output.push(Call {
             annos: Vec::new(),
             target: "core::jump".to_owned(),
-            args: vec![imp_ast::Arg {
-                key: "target".to_owned(),
-                value: Atom::Str(end.clone()),
-            }],
+            args: vec![generated_arg("target", Atom::Str(end.clone()), call.span)],
             line: call.line,
+            span: call.span,
         });
 
         output.push(Call {
             annos: Vec::new(),
             target: "core::label".to_owned(),
-            args: vec![imp_ast::Arg {
-                key: "name".to_owned(),
-                value: Atom::Str(handler),
-            }],
+            args: vec![generated_arg("name", Atom::Str(handler), call.span)],
             line: call.line,
+            span: call.span,
         });
 
-        output.push(Call {
-            annos: Vec::new(),
-            target: "core::const".to_owned(),
-            args: vec![
-                imp_ast::Arg {
-                    key: "out".to_owned(),
-                    value: Atom::Ref(out_ref),
-                },
-                imp_ast::Arg {
-                    key: "value".to_owned(),
-                    value: Atom::Null,
-                },
-            ],
-            line: call.line,
-        });
+        if let Some(out_ref) = out_ref {
+            output.push(Call {
+                annos: Vec::new(),
+                target: "core::const".to_owned(),
+                args: vec![
+                    generated_arg("out", Atom::Ref(out_ref), call.span),
+                    generated_arg("value", Atom::Null, call.span),
+                ],
+                line: call.line,
+                span: call.span,
+            });
+        }
 
         output.push(Call {
             annos: Vec::new(),
             target: "core::label".to_owned(),
-            args: vec![imp_ast::Arg {
-                key: "name".to_owned(),
-                value: Atom::Str(end),
-            }],
+            args: vec![generated_arg("name", Atom::Str(end), call.span)],
             line: call.line,
+            span: call.span,
         });
 
         output.push(Call {
@@ -973,27 +2514,459 @@ fn expand_macros(calls: &[Call]) -> Result<Vec<Call>, CompileError> {
             target: "core::try::pop".to_owned(),
             args: Vec::new(),
             line: call.line,
+            span: call.span,
         });
     }
 
+    finally.close_function()?;
     Ok(output)
 }
 
-struct ModuleBuilder {
+/// Open `core::try::begin` blocks, per function and innermost last; the
+/// first entry is top level.
+struct FinallyBlocks {
+    open: Vec<Vec<FinallyBlock>>,
+    count: usize,
+}
+
+struct FinallyBlock {
+    id: usize,
+    line: usize,
+    in_cleanup: bool,
+    /// Whether a `core::exit` left the body, so `core::try::end` must exit
+    /// again once the cleanup has run.
+    exits: bool,
+    /// Labels defined in the body, and the label each jump or branch in the
+    /// body targets, with its line.
+    labels: HashSet<String>,
+    jumps: Vec<(String, usize)>,
+}
+
+impl Default for FinallyBlocks {
+    fn default() -> Self {
+        Self {
+            open: vec![Vec::new()],
+            count: 0,
+        }
+    }
+}
+
+impl FinallyBlocks {
+    /// Lowers one block marker. The body runs under a handler that saves the
+    /// error in `local::__finally_<id>`; both exits meet at the cleanup code,
+    /// and `core::try::end` rethrows the saved error, if any.
+    fn expand(&mut self, call: &Call, output: &mut Vec<Call>) -> Result<(), CompileError> {
+        let blocks = self.open.last_mut().expect("top level is always open");
+        let label =
+            |name: String| generated_call(call, "core::label", vec![("name", Atom::Str(name))]);
+
+        match call.target.as_str() {
+            "core::try::begin" => {
+                let id = self.count;
+                self.count += 1;
+                blocks.push(FinallyBlock {
+                    id,
+                    line: call.line,
+                    in_cleanup: false,
+                    exits: false,
+                    labels: HashSet::new(),
+                    jumps: Vec::new(),
+                });
+                output.push(generated_call(
+                    call,
+                    "core::const",
+                    vec![("out", finally_local("", id)), ("value", Atom::Null)],
+                ));
+                output.push(generated_call(
+                    call,
+                    "core::try::push",
+                    vec![("handler", Atom::Str(format!("__finally_catch_{id}")))],
+                ));
+            }
+            "core::try::finally" => {
+                let Some(block) = blocks.last_mut().filter(|block| !block.in_cleanup) else {
+                    return Err(CompileError::new(
+                        call.line,
+                        "core::try::finally without core::try::begin",
+                    ));
+                };
+                if let Some((target, line)) = block
+                    .jumps
+                    .iter()
+                    .find(|(target, _)| !block.labels.contains(target))
+                {
+                    return Err(CompileError::new(
+                        *line,
+                        format!(
+                            "jump to '{target}' leaves the core::try::begin body on line {}, which would skip its cleanup",
+                            block.line
+                        ),
+                    ));
+                }
+                block.in_cleanup = true;
+                let id = block.id;
+                output.push(generated_call(call, "core::try::pop", Vec::new()));
+                output.push(generated_call(
+                    call,
+                    "core::jump",
+                    vec![("target", Atom::Str(format!("__finally_run_{id}")))],
+                ));
+                output.push(label(format!("__finally_catch_{id}")));
+                output.push(generated_call(
+                    call,
+                    "core::mov",
+                    vec![
+                        (
+                            "from",
+                            Atom::Ref(RefPath {
+                                namespace: "err".to_owned(),
+                                name: "0".to_owned(),
+                            }),
+                        ),
+                        ("to", finally_local("", id)),
+                    ],
+                ));
+                output.push(label(format!("__finally_run_{id}")));
+            }
+            _ => self.end(call, output)?,
+        }
+        Ok(())
+    }
+
+    /// Lowers `core::try::end`: exits again if the body exited, rethrows
+    /// the saved error if it threw, and otherwise falls through.
+    fn end(&mut self, call: &Call, output: &mut Vec<Call>) -> Result<(), CompileError> {
+        let blocks = self.open.last_mut().expect("top level is always open");
+        let label =
+            |name: String| generated_call(call, "core::label", vec![("name", Atom::Str(name))]);
+        let Some(block) = blocks.pop().filter(|block| block.in_cleanup) else {
+            return Err(CompileError::new(
+                call.line,
+                "core::try::end without core::try::finally",
+            ));
+        };
+        let id = block.id;
+        if block.exits {
+            // An exit from the body left `true` where an error would be.
+            output.push(generated_call(
+                call,
+                "core::is::bool",
+                vec![
+                    ("value", finally_local("", id)),
+                    ("out", finally_local("exiting_", id)),
+                ],
+            ));
+            output.push(generated_call(
+                call,
+                "core::br",
+                vec![
+                    ("cond", finally_local("exiting_", id)),
+                    ("then", Atom::Str(format!("__finally_exit_{id}"))),
+                    ("else", Atom::Str(format!("__finally_check_{id}"))),
+                ],
+            ));
+            output.push(label(format!("__finally_exit_{id}")));
+            self.exit(call, output);
+            output.push(label(format!("__finally_check_{id}")));
+        }
+        output.push(generated_call(
+            call,
+            "core::br",
+            vec![
+                ("cond", finally_local("", id)),
+                ("then", Atom::Str(format!("__finally_rethrow_{id}"))),
+                ("else", Atom::Str(format!("__finally_done_{id}"))),
+            ],
+        ));
+        output.push(label(format!("__finally_rethrow_{id}")));
+        output.push(generated_call(
+            call,
+            "core::rethrow",
+            vec![("err", finally_local("", id))],
+        ));
+        output.push(label(format!("__finally_done_{id}")));
+        Ok(())
+    }
+
+    /// Whether the innermost open block is still in its body.
+    fn in_body(&self) -> bool {
+        self.open
+            .last()
+            .and_then(|blocks| blocks.last())
+            .is_some_and(|block| !block.in_cleanup)
+    }
+
+    /// Lowers `core::exit`. Inside a block's body it marks the block as
+    /// exiting and runs its cleanup first; `core::try::end` then exits
+    /// again, through any enclosing block's cleanup in turn.
+    fn exit(&mut self, call: &Call, output: &mut Vec<Call>) {
+        let blocks = self.open.last_mut().expect("top level is always open");
+        let Some(block) = blocks.last_mut().filter(|block| !block.in_cleanup) else {
+            output.push(generated_call(call, "core::exit", Vec::new()));
+            return;
+        };
+        block.exits = true;
+        let id = block.id;
+        output.push(generated_call(
+            call,
+            "core::const",
+            vec![("out", finally_local("", id)), ("value", Atom::Bool(true))],
+        ));
+        output.push(generated_call(call, "core::try::pop", Vec::new()));
+        output.push(generated_call(
+            call,
+            "core::jump",
+            vec![("target", Atom::Str(format!("__finally_run_{id}")))],
+        ));
+    }
+
+    /// Records the labels and jump targets of a source call for every block
+    /// whose body it sits in, so `core::try::finally` can reject jumps out.
+    fn note_jumps(&mut self, call: &Call) {
+        let keys: &[&str] = match call.target.as_str() {
+            "core::label" => &["name"],
+            "core::jump" => &["target"],
+            "core::br" => &["then", "else"],
+            _ => return,
+        };
+        let blocks = self.open.last_mut().expect("top level is always open");
+        for name in keys
+            .iter()
+            .filter_map(|key| call.arg(key).and_then(atom_as_str))
+        {
+            for block in blocks.iter_mut().filter(|block| !block.in_cleanup) {
+                if call.target == "core::label" {
+                    block.labels.insert(name.to_owned());
+                } else {
+                    block.jumps.push((name.to_owned(), call.line));
+                }
+            }
+        }
+    }
+
+    /// Checks that the innermost function body, or top level once every
+    /// function is closed, closed every block it opened.
+    fn close_function(&mut self) -> Result<(), CompileError> {
+        match self.open.pop().unwrap_or_default().first() {
+            Some(block) => Err(CompileError::new(
+                block.line,
+                "core::try::begin is never closed by core::try::end",
+            )),
+            None => Ok(()),
+        }
+    }
+}
+
+/// The hidden local `__finally_<prefix><id>` of block `id`.
+fn finally_local(prefix: &str, id: usize) -> Atom {
+    Atom::Ref(RefPath {
+        namespace: "local".to_owned(),
+        name: format!("__finally_{prefix}{id}"),
+    })
+}
+
+/// A call the compiler generates in place of `call`, with `args`.
+fn generated_call(call: &Call, target: &str, args: Vec<(&str, Atom)>) -> Call {
+    Call {
+        annos: Vec::new(),
+        target: target.to_owned(),
+        args: args
+            .into_iter()
+            .map(|(key, value)| generated_arg(key, value, call.span))
+            .collect(),
+        line: call.line,
+        span: call.span,
+    }
+}
+
+/// An argument the compiler adds to a call it generates, placed at `span`
+/// of the source call it came from.
+fn generated_arg(key: &str, value: Atom, span: Span) -> imp_ast::Arg {
+    imp_ast::Arg {
+        key: key.to_owned(),
+        value,
+        span,
+        value_span: span,
+    }
+}
+
+/// Whether `target` can throw a catchable error. Non-`core::*` targets are
+/// invokes or plugin lowerings, either of which may throw.
+fn may_throw(target: &str) -> bool {
+    !target.starts_with("core::")
+        || matches!(
+            target,
+            "core::add"
+                | "core::sub"
+                | "core::mul"
+                | "core::div"
+                | "core::mod"
+                | "core::pow"
+                | "core::neg"
+                | "core::int::parse"
+                | "core::num::parse"
+                | "core::num::format"
+                | "core::rethrow"
+                | "core::invoke"
+                | "core::invoke::dynamic"
+                | "core::obj::get"
+                | "core::obj::set"
+                | "core::obj::has"
+                | "core::obj::keys"
+                | "core::obj::del"
+                | "core::obj::len"
+                | "core::obj::getpath"
+                | "core::obj::setpath"
+                | "core::str::format"
+                | "core::str::sub"
+                | "core::str::join"
+                | "core::bytes::to_str"
+                | "core::bytes::slice"
+                | "core::host::call"
+        )
+}
+
+/// Rewrites pure core ops whose inputs are all literal atoms into a single
+/// `core::const` holding the result, e.g. `core::str::concat a="ab" b="cd"`.
+/// Annotated calls and anything the VM could reject are left alone.
+fn fold_const_calls(calls: Vec<Call>) -> Vec<Call> {
+    calls
+        .into_iter()
+        .map(|call| {
+            let folded = if call.annos.is_empty() {
+                eval_const_call(&call)
+            } else {
+                None
+            };
+            let (Some(value), Some(out @ Atom::Ref(_))) = (folded, call.arg("out")) else {
+                return call;
+            };
+            Call {
+                annos: Vec::new(),
+                target: "core::const".to_owned(),
+                args: vec![
+                    generated_arg("out", out.clone(), call.span),
+                    generated_arg("value", value, call.span),
+                ],
+                line: call.line,
+                span: call.span,
+            }
+        })
+        .collect()
+}
+
+fn eval_const_call(call: &Call) -> Option<Atom> {
+    let literal = |key: &str| call.arg(key).filter(|atom| !matches!(atom, Atom::Ref(_)));
+    match call.target.as_str() {
+        "core::str::concat" => Some(Atom::Str(format!(
+            "{}{}",
+            literal_text(literal("a")?)?,
+            literal_text(literal("b")?)?
+        ))),
+        "core::str::len" => Some(Atom::Num(f64::from(
+            u32::try_from(literal_text(literal("value")?)?.chars().count()).ok()?,
+        ))),
+        "core::str::upper" => Some(Atom::Str(literal_text(literal("value")?)?.to_uppercase())),
+        "core::str::lower" => Some(Atom::Str(literal_text(literal("value")?)?.to_lowercase())),
+        "core::str::trim" => Some(Atom::Str(
+            literal_text(literal("value")?)?.trim().to_owned(),
+        )),
+        "core::str::trim_start" => Some(Atom::Str(
+            literal_text(literal("value")?)?.trim_start().to_owned(),
+        )),
+        "core::str::trim_end" => Some(Atom::Str(
+            literal_text(literal("value")?)?.trim_end().to_owned(),
+        )),
+        "core::type_of" => Some(Atom::Str(
+            literal_kind(literal("value")?)?.as_str().to_owned(),
+        )),
+        target if target.starts_with("core::is::") => {
+            let kind = ValueKind::parse(&target["core::is::".len()..])?;
+            Some(Atom::Bool(literal_kind(literal("value")?)? == kind))
+        }
+        "core::cmp" => {
+            let ordering = literal_cmp(literal("value_a")?, literal("value_b")?);
+            Some(Atom::Num(f64::from(ordering as i8)))
+        }
+        _ => None,
+    }
+}
+
+/// Text the VM would produce for a literal in string ops.
+fn literal_text(atom: &Atom) -> Option<String> {
+    match atom {
+        Atom::Null => Some("null".to_owned()),
+        Atom::Bool(value) => Some(value.to_string()),
+        Atom::Num(value) => Some(value.to_string()),
+        Atom::Str(value) => Some(value.clone()),
+        Atom::Ref(_) => None,
+    }
+}
+
+/// The kind of value a literal loads as.
+fn literal_kind(atom: &Atom) -> Option<ValueKind> {
+    match atom {
+        Atom::Null => Some(ValueKind::Null),
+        Atom::Bool(_) => Some(ValueKind::Bool),
+        Atom::Num(_) => Some(ValueKind::Num),
+        Atom::Str(_) => Some(ValueKind::Str),
+        Atom::Ref(_) => None,
+    }
+}
+
+/// `core::cmp`'s total order restricted to literals: null < bool < num < str.
+fn literal_cmp(a: &Atom, b: &Atom) -> std::cmp::Ordering {
+    let rank = |atom: &Atom| match atom {
+        Atom::Null => 0,
+        Atom::Bool(_) => 1,
+        Atom::Num(_) => 2,
+        Atom::Str(_) | Atom::Ref(_) => 3,
+    };
+    match (a, b) {
+        (Atom::Bool(a), Atom::Bool(b)) => a.cmp(b),
+        (Atom::Num(a), Atom::Num(b)) => match (a.is_nan(), b.is_nan()) {
+            (false, false) => a.partial_cmp(b).unwrap_or(std::cmp::Ordering::Equal),
+            (a_nan, b_nan) => a_nan.cmp(&b_nan),
+        },
+        (Atom::Str(a), Atom::Str(b)) => a.cmp(b),
+        _ => rank(a).cmp(&rank(b)),
+    }
+}
+
+struct ModuleBuilder<'p> {
     module_name: String,
     globals: HashMap<String, u32>,
     next_global: u32,
+    /// Parameter names of statically known callees, keyed by `namespace::name`.
+    fn_params: HashMap<String, Vec<String>>,
+    /// Foldable module constants, keyed by `mod::NAME` or `alias::NAME`.
+    consts: HashMap<String, ConstValue>,
+    /// Source file path recorded in each function's `DebugInfo`.
+    source: Option<Arc<str>>,
+    opts: &'p CompileOpts,
+    /// Errors so far, in the order they were found.
+    errors: Vec<CompileError>,
 }
 
-impl ModuleBuilder {
-    fn new(module_name: String) -> Self {
+impl<'p> ModuleBuilder<'p> {
+    fn new(module_name: String, opts: &'p CompileOpts) -> Self {
         Self {
             module_name,
             globals: HashMap::new(),
             next_global: 0,
+            fn_params: HashMap::new(),
+            consts: HashMap::new(),
+            source: None,
+            opts,
+            errors: Vec::new(),
         }
     }
 
+    /// The first recorded error, for callers that stop at one.
+    fn first_error(&self) -> Result<(), CompileError> {
+        self.errors.first().cloned().map_or(Ok(()), Err)
+    }
+
     fn resolve_global(&mut self, namespace: &str, name: &str) -> u32 {
         let key = format!("{namespace}::{name}");
         if let Some(existing) = self.globals.get(&key) {
@@ -1004,6 +2977,15 @@ impl ModuleBuilder {
         self.globals.insert(key, slot);
         slot
     }
+
+    /// `namespace::name` of every global, indexed by slot.
+    fn global_names(&self) -> Vec<String> {
+        let mut names = vec![String::new(); self.next_global as usize];
+        for (name, slot) in &self.globals {
+            names[*slot as usize].clone_from(name);
+        }
+        names
+    }
 }
 
 struct SlotEnv {
@@ -1014,13 +2996,16 @@ struct SlotEnv {
     next_local: u32,
     next_err: u32,
     temp_counter: u32,
+    /// Resolve `local::` refs to `local::NAME` globals, so they outlive the
+    /// function (top-level chunks of an incremental session).
+    locals_as_globals: bool,
 }
 
 impl SlotEnv {
     fn new(args: Vec<String>, ret_count: u32) -> Self {
         let mut args_map = HashMap::new();
         for (index, name) in args.into_iter().enumerate() {
-            args_map.insert(name, index as u32);
+            args_map.insert(name, slot_u32(index));
         }
 
         let mut returns = HashMap::new();
@@ -1037,9 +3022,20 @@ impl SlotEnv {
             next_local: 0,
             next_err: 0,
             temp_counter: 0,
+            locals_as_globals: false,
         }
     }
 
+    /// Parameter names in positional order.
+    fn params(&self) -> Vec<Arc<str>> {
+        let mut params = self.args.iter().collect::<Vec<_>>();
+        params.sort_by_key(|(_, index)| **index);
+        params
+            .into_iter()
+            .map(|(name, _)| Arc::from(name.as_str()))
+            .collect()
+    }
+
     fn resolve_local(&mut self, name: &str) -> Slot {
         if let Some(slot) = self.locals.get(name) {
             return Slot::Local(*slot);
@@ -1058,12 +3054,15 @@ impl SlotEnv {
 
     fn resolve_ref(&mut self, path: &RefPath, builder: &mut ModuleBuilder) -> Slot {
         match path.namespace.as_str() {
+            "local" if self.locals_as_globals => {
+                Slot::Global(builder.resolve_global("local", &path.name))
+            }
             "local" => self.resolve_local(&path.name),
             "arg" => {
                 let slot = if let Some(slot) = self.args.get(&path.name) {
                     *slot
                 } else {
-                    let index = self.args.len() as u32;
+                    let index = slot_u32(self.args.len());
                     self.args.insert(path.name.clone(), index);
                     index
                 };
@@ -1073,7 +3072,7 @@ impl SlotEnv {
                 let slot = if let Some(slot) = self.returns.get(&path.name) {
                     *slot
                 } else {
-                    let index = self.returns.len() as u32;
+                    let index = slot_u32(self.returns.len());
                     self.returns.insert(path.name.clone(), index);
                     index
                 };
@@ -1098,23 +3097,43 @@ mod tests {
 
     #[test]
     fn compile_basic_add_program() {
-        let src = r#"
+        let src = r"
 #call core::const out=local::x value=2;
 #call core::const out=local::y value=3;
 #call core::add a=local::x b=local::y out=return::value;
 #call core::exit;
-"#;
+";
         let compiled = compile_program(src, CompileOpts::default()).expect("compile");
         let init = compiled.module.function(0).expect("init");
         assert!(!init.code.is_empty());
     }
 
+    #[test]
+    fn every_listed_core_target_is_lowered() {
+        for target in imp_std::CORE_TARGETS {
+            let report =
+                compile_program_diagnostics(&format!("#call {target};\n"), &CompileOpts::default());
+            for err in &report.diagnostics.errors {
+                assert!(
+                    !err.message.contains("unsupported core target"),
+                    "{target}: {err}"
+                );
+            }
+        }
+        let report = compile_program_diagnostics("#call core::nope;\n", &CompileOpts::default());
+        assert!(
+            report.diagnostics.errors[0]
+                .message
+                .contains("unsupported core target")
+        );
+    }
+
     #[test]
     fn safe_anno_expands() {
-        let src = r#"
+        let src = r"
 #call @safe core::div a=local::a b=local::b out=local::c;
 #call core::exit;
-"#;
+";
         let compiled = compile_program(src, CompileOpts::default()).expect("compile");
         let init = compiled.module.function(0).expect("init");
         assert!(
@@ -1124,6 +3143,45 @@ mod tests {
         );
     }
 
+    #[test]
+    fn safe_wraps_any_throwing_call_and_warns_otherwise() {
+        let src = r#"
+#call core::fn::begin name=main::risky args=[] retshape="scalar";
+#call core::throw code="boom" msg="no";
+#call core::fn::end;
+#call @safe main::risky out=local::r;
+#call @safe core::str::format tmpl="{x}" args=local::r out=local::s;
+#call @safe core::str::len value=local::s out=local::n;
+#call core::exit;
+"#;
+        let compiled = compile_program(src, CompileOpts::default()).expect("compile");
+        let init = compiled.module.function(0).expect("init");
+        // The guarded call is the last instruction before the try's jump.
+        let guarded = init
+            .code
+            .windows(2)
+            .filter(|pair| matches!(pair[1], Instr::Jump { .. }))
+            .map(|pair| &pair[0])
+            .collect::<Vec<_>>();
+        assert!(matches!(
+            guarded[..],
+            [Instr::Invoke { .. }, Instr::StrFormat { .. }]
+        ));
+        assert!(
+            init.code
+                .iter()
+                .any(|instr| matches!(instr, Instr::StrLen { .. }))
+        );
+        assert_eq!(
+            compiled.warnings,
+            [CompileWarning {
+                module: "main".to_owned(),
+                line: 7,
+                message: "@safe has no effect on core::str::len, which never throws".to_owned(),
+            }]
+        );
+    }
+
     #[test]
     fn labels_are_patched_to_pc() {
         let src = r#"
@@ -1185,6 +3243,526 @@ mod tests {
         assert!(!module.imports.is_empty());
     }
 
+    #[test]
+    fn prune_exports_drops_unreachable_functions() {
+        let root = std::env::temp_dir().join("imp_compiler_prune_test");
+        let _ = std::fs::create_dir_all(&root);
+        let dep = root.join("dep.imp");
+        let main = root.join("main.imp");
+        std::fs::write(
+            &dep,
+            "#call core::fn::begin name=mod::double args=\"x\" retshape=\"scalar\";\n\
+             #call core::add a=arg::x b=arg::x out=return::value;\n\
+             #call core::exit;\n\
+             #call core::fn::end;\n\
+             #call core::fn::begin name=mod::triple args=\"x\" retshape=\"scalar\";\n\
+             #call core::add a=arg::x b=arg::x out=local::two;\n\
+             #call core::add a=local::two b=arg::x out=return::value;\n\
+             #call core::exit;\n\
+             #call core::fn::end;\n\
+             #call core::mod::export name=\"double\" value=mod::double;\n\
+             #call core::mod::export name=\"triple\" value=mod::triple;\n\
+             #call core::exit;\n",
+        )
+        .expect("write dep");
+        std::fs::write(
+            &main,
+            "#call core::import alias=\"dep\" path=\"dep.imp\";\n\
+             #call core::fn::begin name=mod::main args=\"\" retshape=\"scalar\";\n\
+             #call core::const out=local::x value=4;\n\
+             #call core::invoke fn=dep::double args=\"local::x\" out=return::value;\n\
+             #call core::exit;\n\
+             #call core::fn::end;\n\
+             #call core::fn::begin name=mod::unused args=\"\" retshape=\"scalar\";\n\
+             #call core::invoke fn=dep::triple args=\"\" out=return::value;\n\
+             #call core::exit;\n\
+             #call core::fn::end;\n\
+             #call core::mod::export name=\"main\" value=mod::main;\n\
+             #call core::mod::export name=\"unused\" value=mod::unused;\n\
+             #call core::exit;\n",
+        )
+        .expect("write main");
+
+        let module = compile_module(&main, &FsModuleLoader).expect("compile");
+        assert_eq!(module.functions.len(), 3);
+        assert_eq!(module.imports[0].module.functions.len(), 3);
+
+        let pruned = prune_exports(&module, &["main".to_owned()]).expect("prune");
+        let names = |module: &CompiledModule| {
+            module
+                .exports
+                .iter()
+                .map(|(name, _)| name.clone())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(pruned.functions.len(), 2);
+        assert_eq!(names(&pruned), ["main"]);
+        let dep = &pruned.imports[0];
+        assert_eq!(dep.module.functions.len(), 2);
+        assert_eq!(names(&dep.module), ["double"]);
+        assert_eq!(dep.export_to_global.len(), 1);
+        assert!(pruned.function(pruned.init_func).is_some());
+
+        let err = prune_exports(&module, &["missing".to_owned()]).expect_err("unknown export");
+        assert!(err.message.contains("missing"), "{}", err.message);
+    }
+
+    #[test]
+    fn import_errors_name_the_import_chain() {
+        let root = std::env::temp_dir().join("imp_compiler_import_error_test");
+        let _ = std::fs::create_dir_all(&root);
+        let leaf = root.join("leaf.imp");
+        let mid = root.join("mid.imp");
+        let main = root.join("main.imp");
+
+        std::fs::write(&leaf, "#call core::exit;\n#call core::bogus;\n").expect("write leaf");
+        std::fs::write(
+            &mid,
+            "#call core::import alias=\"leaf\" path=\"leaf.imp\";\n",
+        )
+        .expect("write mid");
+        std::fs::write(
+            &main,
+            "#call core::import alias=\"mid\" path=\"mid.imp\";\n",
+        )
+        .expect("write main");
+
+        let err = compile_module(&main, &FsModuleLoader).expect_err("leaf fails");
+        assert_eq!(err.line, 2);
+        assert_eq!(err.notes.len(), 2);
+        assert!(err.notes[0].starts_with("while compiling import 'leaf'"));
+        assert!(err.notes[1].starts_with("while compiling import 'mid'"));
+    }
+
+    #[test]
+    fn pinned_imports_verify_their_sha256() {
+        let root = std::env::temp_dir().join("imp_compiler_pinned_import_test");
+        let _ = std::fs::create_dir_all(&root);
+        let dep = root.join("dep.imp");
+        let main = root.join("main.imp");
+        std::fs::write(&dep, "#call core::exit;\n").expect("write dep");
+
+        let pin = |sha: &str| {
+            std::fs::write(
+                &main,
+                format!("#call core::import alias=\"dep\" path=\"dep.imp\" sha256=\"{sha}\";\n"),
+            )
+            .expect("write main");
+            compile_module(&main, &FsModuleLoader)
+        };
+
+        let good = "b1014e79997653e946c7af3f7f421ddff0ad680132256735814ffa5e4def2e52";
+        pin(good).expect("matching pin");
+        pin(&good.to_ascii_uppercase()).expect("hex case is ignored");
+
+        let stale = good.replace('9', "8");
+        let err = pin(&stale).expect_err("mismatch");
+        assert_eq!(err.line, 1);
+        assert!(
+            err.message
+                .contains(&format!("expected {stale}, found {good}"))
+        );
+
+        let err = pin("abc").expect_err("malformed pin");
+        assert!(err.message.contains("must be a 64-digit hex string"));
+    }
+
+    #[test]
+    fn deeply_nested_blocks_are_an_error() {
+        let depth = MAX_BLOCK_DEPTH + 1;
+        let src = format!(
+            "{}{}",
+            "#call core::fn::lambda out=local::f;\n".repeat(depth),
+            "#call core::fn::end;\n".repeat(depth)
+        );
+        let err = compile_program(&src, CompileOpts::default()).expect_err("too deep");
+        assert_eq!(
+            err.to_string(),
+            format!("line {depth}: blocks nest deeper than {MAX_BLOCK_DEPTH}")
+        );
+        fuzz_compile(src.as_bytes());
+    }
+
+    #[test]
+    fn invoke_outs_are_checked() {
+        let error = |src: &str| {
+            compile_program(src, CompileOpts::default())
+                .expect_err("bad outs")
+                .to_string()
+        };
+        assert_eq!(
+            error("#call core::invoke fn=local::f out=local::a outs=\"local::b\";\n"),
+            "line 1: core::invoke takes out or outs, not both"
+        );
+        assert_eq!(
+            error("#call core::invoke fn=local::f outs=\"\";\n"),
+            "line 1: core::invoke outs must list at least one ref"
+        );
+        assert_eq!(
+            error("#call core::invoke fn=local::f outs=\"local::a,7\";\n"),
+            "line 1: invalid invoke out ref '7'"
+        );
+    }
+
+    #[test]
+    fn unbalanced_finally_blocks_are_errors() {
+        let error = |src: &str| {
+            compile_program(src, CompileOpts::default())
+                .expect_err("unbalanced")
+                .to_string()
+        };
+        assert_eq!(
+            error("#call core::try::finally;\n"),
+            "line 1: core::try::finally without core::try::begin"
+        );
+        assert_eq!(
+            error("#call core::try::begin;\n#call core::try::end;\n"),
+            "line 2: core::try::end without core::try::finally"
+        );
+        assert_eq!(
+            error(
+                "#call core::fn::begin name=main::f args=\"\" retshape=\"any\";\n\
+                 #call core::try::begin;\n\
+                 #call core::fn::end;\n\
+                 #call core::try::finally;\n\
+                 #call core::try::end;\n"
+            ),
+            "line 2: core::try::begin is never closed by core::try::end"
+        );
+        assert_eq!(
+            error("#call core::try::begin;\n"),
+            "line 1: core::try::begin is never closed by core::try::end"
+        );
+        assert_eq!(
+            error(
+                "#call core::try::begin;\n\
+                 #call core::label name=\"inside\";\n\
+                 #call core::jump target=\"inside\";\n\
+                 #call core::br cond=local::x then=\"inside\" else=\"after\";\n\
+                 #call core::try::finally;\n\
+                 #call core::try::end;\n\
+                 #call core::label name=\"after\";\n"
+            ),
+            "line 4: jump to 'after' leaves the core::try::begin body on line 1, which would skip its cleanup"
+        );
+    }
+
+    #[test]
+    fn rejects_reserved_and_colliding_namespaces() {
+        let error = |src: &str| {
+            compile_program(src, CompileOpts::default())
+                .expect_err("collision")
+                .to_string()
+        };
+        assert_eq!(
+            error("#call core::import alias=\"local\" path=\"x.imp\";"),
+            "line 1: import alias 'local' is a reserved namespace"
+        );
+        assert_eq!(
+            error(
+                "#call core::import alias=\"dep\" path=\"a.imp\";\n\
+                 #call core::import alias=\"dep\" path=\"b.imp\";"
+            ),
+            "line 2: import alias 'dep' is already used by the import at line 1"
+        );
+        assert_eq!(
+            error(
+                "#call core::fn::begin name=util::f args=\"\" retshape=\"scalar\";\n\
+                 #call core::exit;\n\
+                 #call core::fn::end;\n\
+                 #call core::import alias=\"util\" path=\"util.imp\";"
+            ),
+            "line 4: import alias 'util' collides with a function namespace"
+        );
+        assert_eq!(
+            error("#call core::fn::begin name=err::f args=\"\";\n#call core::fn::end;"),
+            "line 1: function 'err::f' uses reserved namespace 'err'"
+        );
+    }
+
+    #[test]
+    fn test_annotation_lists_functions_in_module_tests() {
+        let module = compile_program(
+            "#call core::fn::begin name=main::helper args=\"x\";\n\
+             #call core::exit;\n\
+             #call core::fn::end;\n\
+             #call @test core::fn::begin name=main::first args=\"\";\n\
+             #call core::exit;\n\
+             #call core::fn::end;\n\
+             #call @test core::fn::begin name=main::second;\n\
+             #call core::exit;\n\
+             #call core::fn::end;\n\
+             #call core::exit;\n",
+            CompileOpts::default(),
+        )
+        .expect("compile")
+        .module;
+        let names = module
+            .tests
+            .iter()
+            .map(|id| module.function(*id).expect("test fn").meta.name.to_string())
+            .collect::<Vec<_>>();
+        assert_eq!(names, ["main::first", "main::second"]);
+
+        let err = compile_program(
+            "#call @test core::fn::begin name=main::t args=\"x\";\n#call core::fn::end;",
+            CompileOpts::default(),
+        )
+        .expect_err("test with args");
+        assert_eq!(
+            err.to_string(),
+            "line 1: @test function 'main::t' must take no arguments"
+        );
+    }
+
+    #[test]
+    fn compile_errors_point_at_the_offending_argument() {
+        let src =
+            "#call core::const out=local::a value=1;\n#call core::mov from=local::a to=\"x\";\n";
+        let err = compile_program(src, CompileOpts::default()).expect_err("non-ref to");
+        let span = err.span.expect("span");
+        assert_eq!(&src[span.start..span.end], "\"x\"");
+        assert_eq!((span.line, span.column), (2, 34));
+
+        let src = "#call core::exit;\n#call core::bogus_op;\n";
+        let err = compile_program(src, CompileOpts::default()).expect_err("unknown op");
+        let span = err.span.expect("span");
+        assert_eq!(&src[span.start..span.end], "#call core::bogus_op");
+    }
+
+    #[test]
+    fn diagnostics_report_every_error_and_stub_failed_functions() {
+        let src = r#"
+#call core::fn::begin name=main::f args="" retshape="scalar";
+#call core::bogus_op;
+#call core::jump target="nowhere";
+#call core::exit;
+#call core::fn::end;
+#call core::fn::begin name=main::g args="" retshape="scalar";
+#call core::const out=return::value value=1;
+#call core::exit;
+#call core::fn::end;
+#call core::mov from=local::a to="x";
+#call core::exit;
+"#;
+        let first = compile_program(src, CompileOpts::default()).expect_err("bad body");
+        let report = compile_program_diagnostics(src, &CompileOpts::default());
+        let errors = &report.diagnostics.errors;
+        let lines = errors.iter().map(|err| err.line).collect::<Vec<_>>();
+        assert_eq!(lines, vec![2, 3, 11]);
+        // The unknown label is reported at the function header.
+        assert_eq!(errors[1].message, first.message);
+
+        let module = report.module.expect("partial module");
+        let stub = |func: usize| match &module.functions[func].code[..] {
+            [Instr::Throw { code, msg, .. }] => (code.clone(), msg.clone()),
+            other => panic!("expected a throwing stub, got {other:?}"),
+        };
+        assert_eq!(
+            stub(0),
+            ("compile_error".to_owned(), errors[2].message.clone())
+        );
+        assert_eq!(stub(1).1, first.message);
+        assert!(matches!(
+            &module.functions[2].code[..],
+            [Instr::StoreConst { .. }, Instr::Exit]
+        ));
+
+        let src = "#call core::bogus_op;\nnot a call;\n#call core::exit;\n";
+        let report = compile_program_diagnostics(src, &CompileOpts::default());
+        assert!(report.module.is_none());
+        assert_eq!(report.diagnostics.errors.len(), 2);
+    }
+
+    #[test]
+    fn compile_limits_reject_oversized_modules() {
+        let src = r#"
+#call core::fn::begin name=main::f args="" retshape="scalar";
+#call core::const out=local::a value=1;
+#call core::const out=local::b value=2;
+#call core::add a=local::a b=local::b out=return::value;
+#call core::exit;
+#call core::fn::end;
+#call core::fn::begin name=main::g args="" retshape="scalar";
+#call core::exit;
+#call core::fn::end;
+#call core::exit;
+"#;
+        let error = |limits: CompileLimits| {
+            let opts = CompileOpts {
+                limits,
+                ..CompileOpts::default()
+            };
+            compile_program(src, opts)
+                .map(|_| ())
+                .map_err(|err| err.to_string())
+        };
+        assert_eq!(
+            error(CompileLimits {
+                max_instructions: Some(3),
+                ..CompileLimits::default()
+            }),
+            Err("line 2: main::f compiles to 4 instructions, over the limit of 3 (max_instructions)"
+                .to_owned())
+        );
+        assert_eq!(
+            error(CompileLimits {
+                max_locals: Some(1),
+                ..CompileLimits::default()
+            }),
+            Err("line 2: main::f uses 2 local slots, over the limit of 1 (max_locals)".to_owned())
+        );
+        assert_eq!(
+            error(CompileLimits {
+                max_functions: Some(1),
+                ..CompileLimits::default()
+            }),
+            Err(
+                "line 8: module declares 2 functions, over the limit of 1 (max_functions)"
+                    .to_owned()
+            )
+        );
+        assert_eq!(
+            error(CompileLimits {
+                max_instructions: Some(4),
+                max_locals: Some(2),
+                max_functions: Some(2),
+            }),
+            Ok(())
+        );
+    }
+
+    #[test]
+    fn compile_output_lists_transitive_dependencies() {
+        let root = std::env::temp_dir().join("imp_compiler_deps_test");
+        let _ = std::fs::create_dir_all(root.join("lib"));
+        let shared = root.join("lib/shared.imp");
+        let left = root.join("lib/left.imp");
+        let main = root.join("main.imp");
+
+        std::fs::write(&shared, "#call core::exit;\n").expect("write shared");
+        std::fs::write(
+            &left,
+            "#call core::import alias=\"shared\" path=\"shared.imp\";\n#call core::exit;\n",
+        )
+        .expect("write left");
+        std::fs::write(
+            &main,
+            "#call core::import alias=\"left\" path=\"lib/left.imp\";\n\
+             #call core::import alias=\"shared\" path=\"lib/shared.imp\";\n\
+             #call core::exit;\n",
+        )
+        .expect("write main");
+
+        let output = compile_module_with_deps(&main, &FsModuleLoader).expect("compile");
+        let mut expected = [&main, &left, &shared]
+            .map(|path| path.canonicalize().expect("canonicalize"))
+            .to_vec();
+        expected.sort();
+        assert_eq!(output.dependencies, expected);
+        assert_eq!(output.module.imports.len(), 2);
+    }
+
+    #[test]
+    fn binds_named_args_to_parameter_positions() {
+        let header = r#"
+#call core::fn::begin name=main::make_user args="name,age" retshape="scalar";
+#call core::exit;
+#call core::fn::end;
+#call core::const out=local::n value="ada";
+"#;
+        let compiled = compile_program(
+            &format!("{header}#call main::make_user age=36 name=local::n out=local::u;\n"),
+            CompileOpts::default(),
+        )
+        .expect("compile");
+        let init = compiled.module.function(0).expect("init");
+        let Some(Instr::Invoke { args, .. }) = init
+            .code
+            .iter()
+            .find(|instr| matches!(instr, Instr::Invoke { .. }))
+        else {
+            panic!("expected invoke");
+        };
+        let Some(Instr::StoreConst {
+            slot: name_slot, ..
+        }) = init.code.first()
+        else {
+            panic!("expected const");
+        };
+        assert_eq!(args.len(), 2);
+        assert_eq!(args[0], *name_slot);
+        assert_ne!(args[1], *name_slot);
+        assert_eq!(
+            compiled.module.function(1).expect("make_user").meta.params,
+            vec![Arc::<str>::from("name"), Arc::<str>::from("age")]
+        );
+
+        for (call, expected) in [
+            ("main::make_user name=local::n", "missing argument 'age'"),
+            (
+                "main::make_user name=local::n age=1 email=local::n",
+                "has no parameter 'email'",
+            ),
+            (
+                "main::make_user args=\"local::n\" age=1",
+                "mixes named and positional",
+            ),
+            ("local::f name=local::n", "named arguments need a function"),
+        ] {
+            let err = compile_program(&format!("{header}#call {call};\n"), CompileOpts::default())
+                .expect_err("named arg error");
+            assert!(err.message.contains(expected), "{call}: {}", err.message);
+        }
+    }
+
+    #[test]
+    fn module_consts_fold_into_importers() {
+        let root = std::env::temp_dir().join("imp_compiler_mod_const_test");
+        let _ = std::fs::create_dir_all(&root);
+        let lib = root.join("consts.imp");
+        let main = root.join("main.imp");
+        std::fs::write(
+            &lib,
+            "#call core::mod::const name=\"PI\" value=3.5;\n#call core::exit;\n",
+        )
+        .expect("write lib");
+        std::fs::write(
+            &main,
+            "#call core::import alias=\"c\" path=\"consts.imp\";\n\
+             #call core::add a=c::PI b=c::PI out=return::value;\n\
+             #call core::exit;\n",
+        )
+        .expect("write main");
+
+        let module = compile_module(&main, &FsModuleLoader).expect("compile");
+        let lib_module = &module.imports[0].module;
+        assert_eq!(
+            lib_module.consts,
+            vec![("PI".to_owned(), ConstValue::Num(3.5))]
+        );
+        assert_eq!(lib_module.global_count, 0);
+        assert_eq!(module.global_count, 0);
+        let init = module.function(0).expect("init");
+        assert!(init.code.iter().any(|instr| matches!(
+            instr,
+            Instr::StoreConst {
+                value: ConstValue::Num(value),
+                ..
+            } if value.to_bits() == 3.5f64.to_bits()
+        )));
+
+        let err = compile_program(
+            "#call core::mod::const name=\"N\" value=1;\n#call core::mov from=local::x to=mod::N;\n",
+            CompileOpts::default(),
+        )
+        .expect_err("assigning a constant");
+        assert!(
+            err.message
+                .contains("cannot assign to module constant 'mod::N'")
+        );
+    }
+
     #[test]
     fn lowers_new_stdlib_enabler_targets() {
         let src = r#"
@@ -1192,7 +3770,7 @@ mod tests {
 #call core::const out=local::k value="name";
 #call core::obj::has obj=local::m key=local::k out=local::has_name;
 #call core::obj::get obj=local::m key=local::k out=local::value;
-#call core::str::concat a="hi " b="there" out=local::joined;
+#call core::str::concat a="hi " b=local::value out=local::joined;
 #call core::str::len value=local::joined out=return::value;
 #call core::exit;
 "#;
@@ -1221,4 +3799,299 @@ mod tests {
                 .any(|instr| matches!(instr, Instr::StrLen { .. }))
         );
     }
+
+    #[test]
+    fn literal_only_pure_calls_fold_to_consts() {
+        let src = r#"
+#call core::str::concat a="ab" b="cd" out=local::s;
+#call core::str::len value="héllo" out=local::n;
+#call core::cmp value_a=1 value_b="x" out=local::c;
+#call core::str::upper value=" Ab " out=local::u;
+#call core::str::trim value=" ab\t" out=local::t;
+#call core::type_of value=null out=local::k;
+#call core::is::num value=1 out=local::isnum;
+#call core::str::concat a="n=" b=local::n out=return::value;
+#call core::exit;
+"#;
+
+        let compiled = compile_program(src, CompileOpts::default()).expect("compile");
+        let init = compiled.module.function(0).expect("init");
+        let consts = init
+            .code
+            .iter()
+            .filter_map(|instr| match instr {
+                Instr::StoreConst { value, .. } => Some(value.clone()),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+
+        assert_eq!(
+            consts[..7],
+            [
+                ConstValue::Str(Arc::from("abcd")),
+                ConstValue::Num(5.0),
+                ConstValue::Num(-1.0),
+                ConstValue::Str(Arc::from(" AB ")),
+                ConstValue::Str(Arc::from("ab")),
+                ConstValue::Str(Arc::from("null")),
+                ConstValue::Bool(true),
+            ]
+        );
+        assert!(
+            !init
+                .code
+                .iter()
+                .any(|instr| matches!(instr, Instr::StrLen { .. } | Instr::Cmp { .. }))
+        );
+        assert_eq!(
+            init.code
+                .iter()
+                .filter(|instr| matches!(instr, Instr::StrConcat { .. }))
+                .count(),
+            1
+        );
+        let err = compile_program(
+            "#call core::is::number value=1 out=local::x;",
+            CompileOpts::default(),
+        )
+        .expect_err("unknown kind");
+        assert!(err.to_string().contains("core::is::number"), "{err}");
+    }
+
+    #[test]
+    fn inline_splices_callees_but_not_recursive_ones() {
+        let src = r#"
+#call @inline core::fn::begin name=main::abs args="x" retshape="scalar";
+#call core::const out=local::zero value=0;
+#call core::lt a=arg::x b=local::zero out=local::is_neg;
+#call core::br cond=local::is_neg then="neg" else="pos";
+#call core::label name="neg";
+#call core::sub a=local::zero b=arg::x out=return::value;
+#call core::exit;
+#call core::label name="pos";
+#call core::mov from=arg::x to=return::value;
+#call core::exit;
+#call core::fn::end;
+#call @inline core::fn::begin name=main::dist args="a,b" retshape="scalar";
+#call core::sub a=arg::a b=arg::b out=local::d;
+#call main::abs args="local::d" out=return::value;
+#call core::exit;
+#call core::fn::end;
+#call @inline core::fn::begin name=main::down args="n" retshape="scalar";
+#call main::down args="arg::n" out=return::value;
+#call core::exit;
+#call core::fn::end;
+#call core::const out=local::a value=2;
+#call core::const out=local::b value=7;
+#call main::dist args="local::a,local::b" out=return::dist;
+#call main::down args="local::a" out=local::never;
+#call core::exit;
+"#;
+        let module = compile_program(src, CompileOpts::default())
+            .expect("compile")
+            .module;
+        let invokes = |func_id: FuncId| {
+            let function = module.function(func_id).expect("function");
+            assert_eq!(
+                function.meta.debug.as_ref().expect("debug").lines.len(),
+                function.code.len()
+            );
+            function
+                .code
+                .iter()
+                .filter(|instr| matches!(instr, Instr::Invoke { .. } | Instr::TailInvoke { .. }))
+                .count()
+        };
+        // `dist` absorbs `abs`, and `<init>` absorbs `dist` but keeps the
+        // call to the self-recursive `down`.
+        assert_eq!(invokes(2), 0);
+        assert_eq!(invokes(3), 1);
+        assert_eq!(invokes(0), 1);
+        let init = module.function(0).expect("init");
+        assert!(init.local_count > 3);
+    }
+
+    #[test]
+    fn tail_calls_are_marked_only_for_single_return_functions() {
+        let src = r#"
+#call core::fn::begin name=main::id args="x" retshape="scalar";
+#call core::mov from=arg::x to=return::value;
+#call core::exit;
+#call core::fn::end;
+#call core::fn::begin name=main::tail args="x" retshape="scalar";
+#call main::id args="arg::x" out=return::value;
+#call core::exit;
+#call core::fn::end;
+#call core::fn::begin name=main::pair args="x" retshape="any" retcount=2;
+#call main::id args="arg::x" out=return::0;
+#call core::exit;
+#call core::fn::end;
+#call core::fn::begin name=main::kept args="x" retshape="scalar";
+#call main::id args="arg::x" out=local::y;
+#call core::mov from=local::y to=return::value;
+#call core::exit;
+#call core::fn::end;
+"#;
+        let module = compile_program(src, CompileOpts::default())
+            .expect("compile")
+            .module;
+        let tail = |func_id: FuncId| {
+            module
+                .function(func_id)
+                .expect("function")
+                .code
+                .iter()
+                .any(|instr| matches!(instr, Instr::TailInvoke { .. }))
+        };
+        assert!(tail(2));
+        assert!(!tail(3));
+        assert!(!tail(4));
+    }
+
+    #[test]
+    fn opt_level_folds_constant_slots_into_store_const() {
+        let src = r#"
+#call core::const out=local::x value=2;
+#call core::const out=local::y value=3;
+#call core::add a=local::x b=local::y out=local::sum;
+#call core::mul a=local::sum b=local::y out=local::product;
+#call core::lt a=local::product b=local::x out=local::small;
+#call core::str::concat a="p=" b=local::product out=return::value;
+#call core::exit;
+"#;
+        let arithmetic = |code: &[Instr]| {
+            code.iter()
+                .filter(|instr| {
+                    matches!(
+                        instr,
+                        Instr::Add { .. }
+                            | Instr::Mul { .. }
+                            | Instr::Lt { .. }
+                            | Instr::StrConcat { .. }
+                    )
+                })
+                .count()
+        };
+
+        let plain = compile_program(src, CompileOpts::default()).expect("compile");
+        let plain_init = plain.module.function(0).expect("init");
+        assert_eq!(arithmetic(&plain_init.code), 4);
+
+        let opts = CompileOpts {
+            opt_level: 1,
+            ..CompileOpts::default()
+        };
+        let optimized = compile_program(src, opts).expect("compile");
+        let init = optimized.module.function(0).expect("init");
+        assert_eq!(arithmetic(&init.code), 0);
+        assert_eq!(init.code.len(), plain_init.code.len());
+        assert!(init.code.contains(&Instr::StoreConst {
+            slot: Slot::Ret(0),
+            value: ConstValue::Str(Arc::from("p=15")),
+        }));
+    }
+
+    #[test]
+    fn opt_level_removes_unreachable_code_and_remaps_targets() {
+        let src = r#"
+#call core::const out=local::flag value=true;
+#call core::jump target="check";
+#call core::const out=local::dead value=1;
+#call core::const out=local::dead value=2;
+#call core::label name="check";
+#call core::br cond=local::flag then="yes" else="no";
+#call core::label name="yes";
+#call core::const out=return::value value="yes";
+#call core::exit;
+#call core::const out=local::dead value=3;
+#call core::label name="no";
+#call core::const out=return::value value="no";
+#call core::exit;
+#call core::const out=local::dead value=4;
+"#;
+        let opts = CompileOpts {
+            opt_level: 1,
+            ..CompileOpts::default()
+        };
+        let compiled = compile_program(src, opts).expect("compile");
+        let init = compiled.module.function(0).expect("init");
+        assert_eq!(
+            init.code[..],
+            [
+                Instr::StoreConst {
+                    slot: Slot::Local(0),
+                    value: ConstValue::Bool(true),
+                },
+                Instr::Jump { target: 2 },
+                Instr::Branch {
+                    cond: Slot::Local(0),
+                    then_pc: 3,
+                    else_pc: 5,
+                },
+                Instr::StoreConst {
+                    slot: Slot::Ret(0),
+                    value: ConstValue::Str(Arc::from("yes")),
+                },
+                Instr::Exit,
+                Instr::StoreConst {
+                    slot: Slot::Ret(0),
+                    value: ConstValue::Str(Arc::from("no")),
+                },
+                Instr::Exit,
+            ]
+        );
+    }
+
+    #[test]
+    fn target_plugins_lower_app_targets() {
+        struct AppTargets;
+        impl TargetLowering for AppTargets {
+            fn handles(&self, target: &str) -> bool {
+                target.starts_with("app::")
+            }
+
+            fn lower(&self, cx: &mut LoweringCx<'_, '_>) -> Result<(), CompileError> {
+                match cx.call().target.as_str() {
+                    "app::double" => {
+                        let value = cx.slot("value")?;
+                        let out = cx.out("out")?;
+                        cx.emit(Instr::Add {
+                            a: value,
+                            b: value,
+                            out,
+                        });
+                    }
+                    "app::log" => {
+                        let value = cx.slot("value")?;
+                        let out = cx.temp();
+                        cx.emit(Instr::HostCall {
+                            name: "app::log".to_owned(),
+                            args: vec![value],
+                            out,
+                        });
+                    }
+                    other => return Err(cx.error(format!("unknown app target '{other}'"))),
+                }
+                Ok(())
+            }
+        }
+
+        let opts = || CompileOpts {
+            target_plugins: vec![Box::new(AppTargets)],
+            ..CompileOpts::default()
+        };
+        let compiled = compile_program(
+            "#call app::double value=21 out=return::value;\n\
+             #call app::log value=return::value;\n\
+             #call core::exit;\n",
+            opts(),
+        )
+        .expect("compile");
+        let code = &compiled.module.functions[0].code;
+        assert!(matches!(code[1], Instr::Add { a, b, .. } if a == b));
+        assert!(matches!(&code[2], Instr::HostCall { name, .. } if name == "app::log"));
+
+        let err = compile_program("#call app::nope;\n", opts()).expect_err("rejected");
+        assert!(err.message.contains("unknown app target 'app::nope'"));
+    }
 }
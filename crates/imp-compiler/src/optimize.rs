@@ -0,0 +1,262 @@
+//! Optimization passes over lowered IR, run when `CompileOpts::opt_level`
+//! asks for them.
+
+use crate::{literal_cmp, literal_text};
+use imp_ast::Atom;
+use imp_ir::{ConstValue, Instr, Slot, ValueKind};
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::sync::Arc;
+
+/// Rewrites arithmetic, comparisons and string ops whose operands are local
+/// slots holding known constants into a `StoreConst` of the result.
+///
+/// Constants are tracked within a basic block only, and only for `Local`
+/// slots, which nothing outside the frame can write. Instructions are
+/// rewritten in place, so jump targets stay valid. Anything the VM would
+/// reject at runtime (a non-number operand, division by zero) is left for the
+/// VM to report.
+pub(crate) fn fold_constants(code: &mut [Instr]) {
+    let leaders = block_leaders(code);
+    let mut known: HashMap<u32, ConstValue> = HashMap::new();
+    for (pc, instr) in code.iter_mut().enumerate() {
+        if leaders[pc] {
+            known.clear();
+        }
+        if let Some((out, value)) = fold(instr, &known) {
+            *instr = Instr::StoreConst { slot: out, value };
+        }
+        match instr {
+            Instr::StoreConst { slot, value } => {
+                if let Slot::Local(index) = slot {
+                    known.insert(*index, value.clone());
+                }
+            }
+            Instr::Move { to: out, .. }
+            | Instr::Add { out, .. }
+            | Instr::Sub { out, .. }
+            | Instr::Mul { out, .. }
+            | Instr::Div { out, .. }
+            | Instr::Mod { out, .. }
+            | Instr::Pow { out, .. }
+            | Instr::Neg { out, .. }
+            | Instr::Eq { out, .. }
+            | Instr::Ne { out, .. }
+            | Instr::Lt { out, .. }
+            | Instr::Le { out, .. }
+            | Instr::Gt { out, .. }
+            | Instr::Ge { out, .. }
+            | Instr::Cmp { out, .. }
+            | Instr::StrConcat { out, .. }
+            | Instr::StrLen { out, .. }
+            | Instr::TypeOf { out, .. }
+            | Instr::IsType { out, .. }
+            | Instr::StrUpper { out, .. }
+            | Instr::StrLower { out, .. }
+            | Instr::StrTrim { out, .. }
+            | Instr::StrTrimStart { out, .. }
+            | Instr::StrTrimEnd { out, .. } => {
+                if let Slot::Local(index) = out {
+                    known.remove(index);
+                }
+            }
+            _ => known.clear(),
+        }
+    }
+}
+
+/// Drops instructions no path from the entry reaches, such as code after
+/// `core::exit` that no label targets, along with their `lines` entries, and
+/// renumbers jump, branch and handler targets to match.
+pub(crate) fn remove_unreachable(code: &mut Vec<Instr>, lines: &mut Vec<u32>) {
+    let mut reachable = vec![false; code.len()];
+    let mut pending = vec![0];
+    while let Some(pc) = pending.pop() {
+        if pc >= code.len() || reachable[pc] {
+            continue;
+        }
+        reachable[pc] = true;
+        match &code[pc] {
+            Instr::Jump { target } => pending.push(*target),
+            Instr::Branch {
+                then_pc, else_pc, ..
+            } => pending.extend([*then_pc, *else_pc]),
+            Instr::TryPush { handler_pc } => pending.extend([*handler_pc, pc + 1]),
+            Instr::Exit | Instr::Throw { .. } | Instr::Rethrow { .. } => {}
+            _ => pending.push(pc + 1),
+        }
+    }
+    if reachable.iter().all(|live| *live) {
+        return;
+    }
+
+    // `remap[pc]` is the new pc of the first live instruction at or after
+    // `pc`; the extra entry covers targets one past the end.
+    let mut remap = Vec::with_capacity(code.len() + 1);
+    let mut next = 0;
+    for live in &reachable {
+        remap.push(next);
+        next += usize::from(*live);
+    }
+    remap.push(next);
+
+    let mut pc = 0;
+    code.retain(|_| {
+        pc += 1;
+        reachable[pc - 1]
+    });
+    let mut pc = 0;
+    lines.retain(|_| {
+        pc += 1;
+        reachable[pc - 1]
+    });
+    for instr in code.iter_mut() {
+        match instr {
+            Instr::Jump { target } => *target = remap[*target],
+            Instr::Branch {
+                then_pc, else_pc, ..
+            } => {
+                *then_pc = remap[*then_pc];
+                *else_pc = remap[*else_pc];
+            }
+            Instr::TryPush { handler_pc } => *handler_pc = remap[*handler_pc],
+            _ => {}
+        }
+    }
+}
+
+/// Marks the first instruction of every basic block: the entry, every jump,
+/// branch and handler target, and whatever follows a control transfer.
+fn block_leaders(code: &[Instr]) -> Vec<bool> {
+    let mut leaders = vec![false; code.len() + 1];
+    leaders[0] = true;
+    let mut mark = |pc: usize| {
+        if let Some(leader) = leaders.get_mut(pc) {
+            *leader = true;
+        }
+    };
+    for (pc, instr) in code.iter().enumerate() {
+        match instr {
+            Instr::Jump { target } => {
+                mark(*target);
+                mark(pc + 1);
+            }
+            Instr::Branch {
+                then_pc, else_pc, ..
+            } => {
+                mark(*then_pc);
+                mark(*else_pc);
+                mark(pc + 1);
+            }
+            Instr::TryPush { handler_pc } => mark(*handler_pc),
+            Instr::Exit | Instr::Throw { .. } | Instr::Rethrow { .. } => mark(pc + 1),
+            _ => {}
+        }
+    }
+    leaders
+}
+
+fn fold(instr: &Instr, known: &HashMap<u32, ConstValue>) -> Option<(Slot, ConstValue)> {
+    let get = |slot: &Slot| match slot {
+        Slot::Local(index) => known.get(index),
+        _ => None,
+    };
+    let num = |slot: &Slot| match get(slot)? {
+        ConstValue::Num(value) => Some(*value),
+        _ => None,
+    };
+    let atom = |slot: &Slot| const_atom(get(slot)?);
+    match instr {
+        Instr::Move { from, to } => Some((*to, get(from)?.clone())),
+        Instr::Add { a, b, out } => Some((*out, ConstValue::Num(num(a)? + num(b)?))),
+        Instr::Sub { a, b, out } => Some((*out, ConstValue::Num(num(a)? - num(b)?))),
+        Instr::Mul { a, b, out } => Some((*out, ConstValue::Num(num(a)? * num(b)?))),
+        Instr::Div { a, b, out } => {
+            let divisor = num(b)?;
+            if divisor == 0.0 {
+                return None;
+            }
+            Some((*out, ConstValue::Num(num(a)? / divisor)))
+        }
+        Instr::Mod { a, b, out } => {
+            let divisor = num(b)?;
+            if divisor == 0.0 {
+                return None;
+            }
+            Some((*out, ConstValue::Num(num(a)? % divisor)))
+        }
+        Instr::Pow { a, b, out } => Some((*out, ConstValue::Num(num(a)?.powf(num(b)?)))),
+        Instr::Neg { value, out } => Some((*out, ConstValue::Num(-num(value)?))),
+        Instr::Eq { a, b, out } => Some((*out, ConstValue::Bool(get(a)? == get(b)?))),
+        Instr::Ne { a, b, out } => Some((*out, ConstValue::Bool(get(a)? != get(b)?))),
+        Instr::Lt { a, b, out }
+        | Instr::Le { a, b, out }
+        | Instr::Gt { a, b, out }
+        | Instr::Ge { a, b, out } => {
+            let ordering = match (get(a)?, get(b)?) {
+                (ConstValue::Num(a), ConstValue::Num(b)) => a.partial_cmp(b),
+                (ConstValue::Str(a), ConstValue::Str(b)) => Some(a.cmp(b)),
+                _ => return None,
+            };
+            let holds = match instr {
+                Instr::Lt { .. } => ordering.is_some_and(Ordering::is_lt),
+                Instr::Le { .. } => ordering.is_some_and(Ordering::is_le),
+                Instr::Gt { .. } => ordering.is_some_and(Ordering::is_gt),
+                _ => ordering.is_some_and(Ordering::is_ge),
+            };
+            Some((*out, ConstValue::Bool(holds)))
+        }
+        Instr::Cmp { a, b, out } => {
+            let ordering = literal_cmp(&atom(a)?, &atom(b)?);
+            Some((*out, ConstValue::Num(f64::from(ordering as i8))))
+        }
+        Instr::StrConcat { a, b, out } => {
+            let text = format!("{}{}", literal_text(&atom(a)?)?, literal_text(&atom(b)?)?);
+            Some((*out, ConstValue::Str(Arc::from(text))))
+        }
+        Instr::TypeOf { value, out } => {
+            let kind = ValueKind::of_const(get(value)?);
+            Some((*out, ConstValue::Str(Arc::from(kind.as_str()))))
+        }
+        Instr::IsType { value, kind, out } => Some((
+            *out,
+            ConstValue::Bool(ValueKind::of_const(get(value)?) == *kind),
+        )),
+        Instr::StrLen { value, out } => {
+            let len = u32::try_from(literal_text(&atom(value)?)?.chars().count()).ok()?;
+            Some((*out, ConstValue::Num(f64::from(len))))
+        }
+        Instr::StrUpper { value, out } => {
+            let text = literal_text(&atom(value)?)?;
+            Some((*out, ConstValue::Str(Arc::from(text.to_uppercase()))))
+        }
+        Instr::StrLower { value, out } => {
+            let text = literal_text(&atom(value)?)?;
+            Some((*out, ConstValue::Str(Arc::from(text.to_lowercase()))))
+        }
+        Instr::StrTrim { value, out } => {
+            let text = literal_text(&atom(value)?)?;
+            Some((*out, ConstValue::Str(Arc::from(text.trim()))))
+        }
+        Instr::StrTrimStart { value, out } => {
+            let text = literal_text(&atom(value)?)?;
+            Some((*out, ConstValue::Str(Arc::from(text.trim_start()))))
+        }
+        Instr::StrTrimEnd { value, out } => {
+            let text = literal_text(&atom(value)?)?;
+            Some((*out, ConstValue::Str(Arc::from(text.trim_end()))))
+        }
+        _ => None,
+    }
+}
+
+/// `value` as a literal atom; bytes and integers have no literal form.
+fn const_atom(value: &ConstValue) -> Option<Atom> {
+    match value {
+        ConstValue::Null => Some(Atom::Null),
+        ConstValue::Bool(value) => Some(Atom::Bool(*value)),
+        ConstValue::Num(value) => Some(Atom::Num(*value)),
+        ConstValue::Str(value) => Some(Atom::Str(value.to_string())),
+        ConstValue::Int(_) | ConstValue::Bytes(_) => None,
+    }
+}
@@ -0,0 +1,99 @@
+use crate::{
+    CompileError, ModuleBuilder, SlotEnv, get_string_arg, resolve_atom_to_slot, resolve_named_ref,
+};
+use imp_ast::Call;
+use imp_ir::{Instr, Slot};
+
+/// Lowering for embedder-defined call targets such as `app::log`.
+///
+/// Plugins are consulted, in registration order, for every non-`core::*`
+/// target before it would otherwise become an `Invoke`; the first plugin whose
+/// [`handles`](TargetLowering::handles) returns true lowers the call.
+pub trait TargetLowering: Send + Sync {
+    fn handles(&self, target: &str) -> bool;
+
+    /// Emits the instructions for `cx.call()`. Emitted jumps and branches use
+    /// absolute pcs; see [`LoweringCx::pc`].
+    ///
+    /// # Errors
+    ///
+    /// A call the plugin cannot lower; the compile fails with it.
+    fn lower(&self, cx: &mut LoweringCx<'_, '_>) -> Result<(), CompileError>;
+}
+
+/// Slot resolution and emission for a [`TargetLowering`] plugin.
+pub struct LoweringCx<'a, 'p> {
+    pub(crate) call: &'a Call,
+    pub(crate) env: &'a mut SlotEnv,
+    pub(crate) builder: &'a mut ModuleBuilder<'p>,
+    pub(crate) code: &'a mut Vec<Instr>,
+}
+
+impl LoweringCx<'_, '_> {
+    #[must_use]
+    pub fn call(&self) -> &Call {
+        self.call
+    }
+
+    /// Slot holding argument `key`; literals are stored into a fresh temp.
+    ///
+    /// # Errors
+    ///
+    /// A missing argument or one that names no slot.
+    pub fn slot(&mut self, key: &str) -> Result<Slot, CompileError> {
+        let atom = self
+            .call
+            .arg(key)
+            .ok_or_else(|| self.error(format!("{} missing {key}", self.call.target)))?;
+        resolve_atom_to_slot(atom, self.env, self.builder, self.code, self.call.line)
+    }
+
+    /// Like [`slot`](Self::slot), or `None` when the argument is absent.
+    ///
+    /// # Errors
+    ///
+    /// An argument that names no slot.
+    pub fn opt_slot(&mut self, key: &str) -> Result<Option<Slot>, CompileError> {
+        match self.call.arg(key) {
+            Some(_) => self.slot(key).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    /// Destination slot named by ref argument `key` (e.g. `out`).
+    ///
+    /// # Errors
+    ///
+    /// A missing argument or one that is not a ref.
+    pub fn out(&mut self, key: &str) -> Result<Slot, CompileError> {
+        resolve_named_ref(self.call, key, self.env, self.builder)
+    }
+
+    /// String argument `key`.
+    ///
+    /// # Errors
+    ///
+    /// A missing argument or one that is not a string.
+    pub fn string(&self, key: &str) -> Result<String, CompileError> {
+        get_string_arg(self.call, key)
+    }
+
+    /// A fresh local no source name can refer to.
+    pub fn temp(&mut self) -> Slot {
+        self.env.resolve_temp_local("plugin")
+    }
+
+    /// Pc the next emitted instruction will have.
+    #[must_use]
+    pub fn pc(&self) -> usize {
+        self.code.len()
+    }
+
+    pub fn emit(&mut self, instr: Instr) {
+        self.code.push(instr);
+    }
+
+    pub fn error(&self, message: impl Into<String>) -> CompileError {
+        CompileError::new(self.call.line, message)
+    }
+}
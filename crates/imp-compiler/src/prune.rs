@@ -0,0 +1,207 @@
+//! Export pruning for `imp build --prune-exports`: keeps what the listed
+//! exports and each module's init can reach, across imports.
+
+use crate::CompileError;
+use imp_ir::{CompiledModule, FuncId, ImportBinding, Slot};
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+
+/// `module` with only the exports and constants named in `keep`, and only
+/// the functions the init and those exports reach through global slots.
+///
+/// Imports are pruned the same way, keeping the exports their importers
+/// still use; an import shared by several modules keeps the union. Every
+/// init still runs, so side effects are preserved.
+///
+/// # Errors
+///
+/// An export in `keep` the module does not have.
+pub fn prune_exports(
+    module: &CompiledModule,
+    keep: &[String],
+) -> Result<CompiledModule, CompileError> {
+    for name in keep {
+        let known = module.exports.iter().any(|(export, _)| export == name)
+            || module.consts.iter().any(|(export, _)| export == name);
+        if !known {
+            return Err(CompileError::new(
+                1,
+                format!(
+                    "cannot keep '{name}': module '{}' has no such export",
+                    module.name
+                ),
+            ));
+        }
+    }
+
+    // Importers come before their imports, so each import's wanted set is
+    // complete before its own imports are looked at.
+    let mut order = Vec::new();
+    let mut seen = HashSet::new();
+    for import in &module.imports {
+        import_order(import, &mut seen, &mut order);
+    }
+    order.reverse();
+
+    let mut wanted: HashMap<&str, HashSet<&str>> = HashMap::new();
+    let root_keep = keep.iter().map(String::as_str).collect();
+    let root_live = Live::of(module, &root_keep);
+    root_live.want_imports(module, &mut wanted);
+    let mut live = HashMap::new();
+    for import in order {
+        let keep = wanted.remove(import.path.as_str()).unwrap_or_default();
+        let import_live = Live::of(&import.module, &keep);
+        import_live.want_imports(&import.module, &mut wanted);
+        live.insert(import.path.as_str(), (keep, import_live));
+    }
+
+    let mut rebuilt = HashMap::new();
+    Ok(rebuild(module, &root_keep, &root_live, &live, &mut rebuilt))
+}
+
+/// Imports reachable from `import`, each once, in DFS post-order.
+fn import_order<'m>(
+    import: &'m ImportBinding,
+    seen: &mut HashSet<&'m str>,
+    order: &mut Vec<&'m ImportBinding>,
+) {
+    if !seen.insert(&import.path) {
+        return;
+    }
+    for nested in &import.module.imports {
+        import_order(nested, seen, order);
+    }
+    order.push(import);
+}
+
+/// What one module keeps.
+#[derive(Debug, Default)]
+struct Live {
+    functions: HashSet<FuncId>,
+    globals: HashSet<u32>,
+}
+
+impl Live {
+    fn of(module: &CompiledModule, keep: &HashSet<&str>) -> Self {
+        let function_at = module
+            .function_globals
+            .iter()
+            .copied()
+            .collect::<HashMap<_, _>>();
+        let mut live = Live::default();
+        let mut pending = vec![module.init_func];
+        for (name, slot) in &module.exports {
+            if keep.contains(name.as_str()) && live.globals.insert(*slot) {
+                pending.extend(function_at.get(slot));
+            }
+        }
+        while let Some(func) = pending.pop() {
+            if !live.functions.insert(func) {
+                continue;
+            }
+            let Some(function) = module.function(func) else {
+                continue;
+            };
+            for instr in function.code.iter() {
+                for slot in instr.slots() {
+                    if let Slot::Global(global) = slot
+                        && live.globals.insert(global)
+                    {
+                        pending.extend(function_at.get(&global));
+                    }
+                }
+            }
+        }
+        live
+    }
+
+    /// Adds the exports of `module`'s imports that live code reads.
+    fn want_imports<'m>(
+        &self,
+        module: &'m CompiledModule,
+        wanted: &mut HashMap<&'m str, HashSet<&'m str>>,
+    ) {
+        for import in &module.imports {
+            let names = wanted.entry(import.path.as_str()).or_default();
+            for (name, global) in &import.export_to_global {
+                if self.globals.contains(global) {
+                    names.insert(name.as_str());
+                }
+            }
+        }
+    }
+}
+
+fn rebuild(
+    module: &CompiledModule,
+    keep: &HashSet<&str>,
+    live: &Live,
+    imports: &HashMap<&str, (HashSet<&str>, Live)>,
+    rebuilt: &mut HashMap<String, Arc<CompiledModule>>,
+) -> CompiledModule {
+    let pruned_imports = module
+        .imports
+        .iter()
+        .map(|import| {
+            let pruned = if let Some(pruned) = rebuilt.get(&import.path) {
+                Arc::clone(pruned)
+            } else {
+                let (import_keep, import_live) = &imports[import.path.as_str()];
+                let pruned = Arc::new(rebuild(
+                    &import.module,
+                    import_keep,
+                    import_live,
+                    imports,
+                    rebuilt,
+                ));
+                rebuilt.insert(import.path.clone(), Arc::clone(&pruned));
+                pruned
+            };
+            ImportBinding {
+                path: import.path.clone(),
+                alias: import.alias.clone(),
+                export_to_global: import
+                    .export_to_global
+                    .iter()
+                    .filter(|(_, global)| live.globals.contains(global))
+                    .cloned()
+                    .collect(),
+                module: pruned,
+            }
+        })
+        .collect();
+
+    let is_live = |func: &FuncId| live.functions.contains(func);
+    CompiledModule {
+        name: Arc::clone(&module.name),
+        init_func: module.init_func,
+        functions: module
+            .functions
+            .iter()
+            .filter(|function| is_live(&function.id))
+            .cloned()
+            .collect(),
+        function_globals: module
+            .function_globals
+            .iter()
+            .filter(|(_, func)| is_live(func))
+            .copied()
+            .collect(),
+        exports: module
+            .exports
+            .iter()
+            .filter(|(name, _)| keep.contains(name.as_str()))
+            .cloned()
+            .collect(),
+        imports: pruned_imports,
+        consts: module
+            .consts
+            .iter()
+            .filter(|(name, _)| keep.contains(name.as_str()))
+            .cloned()
+            .collect(),
+        global_count: module.global_count,
+        tests: module.tests.iter().copied().filter(is_live).collect(),
+        global_names: module.global_names.clone(),
+    }
+}
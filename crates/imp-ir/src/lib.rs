@@ -1,3 +1,4 @@
+use std::fmt;
 use std::sync::Arc;
 
 pub type FuncId = u32;
@@ -16,7 +17,9 @@ pub enum ConstValue {
     Null,
     Bool(bool),
     Num(f64),
+    Int(i64),
     Str(Arc<str>),
+    Bytes(Arc<[u8]>),
 }
 
 #[derive(Debug, Clone, PartialEq)]
@@ -50,6 +53,21 @@ pub enum Instr {
         b: Slot,
         out: Slot,
     },
+    /// Remainder with the sign of `a`, as Rust's `%`.
+    Mod {
+        a: Slot,
+        b: Slot,
+        out: Slot,
+    },
+    Pow {
+        a: Slot,
+        b: Slot,
+        out: Slot,
+    },
+    Neg {
+        value: Slot,
+        out: Slot,
+    },
 
     Eq {
         a: Slot,
@@ -61,6 +79,33 @@ pub enum Instr {
         b: Slot,
         out: Slot,
     },
+    /// `!(a == b)`.
+    Ne {
+        a: Slot,
+        b: Slot,
+        out: Slot,
+    },
+    Le {
+        a: Slot,
+        b: Slot,
+        out: Slot,
+    },
+    Gt {
+        a: Slot,
+        b: Slot,
+        out: Slot,
+    },
+    Ge {
+        a: Slot,
+        b: Slot,
+        out: Slot,
+    },
+    /// -1/0/1 under the VM's total value order.
+    Cmp {
+        a: Slot,
+        b: Slot,
+        out: Slot,
+    },
 
     Jump {
         target: usize,
@@ -76,15 +121,44 @@ pub enum Instr {
         args: Vec<Slot>,
         out: Slot,
     },
+    /// `Invoke` that stores the callee's return values in `outs`, in order;
+    /// outs past the last return value receive null.
+    InvokeMulti {
+        fn_slot: Slot,
+        args: Vec<Slot>,
+        outs: Vec<Slot>,
+    },
+    /// `Invoke` storing into `return::0`, emitted where `core::exit`
+    /// follows. With no try handler pushed, the callee's frame replaces the
+    /// current one instead of stacking on it.
+    TailInvoke {
+        fn_slot: Slot,
+        args: Vec<Slot>,
+    },
+    /// `Invoke` with one argument per item of the list in `arglist`.
+    InvokeDynamic {
+        fn_slot: Slot,
+        arglist: Slot,
+        out: Slot,
+    },
     ReturnSet {
         slot_id: u32,
         value: Slot,
     },
     Exit,
 
+    /// `data` attaches a value, typically an object, for handlers to read
+    /// back with `ErrorData`.
     Throw {
         code: String,
         msg: String,
+        cause: Option<Slot>,
+        data: Option<Slot>,
+    },
+    /// Throws the error in `err` again, keeping its code, message, cause
+    /// and data.
+    Rethrow {
+        err: Slot,
     },
     TryPush {
         handler_pc: usize,
@@ -110,6 +184,68 @@ pub enum Instr {
         key: Slot,
         out: Slot,
     },
+    /// `{key, value}` pair objects at keys `0..n` in key order; `len`
+    /// receives `n`.
+    ObjEntries {
+        obj: Slot,
+        out: Slot,
+        len: Option<Slot>,
+    },
+    /// The object's keys as a list of strings, in key order.
+    ObjKeys {
+        obj: Slot,
+        out: Slot,
+    },
+    /// Writes the object without `key` to `out`; a missing key is no error.
+    ObjDel {
+        obj: Slot,
+        key: Slot,
+        out: Slot,
+    },
+    ObjLen {
+        obj: Slot,
+        out: Slot,
+    },
+    /// Follows the dotted `path` down nested objects. A missing key reads as
+    /// null; when `safe`, so does walking through a non-object.
+    ObjGetPath {
+        obj: Slot,
+        path: Slot,
+        out: Slot,
+        safe: bool,
+    },
+    /// Writes the object with `value` stored at the dotted `path`, creating
+    /// missing levels; when `safe`, null levels are replaced too.
+    ObjSetPath {
+        obj: Slot,
+        path: Slot,
+        value: Slot,
+        out: Slot,
+        safe: bool,
+    },
+    ListNew {
+        out: Slot,
+    },
+    ListPush {
+        list: Slot,
+        value: Slot,
+        out: Slot,
+    },
+    ListGet {
+        list: Slot,
+        index: Slot,
+        out: Slot,
+    },
+    ListSet {
+        list: Slot,
+        index: Slot,
+        value: Slot,
+        out: Slot,
+    },
+    ListLen {
+        list: Slot,
+        out: Slot,
+    },
     StrConcat {
         a: Slot,
         b: Slot,
@@ -119,10 +255,784 @@ pub enum Instr {
         value: Slot,
         out: Slot,
     },
+    /// Chars `start..end` of `value`, to the end when `end` is absent.
+    StrSub {
+        value: Slot,
+        start: Slot,
+        end: Option<Slot>,
+        out: Slot,
+    },
+    /// Char index of the first `pattern` in `value`, or -1.
+    StrFind {
+        value: Slot,
+        pattern: Slot,
+        out: Slot,
+    },
+    /// `value` with every `pattern` replaced by `with`.
+    StrReplace {
+        value: Slot,
+        pattern: Slot,
+        with: Slot,
+        out: Slot,
+    },
+    StrContains {
+        value: Slot,
+        pattern: Slot,
+        out: Slot,
+    },
+    StrUpper {
+        value: Slot,
+        out: Slot,
+    },
+    StrLower {
+        value: Slot,
+        out: Slot,
+    },
+    /// `value` without leading and trailing Unicode whitespace.
+    StrTrim {
+        value: Slot,
+        out: Slot,
+    },
+    StrTrimStart {
+        value: Slot,
+        out: Slot,
+    },
+    StrTrimEnd {
+        value: Slot,
+        out: Slot,
+    },
+    /// The parts of `value` between each `sep`, as a list of strings; an
+    /// empty `sep` splits into chars.
+    StrSplit {
+        value: Slot,
+        sep: Slot,
+        out: Slot,
+    },
+    /// The items of the list in `list` as text, with `sep` between them.
+    StrJoin {
+        list: Slot,
+        sep: Slot,
+        out: Slot,
+    },
+    /// `{name}` placeholders in `tmpl` filled from the object in `args`;
+    /// `{{` and `}}` are literal braces.
+    StrFormat {
+        tmpl: Slot,
+        args: Slot,
+        out: Slot,
+    },
+    BytesFromStr {
+        value: Slot,
+        out: Slot,
+    },
+    BytesToStr {
+        value: Slot,
+        out: Slot,
+    },
+    BytesLen {
+        value: Slot,
+        out: Slot,
+    },
+    BytesConcat {
+        a: Slot,
+        b: Slot,
+        out: Slot,
+    },
+    /// `end: None` slices to the end of the buffer.
+    BytesSlice {
+        value: Slot,
+        start: Slot,
+        end: Option<Slot>,
+        out: Slot,
+    },
+    /// Integer from a decimal string, an integral number, or an integer.
+    IntParse {
+        value: Slot,
+        out: Slot,
+    },
+    /// The name of `value`'s kind, such as `"num"`.
+    TypeOf {
+        value: Slot,
+        out: Slot,
+    },
+    /// Whether `value` is of `kind`.
+    IsType {
+        value: Slot,
+        kind: ValueKind,
+        out: Slot,
+    },
+    /// Number from a decimal string, a number, or an integer.
+    NumParse {
+        value: Slot,
+        out: Slot,
+    },
+    /// `value` as a string with `precision` fractional digits, padded on
+    /// the left to `width` chars.
+    NumFormat {
+        value: Slot,
+        precision: Option<Slot>,
+        width: Option<Slot>,
+        out: Slot,
+    },
+    FnMeta {
+        func: Slot,
+        out: Slot,
+    },
+    FnBind {
+        func: Slot,
+        args: Vec<Slot>,
+        out: Slot,
+    },
+    /// A closure calling the function value in `func` with the values of
+    /// `captures`, read now, ahead of the call's own arguments.
+    MakeClosure {
+        func: Slot,
+        captures: Vec<Slot>,
+        out: Slot,
+    },
+    ErrorWrap {
+        err: Slot,
+        code: String,
+        msg: String,
+        out: Slot,
+    },
+    ErrorCause {
+        err: Slot,
+        out: Slot,
+    },
+    ErrorCode {
+        err: Slot,
+        out: Slot,
+    },
+    ErrorMsg {
+        err: Slot,
+        out: Slot,
+    },
+    ErrorData {
+        err: Slot,
+        out: Slot,
+    },
 
+    /// Embedder-defined op, executed by the handler registered for `op` in
+    /// `VmConfig.ext_ops`; only emitted by compiler plugins.
+    Ext {
+        op: u16,
+        operands: Vec<Slot>,
+    },
+    /// Calls a function the embedder registered in `VmConfig.host_fns`.
+    HostCall {
+        name: String,
+        args: Vec<Slot>,
+        out: Slot,
+    },
     HostPrint {
         slot: Slot,
+        stream: PrintStream,
+        level: PrintLevel,
+    },
+    /// `core::host::now` / `core::host::monotonic`: reads `clock` in
+    /// milliseconds.
+    HostTime {
+        clock: HostClock,
+        out: Slot,
+    },
+    /// `core::host::random`: a num in `[0, 1)` from the VM's generator.
+    HostRandom {
+        out: Slot,
+    },
+    /// `core::host::args`: the program arguments as a list.
+    HostArgs {
+        out: Slot,
+    },
+    /// `core::host::read_line`: the next input line, or null at the end.
+    HostReadLine {
+        out: Slot,
     },
+    /// `core::host::read_all`: the rest of the input.
+    HostReadAll {
+        out: Slot,
+    },
+    /// `core::host::env`: environment variable `key`, or null when unset.
+    HostEnvVar {
+        key: Slot,
+        out: Slot,
+    },
+    /// `core::host::random_int`: an int in `[lo, hi)`.
+    HostRandomInt {
+        lo: Slot,
+        hi: Slot,
+        out: Slot,
+    },
+    /// `core::host::fs::read`: the file at `path` as a string.
+    HostFsRead {
+        path: Slot,
+        out: Slot,
+    },
+    /// `core::host::fs::write`: replaces the file at `path` with `data`.
+    HostFsWrite {
+        path: Slot,
+        data: Slot,
+    },
+    /// `core::host::fs::exists`: whether anything is at `path`.
+    HostFsExists {
+        path: Slot,
+        out: Slot,
+    },
+    /// `core::json::parse`: the value a JSON text spells.
+    JsonParse {
+        value: Slot,
+        out: Slot,
+    },
+    /// `core::json::stringify`: compact JSON text for a value.
+    JsonStringify {
+        value: Slot,
+        out: Slot,
+    },
+}
+
+impl Instr {
+    /// Every slot the instruction reads or writes, in field order.
+    #[must_use]
+    #[allow(clippy::too_many_lines)] // one arm per instruction
+    pub fn slots(&self) -> Vec<Slot> {
+        match self {
+            Self::Jump { .. } | Self::TryPush { .. } | Self::TryPop | Self::Exit => Vec::new(),
+            Self::StoreConst { slot, .. }
+            | Self::Branch { cond: slot, .. }
+            | Self::ReturnSet { value: slot, .. }
+            | Self::ObjNew { out: slot }
+            | Self::ListNew { out: slot }
+            | Self::HostPrint { slot, .. }
+            | Self::HostTime { out: slot, .. }
+            | Self::HostRandom { out: slot }
+            | Self::HostArgs { out: slot }
+            | Self::HostReadLine { out: slot }
+            | Self::HostReadAll { out: slot }
+            | Self::Rethrow { err: slot } => vec![*slot],
+            Self::HostRandomInt { lo, hi, out } => vec![*lo, *hi, *out],
+            Self::Throw { cause, data, .. } => cause.iter().chain(data).copied().collect(),
+            Self::HostFsRead { path: a, out: b }
+            | Self::HostFsWrite { path: a, data: b }
+            | Self::HostFsExists { path: a, out: b }
+            | Self::Move { from: a, to: b }
+            | Self::ListLen { list: a, out: b }
+            | Self::ObjKeys { obj: a, out: b }
+            | Self::ObjLen { obj: a, out: b }
+            | Self::StrLen { value: a, out: b }
+            | Self::StrUpper { value: a, out: b }
+            | Self::StrLower { value: a, out: b }
+            | Self::StrTrim { value: a, out: b }
+            | Self::StrTrimStart { value: a, out: b }
+            | Self::StrTrimEnd { value: a, out: b }
+            | Self::BytesFromStr { value: a, out: b }
+            | Self::BytesToStr { value: a, out: b }
+            | Self::BytesLen { value: a, out: b }
+            | Self::IntParse { value: a, out: b }
+            | Self::NumParse { value: a, out: b }
+            | Self::TypeOf { value: a, out: b }
+            | Self::JsonParse { value: a, out: b }
+            | Self::JsonStringify { value: a, out: b }
+            | Self::HostEnvVar { key: a, out: b }
+            | Self::IsType {
+                value: a, out: b, ..
+            }
+            | Self::Neg { value: a, out: b }
+            | Self::FnMeta { func: a, out: b }
+            | Self::ErrorWrap { err: a, out: b, .. }
+            | Self::ErrorCause { err: a, out: b }
+            | Self::ErrorData { err: a, out: b }
+            | Self::ErrorCode { err: a, out: b }
+            | Self::ErrorMsg { err: a, out: b } => vec![*a, *b],
+            Self::Add { a, b, out }
+            | Self::Sub { a, b, out }
+            | Self::Mul { a, b, out }
+            | Self::Div { a, b, out }
+            | Self::Mod { a, b, out }
+            | Self::Pow { a, b, out }
+            | Self::Eq { a, b, out }
+            | Self::Lt { a, b, out }
+            | Self::Ne { a, b, out }
+            | Self::Le { a, b, out }
+            | Self::Gt { a, b, out }
+            | Self::Ge { a, b, out }
+            | Self::Cmp { a, b, out }
+            | Self::StrConcat { a, b, out }
+            | Self::StrFind {
+                value: a,
+                pattern: b,
+                out,
+            }
+            | Self::StrContains {
+                value: a,
+                pattern: b,
+                out,
+            }
+            | Self::StrSplit {
+                value: a,
+                sep: b,
+                out,
+            }
+            | Self::StrJoin {
+                list: a,
+                sep: b,
+                out,
+            }
+            | Self::BytesConcat { a, b, out }
+            | Self::InvokeDynamic {
+                fn_slot: a,
+                arglist: b,
+                out,
+            }
+            | Self::ObjGet {
+                obj: a,
+                key: b,
+                out,
+            }
+            | Self::ObjHas {
+                obj: a,
+                key: b,
+                out,
+            }
+            | Self::ObjDel {
+                obj: a,
+                key: b,
+                out,
+            }
+            | Self::ObjGetPath {
+                obj: a,
+                path: b,
+                out,
+                ..
+            }
+            | Self::ListPush {
+                list: a,
+                value: b,
+                out,
+            }
+            | Self::ListGet {
+                list: a,
+                index: b,
+                out,
+            }
+            | Self::StrFormat {
+                tmpl: a,
+                args: b,
+                out,
+            } => vec![*a, *b, *out],
+            Self::ObjSet {
+                obj: a,
+                key: b,
+                value: c,
+                out,
+            }
+            | Self::ObjSetPath {
+                obj: a,
+                path: b,
+                value: c,
+                out,
+                ..
+            }
+            | Self::StrReplace {
+                value: a,
+                pattern: b,
+                with: c,
+                out,
+            }
+            | Self::ListSet {
+                list: a,
+                index: b,
+                value: c,
+                out,
+            } => vec![*a, *b, *c, *out],
+            Self::ObjEntries { obj, out, len } => {
+                std::iter::once(*obj).chain([*out]).chain(*len).collect()
+            }
+            Self::BytesSlice {
+                value,
+                start,
+                end,
+                out,
+            }
+            | Self::StrSub {
+                value,
+                start,
+                end,
+                out,
+            } => [*value, *start]
+                .into_iter()
+                .chain(*end)
+                .chain([*out])
+                .collect(),
+            Self::NumFormat {
+                value,
+                precision,
+                width,
+                out,
+            } => std::iter::once(*value)
+                .chain(*precision)
+                .chain(*width)
+                .chain([*out])
+                .collect(),
+            Self::Invoke {
+                fn_slot: func,
+                args,
+                out,
+            }
+            | Self::FnBind { func, args, out }
+            | Self::MakeClosure {
+                func,
+                captures: args,
+                out,
+            } => std::iter::once(*func)
+                .chain(args.iter().copied())
+                .chain([*out])
+                .collect(),
+            Self::InvokeMulti {
+                fn_slot,
+                args,
+                outs,
+            } => std::iter::once(*fn_slot)
+                .chain(args.iter().copied())
+                .chain(outs.iter().copied())
+                .collect(),
+            Self::TailInvoke { fn_slot, args } => std::iter::once(*fn_slot)
+                .chain(args.iter().copied())
+                .collect(),
+            Self::Ext { operands, .. } => operands.clone(),
+            Self::HostCall { args, out, .. } => args.iter().copied().chain([*out]).collect(),
+        }
+    }
+
+    /// [`Self::slots`] by reference, for passes that rename slots.
+    #[allow(clippy::too_many_lines)] // one arm per instruction
+    pub fn slots_mut(&mut self) -> Vec<&mut Slot> {
+        match self {
+            Self::Jump { .. } | Self::TryPush { .. } | Self::TryPop | Self::Exit => Vec::new(),
+            Self::StoreConst { slot, .. }
+            | Self::Branch { cond: slot, .. }
+            | Self::ReturnSet { value: slot, .. }
+            | Self::ObjNew { out: slot }
+            | Self::ListNew { out: slot }
+            | Self::HostPrint { slot, .. }
+            | Self::HostTime { out: slot, .. }
+            | Self::HostRandom { out: slot }
+            | Self::HostArgs { out: slot }
+            | Self::HostReadLine { out: slot }
+            | Self::HostReadAll { out: slot }
+            | Self::Rethrow { err: slot } => vec![slot],
+            Self::HostRandomInt { lo, hi, out } => vec![lo, hi, out],
+            Self::Throw { cause, data, .. } => cause.iter_mut().chain(data.iter_mut()).collect(),
+            Self::HostFsRead { path: a, out: b }
+            | Self::HostFsWrite { path: a, data: b }
+            | Self::HostFsExists { path: a, out: b }
+            | Self::Move { from: a, to: b }
+            | Self::ListLen { list: a, out: b }
+            | Self::ObjKeys { obj: a, out: b }
+            | Self::ObjLen { obj: a, out: b }
+            | Self::StrLen { value: a, out: b }
+            | Self::StrUpper { value: a, out: b }
+            | Self::StrLower { value: a, out: b }
+            | Self::StrTrim { value: a, out: b }
+            | Self::StrTrimStart { value: a, out: b }
+            | Self::StrTrimEnd { value: a, out: b }
+            | Self::BytesFromStr { value: a, out: b }
+            | Self::BytesToStr { value: a, out: b }
+            | Self::BytesLen { value: a, out: b }
+            | Self::IntParse { value: a, out: b }
+            | Self::NumParse { value: a, out: b }
+            | Self::TypeOf { value: a, out: b }
+            | Self::JsonParse { value: a, out: b }
+            | Self::JsonStringify { value: a, out: b }
+            | Self::HostEnvVar { key: a, out: b }
+            | Self::IsType {
+                value: a, out: b, ..
+            }
+            | Self::Neg { value: a, out: b }
+            | Self::FnMeta { func: a, out: b }
+            | Self::ErrorWrap { err: a, out: b, .. }
+            | Self::ErrorCause { err: a, out: b }
+            | Self::ErrorData { err: a, out: b }
+            | Self::ErrorCode { err: a, out: b }
+            | Self::ErrorMsg { err: a, out: b } => vec![a, b],
+            Self::Add { a, b, out }
+            | Self::Sub { a, b, out }
+            | Self::Mul { a, b, out }
+            | Self::Div { a, b, out }
+            | Self::Mod { a, b, out }
+            | Self::Pow { a, b, out }
+            | Self::Eq { a, b, out }
+            | Self::Lt { a, b, out }
+            | Self::Ne { a, b, out }
+            | Self::Le { a, b, out }
+            | Self::Gt { a, b, out }
+            | Self::Ge { a, b, out }
+            | Self::Cmp { a, b, out }
+            | Self::StrConcat { a, b, out }
+            | Self::StrFind {
+                value: a,
+                pattern: b,
+                out,
+            }
+            | Self::StrContains {
+                value: a,
+                pattern: b,
+                out,
+            }
+            | Self::StrSplit {
+                value: a,
+                sep: b,
+                out,
+            }
+            | Self::StrJoin {
+                list: a,
+                sep: b,
+                out,
+            }
+            | Self::BytesConcat { a, b, out }
+            | Self::InvokeDynamic {
+                fn_slot: a,
+                arglist: b,
+                out,
+            }
+            | Self::ObjGet {
+                obj: a,
+                key: b,
+                out,
+            }
+            | Self::ObjHas {
+                obj: a,
+                key: b,
+                out,
+            }
+            | Self::ObjDel {
+                obj: a,
+                key: b,
+                out,
+            }
+            | Self::ObjGetPath {
+                obj: a,
+                path: b,
+                out,
+                ..
+            }
+            | Self::ListPush {
+                list: a,
+                value: b,
+                out,
+            }
+            | Self::ListGet {
+                list: a,
+                index: b,
+                out,
+            }
+            | Self::StrFormat {
+                tmpl: a,
+                args: b,
+                out,
+            } => vec![a, b, out],
+            Self::ObjSet {
+                obj: a,
+                key: b,
+                value: c,
+                out,
+            }
+            | Self::ObjSetPath {
+                obj: a,
+                path: b,
+                value: c,
+                out,
+                ..
+            }
+            | Self::StrReplace {
+                value: a,
+                pattern: b,
+                with: c,
+                out,
+            }
+            | Self::ListSet {
+                list: a,
+                index: b,
+                value: c,
+                out,
+            } => vec![a, b, c, out],
+            Self::ObjEntries { obj, out, len } => std::iter::once(obj)
+                .chain([out])
+                .chain(len.as_mut())
+                .collect(),
+            Self::BytesSlice {
+                value,
+                start,
+                end,
+                out,
+            }
+            | Self::StrSub {
+                value,
+                start,
+                end,
+                out,
+            } => [value, start]
+                .into_iter()
+                .chain(end.as_mut())
+                .chain([out])
+                .collect(),
+            Self::NumFormat {
+                value,
+                precision,
+                width,
+                out,
+            } => std::iter::once(value)
+                .chain(precision.as_mut())
+                .chain(width.as_mut())
+                .chain([out])
+                .collect(),
+            Self::Invoke {
+                fn_slot: func,
+                args,
+                out,
+            }
+            | Self::FnBind { func, args, out }
+            | Self::MakeClosure {
+                func,
+                captures: args,
+                out,
+            } => std::iter::once(func)
+                .chain(args.iter_mut())
+                .chain([out])
+                .collect(),
+            Self::InvokeMulti {
+                fn_slot,
+                args,
+                outs,
+            } => std::iter::once(fn_slot)
+                .chain(args.iter_mut())
+                .chain(outs.iter_mut())
+                .collect(),
+            Self::TailInvoke { fn_slot, args } => {
+                std::iter::once(fn_slot).chain(args.iter_mut()).collect()
+            }
+            Self::Ext { operands, .. } => operands.iter_mut().collect(),
+            Self::HostCall { args, out, .. } => args.iter_mut().chain([out]).collect(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub enum PrintStream {
+    #[default]
+    Stdout,
+    Stderr,
+}
+
+/// The clocks `Instr::HostTime` reads.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum HostClock {
+    /// Wall-clock time since the Unix epoch.
+    Wall,
+    /// Time since the VM was created; never goes backwards.
+    Monotonic,
+}
+
+/// The kinds of runtime value, as `core::type_of` names them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ValueKind {
+    Null,
+    Bool,
+    Num,
+    Int,
+    Str,
+    Bytes,
+    List,
+    Obj,
+    Func,
+    Closure,
+    Error,
+    Handle,
+}
+
+impl ValueKind {
+    /// Every kind, in the order of their bytecode tags.
+    pub const ALL: [Self; 12] = [
+        Self::Null,
+        Self::Bool,
+        Self::Num,
+        Self::Int,
+        Self::Str,
+        Self::Bytes,
+        Self::List,
+        Self::Obj,
+        Self::Func,
+        Self::Closure,
+        Self::Error,
+        Self::Handle,
+    ];
+
+    #[must_use]
+    pub fn parse(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|kind| kind.as_str() == name)
+    }
+
+    #[must_use]
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Null => "null",
+            Self::Bool => "bool",
+            Self::Num => "num",
+            Self::Int => "int",
+            Self::Str => "str",
+            Self::Bytes => "bytes",
+            Self::List => "list",
+            Self::Obj => "obj",
+            Self::Func => "func",
+            Self::Closure => "closure",
+            Self::Error => "error",
+            Self::Handle => "handle",
+        }
+    }
+
+    /// The kind of value `value` loads as.
+    #[must_use]
+    pub fn of_const(value: &ConstValue) -> Self {
+        match value {
+            ConstValue::Null => Self::Null,
+            ConstValue::Bool(_) => Self::Bool,
+            ConstValue::Num(_) => Self::Num,
+            ConstValue::Int(_) => Self::Int,
+            ConstValue::Str(_) => Self::Str,
+            ConstValue::Bytes(_) => Self::Bytes,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub enum PrintLevel {
+    Debug,
+    #[default]
+    Info,
+    Warn,
+    Error,
+}
+
+impl PrintLevel {
+    #[must_use]
+    pub fn parse(name: &str) -> Option<Self> {
+        match name {
+            "debug" => Some(Self::Debug),
+            "info" => Some(Self::Info),
+            "warn" => Some(Self::Warn),
+            "error" => Some(Self::Error),
+            _ => None,
+        }
+    }
+
+    #[must_use]
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Debug => "debug",
+            Self::Info => "info",
+            Self::Warn => "warn",
+            Self::Error => "error",
+        }
+    }
 }
 
 #[derive(Debug, Clone, PartialEq)]
@@ -133,12 +1043,46 @@ pub enum RetShape {
     Any,
 }
 
+impl fmt::Display for RetShape {
+    /// Renders the shape in the `retshape=` source syntax.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Scalar => f.write_str("scalar"),
+            Self::Any => f.write_str("any"),
+            Self::Either(names) => write!(f, "either({})", names.join(",")),
+            Self::Record(names) => write!(f, "record({})", names.join(",")),
+        }
+    }
+}
+
 #[derive(Debug, Clone, PartialEq)]
 pub struct FnMeta {
     pub name: Arc<str>,
+    /// Parameter names in positional order.
+    pub params: Vec<Arc<str>>,
     pub arg_count: u32,
     pub ret_count: u32,
     pub retshape: RetShape,
+    /// Per-invocation time limit from `@timeout(ms=..)`.
+    pub timeout_ms: Option<u64>,
+    /// Source positions of the function's instructions, if recorded.
+    pub debug: Option<Arc<DebugInfo>>,
+}
+
+/// Maps a function's instructions back to the source they were lowered from.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DebugInfo {
+    /// Path of the source file; `None` for source compiled from a string.
+    pub source: Option<Arc<str>>,
+    /// 1-based source line of the instruction at each pc.
+    pub lines: Arc<[u32]>,
+}
+
+impl DebugInfo {
+    #[must_use]
+    pub fn line(&self, pc: usize) -> Option<u32> {
+        self.lines.get(pc).copied()
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -168,10 +1112,18 @@ pub struct CompiledModule {
     pub function_globals: Vec<(u32, FuncId)>,
     pub exports: Vec<(String, u32)>,
     pub imports: Vec<ImportBinding>,
+    /// `core::mod::const` values; exported by name and folded into importers.
+    pub consts: Vec<(String, ConstValue)>,
     pub global_count: u32,
+    /// Functions declared with `@test`, in declaration order.
+    pub tests: Vec<FuncId>,
+    /// Debug name of each global slot (`mod::count`, `dep::double`),
+    /// indexed by slot; empty when the module carries none.
+    pub global_names: Vec<String>,
 }
 
 impl CompiledModule {
+    #[must_use]
     pub fn function(&self, id: FuncId) -> Option<&CompiledFunction> {
         self.functions.iter().find(|f| f.id == id)
     }
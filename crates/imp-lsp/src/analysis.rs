@@ -0,0 +1,476 @@
+//! What the server knows about the open documents: their diagnostics, and
+//! the definition, hover and completions at a position. Positions are the
+//! protocol's: 0-based lines, columns in UTF-16 code units.
+
+use imp_ast::{Atom, Program, RefPath, Span, parse_program_recovering};
+use imp_compiler::{
+    CompileError, CompileOpts, FsModuleLoader, ModuleLoader, compile_module_diagnostics,
+};
+use imp_ir::{CompiledModule, FnMeta};
+use imp_std::{CORE_TARGETS, RESERVED_NAMESPACES};
+use std::collections::HashMap;
+use std::fmt::Write as _;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Most imports (and re-exports) followed to find a definition.
+const MAX_DEFINITION_DEPTH: usize = 16;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Position {
+    pub line: u32,
+    pub character: u32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Range {
+    pub start: Position,
+    pub end: Position,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Location {
+    pub path: PathBuf,
+    pub range: Range,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Severity {
+    Error = 1,
+    Warning = 2,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Diagnostic {
+    pub range: Range,
+    pub severity: Severity,
+    pub message: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CompletionKind {
+    Function = 3,
+    Constant = 21,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Completion {
+    pub label: String,
+    pub kind: CompletionKind,
+    /// The function's signature, when the label names one.
+    pub detail: Option<String>,
+    /// The text the completion replaces: the partial name before the cursor.
+    pub range: Range,
+}
+
+#[derive(Debug)]
+struct Document {
+    text: String,
+    /// The last module the document compiled to, kept while later edits
+    /// fail to compile.
+    module: Option<CompiledModule>,
+}
+
+/// The open documents, keyed by canonical path. Open documents shadow the
+/// files on disk, so imports see unsaved edits.
+#[derive(Debug, Default)]
+pub struct Workspace {
+    documents: HashMap<PathBuf, Document>,
+}
+
+impl Workspace {
+    /// Opens `path` with `text`, or replaces its text, and returns its
+    /// diagnostics.
+    pub fn open(&mut self, path: &Path, text: String) -> Vec<Diagnostic> {
+        let key = canonical(path);
+        let module = self.documents.remove(&key).and_then(|doc| doc.module);
+        self.documents
+            .insert(key.clone(), Document { text, module });
+        self.check(&key)
+    }
+
+    pub fn close(&mut self, path: &Path) {
+        self.documents.remove(&canonical(path));
+    }
+
+    /// Compiles the open document `path` again; empty when it is not open.
+    pub fn check(&mut self, path: &Path) -> Vec<Diagnostic> {
+        let key = canonical(path);
+        let Some(text) = self.documents.get(&key).map(|doc| doc.text.clone()) else {
+            return Vec::new();
+        };
+        let report =
+            compile_module_diagnostics(&key, &Overlay(&self.documents), &CompileOpts::default());
+        let root = key.file_stem().and_then(|stem| stem.to_str());
+        let errors = report.diagnostics.errors.iter().map(|err| Diagnostic {
+            range: err.span.map_or_else(
+                || line_range(&text, err.line),
+                |span| span_range(&text, span),
+            ),
+            severity: Severity::Error,
+            message: error_message(err),
+        });
+        let warnings = report
+            .diagnostics
+            .warnings
+            .iter()
+            .filter(|warning| Some(warning.module.as_str()) == root)
+            .map(|warning| Diagnostic {
+                range: line_range(&text, warning.line),
+                severity: Severity::Warning,
+                message: warning.message.clone(),
+            });
+        let diagnostics = errors.chain(warnings).collect();
+        if let (Some(module), Some(doc)) = (report.module, self.documents.get_mut(&key)) {
+            doc.module = Some(module);
+        }
+        diagnostics
+    }
+
+    /// Where the function ref or import under `position` is defined.
+    #[must_use]
+    pub fn definition(&self, path: &Path, position: Position) -> Option<Location> {
+        let path = canonical(path);
+        let text = self.source(&path)?;
+        let program = parse_program_recovering(&text).0;
+        match reference_at(&program, &text, offset_at(&text, position))? {
+            Reference::Function(name) => {
+                self.find_function(&path, &text, &name, MAX_DEFINITION_DEPTH)
+            }
+            Reference::Import(raw) => Some(Location {
+                path: canonical(&import_path(&path, &raw)),
+                range: Range::default(),
+            }),
+        }
+    }
+
+    /// The signature and counts of the function ref under `position`, as
+    /// markdown.
+    #[must_use]
+    pub fn hover(&self, path: &Path, position: Position) -> Option<String> {
+        let doc = self.documents.get(&canonical(path))?;
+        let module = doc.module.as_ref()?;
+        let program = parse_program_recovering(&doc.text).0;
+        let Reference::Function(name) =
+            reference_at(&program, &doc.text, offset_at(&doc.text, position))?
+        else {
+            return None;
+        };
+        let meta = if name.namespace == "main" {
+            let full = format!("main::{}", name.name);
+            module
+                .functions
+                .iter()
+                .find(|function| *function.meta.name == *full)
+                .map(|function| &function.meta)?
+        } else {
+            let import = module
+                .imports
+                .iter()
+                .find(|import| import.alias == name.namespace)?;
+            exported_function(&import.module, &name.name)?
+        };
+        let mut text = format!(
+            "```imp\n{}\n```\n\nargs: {}, returns: {}, retshape: `{}`",
+            signature(meta),
+            meta.arg_count,
+            meta.ret_count,
+            meta.retshape
+        );
+        if let Some(ms) = meta.timeout_ms {
+            let _ = write!(text, ", timeout: {ms}ms");
+        }
+        Some(text)
+    }
+
+    /// `core::` targets, the document's functions and its imports' exports
+    /// that start with the name before `position`.
+    pub fn completions(&self, path: &Path, position: Position) -> Vec<Completion> {
+        let Some(doc) = self.documents.get(&canonical(path)) else {
+            return Vec::new();
+        };
+        let end = offset_at(&doc.text, position);
+        let start = doc.text[..end]
+            .rfind(|ch: char| !(ch.is_ascii_alphanumeric() || ch == '_' || ch == ':'))
+            .map_or(0, |at| at + 1);
+        let prefix = &doc.text[start..end];
+        let range = Range {
+            start: position_at(&doc.text, start),
+            end: position,
+        };
+
+        let mut candidates = CORE_TARGETS
+            .iter()
+            .map(|target| (target.to_string(), CompletionKind::Function, None))
+            .collect::<Vec<_>>();
+        if let Some(module) = &doc.module {
+            for function in &module.functions {
+                if function.id != module.init_func && function.meta.name.starts_with("main::") {
+                    let detail = Some(signature(&function.meta));
+                    candidates.push((
+                        function.meta.name.to_string(),
+                        CompletionKind::Function,
+                        detail,
+                    ));
+                }
+            }
+            for import in &module.imports {
+                for (name, _) in &import.module.exports {
+                    let detail = exported_function(&import.module, name).map(signature);
+                    candidates.push((
+                        format!("{}::{name}", import.alias),
+                        CompletionKind::Function,
+                        detail,
+                    ));
+                }
+                for (name, _) in &import.module.consts {
+                    if !name.contains("::") {
+                        candidates.push((
+                            format!("{}::{name}", import.alias),
+                            CompletionKind::Constant,
+                            None,
+                        ));
+                    }
+                }
+            }
+        }
+        candidates
+            .into_iter()
+            .filter(|(label, _, _)| label.starts_with(prefix))
+            .map(|(label, kind, detail)| Completion {
+                label,
+                kind,
+                detail,
+                range,
+            })
+            .collect()
+    }
+
+    /// The text of `path`: the open document's, else the file's.
+    fn source(&self, path: &Path) -> Option<String> {
+        match self.documents.get(path) {
+            Some(doc) => Some(doc.text.clone()),
+            None => fs::read_to_string(path).ok(),
+        }
+    }
+
+    /// The `name=` of the `core::fn::begin` declaring `name` as seen from
+    /// `path`, following import aliases and `core::mod::export` re-exports.
+    fn find_function(
+        &self,
+        path: &Path,
+        text: &str,
+        name: &RefPath,
+        depth: usize,
+    ) -> Option<Location> {
+        let program = parse_program_recovering(text).0;
+        if name.namespace == "main" {
+            let span = program
+                .calls
+                .iter()
+                .filter(|call| call.target == "core::fn::begin")
+                .find(|call| matches!(call.arg("name"), Some(Atom::Ref(declared)) if declared == name))
+                .and_then(|call| call.arg_span("name"))?;
+            return Some(Location {
+                path: path.to_path_buf(),
+                range: span_range(text, span),
+            });
+        }
+        let depth = depth.checked_sub(1)?;
+        let import = program.calls.iter().find(|call| {
+            call.target == "core::import"
+                && matches!(call.arg("alias"), Some(Atom::Str(alias)) if *alias == name.namespace)
+        })?;
+        let Some(Atom::Str(raw)) = import.arg("path") else {
+            return None;
+        };
+        let dep_path = canonical(&import_path(path, raw));
+        let dep_text = self.source(&dep_path)?;
+        let dep = parse_program_recovering(&dep_text).0;
+        let reexport = dep.calls.iter().find_map(|call| {
+            if call.target != "core::mod::export" {
+                return None;
+            }
+            match (call.arg("name"), call.arg("value")) {
+                (Some(Atom::Str(exported)), Some(Atom::Ref(value))) if *exported == name.name => {
+                    Some(value.clone())
+                }
+                _ => None,
+            }
+        });
+        let target = reexport.unwrap_or_else(|| RefPath {
+            namespace: "main".to_owned(),
+            name: name.name.clone(),
+        });
+        self.find_function(&dep_path, &dep_text, &target, depth)
+    }
+}
+
+/// Loads open documents from memory and everything else from disk.
+struct Overlay<'a>(&'a HashMap<PathBuf, Document>);
+
+impl ModuleLoader for Overlay<'_> {
+    fn load(&self, path: &Path) -> Result<String, CompileError> {
+        match self.0.get(path) {
+            Some(doc) => Ok(doc.text.clone()),
+            None => FsModuleLoader.load(path),
+        }
+    }
+
+    fn normalize(&self, path: &Path) -> Result<PathBuf, CompileError> {
+        // A document that was never saved has no canonical path.
+        FsModuleLoader.normalize(path).or_else(|err| {
+            if self.0.contains_key(path) {
+                Ok(path.to_path_buf())
+            } else {
+                Err(err)
+            }
+        })
+    }
+}
+
+enum Reference {
+    Function(RefPath),
+    /// The `path=` of a `core::import`.
+    Import(String),
+}
+
+/// The function ref (a call target or ref argument outside the reserved
+/// namespaces) or import at byte `offset`.
+fn reference_at(program: &Program, text: &str, offset: usize) -> Option<Reference> {
+    let call = program
+        .calls
+        .iter()
+        .find(|call| contains(call.span, offset))?;
+    if call.target == "core::import" {
+        return match call.arg("path") {
+            Some(Atom::Str(raw)) => Some(Reference::Import(raw.clone())),
+            _ => None,
+        };
+    }
+    if let Some(arg) = call
+        .args
+        .iter()
+        .find(|arg| contains(arg.value_span, offset))
+    {
+        return match &arg.value {
+            Atom::Ref(name) if !is_reserved(name) => Some(Reference::Function(name.clone())),
+            _ => None,
+        };
+    }
+    let start = call.span.start
+        + text
+            .get(call.span.start..call.span.end)?
+            .find(&call.target)?;
+    if !(start..=start + call.target.len()).contains(&offset) {
+        return None;
+    }
+    RefPath::parse(&call.target)
+        .filter(|name| !is_reserved(name))
+        .map(Reference::Function)
+}
+
+fn is_reserved(name: &RefPath) -> bool {
+    RESERVED_NAMESPACES.contains(&name.namespace.as_str())
+}
+
+fn contains(span: Span, offset: usize) -> bool {
+    (span.start..=span.end).contains(&offset)
+}
+
+/// The function `module` exports as `name`.
+fn exported_function<'m>(module: &'m CompiledModule, name: &str) -> Option<&'m FnMeta> {
+    let (_, global) = module.exports.iter().find(|(export, _)| export == name)?;
+    let (_, id) = module
+        .function_globals
+        .iter()
+        .find(|(slot, _)| slot == global)?;
+    module.function(*id).map(|function| &function.meta)
+}
+
+/// `main::inc(x) -> scalar`.
+fn signature(meta: &FnMeta) -> String {
+    let params = meta.params.iter().map(AsRef::as_ref).collect::<Vec<_>>();
+    format!("{}({}) -> {}", meta.name, params.join(", "), meta.retshape)
+}
+
+fn error_message(err: &CompileError) -> String {
+    let mut message = err.message.clone();
+    for note in &err.notes {
+        message.push('\n');
+        message.push_str(note);
+    }
+    message
+}
+
+fn import_path(importer: &Path, raw: &str) -> PathBuf {
+    match importer.parent() {
+        Some(dir) => dir.join(raw),
+        None => PathBuf::from(raw),
+    }
+}
+
+fn canonical(path: &Path) -> PathBuf {
+    path.canonicalize().unwrap_or_else(|_| path.to_path_buf())
+}
+
+fn span_range(text: &str, span: Span) -> Range {
+    Range {
+        start: position_at(text, span.start),
+        end: position_at(text, span.end),
+    }
+}
+
+/// The whole of 1-based `line`.
+fn line_range(text: &str, line: usize) -> Range {
+    let line = line.saturating_sub(1);
+    let content = text.lines().nth(line).unwrap_or("");
+    let line = lsp_u32(line);
+    Range {
+        start: Position { line, character: 0 },
+        end: Position {
+            line,
+            character: lsp_u32(content.encode_utf16().count()),
+        },
+    }
+}
+
+pub(crate) fn position_at(text: &str, offset: usize) -> Position {
+    let mut offset = offset.min(text.len());
+    while !text.is_char_boundary(offset) {
+        offset -= 1;
+    }
+    let before = &text[..offset];
+    let line_start = before.rfind('\n').map_or(0, |at| at + 1);
+    Position {
+        line: lsp_u32(before.matches('\n').count()),
+        character: lsp_u32(before[line_start..].encode_utf16().count()),
+    }
+}
+
+/// `count` as the protocol's `u32`, saturating for absurdly long text.
+fn lsp_u32(count: usize) -> u32 {
+    u32::try_from(count).unwrap_or(u32::MAX)
+}
+
+/// The byte offset of `position`, clamped to the end of its line.
+pub(crate) fn offset_at(text: &str, position: Position) -> usize {
+    let mut line_start = 0;
+    for _ in 0..position.line {
+        match text[line_start..].find('\n') {
+            Some(at) => line_start += at + 1,
+            None => return text.len(),
+        }
+    }
+    let line_end = text[line_start..]
+        .find('\n')
+        .map_or(text.len(), |at| line_start + at);
+    let mut units = 0;
+    for (at, ch) in text[line_start..line_end].char_indices() {
+        if units >= position.character as usize {
+            return line_start + at;
+        }
+        units += ch.len_utf16();
+    }
+    line_end
+}
@@ -0,0 +1,560 @@
+//! A Language Server Protocol server for imp: diagnostics from the
+//! compiler, go-to-definition for function refs and imports, hover with a
+//! function's `FnMeta`, and completion of `core::` targets and the exports
+//! of imported modules.
+//!
+//! [`serve`] speaks the protocol over any reader and writer; `imp lsp`
+//! runs it on stdin/stdout. Documents sync in full.
+
+mod analysis;
+
+pub use analysis::{
+    Completion, CompletionKind, Diagnostic, Location, Position, Range, Severity, Workspace,
+};
+
+use imp_std::json::{Json, obj};
+use std::fmt::Write as _;
+use std::io::{self, BufRead, Write};
+use std::path::{Path, PathBuf};
+
+const PARSE_ERROR: i32 = -32700;
+const INVALID_REQUEST: i32 = -32600;
+const METHOD_NOT_FOUND: i32 = -32601;
+const INVALID_PARAMS: i32 = -32602;
+
+/// Largest `Content-Length` [`read_message`] accepts; the body is
+/// allocated up front, so a bigger claim is refused before reading it.
+pub const MAX_MESSAGE_BYTES: usize = 64 << 20;
+
+/// Serves one client until `exit` or EOF.
+///
+/// # Errors
+///
+/// Read and write failures, and malformed message frames.
+pub fn serve(mut input: impl BufRead, mut output: impl Write) -> io::Result<()> {
+    let mut server = Server::default();
+    while let Some(body) = read_message(&mut input)? {
+        let replies = match Json::parse(&body) {
+            Ok(message) => server.handle(&message),
+            Err(err) => vec![error_response(Json::Null, PARSE_ERROR, &err)],
+        };
+        for reply in &replies {
+            write_message(&mut output, reply)?;
+        }
+        if server.exited() {
+            break;
+        }
+    }
+    Ok(())
+}
+
+/// The body of the next `Content-Length`-framed message; `None` at EOF.
+///
+/// # Errors
+///
+/// Read failures, a missing `Content-Length` or one above
+/// [`MAX_MESSAGE_BYTES`], and bodies that are not UTF-8.
+pub fn read_message(input: &mut impl BufRead) -> io::Result<Option<String>> {
+    let mut length = None;
+    loop {
+        let mut line = String::new();
+        if input.read_line(&mut line)? == 0 {
+            return Ok(None);
+        }
+        let line = line.trim_end();
+        if line.is_empty() {
+            break;
+        }
+        if let Some((name, value)) = line.split_once(':')
+            && name.eq_ignore_ascii_case("Content-Length")
+        {
+            length = value.trim().parse().ok();
+        }
+    }
+    let length = length
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "missing Content-Length"))?;
+    if length > MAX_MESSAGE_BYTES {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("Content-Length {length} exceeds the {MAX_MESSAGE_BYTES} byte limit"),
+        ));
+    }
+    let mut body = vec![0; length];
+    input.read_exact(&mut body)?;
+    String::from_utf8(body)
+        .map(Some)
+        .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))
+}
+
+/// Writes `message` with its `Content-Length` header.
+///
+/// # Errors
+///
+/// Write failures.
+pub fn write_message(output: &mut impl Write, message: &Json) -> io::Result<()> {
+    let text = message.to_string();
+    write!(output, "Content-Length: {}\r\n\r\n{text}", text.len())?;
+    output.flush()
+}
+
+/// The protocol state around a [`Workspace`].
+#[derive(Debug, Default)]
+pub struct Server {
+    workspace: Workspace,
+    shutdown: bool,
+    exited: bool,
+}
+
+impl Server {
+    /// The responses and notifications for one client message, in order.
+    pub fn handle(&mut self, message: &Json) -> Vec<Json> {
+        let method = message.get("method").and_then(Json::as_str).unwrap_or("");
+        let params = message.get("params").unwrap_or(&Json::Null);
+        let Some(id) = message.get("id").cloned() else {
+            return self.notify(method, params);
+        };
+        if self.shutdown {
+            return vec![error_response(id, INVALID_REQUEST, "server is shut down")];
+        }
+        let result = match method {
+            "initialize" => Ok(obj([
+                ("capabilities", capabilities()),
+                (
+                    "serverInfo",
+                    obj([
+                        ("name", "imp-lsp".into()),
+                        ("version", env!("CARGO_PKG_VERSION").into()),
+                    ]),
+                ),
+            ])),
+            "shutdown" => {
+                self.shutdown = true;
+                Ok(Json::Null)
+            }
+            "textDocument/definition" => self.definition(params),
+            "textDocument/hover" => self.hover(params),
+            "textDocument/completion" => self.completion(params),
+            _ => Err((METHOD_NOT_FOUND, format!("unknown method '{method}'"))),
+        };
+        vec![match result {
+            Ok(result) => obj([("jsonrpc", "2.0".into()), ("id", id), ("result", result)]),
+            Err((code, message)) => error_response(id, code, &message),
+        }]
+    }
+
+    /// Whether the client sent `exit`.
+    #[must_use]
+    pub fn exited(&self) -> bool {
+        self.exited
+    }
+
+    fn notify(&mut self, method: &str, params: &Json) -> Vec<Json> {
+        let document = params.get("textDocument").unwrap_or(&Json::Null);
+        let Some(uri) = document.get("uri").and_then(Json::as_str) else {
+            if method == "exit" {
+                self.exited = true;
+            }
+            return Vec::new();
+        };
+        let Some(path) = uri_to_path(uri) else {
+            return Vec::new();
+        };
+        let diagnostics = match method {
+            "textDocument/didOpen" => {
+                let text = document.get("text").and_then(Json::as_str).unwrap_or("");
+                self.workspace.open(&path, text.to_owned())
+            }
+            "textDocument/didChange" => {
+                let changes = params.get("contentChanges").map_or(&[][..], Json::as_array);
+                let Some(text) = changes
+                    .last()
+                    .and_then(|change| change.get("text"))
+                    .and_then(Json::as_str)
+                else {
+                    return Vec::new();
+                };
+                self.workspace.open(&path, text.to_owned())
+            }
+            "textDocument/didSave" => self.workspace.check(&path),
+            "textDocument/didClose" => {
+                self.workspace.close(&path);
+                Vec::new()
+            }
+            _ => return Vec::new(),
+        };
+        vec![publish_diagnostics(uri, &diagnostics)]
+    }
+
+    fn definition(&self, params: &Json) -> Result<Json, (i32, String)> {
+        let (path, position) = text_position(params)?;
+        Ok(self
+            .workspace
+            .definition(&path, position)
+            .map_or(Json::Null, |location| {
+                obj([
+                    ("uri", path_to_uri(&location.path).into()),
+                    ("range", range_json(location.range)),
+                ])
+            }))
+    }
+
+    fn hover(&self, params: &Json) -> Result<Json, (i32, String)> {
+        let (path, position) = text_position(params)?;
+        Ok(self
+            .workspace
+            .hover(&path, position)
+            .map_or(Json::Null, |text| {
+                obj([(
+                    "contents",
+                    obj([("kind", "markdown".into()), ("value", text.into())]),
+                )])
+            }))
+    }
+
+    fn completion(&self, params: &Json) -> Result<Json, (i32, String)> {
+        let (path, position) = text_position(params)?;
+        let items = self
+            .workspace
+            .completions(&path, position)
+            .into_iter()
+            .map(|completion| {
+                let mut item = vec![
+                    ("label".to_owned(), Json::from(completion.label.as_str())),
+                    ("kind".to_owned(), Json::from(completion.kind as u32)),
+                ];
+                if let Some(detail) = completion.detail {
+                    item.push(("detail".to_owned(), detail.into()));
+                }
+                item.push((
+                    "textEdit".to_owned(),
+                    obj([
+                        ("range", range_json(completion.range)),
+                        ("newText", completion.label.into()),
+                    ]),
+                ));
+                Json::Obj(item)
+            })
+            .collect::<Vec<_>>();
+        Ok(items.into())
+    }
+}
+
+fn capabilities() -> Json {
+    obj([
+        ("textDocumentSync", 1u32.into()),
+        ("hoverProvider", true.into()),
+        ("definitionProvider", true.into()),
+        (
+            "completionProvider",
+            obj([("triggerCharacters", vec![Json::from(":")].into())]),
+        ),
+    ])
+}
+
+/// The document path and position of a `TextDocumentPositionParams`.
+fn text_position(params: &Json) -> Result<(PathBuf, Position), (i32, String)> {
+    let invalid = |what: &str| (INVALID_PARAMS, format!("missing or invalid {what}"));
+    let path = params
+        .get("textDocument")
+        .and_then(|document| document.get("uri"))
+        .and_then(Json::as_str)
+        .and_then(uri_to_path)
+        .ok_or_else(|| invalid("textDocument.uri"))?;
+    let position = params
+        .get("position")
+        .and_then(position_from_json)
+        .ok_or_else(|| invalid("position"))?;
+    Ok((path, position))
+}
+
+fn position_from_json(json: &Json) -> Option<Position> {
+    Some(Position {
+        line: json.get("line")?.as_u32()?,
+        character: json.get("character")?.as_u32()?,
+    })
+}
+
+fn position_json(position: Position) -> Json {
+    obj([
+        ("line", position.line.into()),
+        ("character", position.character.into()),
+    ])
+}
+
+fn range_json(range: Range) -> Json {
+    obj([
+        ("start", position_json(range.start)),
+        ("end", position_json(range.end)),
+    ])
+}
+
+fn publish_diagnostics(uri: &str, diagnostics: &[Diagnostic]) -> Json {
+    let diagnostics = diagnostics
+        .iter()
+        .map(|diagnostic| {
+            obj([
+                ("range", range_json(diagnostic.range)),
+                ("severity", (diagnostic.severity as u32).into()),
+                ("source", "imp".into()),
+                ("message", diagnostic.message.as_str().into()),
+            ])
+        })
+        .collect::<Vec<_>>();
+    obj([
+        ("jsonrpc", "2.0".into()),
+        ("method", "textDocument/publishDiagnostics".into()),
+        (
+            "params",
+            obj([("uri", uri.into()), ("diagnostics", diagnostics.into())]),
+        ),
+    ])
+}
+
+fn error_response(id: Json, code: i32, message: &str) -> Json {
+    obj([
+        ("jsonrpc", "2.0".into()),
+        ("id", id),
+        (
+            "error",
+            obj([
+                ("code", Json::Num(f64::from(code))),
+                ("message", message.into()),
+            ]),
+        ),
+    ])
+}
+
+/// The path of a `file://` URI.
+pub fn uri_to_path(uri: &str) -> Option<PathBuf> {
+    let encoded = uri.strip_prefix("file://")?;
+    let bytes = encoded.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut at = 0;
+    while at < bytes.len() {
+        if bytes[at] == b'%' {
+            let hex = encoded.get(at + 1..at + 3)?;
+            decoded.push(u8::from_str_radix(hex, 16).ok()?);
+            at += 3;
+        } else {
+            decoded.push(bytes[at]);
+            at += 1;
+        }
+    }
+    String::from_utf8(decoded).ok().map(PathBuf::from)
+}
+
+/// The `file://` URI of `path`.
+#[must_use]
+pub fn path_to_uri(path: &Path) -> String {
+    let mut uri = String::from("file://");
+    for byte in path.to_string_lossy().bytes() {
+        if byte.is_ascii_alphanumeric() || b"/-._~".contains(&byte) {
+            uri.push(byte as char);
+        } else {
+            let _ = write!(uri, "%{byte:02X}");
+        }
+    }
+    uri
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    fn scratch(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("imp_lsp_{name}"));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).expect("scratch dir");
+        dir.canonicalize().expect("canonical scratch dir")
+    }
+
+    fn at(line: u32, character: u32) -> Position {
+        Position { line, character }
+    }
+
+    const LIB: &str = "#call core::fn::begin name=main::double args=\"x\" retshape=\"scalar\";\n\
+                       #call core::mul a=arg::x b=arg::x out=return::0;\n\
+                       #call core::fn::end;\n\
+                       #call core::mod::export name=\"twice\" value=main::double;\n\
+                       #call core::mod::const name=\"LIMIT\" value=3;\n\
+                       #call core::exit;\n";
+
+    const MAIN: &str = "#call core::import alias=\"dep\" path=\"dep.imp\";\n\
+                        #call @timeout(ms=50) core::fn::begin name=main::inc args=\"x\";\n\
+                        #call core::const value=1 out=local::one;\n\
+                        #call core::add a=arg::x b=local::one out=return::0;\n\
+                        #call core::fn::end;\n\
+                        #call core::const value=2 out=local::two;\n\
+                        #call dep::twice args=\"local::two\" out=local::r;\n\
+                        #call main::inc args=\"local::r\" out=local::s;\n\
+                        #call core::exit;\n";
+
+    fn workspace(name: &str) -> (Workspace, PathBuf) {
+        let dir = scratch(name);
+        fs::write(dir.join("dep.imp"), LIB).expect("write dep");
+        let main = dir.join("main.imp");
+        let mut workspace = Workspace::default();
+        let diagnostics = workspace.open(&main, MAIN.to_owned());
+        assert!(diagnostics.is_empty(), "{diagnostics:?}");
+        (workspace, dir)
+    }
+
+    #[test]
+    fn diagnostics_point_at_the_failing_argument() {
+        let dir = scratch("diagnostics");
+        let main = dir.join("main.imp");
+        let mut workspace = Workspace::default();
+        let diagnostics = workspace.open(
+            &main,
+            "#call core::exit;\n#call core::add a=7 b=local::x out=local::y;\n".to_owned(),
+        );
+        assert_eq!(diagnostics.len(), 1, "{diagnostics:?}");
+        let diagnostic = &diagnostics[0];
+        assert_eq!(diagnostic.severity, Severity::Error);
+        assert_eq!(diagnostic.range.start.line, 1);
+        assert_eq!(diagnostic.range.start.character, 18);
+        assert_eq!(diagnostic.range.end.character, 19);
+
+        assert!(
+            workspace
+                .open(&main, "#call core::exit;\n".to_owned())
+                .is_empty()
+        );
+    }
+
+    #[test]
+    fn definition_follows_local_refs_imports_and_reexports() {
+        let (workspace, dir) = workspace("definition");
+        let main = dir.join("main.imp");
+
+        // The `main::inc` call target.
+        let local = workspace.definition(&main, at(7, 10)).expect("local");
+        assert_eq!(local.path, main);
+        assert_eq!(local.range.start, at(1, 43));
+
+        // `dep::twice` re-exports `main::double` in dep.imp.
+        let imported = workspace.definition(&main, at(6, 10)).expect("imported");
+        assert_eq!(imported.path, dir.join("dep.imp"));
+        assert_eq!(imported.range.start, at(0, 27));
+
+        let import = workspace.definition(&main, at(0, 40)).expect("import");
+        assert_eq!(import.path, dir.join("dep.imp"));
+        assert_eq!(workspace.definition(&main, at(2, 3)), None);
+    }
+
+    #[test]
+    fn hover_shows_fn_meta() {
+        let (workspace, dir) = workspace("hover");
+        let main = dir.join("main.imp");
+        let local = workspace.hover(&main, at(7, 10)).expect("local hover");
+        assert!(local.contains("main::inc(x) -> any"), "{local}");
+        assert!(local.contains("args: 1, returns: 1"), "{local}");
+        assert!(local.contains("timeout: 50ms"), "{local}");
+
+        let imported = workspace.hover(&main, at(6, 10)).expect("imported hover");
+        assert!(imported.contains("main::double(x) -> scalar"), "{imported}");
+    }
+
+    #[test]
+    fn completion_offers_core_targets_locals_and_imports() {
+        let (mut workspace, dir) = workspace("completion");
+        let main = dir.join("main.imp");
+        let text = format!("{MAIN}#call core::fn::be");
+        workspace.open(&main, text);
+        let completions = workspace.completions(&main, at(9, 19));
+        let labels = completions
+            .iter()
+            .map(|completion| completion.label.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(labels, ["core::fn::begin"]);
+        assert_eq!(completions[0].range.start, at(9, 6));
+
+        let text = format!("{MAIN}#call dep::");
+        workspace.open(&main, text);
+        let completions = workspace.completions(&main, at(9, 11));
+        let labels = completions
+            .iter()
+            .map(|completion| completion.label.as_str())
+            .collect::<Vec<_>>();
+        // An explicit `core::mod::export` replaces the implicit ones.
+        assert_eq!(labels, ["dep::twice", "dep::LIMIT"]);
+        let twice = completions
+            .iter()
+            .find(|completion| completion.label == "dep::twice")
+            .expect("twice");
+        assert_eq!(twice.detail.as_deref(), Some("main::double(x) -> scalar"));
+
+        let text = format!("{MAIN}#call main::");
+        workspace.open(&main, text);
+        let labels = workspace
+            .completions(&main, at(9, 12))
+            .into_iter()
+            .map(|completion| completion.label)
+            .collect::<Vec<_>>();
+        assert_eq!(labels, ["main::inc"]);
+    }
+
+    #[test]
+    fn serve_answers_initialize_and_publishes_diagnostics() {
+        let dir = scratch("serve");
+        let uri = path_to_uri(&dir.join("my file.imp"));
+        assert!(uri.ends_with("/my%20file.imp"));
+        assert_eq!(uri_to_path(&uri), Some(dir.join("my file.imp")));
+
+        let messages = [
+            r#"{"jsonrpc":"2.0","id":1,"method":"initialize","params":{}}"#.to_owned(),
+            format!(
+                r##"{{"jsonrpc":"2.0","method":"textDocument/didOpen","params":{{"textDocument":{{"uri":"{uri}","languageId":"imp","version":1,"text":"#call core::bogus;\n"}}}}}}"##
+            ),
+            r#"{"jsonrpc":"2.0","id":2,"method":"workspace/symbol","params":{}}"#.to_owned(),
+            r#"{"jsonrpc":"2.0","id":3,"method":"shutdown"}"#.to_owned(),
+            r#"{"jsonrpc":"2.0","method":"exit"}"#.to_owned(),
+        ];
+        let mut input = Vec::new();
+        for message in &messages {
+            write!(input, "Content-Length: {}\r\n\r\n{message}", message.len()).expect("frame");
+        }
+        let mut output = Vec::new();
+        serve(&input[..], &mut output).expect("serve");
+
+        let mut output = &output[..];
+        let mut replies = Vec::new();
+        while let Some(body) = read_message(&mut output).expect("read reply") {
+            replies.push(Json::parse(&body).expect("reply json"));
+        }
+        assert_eq!(replies.len(), 4);
+        let capabilities = replies[0]
+            .get("result")
+            .and_then(|result| result.get("capabilities"))
+            .expect("capabilities");
+        assert_eq!(
+            capabilities.get("definitionProvider"),
+            Some(&Json::Bool(true))
+        );
+        let published = replies[1].get("params").expect("diagnostics params");
+        assert_eq!(published.get("uri").and_then(Json::as_str), Some(&*uri));
+        let diagnostics = published.get("diagnostics").map_or(&[][..], Json::as_array);
+        assert_eq!(diagnostics.len(), 1);
+        let code = replies[2]
+            .get("error")
+            .and_then(|error| error.get("code"))
+            .and_then(Json::as_f64);
+        assert_eq!(code, Some(f64::from(METHOD_NOT_FOUND)));
+        assert_eq!(replies[3].get("result"), Some(&Json::Null));
+    }
+
+    #[test]
+    fn read_message_refuses_oversized_content_length() {
+        let huge = format!("Content-Length: {}\r\n\r\n{{}}", usize::MAX);
+        let err = read_message(&mut huge.as_bytes()).expect_err("oversized frame");
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        let limit = format!("Content-Length: {}\r\n\r\n", MAX_MESSAGE_BYTES + 1);
+        assert!(read_message(&mut limit.as_bytes()).is_err());
+        let small = "Content-Length: 2\r\n\r\n{}";
+        assert_eq!(
+            read_message(&mut small.as_bytes()).expect("small frame"),
+            Some("{}".to_owned())
+        );
+    }
+}
@@ -1,5 +1,135 @@
+pub mod json;
+mod sha256;
+
+pub use sha256::{sha256, sha256_hex};
+
 pub const ANNO_SAFE: &str = "safe";
+pub const ANNO_TIMEOUT: &str = "timeout";
+pub const ANNO_TEST: &str = "test";
+pub const ANNO_INLINE: &str = "inline";
+
+/// Ref namespaces with built-in meaning, which import aliases and function
+/// names may not use.
+pub const RESERVED_NAMESPACES: &[&str] = &["local", "arg", "return", "err", "core"];
+
+/// Every `core::` call target the compiler lowers, sorted; for editor
+/// completion.
+pub const CORE_TARGETS: &[&str] = &[
+    "core::add",
+    "core::br",
+    "core::bytes::concat",
+    "core::bytes::from_str",
+    "core::bytes::len",
+    "core::bytes::slice",
+    "core::bytes::to_str",
+    "core::cmp",
+    "core::const",
+    "core::div",
+    "core::eq",
+    "core::err::code",
+    "core::err::data",
+    "core::err::msg",
+    "core::error::cause",
+    "core::error::code",
+    "core::error::data",
+    "core::error::msg",
+    "core::error::wrap",
+    "core::exit",
+    "core::fn::begin",
+    "core::fn::bind",
+    "core::fn::end",
+    "core::fn::lambda",
+    "core::fn::meta",
+    "core::ge",
+    "core::gt",
+    "core::host::args",
+    "core::host::call",
+    "core::host::env",
+    "core::host::eprint",
+    "core::host::fs::exists",
+    "core::host::fs::read",
+    "core::host::fs::write",
+    "core::host::monotonic",
+    "core::host::now",
+    "core::host::print",
+    "core::host::random",
+    "core::host::random_int",
+    "core::host::read_all",
+    "core::host::read_line",
+    "core::import",
+    "core::int::parse",
+    "core::invoke",
+    "core::invoke::dynamic",
+    "core::is::bool",
+    "core::is::bytes",
+    "core::is::closure",
+    "core::is::error",
+    "core::is::func",
+    "core::is::handle",
+    "core::is::int",
+    "core::is::list",
+    "core::is::null",
+    "core::is::num",
+    "core::is::obj",
+    "core::is::str",
+    "core::json::parse",
+    "core::json::stringify",
+    "core::jump",
+    "core::label",
+    "core::le",
+    "core::list::get",
+    "core::list::len",
+    "core::list::new",
+    "core::list::push",
+    "core::list::set",
+    "core::lt",
+    "core::mod",
+    "core::mod::const",
+    "core::mod::export",
+    "core::mov",
+    "core::mul",
+    "core::ne",
+    "core::neg",
+    "core::num::format",
+    "core::num::parse",
+    "core::obj::del",
+    "core::obj::entries",
+    "core::obj::get",
+    "core::obj::getpath",
+    "core::obj::has",
+    "core::obj::keys",
+    "core::obj::len",
+    "core::obj::new",
+    "core::obj::set",
+    "core::obj::setpath",
+    "core::pow",
+    "core::ret::set",
+    "core::rethrow",
+    "core::str::concat",
+    "core::str::contains",
+    "core::str::find",
+    "core::str::format",
+    "core::str::join",
+    "core::str::len",
+    "core::str::lower",
+    "core::str::replace",
+    "core::str::split",
+    "core::str::sub",
+    "core::str::trim",
+    "core::str::trim_end",
+    "core::str::trim_start",
+    "core::str::upper",
+    "core::sub",
+    "core::throw",
+    "core::try::begin",
+    "core::try::end",
+    "core::try::finally",
+    "core::try::pop",
+    "core::try::push",
+    "core::type_of",
+];
 
+#[must_use]
 pub fn is_core_target(target: &str) -> bool {
     target.starts_with("core::")
 }
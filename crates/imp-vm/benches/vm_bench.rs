@@ -67,6 +67,7 @@ fn compile_bench_module(src: &str) -> CompiledModule {
         src,
         CompileOpts {
             module_name: "bench".to_owned(),
+            ..CompileOpts::default()
         },
     )
     .expect("compile benchmark program")
@@ -74,10 +75,10 @@ fn compile_bench_module(src: &str) -> CompiledModule {
 }
 
 fn run_module(module: &CompiledModule, enable_jit: bool) -> Value {
-    let mut vm = Vm::new(VmConfig {
-        enable_host_print: false,
-        enable_jit,
-    });
+    let mut cfg = VmConfig::default();
+    cfg.enable_host_print = false;
+    cfg.enable_jit = enable_jit;
+    let mut vm = Vm::new(cfg);
     let result = vm
         .run_main(black_box(module))
         .expect("run benchmark program");
@@ -86,10 +87,10 @@ fn run_module(module: &CompiledModule, enable_jit: bool) -> Value {
 
 fn bench_program(c: &mut Criterion, name: &str, src: &'static str) {
     let module = compile_bench_module(src);
-    bench_compiled_module(c, name, module);
+    bench_compiled_module(c, name, &module);
 }
 
-fn bench_compiled_module(c: &mut Criterion, name: &str, module: CompiledModule) {
+fn bench_compiled_module(c: &mut Criterion, name: &str, module: &CompiledModule) {
     let mut group = c.benchmark_group(name);
     group.sample_size(30);
 
@@ -99,7 +100,7 @@ fn bench_compiled_module(c: &mut Criterion, name: &str, module: CompiledModule)
             &enable_jit,
             |b, &jit| {
                 b.iter(|| {
-                    let value = run_module(&module, jit);
+                    let value = run_module(module, jit);
                     black_box(value)
                 });
             },
@@ -157,7 +158,7 @@ fn vm_benchmarks(c: &mut Criterion) {
     bench_compiled_module(
         c,
         "module_invoke_chain",
-        compile_import_invoke_chain_module(),
+        &compile_import_invoke_chain_module(),
     );
 }
 
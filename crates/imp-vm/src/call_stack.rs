@@ -0,0 +1,577 @@
+use crate::{
+    ErrorDisposition, ForeignFunc, Frame, JitFunction, ShadowRun, TraceFrame, Value, Vm, VmError,
+    describe_divergence, validate_branch_targets,
+};
+use imp_ir::{CompiledModule, FuncId, Slot};
+use std::fmt;
+use std::rc::Rc;
+use std::sync::Arc;
+
+/// Why a frame's run loop handed control back to the driver.
+pub(crate) enum FrameExit {
+    Return(Vec<Value>),
+    /// `Invoke` of `func`. The caller resumes at `pc + 1` with the callee's
+    /// return values in `out`.
+    Call {
+        func: FuncId,
+        args: Vec<Value>,
+        out: CallOut,
+    },
+    /// `TailInvoke` of `func` with no handler pushed: the callee's frame
+    /// takes the place of the current one.
+    TailCall {
+        func: FuncId,
+        args: Vec<Value>,
+    },
+    /// The step budget of a host-driven run is spent; the frame resumes at
+    /// its `pc`.
+    Yield,
+}
+
+/// Activations of one `execute_function`, innermost last.
+///
+/// `Invoke` pushes onto this stack instead of recursing, so imp call depth is
+/// bounded by `VmConfig.max_call_depth` rather than by the host stack.
+/// Caller slots for a callee's return values.
+#[derive(Debug, Clone)]
+pub(crate) enum CallOut {
+    /// `Invoke`: the first return value.
+    One(Slot),
+    /// `InvokeMulti`: return values in order, null past the last one.
+    Many(Rc<[Slot]>),
+}
+
+impl CallOut {
+    fn deliver(&self, frame: &mut Frame, values: Vec<Value>, globals: &mut [Value]) {
+        match self {
+            Self::One(out) => {
+                let value = values.into_iter().next().unwrap_or(Value::Null);
+                frame.set(*out, value, globals);
+            }
+            Self::Many(outs) => {
+                let mut values = values.into_iter();
+                for out in outs.iter() {
+                    frame.set(*out, values.next().unwrap_or(Value::Null), globals);
+                }
+            }
+        }
+    }
+}
+
+#[derive(Default, Clone)]
+pub(crate) struct CallStack {
+    frames: Vec<Activation>,
+    /// Module and globals of calls into other modules. Activations refer to
+    /// these by index; `None` is the module the stack was entered with.
+    contexts: Vec<Context>,
+    /// Final pc of the outermost frame, once it has finished.
+    root_pc: usize,
+}
+
+/// Activations under the running frame of every call stack being driven,
+/// outermost stack first. They are parked in the VM while that frame runs
+/// so `VmConfig.max_memory` can measure what they hold.
+#[derive(Default, Clone)]
+pub(crate) struct Suspended(Vec<Vec<Activation>>);
+
+impl Suspended {
+    /// Every register of the parked frames.
+    pub(crate) fn values(&self) -> impl Iterator<Item = &Value> {
+        self.0
+            .iter()
+            .flatten()
+            .flat_map(|activation| activation.frame.regs.iter())
+    }
+}
+
+impl fmt::Debug for Suspended {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let frames = self.0.iter().map(Vec::len).sum::<usize>();
+        write!(f, "Suspended({frames} frames)")
+    }
+}
+
+#[derive(Clone)]
+struct Context {
+    module: Arc<CompiledModule>,
+    globals: Vec<Value>,
+    /// Instance the globals were checked out of, returned on pop.
+    instance: Option<String>,
+}
+
+#[derive(Clone)]
+struct Activation {
+    frame: Frame,
+    /// Plan to run the frame on; `None` runs the interpreter.
+    jit: Option<Rc<JitFunction>>,
+    context: Option<usize>,
+    /// Whether this activation pushed `context` and pops it on return.
+    owns_context: bool,
+    /// Caller slots for the return values; `None` for the outermost call.
+    out: Option<CallOut>,
+    shadow: Option<ShadowRun>,
+    /// The frame was replaced by a tail call, so the activation returns only
+    /// the first value, as the `Invoke` into `return::0` would have.
+    tail: bool,
+}
+
+fn context_mut<'a>(
+    contexts: &'a mut [Context],
+    index: Option<usize>,
+    module: &'a CompiledModule,
+    globals: &'a mut [Value],
+) -> (&'a CompiledModule, &'a mut [Value]) {
+    match index {
+        Some(index) => {
+            let context = &mut contexts[index];
+            (&context.module, &mut context.globals)
+        }
+        None => (module, globals),
+    }
+}
+
+/// Hands `err` from a call that never started to the frame that made it.
+fn catch_in_caller(
+    stack: &mut CallStack,
+    module: &CompiledModule,
+    globals: &mut [Value],
+    err: VmError,
+) -> Result<(), VmError> {
+    let caller = stack.frames.last_mut().expect("caller is on the stack");
+    let (_, caller_globals) = context_mut(&mut stack.contexts, caller.context, module, globals);
+    caller.frame.catch(err, caller_globals)
+}
+
+impl CallStack {
+    /// The activation `depth` below the innermost, with the module and
+    /// globals it runs on.
+    pub(crate) fn activation<'a>(
+        &'a self,
+        depth: usize,
+        module: &'a CompiledModule,
+        globals: &'a [Value],
+    ) -> Option<(&'a Frame, &'a CompiledModule, &'a [Value])> {
+        let activation = self.frames.iter().rev().nth(depth)?;
+        Some(match activation.context {
+            Some(index) => {
+                let context = &self.contexts[index];
+                (&activation.frame, &context.module, &context.globals)
+            }
+            None => (&activation.frame, module, globals),
+        })
+    }
+
+    /// Activations, innermost first, as trace frames.
+    pub(crate) fn trace(&self) -> Vec<TraceFrame> {
+        self.frames
+            .iter()
+            .rev()
+            .map(|activation| TraceFrame::of(&activation.frame))
+            .collect()
+    }
+}
+
+impl Vm {
+    pub(crate) fn execute_function(
+        &mut self,
+        module: &CompiledModule,
+        func_id: FuncId,
+        args: &[Value],
+        globals: &mut [Value],
+    ) -> Result<Vec<Value>, VmError> {
+        let mut stack = CallStack::default();
+        self.push_call(&mut stack, module, globals, func_id, args.to_vec(), None)?;
+        self.run_call_stack(&mut stack, module, globals)
+    }
+
+    /// Runs an already prepared `frame` on the interpreter; also returns the
+    /// pc it finished at.
+    pub(crate) fn run_interpreter_frame(
+        &mut self,
+        module: &CompiledModule,
+        frame: Frame,
+        globals: &mut [Value],
+    ) -> (Result<Vec<Value>, VmError>, usize) {
+        let mut stack = CallStack::default();
+        self.push_activation(
+            &mut stack,
+            Activation {
+                frame,
+                jit: None,
+                context: None,
+                owns_context: false,
+                out: None,
+                shadow: None,
+                tail: false,
+            },
+        );
+        let result = self.run_call_stack(&mut stack, module, globals);
+        (result, stack.root_pc)
+    }
+
+    fn push_activation(&mut self, stack: &mut CallStack, activation: Activation) {
+        self.call_depth += 1;
+        self.stats.peak_call_depth = self.stats.peak_call_depth.max(self.call_depth);
+        stack.frames.push(activation);
+    }
+
+    /// Drops a stack that will not run again, handing back the instance
+    /// globals it had checked out.
+    pub(crate) fn abandon_call_stack(&mut self, stack: CallStack) {
+        self.call_depth -= stack.frames.len();
+        for activation in &stack.frames {
+            self.profile_leave(&activation.frame);
+        }
+        for context in stack.contexts {
+            self.leave_module(context);
+        }
+    }
+
+    /// Resolves `func_id` (bound handles, imported functions) from the
+    /// innermost activation and pushes its frame. Nothing is pushed on error.
+    pub(crate) fn push_call(
+        &mut self,
+        stack: &mut CallStack,
+        module: &CompiledModule,
+        globals: &[Value],
+        mut func_id: FuncId,
+        mut args: Vec<Value>,
+        out: Option<CallOut>,
+    ) -> Result<(), VmError> {
+        let caller_context = stack.frames.last().and_then(|caller| caller.context);
+        let mut foreign: Option<ForeignFunc> = None;
+        loop {
+            if let Some(bound) = self.bound_funcs.get(&func_id) {
+                args.splice(0..0, bound.args.iter().cloned());
+                func_id = bound.target;
+                continue;
+            }
+            let current = match (&foreign, caller_context) {
+                (Some(target), _) => target.module.as_ref(),
+                (None, Some(index)) => stack.contexts[index].module.as_ref(),
+                (None, None) => module,
+            };
+            if current.function(func_id).is_some() {
+                break;
+            }
+            let Some(target) = self.foreign_funcs.get(&func_id).cloned() else {
+                return Err(self.unknown_function(func_id));
+            };
+            let caller_module = Arc::new(current.clone());
+            for value in &mut args {
+                *value = self.bridge_value_for_module(&caller_module, value);
+            }
+            func_id = target.func_id;
+            foreign = Some(target);
+        }
+
+        let current = match (&foreign, caller_context) {
+            (Some(target), _) => target.module.as_ref(),
+            (None, Some(index)) => stack.contexts[index].module.as_ref(),
+            (None, None) => module,
+        };
+        let function = current
+            .function(func_id)
+            .ok_or_else(|| self.unknown_function(func_id))?;
+        let use_jit = self.cfg.enable_jit && self.shadow_runs == 0;
+        let jit = if use_jit {
+            Some(self.get_or_compile_jit(current, function)?)
+        } else {
+            validate_branch_targets(&function.meta.name, &function.code)?;
+            None
+        };
+
+        // A call into another module runs on that module's globals: a context
+        // already on this stack, its instance, or (for callbacks) fresh ones.
+        let (context, owns_context) = match foreign {
+            None => (caller_context, false),
+            Some(target) => {
+                if let Some(index) = stack.contexts.iter().position(|context| {
+                    context.instance.is_some() && context.instance == target.instance
+                }) {
+                    (Some(index), false)
+                } else {
+                    let context = self.enter_module(target)?;
+                    stack.contexts.push(context);
+                    (Some(stack.contexts.len() - 1), true)
+                }
+            }
+        };
+        let (current, current_globals) = match context {
+            Some(index) => {
+                let context = &stack.contexts[index];
+                (context.module.as_ref(), context.globals.as_slice())
+            }
+            None => (module, globals),
+        };
+        let function = current
+            .function(func_id)
+            .ok_or_else(|| self.unknown_function(func_id))?;
+        let shadow = (use_jit && self.cfg.verify_jit)
+            .then(|| self.run_shadow_interpreter(current, function, &args, current_globals));
+        let mut frame = Frame::new(function, &args);
+        let caller_deadline = stack
+            .frames
+            .last()
+            .and_then(|caller| caller.frame.deadline.as_ref());
+        frame.deadline = self.frame_deadline(&function.meta, caller_deadline);
+        self.recycle_args(args);
+
+        self.push_activation(
+            stack,
+            Activation {
+                frame,
+                jit,
+                context,
+                owns_context,
+                out,
+                shadow,
+                tail: false,
+            },
+        );
+        Ok(())
+    }
+
+    /// Replaces the innermost frame with one for `func`, keeping its
+    /// activation's caller slots and context. A call that leaves the
+    /// activation's module is pushed as an `Invoke` into `return::0` instead;
+    /// the `core::exit` after it returns the value.
+    fn tail_call(
+        &mut self,
+        stack: &mut CallStack,
+        module: &CompiledModule,
+        globals: &[Value],
+        mut func_id: FuncId,
+        mut args: Vec<Value>,
+    ) -> Result<(), VmError> {
+        while let Some(bound) = self.bound_funcs.get(&func_id) {
+            args.splice(0..0, bound.args.iter().cloned());
+            func_id = bound.target;
+        }
+        let top = stack.frames.last_mut().expect("caller is on the stack");
+        let current = match top.context {
+            Some(index) => stack.contexts[index].module.as_ref(),
+            None => module,
+        };
+        let Some(function) = current.function(func_id) else {
+            if let Some(msg) = self.stack_overflow_message() {
+                return Err(VmError::Thrown {
+                    code: Arc::from("stack_overflow"),
+                    msg: Arc::from(msg.as_str()),
+                    cause: None,
+                    data: None,
+                    traceback: Vec::new(),
+                });
+            }
+            let out = Some(CallOut::One(Slot::Ret(0)));
+            return self.push_call(stack, module, globals, func_id, args, out);
+        };
+        let jit = if self.cfg.enable_jit && self.shadow_runs == 0 {
+            Some(self.get_or_compile_jit(current, function)?)
+        } else {
+            validate_branch_targets(&function.meta.name, &function.code)?;
+            None
+        };
+        let mut frame = Frame::new(function, &args);
+        frame.deadline = self.frame_deadline(&function.meta, top.frame.deadline.as_ref());
+        self.recycle_args(args);
+        self.stats.throws += top.frame.throws;
+        self.profile_leave(&top.frame);
+        top.frame = frame;
+        top.jit = jit;
+        top.tail = true;
+        Ok(())
+    }
+
+    /// Globals for a call into `target`'s module. An import's instance
+    /// globals are checked out until [`Self::leave_module`]; shadow runs work
+    /// on a copy so they leave no trace.
+    fn enter_module(&mut self, target: ForeignFunc) -> Result<Context, VmError> {
+        let shadow = self.shadow_runs > 0;
+        let checked_out = target
+            .instance
+            .as_ref()
+            .and_then(|path| self.instances.get_mut(path))
+            .and_then(|instance| {
+                if shadow {
+                    instance.globals.clone()
+                } else {
+                    instance.globals.take()
+                }
+            });
+        let (globals, instance) = match checked_out {
+            Some(globals) => (globals, target.instance.filter(|_| !shadow)),
+            // Callbacks, or an instance held by an enclosing run.
+            None => (self.build_module_globals(&target.module)?, None),
+        };
+        Ok(Context {
+            module: target.module,
+            globals,
+            instance,
+        })
+    }
+
+    /// Closes `frame`'s profiler activation, if it opened one.
+    fn profile_leave(&mut self, frame: &Frame) {
+        if let Some(slot) = frame.profile_slot {
+            let now = self.env().monotonic();
+            self.profiler.leave(slot, now);
+        }
+    }
+
+    fn leave_module(&mut self, context: Context) {
+        if let Some(instance) = context
+            .instance
+            .and_then(|path| self.instances.get_mut(&path))
+        {
+            instance.globals = Some(context.globals);
+        }
+    }
+
+    /// Lets `VmConfig.on_uncaught` raise or replace an error that escaped a
+    /// run's outermost frame. Shadow runs and errors imp code cannot raise
+    /// (fuel, bad bytecode) always raise.
+    fn dispose_uncaught(&self, result: Result<Vec<Value>, VmError>) -> Result<Vec<Value>, VmError> {
+        let (Err(err), Some(hook)) = (&result, &self.cfg.on_uncaught) else {
+            return result;
+        };
+        if self.shadow_runs > 0 {
+            return result;
+        }
+        let (code, msg) = match err {
+            VmError::Thrown { code, msg, .. } => (code.as_ref(), msg.as_ref()),
+            VmError::Runtime { msg, .. } => ("runtime", msg.as_str()),
+            _ => return result,
+        };
+        match hook(code, msg, err.traceback()) {
+            ErrorDisposition::Raise => result,
+            ErrorDisposition::Return(values) => Ok(values),
+        }
+    }
+
+    /// Runs `stack` to completion. Nested runs (imports, shadow runs,
+    /// `invoke` from host code) finish even inside a host-stepped run.
+    fn run_call_stack(
+        &mut self,
+        stack: &mut CallStack,
+        module: &CompiledModule,
+        globals: &mut [Value],
+    ) -> Result<Vec<Value>, VmError> {
+        let budget = self.step_budget.take();
+        let result = self.resume_call_stack(stack, module, globals);
+        self.step_budget = budget;
+        result.map(|returns| returns.expect("runs without a step budget never pause"))
+    }
+
+    /// Runs `stack` until the outermost frame returns, or until the step
+    /// budget is spent (`None`, with the stack left ready to resume).
+    pub(crate) fn resume_call_stack(
+        &mut self,
+        stack: &mut CallStack,
+        module: &CompiledModule,
+        globals: &mut [Value],
+    ) -> Result<Option<Vec<Value>>, VmError> {
+        loop {
+            let mut top = stack
+                .frames
+                .pop()
+                .expect("call stack is non-empty while running");
+            let (top_module, top_globals) =
+                context_mut(&mut stack.contexts, top.context, module, globals);
+            if self.cfg.profile && self.shadow_runs == 0 && top.frame.profile_slot.is_none() {
+                let now = self.env().monotonic();
+                top.frame.profile_slot = Some(self.profiler.enter(
+                    &top_module.name,
+                    &top.frame.meta.name,
+                    now,
+                ));
+            }
+            self.suspended.0.push(std::mem::take(&mut stack.frames));
+            let exit = match &top.jit {
+                Some(jit) => {
+                    self.execute_function_jit(top_module, &mut top.frame, top_globals, jit)
+                }
+                None => self.execute_function_interpreter(top_module, &mut top.frame, top_globals),
+            };
+            stack.frames = self.suspended.0.pop().expect("parked above");
+            stack.frames.push(top);
+            let mut result = match exit {
+                Ok(FrameExit::Call { func, args, out }) => {
+                    let Err(err) = self.push_call(stack, module, globals, func, args, Some(out))
+                    else {
+                        continue;
+                    };
+                    // The callee never started; the invoking frame handles it.
+                    match catch_in_caller(stack, module, globals, err) {
+                        Ok(()) => continue,
+                        Err(err) => Err(err),
+                    }
+                }
+                Ok(FrameExit::TailCall { func, args }) => {
+                    let Err(err) = self.tail_call(stack, module, globals, func, args) else {
+                        continue;
+                    };
+                    match catch_in_caller(stack, module, globals, err) {
+                        Ok(()) => continue,
+                        Err(err) => Err(err),
+                    }
+                }
+                Ok(FrameExit::Yield) => return Ok(None),
+                Ok(FrameExit::Return(values)) => Ok(values),
+                Err(err) => Err(err),
+            };
+
+            // Pop finished frames until one resumes or the stack is empty.
+            loop {
+                let done = stack.frames.pop().expect("finished frame is on the stack");
+                self.call_depth -= 1;
+                self.stats.throws += done.frame.throws;
+                self.profile_leave(&done.frame);
+                if let Err(err) = &mut result {
+                    err.push_trace(&done.frame);
+                }
+                if done.tail
+                    && let Ok(values) = &mut result
+                {
+                    values.resize(1, Value::Null);
+                }
+                if let Some(shadow) = &done.shadow {
+                    let (_, done_globals) =
+                        context_mut(&mut stack.contexts, done.context, module, globals);
+                    if let Some(detail) = describe_divergence(&result, done_globals, shadow) {
+                        result = Err(VmError::JitDivergence {
+                            function: Arc::clone(&done.frame.meta.name),
+                            pc: done.frame.pc,
+                            detail,
+                        });
+                    }
+                }
+                if done.owns_context
+                    && let Some(context) = stack.contexts.pop()
+                {
+                    self.leave_module(context);
+                }
+
+                let Some(caller) = stack.frames.last_mut() else {
+                    stack.root_pc = done.frame.pc;
+                    return self.dispose_uncaught(result).map(Some);
+                };
+                let (_, caller_globals) =
+                    context_mut(&mut stack.contexts, caller.context, module, globals);
+                match result {
+                    Ok(values) => {
+                        if let Some(out) = &done.out {
+                            out.deliver(&mut caller.frame, values, caller_globals);
+                        }
+                        caller.frame.pc += 1;
+                        break;
+                    }
+                    Err(err) => match caller.frame.catch(err, caller_globals) {
+                        Ok(()) => break,
+                        Err(err) => result = Err(err),
+                    },
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,29 @@
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+/// Flag a host trips, from any thread, to stop the runs of a VM whose
+/// `VmConfig.cancel` holds a clone. Runs notice it within a few hundred
+/// instructions and fail with `VmError::Cancelled`.
+#[derive(Debug, Clone, Default)]
+pub struct CancelToken(Arc<AtomicBool>);
+
+impl CancelToken {
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+
+    #[must_use]
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+
+    /// Clears the flag so later runs go ahead.
+    pub fn reset(&self) {
+        self.0.store(false, Ordering::Relaxed);
+    }
+}
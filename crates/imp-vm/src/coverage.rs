@@ -0,0 +1,188 @@
+use crate::count_num;
+use imp_ir::{CompiledModule, DebugInfo, Instr};
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::Write;
+use std::sync::Arc;
+
+/// Instruction hit counts for one function.
+#[derive(Debug, Clone)]
+pub struct FunctionCoverage {
+    pub module: Arc<str>,
+    pub function: Arc<str>,
+    pub code: Arc<[Instr]>,
+    /// Executions per pc, parallel to `code`.
+    pub hits: Vec<u64>,
+    /// Source lines of `code`, when the function was compiled with them.
+    pub debug: Option<Arc<DebugInfo>>,
+}
+
+impl FunctionCoverage {
+    #[must_use]
+    pub fn covered(&self) -> usize {
+        self.hits.iter().filter(|hits| **hits > 0).count()
+    }
+}
+
+/// Coverage accumulated by a VM with `VmConfig.coverage` enabled.
+///
+/// Every function of a module is listed once any of its code runs, so
+/// functions that were never called show up as fully uncovered.
+#[derive(Debug, Clone, Default)]
+pub struct Coverage {
+    functions: Vec<FunctionCoverage>,
+    index: HashMap<Arc<str>, HashMap<Arc<str>, usize>>,
+}
+
+impl Coverage {
+    #[must_use]
+    pub fn functions(&self) -> &[FunctionCoverage] {
+        &self.functions
+    }
+
+    pub fn covered(&self) -> usize {
+        self.functions.iter().map(FunctionCoverage::covered).sum()
+    }
+
+    #[must_use]
+    pub fn total(&self) -> usize {
+        self.functions
+            .iter()
+            .map(|function| function.hits.len())
+            .sum()
+    }
+
+    pub(crate) fn record(&mut self, module: &CompiledModule, function: &str, pc: usize) {
+        if !self.index.contains_key(module.name.as_ref()) {
+            self.register(module);
+        }
+        let slot = self.index[module.name.as_ref()].get(function).copied();
+        if let Some(hits) = slot.and_then(|slot| self.functions[slot].hits.get_mut(pc)) {
+            *hits += 1;
+        }
+    }
+
+    /// Adds `other`'s hits, as when each test of a suite ran on its own VM.
+    pub fn merge(&mut self, other: &Coverage) {
+        for function in &other.functions {
+            let slot = self
+                .index
+                .get(function.module.as_ref())
+                .and_then(|by_name| by_name.get(function.function.as_ref()))
+                .copied();
+            if let Some(slot) = slot {
+                for (total, hits) in self.functions[slot].hits.iter_mut().zip(&function.hits) {
+                    *total += hits;
+                }
+            } else {
+                self.index
+                    .entry(Arc::clone(&function.module))
+                    .or_default()
+                    .insert(Arc::clone(&function.function), self.functions.len());
+                self.functions.push(function.clone());
+            }
+        }
+    }
+
+    fn register(&mut self, module: &CompiledModule) {
+        let mut by_name = HashMap::new();
+        for function in &module.functions {
+            by_name.insert(Arc::clone(&function.meta.name), self.functions.len());
+            self.functions.push(FunctionCoverage {
+                module: Arc::clone(&module.name),
+                function: Arc::clone(&function.meta.name),
+                code: Arc::clone(&function.code),
+                hits: vec![0; function.code.len()],
+                debug: function.meta.debug.clone(),
+            });
+        }
+        self.index.insert(Arc::clone(&module.name), by_name);
+    }
+
+    /// Annotated IR listing in gcov style: hit count (or `#####` when never
+    /// executed), pc, instruction; followed by a per-function and total summary.
+    #[must_use]
+    pub fn report(&self) -> String {
+        let mut out = String::new();
+        for function in &self.functions {
+            let _ = writeln!(
+                out,
+                "{}: fn {} {}/{}",
+                function.module,
+                function.function,
+                function.covered(),
+                function.hits.len()
+            );
+            for (pc, (instr, hits)) in function.code.iter().zip(&function.hits).enumerate() {
+                let count = if *hits == 0 {
+                    "#####".to_owned()
+                } else {
+                    hits.to_string()
+                };
+                let _ = writeln!(out, "  {count:>8}  {pc:04}: {instr:?}");
+            }
+        }
+        let total = self.total();
+        let percent = if total == 0 {
+            100.0
+        } else {
+            count_num(self.covered()) * 100.0 / count_num(total)
+        };
+        let _ = writeln!(
+            out,
+            "coverage: {}/{total} instructions ({percent:.1}%)",
+            self.covered()
+        );
+        out
+    }
+
+    /// An lcov tracefile (`genhtml`, editor coverage gutters) with one
+    /// record per source file. A line's count is the most any of its
+    /// instructions ran, and a function's is how often its first
+    /// instruction ran. Functions without debug info have no lines to map
+    /// and are left out; source compiled from a string is named after its
+    /// module.
+    #[must_use]
+    pub fn lcov(&self) -> String {
+        let mut files: Vec<(Arc<str>, Vec<&FunctionCoverage>)> = Vec::new();
+        for function in &self.functions {
+            let Some(debug) = &function.debug else {
+                continue;
+            };
+            let source = debug
+                .source
+                .clone()
+                .unwrap_or_else(|| Arc::clone(&function.module));
+            match files.iter_mut().find(|(path, _)| *path == source) {
+                Some((_, functions)) => functions.push(function),
+                None => files.push((source, vec![function])),
+            }
+        }
+        let mut out = String::new();
+        for (source, functions) in files {
+            let _ = writeln!(out, "TN:\nSF:{source}");
+            let mut lines = BTreeMap::new();
+            let mut entered = 0;
+            for function in &functions {
+                let Some(debug) = &function.debug else {
+                    continue;
+                };
+                for (line, hits) in debug.lines.iter().zip(&function.hits) {
+                    let count = lines.entry(*line).or_insert(0);
+                    *count = (*count).max(*hits);
+                }
+                let first = debug.lines.iter().min().copied().unwrap_or(1);
+                let calls = function.hits.first().copied().unwrap_or(0);
+                entered += usize::from(calls > 0);
+                let name = &function.function;
+                let _ = writeln!(out, "FN:{first},{name}\nFNDA:{calls},{name}");
+            }
+            let _ = writeln!(out, "FNF:{}\nFNH:{entered}", functions.len());
+            for (line, hits) in &lines {
+                let _ = writeln!(out, "DA:{line},{hits}");
+            }
+            let hit = lines.values().filter(|hits| **hits > 0).count();
+            let _ = writeln!(out, "LF:{}\nLH:{hit}\nend_of_record", lines.len());
+        }
+        out
+    }
+}
@@ -0,0 +1,204 @@
+use crate::Value;
+use std::fmt::{self, Write};
+
+/// Rendering limits for [`Value::display_with`].
+///
+/// `None` means unlimited. `indent: Some(n)` switches to multi-line output
+/// with `n` spaces per nesting level.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct DisplayOptions {
+    pub max_depth: Option<usize>,
+    pub max_items: Option<usize>,
+    pub max_str_chars: Option<usize>,
+    pub indent: Option<usize>,
+}
+
+pub struct ValueDisplay<'a> {
+    value: &'a Value,
+    opts: DisplayOptions,
+}
+
+impl Value {
+    #[must_use]
+    pub fn display_with(&self, opts: DisplayOptions) -> ValueDisplay<'_> {
+        ValueDisplay { value: self, opts }
+    }
+
+    #[must_use]
+    pub fn pretty(&self, indent: usize) -> String {
+        self.display_with(DisplayOptions {
+            indent: Some(indent),
+            ..DisplayOptions::default()
+        })
+        .to_string()
+    }
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write_value(f, self, &DisplayOptions::default(), 0, &mut Vec::new())
+    }
+}
+
+impl fmt::Display for ValueDisplay<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write_value(f, self.value, &self.opts, 0, &mut Vec::new())
+    }
+}
+
+/// `open` holds the objects being written further up, so an object that
+/// contains itself is cut short as `{...}`.
+#[allow(clippy::too_many_lines)] // one arm per value kind
+fn write_value(
+    f: &mut fmt::Formatter<'_>,
+    value: &Value,
+    opts: &DisplayOptions,
+    depth: usize,
+    open: &mut Vec<usize>,
+) -> fmt::Result {
+    match value {
+        Value::Null => f.write_str("null"),
+        Value::Bool(flag) => write!(f, "{flag}"),
+        Value::Num(num) => write!(f, "{num}"),
+        Value::Int(int) => write!(f, "{int}"),
+        Value::Str(text) => write_quoted(f, text, opts.max_str_chars),
+        Value::Bytes(bytes) => {
+            f.write_str("bytes(")?;
+            for byte in bytes.iter() {
+                write!(f, "{byte:02x}")?;
+            }
+            f.write_str(")")
+        }
+        Value::Func(id) => write!(f, "<fn #{id}>"),
+        Value::Closure { func, captures } => {
+            write!(f, "<closure #{func} capturing {}>", captures.len())
+        }
+        Value::Handle(handle) => write!(f, "<handle {}>", handle.type_name()),
+        Value::Error {
+            code,
+            msg,
+            cause,
+            data,
+        } => {
+            write!(f, "error({code}): {msg}")?;
+            if let Some(data) = data {
+                f.write_str("; data: ")?;
+                write_value(f, data, opts, depth, open)?;
+            }
+            match cause {
+                Some(cause) => {
+                    f.write_str("; caused by: ")?;
+                    write_value(f, cause, opts, depth, open)
+                }
+                None => Ok(()),
+            }
+        }
+        Value::List(items) => {
+            if items.is_empty() {
+                return f.write_str("[]");
+            }
+            if opts.max_depth.is_some_and(|max| depth >= max) {
+                return f.write_str("[...]");
+            }
+            let shown = opts
+                .max_items
+                .map_or(items.len(), |max| max.min(items.len()));
+
+            f.write_char('[')?;
+            for (index, item) in items.iter().take(shown).enumerate() {
+                if index > 0 {
+                    f.write_char(',')?;
+                }
+                write_separator(f, opts, depth + 1, index == 0)?;
+                write_value(f, item, opts, depth + 1, open)?;
+            }
+            if shown < items.len() {
+                if shown > 0 {
+                    f.write_char(',')?;
+                }
+                write_separator(f, opts, depth + 1, shown == 0)?;
+                write!(f, "... (+{} more)", items.len() - shown)?;
+            }
+            match opts.indent {
+                Some(width) => write!(f, "\n{:1$}]", "", width * depth),
+                None => f.write_char(']'),
+            }
+        }
+        Value::Obj(obj) => {
+            let map = obj.borrow();
+            if map.is_empty() {
+                return f.write_str("{}");
+            }
+            if opts.max_depth.is_some_and(|max| depth >= max) || open.contains(&obj.addr()) {
+                return f.write_str("{...}");
+            }
+            open.push(obj.addr());
+            let mut keys = map.keys().collect::<Vec<_>>();
+            keys.sort();
+            let shown = opts.max_items.map_or(keys.len(), |max| max.min(keys.len()));
+
+            f.write_char('{')?;
+            for (index, key) in keys.iter().take(shown).enumerate() {
+                if index > 0 {
+                    f.write_char(',')?;
+                }
+                write_separator(f, opts, depth + 1, index == 0)?;
+                write_key(f, key)?;
+                f.write_str(": ")?;
+                write_value(f, &map[key.as_str()], opts, depth + 1, open)?;
+            }
+            if shown < keys.len() {
+                if shown > 0 {
+                    f.write_char(',')?;
+                }
+                write_separator(f, opts, depth + 1, shown == 0)?;
+                write!(f, "... (+{} more)", keys.len() - shown)?;
+            }
+            open.pop();
+            match opts.indent {
+                Some(width) => write!(f, "\n{:1$}}}", "", width * depth),
+                None => f.write_char('}'),
+            }
+        }
+    }
+}
+
+fn write_separator(
+    f: &mut fmt::Formatter<'_>,
+    opts: &DisplayOptions,
+    depth: usize,
+    first: bool,
+) -> fmt::Result {
+    match opts.indent {
+        Some(width) => write!(f, "\n{:1$}", "", width * depth),
+        None if first => Ok(()),
+        None => f.write_char(' '),
+    }
+}
+
+fn write_key(f: &mut fmt::Formatter<'_>, key: &str) -> fmt::Result {
+    let bare = !key.is_empty() && key.chars().all(|ch| ch.is_alphanumeric() || ch == '_');
+    if bare {
+        f.write_str(key)
+    } else {
+        write_quoted(f, key, None)
+    }
+}
+
+fn write_quoted(f: &mut fmt::Formatter<'_>, text: &str, max_chars: Option<usize>) -> fmt::Result {
+    f.write_char('"')?;
+    for (index, ch) in text.chars().enumerate() {
+        if max_chars.is_some_and(|max| index >= max) {
+            f.write_str("...")?;
+            break;
+        }
+        match ch {
+            '"' => f.write_str("\\\"")?,
+            '\\' => f.write_str("\\\\")?,
+            '\n' => f.write_str("\\n")?,
+            '\t' => f.write_str("\\t")?,
+            other => f.write_char(other)?,
+        }
+    }
+    f.write_char('"')
+}
@@ -0,0 +1,48 @@
+use std::collections::hash_map::RandomState;
+use std::hash::BuildHasher;
+use std::time::{Instant, SystemTime};
+
+/// Ambient host state the VM reads: clocks, randomness and variables.
+///
+/// Everything time- or entropy-dependent in the VM goes through
+/// `VmConfig.env`, so tests and deterministic replay can substitute fixed
+/// values for the real ones.
+pub trait HostEnv: Send + Sync {
+    /// Wall-clock time, for timestamps.
+    fn now(&self) -> SystemTime;
+
+    /// Monotonic time, for durations and deadlines. Only differences between
+    /// two readings are meaningful.
+    fn monotonic(&self) -> Instant;
+
+    /// Seed for VM-side random number generation.
+    fn random_seed(&self) -> u64;
+
+    /// Environment variable `name`, for `core::host::env`. Defaults to none
+    /// set, so substitute environments stay hermetic.
+    fn var(&self, _name: &str) -> Option<String> {
+        None
+    }
+}
+
+/// Default environment: the process clocks and OS-seeded randomness.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct SystemEnv;
+
+impl HostEnv for SystemEnv {
+    fn now(&self) -> SystemTime {
+        SystemTime::now()
+    }
+
+    fn monotonic(&self) -> Instant {
+        Instant::now()
+    }
+
+    fn random_seed(&self) -> u64 {
+        RandomState::new().hash_one(SystemTime::now())
+    }
+
+    fn var(&self, name: &str) -> Option<String> {
+        std::env::var(name).ok()
+    }
+}
@@ -0,0 +1,100 @@
+use crate::{Frame, HostFnError, Value, VmError};
+use imp_ir::Slot;
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::Arc;
+
+/// Handler for one `Instr::Ext` opcode.
+pub type ExtHandler = Arc<dyn Fn(&mut ExtArgs<'_>) -> Result<(), HostFnError> + Send + Sync>;
+
+/// Handlers for embedder-defined `Instr::Ext` opcodes, keyed by opcode.
+///
+/// The compiler never emits `Ext` itself; a `TargetLowering` plugin does, and
+/// the embedder registers the matching handler here.
+#[derive(Clone, Default)]
+pub struct ExtOps {
+    ops: HashMap<u16, (Arc<str>, ExtHandler)>,
+}
+
+impl ExtOps {
+    /// Registers `handler` for `op`; `name` only appears in diagnostics.
+    pub fn register(
+        &mut self,
+        op: u16,
+        name: &str,
+        handler: impl Fn(&mut ExtArgs<'_>) -> Result<(), HostFnError> + Send + Sync + 'static,
+    ) {
+        self.ops.insert(op, (Arc::from(name), Arc::new(handler)));
+    }
+
+    pub(crate) fn get(&self, op: u16) -> Option<&ExtHandler> {
+        self.ops.get(&op).map(|(_, handler)| handler)
+    }
+}
+
+impl fmt::Debug for ExtOps {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut ops = self
+            .ops
+            .iter()
+            .map(|(op, (name, _))| (*op, name))
+            .collect::<Vec<_>>();
+        ops.sort();
+        f.debug_map().entries(ops).finish()
+    }
+}
+
+/// The operand slots of an `Ext` instruction, as seen by its handler.
+pub struct ExtArgs<'a> {
+    pub(crate) frame: &'a mut Frame,
+    pub(crate) globals: &'a mut [Value],
+    pub(crate) operands: &'a [Slot],
+}
+
+impl ExtArgs<'_> {
+    #[must_use]
+    pub fn len(&self) -> usize {
+        self.operands.len()
+    }
+
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.operands.is_empty()
+    }
+
+    /// Value in operand `index`.
+    ///
+    /// # Errors
+    ///
+    /// `ext_operand` when `index` is not an operand or its slot cannot be
+    /// read.
+    pub fn get(&self, index: usize) -> Result<Value, HostFnError> {
+        let slot = self.operand(index)?;
+        self.frame
+            .get(slot, self.globals)
+            .map_err(|err: VmError| HostFnError::new("ext_operand", err.to_string()))
+    }
+
+    /// Writes `value` to operand `index`.
+    ///
+    /// # Errors
+    ///
+    /// `ext_operand` when `index` is not an operand.
+    pub fn set(&mut self, index: usize, value: Value) -> Result<(), HostFnError> {
+        let slot = self.operand(index)?;
+        self.frame.set(slot, value, self.globals);
+        Ok(())
+    }
+
+    fn operand(&self, index: usize) -> Result<Slot, HostFnError> {
+        self.operands.get(index).copied().ok_or_else(|| {
+            HostFnError::new(
+                "ext_operand",
+                format!(
+                    "operand {index} out of range ({} operands)",
+                    self.operands.len()
+                ),
+            )
+        })
+    }
+}
@@ -0,0 +1,67 @@
+use std::fs::File;
+use std::io::{self, Read};
+use std::path::{Component, Path, PathBuf};
+
+/// The `core::host::fs::*` instructions.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum FsOp {
+    Read,
+    Write,
+    Exists,
+}
+
+impl FsOp {
+    pub(crate) fn name(self) -> &'static str {
+        match self {
+            Self::Read => "core::host::fs::read",
+            Self::Write => "core::host::fs::write",
+            Self::Exists => "core::host::fs::exists",
+        }
+    }
+}
+
+/// `path` resolved against the working directory when it lies under one of
+/// `roots`, following symlinks in whatever part of it already exists.
+/// Paths that climb with `..` are refused outright.
+pub(crate) fn allowed_path(roots: &[PathBuf], path: &Path) -> Option<PathBuf> {
+    if path
+        .components()
+        .any(|component| matches!(component, Component::ParentDir))
+    {
+        return None;
+    }
+    let absolute = std::path::absolute(path).ok()?;
+    let resolved = resolve_existing(&absolute)?;
+    roots
+        .iter()
+        .filter_map(|root| root.canonicalize().ok())
+        .any(|root| resolved.starts_with(root))
+        .then_some(resolved)
+}
+
+/// At most `limit` bytes of the file at `path`, as UTF-8 text.
+pub(crate) fn read_text(path: &Path, limit: usize) -> io::Result<String> {
+    let mut bytes = Vec::new();
+    File::open(path)?
+        .take(u64::try_from(limit).unwrap_or(u64::MAX))
+        .read_to_end(&mut bytes)?;
+    String::from_utf8(bytes).map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))
+}
+
+/// Canonicalizes the longest existing ancestor of `path` and appends the
+/// rest unchanged.
+fn resolve_existing(path: &Path) -> Option<PathBuf> {
+    let mut rest = Vec::new();
+    let mut current = path;
+    loop {
+        if let Ok(canonical) = current.canonicalize() {
+            return Some(
+                rest.iter()
+                    .rev()
+                    .fold(canonical, |dir, name| dir.join(name)),
+            );
+        }
+        rest.push(current.file_name()?);
+        current = current.parent()?;
+    }
+}
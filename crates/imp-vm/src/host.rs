@@ -0,0 +1,161 @@
+use crate::{Capability, Value};
+use std::any::{Any, TypeId};
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::Arc;
+
+/// An embedder resource (file, connection, socket, ...) carried through imp
+/// code as an opaque value.
+///
+/// Imp code can only store, pass and compare handles; host functions get the
+/// resource back with [`HostHandle::downcast_ref`]. The resource is dropped
+/// when the last value referring to it goes away.
+#[derive(Clone)]
+pub struct HostHandle {
+    type_id: TypeId,
+    type_name: &'static str,
+    value: Arc<dyn Any + Send + Sync>,
+}
+
+impl HostHandle {
+    pub fn new<T: Any + Send + Sync>(value: T) -> Self {
+        Self {
+            type_id: TypeId::of::<T>(),
+            type_name: std::any::type_name::<T>(),
+            value: Arc::new(value),
+        }
+    }
+
+    #[must_use]
+    pub fn type_name(&self) -> &'static str {
+        self.type_name
+    }
+
+    #[must_use]
+    pub fn is<T: Any>(&self) -> bool {
+        self.type_id == TypeId::of::<T>()
+    }
+
+    #[must_use]
+    pub fn downcast_ref<T: Any>(&self) -> Option<&T> {
+        if self.is::<T>() {
+            self.value.downcast_ref()
+        } else {
+            None
+        }
+    }
+
+    /// Identity of the shared resource, for ordering within a run.
+    pub(crate) fn addr(&self) -> usize {
+        Arc::as_ptr(&self.value).cast::<()>() as usize
+    }
+
+    #[must_use]
+    pub fn downcast<T: Any + Send + Sync>(&self) -> Option<Arc<T>> {
+        if self.is::<T>() {
+            Arc::clone(&self.value).downcast().ok()
+        } else {
+            None
+        }
+    }
+}
+
+/// Handles are equal when they refer to the same resource.
+impl PartialEq for HostHandle {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.value, &other.value)
+    }
+}
+
+impl fmt::Debug for HostHandle {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "HostHandle({})", self.type_name)
+    }
+}
+
+/// Error returned by a host function; thrown into imp code as a catchable
+/// error with the given code.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HostFnError {
+    pub code: String,
+    pub msg: String,
+}
+
+impl HostFnError {
+    pub fn new(code: impl Into<String>, msg: impl Into<String>) -> Self {
+        Self {
+            code: code.into(),
+            msg: msg.into(),
+        }
+    }
+
+    /// Error for an argument of the wrong kind.
+    #[must_use]
+    pub fn bad_arg(name: &str, index: usize, expected: &str, found: &Value) -> Self {
+        let found = match found {
+            Value::Handle(handle) => handle.type_name(),
+            other => other.type_name(),
+        };
+        Self::new(
+            "host_arg",
+            format!("{name}: argument {index} expects {expected}, found {found}"),
+        )
+    }
+}
+
+pub type HostFn = Arc<dyn Fn(&[Value]) -> Result<Value, HostFnError> + Send + Sync>;
+
+/// Functions callable from imp code through `core::host::call name="..."`.
+///
+/// Calls go through `VmConfig.host_policy` and `VmConfig.audit` under the
+/// registered name, like the built-in host ops, and through
+/// `VmConfig.capabilities` when registered with one.
+#[derive(Clone, Default)]
+pub struct HostFunctions {
+    funcs: HashMap<String, HostFn>,
+    capabilities: HashMap<String, Capability>,
+}
+
+impl HostFunctions {
+    pub fn register(
+        &mut self,
+        name: impl Into<String>,
+        func: impl Fn(&[Value]) -> Result<Value, HostFnError> + Send + Sync + 'static,
+    ) {
+        let name = name.into();
+        self.capabilities.remove(&name);
+        self.funcs.insert(name, Arc::new(func));
+    }
+
+    /// Registers a function that opens `capability`; calls throw
+    /// `cap_denied` while `VmConfig.capabilities` keeps it closed.
+    pub fn register_with(
+        &mut self,
+        name: impl Into<String>,
+        capability: Capability,
+        func: impl Fn(&[Value]) -> Result<Value, HostFnError> + Send + Sync + 'static,
+    ) {
+        let name = name.into();
+        self.register(name.clone(), func);
+        self.capabilities.insert(name, capability);
+    }
+
+    #[must_use]
+    pub fn get(&self, name: &str) -> Option<&HostFn> {
+        self.funcs.get(name)
+    }
+
+    /// The capability `name` was registered with, if any.
+    #[must_use]
+    pub fn capability(&self, name: &str) -> Option<Capability> {
+        self.capabilities.get(name).copied()
+    }
+}
+
+impl fmt::Debug for HostFunctions {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut names = self.funcs.keys().collect::<Vec<_>>();
+        names.sort();
+        f.debug_set().entries(names).finish()
+    }
+}
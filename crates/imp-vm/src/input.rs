@@ -0,0 +1,79 @@
+use std::io::{self, BufRead, Read};
+use std::sync::Mutex;
+
+/// Source for `core::host::read_line`/`core::host::read_all`.
+pub trait HostReader: Send + Sync {
+    /// The next line without its `\n` or `\r\n`; `None` at end of input.
+    ///
+    /// # Errors
+    ///
+    /// Failures reading the input.
+    fn read_line(&self) -> io::Result<Option<String>>;
+
+    /// Everything left, possibly empty.
+    ///
+    /// # Errors
+    ///
+    /// Failures reading the input.
+    fn read_all(&self) -> io::Result<String>;
+}
+
+/// Default reader: the process stdin.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct StdinReader;
+
+impl HostReader for StdinReader {
+    fn read_line(&self) -> io::Result<Option<String>> {
+        let mut line = String::new();
+        if io::stdin().lock().read_line(&mut line)? == 0 {
+            return Ok(None);
+        }
+        Ok(Some(trim_newline(line)))
+    }
+
+    fn read_all(&self) -> io::Result<String> {
+        let mut text = String::new();
+        io::stdin().lock().read_to_string(&mut text)?;
+        Ok(text)
+    }
+}
+
+/// In-memory reader over a fixed string; share it via `Arc`.
+#[derive(Debug, Default)]
+pub struct InputBuffer {
+    rest: Mutex<String>,
+}
+
+impl InputBuffer {
+    pub fn new(text: impl Into<String>) -> Self {
+        Self {
+            rest: Mutex::new(text.into()),
+        }
+    }
+}
+
+impl HostReader for InputBuffer {
+    fn read_line(&self) -> io::Result<Option<String>> {
+        let mut rest = self.rest.lock().map_err(|_| io::ErrorKind::Other)?;
+        if rest.is_empty() {
+            return Ok(None);
+        }
+        let end = rest.find('\n').map_or(rest.len(), |at| at + 1);
+        Ok(Some(trim_newline(rest.drain(..end).collect())))
+    }
+
+    fn read_all(&self) -> io::Result<String> {
+        let mut rest = self.rest.lock().map_err(|_| io::ErrorKind::Other)?;
+        Ok(std::mem::take(&mut *rest))
+    }
+}
+
+fn trim_newline(mut line: String) -> String {
+    if line.ends_with('\n') {
+        line.pop();
+        if line.ends_with('\r') {
+            line.pop();
+        }
+    }
+    line
+}
@@ -0,0 +1,76 @@
+use crate::{JitFunction, JitKey, JitStep};
+use std::collections::{BTreeMap, HashMap};
+use std::rc::Rc;
+
+/// JIT plans keyed by function, evicted least-recently-used first once
+/// `VmConfig.jit_cache_max_entries` or `jit_cache_max_bytes` is exceeded.
+///
+/// Evicting a plan only drops the cache's reference; calls already running
+/// it keep their own `Arc`.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct JitCache {
+    entries: HashMap<JitKey, Entry>,
+    /// Last-use tick -> key, oldest first.
+    lru: BTreeMap<u64, JitKey>,
+    tick: u64,
+    bytes: usize,
+}
+
+#[derive(Debug, Clone)]
+struct Entry {
+    plan: Rc<JitFunction>,
+    last_used: u64,
+    bytes: usize,
+}
+
+impl JitCache {
+    pub(crate) fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub(crate) fn get(&mut self, key: &JitKey) -> Option<Rc<JitFunction>> {
+        let entry = self.entries.get_mut(key)?;
+        self.tick += 1;
+        self.lru.remove(&entry.last_used);
+        self.lru.insert(self.tick, key.clone());
+        entry.last_used = self.tick;
+        Some(Rc::clone(&entry.plan))
+    }
+
+    pub(crate) fn insert(
+        &mut self,
+        key: JitKey,
+        plan: Rc<JitFunction>,
+        max_entries: Option<usize>,
+        max_bytes: Option<usize>,
+    ) {
+        let bytes = plan.steps.len() * std::mem::size_of::<JitStep>();
+        self.tick += 1;
+        self.lru.insert(self.tick, key.clone());
+        if let Some(old) = self.entries.insert(
+            key,
+            Entry {
+                plan,
+                last_used: self.tick,
+                bytes,
+            },
+        ) {
+            self.lru.remove(&old.last_used);
+            self.bytes -= old.bytes;
+        }
+        self.bytes += bytes;
+
+        // The newest plan always stays, even if it alone exceeds the limits.
+        while self.entries.len() > 1
+            && (max_entries.is_some_and(|max| self.entries.len() > max)
+                || max_bytes.is_some_and(|max| self.bytes > max))
+        {
+            let Some((_, oldest)) = self.lru.pop_first() else {
+                break;
+            };
+            if let Some(evicted) = self.entries.remove(&oldest) {
+                self.bytes -= evicted.bytes;
+            }
+        }
+    }
+}
@@ -0,0 +1,115 @@
+//! `core::json::parse` / `core::json::stringify` on top of
+//! `imp_std::json`. Parsing goes through its reader unless the
+//! `serde_json` feature hands it to `serde_json`; printing always uses its
+//! string quoting so both builds write the same text.
+
+use crate::{ObjRef, Value};
+#[cfg(not(feature = "serde_json"))]
+use imp_std::json::Json;
+use imp_std::json::{MAX_DEPTH, write_quoted};
+use std::fmt::Write as _;
+use std::sync::Arc;
+
+/// Compact JSON for `value`, object keys sorted. Nums that are not finite
+/// and values JSON has no form for (bytes, functions, errors, handles) are
+/// errors, as is nesting deeper than `MAX_DEPTH`, which also catches
+/// cycles.
+pub(crate) fn stringify(value: &Value) -> Result<String, String> {
+    let mut out = String::new();
+    write_value(&mut out, value, 0)?;
+    Ok(out)
+}
+
+fn write_value(out: &mut String, value: &Value, depth: usize) -> Result<(), String> {
+    if depth > MAX_DEPTH {
+        return Err(format!("nesting deeper than {MAX_DEPTH} levels"));
+    }
+    match value {
+        Value::Null => out.push_str("null"),
+        Value::Bool(flag) => out.push_str(if *flag { "true" } else { "false" }),
+        Value::Num(num) if num.is_finite() => {
+            let _ = write!(out, "{num}");
+        }
+        Value::Num(num) => return Err(format!("{num} has no JSON form")),
+        Value::Int(int) => {
+            let _ = write!(out, "{int}");
+        }
+        Value::Str(text) => {
+            let _ = write_quoted(out, text);
+        }
+        Value::List(items) => {
+            out.push('[');
+            for (index, item) in items.iter().enumerate() {
+                if index > 0 {
+                    out.push(',');
+                }
+                write_value(out, item, depth + 1)?;
+            }
+            out.push(']');
+        }
+        Value::Obj(obj) => {
+            let map = obj.borrow();
+            let mut keys = map.keys().collect::<Vec<_>>();
+            keys.sort();
+            out.push('{');
+            for (index, key) in keys.into_iter().enumerate() {
+                if index > 0 {
+                    out.push(',');
+                }
+                let _ = write_quoted(out, key);
+                out.push(':');
+                write_value(out, &map[key], depth + 1)?;
+            }
+            out.push('}');
+        }
+        other => return Err(format!("a {} has no JSON form", other.type_name())),
+    }
+    Ok(())
+}
+
+/// The value `text` spells: objects become objs, arrays lists and every
+/// number a num.
+#[cfg(feature = "serde_json")]
+pub(crate) fn parse(text: &str) -> Result<Value, String> {
+    fn convert(value: serde_json::Value) -> Value {
+        match value {
+            serde_json::Value::Null => Value::Null,
+            serde_json::Value::Bool(flag) => Value::Bool(flag),
+            serde_json::Value::Number(num) => Value::Num(num.as_f64().unwrap_or(f64::NAN)),
+            serde_json::Value::String(text) => Value::Str(Arc::from(text)),
+            serde_json::Value::Array(items) => {
+                Value::List(items.into_iter().map(convert).collect())
+            }
+            serde_json::Value::Object(map) => Value::Obj(ObjRef::new(
+                map.into_iter()
+                    .map(|(key, value)| (key, convert(value)))
+                    .collect(),
+            )),
+        }
+    }
+    serde_json::from_str(text)
+        .map(convert)
+        .map_err(|err| err.to_string())
+}
+
+/// The value `text` spells: objects become objs, arrays lists and every
+/// number a num; a key given twice keeps its last value.
+#[cfg(not(feature = "serde_json"))]
+pub(crate) fn parse(text: &str) -> Result<Value, String> {
+    fn convert(value: Json) -> Value {
+        match value {
+            Json::Null => Value::Null,
+            Json::Bool(flag) => Value::Bool(flag),
+            Json::Num(num) => Value::Num(num),
+            Json::Str(text) => Value::Str(Arc::from(text)),
+            Json::Arr(items) => Value::List(items.into_iter().map(convert).collect()),
+            Json::Obj(fields) => Value::Obj(ObjRef::new(
+                fields
+                    .into_iter()
+                    .map(|(key, value)| (key, convert(value)))
+                    .collect(),
+            )),
+        }
+    }
+    Json::parse(text).map(convert)
+}
@@ -1,17 +1,85 @@
-use imp_ir::{CompiledFunction, CompiledModule, ConstValue, FnMeta, FuncId, Instr, RetShape, Slot};
-use std::collections::HashMap;
+use imp_ir::{
+    CompiledFunction, CompiledModule, ConstValue, FnMeta, FuncId, HostClock, Instr, RetShape, Slot,
+};
+use std::cmp::Ordering;
+use std::collections::{HashMap, HashSet, VecDeque};
 use std::fmt;
-use std::sync::Arc;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime};
+
+mod call_stack;
+mod cancel;
+mod coverage;
+mod display;
+mod env;
+mod ext;
+mod fs;
+mod host;
+mod input;
+mod jit_cache;
+mod json;
+mod obj;
+mod order;
+mod profile;
+mod rng;
+mod sandbox;
+mod stepping;
+mod trace;
+
+pub use cancel::CancelToken;
+pub use coverage::{Coverage, FunctionCoverage};
+pub use display::{DisplayOptions, ValueDisplay};
+pub use env::{HostEnv, SystemEnv};
+pub use ext::{ExtArgs, ExtHandler, ExtOps};
+pub use host::{HostFn, HostFnError, HostFunctions, HostHandle};
+pub use imp_ir::{PrintLevel, PrintStream, ValueKind};
+pub use input::{HostReader, InputBuffer, StdinReader};
+pub use obj::ObjRef;
+pub use profile::FunctionStats;
+pub use sandbox::{Capabilities, Capability};
+pub use stepping::{Breakpoint, FrameInfo, RunState, StepOutcome, Steps};
+pub use trace::{TraceEvent, TraceLog, TraceSink, TraceWriter};
+
+use call_stack::{CallOut, FrameExit, Suspended};
+use fs::FsOp;
+use jit_cache::JitCache;
+use profile::Profiler;
+use rng::Rng;
+use stepping::SteppedRun;
 
 #[derive(Debug, Clone, PartialEq)]
+#[non_exhaustive]
 pub enum Value {
     Null,
     Bool(bool),
     Num(f64),
+    /// A 64-bit integer from `core::int::parse`; arithmetic on two integers
+    /// is exact and throws `int_overflow` instead of wrapping.
+    Int(i64),
     Str(Arc<str>),
-    Obj(HashMap<String, Value>),
+    Bytes(Arc<[u8]>),
+    List(Vec<Value>),
+    /// A shared, mutable object: copies of the value alias one map.
+    Obj(ObjRef),
     Func(FuncId),
-    Error { code: Arc<str>, msg: Arc<str> },
+    /// A `core::fn::lambda` value: calling it calls `func` with `captures`
+    /// ahead of the call's own arguments.
+    Closure {
+        func: FuncId,
+        captures: Rc<[Value]>,
+    },
+    Error {
+        code: Arc<str>,
+        msg: Arc<str>,
+        cause: Option<Rc<Value>>,
+        /// The value `core::throw ... data=` attached.
+        data: Option<Rc<Value>>,
+    },
+    /// Opaque resource returned by a host function.
+    Handle(HostHandle),
 }
 
 impl Value {
@@ -20,73 +88,838 @@ impl Value {
             ConstValue::Null => Self::Null,
             ConstValue::Bool(flag) => Self::Bool(*flag),
             ConstValue::Num(num) => Self::Num(*num),
+            ConstValue::Int(int) => Self::Int(*int),
             ConstValue::Str(text) => Self::Str(Arc::clone(text)),
+            ConstValue::Bytes(bytes) => Self::Bytes(Arc::clone(bytes)),
         }
     }
 
     fn as_num(&self) -> Result<f64, VmError> {
-        if let Self::Num(num) = self {
-            Ok(*num)
-        } else {
-            Err(VmError::Runtime("expected numeric value".to_owned()))
+        match self {
+            Self::Num(num) => Ok(*num),
+            Self::Int(int) => Ok(int_num(*int)),
+            _ => Err(VmError::runtime("expected numeric value".to_owned())),
+        }
+    }
+
+    #[must_use]
+    pub fn type_name(&self) -> &'static str {
+        self.kind().as_str()
+    }
+
+    /// The kind `core::type_of` names.
+    #[must_use]
+    pub fn kind(&self) -> ValueKind {
+        match self {
+            Self::Null => ValueKind::Null,
+            Self::Bool(_) => ValueKind::Bool,
+            Self::Num(_) => ValueKind::Num,
+            Self::Int(_) => ValueKind::Int,
+            Self::Str(_) => ValueKind::Str,
+            Self::Bytes(_) => ValueKind::Bytes,
+            Self::List(_) => ValueKind::List,
+            Self::Obj(_) => ValueKind::Obj,
+            Self::Func(_) => ValueKind::Func,
+            Self::Closure { .. } => ValueKind::Closure,
+            Self::Error { .. } => ValueKind::Error,
+            Self::Handle(_) => ValueKind::Handle,
         }
     }
 
+    /// Compact JSON as `core::json::stringify` writes it, object keys
+    /// sorted.
+    ///
+    /// # Errors
+    ///
+    /// A message naming what has no JSON form: non-finite nums, bytes,
+    /// functions, errors, handles, or nesting past `MAX_DEPTH`.
+    pub fn to_json(&self) -> Result<String, String> {
+        json::stringify(self)
+    }
+
     fn as_bool(&self) -> bool {
         match self {
             Self::Null => false,
             Self::Bool(value) => *value,
             Self::Num(value) => *value != 0.0,
+            Self::Int(value) => *value != 0,
             Self::Str(value) => !value.is_empty(),
-            Self::Obj(map) => !map.is_empty(),
-            Self::Func(_) => true,
-            Self::Error { .. } => true,
+            Self::Bytes(bytes) => !bytes.is_empty(),
+            Self::List(items) => !items.is_empty(),
+            Self::Obj(obj) => !obj.borrow().is_empty(),
+            Self::Func(_) | Self::Closure { .. } | Self::Error { .. } | Self::Handle(_) => true,
         }
     }
 }
 
-#[derive(Debug, Clone)]
+/// How a [`Vm`] runs. Outside this crate, start from
+/// [`VmConfig::default`] and set fields; new ones may be added.
+// Each bool is an independent switch an embedder sets on its own.
+#[allow(clippy::struct_excessive_bools)]
+#[derive(Clone)]
+#[non_exhaustive]
 pub struct VmConfig {
     pub enable_host_print: bool,
     pub enable_jit: bool,
+    pub host_policy: HostPolicy,
+    /// Side channels host ops may use; [`Capabilities::sandbox`] closes
+    /// them all.
+    pub capabilities: Capabilities,
+    /// Directories `core::host::fs::*` may touch, along with everything
+    /// under them; empty, the default, leaves file I/O off.
+    pub fs_roots: Vec<PathBuf>,
+    /// What `core::host::args` returns, as strs; `imp run` fills it with
+    /// the arguments after `--`. Plain strings keep `VmConfig` `Send`.
+    pub program_args: Vec<String>,
+    /// Variables `core::host::env` may read; `None`, the default, allows
+    /// any.
+    pub env_allowlist: Option<Vec<String>>,
+    pub audit: Option<Arc<dyn AuditSink>>,
+    pub profile: bool,
+    /// Re-runs every JIT call on the interpreter and fails on any difference.
+    pub verify_jit: bool,
+    /// Nested `Invoke` depth at which calls throw a catchable `stack_overflow`.
+    /// Calls live on a VM-managed stack, so `None` is bounded only by memory.
+    pub max_call_depth: Option<usize>,
+    /// Destination for host print output; `None` writes to stdout/stderr.
+    pub writer: Option<Arc<dyn HostWriter>>,
+    /// Source for `core::host::read_line`/`read_all`; `None` reads stdin.
+    pub reader: Option<Arc<dyn HostReader>>,
+    /// Records per-instruction hit counts, readable via [`Vm::coverage`].
+    pub coverage: bool,
+    /// Told about every instruction just before it runs, with the values
+    /// of the slots it names; `imp run --trace` uses a [`TraceWriter`].
+    pub trace: Option<Arc<dyn TraceSink>>,
+    /// Embedder functions reachable through `core::host::call`.
+    pub host_fns: HostFunctions,
+    /// Handlers for `Instr::Ext` opcodes.
+    pub ext_ops: ExtOps,
+    /// Most JIT plans kept at once; least recently used are evicted first.
+    pub jit_cache_max_entries: Option<usize>,
+    /// Approximate byte budget for cached JIT plans, evicted LRU first.
+    pub jit_cache_max_bytes: Option<usize>,
+    /// Instructions one run may execute before failing with
+    /// `VmError::FuelExhausted`; `None` is unmetered.
+    pub max_steps: Option<u64>,
+    /// Lifetime allocation budget: approximate heap bytes one run may
+    /// allocate in total for strings, bytes, lists and objects before
+    /// allocations throw a catchable `oom`. Nothing is credited back when
+    /// values are dropped, so long-running loops use it up. `None` is
+    /// unbounded and skips the accounting.
+    pub max_alloc_bytes: Option<usize>,
+    /// Live memory budget: approximate heap bytes the strings, bytes,
+    /// lists and objects a run still holds may take at once. Once the
+    /// bytes allocated since the last check pass it, the VM measures what
+    /// frames and module globals hold and throws a catchable `oom` only if
+    /// that is still over, so dropped values are credited back. `None` is
+    /// unbounded and skips the accounting.
+    pub max_memory: Option<usize>,
+    /// Wall-clock time one run may take before failing with
+    /// `VmError::Timeout`, measured on `env`'s monotonic clock; `None` is
+    /// unbounded.
+    pub timeout: Option<Duration>,
+    /// Host-side off switch: once tripped, runs fail with
+    /// `VmError::Cancelled`.
+    pub cancel: Option<CancelToken>,
+    /// Clocks and randomness; `None` uses [`SystemEnv`].
+    pub env: Option<Arc<dyn HostEnv>>,
+    /// Seed for `core::host::random`; `None` takes `env`'s
+    /// `random_seed`. The generator is seeded once per VM.
+    pub rng_seed: Option<u64>,
+    /// Consulted when a throw or runtime error leaves the outermost frame of
+    /// a run (`run_main`, `invoke`, an import's init, ...); `None` raises.
+    pub on_uncaught: Option<UncaughtHook>,
+    /// Fills `RunResult.global_deltas` with the module globals a run changed.
+    pub track_global_deltas: bool,
+    /// Fails the run with `VmError::Runtime` when a `core::obj::*` op meets
+    /// a non-object, a `core::list::*` op or `core::str::join` a non-list,
+    /// or an invoke a non-function, instead of throwing a catchable
+    /// `type_error` or `not_callable`.
+    pub strict_type_errors: bool,
 }
 
+/// Default `VmConfig.max_call_depth`.
+pub const DEFAULT_MAX_CALL_DEPTH: usize = 100_000;
+
+/// Bytes `VmConfig.max_alloc_bytes` charges per list item, object entry or
+/// object.
+const VALUE_SIZE: usize = std::mem::size_of::<Value>();
+
+/// Most entries a `VmError` traceback holds; frames further out are
+/// counted in the last entry's `TraceFrame::omitted`.
+const MAX_TRACEBACK_FRAMES: usize = 256;
+
+/// Steps between checks of `VmConfig.timeout` and `VmConfig.cancel`.
+const INTERRUPT_POLL_STEPS: u32 = 256;
+
 impl Default for VmConfig {
     fn default() -> Self {
         Self {
             enable_host_print: true,
             enable_jit: true,
+            host_policy: HostPolicy::default(),
+            capabilities: Capabilities::default(),
+            fs_roots: Vec::new(),
+            program_args: Vec::new(),
+            env_allowlist: None,
+            audit: None,
+            profile: false,
+            verify_jit: false,
+            max_call_depth: Some(DEFAULT_MAX_CALL_DEPTH),
+            writer: None,
+            reader: None,
+            coverage: false,
+            trace: None,
+            host_fns: HostFunctions::default(),
+            ext_ops: ExtOps::default(),
+            jit_cache_max_entries: None,
+            jit_cache_max_bytes: None,
+            max_steps: None,
+            max_alloc_bytes: None,
+            max_memory: None,
+            timeout: None,
+            cancel: None,
+            env: None,
+            rng_seed: None,
+            on_uncaught: None,
+            track_global_deltas: false,
+            strict_type_errors: false,
+        }
+    }
+}
+
+/// Callback for `VmConfig.on_uncaught`: receives the error code (`runtime`
+/// for runtime errors), message and traceback, innermost frame first.
+pub type UncaughtHook = Arc<dyn Fn(&str, &str, &[TraceFrame]) -> ErrorDisposition + Send + Sync>;
+
+/// What an [`UncaughtHook`] does with an error.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ErrorDisposition {
+    /// Fail the run with the error, as without a hook.
+    Raise,
+    /// Finish the run as if its outermost function returned these values.
+    Return(Vec<Value>),
+}
+
+/// Callback consulted after the allow/deny lists; receives the op name and the
+/// list verdict and returns the final verdict.
+pub type HostPolicyHook = Arc<dyn Fn(&str, bool) -> bool + Send + Sync>;
+
+/// Allow/deny rules for host-effectful operations.
+///
+/// Patterns match an op name exactly (`core::host::print`) or a whole
+/// namespace (`core::host` or `core::host::*`). Deny rules win over allow
+/// rules; `allow: None` permits everything not denied.
+#[derive(Clone, Default)]
+pub struct HostPolicy {
+    pub allow: Option<Vec<String>>,
+    pub deny: Vec<String>,
+    pub hook: Option<HostPolicyHook>,
+}
+
+impl HostPolicy {
+    pub fn allow_only<I, S>(patterns: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        Self {
+            allow: Some(patterns.into_iter().map(Into::into).collect()),
+            ..Self::default()
+        }
+    }
+
+    #[must_use]
+    pub fn permits(&self, op: &str) -> bool {
+        let listed = !self.deny.iter().any(|pattern| policy_matches(pattern, op))
+            && self
+                .allow
+                .as_ref()
+                .is_none_or(|allow| allow.iter().any(|pattern| policy_matches(pattern, op)));
+        match &self.hook {
+            Some(hook) => hook(op, listed),
+            None => listed,
+        }
+    }
+}
+
+impl fmt::Debug for HostPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("HostPolicy")
+            .field("allow", &self.allow)
+            .field("deny", &self.deny)
+            .field("hook", &self.hook.as_ref().map(|_| "<fn>"))
+            .finish()
+    }
+}
+
+impl fmt::Debug for VmConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("VmConfig")
+            .field("enable_host_print", &self.enable_host_print)
+            .field("enable_jit", &self.enable_jit)
+            .field("host_policy", &self.host_policy)
+            .field("capabilities", &self.capabilities)
+            .field("fs_roots", &self.fs_roots)
+            .field("program_args", &self.program_args)
+            .field("env_allowlist", &self.env_allowlist)
+            .field("audit", &self.audit.as_ref().map(|_| "<sink>"))
+            .field("profile", &self.profile)
+            .field("verify_jit", &self.verify_jit)
+            .field("max_call_depth", &self.max_call_depth)
+            .field("writer", &self.writer.as_ref().map(|_| "<writer>"))
+            .field("reader", &self.reader.as_ref().map(|_| "<reader>"))
+            .field("coverage", &self.coverage)
+            .field("trace", &self.trace.as_ref().map(|_| "<sink>"))
+            .field("host_fns", &self.host_fns)
+            .field("ext_ops", &self.ext_ops)
+            .field("jit_cache_max_entries", &self.jit_cache_max_entries)
+            .field("jit_cache_max_bytes", &self.jit_cache_max_bytes)
+            .field("max_steps", &self.max_steps)
+            .field("max_alloc_bytes", &self.max_alloc_bytes)
+            .field("max_memory", &self.max_memory)
+            .field("timeout", &self.timeout)
+            .field("cancel", &self.cancel)
+            .field("env", &self.env.as_ref().map(|_| "<env>"))
+            .field("rng_seed", &self.rng_seed)
+            .field("on_uncaught", &self.on_uncaught.as_ref().map(|_| "<fn>"))
+            .field("track_global_deltas", &self.track_global_deltas)
+            .field("strict_type_errors", &self.strict_type_errors)
+            .finish()
+    }
+}
+
+/// One host op invocation, recorded whether or not the policy allowed it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AuditEntry {
+    pub op: Arc<str>,
+    pub args: Vec<String>,
+    pub timestamp: SystemTime,
+    pub function: Arc<str>,
+    pub pc: usize,
+    pub allowed: bool,
+}
+
+pub trait AuditSink: Send + Sync {
+    fn record(&self, entry: AuditEntry);
+}
+
+/// In-memory audit sink; share it via `Arc` and read it back after a run.
+#[derive(Debug, Default)]
+pub struct AuditLog {
+    entries: Mutex<Vec<AuditEntry>>,
+}
+
+impl AuditLog {
+    pub fn entries(&self) -> Vec<AuditEntry> {
+        self.entries
+            .lock()
+            .map(|entries| entries.clone())
+            .unwrap_or_default()
+    }
+}
+
+impl AuditSink for AuditLog {
+    fn record(&self, entry: AuditEntry) {
+        if let Ok(mut entries) = self.entries.lock() {
+            entries.push(entry);
+        }
+    }
+}
+
+/// Receives rendered `core::host::print`/`core::host::eprint` output.
+pub trait HostWriter: Send + Sync {
+    fn write(&self, stream: PrintStream, level: PrintLevel, text: &str);
+}
+
+/// Default writer: one line per print on the process stdout/stderr, with a
+/// `level: ` prefix for anything other than `info`.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct StdioWriter;
+
+impl HostWriter for StdioWriter {
+    fn write(&self, stream: PrintStream, level: PrintLevel, text: &str) {
+        let prefix = match level {
+            PrintLevel::Info => String::new(),
+            other => format!("{}: ", other.as_str()),
+        };
+        // Printing is best-effort; a closed pipe must not abort the program.
+        let _ = match stream {
+            PrintStream::Stdout => writeln!(std::io::stdout(), "{prefix}{text}"),
+            PrintStream::Stderr => writeln!(std::io::stderr(), "{prefix}{text}"),
+        };
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OutputLine {
+    pub stream: PrintStream,
+    pub level: PrintLevel,
+    pub text: String,
+}
+
+/// In-memory writer; share it via `Arc` and read the lines back after a run.
+#[derive(Debug, Default)]
+pub struct OutputBuffer {
+    lines: Mutex<Vec<OutputLine>>,
+}
+
+impl OutputBuffer {
+    pub fn lines(&self) -> Vec<OutputLine> {
+        self.lines
+            .lock()
+            .map(|lines| lines.clone())
+            .unwrap_or_default()
+    }
+}
+
+impl HostWriter for OutputBuffer {
+    fn write(&self, stream: PrintStream, level: PrintLevel, text: &str) {
+        if let Ok(mut lines) = self.lines.lock() {
+            lines.push(OutputLine {
+                stream,
+                level,
+                text: text.to_owned(),
+            });
         }
     }
 }
 
+fn policy_matches(pattern: &str, op: &str) -> bool {
+    let pattern = pattern.strip_suffix("::*").unwrap_or(pattern);
+    op == pattern
+        || op
+            .strip_prefix(pattern)
+            .is_some_and(|rest| rest.starts_with("::"))
+}
+
 #[derive(Debug, Clone)]
 pub struct RunResult {
     pub returns: Vec<Value>,
     pub exports: HashMap<String, Value>,
+    /// Present when `VmConfig.profile` is enabled; covers import initialization too.
+    pub stats: Option<RunStats>,
+    /// Present when `VmConfig.track_global_deltas` is enabled: every global
+    /// of the top-level module whose value the init changed, in slot order.
+    pub global_deltas: Option<Vec<GlobalDelta>>,
+}
+
+/// One module global whose value differs between linking and the end of a
+/// run.
+#[derive(Debug, Clone, PartialEq)]
+pub struct GlobalDelta {
+    /// Debug name (`main::count`), or `#slot` when the module carries none.
+    pub name: String,
+    pub before: Value,
+    pub after: Value,
+}
+
+impl RunResult {
+    /// Converts the named export into `T`, e.g. `result.export::<f64>("total")`.
+    ///
+    /// # Errors
+    ///
+    /// [`ExportError::Missing`] for an unknown name and
+    /// [`ExportError::Type`] when the value is not a `T`.
+    pub fn export<T>(&self, name: &str) -> Result<T, ExportError>
+    where
+        T: TryFrom<Value, Error = ValueTypeError>,
+    {
+        let value = self
+            .exports
+            .get(name)
+            .ok_or_else(|| ExportError::Missing(name.to_owned()))?;
+        T::try_from(value.clone()).map_err(|source| ExportError::Type {
+            path: name.to_owned(),
+            source,
+        })
+    }
+
+    /// Stable text form of returns and exports for golden-file comparison.
+    #[must_use]
+    pub fn to_snapshot(&self) -> String {
+        use fmt::Write as _;
+
+        let mut out = String::from("returns:\n");
+        for (index, value) in self.returns.iter().enumerate() {
+            let _ = writeln!(out, "  {index}: {value}");
+        }
+        out.push_str("exports:\n");
+        let mut names = self.exports.keys().collect::<Vec<_>>();
+        names.sort();
+        for name in names {
+            let _ = writeln!(out, "  {name}: {}", self.exports[name]);
+        }
+        out
+    }
+
+    /// Looks up a dotted path: the first segment names an export, the rest
+    /// walk nested object keys (`"config.server.port"`).
+    #[must_use]
+    pub fn exports_path(&self, path: &str) -> Option<Value> {
+        let mut segments = path.split('.');
+        let mut current = self.exports.get(segments.next()?)?.clone();
+        for segment in segments {
+            let Value::Obj(obj) = current else {
+                return None;
+            };
+            current = obj.borrow().get(segment)?.clone();
+        }
+        Some(current)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ValueTypeError {
+    pub expected: &'static str,
+    pub found: &'static str,
+}
+
+impl fmt::Display for ValueTypeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "expected {}, found {}", self.expected, self.found)
+    }
+}
+
+impl std::error::Error for ValueTypeError {}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ExportError {
+    Missing(String),
+    Type {
+        path: String,
+        source: ValueTypeError,
+    },
+}
+
+impl fmt::Display for ExportError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Missing(name) => write!(f, "export '{name}' not found"),
+            Self::Type { path, source } => write!(f, "export '{path}': {source}"),
+        }
+    }
+}
+
+impl std::error::Error for ExportError {}
+
+macro_rules! impl_try_from_value {
+    ($ty:ty, $expected:literal, $pattern:pat => $out:expr) => {
+        impl TryFrom<Value> for $ty {
+            type Error = ValueTypeError;
+
+            fn try_from(value: Value) -> Result<Self, Self::Error> {
+                match value {
+                    $pattern => Ok($out),
+                    other => Err(ValueTypeError {
+                        expected: $expected,
+                        found: other.type_name(),
+                    }),
+                }
+            }
+        }
+    };
+}
+
+impl_try_from_value!(f64, "num", Value::Num(num) => num);
+impl_try_from_value!(bool, "bool", Value::Bool(flag) => flag);
+impl_try_from_value!(String, "str", Value::Str(text) => text.to_string());
+impl_try_from_value!(Arc<str>, "str", Value::Str(text) => text);
+impl_try_from_value!(HashMap<String, Value>, "obj", Value::Obj(obj) => obj.to_map());
+impl_try_from_value!(ObjRef, "obj", Value::Obj(obj) => obj);
+
+impl TryFrom<Value> for i64 {
+    type Error = ValueTypeError;
+
+    fn try_from(value: Value) -> Result<Self, Self::Error> {
+        match value {
+            Value::Int(int) => Ok(int),
+            Value::Num(num) if num.fract() == 0.0 && num.abs() <= 9_007_199_254_740_992.0 => {
+                // Whole and within 2^53, so the cast is exact.
+                #[allow(clippy::cast_possible_truncation)]
+                let int = num as i64;
+                Ok(int)
+            }
+            other => Err(ValueTypeError {
+                expected: "integral num",
+                found: other.type_name(),
+            }),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct RunStats {
+    pub instructions: u64,
+    pub peak_call_depth: usize,
+    pub throws: u64,
+    pub objects_allocated: u64,
+    pub strings_allocated: u64,
+    pub wall_time: Duration,
+    /// Per-function counters, hottest first; see [`RunStats::report`].
+    pub functions: Vec<FunctionStats>,
 }
 
 #[derive(Debug, Clone)]
+#[non_exhaustive]
 pub enum VmError {
-    Runtime(String),
-    Thrown { code: Arc<str>, msg: Arc<str> },
+    Runtime {
+        msg: String,
+        /// Frames the error escaped, innermost first.
+        traceback: Vec<TraceFrame>,
+    },
+    Thrown {
+        code: Arc<str>,
+        msg: Arc<str>,
+        cause: Option<Rc<Value>>,
+        data: Option<Rc<Value>>,
+        /// Frames the throw escaped, innermost first.
+        traceback: Vec<TraceFrame>,
+    },
+    JitDivergence {
+        function: Arc<str>,
+        pc: usize,
+        detail: String,
+    },
+    ImportInit {
+        alias: String,
+        path: String,
+        source: Box<VmError>,
+    },
+    /// A function id that does not resolve in the calling module; `symbol`
+    /// names what the id referred to when the VM handed it out.
+    UnknownFunction {
+        id: FuncId,
+        symbol: Option<FuncSymbol>,
+    },
+    /// A `Jump`, `Branch` or `TryPush` at `pc` whose target lies outside the
+    /// function's `len` instructions; reported before the function runs.
+    BadBranchTarget {
+        function: Arc<str>,
+        pc: usize,
+        target: usize,
+        len: usize,
+    },
+    /// The run used up `VmConfig.max_steps`; not catchable by imp code.
+    FuelExhausted {
+        max_steps: u64,
+        function: Arc<str>,
+        pc: usize,
+    },
+    /// The run outlasted `VmConfig.timeout`; not catchable by imp code.
+    Timeout {
+        timeout: Duration,
+        function: Arc<str>,
+        pc: usize,
+    },
+    /// `VmConfig.cancel` was tripped; not catchable by imp code.
+    Cancelled { function: Arc<str>, pc: usize },
+}
+
+/// One function an error escaped, with the pc it was at and, when the
+/// function carries `DebugInfo`, the matching source position.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TraceFrame {
+    pub function: Arc<str>,
+    pub pc: usize,
+    pub line: Option<u32>,
+    pub source: Option<Arc<str>>,
+    /// Frames just outside this one at the same function and pc (deep
+    /// recursion), folded into it.
+    pub repeats: usize,
+    /// Frames further out that a traceback past `MAX_TRACEBACK_FRAMES`
+    /// entries only counts; set on its last entry.
+    pub omitted: usize,
+}
+
+impl TraceFrame {
+    pub(crate) fn of(frame: &Frame) -> Self {
+        let debug = frame.meta.debug.as_deref();
+        Self {
+            function: Arc::clone(&frame.meta.name),
+            pc: frame.pc,
+            line: debug.and_then(|debug| debug.line(frame.pc)),
+            source: debug.and_then(|debug| debug.source.clone()),
+            repeats: 0,
+            omitted: 0,
+        }
+    }
+
+    fn same_site(&self, other: &Self) -> bool {
+        self.pc == other.pc && self.function == other.function
+    }
+}
+
+impl fmt::Display for TraceFrame {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} (", self.function)?;
+        match (&self.source, self.line) {
+            (Some(source), Some(line)) => write!(f, "{source}:{line}, ")?,
+            (None, Some(line)) => write!(f, "line {line}, ")?,
+            _ => {}
+        }
+        write!(f, "pc {})", self.pc)
+    }
+}
+
+impl VmError {
+    pub(crate) fn runtime(msg: impl Into<String>) -> Self {
+        Self::Runtime {
+            msg: msg.into(),
+            traceback: Vec::new(),
+        }
+    }
+
+    /// Call stack an uncaught runtime error or throw unwound through,
+    /// innermost frame first; empty for other errors.
+    #[must_use]
+    pub fn traceback(&self) -> &[TraceFrame] {
+        match self {
+            Self::Runtime { traceback, .. } | Self::Thrown { traceback, .. } => traceback,
+            Self::ImportInit { source, .. } => source.traceback(),
+            _ => &[],
+        }
+    }
+
+    /// Records `frame` as the next one out, folding it into the last entry
+    /// when it repeats it and only counting it once the traceback is full.
+    pub(crate) fn push_trace(&mut self, frame: &Frame) {
+        let (Self::Runtime { traceback, .. } | Self::Thrown { traceback, .. }) = self else {
+            return;
+        };
+        let frame = TraceFrame::of(frame);
+        let full = traceback.len() >= MAX_TRACEBACK_FRAMES;
+        match traceback.last_mut() {
+            Some(last) if last.omitted == 0 && last.same_site(&frame) => last.repeats += 1,
+            Some(last) if full => last.omitted += 1,
+            _ => traceback.push(frame),
+        }
+    }
+}
+
+/// What a VM-issued function handle points at, for error messages.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FuncSymbol {
+    pub name: Arc<str>,
+    pub module: Arc<str>,
+    /// Import alias the handle was linked through, if any.
+    pub alias: Option<String>,
+}
+
+impl fmt::Display for FuncSymbol {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} in module {}", self.name, self.module)?;
+        if let Some(alias) = &self.alias {
+            write!(f, " imported as '{alias}'")?;
+        }
+        Ok(())
+    }
 }
 
 impl fmt::Display for VmError {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
-            Self::Runtime(message) => write!(f, "runtime error: {message}"),
-            Self::Thrown { code, msg } => write!(f, "uncaught throw ({code}): {msg}"),
+            Self::Runtime { msg, .. } => write!(f, "runtime error: {msg}"),
+            Self::Thrown {
+                code,
+                msg,
+                cause,
+                data,
+                ..
+            } => {
+                write!(f, "uncaught throw ({code}): {msg}")?;
+                if let Some(data) = data {
+                    write!(f, "\ndata: {data}")?;
+                }
+                let mut next = cause.as_deref();
+                while let Some(cause) = next {
+                    match cause {
+                        Value::Error {
+                            code, msg, cause, ..
+                        } => {
+                            write!(f, "\ncaused by ({code}): {msg}")?;
+                            next = cause.as_deref();
+                        }
+                        other => {
+                            write!(f, "\ncaused by: {other}")?;
+                            next = None;
+                        }
+                    }
+                }
+                Ok(())
+            }
+            Self::JitDivergence {
+                function,
+                pc,
+                detail,
+            } => write!(f, "jit divergence in {function} at pc {pc}: {detail}"),
+            Self::ImportInit {
+                alias,
+                path,
+                source,
+            } => write!(
+                f,
+                "{source}\n  while initializing import '{alias}' ({path})"
+            ),
+            Self::UnknownFunction { id, symbol: None } => write!(f, "unknown function id {id}"),
+            Self::UnknownFunction {
+                id,
+                symbol: Some(symbol),
+            } => write!(
+                f,
+                "function id {id} ({symbol}) is not callable from this module"
+            ),
+            Self::BadBranchTarget {
+                function,
+                pc,
+                target,
+                len,
+            } => write!(
+                f,
+                "bad branch target in {function}: instruction {pc} targets pc {target} \
+                 (function has {len} instructions)"
+            ),
+            Self::FuelExhausted {
+                max_steps,
+                function,
+                pc,
+            } => write!(
+                f,
+                "fuel exhausted after {max_steps} steps in {function} at pc {pc}"
+            ),
+            Self::Timeout {
+                timeout,
+                function,
+                pc,
+            } => write!(
+                f,
+                "timed out after {}ms in {function} at pc {pc}",
+                timeout.as_millis()
+            ),
+            Self::Cancelled { function, pc } => {
+                write!(f, "cancelled in {function} at pc {pc}")
+            }
         }
     }
 }
 
-impl std::error::Error for VmError {}
+impl std::error::Error for VmError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::ImportInit { source, .. } => Some(source.as_ref()),
+            _ => None,
+        }
+    }
+}
 
-#[derive(Debug, Clone, Hash, PartialEq, Eq)]
+/// Identifies a function by its code, so same-named modules (or recompiled
+/// chunks of one) never share a plan. The key holds the code, so its address
+/// cannot be reused while the entry is cached.
+#[derive(Debug, Clone)]
 struct JitKey {
     module_name: String,
     func_id: FuncId,
+    code: Arc<[Instr]>,
 }
 
 impl JitKey {
@@ -94,10 +927,29 @@ impl JitKey {
         Self {
             module_name: module.name.to_string(),
             func_id: function.id,
+            code: Arc::clone(&function.code),
         }
     }
 }
 
+impl PartialEq for JitKey {
+    fn eq(&self, other: &Self) -> bool {
+        self.module_name == other.module_name
+            && self.func_id == other.func_id
+            && Arc::ptr_eq(&self.code, &other.code)
+    }
+}
+
+impl Eq for JitKey {}
+
+impl std::hash::Hash for JitKey {
+    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        self.module_name.hash(state);
+        self.func_id.hash(state);
+        self.code.as_ptr().hash(state);
+    }
+}
+
 #[derive(Debug, Clone)]
 struct JitFunction {
     steps: Arc<[JitStep]>,
@@ -107,19 +959,69 @@ struct JitFunction {
 struct ForeignFunc {
     module: Arc<CompiledModule>,
     func_id: FuncId,
+    /// Import path of the module instance whose globals the call runs
+    /// against; `None` runs on fresh globals.
+    instance: Option<String>,
+}
+
+/// An imported module after its init ran once for this VM.
+#[derive(Debug, Clone)]
+struct ModuleInstance {
+    /// Globals its functions run against; `None` while a call into the
+    /// module has them checked out.
+    globals: Option<Vec<Value>>,
+    exports: HashMap<String, Value>,
+}
+
+/// An init's returns, its globals before it ran (when snapshotted) and
+/// after.
+type InitOutcome = (Vec<Value>, Option<Vec<Value>>, Vec<Value>);
+
+/// A function value with leading arguments pre-filled by `core::fn::bind`.
+/// `target` is resolved in the module that invokes the handle.
+#[derive(Debug, Clone)]
+struct BoundFunc {
+    target: FuncId,
+    args: Vec<Value>,
 }
 
 impl JitFunction {
-    fn compile(function: &CompiledFunction) -> Self {
+    fn compile(function: &CompiledFunction) -> Result<Self, VmError> {
+        validate_branch_targets(&function.meta.name, &function.code)?;
+        let layout = RegLayout::of(function);
         let steps = function
             .code
             .iter()
-            .map(JitStep::from_instr)
+            .map(|instr| JitStep::from_instr(instr, layout))
             .collect::<Vec<_>>();
-        Self {
+        Ok(Self {
             steps: Arc::from(steps),
+        })
+    }
+}
+
+/// Checks every `Jump`, `Branch` and `TryPush` target against `code` so a
+/// malformed module fails before running rather than when the pc is reached.
+fn validate_branch_targets(function: &Arc<str>, code: &[Instr]) -> Result<(), VmError> {
+    for (pc, instr) in code.iter().enumerate() {
+        let targets = match instr {
+            Instr::Jump { target } => [Some(*target), None],
+            Instr::Branch {
+                then_pc, else_pc, ..
+            } => [Some(*then_pc), Some(*else_pc)],
+            Instr::TryPush { handler_pc } => [Some(*handler_pc), None],
+            _ => continue,
+        };
+        if let Some(target) = targets.into_iter().flatten().find(|t| *t >= code.len()) {
+            return Err(VmError::BadBranchTarget {
+                function: Arc::clone(function),
+                pc,
+                target,
+                len: code.len(),
+            });
         }
     }
+    Ok(())
 }
 
 #[derive(Debug, Clone)]
@@ -129,55 +1031,82 @@ struct JitStep {
 }
 
 impl JitStep {
-    fn from_instr(instr: &Instr) -> Self {
+    #[allow(clippy::too_many_lines)] // one arm per instruction
+    fn from_instr(instr: &Instr, layout: RegLayout) -> Self {
+        let reg = |slot: &Slot| layout.resolve(*slot);
         match instr {
             Instr::StoreConst { slot, value } => Self {
                 exec: step_store_const,
                 operands: JitOperands::StoreConst {
-                    slot: *slot,
+                    slot: reg(slot),
                     value: Value::from_const(value),
                 },
             },
             Instr::Move { from, to } => Self {
                 exec: step_move,
                 operands: JitOperands::Move {
-                    from: *from,
-                    to: *to,
+                    from: reg(from),
+                    to: reg(to),
                 },
             },
             Instr::Add { a, b, out } => Self {
                 exec: step_binary,
                 operands: JitOperands::Binary {
                     kind: BinaryOp::Add,
-                    a: *a,
-                    b: *b,
-                    out: *out,
+                    a: reg(a),
+                    b: reg(b),
+                    out: reg(out),
                 },
             },
             Instr::Sub { a, b, out } => Self {
                 exec: step_binary,
                 operands: JitOperands::Binary {
                     kind: BinaryOp::Sub,
-                    a: *a,
-                    b: *b,
-                    out: *out,
+                    a: reg(a),
+                    b: reg(b),
+                    out: reg(out),
                 },
             },
             Instr::Mul { a, b, out } => Self {
                 exec: step_binary,
                 operands: JitOperands::Binary {
                     kind: BinaryOp::Mul,
-                    a: *a,
-                    b: *b,
-                    out: *out,
+                    a: reg(a),
+                    b: reg(b),
+                    out: reg(out),
                 },
             },
             Instr::Div { a, b, out } => Self {
                 exec: step_binary,
                 operands: JitOperands::Binary {
                     kind: BinaryOp::Div,
-                    a: *a,
-                    b: *b,
+                    a: reg(a),
+                    b: reg(b),
+                    out: reg(out),
+                },
+            },
+            Instr::Mod { a, b, out } => Self {
+                exec: step_binary,
+                operands: JitOperands::Binary {
+                    kind: BinaryOp::Mod,
+                    a: reg(a),
+                    b: reg(b),
+                    out: reg(out),
+                },
+            },
+            Instr::Pow { a, b, out } => Self {
+                exec: step_binary,
+                operands: JitOperands::Binary {
+                    kind: BinaryOp::Pow,
+                    a: reg(a),
+                    b: reg(b),
+                    out: reg(out),
+                },
+            },
+            Instr::Neg { value, out } => Self {
+                exec: step_neg,
+                operands: JitOperands::Unary {
+                    value: *value,
                     out: *out,
                 },
             },
@@ -185,18 +1114,63 @@ impl JitStep {
                 exec: step_binary,
                 operands: JitOperands::Binary {
                     kind: BinaryOp::Eq,
-                    a: *a,
-                    b: *b,
-                    out: *out,
+                    a: reg(a),
+                    b: reg(b),
+                    out: reg(out),
                 },
             },
             Instr::Lt { a, b, out } => Self {
                 exec: step_binary,
                 operands: JitOperands::Binary {
                     kind: BinaryOp::Lt,
-                    a: *a,
-                    b: *b,
-                    out: *out,
+                    a: reg(a),
+                    b: reg(b),
+                    out: reg(out),
+                },
+            },
+            Instr::Ne { a, b, out } => Self {
+                exec: step_binary,
+                operands: JitOperands::Binary {
+                    kind: BinaryOp::Ne,
+                    a: reg(a),
+                    b: reg(b),
+                    out: reg(out),
+                },
+            },
+            Instr::Le { a, b, out } => Self {
+                exec: step_binary,
+                operands: JitOperands::Binary {
+                    kind: BinaryOp::Le,
+                    a: reg(a),
+                    b: reg(b),
+                    out: reg(out),
+                },
+            },
+            Instr::Gt { a, b, out } => Self {
+                exec: step_binary,
+                operands: JitOperands::Binary {
+                    kind: BinaryOp::Gt,
+                    a: reg(a),
+                    b: reg(b),
+                    out: reg(out),
+                },
+            },
+            Instr::Ge { a, b, out } => Self {
+                exec: step_binary,
+                operands: JitOperands::Binary {
+                    kind: BinaryOp::Ge,
+                    a: reg(a),
+                    b: reg(b),
+                    out: reg(out),
+                },
+            },
+            Instr::Cmp { a, b, out } => Self {
+                exec: step_binary,
+                operands: JitOperands::Binary {
+                    kind: BinaryOp::Cmp,
+                    a: reg(a),
+                    b: reg(b),
+                    out: reg(out),
                 },
             },
             Instr::Jump { target } => Self {
@@ -210,7 +1184,7 @@ impl JitStep {
             } => Self {
                 exec: step_branch,
                 operands: JitOperands::Branch {
-                    cond: *cond,
+                    cond: reg(cond),
                     then_pc: *then_pc,
                     else_pc: *else_pc,
                 },
@@ -223,6 +1197,37 @@ impl JitStep {
                     out: *out,
                 },
             },
+            Instr::InvokeMulti {
+                fn_slot,
+                args,
+                outs,
+            } => Self {
+                exec: step_invoke_multi,
+                operands: JitOperands::InvokeMulti {
+                    fn_slot: *fn_slot,
+                    args: args.clone(),
+                    outs: outs.as_slice().into(),
+                },
+            },
+            Instr::TailInvoke { fn_slot, args } => Self {
+                exec: step_tail_invoke,
+                operands: JitOperands::TailInvoke {
+                    fn_slot: *fn_slot,
+                    args: args.clone(),
+                },
+            },
+            Instr::InvokeDynamic {
+                fn_slot,
+                arglist,
+                out,
+            } => Self {
+                exec: step_invoke_dynamic,
+                operands: JitOperands::InvokeDynamic {
+                    fn_slot: *fn_slot,
+                    arglist: *arglist,
+                    out: *out,
+                },
+            },
             Instr::ReturnSet { slot_id, value } => Self {
                 exec: step_return_set,
                 operands: JitOperands::ReturnSet {
@@ -234,13 +1239,66 @@ impl JitStep {
                 exec: step_exit,
                 operands: JitOperands::None,
             },
-            Instr::Throw { code, msg } => Self {
+            Instr::Throw {
+                code,
+                msg,
+                cause,
+                data,
+            } => Self {
                 exec: step_throw,
                 operands: JitOperands::Throw {
                     code: Arc::from(code.as_str()),
                     msg: Arc::from(msg.as_str()),
+                    cause: *cause,
+                    data: *data,
+                },
+            },
+            Instr::ErrorWrap {
+                err,
+                code,
+                msg,
+                out,
+            } => Self {
+                exec: step_error_wrap,
+                operands: JitOperands::ErrorWrap {
+                    err: *err,
+                    code: Arc::from(code.as_str()),
+                    msg: Arc::from(msg.as_str()),
+                    out: *out,
+                },
+            },
+            Instr::ErrorCause { err, out } => Self {
+                exec: step_error_cause,
+                operands: JitOperands::Unary {
+                    value: *err,
+                    out: *out,
+                },
+            },
+            Instr::ErrorCode { err, out } => Self {
+                exec: step_error_code,
+                operands: JitOperands::Unary {
+                    value: *err,
+                    out: *out,
+                },
+            },
+            Instr::ErrorMsg { err, out } => Self {
+                exec: step_error_msg,
+                operands: JitOperands::Unary {
+                    value: *err,
+                    out: *out,
                 },
             },
+            Instr::ErrorData { err, out } => Self {
+                exec: step_error_data,
+                operands: JitOperands::Unary {
+                    value: *err,
+                    out: *out,
+                },
+            },
+            Instr::Rethrow { err } => Self {
+                exec: step_rethrow,
+                operands: JitOperands::UnarySlot { slot: *err },
+            },
             Instr::TryPush { handler_pc } => Self {
                 exec: step_try_push,
                 operands: JitOperands::TryPush {
@@ -287,28 +1345,219 @@ impl JitStep {
                     out: *out,
                 },
             },
-            Instr::StrConcat { a, b, out } => Self {
-                exec: step_str,
-                operands: JitOperands::StrOp {
-                    kind: StrOpKind::Concat,
-                    a: Some(*a),
-                    b: Some(*b),
+            Instr::ObjEntries { obj, out, len } => Self {
+                exec: step_obj_entries,
+                operands: JitOperands::ObjEntries {
+                    obj: *obj,
                     out: *out,
+                    len: *len,
                 },
             },
-            Instr::StrLen { value, out } => Self {
+            Instr::ObjKeys { .. } | Instr::ObjDel { .. } | Instr::ObjLen { .. } => Self {
+                exec: step_obj_op,
+                operands: JitOperands::Obj(ObjOperands::from_instr(instr)),
+            },
+            Instr::ObjGetPath { .. } | Instr::ObjSetPath { .. } => Self {
+                exec: step_obj_path,
+                operands: JitOperands::ObjPath(PathOperands::from_instr(instr)),
+            },
+            Instr::ListNew { .. }
+            | Instr::ListPush { .. }
+            | Instr::ListGet { .. }
+            | Instr::ListSet { .. }
+            | Instr::ListLen { .. } => Self {
+                exec: step_list,
+                operands: JitOperands::List(ListOperands::from_instr(instr)),
+            },
+            Instr::StrConcat { .. }
+            | Instr::StrLen { .. }
+            | Instr::StrUpper { .. }
+            | Instr::StrLower { .. }
+            | Instr::StrTrim { .. }
+            | Instr::StrTrimStart { .. }
+            | Instr::StrTrimEnd { .. }
+            | Instr::StrSub { .. }
+            | Instr::StrFind { .. }
+            | Instr::StrReplace { .. }
+            | Instr::StrContains { .. }
+            | Instr::StrSplit { .. }
+            | Instr::StrJoin { .. } => Self {
                 exec: step_str,
-                operands: JitOperands::StrOp {
-                    kind: StrOpKind::Len,
-                    a: Some(*value),
-                    b: None,
+                operands: JitOperands::Str(StrOperands::from_instr(instr)),
+            },
+            Instr::StrFormat { tmpl, args, out } => Self {
+                exec: step_str_format,
+                operands: JitOperands::StrFormat {
+                    tmpl: *tmpl,
+                    args: *args,
+                    out: *out,
+                },
+            },
+            Instr::BytesFromStr { .. }
+            | Instr::BytesToStr { .. }
+            | Instr::BytesLen { .. }
+            | Instr::BytesConcat { .. }
+            | Instr::BytesSlice { .. } => Self {
+                exec: step_bytes,
+                operands: JitOperands::Bytes(BytesOperands::from_instr(instr)),
+            },
+            Instr::FnBind { func, args, out } => Self {
+                exec: step_fn_bind,
+                operands: JitOperands::Invoke {
+                    fn_slot: *func,
+                    args: args.clone(),
+                    out: *out,
+                },
+            },
+            Instr::MakeClosure {
+                func,
+                captures,
+                out,
+            } => Self {
+                exec: step_make_closure,
+                operands: JitOperands::Invoke {
+                    fn_slot: *func,
+                    args: captures.clone(),
+                    out: *out,
+                },
+            },
+            Instr::IntParse { value, out } => Self {
+                exec: step_int_parse,
+                operands: JitOperands::Unary {
+                    value: *value,
+                    out: *out,
+                },
+            },
+            Instr::TypeOf { value, out } => Self {
+                exec: step_type_of,
+                operands: JitOperands::Unary {
+                    value: *value,
+                    out: *out,
+                },
+            },
+            Instr::IsType { value, kind, out } => Self {
+                exec: step_is_type,
+                operands: JitOperands::IsType {
+                    value: *value,
+                    kind: *kind,
+                    out: *out,
+                },
+            },
+            Instr::NumParse { value, out } => Self {
+                exec: step_num_parse,
+                operands: JitOperands::Unary {
+                    value: *value,
+                    out: *out,
+                },
+            },
+            Instr::NumFormat {
+                value,
+                precision,
+                width,
+                out,
+            } => Self {
+                exec: step_num_format,
+                operands: JitOperands::NumFormat {
+                    value: *value,
+                    precision: *precision,
+                    width: *width,
+                    out: *out,
+                },
+            },
+            Instr::FnMeta { func, out } => Self {
+                exec: step_fn_meta,
+                operands: JitOperands::Unary {
+                    value: *func,
                     out: *out,
                 },
             },
-            Instr::HostPrint { slot } => Self {
+            Instr::Ext { op, operands } => Self {
+                exec: step_ext,
+                operands: JitOperands::Ext {
+                    op: *op,
+                    operands: operands.clone(),
+                },
+            },
+            Instr::HostCall { name, args, out } => Self {
+                exec: step_host_call,
+                operands: JitOperands::HostCall {
+                    name: Arc::from(name.as_str()),
+                    args: args.clone(),
+                    out: *out,
+                },
+            },
+            Instr::HostPrint {
+                slot,
+                stream,
+                level,
+            } => Self {
                 exec: step_host_print,
-                operands: JitOperands::UnarySlot { slot: *slot },
+                operands: JitOperands::HostPrint {
+                    slot: *slot,
+                    stream: *stream,
+                    level: *level,
+                },
+            },
+            Instr::HostTime { clock, out } => Self {
+                exec: step_host_time,
+                operands: JitOperands::HostTime {
+                    clock: *clock,
+                    out: *out,
+                },
+            },
+            Instr::HostArgs { out } => Self {
+                exec: step_host_args,
+                operands: JitOperands::UnarySlot { slot: *out },
+            },
+            Instr::HostReadLine { out } => Self {
+                exec: step_host_read_line,
+                operands: JitOperands::UnarySlot { slot: *out },
             },
+            Instr::HostReadAll { out } => Self {
+                exec: step_host_read_all,
+                operands: JitOperands::UnarySlot { slot: *out },
+            },
+            Instr::HostEnvVar { key, out } => Self {
+                exec: step_host_env_var,
+                operands: JitOperands::Unary {
+                    value: *key,
+                    out: *out,
+                },
+            },
+            Instr::HostRandom { out } => Self {
+                exec: step_host_random,
+                operands: JitOperands::HostRandom {
+                    range: None,
+                    out: *out,
+                },
+            },
+            Instr::HostRandomInt { lo, hi, out } => Self {
+                exec: step_host_random,
+                operands: JitOperands::HostRandom {
+                    range: Some((*lo, *hi)),
+                    out: *out,
+                },
+            },
+            Instr::JsonParse { value, out } => Self {
+                exec: step_json_parse,
+                operands: JitOperands::Unary {
+                    value: *value,
+                    out: *out,
+                },
+            },
+            Instr::JsonStringify { value, out } => Self {
+                exec: step_json_stringify,
+                operands: JitOperands::Unary {
+                    value: *value,
+                    out: *out,
+                },
+            },
+            Instr::HostFsRead { .. } | Instr::HostFsWrite { .. } | Instr::HostFsExists { .. } => {
+                Self {
+                    exec: step_host_fs,
+                    operands: JitOperands::Fs(FsOperands::from_instr(instr)),
+                }
+            }
         }
     }
 }
@@ -328,25 +1577,52 @@ enum JitOperands {
     UnarySlot {
         slot: Slot,
     },
-    StoreConst {
+    HostPrint {
         slot: Slot,
+        stream: PrintStream,
+        level: PrintLevel,
+    },
+    HostTime {
+        clock: HostClock,
+        out: Slot,
+    },
+    HostRandom {
+        range: Option<(Slot, Slot)>,
+        out: Slot,
+    },
+    Fs(FsOperands),
+    HostCall {
+        name: Arc<str>,
+        args: Vec<Slot>,
+        out: Slot,
+    },
+    Ext {
+        op: u16,
+        operands: Vec<Slot>,
+    },
+    Unary {
+        value: Slot,
+        out: Slot,
+    },
+    StoreConst {
+        slot: Reg,
         value: Value,
     },
     Move {
-        from: Slot,
-        to: Slot,
+        from: Reg,
+        to: Reg,
     },
     Binary {
         kind: BinaryOp,
-        a: Slot,
-        b: Slot,
-        out: Slot,
+        a: Reg,
+        b: Reg,
+        out: Reg,
     },
     Jump {
         target: usize,
     },
     Branch {
-        cond: Slot,
+        cond: Reg,
         then_pc: usize,
         else_pc: usize,
     },
@@ -355,6 +1631,20 @@ enum JitOperands {
         args: Vec<Slot>,
         out: Slot,
     },
+    InvokeMulti {
+        fn_slot: Slot,
+        args: Vec<Slot>,
+        outs: Rc<[Slot]>,
+    },
+    TailInvoke {
+        fn_slot: Slot,
+        args: Vec<Slot>,
+    },
+    InvokeDynamic {
+        fn_slot: Slot,
+        arglist: Slot,
+        out: Slot,
+    },
     ReturnSet {
         slot_id: u32,
         value: Slot,
@@ -362,6 +1652,14 @@ enum JitOperands {
     Throw {
         code: Arc<str>,
         msg: Arc<str>,
+        cause: Option<Slot>,
+        data: Option<Slot>,
+    },
+    ErrorWrap {
+        err: Slot,
+        code: Arc<str>,
+        msg: Arc<str>,
+        out: Slot,
     },
     TryPush {
         handler_pc: usize,
@@ -378,10 +1676,30 @@ enum JitOperands {
         key: Slot,
         out: Slot,
     },
-    StrOp {
-        kind: StrOpKind,
-        a: Option<Slot>,
-        b: Option<Slot>,
+    ObjEntries {
+        obj: Slot,
+        out: Slot,
+        len: Option<Slot>,
+    },
+    Obj(ObjOperands),
+    ObjPath(PathOperands),
+    StrFormat {
+        tmpl: Slot,
+        args: Slot,
+        out: Slot,
+    },
+    Str(StrOperands),
+    Bytes(BytesOperands),
+    List(ListOperands),
+    NumFormat {
+        value: Slot,
+        precision: Option<Slot>,
+        width: Option<Slot>,
+        out: Slot,
+    },
+    IsType {
+        value: Slot,
+        kind: ValueKind,
         out: Slot,
     },
 }
@@ -392,8 +1710,15 @@ enum BinaryOp {
     Sub,
     Mul,
     Div,
+    Mod,
+    Pow,
     Eq,
+    Ne,
     Lt,
+    Le,
+    Gt,
+    Ge,
+    Cmp,
 }
 
 #[derive(Debug, Clone, Copy)]
@@ -403,1405 +1728,9242 @@ enum ObjLookupKind {
 }
 
 #[derive(Debug, Clone, Copy)]
-enum StrOpKind {
-    Concat,
+enum ObjOpKind {
+    Keys,
+    Del,
     Len,
 }
 
+/// Operands of `core::obj::keys`/`del`/`len`, shared by both tiers.
 #[derive(Debug, Clone, Copy)]
-enum StepControl {
-    Next(usize),
-    Exit,
-}
-
-#[derive(Debug, Clone)]
-pub struct Vm {
-    cfg: VmConfig,
-    active_module: Option<CompiledModule>,
-    jit_cache: HashMap<JitKey, Arc<JitFunction>>,
-    foreign_funcs: HashMap<FuncId, ForeignFunc>,
-    import_export_cache: HashMap<String, HashMap<String, Value>>,
-    next_foreign_func_id: FuncId,
+struct ObjOperands {
+    kind: ObjOpKind,
+    obj: Slot,
+    key: Option<Slot>,
+    out: Slot,
 }
 
-impl Vm {
-    pub fn new(cfg: VmConfig) -> Self {
+impl ObjOperands {
+    fn from_instr(instr: &Instr) -> Self {
+        let (kind, obj, key, out) = match instr {
+            Instr::ObjKeys { obj, out } => (ObjOpKind::Keys, *obj, None, *out),
+            Instr::ObjDel { obj, key, out } => (ObjOpKind::Del, *obj, Some(*key), *out),
+            Instr::ObjLen { obj, out } => (ObjOpKind::Len, *obj, None, *out),
+            other => unreachable!("not an object instruction: {other:?}"),
+        };
         Self {
-            cfg,
-            active_module: None,
-            jit_cache: HashMap::new(),
-            foreign_funcs: HashMap::new(),
-            import_export_cache: HashMap::new(),
-            next_foreign_func_id: 1_000_000,
+            kind,
+            obj,
+            key,
+            out,
         }
     }
+}
 
-    pub fn run_main(&mut self, module: &CompiledModule) -> Result<RunResult, VmError> {
-        self.active_module = Some(module.clone());
-        let mut globals = self.build_module_globals(module)?;
-
-        let returns = self.execute_function(module, module.init_func, &[], &mut globals)?;
+/// Operands of `core::obj::getpath`/`setpath`, shared by both tiers;
+/// `value` is set only for `setpath`.
+#[derive(Debug, Clone, Copy)]
+struct PathOperands {
+    obj: Slot,
+    path: Slot,
+    value: Option<Slot>,
+    out: Slot,
+    safe: bool,
+}
 
-        let mut exports = HashMap::new();
-        for (name, slot) in &module.exports {
-            exports.insert(name.clone(), globals[*slot as usize].clone());
+impl PathOperands {
+    fn from_instr(instr: &Instr) -> Self {
+        match instr {
+            Instr::ObjGetPath {
+                obj,
+                path,
+                out,
+                safe,
+            } => Self {
+                obj: *obj,
+                path: *path,
+                value: None,
+                out: *out,
+                safe: *safe,
+            },
+            Instr::ObjSetPath {
+                obj,
+                path,
+                value,
+                out,
+                safe,
+            } => Self {
+                obj: *obj,
+                path: *path,
+                value: Some(*value),
+                out: *out,
+                safe: *safe,
+            },
+            other => unreachable!("not an object path instruction: {other:?}"),
         }
-
-        self.active_module = Some(module.clone());
-        Ok(RunResult { returns, exports })
-    }
-
-    pub fn invoke(&mut self, func: FuncId, args: &[Value]) -> Result<Vec<Value>, VmError> {
-        let module = self
-            .active_module
-            .as_ref()
-            .ok_or_else(|| VmError::Runtime("no active module; call run_main first".to_owned()))?
-            .clone();
-        let mut globals = self.build_module_globals(&module)?;
-        self.execute_function(&module, func, args, &mut globals)
     }
+}
 
-    fn build_module_globals(&mut self, module: &CompiledModule) -> Result<Vec<Value>, VmError> {
-        let mut globals = vec![Value::Null; module.global_count as usize];
+#[derive(Debug, Clone, Copy)]
+enum StrOpKind {
+    Concat,
+    Len,
+    Upper,
+    Lower,
+    Trim,
+    TrimStart,
+    TrimEnd,
+    Sub,
+    Find,
+    Replace,
+    Contains,
+    Split,
+    Join,
+}
 
-        for (slot, func_id) in &module.function_globals {
-            globals[*slot as usize] = Value::Func(*func_id);
+impl StrOpKind {
+    fn name(self) -> &'static str {
+        match self {
+            Self::Concat => "core::str::concat",
+            Self::Len => "core::str::len",
+            Self::Upper => "core::str::upper",
+            Self::Lower => "core::str::lower",
+            Self::Trim => "core::str::trim",
+            Self::TrimStart => "core::str::trim_start",
+            Self::TrimEnd => "core::str::trim_end",
+            Self::Sub => "core::str::sub",
+            Self::Find => "core::str::find",
+            Self::Replace => "core::str::replace",
+            Self::Contains => "core::str::contains",
+            Self::Split => "core::str::split",
+            Self::Join => "core::str::join",
         }
+    }
+}
 
-        for import in &module.imports {
-            if !self.import_export_cache.contains_key(&import.path) {
-                let imported = self.run_main(&import.module)?;
-                let mut linked_exports = HashMap::new();
-                for (name, value) in &imported.exports {
-                    linked_exports.insert(
-                        name.clone(),
-                        self.link_imported_value(value, Arc::clone(&import.module)),
-                    );
-                }
-                self.import_export_cache
-                    .insert(import.path.clone(), linked_exports);
-            }
-            let Some(cached_exports) = self.import_export_cache.get(&import.path) else {
-                continue;
-            };
-            for (name, destination) in &import.export_to_global {
-                if (*destination as usize) < globals.len()
-                    && let Some(value) = cached_exports.get(name)
-                {
-                    globals[*destination as usize] = value.clone();
-                }
+/// Operands of the `core::str::*` instructions other than `format`, shared
+/// by both tiers.
+#[derive(Debug, Clone, Copy)]
+struct StrOperands {
+    kind: StrOpKind,
+    value: Slot,
+    /// `b` for concat, `start` for sub, `pattern` for the searches, `sep`
+    /// for split and join.
+    second: Option<Slot>,
+    /// `end` for sub, `with` for replace.
+    third: Option<Slot>,
+    out: Slot,
+}
+
+impl StrOperands {
+    fn from_instr(instr: &Instr) -> Self {
+        let (kind, value, second, third, out) = match instr {
+            Instr::StrConcat { a, b, out } => (StrOpKind::Concat, *a, Some(*b), None, *out),
+            Instr::StrLen { value, out } => (StrOpKind::Len, *value, None, None, *out),
+            Instr::StrUpper { value, out } => (StrOpKind::Upper, *value, None, None, *out),
+            Instr::StrLower { value, out } => (StrOpKind::Lower, *value, None, None, *out),
+            Instr::StrTrim { value, out } => (StrOpKind::Trim, *value, None, None, *out),
+            Instr::StrTrimStart { value, out } => (StrOpKind::TrimStart, *value, None, None, *out),
+            Instr::StrTrimEnd { value, out } => (StrOpKind::TrimEnd, *value, None, None, *out),
+            Instr::StrSub {
+                value,
+                start,
+                end,
+                out,
+            } => (StrOpKind::Sub, *value, Some(*start), *end, *out),
+            Instr::StrFind {
+                value,
+                pattern,
+                out,
+            } => (StrOpKind::Find, *value, Some(*pattern), None, *out),
+            Instr::StrReplace {
+                value,
+                pattern,
+                with,
+                out,
+            } => (
+                StrOpKind::Replace,
+                *value,
+                Some(*pattern),
+                Some(*with),
+                *out,
+            ),
+            Instr::StrContains {
+                value,
+                pattern,
+                out,
+            } => (StrOpKind::Contains, *value, Some(*pattern), None, *out),
+            Instr::StrSplit { value, sep, out } => {
+                (StrOpKind::Split, *value, Some(*sep), None, *out)
             }
+            Instr::StrJoin { list, sep, out } => (StrOpKind::Join, *list, Some(*sep), None, *out),
+            other => unreachable!("not a str instruction: {other:?}"),
+        };
+        Self {
+            kind,
+            value,
+            second,
+            third,
+            out,
         }
-
-        Ok(globals)
     }
+}
 
-    fn link_imported_value(&mut self, value: &Value, module: Arc<CompiledModule>) -> Value {
-        match value {
-            Value::Func(func_id) => {
-                let handle = self.register_foreign_func(module, *func_id);
-                Value::Func(handle)
-            }
-            Value::Obj(map) => Value::Obj(
-                map.iter()
-                    .map(|(key, value)| {
-                        (
-                            key.clone(),
-                            self.link_imported_value(value, Arc::clone(&module)),
-                        )
-                    })
-                    .collect(),
-            ),
-            _ => value.clone(),
+#[derive(Debug, Clone, Copy)]
+enum BytesOpKind {
+    FromStr,
+    ToStr,
+    Len,
+    Concat,
+    Slice,
+}
+
+/// Operands of the `core::bytes::*` instructions, shared by both tiers.
+#[derive(Debug, Clone, Copy)]
+struct BytesOperands {
+    kind: BytesOpKind,
+    value: Slot,
+    /// `b` for concat, `start` for slice.
+    second: Option<Slot>,
+    /// `end` for slice.
+    third: Option<Slot>,
+    out: Slot,
+}
+
+impl BytesOperands {
+    fn from_instr(instr: &Instr) -> Self {
+        let (kind, value, second, third, out) = match instr {
+            Instr::BytesFromStr { value, out } => (BytesOpKind::FromStr, *value, None, None, *out),
+            Instr::BytesToStr { value, out } => (BytesOpKind::ToStr, *value, None, None, *out),
+            Instr::BytesLen { value, out } => (BytesOpKind::Len, *value, None, None, *out),
+            Instr::BytesConcat { a, b, out } => (BytesOpKind::Concat, *a, Some(*b), None, *out),
+            Instr::BytesSlice {
+                value,
+                start,
+                end,
+                out,
+            } => (BytesOpKind::Slice, *value, Some(*start), *end, *out),
+            other => unreachable!("not a bytes instruction: {other:?}"),
+        };
+        Self {
+            kind,
+            value,
+            second,
+            third,
+            out,
         }
     }
+}
 
-    fn register_foreign_func(&mut self, module: Arc<CompiledModule>, func_id: FuncId) -> FuncId {
-        let handle = self.next_foreign_func_id;
-        self.next_foreign_func_id = self.next_foreign_func_id.saturating_add(1);
-        self.foreign_funcs
-            .insert(handle, ForeignFunc { module, func_id });
-        handle
+#[derive(Debug, Clone, Copy)]
+enum ListOpKind {
+    New,
+    Push,
+    Get,
+    Set,
+    Len,
+}
+
+/// Operands of the `core::host::fs::*` instructions, shared by both tiers.
+#[derive(Debug, Clone, Copy)]
+struct FsOperands {
+    op: FsOp,
+    path: Slot,
+    data: Option<Slot>,
+    out: Option<Slot>,
+}
+
+impl FsOperands {
+    fn from_instr(instr: &Instr) -> Self {
+        let (op, path, data, out) = match instr {
+            Instr::HostFsRead { path, out } => (FsOp::Read, *path, None, Some(*out)),
+            Instr::HostFsWrite { path, data } => (FsOp::Write, *path, Some(*data), None),
+            Instr::HostFsExists { path, out } => (FsOp::Exists, *path, None, Some(*out)),
+            other => unreachable!("not an fs instruction: {other:?}"),
+        };
+        Self {
+            op,
+            path,
+            data,
+            out,
+        }
     }
+}
 
-    fn bridge_value_for_module(&mut self, module: &Arc<CompiledModule>, value: &Value) -> Value {
-        match value {
-            Value::Func(func_id) => {
-                if let Some(foreign) = self.foreign_funcs.get(func_id).cloned() {
-                    Value::Func(self.register_foreign_func(foreign.module, foreign.func_id))
-                } else if module.function(*func_id).is_some() {
-                    Value::Func(self.register_foreign_func(Arc::clone(module), *func_id))
-                } else {
-                    Value::Func(*func_id)
-                }
+/// Operands of the `core::list::*` instructions, shared by both tiers.
+#[derive(Debug, Clone, Copy)]
+struct ListOperands {
+    kind: ListOpKind,
+    list: Option<Slot>,
+    index: Option<Slot>,
+    value: Option<Slot>,
+    out: Slot,
+}
+
+impl ListOperands {
+    fn from_instr(instr: &Instr) -> Self {
+        let (kind, list, index, value, out) = match instr {
+            Instr::ListNew { out } => (ListOpKind::New, None, None, None, *out),
+            Instr::ListPush { list, value, out } => {
+                (ListOpKind::Push, Some(*list), None, Some(*value), *out)
             }
-            Value::Obj(map) => Value::Obj(
-                map.iter()
-                    .map(|(key, value)| (key.clone(), self.bridge_value_for_module(module, value)))
-                    .collect(),
+            Instr::ListGet { list, index, out } => {
+                (ListOpKind::Get, Some(*list), Some(*index), None, *out)
+            }
+            Instr::ListSet {
+                list,
+                index,
+                value,
+                out,
+            } => (
+                ListOpKind::Set,
+                Some(*list),
+                Some(*index),
+                Some(*value),
+                *out,
             ),
-            _ => value.clone(),
+            Instr::ListLen { list, out } => (ListOpKind::Len, Some(*list), None, None, *out),
+            other => unreachable!("not a list instruction: {other:?}"),
+        };
+        Self {
+            kind,
+            list,
+            index,
+            value,
+            out,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+enum StepControl {
+    Next(usize),
+    /// Hand a call to the driver loop; the frame resumes at `pc + 1`.
+    Call {
+        func: FuncId,
+        args: Vec<Value>,
+        out: CallOut,
+    },
+    /// Hand a tail call to the driver loop, which replaces the frame.
+    TailCall {
+        func: FuncId,
+        args: Vec<Value>,
+    },
+    Exit,
+}
+
+#[derive(Debug, Clone)]
+pub struct Vm {
+    cfg: VmConfig,
+    active_module: Option<CompiledModule>,
+    jit_cache: JitCache,
+    /// Spare argument buffers for `Invoke`.
+    arg_pool: Vec<Vec<Value>>,
+    foreign_funcs: HashMap<FuncId, ForeignFunc>,
+    bound_funcs: HashMap<FuncId, BoundFunc>,
+    /// Names for every handle ever issued; kept after the handle is dropped.
+    symbols: HashMap<FuncId, FuncSymbol>,
+    /// Initialized imports by path.
+    instances: HashMap<String, ModuleInstance>,
+    /// Import paths whose init is running, outermost first.
+    initializing: Vec<String>,
+    next_foreign_func_id: FuncId,
+    stats: RunStats,
+    profiler: Profiler,
+    coverage: Coverage,
+    call_depth: usize,
+    run_depth: usize,
+    shadow_runs: usize,
+    /// Steps left in the current run when `max_steps` is set.
+    fuel: Option<u64>,
+    /// Heap bytes charged against `max_alloc_bytes` in the current run.
+    allocated_bytes: usize,
+    /// Heap bytes `max_memory` counts as live: the last measurement plus
+    /// what was allocated since.
+    live_bytes: usize,
+    /// Frames under the running one, measured for `max_memory`.
+    suspended: Suspended,
+    /// When the current run's `VmConfig.timeout` runs out.
+    run_deadline: Option<Instant>,
+    /// Steps until `timeout` and `cancel` are next looked at.
+    interrupt_poll: u32,
+    /// Zero of `core::host::monotonic`.
+    clock_origin: Instant,
+    /// Generator behind `core::host::random`.
+    rng: Rng,
+    /// Input reads made by shadow runs ahead of the real run, which
+    /// consumes them in order instead of reading again.
+    input_lookahead: VecDeque<Option<String>>,
+    /// How much of `input_lookahead` the current shadow run has used.
+    shadow_input_pos: usize,
+    /// Globals carried between [`Vm::run_incremental`] calls.
+    session_globals: Vec<Value>,
+    /// Run begun by [`Vm::start`] and advanced by [`Vm::step`].
+    stepped: Option<SteppedRun>,
+    /// Instructions the current [`Vm::step`] may still run.
+    step_budget: Option<u64>,
+    /// Where [`Vm::step`] pauses; see [`Vm::set_breakpoints`].
+    breakpoints: Vec<Breakpoint>,
+    /// Set by [`Vm::step`] so a run paused at a breakpoint can move past it.
+    resuming: bool,
+}
+
+impl Vm {
+    #[must_use]
+    pub fn new(cfg: VmConfig) -> Self {
+        let env = cfg.env.as_deref().unwrap_or(&SystemEnv);
+        let clock_origin = env.monotonic();
+        let rng = Rng::new(cfg.rng_seed.unwrap_or_else(|| env.random_seed()));
+        Self {
+            cfg,
+            clock_origin,
+            rng,
+            input_lookahead: VecDeque::new(),
+            shadow_input_pos: 0,
+            active_module: None,
+            jit_cache: JitCache::default(),
+            arg_pool: Vec::new(),
+            foreign_funcs: HashMap::new(),
+            bound_funcs: HashMap::new(),
+            symbols: HashMap::new(),
+            instances: HashMap::new(),
+            initializing: Vec::new(),
+            next_foreign_func_id: 1_000_000,
+            stats: RunStats::default(),
+            profiler: Profiler::default(),
+            coverage: Coverage::default(),
+            call_depth: 0,
+            run_depth: 0,
+            shadow_runs: 0,
+            fuel: None,
+            allocated_bytes: 0,
+            live_bytes: 0,
+            suspended: Suspended::default(),
+            run_deadline: None,
+            interrupt_poll: 0,
+            session_globals: Vec::new(),
+            stepped: None,
+            step_budget: None,
+            breakpoints: Vec::new(),
+            resuming: false,
         }
     }
 
-    fn execute_function(
+    /// Runs `module`'s init, after its imports', and returns its returns
+    /// and exports.
+    ///
+    /// # Errors
+    ///
+    /// An uncaught throw or runtime error, a spent step or time budget, a
+    /// cancellation, or a JIT divergence under `verify_jit`.
+    pub fn run_main(&mut self, module: &CompiledModule) -> Result<RunResult, VmError> {
+        self.run_top(module, false)
+    }
+
+    /// Like [`Self::run_main`], but the init runs on the globals left by the
+    /// previous incremental run instead of fresh ones, for a module that
+    /// grows a chunk at a time (`imp repl`). Globals are extended to
+    /// `global_count` and function and import slots are rebound; every other
+    /// slot keeps its value, even across a failed run.
+    ///
+    /// # Errors
+    ///
+    /// As [`Vm::run_main`].
+    pub fn run_incremental(&mut self, module: &CompiledModule) -> Result<RunResult, VmError> {
+        self.run_top(module, true)
+    }
+
+    /// Globals kept by [`Self::run_incremental`], indexed by slot.
+    #[must_use]
+    pub fn session_globals(&self) -> &[Value] {
+        &self.session_globals
+    }
+
+    fn run_top(
         &mut self,
         module: &CompiledModule,
-        func_id: FuncId,
-        args: &[Value],
+        incremental: bool,
+    ) -> Result<RunResult, VmError> {
+        let started = self.env().monotonic();
+        if self.run_depth == 0 {
+            self.stats = RunStats::default();
+            self.profiler = Profiler::default();
+            self.start_budget();
+        }
+        self.run_depth += 1;
+        let outcome = if incremental {
+            self.run_incremental_inner(module)
+        } else {
+            self.run_main_inner(module)
+        };
+        self.run_depth -= 1;
+
+        let (returns, before, globals) = outcome?;
+        let exports = module_exports(module, &globals);
+        if self.run_depth == 0 {
+            self.stats.wall_time = self.env().monotonic().saturating_duration_since(started);
+        }
+        let stats = (self.cfg.profile && self.run_depth == 0).then(|| self.stats());
+        Ok(RunResult {
+            returns,
+            exports,
+            stats,
+            global_deltas: before.map(|before| global_deltas(module, &before, &globals)),
+        })
+    }
+
+    /// Runs `module`'s init; returns its returns, its linked globals when
+    /// [`Self::snapshot_globals`] keeps them, and its final globals.
+    fn run_main_inner(&mut self, module: &CompiledModule) -> Result<InitOutcome, VmError> {
+        self.active_module = Some(module.clone());
+        let mut globals = self.build_module_globals(module)?;
+        let before = self.snapshot_globals(&globals);
+
+        let returns = self.execute_function(module, module.init_func, &[], &mut globals)?;
+
+        self.active_module = Some(module.clone());
+        Ok((returns, before, globals))
+    }
+
+    fn run_incremental_inner(&mut self, module: &CompiledModule) -> Result<InitOutcome, VmError> {
+        self.active_module = Some(module.clone());
+        let linked = self.build_module_globals(module)?;
+        let mut globals = std::mem::take(&mut self.session_globals);
+        globals.resize(linked.len(), Value::Null);
+        let bound = module.function_globals.iter().map(|(slot, _)| *slot).chain(
+            module
+                .imports
+                .iter()
+                .flat_map(|import| import.export_to_global.iter().map(|(_, slot)| *slot)),
+        );
+        for slot in bound {
+            globals[slot as usize] = linked[slot as usize].clone();
+        }
+        let before = self.snapshot_globals(&globals);
+
+        let returns = self.execute_function(module, module.init_func, &[], &mut globals);
+        self.session_globals = globals;
+        self.active_module = Some(module.clone());
+        Ok((returns?, before, self.session_globals.clone()))
+    }
+
+    /// Deep copy of a top-level run's globals before its init, so objects
+    /// changed in place still show up; kept only when
+    /// `VmConfig.track_global_deltas` is enabled.
+    pub(crate) fn snapshot_globals(&self, globals: &[Value]) -> Option<Vec<Value>> {
+        (self.cfg.track_global_deltas && self.run_depth <= 1)
+            .then(|| globals.iter().map(Value::deep_copy).collect())
+    }
+
+    /// Resolves the target of an `Invoke`/`InvokeMulti`/`TailInvoke` and
+    /// gathers its arguments. `None` means the call threw and `frame.pc`
+    /// already points at the handler. A `tail` call reuses the frame, so it
+    /// needs no room on the call stack.
+    fn prepare_invoke(
+        &mut self,
+        frame: &mut Frame,
         globals: &mut [Value],
+        fn_slot: Slot,
+        args: &[Slot],
+        tail: bool,
+    ) -> Result<Option<(FuncId, Vec<Value>)>, VmError> {
+        let target = frame.get(fn_slot, globals)?;
+        let mut values = self.gather_args(frame, globals, args)?;
+        let target_func = match invoke_target(frame, &target, &mut values) {
+            Ok(func) => func,
+            Err((code, msg)) => {
+                self.recycle_args(values);
+                self.throw_type_error(frame, code, &msg, globals)?;
+                return Ok(None);
+            }
+        };
+        if !tail && let Some(msg) = self.stack_overflow_message() {
+            self.recycle_args(values);
+            frame.throw("stack_overflow", &msg, globals)?;
+            return Ok(None);
+        }
+        Ok(Some((target_func, values)))
+    }
+
+    /// Reads invoke arguments into a pooled buffer; hand it back with
+    /// [`Self::recycle_args`] once the callee returns.
+    fn gather_args(
+        &mut self,
+        frame: &Frame,
+        globals: &[Value],
+        slots: &[Slot],
     ) -> Result<Vec<Value>, VmError> {
-        if module.function(func_id).is_none() {
-            if let Some(foreign) = self.foreign_funcs.get(&func_id).cloned() {
-                let mut foreign_globals = self.build_module_globals(&foreign.module)?;
-                let caller_module = Arc::new(module.clone());
-                let bridged_args = args
-                    .iter()
-                    .map(|value| self.bridge_value_for_module(&caller_module, value))
-                    .collect::<Vec<_>>();
-                return self.execute_function(
-                    &foreign.module,
-                    foreign.func_id,
-                    &bridged_args,
-                    &mut foreign_globals,
+        let mut values = self.arg_pool.pop().unwrap_or_default();
+        for slot in slots {
+            values.push(frame.get(*slot, globals)?);
+        }
+        Ok(values)
+    }
+
+    /// Arguments for `InvokeDynamic`: the items of the list in `arglist`.
+    fn spread_args(
+        &mut self,
+        frame: &Frame,
+        globals: &[Value],
+        arglist: Slot,
+    ) -> Result<Vec<Value>, VmError> {
+        let Value::List(items) = frame.get(arglist, globals)? else {
+            return Err(VmError::runtime(format!(
+                "core::invoke::dynamic arglist is not a list in {}",
+                frame.meta.name
+            )));
+        };
+        let mut values = self.arg_pool.pop().unwrap_or_default();
+        values.extend(items);
+        Ok(values)
+    }
+
+    /// Throws a type mismatch in `frame`, or fails the run under
+    /// `VmConfig.strict_type_errors`.
+    fn throw_type_error(
+        &self,
+        frame: &mut Frame,
+        code: &str,
+        msg: &str,
+        globals: &mut [Value],
+    ) -> Result<usize, VmError> {
+        if self.cfg.strict_type_errors {
+            return Err(VmError::runtime(msg));
+        }
+        frame.throw(code, msg, globals)
+    }
+
+    /// `core::rethrow`: throws `err` again as it was caught; anything but an
+    /// error value is a type error.
+    fn rethrow(
+        &self,
+        frame: &mut Frame,
+        err: Value,
+        globals: &mut [Value],
+    ) -> Result<usize, VmError> {
+        match err {
+            Value::Error {
+                code,
+                msg,
+                cause,
+                data,
+            } => frame.throw_error(code, msg, cause, data, globals),
+            other => {
+                let msg = format!(
+                    "core::rethrow expects an error, found {}",
+                    other.type_name()
                 );
+                self.throw_type_error(frame, "type_error", &msg, globals)
             }
-            return Err(VmError::Runtime(format!("unknown function id {func_id}")));
         }
-        let function = module
-            .function(func_id)
-            .ok_or_else(|| VmError::Runtime(format!("unknown function id {func_id}")))?;
-        let mut frame = Frame::new(function, args);
+    }
+
+    fn recycle_args(&mut self, mut values: Vec<Value>) {
+        values.clear();
+        self.arg_pool.push(values);
+    }
+
+    /// Number of functions with a cached JIT plan.
+    #[must_use]
+    pub fn jit_cache_len(&self) -> usize {
+        self.jit_cache.len()
+    }
+
+    /// Coverage accumulated over every run on this VM so far.
+    #[must_use]
+    pub fn coverage(&self) -> &Coverage {
+        &self.coverage
+    }
 
-        if self.cfg.enable_jit {
-            let jit = self.get_or_compile_jit(module, function);
-            return self.execute_function_jit(module, &mut frame, globals, &jit);
+    /// Counters of the current (or last) run; only filled in with
+    /// `VmConfig.profile`. Functions still running count their time so far.
+    #[must_use]
+    pub fn stats(&self) -> RunStats {
+        RunStats {
+            functions: self.profiler.snapshot(self.env().monotonic()),
+            ..self.stats.clone()
         }
+    }
 
-        self.execute_function_interpreter(module, &mut frame, globals)
+    /// Qualified name, defining module and import alias of a function value.
+    #[must_use]
+    pub fn symbol(&self, func: FuncId) -> Option<FuncSymbol> {
+        self.symbol_for(func)
     }
 
-    fn get_or_compile_jit(
+    /// Runs `module`'s init, then calls `func` on the globals it left; `imp
+    /// test` runs each `@test` function this way on a fresh VM.
+    ///
+    /// # Errors
+    ///
+    /// As [`Vm::run_main`], for the init and then for the call.
+    pub fn run_function(
         &mut self,
         module: &CompiledModule,
-        function: &CompiledFunction,
-    ) -> Arc<JitFunction> {
-        let key = JitKey::new(module, function);
-        if let Some(cached) = self.jit_cache.get(&key) {
-            return Arc::clone(cached);
+        func: FuncId,
+        args: &[Value],
+    ) -> Result<Vec<Value>, VmError> {
+        if self.run_depth == 0 {
+            self.stats = RunStats::default();
+            self.profiler = Profiler::default();
+            self.start_budget();
         }
-        let compiled = Arc::new(JitFunction::compile(function));
-        self.jit_cache.insert(key, Arc::clone(&compiled));
-        compiled
+        self.run_depth += 1;
+        let outcome = self.run_main_inner(module).and_then(|(_, _, mut globals)| {
+            self.execute_function(module, func, args, &mut globals)
+        });
+        self.run_depth -= 1;
+        outcome
     }
 
-    fn execute_function_jit(
+    /// Calls `func` of the module the last run left active, on freshly
+    /// linked globals.
+    ///
+    /// # Errors
+    ///
+    /// A runtime error when nothing has run yet; otherwise as
+    /// [`Vm::run_main`].
+    pub fn invoke(&mut self, func: FuncId, args: &[Value]) -> Result<Vec<Value>, VmError> {
+        let module = self
+            .active_module
+            .as_ref()
+            .ok_or_else(|| VmError::runtime("no active module; call run_main first".to_owned()))?
+            .clone();
+        if self.run_depth == 0 {
+            self.start_budget();
+        }
+        let mut globals = self.build_module_globals(&module)?;
+        self.execute_function(&module, func, args, &mut globals)
+    }
+
+    fn env(&self) -> &dyn HostEnv {
+        self.cfg.env.as_deref().unwrap_or(&SystemEnv)
+    }
+
+    /// Steps left in the current (or last) run; `None` when `max_steps` is
+    /// unset.
+    #[must_use]
+    pub fn remaining_fuel(&self) -> Option<u64> {
+        self.fuel
+    }
+
+    /// Approximate heap bytes the current (or last) run has allocated so
+    /// far; only counted while `max_alloc_bytes` is set.
+    #[must_use]
+    pub fn allocated_bytes(&self) -> usize {
+        self.allocated_bytes
+    }
+
+    /// Approximate heap bytes the current (or last) run holds: the last
+    /// measurement plus what was allocated since; only counted while
+    /// `max_memory` is set.
+    #[must_use]
+    pub fn live_bytes(&self) -> usize {
+        self.live_bytes
+    }
+
+    /// Spends one instruction of the [`Self::step`] budget; `false` once it
+    /// is used up, or when `frame` is at a breakpoint.
+    fn take_step(&mut self, frame: &Frame) -> bool {
+        let Some(budget) = self.step_budget else {
+            return true;
+        };
+        if budget == 0 {
+            return false;
+        }
+        if !std::mem::take(&mut self.resuming) && self.at_breakpoint_in(frame) {
+            self.step_budget = Some(0);
+            return false;
+        }
+        self.step_budget = Some(budget - 1);
+        true
+    }
+
+    /// Refills fuel and starts the timeout for a new top-level run.
+    fn start_budget(&mut self) {
+        self.fuel = self.cfg.max_steps;
+        self.allocated_bytes = 0;
+        self.live_bytes = 0;
+        self.run_deadline = self
+            .cfg
+            .timeout
+            .map(|timeout| self.env().monotonic() + timeout);
+        self.interrupt_poll = 0;
+    }
+
+    /// Fails the run once `VmConfig.cancel` is tripped or its `timeout` has
+    /// passed. Both are looked at every `INTERRUPT_POLL_STEPS` steps, so
+    /// the clock stays off the per-instruction path.
+    fn poll_interrupts(&mut self, frame: &Frame) -> Result<(), VmError> {
+        if self.shadow_runs > 0 || (self.run_deadline.is_none() && self.cfg.cancel.is_none()) {
+            return Ok(());
+        }
+        if self.interrupt_poll > 0 {
+            self.interrupt_poll -= 1;
+            return Ok(());
+        }
+        self.interrupt_poll = INTERRUPT_POLL_STEPS;
+        if self
+            .cfg
+            .cancel
+            .as_ref()
+            .is_some_and(CancelToken::is_cancelled)
+        {
+            return Err(VmError::Cancelled {
+                function: Arc::clone(&frame.meta.name),
+                pc: frame.pc,
+            });
+        }
+        if let (Some(at), Some(timeout)) = (self.run_deadline, self.cfg.timeout)
+            && self.env().monotonic() >= at
+        {
+            return Err(VmError::Timeout {
+                timeout,
+                function: Arc::clone(&frame.meta.name),
+                pc: frame.pc,
+            });
+        }
+        Ok(())
+    }
+
+    /// Charges `bytes` of new heap against `VmConfig.max_alloc_bytes` and
+    /// `max_memory`. Past either budget it throws a catchable `oom` in
+    /// `frame` and returns `false`.
+    fn charge_memory(
         &mut self,
-        module: &CompiledModule,
         frame: &mut Frame,
         globals: &mut [Value],
-        jit: &JitFunction,
-    ) -> Result<Vec<Value>, VmError> {
-        let mut pc = 0usize;
-        loop {
-            if pc >= jit.steps.len() {
-                return Err(VmError::Runtime(format!(
-                    "pc {} out of range for {}",
-                    pc, frame.meta.name
-                )));
+        bytes: usize,
+    ) -> Result<bool, VmError> {
+        if self.shadow_runs > 0 || bytes == 0 {
+            return Ok(true);
+        }
+        let used = self.allocated_bytes.saturating_add(bytes);
+        if let Some(max) = self.cfg.max_alloc_bytes
+            && used > max
+        {
+            let msg = format!("allocating {bytes} bytes exceeds max_alloc_bytes {max}");
+            frame.throw("oom", &msg, globals)?;
+            return Ok(false);
+        }
+        if let Some(max) = self.cfg.max_memory {
+            let mut live = self.live_bytes.saturating_add(bytes);
+            if live > max {
+                self.live_bytes = self.measure_live(frame, globals);
+                live = self.live_bytes.saturating_add(bytes);
             }
-
-            frame.pc = pc;
-            let step = &jit.steps[pc];
-            match (step.exec)(self, module, frame, globals, &step.operands, pc)? {
-                StepControl::Next(next) => {
-                    pc = next;
-                }
-                StepControl::Exit => {
-                    validate_retshape(&frame.meta, &frame.ret)?;
-                    return Ok(std::mem::take(&mut frame.ret));
-                }
+            if live > max {
+                let held = self.live_bytes;
+                let msg =
+                    format!("allocating {bytes} bytes exceeds max_memory {max} ({held} live)");
+                frame.throw("oom", &msg, globals)?;
+                return Ok(false);
             }
+            self.live_bytes = live;
         }
+        if self.cfg.max_alloc_bytes.is_some() {
+            self.allocated_bytes = used;
+        }
+        Ok(true)
     }
 
-    fn execute_function_interpreter(
+    /// The [`heap_size`] of everything a run can still reach: `frame`,
+    /// `globals`, the frames under it and imported modules' globals and
+    /// exports. Globals of modules whose calls are on the stack aren't
+    /// seen unless they are `globals`.
+    fn measure_live(&self, frame: &Frame, globals: &[Value]) -> usize {
+        let instances = self.instances.values().flat_map(|instance| {
+            instance
+                .globals
+                .iter()
+                .flatten()
+                .chain(instance.exports.values())
+        });
+        let mut seen = HashSet::new();
+        frame
+            .regs
+            .iter()
+            .chain(globals)
+            .chain(self.suspended.values())
+            .chain(instances)
+            .map(|value| heap_size_with(value, &mut seen))
+            .sum()
+    }
+
+    /// [`Self::charge_memory`] for the [`heap_size`] of `value`, which is
+    /// only measured when there is a budget to charge.
+    fn charge_value(
         &mut self,
-        module: &CompiledModule,
         frame: &mut Frame,
         globals: &mut [Value],
-    ) -> Result<Vec<Value>, VmError> {
-        loop {
-            let Some(instr) = frame.code.get(frame.pc).cloned() else {
-                return Err(VmError::Runtime(format!(
-                    "pc {} out of range for {}",
-                    frame.pc, frame.meta.name
-                )));
-            };
+        value: &Value,
+    ) -> Result<bool, VmError> {
+        if (self.cfg.max_alloc_bytes.is_none() && self.cfg.max_memory.is_none())
+            || self.shadow_runs > 0
+        {
+            return Ok(true);
+        }
+        self.charge_memory(frame, globals, heap_size(value))
+    }
 
-            match instr {
-                Instr::StoreConst { slot, value } => {
-                    frame.set(slot, Value::from_const(&value), globals);
-                    frame.pc += 1;
-                }
-                Instr::Move { from, to } => {
-                    let value = frame.get(from, globals)?;
-                    frame.set(to, value, globals);
-                    frame.pc += 1;
-                }
-                Instr::Add { a, b, out } => {
-                    let sum = frame.get(a, globals)?.as_num()? + frame.get(b, globals)?.as_num()?;
-                    frame.set(out, Value::Num(sum), globals);
-                    frame.pc += 1;
-                }
-                Instr::Sub { a, b, out } => {
-                    let diff =
-                        frame.get(a, globals)?.as_num()? - frame.get(b, globals)?.as_num()?;
-                    frame.set(out, Value::Num(diff), globals);
-                    frame.pc += 1;
-                }
-                Instr::Mul { a, b, out } => {
-                    let product =
-                        frame.get(a, globals)?.as_num()? * frame.get(b, globals)?.as_num()?;
-                    frame.set(out, Value::Num(product), globals);
-                    frame.pc += 1;
-                }
-                Instr::Div { a, b, out } => {
-                    let divisor = frame.get(b, globals)?.as_num()?;
-                    if divisor == 0.0 {
-                        let handled = frame.handle_throw("div_zero", "division by zero", globals);
-                        if handled {
-                            continue;
-                        }
-                        return Err(VmError::Thrown {
-                            code: Arc::from("div_zero"),
-                            msg: Arc::from("division by zero"),
-                        });
-                    }
-                    let quotient = frame.get(a, globals)?.as_num()? / divisor;
-                    frame.set(out, Value::Num(quotient), globals);
-                    frame.pc += 1;
-                }
-                Instr::Eq { a, b, out } => {
-                    let result = frame.get(a, globals)? == frame.get(b, globals)?;
-                    frame.set(out, Value::Bool(result), globals);
-                    frame.pc += 1;
-                }
-                Instr::Lt { a, b, out } => {
-                    let result =
-                        frame.get(a, globals)?.as_num()? < frame.get(b, globals)?.as_num()?;
-                    frame.set(out, Value::Bool(result), globals);
-                    frame.pc += 1;
-                }
-                Instr::Jump { target } => {
-                    frame.pc = target;
-                }
-                Instr::Branch {
-                    cond,
-                    then_pc,
-                    else_pc,
-                } => {
-                    let condition = frame.get(cond, globals)?.as_bool();
-                    frame.pc = if condition { then_pc } else { else_pc };
-                }
-                Instr::Invoke { fn_slot, args, out } => {
-                    let target = frame.get(fn_slot, globals)?;
-                    let mut values = Vec::with_capacity(args.len());
-                    for slot in &args {
-                        values.push(frame.get(*slot, globals)?);
-                    }
-                    let Value::Func(target_func) = target else {
-                        return Err(VmError::Runtime(
-                            "invoke target is not a function".to_owned(),
-                        ));
-                    };
+    /// Charges one step against the run's fuel.
+    fn burn_fuel(&mut self, frame: &Frame) -> Result<(), VmError> {
+        if self.shadow_runs > 0 {
+            return Ok(());
+        }
+        let (Some(fuel), Some(max_steps)) = (self.fuel.as_mut(), self.cfg.max_steps) else {
+            return Ok(());
+        };
+        if *fuel == 0 {
+            return Err(VmError::FuelExhausted {
+                max_steps,
+                function: Arc::clone(&frame.meta.name),
+                pc: frame.pc,
+            });
+        }
+        *fuel -= 1;
+        Ok(())
+    }
 
-                    match self.execute_function(module, target_func, &values, globals) {
-                        Ok(return_values) => {
-                            let value = return_values.into_iter().next().unwrap_or(Value::Null);
-                            frame.set(out, value, globals);
-                            frame.pc += 1;
-                        }
-                        Err(VmError::Thrown { code, msg }) => {
-                            let handled = frame.handle_throw(&code, &msg, globals);
-                            if handled {
-                                continue;
-                            }
-                            return Err(VmError::Thrown { code, msg });
-                        }
-                        Err(err) => return Err(err),
-                    }
-                }
-                Instr::ReturnSet { slot_id, value } => {
-                    let value = frame.get(value, globals)?;
-                    frame.set_ret(slot_id as usize, value);
-                    frame.pc += 1;
-                }
-                Instr::Exit => {
-                    validate_retshape(&frame.meta, &frame.ret)?;
-                    return Ok(std::mem::take(&mut frame.ret));
-                }
-                Instr::Throw { code, msg } => {
-                    let handled = frame.handle_throw(&code, &msg, globals);
-                    if handled {
-                        continue;
-                    }
+    /// Deadline for a new frame of `meta`: its own `@timeout`, or the
+    /// caller's when that ends sooner.
+    fn frame_deadline(&self, meta: &FnMeta, caller: Option<&Deadline>) -> Option<Deadline> {
+        let own = meta.timeout_ms.map(|ms| Deadline {
+            at: self.env().monotonic() + Duration::from_millis(ms),
+            function: Arc::clone(&meta.name),
+            ms,
+        });
+        match (own, caller) {
+            (Some(own), Some(caller)) if caller.at < own.at => Some(caller.clone()),
+            (Some(own), _) => Some(own),
+            (None, caller) => caller.cloned(),
+        }
+    }
+
+    /// Throws `timeout` once the frame's deadline has passed.
+    fn enforce_deadline(
+        &mut self,
+        frame: &mut Frame,
+        globals: &mut [Value],
+    ) -> Result<bool, VmError> {
+        let Some(deadline) = &frame.deadline else {
+            return Ok(true);
+        };
+        if self.shadow_runs > 0 || self.env().monotonic() < deadline.at {
+            return Ok(true);
+        }
+        let msg = format!(
+            "{} exceeded its {}ms timeout",
+            deadline.function, deadline.ms
+        );
+        frame.throw("timeout", &msg, globals)?;
+        Ok(false)
+    }
+
+    /// Policy and audit gate shared by every host-effectful instruction.
+    fn enter_host_op(&self, op: &str, frame: &Frame, args: &[Value]) -> bool {
+        let allowed = self.cfg.host_policy.permits(op);
+        self.audit_host_op(op, frame, args, allowed);
+        allowed
+    }
+
+    /// Records `op` with the audit sink, unless a shadow run repeats it.
+    fn audit_host_op(&self, op: &str, frame: &Frame, args: &[Value], allowed: bool) {
+        if self.shadow_runs > 0 {
+            return;
+        }
+        if let Some(audit) = &self.cfg.audit {
+            audit.record(AuditEntry {
+                op: Arc::from(op),
+                args: args.iter().map(ToString::to_string).collect(),
+                timestamp: self.env().now(),
+                function: Arc::clone(&frame.meta.name),
+                pc: frame.pc,
+                allowed,
+            });
+        }
+    }
+
+    fn build_module_globals(&mut self, module: &CompiledModule) -> Result<Vec<Value>, VmError> {
+        let mut globals = vec![Value::Null; module.global_count as usize];
+
+        for (slot, func_id) in &module.function_globals {
+            globals[*slot as usize] = Value::Func(*func_id);
+        }
+
+        for import in &module.imports {
+            if !self.instances.contains_key(&import.path) {
+                if let Some(start) = self.initializing.iter().position(|p| *p == import.path) {
+                    let mut cycle = self.initializing[start..].to_vec();
+                    cycle.push(import.path.clone());
                     return Err(VmError::Thrown {
-                        code: Arc::from(code),
-                        msg: Arc::from(msg),
+                        code: Arc::from("import_cycle"),
+                        msg: Arc::from(format!(
+                            "import '{}' is still initializing: {}",
+                            import.alias,
+                            cycle.join(" -> ")
+                        )),
+                        cause: None,
+                        data: None,
+                        traceback: Vec::new(),
                     });
                 }
-                Instr::TryPush { handler_pc } => {
-                    frame.try_stack.push(handler_pc);
-                    frame.pc += 1;
-                }
-                Instr::TryPop => {
-                    frame.try_stack.pop();
-                    frame.pc += 1;
-                }
-                Instr::ObjNew { out } => {
-                    frame.set(out, Value::Obj(HashMap::new()), globals);
-                    frame.pc += 1;
-                }
-                Instr::ObjSet {
-                    obj,
-                    key,
-                    value,
-                    out,
-                } => {
-                    let mut object = match frame.get(obj, globals)? {
-                        Value::Obj(map) => map,
-                        _ => {
-                            return Err(VmError::Runtime(
-                                "core::obj::set target is not an object".to_owned(),
-                            ));
-                        }
-                    };
-                    let key_text = value_to_text(&frame.get(key, globals)?)?;
-                    object.insert(key_text, frame.get(value, globals)?);
-                    frame.set(out, Value::Obj(object), globals);
-                    frame.pc += 1;
-                }
-                Instr::ObjGet { obj, key, out } => {
-                    let object = frame.get(obj, globals)?;
-                    let key_text = value_to_text(&frame.get(key, globals)?)?;
-                    let value = object_lookup(&object, &key_text)?;
-                    frame.set(out, value.unwrap_or(Value::Null), globals);
-                    frame.pc += 1;
-                }
-                Instr::ObjHas { obj, key, out } => {
-                    let object = frame.get(obj, globals)?;
-                    let key_text = value_to_text(&frame.get(key, globals)?)?;
-                    let has = object_lookup(&object, &key_text)?.is_some();
-                    frame.set(out, Value::Bool(has), globals);
-                    frame.pc += 1;
-                }
-                Instr::StrConcat { a, b, out } => {
-                    let av = value_to_text(&frame.get(a, globals)?)?;
-                    let bv = value_to_text(&frame.get(b, globals)?)?;
-                    frame.set(out, Value::Str(Arc::from(format!("{av}{bv}"))), globals);
-                    frame.pc += 1;
-                }
-                Instr::StrLen { value, out } => {
-                    let text = value_to_text(&frame.get(value, globals)?)?;
-                    frame.set(out, Value::Num(text.chars().count() as f64), globals);
-                    frame.pc += 1;
+                self.initializing.push(import.path.clone());
+                self.run_depth += 1;
+                let outcome = self.run_main_inner(&import.module);
+                self.run_depth -= 1;
+                self.initializing.pop();
+                let (_, _, import_globals) = outcome.map_err(|source| VmError::ImportInit {
+                    alias: import.alias.clone(),
+                    path: import.path.clone(),
+                    source: Box::new(source),
+                })?;
+                let mut linked_exports = HashMap::new();
+                for (name, value) in module_exports(&import.module, &import_globals) {
+                    let linked = self.link_imported_value(
+                        &value,
+                        &import.module,
+                        &import.path,
+                        &import.alias,
+                    );
+                    linked_exports.insert(name, linked);
                 }
-                Instr::HostPrint { slot } => {
-                    if self.cfg.enable_host_print {
-                        println!("{:?}", frame.get(slot, globals)?);
-                    }
-                    frame.pc += 1;
+                self.instances.insert(
+                    import.path.clone(),
+                    ModuleInstance {
+                        globals: Some(import_globals),
+                        exports: linked_exports,
+                    },
+                );
+            }
+            let Some(instance) = self.instances.get(&import.path) else {
+                continue;
+            };
+            for (name, destination) in &import.export_to_global {
+                if (*destination as usize) < globals.len()
+                    && let Some(value) = instance.exports.get(name)
+                {
+                    globals[*destination as usize] = value.clone();
                 }
             }
         }
-    }
-}
 
-fn step_store_const(
-    _vm: &mut Vm,
-    _module: &CompiledModule,
-    frame: &mut Frame,
-    globals: &mut [Value],
-    operands: &JitOperands,
-    pc: usize,
-) -> Result<StepControl, VmError> {
-    let JitOperands::StoreConst { slot, value } = operands else {
-        return Err(VmError::Runtime(
-            "jit operand mismatch for store_const".to_owned(),
-        ));
-    };
-    frame.set(*slot, value.clone(), globals);
-    Ok(StepControl::Next(pc + 1))
-}
+        Ok(globals)
+    }
 
-fn step_move(
-    _vm: &mut Vm,
-    _module: &CompiledModule,
-    frame: &mut Frame,
-    globals: &mut [Value],
-    operands: &JitOperands,
-    pc: usize,
-) -> Result<StepControl, VmError> {
-    let JitOperands::Move { from, to } = operands else {
-        return Err(VmError::Runtime("jit operand mismatch for move".to_owned()));
-    };
-    let value = frame.get(*from, globals)?;
-    frame.set(*to, value, globals);
-    Ok(StepControl::Next(pc + 1))
-}
+    fn link_imported_value(
+        &mut self,
+        value: &Value,
+        module: &Arc<CompiledModule>,
+        path: &str,
+        alias: &str,
+    ) -> Value {
+        match value {
+            Value::Func(func_id) => {
+                let handle = self.register_foreign_func(
+                    Arc::clone(module),
+                    *func_id,
+                    Some(path.to_owned()),
+                    Some(alias),
+                );
+                Value::Func(handle)
+            }
+            Value::Closure { func, captures } => Value::Closure {
+                func: self.register_foreign_func(
+                    Arc::clone(module),
+                    *func,
+                    Some(path.to_owned()),
+                    Some(alias),
+                ),
+                captures: captures
+                    .iter()
+                    .map(|capture| self.link_imported_value(capture, module, path, alias))
+                    .collect(),
+            },
+            Value::List(items) => Value::List(
+                items
+                    .iter()
+                    .map(|item| self.link_imported_value(item, module, path, alias))
+                    .collect(),
+            ),
+            Value::Obj(obj) => Value::Obj(ObjRef::new(
+                obj.borrow()
+                    .iter()
+                    .map(|(key, value)| {
+                        (
+                            key.clone(),
+                            self.link_imported_value(value, module, path, alias),
+                        )
+                    })
+                    .collect(),
+            )),
+            _ => value.clone(),
+        }
+    }
 
-fn step_binary(
-    _vm: &mut Vm,
-    _module: &CompiledModule,
-    frame: &mut Frame,
-    globals: &mut [Value],
-    operands: &JitOperands,
-    pc: usize,
-) -> Result<StepControl, VmError> {
-    let JitOperands::Binary { kind, a, b, out } = operands else {
-        return Err(VmError::Runtime(
-            "jit operand mismatch for binary".to_owned(),
-        ));
-    };
+    fn register_foreign_func(
+        &mut self,
+        module: Arc<CompiledModule>,
+        func_id: FuncId,
+        instance: Option<String>,
+        alias: Option<&str>,
+    ) -> FuncId {
+        let handle = self.next_foreign_func_id;
+        self.next_foreign_func_id = self.next_foreign_func_id.saturating_add(1);
+        let symbol = match module.function(func_id) {
+            Some(function) => Some(FuncSymbol {
+                name: Arc::clone(&function.meta.name),
+                module: Arc::clone(&module.name),
+                alias: alias.map(ToOwned::to_owned),
+            }),
+            // Re-linking an existing handle keeps its original name.
+            None => self.symbols.get(&func_id).cloned(),
+        };
+        if let Some(symbol) = symbol {
+            self.symbols.insert(handle, symbol);
+        }
+        self.foreign_funcs.insert(
+            handle,
+            ForeignFunc {
+                module,
+                func_id,
+                instance,
+            },
+        );
+        handle
+    }
 
-    match kind {
-        BinaryOp::Add => {
-            let sum = frame.get(*a, globals)?.as_num()? + frame.get(*b, globals)?.as_num()?;
-            frame.set(*out, Value::Num(sum), globals);
-            Ok(StepControl::Next(pc + 1))
+    fn register_bound_func(&mut self, target: FuncId, args: Vec<Value>) -> FuncId {
+        let handle = self.next_foreign_func_id;
+        self.next_foreign_func_id = self.next_foreign_func_id.saturating_add(1);
+        let symbol = self.symbol_for(target).map(|symbol| FuncSymbol {
+            name: Arc::from(format!("bind({})", symbol.name)),
+            ..symbol
+        });
+        if let Some(symbol) = symbol {
+            self.symbols.insert(handle, symbol);
         }
-        BinaryOp::Sub => {
-            let diff = frame.get(*a, globals)?.as_num()? - frame.get(*b, globals)?.as_num()?;
-            frame.set(*out, Value::Num(diff), globals);
-            Ok(StepControl::Next(pc + 1))
+        self.bound_funcs.insert(handle, BoundFunc { target, args });
+        handle
+    }
+
+    /// Symbol for a handle, falling back to the active module's own functions.
+    fn symbol_for(&self, func_id: FuncId) -> Option<FuncSymbol> {
+        if let Some(symbol) = self.symbols.get(&func_id) {
+            return Some(symbol.clone());
         }
-        BinaryOp::Mul => {
-            let product = frame.get(*a, globals)?.as_num()? * frame.get(*b, globals)?.as_num()?;
-            frame.set(*out, Value::Num(product), globals);
-            Ok(StepControl::Next(pc + 1))
+        let module = self.active_module.as_ref()?;
+        module.function(func_id).map(|function| FuncSymbol {
+            name: Arc::clone(&function.meta.name),
+            module: Arc::clone(&module.name),
+            alias: None,
+        })
+    }
+
+    fn unknown_function(&self, func_id: FuncId) -> VmError {
+        VmError::UnknownFunction {
+            id: func_id,
+            symbol: self.symbols.get(&func_id).cloned(),
         }
-        BinaryOp::Div => {
-            let divisor = frame.get(*b, globals)?.as_num()?;
-            if divisor == 0.0 {
-                let handled = frame.handle_throw("div_zero", "division by zero", globals);
-                if handled {
-                    return Ok(StepControl::Next(frame.pc));
-                }
-                return Err(VmError::Thrown {
-                    code: Arc::from("div_zero"),
-                    msg: Arc::from("division by zero"),
-                });
+    }
+
+    /// Implements `core::fn::bind`; binding an already-bound handle appends
+    /// to its arguments instead of nesting.
+    fn bind_function(&mut self, target: &Value, args: Vec<Value>) -> Result<Value, VmError> {
+        let (func_id, args) = match target {
+            Value::Func(func_id) => (func_id, args),
+            Value::Closure { func, captures } => {
+                (func, captures.iter().cloned().chain(args).collect())
             }
-            let quotient = frame.get(*a, globals)?.as_num()? / divisor;
-            frame.set(*out, Value::Num(quotient), globals);
-            Ok(StepControl::Next(pc + 1))
+            _ => {
+                return Err(VmError::runtime(format!(
+                    "core::fn::bind expects a function, found {}",
+                    target.type_name()
+                )));
+            }
+        };
+        let (target, args) = match self.bound_funcs.get(func_id) {
+            Some(bound) => (
+                bound.target,
+                bound.args.iter().cloned().chain(args).collect(),
+            ),
+            None => (*func_id, args),
+        };
+        Ok(Value::Func(self.register_bound_func(target, args)))
+    }
+
+    fn bridge_value_for_module(&mut self, module: &Arc<CompiledModule>, value: &Value) -> Value {
+        match value {
+            Value::Func(func_id) => Value::Func(self.bridge_func_for_module(module, *func_id)),
+            Value::Closure { func, captures } => Value::Closure {
+                func: self.bridge_func_for_module(module, *func),
+                captures: captures
+                    .iter()
+                    .map(|capture| self.bridge_value_for_module(module, capture))
+                    .collect(),
+            },
+            Value::List(items) => Value::List(
+                items
+                    .iter()
+                    .map(|item| self.bridge_value_for_module(module, item))
+                    .collect(),
+            ),
+            Value::Obj(obj) => Value::Obj(ObjRef::new(
+                obj.borrow()
+                    .iter()
+                    .map(|(key, value)| (key.clone(), self.bridge_value_for_module(module, value)))
+                    .collect(),
+            )),
+            _ => value.clone(),
         }
-        BinaryOp::Eq => {
-            let result = frame.get(*a, globals)? == frame.get(*b, globals)?;
-            frame.set(*out, Value::Bool(result), globals);
-            Ok(StepControl::Next(pc + 1))
+    }
+
+    fn bridge_func_for_module(&mut self, module: &Arc<CompiledModule>, func_id: FuncId) -> FuncId {
+        if let Some(bound) = self.bound_funcs.get(&func_id).cloned() {
+            let target = self.bridge_func_for_module(module, bound.target);
+            let args = bound
+                .args
+                .iter()
+                .map(|arg| self.bridge_value_for_module(module, arg))
+                .collect();
+            self.register_bound_func(target, args)
+        } else if let Some(foreign) = self.foreign_funcs.get(&func_id).cloned() {
+            self.register_foreign_func(foreign.module, foreign.func_id, foreign.instance, None)
+        } else if module.function(func_id).is_some() {
+            self.register_foreign_func(Arc::clone(module), func_id, None, None)
+        } else {
+            func_id
         }
-        BinaryOp::Lt => {
-            let result = frame.get(*a, globals)?.as_num()? < frame.get(*b, globals)?.as_num()?;
-            frame.set(*out, Value::Bool(result), globals);
-            Ok(StepControl::Next(pc + 1))
+    }
+
+    /// Prints through the configured writer. Returns `false` when the policy
+    /// denied the op and control moved to a try handler.
+    fn host_print(
+        &self,
+        frame: &mut Frame,
+        globals: &mut [Value],
+        slot: Slot,
+        stream: PrintStream,
+        level: PrintLevel,
+    ) -> Result<bool, VmError> {
+        let op = match stream {
+            PrintStream::Stdout => "core::host::print",
+            PrintStream::Stderr => "core::host::eprint",
+        };
+        let value = frame.get(slot, globals)?;
+        if !self.cfg.capabilities.allow_print {
+            frame.throw_incapable(op, Capability::Print, globals)?;
+            return Ok(false);
+        }
+        if !self.enter_host_op(op, frame, std::slice::from_ref(&value)) {
+            frame.throw_denied(op, globals)?;
+            return Ok(false);
         }
+        if self.cfg.enable_host_print && self.shadow_runs == 0 {
+            let text = value.to_string();
+            match &self.cfg.writer {
+                Some(writer) => writer.write(stream, level, &text),
+                None => StdioWriter.write(stream, level, &text),
+            }
+        }
+        Ok(true)
     }
-}
 
-fn step_jump(
-    _vm: &mut Vm,
-    _module: &CompiledModule,
-    _frame: &mut Frame,
-    _globals: &mut [Value],
-    operands: &JitOperands,
-    _pc: usize,
-) -> Result<StepControl, VmError> {
-    let JitOperands::Jump { target } = operands else {
-        return Err(VmError::Runtime("jit operand mismatch for jump".to_owned()));
-    };
-    Ok(StepControl::Next(*target))
-}
+    /// `core::host::now` / `core::host::monotonic`, in milliseconds from
+    /// `VmConfig.env`. Needs the time capability and passes the host policy
+    /// like the other host ops; returns `false` when either throws.
+    fn host_time(
+        &self,
+        frame: &mut Frame,
+        globals: &mut [Value],
+        clock: HostClock,
+        out: Slot,
+    ) -> Result<bool, VmError> {
+        let op = match clock {
+            HostClock::Wall => "core::host::now",
+            HostClock::Monotonic => "core::host::monotonic",
+        };
+        if !self.cfg.capabilities.allow_time {
+            frame.throw_incapable(op, Capability::Time, globals)?;
+            return Ok(false);
+        }
+        if !self.enter_host_op(op, frame, &[]) {
+            frame.throw_denied(op, globals)?;
+            return Ok(false);
+        }
+        let elapsed = match clock {
+            HostClock::Wall => self
+                .env()
+                .now()
+                .duration_since(SystemTime::UNIX_EPOCH)
+                .unwrap_or_default(),
+            HostClock::Monotonic => self
+                .env()
+                .monotonic()
+                .saturating_duration_since(self.clock_origin),
+        };
+        frame.set(out, Value::Num(elapsed.as_secs_f64() * 1000.0), globals);
+        Ok(true)
+    }
 
-fn step_branch(
+    /// `core::host::args`. Needs the env capability and passes the host
+    /// policy; returns `false` when control moved to a try handler.
+    fn host_args(
+        &mut self,
+        frame: &mut Frame,
+        globals: &mut [Value],
+        out: Slot,
+    ) -> Result<bool, VmError> {
+        const OP: &str = "core::host::args";
+        if !self.cfg.capabilities.allow_env {
+            frame.throw_incapable(OP, Capability::Env, globals)?;
+            return Ok(false);
+        }
+        if !self.enter_host_op(OP, frame, &[]) {
+            frame.throw_denied(OP, globals)?;
+            return Ok(false);
+        }
+        let args = Value::List(
+            self.cfg
+                .program_args
+                .iter()
+                .map(|arg| Value::Str(Arc::from(arg.as_str())))
+                .collect(),
+        );
+        if !self.charge_value(frame, globals, &args)? {
+            return Ok(false);
+        }
+        frame.set(out, args, globals);
+        Ok(true)
+    }
+
+    /// `core::host::read_line` (`all: false`) or `core::host::read_all` from
+    /// `VmConfig.reader`. Needs the input capability and passes the host
+    /// policy; read failures throw a catchable `io_error`. Returns `false`
+    /// when control moved to a try handler.
+    fn host_read(
+        &mut self,
+        frame: &mut Frame,
+        globals: &mut [Value],
+        all: bool,
+        out: Slot,
+    ) -> Result<bool, VmError> {
+        let op = if all {
+            "core::host::read_all"
+        } else {
+            "core::host::read_line"
+        };
+        if !self.cfg.capabilities.allow_input {
+            frame.throw_incapable(op, Capability::Input, globals)?;
+            return Ok(false);
+        }
+        if !self.enter_host_op(op, frame, &[]) {
+            frame.throw_denied(op, globals)?;
+            return Ok(false);
+        }
+        let value = match self.next_input(all) {
+            Ok(Some(text)) => Value::Str(Arc::from(text)),
+            Ok(None) => Value::Null,
+            Err(err) => {
+                frame.throw("io_error", &format!("{op}: {err}"), globals)?;
+                return Ok(false);
+            }
+        };
+        if !self.charge_value(frame, globals, &value)? {
+            return Ok(false);
+        }
+        frame.set(out, value, globals);
+        Ok(true)
+    }
+
+    /// The next read for `host_read`. Shadow runs read ahead into
+    /// `input_lookahead` so the real run sees the same input.
+    fn next_input(&mut self, all: bool) -> std::io::Result<Option<String>> {
+        if self.shadow_runs > 0 {
+            if self.shadow_input_pos == self.input_lookahead.len() {
+                let read = self.read_input(all)?;
+                self.input_lookahead.push_back(read);
+            }
+            let read = self.input_lookahead[self.shadow_input_pos].clone();
+            self.shadow_input_pos += 1;
+            return Ok(read);
+        }
+        match self.input_lookahead.pop_front() {
+            Some(read) => Ok(read),
+            None => self.read_input(all),
+        }
+    }
+
+    fn read_input(&self, all: bool) -> std::io::Result<Option<String>> {
+        let reader = self.cfg.reader.as_deref().unwrap_or(&StdinReader);
+        if all {
+            reader.read_all().map(Some)
+        } else {
+            reader.read_line()
+        }
+    }
+
+    /// `core::host::env`. Needs the env capability, a name on
+    /// `VmConfig.env_allowlist` if one is set, and the host policy; returns
+    /// `false` when control moved to a try handler.
+    fn host_env_var(
+        &mut self,
+        frame: &mut Frame,
+        globals: &mut [Value],
+        key: Slot,
+        out: Slot,
+    ) -> Result<bool, VmError> {
+        const OP: &str = "core::host::env";
+        if !self.cfg.capabilities.allow_env {
+            frame.throw_incapable(OP, Capability::Env, globals)?;
+            return Ok(false);
+        }
+        let key = frame.get(key, globals)?;
+        let Value::Str(name) = &key else {
+            let msg = format!("{OP} expects a str key, found {}", key.type_name());
+            self.throw_type_error(frame, "type_error", &msg, globals)?;
+            return Ok(false);
+        };
+        // The audit sees reads the allow-list turns away too.
+        let listed = self
+            .cfg
+            .env_allowlist
+            .as_ref()
+            .is_none_or(|allowlist| allowlist.iter().any(|allowed| allowed == name.as_ref()));
+        let permitted = self.cfg.host_policy.permits(OP);
+        self.audit_host_op(OP, frame, std::slice::from_ref(&key), permitted && listed);
+        if !permitted {
+            frame.throw_denied(OP, globals)?;
+            return Ok(false);
+        }
+        if !listed {
+            frame.throw(
+                "cap_denied",
+                &format!("env var '{name}' is not on the allow-list"),
+                globals,
+            )?;
+            return Ok(false);
+        }
+        let value = match self.env().var(name) {
+            Some(value) => Value::Str(Arc::from(value)),
+            None => Value::Null,
+        };
+        if !self.charge_value(frame, globals, &value)? {
+            return Ok(false);
+        }
+        frame.set(out, value, globals);
+        Ok(true)
+    }
+
+    /// `core::host::random` (`range: None`) and `core::host::random_int`.
+    /// Needs the random capability and passes the host policy; bad bounds
+    /// throw a catchable `type_error` or `random_range`. Returns `false`
+    /// when control moved to a try handler.
+    fn host_random(
+        &mut self,
+        frame: &mut Frame,
+        globals: &mut [Value],
+        range: Option<(Slot, Slot)>,
+        out: Slot,
+    ) -> Result<bool, VmError> {
+        let op = match range {
+            None => "core::host::random",
+            Some(_) => "core::host::random_int",
+        };
+        let bounds = match range {
+            None => Vec::new(),
+            Some((lo, hi)) => vec![frame.get(lo, globals)?, frame.get(hi, globals)?],
+        };
+        if !self.cfg.capabilities.allow_random {
+            frame.throw_incapable(op, Capability::Random, globals)?;
+            return Ok(false);
+        }
+        if !self.enter_host_op(op, frame, &bounds) {
+            frame.throw_denied(op, globals)?;
+            return Ok(false);
+        }
+        let value = match bounds.as_slice() {
+            [lo, hi] => {
+                let (lo, hi) = match (random_bound(lo), random_bound(hi)) {
+                    (Ok(lo), Ok(hi)) => (lo, hi),
+                    (Err(other), _) | (_, Err(other)) => {
+                        let msg = format!(
+                            "core::host::random_int expects int bounds, found {}",
+                            other.type_name()
+                        );
+                        self.throw_type_error(frame, "type_error", &msg, globals)?;
+                        return Ok(false);
+                    }
+                };
+                let (Some(lo), Some(hi)) = (lo, hi) else {
+                    let msg = "random_int bounds must be whole numbers";
+                    frame.throw("random_range", msg, globals)?;
+                    return Ok(false);
+                };
+                if lo >= hi {
+                    let msg = format!("random_int range {lo}..{hi} is empty");
+                    frame.throw("random_range", &msg, globals)?;
+                    return Ok(false);
+                }
+                let span = hi.abs_diff(lo);
+                Value::Int(lo.wrapping_add_unsigned(self.rng.below(span)))
+            }
+            _ => Value::Num(self.rng.next_f64()),
+        };
+        frame.set(out, value, globals);
+        Ok(true)
+    }
+
+    /// `core::host::fs::*`. Needs the fs capability, the host policy and a
+    /// path under `VmConfig.fs_roots`, else throws `cap_denied`; I/O
+    /// failures throw a catchable `io_error`. Returns `false` when control
+    /// moved to a try handler.
+    fn host_fs(
+        &mut self,
+        frame: &mut Frame,
+        globals: &mut [Value],
+        ops: FsOperands,
+    ) -> Result<bool, VmError> {
+        let name = ops.op.name();
+        let path = frame.get(ops.path, globals)?;
+        let data = ops.data.map(|slot| frame.get(slot, globals)).transpose()?;
+        if !self.cfg.capabilities.allow_fs {
+            frame.throw_incapable(name, Capability::Fs, globals)?;
+            return Ok(false);
+        }
+        let args = std::iter::once(path.clone())
+            .chain(data.clone())
+            .collect::<Vec<_>>();
+        if !self.enter_host_op(name, frame, &args) {
+            frame.throw_denied(name, globals)?;
+            return Ok(false);
+        }
+        let Value::Str(path) = path else {
+            let msg = format!("{name} expects a str path, found {}", path.type_name());
+            self.throw_type_error(frame, "type_error", &msg, globals)?;
+            return Ok(false);
+        };
+        let Some(resolved) = fs::allowed_path(&self.cfg.fs_roots, Path::new(path.as_ref())) else {
+            let msg = format!("{name} may not touch '{path}'");
+            frame.throw("cap_denied", &msg, globals)?;
+            return Ok(false);
+        };
+        let result = match ops.op {
+            // Charged by the file's size before reading, and no more than
+            // that is read should it grow meanwhile.
+            FsOp::Read => match std::fs::metadata(&resolved) {
+                Ok(meta) => {
+                    let size = usize::try_from(meta.len()).unwrap_or(usize::MAX);
+                    if !self.charge_memory(frame, globals, size)? {
+                        return Ok(false);
+                    }
+                    fs::read_text(&resolved, size).map(|text| Value::Str(Arc::from(text)))
+                }
+                Err(err) => Err(err),
+            },
+            FsOp::Exists => Ok(Value::Bool(resolved.try_exists().unwrap_or(false))),
+            FsOp::Write => {
+                let bytes = match data.unwrap_or(Value::Null) {
+                    Value::Str(text) => text.as_bytes().to_vec(),
+                    Value::Bytes(bytes) => bytes.to_vec(),
+                    other => {
+                        let msg = format!(
+                            "{name} expects str or bytes data, found {}",
+                            other.type_name()
+                        );
+                        self.throw_type_error(frame, "type_error", &msg, globals)?;
+                        return Ok(false);
+                    }
+                };
+                // Shadow runs must not write twice.
+                if self.shadow_runs > 0 {
+                    Ok(Value::Null)
+                } else {
+                    std::fs::write(&resolved, bytes).map(|()| Value::Null)
+                }
+            }
+        };
+        match result {
+            Ok(value) => {
+                if let Some(out) = ops.out {
+                    frame.set(out, value, globals);
+                }
+                Ok(true)
+            }
+            Err(err) => {
+                frame.throw("io_error", &format!("{name} '{path}': {err}"), globals)?;
+                Ok(false)
+            }
+        }
+    }
+
+    /// `core::json::parse` (`parse`) or `core::json::stringify`. Malformed
+    /// text and values JSON cannot hold throw a catchable `json_error`;
+    /// returns `false` in that case.
+    fn json_op(
+        &mut self,
+        frame: &mut Frame,
+        globals: &mut [Value],
+        parse: bool,
+        value: Slot,
+        out: Slot,
+    ) -> Result<bool, VmError> {
+        let value = frame.get(value, globals)?;
+        let result = if parse {
+            match &value {
+                Value::Str(text) => json::parse(text),
+                other => {
+                    let msg = format!(
+                        "core::json::parse expects a str, found {}",
+                        other.type_name()
+                    );
+                    self.throw_type_error(frame, "type_error", &msg, globals)?;
+                    return Ok(false);
+                }
+            }
+        } else {
+            json::stringify(&value).map(|text| Value::Str(Arc::from(text)))
+        };
+        match result {
+            Ok(value) => {
+                if !self.charge_value(frame, globals, &value)? {
+                    return Ok(false);
+                }
+                if parse {
+                    self.stats.objects_allocated += containers(&value);
+                } else {
+                    self.stats.strings_allocated += 1;
+                }
+                frame.set(out, value, globals);
+                Ok(true)
+            }
+            Err(msg) => {
+                frame.throw("json_error", &msg, globals)?;
+                Ok(false)
+            }
+        }
+    }
+
+    /// Runs the handler for ext opcode `op`. Returns `false` when the handler
+    /// failed and control moved to a try handler.
+    fn run_ext(
+        &self,
+        frame: &mut Frame,
+        globals: &mut [Value],
+        op: u16,
+        operands: &[Slot],
+    ) -> Result<bool, VmError> {
+        let handler =
+            self.cfg.ext_ops.get(op).ok_or_else(|| {
+                VmError::runtime(format!("no handler registered for ext op {op}"))
+            })?;
+        let outcome = handler(&mut ExtArgs {
+            frame,
+            globals,
+            operands,
+        });
+        match outcome {
+            Ok(()) => Ok(true),
+            Err(err) => {
+                frame.throw(&err.code, &err.msg, globals)?;
+                Ok(false)
+            }
+        }
+    }
+
+    /// Runs a registered host function under the host policy. Returns `false`
+    /// when control moved to a try handler (denied or host error).
+    fn host_call(
+        &self,
+        frame: &mut Frame,
+        globals: &mut [Value],
+        name: &str,
+        args: &[Slot],
+        out: Slot,
+    ) -> Result<bool, VmError> {
+        let mut values = Vec::with_capacity(args.len());
+        for slot in args {
+            values.push(frame.get(*slot, globals)?);
+        }
+        if let Some(capability) = self.cfg.host_fns.capability(name)
+            && !self.cfg.capabilities.permits(capability)
+        {
+            frame.throw_incapable(name, capability, globals)?;
+            return Ok(false);
+        }
+        if !self.enter_host_op(name, frame, &values) {
+            frame.throw_denied(name, globals)?;
+            return Ok(false);
+        }
+        let func = self
+            .cfg
+            .host_fns
+            .get(name)
+            .ok_or_else(|| VmError::runtime(format!("unknown host function '{name}'")))?;
+        match func(&values) {
+            Ok(value) => {
+                frame.set(out, value, globals);
+                Ok(true)
+            }
+            Err(err) => {
+                frame.throw(&err.code, &err.msg, globals)?;
+                Ok(false)
+            }
+        }
+    }
+
+    /// Implements `core::obj::entries`: a list-shaped object (keys `0..n`)
+    /// of `{key, value}` pairs sorted by key, plus `n`. A non-object throws
+    /// a catchable `type_error`; returns `None` in that case.
+    fn obj_entries(
+        &mut self,
+        frame: &mut Frame,
+        globals: &mut [Value],
+        value: &Value,
+    ) -> Result<Option<(Value, usize)>, VmError> {
+        let Value::Obj(obj) = value else {
+            let msg = not_an_object("core::obj::entries", value);
+            self.throw_type_error(frame, "type_error", &msg, globals)?;
+            return Ok(None);
+        };
+        let map = obj.borrow();
+        let mut keys = map.keys().collect::<Vec<_>>();
+        keys.sort();
+        let entries = keys
+            .into_iter()
+            .enumerate()
+            .map(|(index, key)| {
+                let pair = HashMap::from([
+                    ("key".to_owned(), Value::Str(Arc::from(key.as_str()))),
+                    ("value".to_owned(), map[key].clone()),
+                ]);
+                (index.to_string(), Value::Obj(ObjRef::new(pair)))
+            })
+            .collect::<HashMap<_, _>>();
+        let count = entries.len();
+        self.stats.objects_allocated += count as u64 + 1;
+        Ok(Some((Value::Obj(ObjRef::new(entries)), count)))
+    }
+
+    /// `core::str::format`. A missing substitution throws a catchable
+    /// `format_key`, a stray brace `format_syntax` and a non-string `tmpl`,
+    /// non-object `args` or non-scalar substitution a `type_error`,
+    /// returning `false`.
+    fn str_format(
+        &mut self,
+        frame: &mut Frame,
+        globals: &mut [Value],
+        tmpl: Slot,
+        args: Slot,
+        out: Slot,
+    ) -> Result<bool, VmError> {
+        const OP: &str = "core::str::format";
+        let template = match frame.get(tmpl, globals)? {
+            Value::Str(template) => template,
+            other => {
+                let msg = format!("{OP} expects str tmpl, found {}", other.type_name());
+                self.throw_type_error(frame, "type_error", &msg, globals)?;
+                return Ok(false);
+            }
+        };
+        let subs = match frame.get(args, globals)? {
+            Value::Obj(subs) => subs,
+            other => {
+                let msg = format!("{OP} expects obj args, found {}", other.type_name());
+                self.throw_type_error(frame, "type_error", &msg, globals)?;
+                return Ok(false);
+            }
+        };
+
+        let mut text = String::with_capacity(template.len());
+        let mut rest = template.as_ref();
+        while let Some(at) = rest.find(['{', '}']) {
+            text.push_str(&rest[..at]);
+            let tail = &rest[at..];
+            if let Some(after) = tail.strip_prefix("{{") {
+                text.push('{');
+                rest = after;
+                continue;
+            }
+            if let Some(after) = tail.strip_prefix("}}") {
+                text.push('}');
+                rest = after;
+                continue;
+            }
+            let offset = template.len() - tail.len();
+            let close = match tail.find('}') {
+                Some(close) if tail.starts_with('{') => close,
+                found => {
+                    let msg = if found.is_some() {
+                        format!("unmatched '}}' at byte {offset}")
+                    } else {
+                        format!("unclosed '{{' at byte {offset}")
+                    };
+                    frame.throw("format_syntax", &msg, globals)?;
+                    return Ok(false);
+                }
+            };
+            let name = &tail[1..close];
+            let Some(value) = subs.borrow().get(name).cloned() else {
+                let msg = format!("missing substitution '{name}'");
+                frame.throw("format_key", &msg, globals)?;
+                return Ok(false);
+            };
+            match operand_text(OP, &value) {
+                Ok(value) => text.push_str(&value),
+                Err((code, msg)) => {
+                    self.throw_type_error(frame, code, &msg, globals)?;
+                    return Ok(false);
+                }
+            }
+            rest = &tail[close + 1..];
+        }
+        text.push_str(rest);
+
+        if !self.charge_memory(frame, globals, text.len())? {
+            return Ok(false);
+        }
+        self.stats.strings_allocated += 1;
+        frame.set(out, Value::Str(Arc::from(text)), globals);
+        Ok(true)
+    }
+
+    /// Runs a `core::list::*` op. `get` past the end yields null; `set` past
+    /// the end throws a catchable `index_range`, returning `false`.
+    fn list_op(
+        &mut self,
+        frame: &mut Frame,
+        globals: &mut [Value],
+        ops: ListOperands,
+    ) -> Result<bool, VmError> {
+        let get = |slot: Option<Slot>, frame: &Frame, globals: &[Value]| {
+            slot.map_or(Ok(Value::Null), |slot| frame.get(slot, globals))
+        };
+        let list = get(ops.list, frame, globals)?;
+        let index = get(ops.index, frame, globals)?;
+        let value = get(ops.value, frame, globals)?;
+        let mut items = match (ops.kind, list) {
+            (ListOpKind::New, _) => Vec::new(),
+            (_, Value::List(items)) => items,
+            (kind, other) => {
+                let msg = not_a_list(kind, &other);
+                self.throw_type_error(frame, "type_error", &msg, globals)?;
+                return Ok(false);
+            }
+        };
+        let grows = matches!(ops.kind, ListOpKind::New | ListOpKind::Push);
+        if grows && !self.charge_memory(frame, globals, VALUE_SIZE)? {
+            return Ok(false);
+        }
+        let result = match ops.kind {
+            ListOpKind::New => {
+                self.stats.objects_allocated += 1;
+                Value::List(items)
+            }
+            ListOpKind::Push => {
+                items.push(value);
+                Value::List(items)
+            }
+            ListOpKind::Get => {
+                list_index(index.as_num()?, items.len()).map_or(Value::Null, |at| items[at].clone())
+            }
+            ListOpKind::Set => {
+                let at = index.as_num()?;
+                let Some(at) = list_index(at, items.len()) else {
+                    let msg = format!("index {at} is out of range for {} items", items.len());
+                    frame.throw("index_range", &msg, globals)?;
+                    return Ok(false);
+                };
+                items[at] = value;
+                Value::List(items)
+            }
+            ListOpKind::Len => Value::Num(count_num(items.len())),
+        };
+        frame.set(ops.out, result, globals);
+        Ok(true)
+    }
+
+    /// Runs `core::obj::keys`/`del`/`len`. A non-object throws a catchable
+    /// `type_error`; returns `false` in that case.
+    fn obj_op(
+        &mut self,
+        frame: &mut Frame,
+        globals: &mut [Value],
+        ops: ObjOperands,
+    ) -> Result<bool, VmError> {
+        let op = match ops.kind {
+            ObjOpKind::Keys => "core::obj::keys",
+            ObjOpKind::Del => "core::obj::del",
+            ObjOpKind::Len => "core::obj::len",
+        };
+        let obj = match frame.get(ops.obj, globals)? {
+            Value::Obj(obj) => obj,
+            other => {
+                self.throw_type_error(frame, "type_error", &not_an_object(op, &other), globals)?;
+                return Ok(false);
+            }
+        };
+        let result = match ops.kind {
+            ObjOpKind::Keys => {
+                let mut keys = obj.borrow().keys().cloned().collect::<Vec<_>>();
+                keys.sort();
+                self.stats.objects_allocated += 1;
+                self.stats.strings_allocated += keys.len() as u64;
+                Value::List(
+                    keys.into_iter()
+                        .map(|key| Value::Str(Arc::from(key)))
+                        .collect(),
+                )
+            }
+            ObjOpKind::Del => {
+                if let Some(key) = ops.key {
+                    let key = value_to_text(&frame.get(key, globals)?)?;
+                    obj.borrow_mut().remove(&key);
+                }
+                Value::Obj(obj)
+            }
+            ObjOpKind::Len => Value::Num(count_num(obj.borrow().len())),
+        };
+        let fresh = matches!(ops.kind, ObjOpKind::Keys);
+        if fresh && !self.charge_value(frame, globals, &result)? {
+            return Ok(false);
+        }
+        frame.set(ops.out, result, globals);
+        Ok(true)
+    }
+
+    /// Runs `core::obj::getpath`/`setpath`. Walking through a value that is
+    /// not an object throws a catchable `type_error`; returns `false` in
+    /// that case.
+    fn obj_path(
+        &mut self,
+        frame: &mut Frame,
+        globals: &mut [Value],
+        ops: PathOperands,
+    ) -> Result<bool, VmError> {
+        let object = frame.get(ops.obj, globals)?;
+        let path = value_to_text(&frame.get(ops.path, globals)?)?;
+        let result = match ops.value {
+            None => get_path(&object, &path, ops.safe).map(|value| (value, 0)),
+            Some(value) => set_path(object, &path, frame.get(value, globals)?, ops.safe),
+        };
+        match result {
+            Ok((value, created)) => {
+                // Each object made along the path plus the entry at its end.
+                let objects = usize::try_from(created).unwrap_or(usize::MAX);
+                let bytes = path
+                    .len()
+                    .saturating_add(VALUE_SIZE.saturating_mul(objects.saturating_add(1)));
+                if ops.value.is_some() && !self.charge_memory(frame, globals, bytes)? {
+                    return Ok(false);
+                }
+                self.stats.objects_allocated += created;
+                frame.set(ops.out, value, globals);
+                Ok(true)
+            }
+            Err(msg) => {
+                self.throw_type_error(frame, "type_error", &msg, globals)?;
+                Ok(false)
+            }
+        }
+    }
+
+    /// Runs a `core::str::*` op other than `format`. Indices count chars,
+    /// like `core::str::len`. An out-of-range `sub` throws a catchable
+    /// `str_range`, and an operand of the wrong kind (a list, object,
+    /// function or bytes where text is expected, a non-number index, a
+    /// `join` of a non-list) a `type_error`; returns `false` in those cases.
+    #[allow(clippy::too_many_lines)] // one arm per string op
+    fn str_op(
+        &mut self,
+        frame: &mut Frame,
+        globals: &mut [Value],
+        ops: StrOperands,
+    ) -> Result<bool, VmError> {
+        let value = frame.get(ops.value, globals)?;
+        let second = ops
+            .second
+            .map(|slot| frame.get(slot, globals))
+            .transpose()?;
+        let third = ops.third.map(|slot| frame.get(slot, globals)).transpose()?;
+        let op = ops.kind.name();
+        let text = |value: &Value| operand_text(op, value);
+        let text_arg = |value: Option<Value>| text(&value.unwrap_or(Value::Null));
+        let num_arg = |value: Option<Value>| match value.unwrap_or(Value::Null) {
+            Value::Num(num) => Ok(num),
+            Value::Int(int) => Ok(int_num(int)),
+            other => Err((
+                "type_error",
+                format!("{op} expects a number, found {}", other.type_name()),
+            )),
+        };
+        let stats = &mut self.stats;
+        let result = (|| match ops.kind {
+            StrOpKind::Concat => {
+                stats.strings_allocated += 1;
+                let (a, b) = (text(&value)?, text_arg(second)?);
+                Ok(Value::Str(Arc::from(format!("{a}{b}"))))
+            }
+            StrOpKind::Len => Ok(Value::Num(count_num(text(&value)?.chars().count()))),
+            StrOpKind::Upper
+            | StrOpKind::Lower
+            | StrOpKind::Trim
+            | StrOpKind::TrimStart
+            | StrOpKind::TrimEnd => {
+                let text = text(&value)?;
+                let result = match ops.kind {
+                    StrOpKind::Upper => text.to_uppercase(),
+                    StrOpKind::Lower => text.to_lowercase(),
+                    StrOpKind::Trim => text.trim().to_owned(),
+                    StrOpKind::TrimStart => text.trim_start().to_owned(),
+                    _ => text.trim_end().to_owned(),
+                };
+                stats.strings_allocated += 1;
+                Ok(Value::Str(Arc::from(result)))
+            }
+            StrOpKind::Sub => {
+                let text = text(&value)?;
+                let len = text.chars().count();
+                let start = num_arg(second)?;
+                let end = match third {
+                    Some(end) => num_arg(Some(end))?,
+                    None => count_num(len),
+                };
+                match byte_range(start, end, len) {
+                    Some(range) => {
+                        stats.strings_allocated += 1;
+                        let sub = text
+                            .chars()
+                            .skip(range.start)
+                            .take(range.len())
+                            .collect::<String>();
+                        Ok(Value::Str(Arc::from(sub)))
+                    }
+                    None => Err((
+                        "str_range",
+                        format!("sub {start}..{end} is out of range for {len} chars"),
+                    )),
+                }
+            }
+            StrOpKind::Find => {
+                let (text, pattern) = (text(&value)?, text_arg(second)?);
+                Ok(Value::Num(match text.find(&pattern) {
+                    Some(offset) => count_num(text[..offset].chars().count()),
+                    None => -1.0,
+                }))
+            }
+            StrOpKind::Replace => {
+                let text = text(&value)?;
+                let (pattern, with) = (text_arg(second)?, text_arg(third)?);
+                stats.strings_allocated += 1;
+                Ok(Value::Str(Arc::from(text.replace(&pattern, &with))))
+            }
+            StrOpKind::Contains => {
+                let (text, pattern) = (text(&value)?, text_arg(second)?);
+                Ok(Value::Bool(text.contains(&pattern)))
+            }
+            StrOpKind::Split => {
+                let (text, sep) = (text(&value)?, text_arg(second)?);
+                let parts = if sep.is_empty() {
+                    text.chars()
+                        .map(|ch| Value::Str(Arc::from(ch.to_string())))
+                        .collect::<Vec<_>>()
+                } else {
+                    text.split(sep.as_str())
+                        .map(|part| Value::Str(Arc::from(part)))
+                        .collect()
+                };
+                stats.strings_allocated += parts.len() as u64;
+                Ok(Value::List(parts))
+            }
+            StrOpKind::Join => match &value {
+                Value::List(items) => {
+                    let parts = items.iter().map(text).collect::<Result<Vec<_>, _>>()?;
+                    stats.strings_allocated += 1;
+                    Ok(Value::Str(Arc::from(parts.join(&text_arg(second)?))))
+                }
+                other => Err((
+                    "type_error",
+                    format!("{op} expects a list, found {}", other.type_name()),
+                )),
+            },
+        })();
+        match result {
+            Ok(value) => {
+                if !self.charge_value(frame, globals, &value)? {
+                    return Ok(false);
+                }
+                frame.set(ops.out, value, globals);
+                Ok(true)
+            }
+            Err(("type_error", msg)) => {
+                self.throw_type_error(frame, "type_error", &msg, globals)?;
+                Ok(false)
+            }
+            Err((code, msg)) => {
+                frame.throw(code, &msg, globals)?;
+                Ok(false)
+            }
+        }
+    }
+
+    /// Runs a `core::bytes::*` op. Range and UTF-8 failures throw catchable
+    /// `bytes_range` / `invalid_utf8` errors; returns `false` in that case.
+    fn bytes_op(
+        &mut self,
+        frame: &mut Frame,
+        globals: &mut [Value],
+        ops: BytesOperands,
+    ) -> Result<bool, VmError> {
+        let value = frame.get(ops.value, globals)?;
+        let second = ops
+            .second
+            .map(|slot| frame.get(slot, globals))
+            .transpose()?;
+        let third = ops.third.map(|slot| frame.get(slot, globals)).transpose()?;
+        let result = match ops.kind {
+            BytesOpKind::FromStr => match value {
+                Value::Str(text) => Ok(Value::Bytes(Arc::from(text.as_bytes()))),
+                other => return Err(bytes_type_error("from_str", "str", &other)),
+            },
+            BytesOpKind::ToStr => {
+                let bytes = expect_bytes("to_str", &value)?;
+                match std::str::from_utf8(bytes) {
+                    Ok(text) => {
+                        self.stats.strings_allocated += 1;
+                        Ok(Value::Str(Arc::from(text)))
+                    }
+                    Err(err) => Err((
+                        "invalid_utf8",
+                        format!("bytes are not valid UTF-8 at offset {}", err.valid_up_to()),
+                    )),
+                }
+            }
+            BytesOpKind::Len => Ok(Value::Num(count_num(expect_bytes("len", &value)?.len()))),
+            BytesOpKind::Concat => {
+                let b = second.unwrap_or(Value::Null);
+                let joined =
+                    [expect_bytes("concat", &value)?, expect_bytes("concat", &b)?].concat();
+                Ok(Value::Bytes(Arc::from(joined)))
+            }
+            BytesOpKind::Slice => {
+                let bytes = expect_bytes("slice", &value)?;
+                let start = second.unwrap_or(Value::Null).as_num()?;
+                let end = match third {
+                    Some(end) => end.as_num()?,
+                    None => count_num(bytes.len()),
+                };
+                match byte_range(start, end, bytes.len()) {
+                    Some(range) => Ok(Value::Bytes(Arc::from(&bytes[range]))),
+                    None => Err((
+                        "bytes_range",
+                        format!(
+                            "slice {start}..{end} is out of range for {} bytes",
+                            bytes.len()
+                        ),
+                    )),
+                }
+            }
+        };
+        match result {
+            Ok(value) => {
+                if !self.charge_value(frame, globals, &value)? {
+                    return Ok(false);
+                }
+                frame.set(ops.out, value, globals);
+                Ok(true)
+            }
+            Err((code, msg)) => {
+                frame.throw(code, &msg, globals)?;
+                Ok(false)
+            }
+        }
+    }
+
+    /// Builds the `core::fn::meta` object for a function value, following
+    /// foreign-function handles back to their defining module.
+    fn fn_meta_value(&mut self, module: &CompiledModule, value: &Value) -> Result<Value, VmError> {
+        let (func_id, captured) = match value {
+            Value::Func(func_id) => (func_id, 0),
+            Value::Closure { func, captures } => (func, captures.len()),
+            _ => {
+                return Err(VmError::runtime(format!(
+                    "core::fn::meta expects a function, found {}",
+                    value.type_name()
+                )));
+            }
+        };
+        let (func_id, bound_count) = match self.bound_funcs.get(func_id) {
+            Some(bound) => (bound.target, bound.args.len() + captured),
+            None => (*func_id, captured),
+        };
+        let function = match module.function(func_id) {
+            Some(function) => function,
+            None => self
+                .foreign_funcs
+                .get(&func_id)
+                .and_then(|foreign| foreign.module.function(foreign.func_id))
+                .ok_or_else(|| self.unknown_function(func_id))?,
+        };
+        let meta = &function.meta;
+        let arg_count = (meta.arg_count as usize).saturating_sub(bound_count);
+        let mut fields = HashMap::from([
+            ("name".to_owned(), Value::Str(Arc::clone(&meta.name))),
+            ("arg_count".to_owned(), Value::Num(count_num(arg_count))),
+            (
+                "ret_count".to_owned(),
+                Value::Num(f64::from(meta.ret_count)),
+            ),
+            (
+                "retshape".to_owned(),
+                Value::Str(Arc::from(meta.retshape.to_string())),
+            ),
+        ]);
+        if let Some(ms) = meta.timeout_ms {
+            // No timeout is anywhere near 2^53 ms.
+            #[allow(clippy::cast_precision_loss)]
+            let ms = ms as f64;
+            fields.insert("timeout_ms".to_owned(), Value::Num(ms));
+        }
+        self.stats.objects_allocated += 1;
+        Ok(Value::Obj(ObjRef::new(fields)))
+    }
+
+    fn stack_overflow_message(&self) -> Option<String> {
+        let max = self.cfg.max_call_depth?;
+        (self.call_depth >= max).then(|| {
+            format!(
+                "call depth {} exceeds max_call_depth {max}",
+                self.call_depth
+            )
+        })
+    }
+
+    /// Runs `function` on the interpreter against deep copies of `args` and
+    /// `globals`, so objects the callee mutates are not shared with the
+    /// real run, without leaving observable VM state behind (handles,
+    /// stats, host output).
+    fn run_shadow_interpreter(
+        &mut self,
+        module: &CompiledModule,
+        function: &CompiledFunction,
+        args: &[Value],
+        globals: &[Value],
+    ) -> ShadowRun {
+        let foreign_funcs = self.foreign_funcs.clone();
+        let bound_funcs = self.bound_funcs.clone();
+        let next_foreign_func_id = self.next_foreign_func_id;
+        let stats = self.stats.clone();
+        let rng = self.rng;
+        self.shadow_input_pos = 0;
+
+        let [args, mut shadow_globals] = Value::deep_copy_all([args, globals]);
+        let frame = Frame::new(function, &args);
+        self.shadow_runs += 1;
+        let (result, pc) = self.run_interpreter_frame(module, frame, &mut shadow_globals);
+        self.shadow_runs -= 1;
+
+        self.foreign_funcs = foreign_funcs;
+        self.bound_funcs = bound_funcs;
+        self.next_foreign_func_id = next_foreign_func_id;
+        self.stats = stats;
+        self.rng = rng;
+        ShadowRun {
+            result,
+            globals: shadow_globals,
+            pc,
+        }
+    }
+
+    fn get_or_compile_jit(
+        &mut self,
+        module: &CompiledModule,
+        function: &CompiledFunction,
+    ) -> Result<Rc<JitFunction>, VmError> {
+        let key = JitKey::new(module, function);
+        if let Some(cached) = self.jit_cache.get(&key) {
+            return Ok(cached);
+        }
+        let compiled = Rc::new(JitFunction::compile(function)?);
+        self.jit_cache.insert(
+            key,
+            Rc::clone(&compiled),
+            self.cfg.jit_cache_max_entries,
+            self.cfg.jit_cache_max_bytes,
+        );
+        Ok(compiled)
+    }
+
+    /// Reports the instruction at `pc` to `VmConfig.trace`.
+    fn trace_instr(&self, module: &CompiledModule, frame: &Frame, globals: &[Value], pc: usize) {
+        let (Some(trace), Some(instr)) = (&self.cfg.trace, frame.code.get(pc)) else {
+            return;
+        };
+        let mut operands: Vec<(Slot, Value)> = Vec::new();
+        for slot in instr.slots() {
+            if operands.iter().all(|(seen, _)| *seen != slot)
+                && let Ok(value) = frame.get(slot, globals)
+            {
+                operands.push((slot, value));
+            }
+        }
+        trace.instruction(&TraceEvent {
+            module: &module.name,
+            function: &frame.meta.name,
+            pc,
+            instr,
+            operands: &operands,
+        });
+    }
+
+    fn execute_function_jit(
+        &mut self,
+        module: &CompiledModule,
+        frame: &mut Frame,
+        globals: &mut [Value],
+        jit: &JitFunction,
+    ) -> Result<FrameExit, VmError> {
+        let mut pc = frame.pc;
+        loop {
+            if pc >= jit.steps.len() {
+                return Err(VmError::runtime(format!(
+                    "pc {} out of range for {}",
+                    pc, frame.meta.name
+                )));
+            }
+
+            frame.pc = pc;
+            if !self.take_step(frame) {
+                return Ok(FrameExit::Yield);
+            }
+            self.burn_fuel(frame)?;
+            self.poll_interrupts(frame)?;
+            if !self.enforce_deadline(frame, globals)? {
+                pc = frame.pc;
+                continue;
+            }
+            if self.cfg.profile {
+                self.stats.instructions += 1;
+                if let Some(slot) = frame.profile_slot {
+                    self.profiler.count_instruction(slot);
+                }
+            }
+            if self.cfg.coverage && self.shadow_runs == 0 {
+                self.coverage.record(module, &frame.meta.name, pc);
+            }
+            if self.cfg.trace.is_some() && self.shadow_runs == 0 {
+                self.trace_instr(module, frame, globals, pc);
+            }
+            let step = &jit.steps[pc];
+            match (step.exec)(self, module, frame, globals, &step.operands, pc)? {
+                StepControl::Next(next) => {
+                    pc = next;
+                }
+                StepControl::Call { func, args, out } => {
+                    return Ok(FrameExit::Call { func, args, out });
+                }
+                StepControl::TailCall { func, args } => {
+                    return Ok(FrameExit::TailCall { func, args });
+                }
+                StepControl::Exit => {
+                    validate_retshape(&frame.meta, frame.returns())?;
+                    return Ok(FrameExit::Return(frame.take_returns()));
+                }
+            }
+        }
+    }
+
+    // The interpreter loop: one arm per instruction.
+    #[allow(clippy::too_many_lines)]
+    fn execute_function_interpreter(
+        &mut self,
+        module: &CompiledModule,
+        frame: &mut Frame,
+        globals: &mut [Value],
+    ) -> Result<FrameExit, VmError> {
+        loop {
+            let Some(instr) = frame.code.get(frame.pc).cloned() else {
+                return Err(VmError::runtime(format!(
+                    "pc {} out of range for {}",
+                    frame.pc, frame.meta.name
+                )));
+            };
+            if !self.take_step(frame) {
+                return Ok(FrameExit::Yield);
+            }
+            self.burn_fuel(frame)?;
+            self.poll_interrupts(frame)?;
+            if !self.enforce_deadline(frame, globals)? {
+                continue;
+            }
+            if self.cfg.profile {
+                self.stats.instructions += 1;
+                if let Some(slot) = frame.profile_slot {
+                    self.profiler.count_instruction(slot);
+                }
+            }
+            if self.cfg.coverage && self.shadow_runs == 0 {
+                self.coverage.record(module, &frame.meta.name, frame.pc);
+            }
+            if self.cfg.trace.is_some() && self.shadow_runs == 0 {
+                self.trace_instr(module, frame, globals, frame.pc);
+            }
+
+            match instr {
+                Instr::StoreConst { slot, value } => {
+                    let value = Value::from_const(&value);
+                    if self.charge_value(frame, globals, &value)? {
+                        frame.set(slot, value, globals);
+                        frame.pc += 1;
+                    }
+                }
+                Instr::Move { from, to } => {
+                    let value = frame.get(from, globals)?;
+                    frame.set(to, value, globals);
+                    frame.pc += 1;
+                }
+                Instr::Add { a, b, out }
+                | Instr::Sub { a, b, out }
+                | Instr::Mul { a, b, out }
+                | Instr::Div { a, b, out }
+                | Instr::Mod { a, b, out }
+                | Instr::Pow { a, b, out } => {
+                    let op = match instr {
+                        Instr::Add { .. } => BinaryOp::Add,
+                        Instr::Sub { .. } => BinaryOp::Sub,
+                        Instr::Mul { .. } => BinaryOp::Mul,
+                        Instr::Div { .. } => BinaryOp::Div,
+                        Instr::Mod { .. } => BinaryOp::Mod,
+                        _ => BinaryOp::Pow,
+                    };
+                    match arith(op, &frame.get(a, globals)?, &frame.get(b, globals)?)? {
+                        Ok(value) => {
+                            frame.set(out, value, globals);
+                            frame.pc += 1;
+                        }
+                        Err((code, msg)) => {
+                            frame.throw(code, &msg, globals)?;
+                        }
+                    }
+                }
+                Instr::Eq { a, b, out } => {
+                    let result = values_eq(&frame.get(a, globals)?, &frame.get(b, globals)?);
+                    frame.set(out, Value::Bool(result), globals);
+                    frame.pc += 1;
+                }
+                Instr::Ne { a, b, out } => {
+                    let result = !values_eq(&frame.get(a, globals)?, &frame.get(b, globals)?);
+                    frame.set(out, Value::Bool(result), globals);
+                    frame.pc += 1;
+                }
+                Instr::Lt { a, b, out }
+                | Instr::Le { a, b, out }
+                | Instr::Gt { a, b, out }
+                | Instr::Ge { a, b, out } => {
+                    let op = match instr {
+                        Instr::Lt { .. } => BinaryOp::Lt,
+                        Instr::Le { .. } => BinaryOp::Le,
+                        Instr::Gt { .. } => BinaryOp::Gt,
+                        _ => BinaryOp::Ge,
+                    };
+                    let result = compare(op, &frame.get(a, globals)?, &frame.get(b, globals)?)?;
+                    frame.set(out, Value::Bool(result), globals);
+                    frame.pc += 1;
+                }
+                Instr::Cmp { a, b, out } => {
+                    let ordering = frame.get(a, globals)?.total_cmp(&frame.get(b, globals)?);
+                    frame.set(out, Value::Num(f64::from(ordering as i8)), globals);
+                    frame.pc += 1;
+                }
+                Instr::Jump { target } => {
+                    frame.pc = target;
+                }
+                Instr::Branch {
+                    cond,
+                    then_pc,
+                    else_pc,
+                } => {
+                    let condition = frame.get(cond, globals)?.as_bool();
+                    frame.pc = if condition { then_pc } else { else_pc };
+                }
+                Instr::Invoke { fn_slot, args, out } => {
+                    let Some((func, args)) =
+                        self.prepare_invoke(frame, globals, fn_slot, &args, false)?
+                    else {
+                        continue;
+                    };
+                    return Ok(FrameExit::Call {
+                        func,
+                        args,
+                        out: CallOut::One(out),
+                    });
+                }
+                Instr::InvokeMulti {
+                    fn_slot,
+                    args,
+                    outs,
+                } => {
+                    let Some((func, args)) =
+                        self.prepare_invoke(frame, globals, fn_slot, &args, false)?
+                    else {
+                        continue;
+                    };
+                    return Ok(FrameExit::Call {
+                        func,
+                        args,
+                        out: CallOut::Many(outs.into()),
+                    });
+                }
+                Instr::TailInvoke { fn_slot, args } => {
+                    // A handler must still catch what the callee throws.
+                    let tail = frame.try_stack.is_empty();
+                    let Some((func, args)) =
+                        self.prepare_invoke(frame, globals, fn_slot, &args, tail)?
+                    else {
+                        continue;
+                    };
+                    return Ok(if tail {
+                        FrameExit::TailCall { func, args }
+                    } else {
+                        FrameExit::Call {
+                            func,
+                            args,
+                            out: CallOut::One(Slot::Ret(0)),
+                        }
+                    });
+                }
+                Instr::InvokeDynamic {
+                    fn_slot,
+                    arglist,
+                    out,
+                } => {
+                    let target = frame.get(fn_slot, globals)?;
+                    let mut values = self.spread_args(frame, globals, arglist)?;
+                    let target_func = match invoke_target(frame, &target, &mut values) {
+                        Ok(func) => func,
+                        Err((code, msg)) => {
+                            self.throw_type_error(frame, code, &msg, globals)?;
+                            continue;
+                        }
+                    };
+                    if let Some(msg) = self.stack_overflow_message() {
+                        frame.throw("stack_overflow", &msg, globals)?;
+                        continue;
+                    }
+
+                    return Ok(FrameExit::Call {
+                        func: target_func,
+                        args: values,
+                        out: CallOut::One(out),
+                    });
+                }
+                Instr::ReturnSet { slot_id, value } => {
+                    let value = frame.get(value, globals)?;
+                    frame.set_ret(slot_id as usize, value);
+                    frame.pc += 1;
+                }
+                Instr::Exit => {
+                    validate_retshape(&frame.meta, frame.returns())?;
+                    return Ok(FrameExit::Return(frame.take_returns()));
+                }
+                Instr::Throw {
+                    code,
+                    msg,
+                    cause,
+                    data,
+                } => {
+                    let [cause, data] = [cause, data].map(|slot| {
+                        slot.map(|slot| frame.get(slot, globals).map(Rc::new))
+                            .transpose()
+                    });
+                    frame.throw_error(Arc::from(code), Arc::from(msg), cause?, data?, globals)?;
+                }
+                Instr::ErrorWrap {
+                    err,
+                    code,
+                    msg,
+                    out,
+                } => {
+                    let cause = frame.get(err, globals)?;
+                    frame.set(out, wrap_error(&code, &msg, cause), globals);
+                    frame.pc += 1;
+                }
+                Instr::ErrorCause { err, out } => {
+                    let cause = error_cause(&frame.get(err, globals)?);
+                    frame.set(out, cause, globals);
+                    frame.pc += 1;
+                }
+                Instr::ErrorCode { err, out } => {
+                    let code = error_code(&frame.get(err, globals)?);
+                    frame.set(out, code, globals);
+                    frame.pc += 1;
+                }
+                Instr::ErrorMsg { err, out } => {
+                    let msg = error_msg(&frame.get(err, globals)?);
+                    frame.set(out, msg, globals);
+                    frame.pc += 1;
+                }
+                Instr::ErrorData { err, out } => {
+                    let data = error_data(&frame.get(err, globals)?);
+                    frame.set(out, data, globals);
+                    frame.pc += 1;
+                }
+                Instr::Rethrow { err } => {
+                    let err = frame.get(err, globals)?;
+                    self.rethrow(frame, err, globals)?;
+                }
+                Instr::TryPush { handler_pc } => {
+                    frame.try_stack.push(handler_pc);
+                    frame.pc += 1;
+                }
+                Instr::TryPop => {
+                    frame.try_stack.pop();
+                    frame.pc += 1;
+                }
+                Instr::ObjNew { out } => {
+                    if self.charge_memory(frame, globals, VALUE_SIZE)? {
+                        self.stats.objects_allocated += 1;
+                        frame.set(out, Value::Obj(ObjRef::default()), globals);
+                        frame.pc += 1;
+                    }
+                }
+                Instr::ObjSet {
+                    obj,
+                    key,
+                    value,
+                    out,
+                } => {
+                    let object = match frame.get(obj, globals)? {
+                        Value::Obj(object) => object,
+                        other => {
+                            let msg = not_an_object("core::obj::set", &other);
+                            self.throw_type_error(frame, "type_error", &msg, globals)?;
+                            continue;
+                        }
+                    };
+                    let key_text = value_to_text(&frame.get(key, globals)?)?;
+                    let value = frame.get(value, globals)?;
+                    if !self.charge_memory(frame, globals, entry_size(&object, &key_text))? {
+                        continue;
+                    }
+                    object.borrow_mut().insert(key_text, value);
+                    frame.set(out, Value::Obj(object), globals);
+                    frame.pc += 1;
+                }
+                Instr::ObjGet { obj, key, out } => {
+                    let object = frame.get(obj, globals)?;
+                    let key_text = value_to_text(&frame.get(key, globals)?)?;
+                    match object_lookup("core::obj::get", &object, &key_text) {
+                        Ok(value) => {
+                            frame.set(out, value.unwrap_or(Value::Null), globals);
+                            frame.pc += 1;
+                        }
+                        Err((code, msg)) => {
+                            self.throw_type_error(frame, code, &msg, globals)?;
+                        }
+                    }
+                }
+                Instr::ObjHas { obj, key, out } => {
+                    let object = frame.get(obj, globals)?;
+                    let key_text = value_to_text(&frame.get(key, globals)?)?;
+                    match object_lookup("core::obj::has", &object, &key_text) {
+                        Ok(value) => {
+                            frame.set(out, Value::Bool(value.is_some()), globals);
+                            frame.pc += 1;
+                        }
+                        Err((code, msg)) => {
+                            self.throw_type_error(frame, code, &msg, globals)?;
+                        }
+                    }
+                }
+                Instr::ObjEntries { obj, out, len } => {
+                    let value = frame.get(obj, globals)?;
+                    let Some((entries, count)) = self.obj_entries(frame, globals, &value)? else {
+                        continue;
+                    };
+                    if !self.charge_value(frame, globals, &entries)? {
+                        continue;
+                    }
+                    frame.set(out, entries, globals);
+                    if let Some(len) = len {
+                        frame.set(len, Value::Num(count_num(count)), globals);
+                    }
+                    frame.pc += 1;
+                }
+                Instr::ObjKeys { .. } | Instr::ObjDel { .. } | Instr::ObjLen { .. } => {
+                    let ops = ObjOperands::from_instr(&instr);
+                    if self.obj_op(frame, globals, ops)? {
+                        frame.pc += 1;
+                    }
+                }
+                Instr::ObjGetPath { .. } | Instr::ObjSetPath { .. } => {
+                    let ops = PathOperands::from_instr(&instr);
+                    if self.obj_path(frame, globals, ops)? {
+                        frame.pc += 1;
+                    }
+                }
+                Instr::ListNew { .. }
+                | Instr::ListPush { .. }
+                | Instr::ListGet { .. }
+                | Instr::ListSet { .. }
+                | Instr::ListLen { .. } => {
+                    let ops = ListOperands::from_instr(&instr);
+                    if self.list_op(frame, globals, ops)? {
+                        frame.pc += 1;
+                    }
+                }
+                Instr::StrConcat { .. }
+                | Instr::StrLen { .. }
+                | Instr::StrUpper { .. }
+                | Instr::StrLower { .. }
+                | Instr::StrTrim { .. }
+                | Instr::StrTrimStart { .. }
+                | Instr::StrTrimEnd { .. }
+                | Instr::StrSub { .. }
+                | Instr::StrFind { .. }
+                | Instr::StrReplace { .. }
+                | Instr::StrContains { .. }
+                | Instr::StrSplit { .. }
+                | Instr::StrJoin { .. } => {
+                    let ops = StrOperands::from_instr(&instr);
+                    if self.str_op(frame, globals, ops)? {
+                        frame.pc += 1;
+                    }
+                }
+                Instr::StrFormat { tmpl, args, out } => {
+                    if self.str_format(frame, globals, tmpl, args, out)? {
+                        frame.pc += 1;
+                    }
+                }
+                Instr::BytesFromStr { .. }
+                | Instr::BytesToStr { .. }
+                | Instr::BytesLen { .. }
+                | Instr::BytesConcat { .. }
+                | Instr::BytesSlice { .. } => {
+                    let ops = BytesOperands::from_instr(&instr);
+                    if self.bytes_op(frame, globals, ops)? {
+                        frame.pc += 1;
+                    }
+                }
+                Instr::FnBind { func, args, out } => {
+                    let target = frame.get(func, globals)?;
+                    let mut values = Vec::with_capacity(args.len());
+                    for slot in &args {
+                        values.push(frame.get(*slot, globals)?);
+                    }
+                    let bound = self.bind_function(&target, values)?;
+                    frame.set(out, bound, globals);
+                    frame.pc += 1;
+                }
+                Instr::MakeClosure {
+                    func,
+                    captures,
+                    out,
+                } => {
+                    let target = frame.get(func, globals)?;
+                    let mut values = Vec::with_capacity(captures.len());
+                    for slot in &captures {
+                        values.push(frame.get(*slot, globals)?);
+                    }
+                    let closure = make_closure(&target, values)?;
+                    frame.set(out, closure, globals);
+                    frame.pc += 1;
+                }
+                Instr::Neg { value, out } => match neg(&frame.get(value, globals)?)? {
+                    Ok(negated) => {
+                        frame.set(out, negated, globals);
+                        frame.pc += 1;
+                    }
+                    Err((code, msg)) => {
+                        frame.throw(code, &msg, globals)?;
+                    }
+                },
+                Instr::IntParse { value, out } => match int_parse(&frame.get(value, globals)?)? {
+                    Ok(int) => {
+                        frame.set(out, int, globals);
+                        frame.pc += 1;
+                    }
+                    Err((code, msg)) => {
+                        frame.throw(code, &msg, globals)?;
+                    }
+                },
+                Instr::TypeOf { value, out } => {
+                    let kind = frame.get(value, globals)?.kind();
+                    frame.set(out, Value::Str(Arc::from(kind.as_str())), globals);
+                    frame.pc += 1;
+                }
+                Instr::IsType { value, kind, out } => {
+                    let holds = frame.get(value, globals)?.kind() == kind;
+                    frame.set(out, Value::Bool(holds), globals);
+                    frame.pc += 1;
+                }
+                Instr::NumParse { value, out } => match num_parse(&frame.get(value, globals)?)? {
+                    Ok(num) => {
+                        frame.set(out, num, globals);
+                        frame.pc += 1;
+                    }
+                    Err((code, msg)) => {
+                        frame.throw(code, &msg, globals)?;
+                    }
+                },
+                Instr::NumFormat {
+                    value,
+                    precision,
+                    width,
+                    out,
+                } => {
+                    let precision = precision.map(|slot| frame.get(slot, globals)).transpose()?;
+                    let width = width.map(|slot| frame.get(slot, globals)).transpose()?;
+                    match num_format(&frame.get(value, globals)?, precision, width)? {
+                        Ok(text) => {
+                            self.stats.strings_allocated += 1;
+                            frame.set(out, text, globals);
+                            frame.pc += 1;
+                        }
+                        Err((code, msg)) => {
+                            frame.throw(code, &msg, globals)?;
+                        }
+                    }
+                }
+                Instr::FnMeta { func, out } => {
+                    let value = self.fn_meta_value(module, &frame.get(func, globals)?)?;
+                    frame.set(out, value, globals);
+                    frame.pc += 1;
+                }
+                Instr::Ext { op, operands } => {
+                    if self.run_ext(frame, globals, op, &operands)? {
+                        frame.pc += 1;
+                    }
+                }
+                Instr::HostCall { name, args, out } => {
+                    if self.host_call(frame, globals, &name, &args, out)? {
+                        frame.pc += 1;
+                    }
+                }
+                Instr::HostPrint {
+                    slot,
+                    stream,
+                    level,
+                } => {
+                    if self.host_print(frame, globals, slot, stream, level)? {
+                        frame.pc += 1;
+                    }
+                }
+                Instr::HostTime { clock, out } => {
+                    if self.host_time(frame, globals, clock, out)? {
+                        frame.pc += 1;
+                    }
+                }
+                Instr::HostArgs { out } => {
+                    if self.host_args(frame, globals, out)? {
+                        frame.pc += 1;
+                    }
+                }
+                Instr::HostReadLine { out } => {
+                    if self.host_read(frame, globals, false, out)? {
+                        frame.pc += 1;
+                    }
+                }
+                Instr::HostReadAll { out } => {
+                    if self.host_read(frame, globals, true, out)? {
+                        frame.pc += 1;
+                    }
+                }
+                Instr::HostEnvVar { key, out } => {
+                    if self.host_env_var(frame, globals, key, out)? {
+                        frame.pc += 1;
+                    }
+                }
+                Instr::HostRandom { out } => {
+                    if self.host_random(frame, globals, None, out)? {
+                        frame.pc += 1;
+                    }
+                }
+                Instr::HostRandomInt { lo, hi, out } => {
+                    if self.host_random(frame, globals, Some((lo, hi)), out)? {
+                        frame.pc += 1;
+                    }
+                }
+                Instr::JsonParse { value, out } => {
+                    if self.json_op(frame, globals, true, value, out)? {
+                        frame.pc += 1;
+                    }
+                }
+                Instr::JsonStringify { value, out } => {
+                    if self.json_op(frame, globals, false, value, out)? {
+                        frame.pc += 1;
+                    }
+                }
+                Instr::HostFsRead { .. }
+                | Instr::HostFsWrite { .. }
+                | Instr::HostFsExists { .. } => {
+                    if self.host_fs(frame, globals, FsOperands::from_instr(&instr))? {
+                        frame.pc += 1;
+                    }
+                }
+            }
+        }
+    }
+}
+
+fn step_store_const(
+    vm: &mut Vm,
+    _module: &CompiledModule,
+    frame: &mut Frame,
+    globals: &mut [Value],
+    operands: &JitOperands,
+    pc: usize,
+) -> Result<StepControl, VmError> {
+    let JitOperands::StoreConst { slot, value } = operands else {
+        return Err(VmError::runtime(
+            "jit operand mismatch for store_const".to_owned(),
+        ));
+    };
+    if !vm.charge_value(frame, globals, value)? {
+        return Ok(StepControl::Next(frame.pc));
+    }
+    frame.store(*slot, value.clone(), globals);
+    Ok(StepControl::Next(pc + 1))
+}
+
+fn step_move(
+    _vm: &mut Vm,
+    _module: &CompiledModule,
+    frame: &mut Frame,
+    globals: &mut [Value],
+    operands: &JitOperands,
+    pc: usize,
+) -> Result<StepControl, VmError> {
+    let JitOperands::Move { from, to } = operands else {
+        return Err(VmError::runtime("jit operand mismatch for move".to_owned()));
+    };
+    let value = frame.load(*from, globals)?;
+    frame.store(*to, value, globals);
+    Ok(StepControl::Next(pc + 1))
+}
+
+fn step_binary(
+    _vm: &mut Vm,
+    _module: &CompiledModule,
+    frame: &mut Frame,
+    globals: &mut [Value],
+    operands: &JitOperands,
+    pc: usize,
+) -> Result<StepControl, VmError> {
+    let JitOperands::Binary { kind, a, b, out } = operands else {
+        return Err(VmError::runtime(
+            "jit operand mismatch for binary".to_owned(),
+        ));
+    };
+
+    match kind {
+        BinaryOp::Add
+        | BinaryOp::Sub
+        | BinaryOp::Mul
+        | BinaryOp::Div
+        | BinaryOp::Mod
+        | BinaryOp::Pow => {
+            match arith(*kind, &frame.load(*a, globals)?, &frame.load(*b, globals)?)? {
+                Ok(value) => {
+                    frame.store(*out, value, globals);
+                    Ok(StepControl::Next(pc + 1))
+                }
+                Err((code, msg)) => Ok(StepControl::Next(frame.throw(code, &msg, globals)?)),
+            }
+        }
+        BinaryOp::Eq => {
+            let result = values_eq(&frame.load(*a, globals)?, &frame.load(*b, globals)?);
+            frame.store(*out, Value::Bool(result), globals);
+            Ok(StepControl::Next(pc + 1))
+        }
+        BinaryOp::Ne => {
+            let result = !values_eq(&frame.load(*a, globals)?, &frame.load(*b, globals)?);
+            frame.store(*out, Value::Bool(result), globals);
+            Ok(StepControl::Next(pc + 1))
+        }
+        BinaryOp::Lt | BinaryOp::Le | BinaryOp::Gt | BinaryOp::Ge => {
+            let result = compare(*kind, &frame.load(*a, globals)?, &frame.load(*b, globals)?)?;
+            frame.store(*out, Value::Bool(result), globals);
+            Ok(StepControl::Next(pc + 1))
+        }
+        BinaryOp::Cmp => {
+            let ordering = frame
+                .load(*a, globals)?
+                .total_cmp(&frame.load(*b, globals)?);
+            frame.store(*out, Value::Num(f64::from(ordering as i8)), globals);
+            Ok(StepControl::Next(pc + 1))
+        }
+    }
+}
+
+fn step_jump(
+    _vm: &mut Vm,
+    _module: &CompiledModule,
+    _frame: &mut Frame,
+    _globals: &mut [Value],
+    operands: &JitOperands,
+    _pc: usize,
+) -> Result<StepControl, VmError> {
+    let JitOperands::Jump { target } = operands else {
+        return Err(VmError::runtime("jit operand mismatch for jump".to_owned()));
+    };
+    Ok(StepControl::Next(*target))
+}
+
+fn step_branch(
+    _vm: &mut Vm,
+    _module: &CompiledModule,
+    frame: &mut Frame,
+    globals: &mut [Value],
+    operands: &JitOperands,
+    _pc: usize,
+) -> Result<StepControl, VmError> {
+    let JitOperands::Branch {
+        cond,
+        then_pc,
+        else_pc,
+    } = operands
+    else {
+        return Err(VmError::runtime(
+            "jit operand mismatch for branch".to_owned(),
+        ));
+    };
+    let condition = frame.load(*cond, globals)?.as_bool();
+    Ok(StepControl::Next(if condition {
+        *then_pc
+    } else {
+        *else_pc
+    }))
+}
+
+fn step_invoke(
+    vm: &mut Vm,
+    _module: &CompiledModule,
+    frame: &mut Frame,
+    globals: &mut [Value],
+    operands: &JitOperands,
+    _pc: usize,
+) -> Result<StepControl, VmError> {
+    let JitOperands::Invoke { fn_slot, args, out } = operands else {
+        return Err(VmError::runtime(
+            "jit operand mismatch for invoke".to_owned(),
+        ));
+    };
+
+    let Some((func, args)) = vm.prepare_invoke(frame, globals, *fn_slot, args, false)? else {
+        return Ok(StepControl::Next(frame.pc));
+    };
+    Ok(StepControl::Call {
+        func,
+        args,
+        out: CallOut::One(*out),
+    })
+}
+
+fn step_tail_invoke(
+    vm: &mut Vm,
+    _module: &CompiledModule,
+    frame: &mut Frame,
+    globals: &mut [Value],
+    operands: &JitOperands,
+    _pc: usize,
+) -> Result<StepControl, VmError> {
+    let JitOperands::TailInvoke { fn_slot, args } = operands else {
+        return Err(VmError::runtime(
+            "jit operand mismatch for tail_invoke".to_owned(),
+        ));
+    };
+
+    let tail = frame.try_stack.is_empty();
+    let Some((func, args)) = vm.prepare_invoke(frame, globals, *fn_slot, args, tail)? else {
+        return Ok(StepControl::Next(frame.pc));
+    };
+    Ok(if tail {
+        StepControl::TailCall { func, args }
+    } else {
+        StepControl::Call {
+            func,
+            args,
+            out: CallOut::One(Slot::Ret(0)),
+        }
+    })
+}
+
+fn step_invoke_multi(
+    vm: &mut Vm,
+    _module: &CompiledModule,
+    frame: &mut Frame,
+    globals: &mut [Value],
+    operands: &JitOperands,
+    _pc: usize,
+) -> Result<StepControl, VmError> {
+    let JitOperands::InvokeMulti {
+        fn_slot,
+        args,
+        outs,
+    } = operands
+    else {
+        return Err(VmError::runtime(
+            "jit operand mismatch for invoke_multi".to_owned(),
+        ));
+    };
+
+    let Some((func, args)) = vm.prepare_invoke(frame, globals, *fn_slot, args, false)? else {
+        return Ok(StepControl::Next(frame.pc));
+    };
+    Ok(StepControl::Call {
+        func,
+        args,
+        out: CallOut::Many(Rc::clone(outs)),
+    })
+}
+
+fn step_invoke_dynamic(
+    vm: &mut Vm,
+    _module: &CompiledModule,
+    frame: &mut Frame,
+    globals: &mut [Value],
+    operands: &JitOperands,
+    _pc: usize,
+) -> Result<StepControl, VmError> {
+    let JitOperands::InvokeDynamic {
+        fn_slot,
+        arglist,
+        out,
+    } = operands
+    else {
+        return Err(VmError::runtime(
+            "jit operand mismatch for invoke_dynamic".to_owned(),
+        ));
+    };
+
+    let target = frame.get(*fn_slot, globals)?;
+    let mut values = vm.spread_args(frame, globals, *arglist)?;
+
+    let target_func = match invoke_target(frame, &target, &mut values) {
+        Ok(func) => func,
+        Err((code, msg)) => {
+            return Ok(StepControl::Next(
+                vm.throw_type_error(frame, code, &msg, globals)?,
+            ));
+        }
+    };
+    if let Some(msg) = vm.stack_overflow_message() {
+        return Ok(StepControl::Next(frame.throw(
+            "stack_overflow",
+            &msg,
+            globals,
+        )?));
+    }
+
+    Ok(StepControl::Call {
+        func: target_func,
+        args: values,
+        out: CallOut::One(*out),
+    })
+}
+
+fn step_return_set(
+    _vm: &mut Vm,
+    _module: &CompiledModule,
+    frame: &mut Frame,
+    globals: &mut [Value],
+    operands: &JitOperands,
+    pc: usize,
+) -> Result<StepControl, VmError> {
+    let JitOperands::ReturnSet { slot_id, value } = operands else {
+        return Err(VmError::runtime(
+            "jit operand mismatch for return_set".to_owned(),
+        ));
+    };
+    let value = frame.get(*value, globals)?;
+    frame.set_ret(*slot_id as usize, value);
+    Ok(StepControl::Next(pc + 1))
+}
+
+fn step_exit(
+    _vm: &mut Vm,
+    _module: &CompiledModule,
+    _frame: &mut Frame,
+    _globals: &mut [Value],
+    operands: &JitOperands,
+    _pc: usize,
+) -> Result<StepControl, VmError> {
+    if !matches!(operands, JitOperands::None) {
+        return Err(VmError::runtime("jit operand mismatch for exit".to_owned()));
+    }
+    Ok(StepControl::Exit)
+}
+
+fn step_throw(
+    _vm: &mut Vm,
+    _module: &CompiledModule,
+    frame: &mut Frame,
+    globals: &mut [Value],
+    operands: &JitOperands,
+    _pc: usize,
+) -> Result<StepControl, VmError> {
+    let JitOperands::Throw {
+        code,
+        msg,
+        cause,
+        data,
+    } = operands
+    else {
+        return Err(VmError::runtime(
+            "jit operand mismatch for throw".to_owned(),
+        ));
+    };
+    let [cause, data] = [cause, data].map(|slot| {
+        slot.map(|slot| frame.get(slot, globals).map(Rc::new))
+            .transpose()
+    });
+    Ok(StepControl::Next(frame.throw_error(
+        Arc::clone(code),
+        Arc::clone(msg),
+        cause?,
+        data?,
+        globals,
+    )?))
+}
+
+fn step_error_wrap(
+    _vm: &mut Vm,
+    _module: &CompiledModule,
+    frame: &mut Frame,
+    globals: &mut [Value],
+    operands: &JitOperands,
+    pc: usize,
+) -> Result<StepControl, VmError> {
+    let JitOperands::ErrorWrap {
+        err,
+        code,
+        msg,
+        out,
+    } = operands
+    else {
+        return Err(VmError::runtime(
+            "jit operand mismatch for error_wrap".to_owned(),
+        ));
+    };
+    let cause = frame.get(*err, globals)?;
+    frame.set(*out, wrap_error(code, msg, cause), globals);
+    Ok(StepControl::Next(pc + 1))
+}
+
+fn step_error_cause(
+    _vm: &mut Vm,
+    _module: &CompiledModule,
+    frame: &mut Frame,
+    globals: &mut [Value],
+    operands: &JitOperands,
+    pc: usize,
+) -> Result<StepControl, VmError> {
+    let JitOperands::Unary { value, out } = operands else {
+        return Err(VmError::runtime(
+            "jit operand mismatch for error_cause".to_owned(),
+        ));
+    };
+    let cause = error_cause(&frame.get(*value, globals)?);
+    frame.set(*out, cause, globals);
+    Ok(StepControl::Next(pc + 1))
+}
+
+fn step_error_code(
+    _vm: &mut Vm,
+    _module: &CompiledModule,
+    frame: &mut Frame,
+    globals: &mut [Value],
+    operands: &JitOperands,
+    pc: usize,
+) -> Result<StepControl, VmError> {
+    let JitOperands::Unary { value, out } = operands else {
+        return Err(VmError::runtime(
+            "jit operand mismatch for error_code".to_owned(),
+        ));
+    };
+    let code = error_code(&frame.get(*value, globals)?);
+    frame.set(*out, code, globals);
+    Ok(StepControl::Next(pc + 1))
+}
+
+fn step_error_msg(
+    _vm: &mut Vm,
+    _module: &CompiledModule,
+    frame: &mut Frame,
+    globals: &mut [Value],
+    operands: &JitOperands,
+    pc: usize,
+) -> Result<StepControl, VmError> {
+    let JitOperands::Unary { value, out } = operands else {
+        return Err(VmError::runtime(
+            "jit operand mismatch for error_msg".to_owned(),
+        ));
+    };
+    let msg = error_msg(&frame.get(*value, globals)?);
+    frame.set(*out, msg, globals);
+    Ok(StepControl::Next(pc + 1))
+}
+
+fn step_rethrow(
+    vm: &mut Vm,
+    _module: &CompiledModule,
+    frame: &mut Frame,
+    globals: &mut [Value],
+    operands: &JitOperands,
+    _pc: usize,
+) -> Result<StepControl, VmError> {
+    let JitOperands::UnarySlot { slot } = operands else {
+        return Err(VmError::runtime(
+            "jit operand mismatch for rethrow".to_owned(),
+        ));
+    };
+    let err = frame.get(*slot, globals)?;
+    Ok(StepControl::Next(vm.rethrow(frame, err, globals)?))
+}
+
+fn step_error_data(
+    _vm: &mut Vm,
+    _module: &CompiledModule,
+    frame: &mut Frame,
+    globals: &mut [Value],
+    operands: &JitOperands,
+    pc: usize,
+) -> Result<StepControl, VmError> {
+    let JitOperands::Unary { value, out } = operands else {
+        return Err(VmError::runtime(
+            "jit operand mismatch for error_data".to_owned(),
+        ));
+    };
+    let data = error_data(&frame.get(*value, globals)?);
+    frame.set(*out, data, globals);
+    Ok(StepControl::Next(pc + 1))
+}
+
+fn wrap_error(code: &str, msg: &str, cause: Value) -> Value {
+    Value::Error {
+        code: Arc::from(code),
+        msg: Arc::from(msg),
+        cause: Some(Rc::new(cause)),
+        data: None,
+    }
+}
+
+/// `core::error::cause`: the wrapped cause, or null for unwrapped errors and
+/// non-error values.
+fn error_cause(value: &Value) -> Value {
+    match value {
+        Value::Error {
+            cause: Some(cause), ..
+        } => cause.as_ref().clone(),
+        _ => Value::Null,
+    }
+}
+
+/// `core::error::code`: the code as a string, or null for non-error values.
+fn error_code(value: &Value) -> Value {
+    match value {
+        Value::Error { code, .. } => Value::Str(code.clone()),
+        _ => Value::Null,
+    }
+}
+
+/// `core::error::msg`: the message as a string, or null for non-error values.
+fn error_msg(value: &Value) -> Value {
+    match value {
+        Value::Error { msg, .. } => Value::Str(msg.clone()),
+        _ => Value::Null,
+    }
+}
+
+/// `core::error::data`: the attached data, or null for errors thrown without
+/// `data=` and non-error values.
+fn error_data(value: &Value) -> Value {
+    match value {
+        Value::Error {
+            data: Some(data), ..
+        } => data.as_ref().clone(),
+        _ => Value::Null,
+    }
+}
+
+/// Lists and objects in `value`, nested ones included: what
+/// `core::json::parse` allocated for it. Parsed values hold no cycles.
+fn containers(value: &Value) -> u64 {
+    match value {
+        Value::List(items) => 1 + items.iter().map(containers).sum::<u64>(),
+        Value::Obj(obj) => 1 + obj.borrow().values().map(containers).sum::<u64>(),
+        _ => 0,
+    }
+}
+
+fn step_try_push(
+    _vm: &mut Vm,
+    _module: &CompiledModule,
+    frame: &mut Frame,
+    _globals: &mut [Value],
+    operands: &JitOperands,
+    pc: usize,
+) -> Result<StepControl, VmError> {
+    let JitOperands::TryPush { handler_pc } = operands else {
+        return Err(VmError::runtime(
+            "jit operand mismatch for try_push".to_owned(),
+        ));
+    };
+    frame.try_stack.push(*handler_pc);
+    Ok(StepControl::Next(pc + 1))
+}
+
+fn step_try_pop(
+    _vm: &mut Vm,
+    _module: &CompiledModule,
+    frame: &mut Frame,
+    _globals: &mut [Value],
+    operands: &JitOperands,
+    pc: usize,
+) -> Result<StepControl, VmError> {
+    if !matches!(operands, JitOperands::None) {
+        return Err(VmError::runtime(
+            "jit operand mismatch for try_pop".to_owned(),
+        ));
+    }
+    frame.try_stack.pop();
+    Ok(StepControl::Next(pc + 1))
+}
+
+fn step_obj_entries(
+    vm: &mut Vm,
+    _module: &CompiledModule,
+    frame: &mut Frame,
+    globals: &mut [Value],
+    operands: &JitOperands,
+    pc: usize,
+) -> Result<StepControl, VmError> {
+    let JitOperands::ObjEntries { obj, out, len } = operands else {
+        return Err(VmError::runtime(
+            "jit operand mismatch for obj_entries".to_owned(),
+        ));
+    };
+    let value = frame.get(*obj, globals)?;
+    let Some((entries, count)) = vm.obj_entries(frame, globals, &value)? else {
+        return Ok(StepControl::Next(frame.pc));
+    };
+    if !vm.charge_value(frame, globals, &entries)? {
+        return Ok(StepControl::Next(frame.pc));
+    }
+    frame.set(*out, entries, globals);
+    if let Some(len) = len {
+        frame.set(*len, Value::Num(count_num(count)), globals);
+    }
+    Ok(StepControl::Next(pc + 1))
+}
+
+fn step_obj_new(
+    vm: &mut Vm,
+    _module: &CompiledModule,
+    frame: &mut Frame,
+    globals: &mut [Value],
+    operands: &JitOperands,
+    pc: usize,
+) -> Result<StepControl, VmError> {
+    let JitOperands::UnarySlot { slot } = operands else {
+        return Err(VmError::runtime(
+            "jit operand mismatch for obj_new".to_owned(),
+        ));
+    };
+    if !vm.charge_memory(frame, globals, VALUE_SIZE)? {
+        return Ok(StepControl::Next(frame.pc));
+    }
+    vm.stats.objects_allocated += 1;
+    frame.set(*slot, Value::Obj(ObjRef::default()), globals);
+    Ok(StepControl::Next(pc + 1))
+}
+
+fn step_obj_set(
+    vm: &mut Vm,
+    _module: &CompiledModule,
+    frame: &mut Frame,
+    globals: &mut [Value],
+    operands: &JitOperands,
+    pc: usize,
+) -> Result<StepControl, VmError> {
+    let JitOperands::ObjSet {
+        obj,
+        key,
+        value,
+        out,
+    } = operands
+    else {
+        return Err(VmError::runtime(
+            "jit operand mismatch for obj_set".to_owned(),
+        ));
+    };
+
+    let object = match frame.get(*obj, globals)? {
+        Value::Obj(object) => object,
+        other => {
+            let msg = not_an_object("core::obj::set", &other);
+            return Ok(StepControl::Next(vm.throw_type_error(
+                frame,
+                "type_error",
+                &msg,
+                globals,
+            )?));
+        }
+    };
+    let key_text = value_to_text(&frame.get(*key, globals)?)?;
+    let value = frame.get(*value, globals)?;
+    if !vm.charge_memory(frame, globals, entry_size(&object, &key_text))? {
+        return Ok(StepControl::Next(frame.pc));
+    }
+    object.borrow_mut().insert(key_text, value);
+    frame.set(*out, Value::Obj(object), globals);
+    Ok(StepControl::Next(pc + 1))
+}
+
+fn step_obj_get(
+    vm: &mut Vm,
+    _module: &CompiledModule,
+    frame: &mut Frame,
+    globals: &mut [Value],
+    operands: &JitOperands,
+    pc: usize,
+) -> Result<StepControl, VmError> {
+    let JitOperands::ObjLookup {
+        kind,
+        obj,
+        key,
+        out,
+    } = operands
+    else {
+        return Err(VmError::runtime(
+            "jit operand mismatch for obj_lookup".to_owned(),
+        ));
+    };
+
+    let object = frame.get(*obj, globals)?;
+    let key_text = value_to_text(&frame.get(*key, globals)?)?;
+    let op = match kind {
+        ObjLookupKind::Get => "core::obj::get",
+        ObjLookupKind::Has => "core::obj::has",
+    };
+    let value = match object_lookup(op, &object, &key_text) {
+        Ok(value) => value,
+        Err((code, msg)) => {
+            return Ok(StepControl::Next(
+                vm.throw_type_error(frame, code, &msg, globals)?,
+            ));
+        }
+    };
+    match kind {
+        ObjLookupKind::Get => frame.set(*out, value.unwrap_or(Value::Null), globals),
+        ObjLookupKind::Has => frame.set(*out, Value::Bool(value.is_some()), globals),
+    }
+    Ok(StepControl::Next(pc + 1))
+}
+
+fn step_str(
+    vm: &mut Vm,
+    _module: &CompiledModule,
+    frame: &mut Frame,
+    globals: &mut [Value],
+    operands: &JitOperands,
+    pc: usize,
+) -> Result<StepControl, VmError> {
+    let JitOperands::Str(ops) = operands else {
+        return Err(VmError::runtime(
+            "jit operand mismatch for str op".to_owned(),
+        ));
+    };
+    if vm.str_op(frame, globals, *ops)? {
+        Ok(StepControl::Next(pc + 1))
+    } else {
+        Ok(StepControl::Next(frame.pc))
+    }
+}
+
+fn step_bytes(
+    vm: &mut Vm,
+    _module: &CompiledModule,
+    frame: &mut Frame,
+    globals: &mut [Value],
+    operands: &JitOperands,
+    pc: usize,
+) -> Result<StepControl, VmError> {
+    let JitOperands::Bytes(ops) = operands else {
+        return Err(VmError::runtime(
+            "jit operand mismatch for bytes op".to_owned(),
+        ));
+    };
+    if vm.bytes_op(frame, globals, *ops)? {
+        Ok(StepControl::Next(pc + 1))
+    } else {
+        Ok(StepControl::Next(frame.pc))
+    }
+}
+
+fn step_str_format(
+    vm: &mut Vm,
+    _module: &CompiledModule,
+    frame: &mut Frame,
+    globals: &mut [Value],
+    operands: &JitOperands,
+    pc: usize,
+) -> Result<StepControl, VmError> {
+    let JitOperands::StrFormat { tmpl, args, out } = operands else {
+        return Err(VmError::runtime(
+            "jit operand mismatch for str_format".to_owned(),
+        ));
+    };
+    if vm.str_format(frame, globals, *tmpl, *args, *out)? {
+        Ok(StepControl::Next(pc + 1))
+    } else {
+        Ok(StepControl::Next(frame.pc))
+    }
+}
+
+fn step_list(
+    vm: &mut Vm,
+    _module: &CompiledModule,
+    frame: &mut Frame,
+    globals: &mut [Value],
+    operands: &JitOperands,
+    pc: usize,
+) -> Result<StepControl, VmError> {
+    let JitOperands::List(ops) = operands else {
+        return Err(VmError::runtime(
+            "jit operand mismatch for list op".to_owned(),
+        ));
+    };
+    if vm.list_op(frame, globals, *ops)? {
+        Ok(StepControl::Next(pc + 1))
+    } else {
+        Ok(StepControl::Next(frame.pc))
+    }
+}
+
+fn step_obj_op(
+    vm: &mut Vm,
+    _module: &CompiledModule,
+    frame: &mut Frame,
+    globals: &mut [Value],
+    operands: &JitOperands,
+    pc: usize,
+) -> Result<StepControl, VmError> {
+    let JitOperands::Obj(ops) = operands else {
+        return Err(VmError::runtime(
+            "jit operand mismatch for obj op".to_owned(),
+        ));
+    };
+    if vm.obj_op(frame, globals, *ops)? {
+        Ok(StepControl::Next(pc + 1))
+    } else {
+        Ok(StepControl::Next(frame.pc))
+    }
+}
+
+fn step_obj_path(
+    vm: &mut Vm,
+    _module: &CompiledModule,
+    frame: &mut Frame,
+    globals: &mut [Value],
+    operands: &JitOperands,
+    pc: usize,
+) -> Result<StepControl, VmError> {
+    let JitOperands::ObjPath(ops) = operands else {
+        return Err(VmError::runtime(
+            "jit operand mismatch for obj path op".to_owned(),
+        ));
+    };
+    if vm.obj_path(frame, globals, *ops)? {
+        Ok(StepControl::Next(pc + 1))
+    } else {
+        Ok(StepControl::Next(frame.pc))
+    }
+}
+
+fn step_fn_bind(
+    vm: &mut Vm,
+    _module: &CompiledModule,
+    frame: &mut Frame,
+    globals: &mut [Value],
+    operands: &JitOperands,
+    pc: usize,
+) -> Result<StepControl, VmError> {
+    let JitOperands::Invoke { fn_slot, args, out } = operands else {
+        return Err(VmError::runtime(
+            "jit operand mismatch for fn_bind".to_owned(),
+        ));
+    };
+    let target = frame.get(*fn_slot, globals)?;
+    let mut values = Vec::with_capacity(args.len());
+    for slot in args {
+        values.push(frame.get(*slot, globals)?);
+    }
+    let bound = vm.bind_function(&target, values)?;
+    frame.set(*out, bound, globals);
+    Ok(StepControl::Next(pc + 1))
+}
+
+fn step_make_closure(
+    _vm: &mut Vm,
+    _module: &CompiledModule,
+    frame: &mut Frame,
+    globals: &mut [Value],
+    operands: &JitOperands,
+    pc: usize,
+) -> Result<StepControl, VmError> {
+    let JitOperands::Invoke { fn_slot, args, out } = operands else {
+        return Err(VmError::runtime(
+            "jit operand mismatch for make_closure".to_owned(),
+        ));
+    };
+    let target = frame.get(*fn_slot, globals)?;
+    let mut values = Vec::with_capacity(args.len());
+    for slot in args {
+        values.push(frame.get(*slot, globals)?);
+    }
+    let closure = make_closure(&target, values)?;
+    frame.set(*out, closure, globals);
+    Ok(StepControl::Next(pc + 1))
+}
+
+fn step_neg(
+    _vm: &mut Vm,
+    _module: &CompiledModule,
+    frame: &mut Frame,
+    globals: &mut [Value],
+    operands: &JitOperands,
+    pc: usize,
+) -> Result<StepControl, VmError> {
+    let JitOperands::Unary { value, out } = operands else {
+        return Err(VmError::runtime("jit operand mismatch for neg".to_owned()));
+    };
+    match neg(&frame.get(*value, globals)?)? {
+        Ok(negated) => {
+            frame.set(*out, negated, globals);
+            Ok(StepControl::Next(pc + 1))
+        }
+        Err((code, msg)) => Ok(StepControl::Next(frame.throw(code, &msg, globals)?)),
+    }
+}
+
+fn step_int_parse(
+    _vm: &mut Vm,
+    _module: &CompiledModule,
+    frame: &mut Frame,
+    globals: &mut [Value],
+    operands: &JitOperands,
+    pc: usize,
+) -> Result<StepControl, VmError> {
+    let JitOperands::Unary { value, out } = operands else {
+        return Err(VmError::runtime(
+            "jit operand mismatch for int_parse".to_owned(),
+        ));
+    };
+    match int_parse(&frame.get(*value, globals)?)? {
+        Ok(int) => {
+            frame.set(*out, int, globals);
+            Ok(StepControl::Next(pc + 1))
+        }
+        Err((code, msg)) => Ok(StepControl::Next(frame.throw(code, &msg, globals)?)),
+    }
+}
+
+fn step_type_of(
+    _vm: &mut Vm,
+    _module: &CompiledModule,
+    frame: &mut Frame,
+    globals: &mut [Value],
+    operands: &JitOperands,
+    pc: usize,
+) -> Result<StepControl, VmError> {
+    let JitOperands::Unary { value, out } = operands else {
+        return Err(VmError::runtime(
+            "jit operand mismatch for type_of".to_owned(),
+        ));
+    };
+    let kind = frame.get(*value, globals)?.kind();
+    frame.set(*out, Value::Str(Arc::from(kind.as_str())), globals);
+    Ok(StepControl::Next(pc + 1))
+}
+
+fn step_is_type(
+    _vm: &mut Vm,
+    _module: &CompiledModule,
+    frame: &mut Frame,
+    globals: &mut [Value],
+    operands: &JitOperands,
+    pc: usize,
+) -> Result<StepControl, VmError> {
+    let JitOperands::IsType { value, kind, out } = operands else {
+        return Err(VmError::runtime(
+            "jit operand mismatch for is_type".to_owned(),
+        ));
+    };
+    let holds = frame.get(*value, globals)?.kind() == *kind;
+    frame.set(*out, Value::Bool(holds), globals);
+    Ok(StepControl::Next(pc + 1))
+}
+
+fn step_num_parse(
     _vm: &mut Vm,
     _module: &CompiledModule,
     frame: &mut Frame,
     globals: &mut [Value],
     operands: &JitOperands,
-    _pc: usize,
+    pc: usize,
+) -> Result<StepControl, VmError> {
+    let JitOperands::Unary { value, out } = operands else {
+        return Err(VmError::runtime(
+            "jit operand mismatch for num_parse".to_owned(),
+        ));
+    };
+    match num_parse(&frame.get(*value, globals)?)? {
+        Ok(num) => {
+            frame.set(*out, num, globals);
+            Ok(StepControl::Next(pc + 1))
+        }
+        Err((code, msg)) => Ok(StepControl::Next(frame.throw(code, &msg, globals)?)),
+    }
+}
+
+fn step_num_format(
+    vm: &mut Vm,
+    _module: &CompiledModule,
+    frame: &mut Frame,
+    globals: &mut [Value],
+    operands: &JitOperands,
+    pc: usize,
+) -> Result<StepControl, VmError> {
+    let JitOperands::NumFormat {
+        value,
+        precision,
+        width,
+        out,
+    } = operands
+    else {
+        return Err(VmError::runtime(
+            "jit operand mismatch for num_format".to_owned(),
+        ));
+    };
+    let precision = precision.map(|slot| frame.get(slot, globals)).transpose()?;
+    let width = width.map(|slot| frame.get(slot, globals)).transpose()?;
+    match num_format(&frame.get(*value, globals)?, precision, width)? {
+        Ok(text) => {
+            vm.stats.strings_allocated += 1;
+            frame.set(*out, text, globals);
+            Ok(StepControl::Next(pc + 1))
+        }
+        Err((code, msg)) => Ok(StepControl::Next(frame.throw(code, &msg, globals)?)),
+    }
+}
+
+fn step_fn_meta(
+    vm: &mut Vm,
+    module: &CompiledModule,
+    frame: &mut Frame,
+    globals: &mut [Value],
+    operands: &JitOperands,
+    pc: usize,
+) -> Result<StepControl, VmError> {
+    let JitOperands::Unary { value, out } = operands else {
+        return Err(VmError::runtime(
+            "jit operand mismatch for fn_meta".to_owned(),
+        ));
+    };
+    let meta = vm.fn_meta_value(module, &frame.get(*value, globals)?)?;
+    frame.set(*out, meta, globals);
+    Ok(StepControl::Next(pc + 1))
+}
+
+fn step_host_print(
+    vm: &mut Vm,
+    _module: &CompiledModule,
+    frame: &mut Frame,
+    globals: &mut [Value],
+    operands: &JitOperands,
+    pc: usize,
+) -> Result<StepControl, VmError> {
+    let JitOperands::HostPrint {
+        slot,
+        stream,
+        level,
+    } = operands
+    else {
+        return Err(VmError::runtime(
+            "jit operand mismatch for host_print".to_owned(),
+        ));
+    };
+    if vm.host_print(frame, globals, *slot, *stream, *level)? {
+        Ok(StepControl::Next(pc + 1))
+    } else {
+        Ok(StepControl::Next(frame.pc))
+    }
+}
+
+fn step_host_time(
+    vm: &mut Vm,
+    _module: &CompiledModule,
+    frame: &mut Frame,
+    globals: &mut [Value],
+    operands: &JitOperands,
+    pc: usize,
+) -> Result<StepControl, VmError> {
+    let JitOperands::HostTime { clock, out } = operands else {
+        return Err(VmError::runtime(
+            "jit operand mismatch for host_time".to_owned(),
+        ));
+    };
+    if vm.host_time(frame, globals, *clock, *out)? {
+        Ok(StepControl::Next(pc + 1))
+    } else {
+        Ok(StepControl::Next(frame.pc))
+    }
+}
+
+fn step_host_args(
+    vm: &mut Vm,
+    _module: &CompiledModule,
+    frame: &mut Frame,
+    globals: &mut [Value],
+    operands: &JitOperands,
+    pc: usize,
+) -> Result<StepControl, VmError> {
+    let JitOperands::UnarySlot { slot } = operands else {
+        return Err(VmError::runtime(
+            "jit operand mismatch for host_args".to_owned(),
+        ));
+    };
+    if vm.host_args(frame, globals, *slot)? {
+        Ok(StepControl::Next(pc + 1))
+    } else {
+        Ok(StepControl::Next(frame.pc))
+    }
+}
+
+fn step_host_read_line(
+    vm: &mut Vm,
+    _module: &CompiledModule,
+    frame: &mut Frame,
+    globals: &mut [Value],
+    operands: &JitOperands,
+    pc: usize,
+) -> Result<StepControl, VmError> {
+    let JitOperands::UnarySlot { slot } = operands else {
+        return Err(VmError::runtime(
+            "jit operand mismatch for host_read_line".to_owned(),
+        ));
+    };
+    if vm.host_read(frame, globals, false, *slot)? {
+        Ok(StepControl::Next(pc + 1))
+    } else {
+        Ok(StepControl::Next(frame.pc))
+    }
+}
+
+fn step_host_read_all(
+    vm: &mut Vm,
+    _module: &CompiledModule,
+    frame: &mut Frame,
+    globals: &mut [Value],
+    operands: &JitOperands,
+    pc: usize,
+) -> Result<StepControl, VmError> {
+    let JitOperands::UnarySlot { slot } = operands else {
+        return Err(VmError::runtime(
+            "jit operand mismatch for host_read_all".to_owned(),
+        ));
+    };
+    if vm.host_read(frame, globals, true, *slot)? {
+        Ok(StepControl::Next(pc + 1))
+    } else {
+        Ok(StepControl::Next(frame.pc))
+    }
+}
+
+fn step_host_env_var(
+    vm: &mut Vm,
+    _module: &CompiledModule,
+    frame: &mut Frame,
+    globals: &mut [Value],
+    operands: &JitOperands,
+    pc: usize,
+) -> Result<StepControl, VmError> {
+    let JitOperands::Unary { value, out } = operands else {
+        return Err(VmError::runtime(
+            "jit operand mismatch for host_env_var".to_owned(),
+        ));
+    };
+    if vm.host_env_var(frame, globals, *value, *out)? {
+        Ok(StepControl::Next(pc + 1))
+    } else {
+        Ok(StepControl::Next(frame.pc))
+    }
+}
+
+fn step_host_random(
+    vm: &mut Vm,
+    _module: &CompiledModule,
+    frame: &mut Frame,
+    globals: &mut [Value],
+    operands: &JitOperands,
+    pc: usize,
+) -> Result<StepControl, VmError> {
+    let JitOperands::HostRandom { range, out } = operands else {
+        return Err(VmError::runtime(
+            "jit operand mismatch for host_random".to_owned(),
+        ));
+    };
+    if vm.host_random(frame, globals, *range, *out)? {
+        Ok(StepControl::Next(pc + 1))
+    } else {
+        Ok(StepControl::Next(frame.pc))
+    }
+}
+
+fn step_host_fs(
+    vm: &mut Vm,
+    _module: &CompiledModule,
+    frame: &mut Frame,
+    globals: &mut [Value],
+    operands: &JitOperands,
+    pc: usize,
+) -> Result<StepControl, VmError> {
+    let JitOperands::Fs(ops) = operands else {
+        return Err(VmError::runtime(
+            "jit operand mismatch for host_fs".to_owned(),
+        ));
+    };
+    if vm.host_fs(frame, globals, *ops)? {
+        Ok(StepControl::Next(pc + 1))
+    } else {
+        Ok(StepControl::Next(frame.pc))
+    }
+}
+
+fn step_json_parse(
+    vm: &mut Vm,
+    _module: &CompiledModule,
+    frame: &mut Frame,
+    globals: &mut [Value],
+    operands: &JitOperands,
+    pc: usize,
+) -> Result<StepControl, VmError> {
+    let JitOperands::Unary { value, out } = operands else {
+        return Err(VmError::runtime(
+            "jit operand mismatch for json_parse".to_owned(),
+        ));
+    };
+    if vm.json_op(frame, globals, true, *value, *out)? {
+        Ok(StepControl::Next(pc + 1))
+    } else {
+        Ok(StepControl::Next(frame.pc))
+    }
+}
+
+fn step_json_stringify(
+    vm: &mut Vm,
+    _module: &CompiledModule,
+    frame: &mut Frame,
+    globals: &mut [Value],
+    operands: &JitOperands,
+    pc: usize,
 ) -> Result<StepControl, VmError> {
-    let JitOperands::Branch {
-        cond,
-        then_pc,
-        else_pc,
-    } = operands
-    else {
-        return Err(VmError::Runtime(
-            "jit operand mismatch for branch".to_owned(),
+    let JitOperands::Unary { value, out } = operands else {
+        return Err(VmError::runtime(
+            "jit operand mismatch for json_stringify".to_owned(),
+        ));
+    };
+    if vm.json_op(frame, globals, false, *value, *out)? {
+        Ok(StepControl::Next(pc + 1))
+    } else {
+        Ok(StepControl::Next(frame.pc))
+    }
+}
+
+fn step_ext(
+    vm: &mut Vm,
+    _module: &CompiledModule,
+    frame: &mut Frame,
+    globals: &mut [Value],
+    operands: &JitOperands,
+    pc: usize,
+) -> Result<StepControl, VmError> {
+    let JitOperands::Ext { op, operands } = operands else {
+        return Err(VmError::runtime("jit operand mismatch for ext".to_owned()));
+    };
+    if vm.run_ext(frame, globals, *op, operands)? {
+        Ok(StepControl::Next(pc + 1))
+    } else {
+        Ok(StepControl::Next(frame.pc))
+    }
+}
+
+fn step_host_call(
+    vm: &mut Vm,
+    _module: &CompiledModule,
+    frame: &mut Frame,
+    globals: &mut [Value],
+    operands: &JitOperands,
+    pc: usize,
+) -> Result<StepControl, VmError> {
+    let JitOperands::HostCall { name, args, out } = operands else {
+        return Err(VmError::runtime(
+            "jit operand mismatch for host_call".to_owned(),
+        ));
+    };
+    if vm.host_call(frame, globals, name, args, *out)? {
+        Ok(StepControl::Next(pc + 1))
+    } else {
+        Ok(StepControl::Next(frame.pc))
+    }
+}
+
+#[derive(Clone)]
+struct ShadowRun {
+    result: Result<Vec<Value>, VmError>,
+    globals: Vec<Value>,
+    pc: usize,
+}
+
+fn describe_divergence(
+    jit: &Result<Vec<Value>, VmError>,
+    jit_globals: &[Value],
+    shadow: &ShadowRun,
+) -> Option<String> {
+    let outcome = match (jit, &shadow.result) {
+        (Ok(jit_returns), Ok(interp_returns)) if jit_returns != interp_returns => Some(format!(
+            "returns differ (jit {jit_returns:?}, interpreter {interp_returns:?} at pc {})",
+            shadow.pc
+        )),
+        (Err(jit_err), Err(interp_err)) if jit_err.to_string() != interp_err.to_string() => {
+            Some(format!(
+                "errors differ (jit '{jit_err}', interpreter '{interp_err}' at pc {})",
+                shadow.pc
+            ))
+        }
+        (Ok(_), Ok(_)) | (Err(_), Err(_)) => None,
+        (Ok(_), Err(interp_err)) => Some(format!(
+            "interpreter failed with '{interp_err}' at pc {} but jit succeeded",
+            shadow.pc
+        )),
+        (Err(jit_err), Ok(_)) => Some(format!(
+            "jit failed with '{jit_err}' but interpreter succeeded"
+        )),
+    };
+    outcome.or_else(|| {
+        jit_globals
+            .iter()
+            .zip(&shadow.globals)
+            .position(|(jit, interp)| jit != interp)
+            .map(|slot| {
+                format!(
+                    "global slot {slot} differs (jit {:?}, interpreter {:?})",
+                    jit_globals[slot], shadow.globals[slot]
+                )
+            })
+    })
+}
+
+/// Exported globals of an initialized module, plus its constants.
+fn module_exports(module: &CompiledModule, globals: &[Value]) -> HashMap<String, Value> {
+    let mut exports = HashMap::new();
+    for (name, slot) in &module.exports {
+        exports.insert(name.clone(), globals[*slot as usize].clone());
+    }
+    for (name, value) in &module.consts {
+        exports
+            .entry(name.clone())
+            .or_insert_with(|| Value::from_const(value));
+    }
+    exports
+}
+
+/// Globals whose value differs between `before` and `after`, named from
+/// `module.global_names`.
+fn global_deltas(module: &CompiledModule, before: &[Value], after: &[Value]) -> Vec<GlobalDelta> {
+    after
+        .iter()
+        .enumerate()
+        .filter_map(|(slot, value)| {
+            let previous = before.get(slot).unwrap_or(&Value::Null);
+            (previous != value).then(|| GlobalDelta {
+                name: match module.global_names.get(slot) {
+                    Some(name) if !name.is_empty() => name.clone(),
+                    _ => format!("#{slot}"),
+                },
+                before: previous.clone(),
+                after: value.clone(),
+            })
+        })
+        .collect()
+}
+
+/// `key` of `object` for `op`, or the `type_error` to throw when `object`
+/// is not an object.
+fn object_lookup(
+    op: &str,
+    object: &Value,
+    key: &str,
+) -> Result<Option<Value>, (&'static str, String)> {
+    match object {
+        Value::Obj(obj) => Ok(obj.borrow().get(key).cloned()),
+        other => Err(("type_error", not_an_object(op, other))),
+    }
+}
+
+fn not_an_object(op: &str, found: &Value) -> String {
+    format!("{op} target is not an object (got {})", found.type_name())
+}
+
+/// Size charged against `VmConfig.max_alloc_bytes` and `max_memory` for a
+/// freshly made value: string and byte lengths, plus one value per list
+/// item or object entry. Each object is counted once, so shared and
+/// self-referencing objects don't recurse forever.
+fn heap_size(value: &Value) -> usize {
+    heap_size_with(value, &mut HashSet::new())
+}
+
+fn heap_size_with(value: &Value, seen: &mut HashSet<usize>) -> usize {
+    match value {
+        Value::Str(text) => text.len(),
+        Value::Bytes(bytes) => bytes.len(),
+        Value::List(items) => items
+            .iter()
+            .map(|item| VALUE_SIZE + heap_size_with(item, seen))
+            .sum(),
+        Value::Obj(obj) => {
+            if !seen.insert(obj.addr()) {
+                return 0;
+            }
+            obj.borrow()
+                .iter()
+                .map(|(key, value)| key.len() + VALUE_SIZE + heap_size_with(value, seen))
+                .sum()
+        }
+        _ => 0,
+    }
+}
+
+/// Size `core::obj::set` charges for storing `key`: nothing when it
+/// replaces an existing entry.
+fn entry_size(obj: &ObjRef, key: &str) -> usize {
+    if obj.borrow().contains_key(key) {
+        0
+    } else {
+        key.len() + VALUE_SIZE
+    }
+}
+
+/// `core::obj::getpath`: the value at the dotted `path` under `object`,
+/// null for a missing key. An empty path is `object` itself.
+fn get_path(object: &Value, path: &str, safe: bool) -> Result<Value, String> {
+    if path.is_empty() {
+        return Ok(object.clone());
+    }
+    let mut current = object.clone();
+    for (depth, key) in path.split('.').enumerate() {
+        current = match current {
+            Value::Obj(obj) => match obj.borrow().get(key) {
+                Some(value) => value.clone(),
+                None => return Ok(Value::Null),
+            },
+            _ if safe => return Ok(Value::Null),
+            other => return Err(not_an_object_at("core::obj::getpath", path, depth, &other)),
+        };
+    }
+    Ok(current)
+}
+
+/// `core::obj::setpath`: stores `value` at the dotted `path` under
+/// `object`, in place, creating objects for missing keys (and, when `safe`,
+/// for nulls). Returns the object, a new one if `object` itself was a
+/// replaced null, and how many objects it created. An empty path gives
+/// `value` itself.
+fn set_path(object: Value, path: &str, value: Value, safe: bool) -> Result<(Value, u64), String> {
+    if path.is_empty() {
+        return Ok((value, 0));
+    }
+    let mut created = 0;
+    let mut level = |found: Option<Value>, depth: usize| {
+        match found {
+            Some(Value::Obj(obj)) => return Ok(obj),
+            None => {}
+            Some(Value::Null) if safe => {}
+            Some(other) => return Err(not_an_object_at("core::obj::setpath", path, depth, &other)),
+        }
+        created += 1;
+        Ok(ObjRef::default())
+    };
+    let root = level(Some(object), 0)?;
+    let mut current = root.clone();
+    let mut keys = path.split('.').enumerate().peekable();
+    while let Some((depth, key)) = keys.next() {
+        if keys.peek().is_none() {
+            current.borrow_mut().insert(key.to_owned(), value);
+            break;
+        }
+        let found = current.borrow().get(key).cloned();
+        let next = level(found, depth + 1)?;
+        current
+            .borrow_mut()
+            .insert(key.to_owned(), Value::Obj(next.clone()));
+        current = next;
+    }
+    Ok((Value::Obj(root), created))
+}
+
+/// The `type_error` message for a path walk that met `found` after
+/// following `depth` keys.
+fn not_an_object_at(op: &str, path: &str, depth: usize, found: &Value) -> String {
+    let walked = path.split('.').take(depth).collect::<Vec<_>>().join(".");
+    if walked.is_empty() {
+        not_an_object(op, found)
+    } else {
+        format!(
+            "{op} '{walked}' is not an object (got {})",
+            found.type_name()
+        )
+    }
+}
+
+/// `core::fn::lambda`: closes the function value `target` over `captures`.
+fn make_closure(target: &Value, captures: Vec<Value>) -> Result<Value, VmError> {
+    let Value::Func(func) = target else {
+        return Err(VmError::runtime(format!(
+            "core::fn::lambda expects a function, found {}",
+            target.type_name()
+        )));
+    };
+    Ok(Value::Closure {
+        func: *func,
+        captures: captures.into(),
+    })
+}
+
+/// The function an invoke in `frame` calls, or the `not_callable` to throw
+/// for a non-function. A closure's captures are put ahead of `args`.
+fn invoke_target(
+    frame: &Frame,
+    target: &Value,
+    args: &mut Vec<Value>,
+) -> Result<FuncId, (&'static str, String)> {
+    match target {
+        Value::Func(func) => Ok(*func),
+        Value::Closure { func, captures } => {
+            args.splice(0..0, captures.iter().cloned());
+            Ok(*func)
+        }
+        _ => Err((
+            "not_callable",
+            format!(
+                "invoke target is not a function (got {}) in {}",
+                target.type_name(),
+                frame.meta.name
+            ),
+        )),
+    }
+}
+
+/// `core::add`/`sub`/`mul`/`div`/`mod`/`pow`, or the `(code, msg)` to
+/// throw. Two integers give an exact integer (division truncates, and a
+/// negative power falls back to a num); any other pair of numbers is
+/// computed in floating point.
+fn arith(
+    op: BinaryOp,
+    a: &Value,
+    b: &Value,
+) -> Result<Result<Value, (&'static str, String)>, VmError> {
+    if let (Value::Int(a), Value::Int(b)) = (a, b) {
+        if matches!(op, BinaryOp::Div | BinaryOp::Mod) && *b == 0 {
+            return Ok(Err(("div_zero", "division by zero".to_owned())));
+        }
+        let (result, sign) = match op {
+            BinaryOp::Add => (a.checked_add(*b), "+"),
+            BinaryOp::Sub => (a.checked_sub(*b), "-"),
+            BinaryOp::Mul => (a.checked_mul(*b), "*"),
+            BinaryOp::Div => (a.checked_div(*b), "/"),
+            BinaryOp::Mod => (a.checked_rem(*b), "%"),
+            _ if *b < 0 => return Ok(Ok(Value::Num(int_num(*a).powf(int_num(*b))))),
+            _ => (
+                u32::try_from(*b).ok().and_then(|exp| a.checked_pow(exp)),
+                "**",
+            ),
+        };
+        return Ok(result
+            .map(Value::Int)
+            .ok_or_else(|| ("int_overflow", format!("{a} {sign} {b} overflows int"))));
+    }
+    let (a, b) = (a.as_num()?, b.as_num()?);
+    Ok(match op {
+        BinaryOp::Add => Ok(Value::Num(a + b)),
+        BinaryOp::Sub => Ok(Value::Num(a - b)),
+        BinaryOp::Mul => Ok(Value::Num(a * b)),
+        BinaryOp::Pow => Ok(Value::Num(a.powf(b))),
+        _ if b == 0.0 => Err(("div_zero", "division by zero".to_owned())),
+        BinaryOp::Mod => Ok(Value::Num(a % b)),
+        _ => Ok(Value::Num(a / b)),
+    })
+}
+
+/// `core::neg`, or the `(code, msg)` to throw for the lowest int.
+fn neg(value: &Value) -> Result<Result<Value, (&'static str, String)>, VmError> {
+    Ok(match value {
+        Value::Int(int) => int
+            .checked_neg()
+            .map(Value::Int)
+            .ok_or_else(|| ("int_overflow", format!("-({int}) overflows int"))),
+        other => Ok(Value::Num(-other.as_num()?)),
+    })
+}
+
+/// `core::int::parse`, or the `(code, msg)` to throw.
+fn int_parse(value: &Value) -> Result<Result<Value, (&'static str, String)>, VmError> {
+    let parsed = match value {
+        Value::Int(int) => Some(*int),
+        Value::Num(num) => whole_int(*num),
+        Value::Str(text) => text.trim().parse().ok(),
+        other => {
+            return Err(VmError::runtime(format!(
+                "core::int::parse expects str, num or int, got {}",
+                other.type_name()
+            )));
+        }
+    };
+    Ok(parsed
+        .map(Value::Int)
+        .ok_or_else(|| ("int_parse", format!("{value} is not an integer"))))
+}
+
+/// `core::num::parse`, or the `(code, msg)` to throw. Strings that parse to
+/// infinity or NaN are rejected like any other non-number.
+fn num_parse(value: &Value) -> Result<Result<Value, (&'static str, String)>, VmError> {
+    let parsed = match value {
+        Value::Num(num) => Some(*num),
+        Value::Int(int) => Some(int_num(*int)),
+        Value::Str(text) => text
+            .trim()
+            .parse::<f64>()
+            .ok()
+            .filter(|num| num.is_finite()),
+        other => {
+            return Err(VmError::runtime(format!(
+                "core::num::parse expects str, num or int, got {}",
+                other.type_name()
+            )));
+        }
+    };
+    Ok(parsed
+        .map(Value::Num)
+        .ok_or_else(|| ("parse_error", format!("{value} is not a number"))))
+}
+
+/// Largest `precision` `core::num::format` accepts.
+const MAX_FORMAT_PRECISION: usize = 100;
+/// Largest `width` `core::num::format` accepts.
+const MAX_FORMAT_WIDTH: usize = 1024;
+
+/// `core::num::format`, or the `(code, msg)` to throw. Without `precision`
+/// the number prints as `core::host::print` would; an int keeps every digit.
+fn num_format(
+    value: &Value,
+    precision: Option<Value>,
+    width: Option<Value>,
+) -> Result<Result<Value, (&'static str, String)>, VmError> {
+    let bound = |name: &str, arg: Option<Value>, max: usize| {
+        let Some(arg) = arg else {
+            return Ok(None);
+        };
+        let bound = match &arg {
+            Value::Int(n) => usize::try_from(*n).ok().filter(|n| *n <= max),
+            Value::Num(n) => whole_usize(*n, max),
+            _ => None,
+        };
+        bound.map(Some).ok_or_else(|| {
+            (
+                "format_arg",
+                format!("{name} must be a whole number from 0 to {max}, got {arg}"),
+            )
+        })
+    };
+    let precision = match bound("precision", precision, MAX_FORMAT_PRECISION) {
+        Ok(precision) => precision,
+        Err(thrown) => return Ok(Err(thrown)),
+    };
+    let width = match bound("width", width, MAX_FORMAT_WIDTH) {
+        Ok(width) => width.unwrap_or(0),
+        Err(thrown) => return Ok(Err(thrown)),
+    };
+    let text = match (value, precision) {
+        (Value::Int(int), Some(digits)) if digits > 0 => format!("{int}.{:0<digits$}", ""),
+        (Value::Int(int), _) => int.to_string(),
+        (Value::Num(num), Some(digits)) => format!("{num:.digits$}"),
+        (Value::Num(num), None) => num.to_string(),
+        other => {
+            return Err(VmError::runtime(format!(
+                "core::num::format expects num or int, got {}",
+                other.0.type_name()
+            )));
+        }
+    };
+    Ok(Ok(Value::Str(format!("{text:>width$}").into())))
+}
+
+/// `core::eq`: structural, except that an int and a num are equal when
+/// they are numerically equal.
+fn values_eq(a: &Value, b: &Value) -> bool {
+    match (a, b) {
+        (Value::Int(int), Value::Num(num)) | (Value::Num(num), Value::Int(int)) => {
+            cmp_int_num(*int, *num).is_some_and(Ordering::is_eq)
+        }
+        _ => a == b,
+    }
+}
+
+/// `core::lt`/`le`/`gt`/`ge`: strings and bytes compare by byte, anything
+/// else as numbers (an int and a num exactly, without rounding the int),
+/// and every ordering involving NaN is false.
+fn compare(op: BinaryOp, a: &Value, b: &Value) -> Result<bool, VmError> {
+    let ordering = match (a, b) {
+        (Value::Int(a), Value::Int(b)) => Some(a.cmp(b)),
+        (Value::Int(a), Value::Num(b)) => cmp_int_num(*a, *b),
+        (Value::Num(a), Value::Int(b)) => cmp_int_num(*b, *a).map(Ordering::reverse),
+        (Value::Str(_), Value::Str(_)) | (Value::Bytes(_), Value::Bytes(_)) => Some(a.total_cmp(b)),
+        _ => a.as_num()?.partial_cmp(&b.as_num()?),
+    };
+    let Some(ordering) = ordering else {
+        return Ok(false);
+    };
+    Ok(match op {
+        BinaryOp::Lt => ordering.is_lt(),
+        BinaryOp::Le => ordering.is_le(),
+        BinaryOp::Gt => ordering.is_gt(),
+        _ => ordering.is_ge(),
+    })
+}
+
+fn not_a_list(kind: ListOpKind, found: &Value) -> String {
+    let op = match kind {
+        ListOpKind::New => "new",
+        ListOpKind::Push => "push",
+        ListOpKind::Get => "get",
+        ListOpKind::Set => "set",
+        ListOpKind::Len => "len",
+    };
+    format!("core::list::{op} expects list, found {}", found.type_name())
+}
+
+/// The numeric order of `int` and `num`, exact for every int; `None` when
+/// `num` is NaN.
+fn cmp_int_num(int: i64, num: f64) -> Option<Ordering> {
+    const LIMIT: f64 = 9_223_372_036_854_775_808.0;
+    if num.is_nan() {
+        return None;
+    }
+    if num >= LIMIT {
+        return Some(Ordering::Less);
+    }
+    if num < -LIMIT {
+        return Some(Ordering::Greater);
+    }
+    let whole = num.trunc();
+    // Whole and within the range of `i64`, so the cast is exact.
+    #[allow(clippy::cast_possible_truncation)]
+    let whole_int = whole as i64;
+    Some(
+        int.cmp(&whole_int)
+            .then_with(|| 0.0.partial_cmp(&(num - whole)).unwrap_or(Ordering::Equal)),
+    )
+}
+
+/// A `core::host::random_int` bound: `Ok(None)` for a num that is not a
+/// whole `i64`, `Err` with the value itself when it is not a number.
+fn random_bound(value: &Value) -> Result<Option<i64>, &Value> {
+    match value {
+        Value::Int(int) => Ok(Some(*int)),
+        Value::Num(num) => Ok(whole_int(*num)),
+        other => Err(other),
+    }
+}
+
+/// Whole-number index below `len`.
+fn list_index(index: f64, len: usize) -> Option<usize> {
+    whole_usize(index, len.checked_sub(1)?)
+}
+
+/// `num` as an index when it is whole and within `0..=max`.
+fn whole_usize(num: f64, max: usize) -> Option<usize> {
+    // Only returned when whole and in range, where the cast is exact.
+    #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+    let index = num as usize;
+    (num.fract() == 0.0 && (0.0..=count_num(max)).contains(&num)).then_some(index)
+}
+
+/// `num` as an int when it is whole and below 2^63 in magnitude, where
+/// every such `f64` converts exactly.
+fn whole_int(num: f64) -> Option<i64> {
+    #[allow(clippy::cast_possible_truncation)]
+    let int = num as i64;
+    (num.fract() == 0.0 && num.abs() < 9_223_372_036_854_775_808.0).then_some(int)
+}
+
+/// A length or count as a script number; no collection in memory is long
+/// enough to round.
+#[allow(clippy::cast_precision_loss)]
+fn count_num(count: usize) -> f64 {
+    count as f64
+}
+
+/// An int as a script number, rounding past 2^53 as mixed arithmetic does.
+#[allow(clippy::cast_precision_loss)]
+fn int_num(int: i64) -> f64 {
+    int as f64
+}
+
+fn expect_bytes<'a>(op: &str, value: &'a Value) -> Result<&'a [u8], VmError> {
+    match value {
+        Value::Bytes(bytes) => Ok(bytes),
+        other => Err(bytes_type_error(op, "bytes", other)),
+    }
+}
+
+fn bytes_type_error(op: &str, expected: &str, found: &Value) -> VmError {
+    VmError::runtime(format!(
+        "core::bytes::{op} expects {expected}, found {}",
+        found.type_name()
+    ))
+}
+
+/// Whole-number `start..end` within `0..=len`.
+fn byte_range(start: f64, end: f64, len: usize) -> Option<std::ops::Range<usize>> {
+    let whole = |n: f64| whole_usize(n, len);
+    let (start, end) = (whole(start)?, whole(end)?);
+    (start <= end).then_some(start..end)
+}
+
+/// `value` as text for `op`, or the `type_error` to throw for a list,
+/// object, function, bytes or handle.
+fn operand_text(op: &str, value: &Value) -> Result<String, (&'static str, String)> {
+    value_to_text(value).map_err(|_| {
+        (
+            "type_error",
+            format!("{op} expects text or a scalar, found {}", value.type_name()),
+        )
+    })
+}
+
+fn value_to_text(value: &Value) -> Result<String, VmError> {
+    match value {
+        Value::Null => Ok("null".to_owned()),
+        Value::Bool(v) => Ok(v.to_string()),
+        Value::Num(v) => Ok(v.to_string()),
+        Value::Int(v) => Ok(v.to_string()),
+        Value::Str(v) => Ok(v.to_string()),
+        Value::Error { .. } => Ok(value.to_string()),
+        Value::Bytes(_)
+        | Value::List(_)
+        | Value::Obj(_)
+        | Value::Func(_)
+        | Value::Closure { .. }
+        | Value::Handle(_) => Err(VmError::runtime(
+            "cannot convert complex value to string".to_owned(),
+        )),
+    }
+}
+
+fn validate_retshape(meta: &FnMeta, values: &[Value]) -> Result<(), VmError> {
+    match &meta.retshape {
+        RetShape::Scalar => {
+            if values.len() != 1 {
+                return Err(VmError::runtime(format!(
+                    "{} expects scalar return with 1 slot, got {}",
+                    meta.name,
+                    values.len()
+                )));
+            }
+        }
+        RetShape::Either(allowed) => {
+            if values.len() != 1 {
+                return Err(VmError::runtime(format!(
+                    "{} expects single either slot",
+                    meta.name
+                )));
+            }
+            if let Value::Str(value) = &values[0]
+                && allowed.iter().any(|item| item == value.as_ref())
+            {
+                return Ok(());
+            }
+            return Err(VmError::runtime(format!(
+                "{} return is not in either(...) set",
+                meta.name
+            )));
+        }
+        RetShape::Record(fields) => {
+            if values.len() != 1 {
+                return Err(VmError::runtime(format!(
+                    "{} expects single record slot",
+                    meta.name
+                )));
+            }
+            let Value::Obj(obj) = &values[0] else {
+                return Err(VmError::runtime(format!(
+                    "{} return is not an object for record shape",
+                    meta.name
+                )));
+            };
+            for field in fields {
+                if !obj.borrow().contains_key(field) {
+                    return Err(VmError::runtime(format!(
+                        "{} missing record field '{field}'",
+                        meta.name
+                    )));
+                }
+            }
+        }
+        RetShape::Any => {}
+    }
+    Ok(())
+}
+
+#[derive(Debug, Clone)]
+struct Frame {
+    code: Arc<[Instr]>,
+    pc: usize,
+    /// Register file laid out by `layout`.
+    regs: Vec<Value>,
+    layout: RegLayout,
+    try_stack: Vec<usize>,
+    meta: FnMeta,
+    throws: u64,
+    deadline: Option<Deadline>,
+    /// `Profiler` slot, from the frame's first instruction to its return.
+    profile_slot: Option<usize>,
+}
+
+/// End of a `@timeout` function's invocation; its callees inherit it.
+#[derive(Debug, Clone)]
+struct Deadline {
+    at: Instant,
+    function: Arc<str>,
+    ms: u64,
+}
+
+impl Frame {
+    fn new(function: &CompiledFunction, args: &[Value]) -> Self {
+        let layout = RegLayout::of(function);
+        let mut regs = vec![Value::Null; layout.rets + function.ret_count as usize];
+        for (reg, value) in regs[layout.args..layout.errs].iter_mut().zip(args) {
+            *reg = value.clone();
+        }
+
+        Self {
+            code: Arc::clone(&function.code),
+            pc: 0,
+            regs,
+            layout,
+            try_stack: Vec::new(),
+            meta: function.meta.clone(),
+            throws: 0,
+            deadline: None,
+            profile_slot: None,
+        }
+    }
+
+    /// Every local, argument, error and return slot, in that order.
+    fn slots(&self) -> Vec<(Slot, Value)> {
+        self.regs
+            .iter()
+            .enumerate()
+            .map(|(reg, value)| (self.layout.slot_at(reg), value.clone()))
+            .collect()
+    }
+
+    fn get(&self, slot: Slot, globals: &[Value]) -> Result<Value, VmError> {
+        // Locals sit at the front of the file, so they index directly.
+        if let Slot::Local(index) = slot
+            && let Some(value) = self.regs[..self.layout.args].get(index as usize)
+        {
+            return Ok(value.clone());
+        }
+        self.load(self.layout.resolve(slot), globals)
+    }
+
+    fn set(&mut self, slot: Slot, value: Value, globals: &mut [Value]) {
+        if let Slot::Local(index) = slot
+            && let Some(local) = self.regs[..self.layout.args].get_mut(index as usize)
+        {
+            *local = value;
+            return;
+        }
+        self.store(self.layout.resolve(slot), value, globals);
+    }
+
+    fn load(&self, reg: Reg, globals: &[Value]) -> Result<Value, VmError> {
+        match reg {
+            Reg::Frame(index) => self
+                .regs
+                .get(index)
+                .cloned()
+                .ok_or_else(|| slot_out_of_range(self.layout.slot_at(index))),
+            Reg::Global(index) => globals.get(index).cloned().ok_or_else(|| {
+                slot_out_of_range(Slot::Global(u32::try_from(index).unwrap_or(u32::MAX)))
+            }),
+            Reg::Missing(slot) => Err(slot_out_of_range(slot)),
+        }
+    }
+
+    /// Stores into `reg`; out-of-range globals are dropped and return slots
+    /// past `ret_count` grow the register file.
+    fn store(&mut self, reg: Reg, value: Value, globals: &mut [Value]) {
+        match reg {
+            Reg::Frame(index) => {
+                if index >= self.regs.len() {
+                    self.regs.resize(index + 1, Value::Null);
+                }
+                self.regs[index] = value;
+            }
+            Reg::Global(index) => {
+                if let Some(global) = globals.get_mut(index) {
+                    *global = value;
+                }
+            }
+            Reg::Missing(_) => {}
+        }
+    }
+
+    fn set_ret(&mut self, index: usize, value: Value) {
+        self.store(Reg::Frame(self.layout.rets + index), value, &mut []);
+    }
+
+    fn returns(&self) -> &[Value] {
+        &self.regs[self.layout.rets..]
+    }
+
+    fn take_returns(&mut self) -> Vec<Value> {
+        self.regs.split_off(self.layout.rets)
+    }
+
+    /// Routes a throw to the innermost handler, returning the handler pc, or
+    /// propagates it as `VmError::Thrown` when no handler is active.
+    fn throw(&mut self, code: &str, msg: &str, globals: &mut [Value]) -> Result<usize, VmError> {
+        self.throw_error(Arc::from(code), Arc::from(msg), None, None, globals)
+    }
+
+    fn throw_error(
+        &mut self,
+        code: Arc<str>,
+        msg: Arc<str>,
+        cause: Option<Rc<Value>>,
+        data: Option<Rc<Value>>,
+        globals: &mut [Value],
+    ) -> Result<usize, VmError> {
+        self.throws += 1;
+        self.raise(code, msg, cause, data, globals)?;
+        Ok(self.pc)
+    }
+
+    /// Delivers an error raised by a callee to this frame's innermost
+    /// handler; errors other than throws pass through unchanged.
+    fn catch(&mut self, err: VmError, globals: &mut [Value]) -> Result<(), VmError> {
+        match err {
+            // Unhandled: pass it on as is, keeping its traceback.
+            VmError::Thrown { .. } if self.try_stack.is_empty() => Err(err),
+            VmError::Thrown {
+                code,
+                msg,
+                cause,
+                data,
+                ..
+            } => self.raise(code, msg, cause, data, globals),
+            other => Err(other),
+        }
+    }
+
+    fn throw_denied(&mut self, op: &str, globals: &mut [Value]) -> Result<usize, VmError> {
+        self.throw(
+            "cap_denied",
+            &format!("host op '{op}' denied by policy"),
+            globals,
+        )
+    }
+
+    fn throw_incapable(
+        &mut self,
+        op: &str,
+        capability: Capability,
+        globals: &mut [Value],
+    ) -> Result<usize, VmError> {
+        self.throw(
+            "cap_denied",
+            &format!("host op '{op}' needs the {capability} capability"),
+            globals,
+        )
+    }
+
+    fn raise(
+        &mut self,
+        code: Arc<str>,
+        msg: Arc<str>,
+        cause: Option<Rc<Value>>,
+        data: Option<Rc<Value>>,
+        globals: &mut [Value],
+    ) -> Result<(), VmError> {
+        let Some(handler_pc) = self.try_stack.pop() else {
+            return Err(VmError::Thrown {
+                code,
+                msg,
+                cause,
+                data,
+                traceback: Vec::new(),
+            });
+        };
+        let err = Value::Error {
+            code,
+            msg,
+            cause,
+            data,
+        };
+        self.set(Slot::Err(0), err, globals);
+        self.pc = handler_pc;
+        Ok(())
+    }
+}
+
+/// Where each slot kind starts in `Frame::regs`. Return slots come last so
+/// stores past `ret_count` can grow the file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct RegLayout {
+    args: usize,
+    errs: usize,
+    rets: usize,
+}
+
+impl RegLayout {
+    fn of(function: &CompiledFunction) -> Self {
+        let args = function.local_count as usize;
+        let errs = args + function.arg_count as usize;
+        let rets = errs + function.err_count.max(1) as usize;
+        Self { args, errs, rets }
+    }
+
+    fn resolve(self, slot: Slot) -> Reg {
+        let (base, end, index) = match slot {
+            Slot::Global(index) => return Reg::Global(index as usize),
+            Slot::Local(index) => (0, self.args, index),
+            Slot::Arg(index) => (self.args, self.errs, index),
+            Slot::Err(index) => (self.errs, self.rets, index),
+            Slot::Ret(index) => (self.rets, usize::MAX, index),
+        };
+        let reg = base.saturating_add(index as usize);
+        if reg < end {
+            Reg::Frame(reg)
+        } else {
+            Reg::Missing(slot)
+        }
+    }
+
+    fn slot_at(self, reg: usize) -> Slot {
+        // Every register came from a `u32` slot index, so each offset fits.
+        let offset = |base: usize| u32::try_from(reg - base).unwrap_or(u32::MAX);
+        if reg >= self.rets {
+            Slot::Ret(offset(self.rets))
+        } else if reg >= self.errs {
+            Slot::Err(offset(self.errs))
+        } else if reg >= self.args {
+            Slot::Arg(offset(self.args))
+        } else {
+            Slot::Local(offset(0))
+        }
+    }
+}
+
+/// A slot resolved against a function's `RegLayout`, so hot paths index
+/// straight into the register file.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Reg {
+    Frame(usize),
+    Global(usize),
+    /// Outside the function's layout; reads fail as the slot would.
+    Missing(Slot),
+}
+
+fn slot_out_of_range(slot: Slot) -> VmError {
+    let (kind, index) = match slot {
+        Slot::Local(index) => ("local", index),
+        Slot::Global(index) => ("global", index),
+        Slot::Arg(index) => ("arg", index),
+        Slot::Ret(index) => ("ret", index),
+        Slot::Err(index) => ("err", index),
+    };
+    VmError::runtime(format!("{kind} slot {index} out of range"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use imp_compiler::{CompileOpts, FsModuleLoader, compile_module, compile_program};
+    use imp_ir::{
+        CompiledFunction, CompiledModule, ConstValue, FnMeta, ImportBinding, Instr, RetShape, Slot,
+    };
+    use std::fs;
+    use std::path::PathBuf;
+
+    fn scalar_meta(name: &str) -> FnMeta {
+        FnMeta {
+            name: Arc::from(name),
+            params: Vec::new(),
+            arg_count: 0,
+            ret_count: 1,
+            retshape: RetShape::Scalar,
+            timeout_ms: None,
+            debug: None,
+        }
+    }
+
+    #[test]
+    fn executes_add_and_return_jit() {
+        let function = CompiledFunction {
+            id: 0,
+            code: Arc::from([
+                Instr::StoreConst {
+                    slot: Slot::Local(0),
+                    value: ConstValue::Num(2.0),
+                },
+                Instr::StoreConst {
+                    slot: Slot::Local(1),
+                    value: ConstValue::Num(3.0),
+                },
+                Instr::Add {
+                    a: Slot::Local(0),
+                    b: Slot::Local(1),
+                    out: Slot::Ret(0),
+                },
+                Instr::Exit,
+            ]),
+            local_count: 2,
+            arg_count: 0,
+            ret_count: 1,
+            err_count: 1,
+            meta: scalar_meta("main"),
+        };
+
+        let module = CompiledModule {
+            name: Arc::from("main"),
+            init_func: 0,
+            functions: vec![function],
+            function_globals: vec![],
+            exports: vec![],
+            imports: vec![],
+            consts: vec![],
+            global_count: 0,
+            tests: Vec::new(),
+            global_names: Vec::new(),
+        };
+
+        let mut vm = Vm::new(VmConfig {
+            enable_host_print: false,
+            enable_jit: true,
+            ..VmConfig::default()
+        });
+        let result = vm.run_main(&module).expect("run");
+        assert_eq!(result.returns, vec![Value::Num(5.0)]);
+    }
+
+    #[test]
+    fn catches_divide_by_zero_with_try_handler_jit() {
+        let function = CompiledFunction {
+            id: 0,
+            code: Arc::from([
+                Instr::StoreConst {
+                    slot: Slot::Local(0),
+                    value: ConstValue::Num(1.0),
+                },
+                Instr::StoreConst {
+                    slot: Slot::Local(1),
+                    value: ConstValue::Num(0.0),
+                },
+                Instr::TryPush { handler_pc: 5 },
+                Instr::Div {
+                    a: Slot::Local(0),
+                    b: Slot::Local(1),
+                    out: Slot::Ret(0),
+                },
+                Instr::Jump { target: 7 },
+                Instr::StoreConst {
+                    slot: Slot::Ret(0),
+                    value: ConstValue::Num(99.0),
+                },
+                Instr::TryPop,
+                Instr::Exit,
+            ]),
+            local_count: 2,
+            arg_count: 0,
+            ret_count: 1,
+            err_count: 1,
+            meta: scalar_meta("main"),
+        };
+
+        let module = CompiledModule {
+            name: Arc::from("main"),
+            init_func: 0,
+            functions: vec![function],
+            function_globals: vec![],
+            exports: vec![],
+            imports: vec![],
+            consts: vec![],
+            global_count: 0,
+            tests: Vec::new(),
+            global_names: Vec::new(),
+        };
+
+        let mut vm = Vm::new(VmConfig {
+            enable_host_print: false,
+            enable_jit: true,
+            ..VmConfig::default()
+        });
+        let result = vm.run_main(&module).expect("run");
+        assert_eq!(result.returns, vec![Value::Num(99.0)]);
+    }
+
+    #[test]
+    fn incremental_chunks_share_globals_and_functions() {
+        for enable_jit in [true, false] {
+            let mut compiler = imp_compiler::IncrementalCompiler::new(CompileOpts::default());
+            let mut vm = Vm::new(VmConfig {
+                enable_jit,
+                ..VmConfig::default()
+            });
+            let mut run = |src: &str| {
+                let chunk = compiler
+                    .compile_chunk(src, &FsModuleLoader)
+                    .map_err(|err| err.to_string())?;
+                vm.run_incremental(&chunk.module)
+                    .map_err(|err| err.to_string())?;
+                Ok::<_, String>(
+                    chunk
+                        .bindings
+                        .iter()
+                        .map(|(name, slot)| {
+                            (name.clone(), vm.session_globals()[*slot as usize].clone())
+                        })
+                        .collect::<Vec<_>>(),
+                )
+            };
+
+            let num = |name: &str, value: f64| vec![(name.to_owned(), Value::Num(value))];
+            assert_eq!(
+                run("#call core::const out=local::x value=20;").unwrap(),
+                num("local::x", 20.0)
+            );
+            run(r#"
+#call core::fn::begin name=main::step args="n" retshape="scalar";
+#call core::const out=local::one value=1;
+#call core::add a=arg::n b=local::one out=return::value;
+#call core::exit;
+#call core::fn::end;
+"#)
+            .unwrap();
+            assert_eq!(
+                run("#call main::step n=local::x out=local::y;").unwrap(),
+                num("local::y", 21.0)
+            );
+
+            // A failed chunk leaves the session as it was.
+            assert!(run("#call core::bogus out=local::x;").is_err());
+            // Redeclaring a function rebinds its name; same-named init chunks
+            // must not reuse each other's JIT plans.
+            run(r#"
+#call core::fn::begin name=main::step args="n" retshape="scalar";
+#call core::const out=local::ten value=10;
+#call core::add a=arg::n b=local::ten out=return::value;
+#call core::exit;
+#call core::fn::end;
+"#)
+            .unwrap();
+            assert_eq!(
+                run("#call main::step n=local::y out=local::y;").unwrap(),
+                num("local::y", 31.0)
+            );
+            assert_eq!(
+                run("#call core::mov from=local::x to=mod::x;").unwrap(),
+                num("mod::x", 20.0)
+            );
+        }
+    }
+
+    #[test]
+    fn register_file_keeps_slot_kinds_apart() {
+        // One local, one arg, one err slot; returns past `ret_count` still
+        // land, and a slot outside the layout fails the same on both tiers.
+        let make = |read: Slot| CompiledFunction {
+            id: 0,
+            code: Arc::from([
+                Instr::StoreConst {
+                    slot: Slot::Local(0),
+                    value: ConstValue::Num(1.0),
+                },
+                Instr::StoreConst {
+                    slot: Slot::Err(0),
+                    value: ConstValue::Num(2.0),
+                },
+                Instr::Move {
+                    from: Slot::Local(0),
+                    to: Slot::Ret(0),
+                },
+                Instr::Move {
+                    from: read,
+                    to: Slot::Ret(2),
+                },
+                Instr::Exit,
+            ]),
+            local_count: 1,
+            arg_count: 1,
+            ret_count: 1,
+            err_count: 1,
+            meta: FnMeta {
+                retshape: RetShape::Any,
+                ..scalar_meta("main")
+            },
+        };
+        let module = |read: Slot| CompiledModule {
+            name: Arc::from("main"),
+            init_func: 0,
+            functions: vec![make(read)],
+            function_globals: vec![],
+            exports: vec![],
+            imports: vec![],
+            consts: vec![],
+            global_count: 0,
+            tests: Vec::new(),
+            global_names: Vec::new(),
+        };
+
+        for enable_jit in [true, false] {
+            let cfg = || VmConfig {
+                enable_jit,
+                ..VmConfig::default()
+            };
+            let returns = Vm::new(cfg())
+                .run_main(&module(Slot::Err(0)))
+                .expect("run")
+                .returns;
+            assert_eq!(
+                returns,
+                vec![Value::Num(1.0), Value::Null, Value::Num(2.0)],
+                "jit={enable_jit}"
+            );
+            let err = Vm::new(cfg())
+                .run_main(&module(Slot::Local(1)))
+                .expect_err("local 1 is outside the layout");
+            assert!(err.to_string().contains("local slot 1 out of range"));
+        }
+    }
+
+    #[test]
+    fn invoke_uses_function_global_slot_jit() {
+        let init = CompiledFunction {
+            id: 0,
+            code: Arc::from([
+                Instr::Invoke {
+                    fn_slot: Slot::Global(0),
+                    args: vec![],
+                    out: Slot::Ret(0),
+                },
+                Instr::Exit,
+            ]),
+            local_count: 0,
+            arg_count: 0,
+            ret_count: 1,
+            err_count: 1,
+            meta: scalar_meta("main"),
+        };
+
+        let callee = CompiledFunction {
+            id: 1,
+            code: Arc::from([
+                Instr::StoreConst {
+                    slot: Slot::Ret(0),
+                    value: ConstValue::Num(7.0),
+                },
+                Instr::Exit,
+            ]),
+            local_count: 0,
+            arg_count: 0,
+            ret_count: 1,
+            err_count: 1,
+            meta: scalar_meta("main::f"),
+        };
+
+        let module = CompiledModule {
+            name: Arc::from("main"),
+            init_func: 0,
+            functions: vec![init, callee],
+            function_globals: vec![(0, 1)],
+            exports: vec![],
+            imports: vec![],
+            consts: vec![],
+            global_count: 1,
+            tests: Vec::new(),
+            global_names: Vec::new(),
+        };
+
+        let mut vm = Vm::new(VmConfig {
+            enable_host_print: false,
+            enable_jit: true,
+            ..VmConfig::default()
+        });
+        let result = vm.run_main(&module).expect("run");
+        assert_eq!(result.returns, vec![Value::Num(7.0)]);
+    }
+
+    #[test]
+    fn interpreter_fallback_matches_behavior() {
+        let function = CompiledFunction {
+            id: 0,
+            code: Arc::from([
+                Instr::StoreConst {
+                    slot: Slot::Local(0),
+                    value: ConstValue::Num(10.0),
+                },
+                Instr::StoreConst {
+                    slot: Slot::Local(1),
+                    value: ConstValue::Num(4.0),
+                },
+                Instr::Sub {
+                    a: Slot::Local(0),
+                    b: Slot::Local(1),
+                    out: Slot::Ret(0),
+                },
+                Instr::Exit,
+            ]),
+            local_count: 2,
+            arg_count: 0,
+            ret_count: 1,
+            err_count: 1,
+            meta: scalar_meta("main"),
+        };
+
+        let module = CompiledModule {
+            name: Arc::from("main"),
+            init_func: 0,
+            functions: vec![function],
+            function_globals: vec![],
+            exports: vec![],
+            imports: vec![],
+            consts: vec![],
+            global_count: 0,
+            tests: Vec::new(),
+            global_names: Vec::new(),
+        };
+
+        let mut vm = Vm::new(VmConfig {
+            enable_host_print: false,
+            enable_jit: false,
+            ..VmConfig::default()
+        });
+        let result = vm.run_main(&module).expect("run");
+        assert_eq!(result.returns, vec![Value::Num(6.0)]);
+    }
+
+    #[test]
+    fn new_core_ops_match_between_jit_and_interpreter() {
+        let function = CompiledFunction {
+            id: 0,
+            code: Arc::from([
+                Instr::ObjNew {
+                    out: Slot::Local(0),
+                },
+                Instr::StoreConst {
+                    slot: Slot::Local(1),
+                    value: ConstValue::Str(Arc::from("neo")),
+                },
+                Instr::StoreConst {
+                    slot: Slot::Local(2),
+                    value: ConstValue::Str(Arc::from("name")),
+                },
+                Instr::ObjSet {
+                    obj: Slot::Local(0),
+                    key: Slot::Local(2),
+                    value: Slot::Local(1),
+                    out: Slot::Local(0),
+                },
+                Instr::StoreConst {
+                    slot: Slot::Local(5),
+                    value: ConstValue::Str(Arc::from("name")),
+                },
+                Instr::ObjHas {
+                    obj: Slot::Local(0),
+                    key: Slot::Local(5),
+                    out: Slot::Local(3),
+                },
+                Instr::ObjGet {
+                    obj: Slot::Local(0),
+                    key: Slot::Local(5),
+                    out: Slot::Local(4),
+                },
+                Instr::StoreConst {
+                    slot: Slot::Local(6),
+                    value: ConstValue::Str(Arc::from("!")),
+                },
+                Instr::StrConcat {
+                    a: Slot::Local(4),
+                    b: Slot::Local(6),
+                    out: Slot::Local(7),
+                },
+                Instr::StrLen {
+                    value: Slot::Local(7),
+                    out: Slot::Ret(0),
+                },
+                Instr::Exit,
+            ]),
+            local_count: 8,
+            arg_count: 0,
+            ret_count: 1,
+            err_count: 1,
+            meta: scalar_meta("main"),
+        };
+
+        let module = CompiledModule {
+            name: Arc::from("main"),
+            init_func: 0,
+            functions: vec![function],
+            function_globals: vec![],
+            exports: vec![],
+            imports: vec![],
+            consts: vec![],
+            global_count: 0,
+            tests: Vec::new(),
+            global_names: Vec::new(),
+        };
+
+        for enable_jit in [true, false] {
+            let mut vm = Vm::new(VmConfig {
+                enable_host_print: false,
+                enable_jit,
+                ..VmConfig::default()
+            });
+            let result = vm.run_main(&module).expect("run");
+            assert_eq!(result.returns, vec![Value::Num(4.0)]);
+        }
+    }
+
+    #[test]
+    fn stdlib_prelude_module_runs() {
+        let prelude = std::path::Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("../../stdlib/prelude.imp")
+            .canonicalize()
+            .expect("canonicalize prelude path");
+
+        let program = format!(
+            r#"#call core::import alias="std" path="{}";
+#call core::const out=local::x value=-3;
+#call std::abs args="local::x" out=local::absx;
+#call core::const out=local::low value=0;
+#call core::const out=local::high value=2;
+#call std::clamp args="local::absx,local::low,local::high" out=local::clamped;
+#call core::mov from=local::clamped to=return::value;
+#call core::exit;
+"#,
+            prelude.display()
+        );
+
+        let main_path = std::env::temp_dir().join("imp_stdlib_prelude_test.imp");
+        fs::write(&main_path, program).expect("write main");
+
+        let module = compile_module(&main_path, &FsModuleLoader).expect("compile module");
+        let mut vm = Vm::new(VmConfig {
+            enable_host_print: false,
+            enable_jit: true,
+            ..VmConfig::default()
+        });
+        let result = vm.run_main(&module).expect("run");
+        assert_eq!(result.returns, vec![Value::Num(2.0)]);
+    }
+
+    #[test]
+    fn namespaced_stdlib_modules_run_together() {
+        let stdlib_root = std::path::Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("../../stdlib")
+            .canonicalize()
+            .expect("canonicalize stdlib root");
+        let map = stdlib_root.join("map.imp");
+        let string = stdlib_root.join("string.imp");
+        let result_mod = stdlib_root.join("result.imp");
+
+        let program = format!(
+            r#"#call core::import alias="std_map" path="{}";
+#call core::import alias="std_str" path="{}";
+#call core::import alias="std_res" path="{}";
+
+#call std_map::new out=local::m;
+#call core::const out=local::name value="imp";
+#call core::obj::set obj=local::m key="name" value=local::name out=local::m;
+#call core::const out=local::key value="name";
+#call core::const out=local::msg value="missing name";
+#call std_map::require args="local::m,local::key,local::msg" out=local::got;
+#call core::const out=local::suffix value="!";
+#call std_str::concat args="local::got,local::suffix" out=local::text;
+#call std_res::ok args="local::text" out=local::r;
+#call core::const out=local::fallback value="fallback";
+#call std_res::unwrap_or args="local::r,local::fallback" out=return::value;
+#call core::exit;
+"#,
+            map.display(),
+            string.display(),
+            result_mod.display()
+        );
+
+        let main_path = std::env::temp_dir().join("imp_stdlib_namespaced_test.imp");
+        fs::write(&main_path, program).expect("write main");
+
+        let module = compile_module(&main_path, &FsModuleLoader).expect("compile module");
+        let mut vm = Vm::new(VmConfig {
+            enable_host_print: false,
+            enable_jit: true,
+            ..VmConfig::default()
+        });
+        let result = vm.run_main(&module).expect("run");
+        assert_eq!(result.returns, vec![Value::Str(Arc::from("imp!"))]);
+    }
+
+    #[test]
+    fn imported_object_function_is_callable_across_modules() {
+        let temp = std::env::temp_dir();
+        let provider_path = temp.join("imp_provider_fn_holder.imp");
+        let consumer_path = temp.join("imp_consumer_fn_holder.imp");
+
+        let provider_src = r#"
+#call core::fn::begin name=main::double args="x" retshape="scalar";
+#call core::const out=local::two value=2;
+#call core::mul a=arg::x b=local::two out=return::value;
+#call core::exit;
+#call core::fn::end;
+
+#call core::obj::new out=main::holder;
+#call core::obj::set obj=main::holder key="fn" value=main::double out=main::holder;
+#call core::mod::export name="holder" value=main::holder;
+#call core::exit;
+"#;
+        fs::write(&provider_path, provider_src).expect("write provider");
+
+        let consumer_src = format!(
+            r#"#call core::import alias="prov" path="{}";
+#call core::obj::get obj=prov::holder key="fn" out=local::fn_ref;
+#call core::const out=local::x value=4;
+#call core::invoke fn=local::fn_ref args="local::x" out=return::value;
+#call core::exit;
+"#,
+            provider_path.display()
+        );
+        fs::write(&consumer_path, consumer_src).expect("write consumer");
+
+        let module = compile_module(&consumer_path, &FsModuleLoader).expect("compile consumer");
+        let mut vm = Vm::new(VmConfig {
+            enable_host_print: false,
+            enable_jit: true,
+            ..VmConfig::default()
+        });
+        let result = vm.run_main(&module).expect("run consumer");
+        assert_eq!(result.returns, vec![Value::Num(8.0)]);
+    }
+
+    fn compile_source(src: &str) -> CompiledModule {
+        compile_program(src, CompileOpts::default())
+            .expect("compile source")
+            .module
+    }
+
+    #[test]
+    fn invoke_reuses_pooled_argument_buffers() {
+        let module = compile_source(
+            r#"
+#call core::fn::begin name=main::inc args="x" retshape="scalar";
+#call core::const out=local::one value=1;
+#call core::add a=arg::x b=local::one out=return::value;
+#call core::exit;
+#call core::fn::end;
+#call core::const out=local::n value=0;
+#call core::const out=local::limit value=50;
+#call core::label name="loop";
+#call core::lt a=local::n b=local::limit out=local::more;
+#call core::br cond=local::more then="body" else="done";
+#call core::label name="body";
+#call main::inc args="local::n" out=local::n;
+#call core::jump target="loop";
+#call core::label name="done";
+#call core::mov from=local::n to=return::value;
+#call core::exit;
+"#,
+        );
+        for enable_jit in [true, false] {
+            let mut vm = Vm::new(VmConfig {
+                enable_jit,
+                ..VmConfig::default()
+            });
+            let returns = vm.run_main(&module).expect("run").returns;
+            assert_eq!(returns, vec![Value::Num(50.0)]);
+            // Sequential calls share a single buffer.
+            assert_eq!(vm.arg_pool.len(), 1);
+            assert!(vm.arg_pool[0].is_empty() && vm.arg_pool[0].capacity() >= 1);
+        }
+    }
+
+    #[test]
+    fn jit_cache_eviction_keeps_running_calls_intact() {
+        let module = compile_source(
+            r#"
+#call core::fn::begin name=main::inner args="x" retshape="scalar";
+#call core::add a=arg::x b=arg::x out=return::value;
+#call core::exit;
+#call core::fn::end;
+#call core::fn::begin name=main::outer args="x" retshape="scalar";
+#call main::inner args="arg::x" out=local::once;
+#call main::inner args="local::once" out=return::value;
+#call core::exit;
+#call core::fn::end;
+#call core::const out=local::x value=3;
+#call main::outer args="local::x" out=local::a;
+#call main::outer args="local::a" out=return::value;
+#call core::exit;
+"#,
+        );
+
+        let limits = [(Some(1), None), (None, Some(1)), (Some(2), None)];
+        for (max_entries, max_bytes) in limits {
+            let mut vm = Vm::new(VmConfig {
+                jit_cache_max_entries: max_entries,
+                jit_cache_max_bytes: max_bytes,
+                ..VmConfig::default()
+            });
+            let returns = vm.run_main(&module).expect("run").returns;
+            assert_eq!(returns, vec![Value::Num(48.0)]);
+            assert!(vm.jit_cache_len() <= max_entries.unwrap_or(1));
+        }
+
+        let mut vm = Vm::new(VmConfig::default());
+        vm.run_main(&module).expect("run");
+        assert_eq!(vm.jit_cache_len(), 3);
+    }
+
+    #[test]
+    fn str_format_fills_named_placeholders() {
+        let module = compile_source(
+            r#"
+#call core::const out=local::user value="Ada";
+#call core::const out=local::count value=3;
+#call core::obj::new out=local::subs;
+#call core::obj::set obj=local::subs key="user" value=local::user;
+#call core::obj::set obj=local::subs key="n" value=local::count;
+#call core::str::format tmpl="Hello {user}! {n} new {{msgs}}" args=local::subs out=local::greeting;
+#call core::mov from=local::greeting to=return::greeting;
+#call core::try::push handler="missing";
+#call core::str::format tmpl="Hi {nobody}" args=local::subs out=local::ignored;
+#call core::label name="missing";
+#call core::mov from=err::0 to=return::missing;
+#call core::try::push handler="syntax";
+#call core::str::format tmpl="oops {user" args=local::subs out=local::ignored;
+#call core::label name="syntax";
+#call core::mov from=err::0 to=return::syntax;
+#call core::exit;
+"#,
+        );
+
+        for enable_jit in [true, false] {
+            let mut vm = Vm::new(VmConfig {
+                enable_host_print: false,
+                enable_jit,
+                ..VmConfig::default()
+            });
+            let returns = vm.run_main(&module).expect("run").returns;
+            assert_eq!(returns[0], Value::Str(Arc::from("Hello Ada! 3 new {msgs}")));
+            let codes = returns[1..]
+                .iter()
+                .map(|value| match value {
+                    Value::Error { code, .. } => code.to_string(),
+                    other => panic!("expected error, got {other:?}"),
+                })
+                .collect::<Vec<_>>();
+            assert_eq!(codes, ["format_key", "format_syntax"]);
+        }
+    }
+
+    #[test]
+    fn escaping_errors_carry_a_traceback() {
+        let module = compile_source(
+            r#"
+#call core::fn::begin name=main::inner args="x" retshape="scalar";
+#call core::add a=arg::x b=arg::x out=return::value;
+#call core::exit;
+#call core::fn::end;
+#call core::fn::begin name=main::outer args="x" retshape="scalar";
+#call core::const out=local::pad value=0;
+#call main::inner args="arg::x" out=local::value;
+#call core::mov from=local::value to=return::value;
+#call core::exit;
+#call core::fn::end;
+#call core::const out=local::text value="not a number";
+#call main::outer args="local::text" out=return::value;
+#call core::exit;
+"#,
+        );
+
+        for enable_jit in [true, false] {
+            let mut vm = Vm::new(VmConfig {
+                enable_host_print: false,
+                enable_jit,
+                ..VmConfig::default()
+            });
+            let err = vm.run_main(&module).expect_err("add on a string");
+            assert!(matches!(err, VmError::Runtime { .. }), "{err:?}");
+            let trace = err
+                .traceback()
+                .iter()
+                .map(ToString::to_string)
+                .collect::<Vec<_>>();
+            assert_eq!(
+                trace,
+                [
+                    "main::inner (line 3, pc 0)",
+                    "main::outer (line 8, pc 1)",
+                    "<init> (line 13, pc 1)"
+                ],
+                "jit={enable_jit}"
+            );
+        }
+    }
+
+    #[test]
+    fn runtime_errors_point_at_source_lines() {
+        let module = compile_source(
+            r#"
+#call core::fn::begin name=main::fail args="" retshape="scalar";
+#call core::const out=local::x value=1;
+#call core::throw code="boom" msg="no";
+#call core::exit;
+#call core::fn::end;
+#call main::fail args="" out=return::value;
+#call core::exit;
+"#,
+        );
+        for enable_jit in [true, false] {
+            let mut vm = Vm::new(VmConfig {
+                enable_jit,
+                ..VmConfig::default()
+            });
+            let err = vm.run_main(&module).expect_err("uncaught throw");
+            let frames = err
+                .traceback()
+                .iter()
+                .map(ToString::to_string)
+                .collect::<Vec<_>>();
+            assert_eq!(
+                frames,
+                ["main::fail (line 4, pc 1)", "<init> (line 7, pc 0)"]
+            );
+        }
+    }
+
+    #[test]
+    fn deep_tracebacks_fold_repeats_and_stay_bounded() {
+        let recursive = compile_source(
+            r#"
+#call core::fn::begin name=main::f args="" retshape="scalar";
+#call main::f args="" out=local::value;
+#call core::mov from=local::value to=return::value;
+#call core::exit;
+#call core::fn::end;
+#call main::f args="" out=return::value;
+#call core::exit;
+"#,
+        );
+        let mutual = compile_source(
+            r#"
+#call core::fn::begin name=main::f args="" retshape="scalar";
+#call main::g args="" out=local::value;
+#call core::mov from=local::value to=return::value;
+#call core::exit;
+#call core::fn::end;
+#call core::fn::begin name=main::g args="" retshape="scalar";
+#call main::f args="" out=local::value;
+#call core::mov from=local::value to=return::value;
+#call core::exit;
+#call core::fn::end;
+#call main::f args="" out=return::value;
+#call core::exit;
+"#,
+        );
+
+        for enable_jit in [true, false] {
+            let mut vm = Vm::new(VmConfig {
+                enable_jit,
+                max_call_depth: Some(1000),
+                ..VmConfig::default()
+            });
+            let err = vm.run_main(&recursive).expect_err("overflow");
+            let trace = err.traceback();
+            assert_eq!(trace.len(), 2, "jit={enable_jit}");
+            assert_eq!((&*trace[0].function, trace[0].repeats), ("main::f", 998));
+            assert_eq!((&*trace[1].function, trace[1].repeats), ("<init>", 0));
+
+            let err = vm.run_main(&mutual).expect_err("overflow");
+            let trace = err.traceback();
+            assert_eq!(trace.len(), MAX_TRACEBACK_FRAMES, "jit={enable_jit}");
+            assert_eq!(trace.len() + trace[trace.len() - 1].omitted, 1000);
+        }
+    }
+
+    #[test]
+    fn list_ops_build_and_index_native_lists() {
+        let module = compile_source(
+            r#"
+#call core::list::new out=local::xs;
+#call core::list::push list=local::xs value=10 out=local::xs;
+#call core::list::push list=local::xs value="b" out=local::xs;
+#call core::list::set list=local::xs index=0 value=11 out=local::xs;
+#call core::list::get list=local::xs index=0 out=return::first;
+#call core::list::get list=local::xs index=5 out=return::missing;
+#call core::list::len list=local::xs out=return::len;
+#call core::mov from=local::xs to=return::list;
+#call core::try::push handler="oob";
+#call core::list::set list=local::xs index=2 value=1 out=local::xs;
+#call core::try::pop;
+#call core::exit;
+#call core::label name="oob";
+#call core::mov from=err::0 to=return::err;
+#call core::exit;
+"#,
+        );
+
+        for enable_jit in [true, false] {
+            let mut vm = Vm::new(VmConfig {
+                enable_jit,
+                ..VmConfig::default()
+            });
+            let returns = vm.run_main(&module).expect("run").returns;
+            assert_eq!(returns[0], Value::Num(11.0));
+            assert_eq!(returns[1], Value::Null);
+            assert_eq!(returns[2], Value::Num(2.0));
+            assert_eq!(returns[3].to_string(), "[11, \"b\"]");
+            assert!(
+                matches!(&returns[4], Value::Error { code, .. } if code.as_ref() == "index_range")
+            );
+        }
+    }
+
+    #[test]
+    fn type_mismatches_throw_catchable_errors_unless_strict() {
+        let module = compile_source(
+            r#"
+#call core::const out=local::nothing value=null;
+#call core::try::push handler="get";
+#call core::obj::get obj=local::nothing key="a" out=local::unused;
+#call core::try::pop;
+#call core::exit;
+#call core::label name="get";
+#call core::error::code err=err::0 out=return::get;
+#call core::try::push handler="set";
+#call core::obj::set obj=local::nothing key="a" value=local::nothing out=local::unused;
+#call core::try::pop;
+#call core::exit;
+#call core::label name="set";
+#call core::error::code err=err::0 out=return::set;
+#call core::const out=local::num value=3;
+#call core::try::push handler="call";
+#call core::invoke fn=local::num args="local::num" out=local::unused;
+#call core::try::pop;
+#call core::exit;
+#call core::label name="call";
+#call core::error::code err=err::0 out=return::call;
+#call core::try::push handler="list";
+#call core::list::len list=local::num out=local::unused;
+#call core::try::pop;
+#call core::exit;
+#call core::label name="list";
+#call core::error::code err=err::0 out=return::list;
+#call core::try::push handler="entries";
+#call core::obj::entries obj=local::num out=local::unused;
+#call core::try::pop;
+#call core::exit;
+#call core::label name="entries";
+#call core::error::code err=err::0 out=return::entries;
+#call core::exit;
+"#,
+        );
+
+        for enable_jit in [true, false] {
+            let mut vm = Vm::new(VmConfig {
+                enable_jit,
+                ..VmConfig::default()
+            });
+            let returns = vm.run_main(&module).expect("run").returns;
+            assert_eq!(
+                returns,
+                vec![
+                    Value::Str(Arc::from("type_error")),
+                    Value::Str(Arc::from("type_error")),
+                    Value::Str(Arc::from("not_callable")),
+                    Value::Str(Arc::from("type_error")),
+                    Value::Str(Arc::from("type_error")),
+                ]
+            );
+
+            let mut strict = Vm::new(VmConfig {
+                enable_jit,
+                strict_type_errors: true,
+                ..VmConfig::default()
+            });
+            let err = strict.run_main(&module).expect_err("strict");
+            assert!(
+                err.to_string()
+                    .contains("core::obj::get target is not an object (got null)"),
+                "{err}"
+            );
+        }
+    }
+
+    #[test]
+    fn obj_keys_del_and_len_enumerate_and_shrink_objects() {
+        let module = compile_source(
+            r#"
+#call core::obj::new out=local::o;
+#call core::const out=local::v value=1;
+#call core::obj::set obj=local::o key="b" value=local::v out=local::o;
+#call core::obj::set obj=local::o key="a" value=local::v out=local::o;
+#call core::obj::set obj=local::o key="c" value=local::v out=local::o;
+#call core::obj::del obj=local::o key="b" out=local::o;
+#call core::obj::del obj=local::o key="missing" out=local::o;
+#call core::obj::keys obj=local::o out=return::keys;
+#call core::obj::len obj=local::o out=return::len;
+#call core::const out=local::nothing value=null;
+#call core::try::push handler="bad";
+#call core::obj::keys obj=local::nothing out=local::unused;
+#call core::try::pop;
+#call core::exit;
+#call core::label name="bad";
+#call core::error::code err=err::0 out=return::bad;
+#call core::exit;
+"#,
+        );
+
+        for enable_jit in [true, false] {
+            let mut vm = Vm::new(VmConfig {
+                enable_jit,
+                ..VmConfig::default()
+            });
+            let returns = vm.run_main(&module).expect("run").returns;
+            assert_eq!(returns[0].to_string(), "[\"a\", \"c\"]");
+            assert_eq!(returns[1], Value::Num(2.0));
+            assert_eq!(returns[2], Value::Str(Arc::from("type_error")));
+        }
+    }
+
+    #[test]
+    fn obj_paths_walk_and_build_nested_objects() {
+        let module = compile_source(
+            r#"
+#call core::obj::new out=local::o;
+#call core::const out=local::v value=7;
+#call core::obj::setpath obj=local::o path="user.address.city" value=local::v out=local::o;
+#call core::obj::getpath obj=local::o path="user.address.city" out=return::city;
+#call core::obj::getpath obj=local::o path="user.phone" out=return::phone;
+#call core::obj::getpath obj=local::o path="user.phone.home" safe=true out=return::home;
+#call core::const out=local::nothing value=null;
+#call core::obj::setpath obj=local::nothing path="a.b" value=local::v safe=true out=local::built;
+#call core::obj::getpath obj=local::built path="a.b" out=return::built;
+#call core::try::push handler="bad";
+#call core::obj::setpath obj=local::o path="user.address.city.zip" value=local::v out=local::unused;
+#call core::try::pop;
+#call core::exit;
+#call core::label name="bad";
+#call core::error::msg err=err::0 out=return::bad;
+#call core::exit;
+"#,
+        );
+
+        for enable_jit in [true, false] {
+            let mut vm = Vm::new(VmConfig {
+                enable_jit,
+                ..VmConfig::default()
+            });
+            let returns = vm.run_main(&module).expect("run").returns;
+            assert_eq!(returns[0], Value::Num(7.0));
+            assert_eq!(returns[1], Value::Null);
+            assert_eq!(returns[2], Value::Null);
+            assert_eq!(returns[3], Value::Num(7.0));
+            assert_eq!(
+                returns[4],
+                Value::Str(Arc::from(
+                    "core::obj::setpath 'user.address.city' is not an object (got num)"
+                ))
+            );
+        }
+    }
+
+    #[test]
+    fn objects_are_shared_between_slots_and_calls() {
+        let module = compile_source(
+            r#"
+#call core::fn::begin name=main::tag args="obj" retshape="scalar";
+#call core::const out=local::yes value=true;
+#call core::obj::set obj=arg::obj key="tagged" value=local::yes;
+#call core::exit;
+#call core::fn::end;
+#call core::obj::new out=local::o;
+#call core::mov from=local::o to=local::alias;
+#call main::tag args="local::alias" out=local::ignored;
+#call core::obj::get obj=local::o key="tagged" out=return::tagged;
+#call core::obj::set obj=local::o key="self" value=local::o;
+#call core::eq a=local::o b=local::alias out=return::same;
+#call core::mov from=local::o to=return::cyclic;
+#call core::obj::new out=mod::config;
+#call core::const out=local::port value=8080;
+#call core::obj::set obj=mod::config key="port" value=local::port;
+"#,
+        );
+
+        for enable_jit in [true, false] {
+            let mut vm = Vm::new(VmConfig {
+                enable_jit,
+                track_global_deltas: true,
+                ..VmConfig::default()
+            });
+            let result = vm.run_main(&module).expect("run");
+            assert_eq!(result.returns[0], Value::Bool(true));
+            assert_eq!(result.returns[1], Value::Bool(true));
+            assert_eq!(result.returns[2].to_string(), "{self: {...}, tagged: true}");
+            let deltas = result.global_deltas.expect("deltas");
+            assert_eq!(deltas[0].after.to_string(), "{port: 8080}");
+        }
+
+        let mut cyclic = HashMap::new();
+        cyclic.insert("n".to_owned(), Value::Num(1.0));
+        let a = ObjRef::new(cyclic.clone());
+        a.borrow_mut()
+            .insert("next".to_owned(), Value::Obj(a.clone()));
+        let b = Value::Obj(a.clone()).deep_copy();
+        let Value::Obj(b_obj) = &b else {
+            panic!("deep copy of an object");
+        };
+        assert!(!b_obj.ptr_eq(&a));
+        assert_eq!(b, Value::Obj(a.clone()));
+        assert_eq!(b.total_cmp(&Value::Obj(a)), std::cmp::Ordering::Equal);
+    }
+
+    #[test]
+    fn ext_ops_run_registered_handlers() {
+        struct Clamp;
+        impl imp_compiler::TargetLowering for Clamp {
+            fn handles(&self, target: &str) -> bool {
+                target == "app::clamp"
+            }
+
+            fn lower(
+                &self,
+                cx: &mut imp_compiler::LoweringCx<'_, '_>,
+            ) -> Result<(), imp_compiler::CompileError> {
+                let operands = vec![
+                    cx.slot("value")?,
+                    cx.slot("lo")?,
+                    cx.slot("hi")?,
+                    cx.out("out")?,
+                ];
+                cx.emit(Instr::Ext { op: 7, operands });
+                Ok(())
+            }
+        }
+
+        let module = compile_program(
+            "#call app::clamp value=42 lo=0 hi=10 out=return::value;\n#call core::exit;\n",
+            CompileOpts {
+                target_plugins: vec![Box::new(Clamp)],
+                ..CompileOpts::default()
+            },
+        )
+        .expect("compile")
+        .module;
+
+        for enable_jit in [true, false] {
+            let mut ext_ops = ExtOps::default();
+            ext_ops.register(7, "clamp", |args| {
+                let num = |index| match args.get(index)? {
+                    Value::Num(num) => Ok(num),
+                    other => Err(HostFnError::bad_arg("clamp", index, "num", &other)),
+                };
+                let clamped = num(0)?.clamp(num(1)?, num(2)?);
+                args.set(3, Value::Num(clamped))
+            });
+            let mut vm = Vm::new(VmConfig {
+                enable_jit,
+                ext_ops,
+                ..VmConfig::default()
+            });
+            assert_eq!(
+                vm.run_main(&module).expect("run").returns,
+                vec![Value::Num(10.0)]
+            );
+
+            let mut vm = Vm::new(VmConfig {
+                enable_jit,
+                ..VmConfig::default()
+            });
+            let err = vm.run_main(&module).expect_err("no handler");
+            assert!(
+                err.to_string()
+                    .contains("no handler registered for ext op 7")
+            );
+        }
+    }
+
+    #[test]
+    fn cmp_uses_a_total_order_across_kinds() {
+        let module = compile_source(
+            r#"
+#call core::const out=local::null value=null;
+#call core::const out=local::one value=1;
+#call core::const out=local::a value="a";
+#call core::const out=local::b value="b";
+#call core::cmp value_a=local::null value_b=local::one out=return::kinds;
+#call core::cmp value_a=local::b value_b=local::a out=return::strs;
+#call core::cmp value_a=local::one value_b=1 out=return::same;
+#call core::lt a=local::a b=local::b out=return::lt;
+#call core::exit;
+"#,
+        );
+        for enable_jit in [true, false] {
+            let mut vm = Vm::new(VmConfig {
+                enable_jit,
+                ..VmConfig::default()
+            });
+            assert_eq!(
+                vm.run_main(&module).expect("run").returns,
+                vec![
+                    Value::Num(-1.0),
+                    Value::Num(1.0),
+                    Value::Num(0.0),
+                    Value::Bool(true)
+                ]
+            );
+        }
+
+        let obj = |pairs: &[(&str, f64)]| {
+            Value::Obj(
+                pairs
+                    .iter()
+                    .map(|(key, num)| ((*key).to_owned(), Value::Num(*num)))
+                    .collect(),
+            )
+        };
+        assert!(
+            obj(&[("a", 1.0)])
+                .total_cmp(&obj(&[("a", 1.0), ("b", 0.0)]))
+                .is_lt()
+        );
+        assert!(obj(&[("b", 0.0)]).total_cmp(&obj(&[("a", 9.0)])).is_gt());
+        assert!(Value::Num(-0.0).total_cmp(&Value::Num(0.0)).is_eq());
+        assert!(
+            Value::Num(f64::NAN)
+                .total_cmp(&Value::Num(f64::INFINITY))
+                .is_gt()
+        );
+        assert!(
+            Value::Str(Arc::from("z"))
+                .total_cmp(&Value::Obj(ObjRef::default()))
+                .is_lt()
+        );
+    }
+
+    #[test]
+    fn obj_entries_lists_pairs_in_key_order() {
+        let module = compile_source(
+            r#"
+#call core::obj::new out=local::o;
+#call core::const out=local::one value=1;
+#call core::const out=local::two value=2;
+#call core::obj::set obj=local::o key="b" value=local::two out=local::o;
+#call core::obj::set obj=local::o key="a" value=local::one out=local::o;
+#call core::obj::entries obj=local::o out=local::pairs len=return::len;
+#call core::const out=local::keys value="";
+#call core::const out=local::i value=0;
+#call core::label name="loop";
+#call core::lt a=local::i b=return::len out=local::more;
+#call core::br cond=local::more then="body" else="done";
+#call core::label name="body";
+#call core::obj::get obj=local::pairs key=local::i out=local::pair;
+#call core::obj::get obj=local::pair key="key" out=local::key;
+#call core::str::concat a=local::keys b=local::key out=local::keys;
+#call core::add a=local::i b=local::one out=local::i;
+#call core::jump target="loop";
+#call core::label name="done";
+#call core::mov from=local::keys to=return::keys;
+#call core::obj::get obj=local::pairs key=1 out=local::last;
+#call core::obj::get obj=local::last key="value" out=return::last;
+#call core::exit;
+"#,
+        );
+
+        for enable_jit in [true, false] {
+            let mut vm = Vm::new(VmConfig {
+                enable_jit,
+                ..VmConfig::default()
+            });
+            let returns = vm.run_main(&module).expect("run").returns;
+            assert_eq!(
+                returns,
+                vec![
+                    Value::Num(2.0),
+                    Value::Str(Arc::from("ab")),
+                    Value::Num(2.0)
+                ]
+            );
+        }
+    }
+
+    #[test]
+    fn bytes_ops_work_on_raw_bytes() {
+        let module = compile_source(
+            r#"
+#call core::bytes::from_str value="héllo" out=local::b;
+#call core::bytes::len value=local::b out=return::len;
+#call core::bytes::slice value=local::b start=0 end=1 out=local::h;
+#call core::bytes::slice value=local::b start=3 out=local::tail;
+#call core::bytes::concat a=local::h b=local::tail out=local::joined;
+#call core::bytes::to_str value=local::joined out=return::text;
+#call core::try::push handler="split";
+#call core::bytes::slice value=local::b start=0 end=2 out=local::half;
+#call core::bytes::to_str value=local::half out=local::unused;
+#call core::try::pop;
+#call core::exit;
+#call core::label name="split";
+#call core::mov from=err::0 to=return::err;
+#call core::exit;
+"#,
+        );
+        assert!(matches!(
+            &module.functions[0].code[0],
+            Instr::StoreConst {
+                value: ConstValue::Bytes(_),
+                ..
+            }
         ));
-    };
-    let condition = frame.get(*cond, globals)?.as_bool();
-    Ok(StepControl::Next(if condition {
-        *then_pc
-    } else {
-        *else_pc
-    }))
-}
 
-fn step_invoke(
-    vm: &mut Vm,
-    module: &CompiledModule,
-    frame: &mut Frame,
-    globals: &mut [Value],
-    operands: &JitOperands,
-    pc: usize,
-) -> Result<StepControl, VmError> {
-    let JitOperands::Invoke { fn_slot, args, out } = operands else {
-        return Err(VmError::Runtime(
-            "jit operand mismatch for invoke".to_owned(),
-        ));
-    };
+        for enable_jit in [true, false] {
+            let mut vm = Vm::new(VmConfig {
+                enable_jit,
+                ..VmConfig::default()
+            });
+            let returns = vm.run_main(&module).expect("run").returns;
+            assert_eq!(returns[0], Value::Num(6.0));
+            assert_eq!(returns[1], Value::Str(Arc::from("hllo")));
+            let Value::Error { code, .. } = &returns[2] else {
+                panic!("expected invalid_utf8, got {returns:?}");
+            };
+            assert_eq!(code.as_ref(), "invalid_utf8");
+        }
+    }
+
+    #[test]
+    fn str_ops_slice_and_search_by_char() {
+        let module = compile_source(
+            r#"
+#call core::const out=local::s value="héllo wörld";
+#call core::str::sub value=local::s start=1 end=4 out=return::sub;
+#call core::str::sub value=local::s start=6 out=return::tail;
+#call core::str::find value=local::s pattern="wö" out=return::at;
+#call core::str::find value=local::s pattern="xyz" out=return::missing;
+#call core::str::replace value=local::s pattern="ö" with="o" out=return::plain;
+#call core::str::contains value=local::s pattern="llo" out=return::has;
+#call core::str::upper value=local::s out=return::upper;
+#call core::const out=local::padded value="  Ünïcode\n";
+#call core::str::trim value=local::padded out=local::trimmed;
+#call core::str::lower value=local::trimmed out=return::lower;
+#call core::str::trim_end value=local::padded out=return::trim_end;
+#call core::try::push handler="range";
+#call core::str::sub value=local::s start=3 end=20 out=local::unused;
+#call core::try::pop;
+#call core::exit;
+#call core::label name="range";
+#call core::error::code err=err::0 out=return::range;
+#call core::exit;
+"#,
+        );
+
+        for enable_jit in [true, false] {
+            let mut vm = Vm::new(VmConfig {
+                enable_jit,
+                ..VmConfig::default()
+            });
+            let returns = vm.run_main(&module).expect("run").returns;
+            assert_eq!(
+                returns,
+                vec![
+                    Value::Str(Arc::from("éll")),
+                    Value::Str(Arc::from("wörld")),
+                    Value::Num(6.0),
+                    Value::Num(-1.0),
+                    Value::Str(Arc::from("héllo world")),
+                    Value::Bool(true),
+                    Value::Str(Arc::from("HÉLLO WÖRLD")),
+                    Value::Str(Arc::from("ünïcode")),
+                    Value::Str(Arc::from("  Ünïcode")),
+                    Value::Str(Arc::from("str_range")),
+                ]
+            );
+        }
+    }
+
+    #[test]
+    fn str_ops_throw_type_errors_on_non_text_operands() {
+        let module = compile_source(
+            r#"
+#call core::list::new out=local::xs;
+#call core::obj::new out=local::o;
+#call core::try::push handler="sub";
+#call core::str::sub value=local::xs start=0 out=local::unused;
+#call core::label name="sub";
+#call core::error::code err=err::0 out=return::sub;
+#call core::try::push handler="find";
+#call core::str::find value="abc" pattern=local::o out=local::unused;
+#call core::label name="find";
+#call core::error::code err=err::0 out=return::find;
+#call core::try::push handler="index";
+#call core::str::sub value="abc" start="one" out=local::unused;
+#call core::label name="index";
+#call core::error::code err=err::0 out=return::index;
+#call core::try::push handler="format";
+#call core::str::format tmpl="{a}" args=local::xs out=local::unused;
+#call core::label name="format";
+#call core::error::code err=err::0 out=return::format;
+#call core::obj::set obj=local::o key="a" value=local::xs;
+#call core::try::push handler="sub_value";
+#call core::str::format tmpl="{a}" args=local::o out=local::unused;
+#call core::label name="sub_value";
+#call core::error::code err=err::0 out=return::sub_value;
+#call core::exit;
+"#,
+        );
+
+        for enable_jit in [true, false] {
+            let mut vm = Vm::new(VmConfig {
+                enable_jit,
+                ..VmConfig::default()
+            });
+            let returns = vm.run_main(&module).expect("run").returns;
+            assert_eq!(
+                returns,
+                vec![Value::Str(Arc::from("type_error")); 5],
+                "jit={enable_jit}"
+            );
+
+            let mut strict = Vm::new(VmConfig {
+                enable_jit,
+                strict_type_errors: true,
+                ..VmConfig::default()
+            });
+            let err = strict.run_main(&module).expect_err("strict");
+            assert!(
+                err.to_string()
+                    .contains("core::str::sub expects text or a scalar, found list"),
+                "{err}"
+            );
+        }
+    }
+
+    #[test]
+    fn str_split_and_join_round_trip_csv_fields() {
+        let module = compile_source(
+            r#"
+#call core::const out=local::row value="ada,,lovelace";
+#call core::str::split value=local::row sep="," out=local::fields;
+#call core::list::len list=local::fields out=return::count;
+#call core::str::join list=local::fields sep=";" out=return::joined;
+#call core::str::split value="héy" sep="" out=local::chars;
+#call core::str::join list=local::chars sep="-" out=return::chars;
+#call core::try::push handler="bad";
+#call core::str::join list=local::row sep="," out=local::unused;
+#call core::try::pop;
+#call core::exit;
+#call core::label name="bad";
+#call core::error::code err=err::0 out=return::bad;
+#call core::obj::new out=local::item;
+#call core::list::push list=local::fields value=local::item out=local::fields;
+#call core::try::push handler="nested";
+#call core::str::join list=local::fields sep="," out=local::unused;
+#call core::try::pop;
+#call core::exit;
+#call core::label name="nested";
+#call core::error::msg err=err::0 out=return::nested;
+#call core::exit;
+"#,
+        );
+
+        for enable_jit in [true, false] {
+            let mut vm = Vm::new(VmConfig {
+                enable_jit,
+                ..VmConfig::default()
+            });
+            let returns = vm.run_main(&module).expect("run").returns;
+            assert_eq!(
+                returns,
+                vec![
+                    Value::Num(3.0),
+                    Value::Str(Arc::from("ada;;lovelace")),
+                    Value::Str(Arc::from("h-é-y")),
+                    Value::Str(Arc::from("type_error")),
+                    Value::Str(Arc::from(
+                        "core::str::join expects text or a scalar, found obj"
+                    )),
+                ]
+            );
+        }
+    }
+
+    #[test]
+    fn str_case_and_trim_ops_follow_unicode_rules() {
+        let module = compile_source(
+            r#"
+#call core::const out=local::s value="\t Straße Ünï \n";
+#call core::str::upper value=local::s out=return::upper;
+#call core::str::lower value=local::s out=return::lower;
+#call core::str::trim value=local::s out=return::trim;
+#call core::str::trim_start value=local::s out=return::trim_start;
+#call core::str::trim_end value=local::s out=return::trim_end;
+#call core::const out=local::n value=12;
+#call core::str::upper value=local::n out=return::number;
+#call core::list::new out=local::xs;
+#call core::try::push handler="bad";
+#call core::str::trim value=local::xs out=local::unused;
+#call core::try::pop;
+#call core::exit;
+#call core::label name="bad";
+#call core::error::code err=err::0 out=return::bad;
+#call core::exit;
+"#,
+        );
+
+        for enable_jit in [true, false] {
+            let mut vm = Vm::new(VmConfig {
+                enable_jit,
+                ..VmConfig::default()
+            });
+            let returns = vm.run_main(&module).expect("run").returns;
+            assert_eq!(
+                returns,
+                vec![
+                    Value::Str(Arc::from("\t STRASSE ÜNÏ \n")),
+                    Value::Str(Arc::from("\t straße ünï \n")),
+                    Value::Str(Arc::from("Straße Ünï")),
+                    Value::Str(Arc::from("Straße Ünï \n")),
+                    Value::Str(Arc::from("\t Straße Ünï")),
+                    Value::Str(Arc::from("12")),
+                    Value::Str(Arc::from("type_error")),
+                ],
+                "jit={enable_jit}"
+            );
+        }
+    }
+
+    #[test]
+    fn ints_are_exact_and_throw_on_overflow() {
+        let module = compile_source(
+            r#"
+#call core::int::parse value="9007199254740993" out=local::big;
+#call core::int::parse value=1 out=local::one;
+#call core::add a=local::big b=local::one out=return::sum;
+#call core::const out=local::text value=" -7 ";
+#call core::int::parse value=local::text out=local::neg;
+#call core::int::parse value=2 out=local::two;
+#call core::div a=local::neg b=local::two out=return::quot;
+#call core::const out=local::half value=0.5;
+#call core::add a=local::neg b=local::half out=return::mixed;
+#call core::try::push handler="overflow";
+#call core::int::parse value="9223372036854775807" out=local::max;
+#call core::add a=local::max b=local::one out=local::unused;
+#call core::try::pop;
+#call core::exit;
+#call core::label name="overflow";
+#call core::error::code err=err::0 out=return::overflow;
+#call core::const out=local::bad value="12x";
+#call core::try::push handler="bad";
+#call core::int::parse value=local::bad out=local::unused;
+#call core::try::pop;
+#call core::exit;
+#call core::label name="bad";
+#call core::error::code err=err::0 out=return::bad;
+#call core::exit;
+"#,
+        );
+
+        for enable_jit in [true, false] {
+            let mut vm = Vm::new(VmConfig {
+                enable_jit,
+                ..VmConfig::default()
+            });
+            let returns = vm.run_main(&module).expect("run").returns;
+            assert_eq!(
+                returns,
+                vec![
+                    Value::Int(9_007_199_254_740_994),
+                    Value::Int(-3),
+                    Value::Num(-6.5),
+                    Value::Str(Arc::from("int_overflow")),
+                    Value::Str(Arc::from("int_parse")),
+                ]
+            );
+        }
+    }
+
+    #[test]
+    fn type_of_and_is_predicates_name_every_kind() {
+        let module = compile_source(
+            r#"
+#call core::fn::begin name=main::id args="x" retshape="scalar";
+#call core::mov from=arg::x to=return::value;
+#call core::exit;
+#call core::fn::end;
+#call core::obj::new out=local::obj;
+#call core::type_of value=local::obj out=return::obj;
+#call core::int::parse value="3" out=local::int;
+#call core::type_of value=local::int out=return::int;
+#call core::type_of value=main::id out=return::func;
+#call core::const out=local::text value="x";
+#call core::is::str value=local::text out=return::is_str;
+#call core::is::num value=local::int out=return::int_is_num;
+#call core::is::null value=local::missing out=return::is_null;
+#call core::exit;
+"#,
+        );
+
+        for enable_jit in [true, false] {
+            let mut vm = Vm::new(VmConfig {
+                enable_jit,
+                ..VmConfig::default()
+            });
+            let returns = vm.run_main(&module).expect("run").returns;
+            assert_eq!(
+                returns,
+                vec![
+                    Value::Str(Arc::from("obj")),
+                    Value::Str(Arc::from("int")),
+                    Value::Str(Arc::from("func")),
+                    Value::Bool(true),
+                    Value::Bool(false),
+                    Value::Bool(true),
+                ]
+            );
+        }
+    }
+
+    #[test]
+    fn num_parse_and_format_round_trip() {
+        let module = compile_source(
+            r#"
+#call core::const out=local::text value=" 2.5e1 ";
+#call core::num::parse value=local::text out=local::num;
+#call core::num::parse value="42" out=local::folded;
+#call core::add a=local::num b=local::folded out=return::sum;
+#call core::num::format value=3.14159 precision=2 out=return::pi;
+#call core::num::format value=local::num precision=1 width=7 out=return::padded;
+#call core::int::parse value="7" out=local::seven;
+#call core::num::format value=local::seven precision=3 out=return::int;
+#call core::try::push handler="width";
+#call core::num::format value=1 width=-1 out=local::unused;
+#call core::try::pop;
+#call core::exit;
+#call core::label name="width";
+#call core::error::code err=err::0 out=return::width;
+#call core::const out=local::bad value="1e999";
+#call core::try::push handler="bad";
+#call core::num::parse value=local::bad out=local::unused;
+#call core::try::pop;
+#call core::exit;
+#call core::label name="bad";
+#call core::error::code err=err::0 out=return::bad;
+#call core::exit;
+"#,
+        );
+
+        for enable_jit in [true, false] {
+            let mut vm = Vm::new(VmConfig {
+                enable_jit,
+                ..VmConfig::default()
+            });
+            let returns = vm.run_main(&module).expect("run").returns;
+            assert_eq!(
+                returns,
+                vec![
+                    Value::Num(67.0),
+                    Value::Str(Arc::from("3.14")),
+                    Value::Str(Arc::from("   25.0")),
+                    Value::Str(Arc::from("7.000")),
+                    Value::Str(Arc::from("format_arg")),
+                    Value::Str(Arc::from("parse_error")),
+                ]
+            );
+        }
+    }
+
+    #[test]
+    fn mod_pow_and_neg_cover_nums_and_ints() {
+        let module = compile_source(
+            r#"
+#call core::const out=local::a value=-7;
+#call core::const out=local::b value=3;
+#call core::mod a=local::a b=local::b out=return::rem;
+#call core::pow a=local::b b=local::b out=return::cube;
+#call core::neg value=local::a out=return::neg;
+#call core::int::parse value=2 out=local::two;
+#call core::int::parse value=62 out=local::big;
+#call core::pow a=local::two b=local::big out=return::int_pow;
+#call core::int::parse value=-1 out=local::minus;
+#call core::pow a=local::two b=local::minus out=return::half;
+#call core::try::push handler="zero";
+#call core::const out=local::zero value=0;
+#call core::mod a=local::a b=local::zero out=local::unused;
+#call core::try::pop;
+#call core::exit;
+#call core::label name="zero";
+#call core::error::code err=err::0 out=return::zero;
+#call core::exit;
+"#,
+        );
+
+        for enable_jit in [true, false] {
+            let mut vm = Vm::new(VmConfig {
+                enable_jit,
+                ..VmConfig::default()
+            });
+            let returns = vm.run_main(&module).expect("run").returns;
+            assert_eq!(
+                returns,
+                vec![
+                    Value::Num(-1.0),
+                    Value::Num(27.0),
+                    Value::Num(7.0),
+                    Value::Int(1 << 62),
+                    Value::Num(0.5),
+                    Value::Str(Arc::from("div_zero")),
+                ]
+            );
+        }
+    }
+
+    #[test]
+    fn full_comparison_set_agrees_across_kinds() {
+        let module = compile_source(
+            r#"
+#call core::const out=local::two value=2;
+#call core::const out=local::three value=3;
+#call core::gt a=local::three b=local::two out=return::gt;
+#call core::ge a=local::two b=local::two out=return::ge;
+#call core::le a=local::three b=local::two out=return::le;
+#call core::ne a=local::two b=local::three out=return::ne;
+#call core::const out=local::apple value="apple";
+#call core::const out=local::pear value="pear";
+#call core::gt a=local::apple b=local::pear out=return::str_gt;
+#call core::int::parse value=2 out=local::int_two;
+#call core::ge a=local::int_two b=local::two out=return::mixed_ge;
+#call core::ne a=local::int_two b=local::two out=return::mixed_ne;
+#call core::lt a=local::int_two b=local::two out=return::mixed_lt;
+#call core::le a=local::two b=local::int_two out=return::mixed_le;
+#call core::eq a=local::two b=local::int_two out=return::mixed_eq;
+#call core::const out=local::half value=2.5;
+#call core::lt a=local::int_two b=local::half out=return::fraction_lt;
+#call core::gt a=local::half b=local::int_two out=return::fraction_gt;
+#call core::exit;
+"#,
+        );
+
+        for enable_jit in [true, false] {
+            let mut vm = Vm::new(VmConfig {
+                enable_jit,
+                ..VmConfig::default()
+            });
+            let returns = vm.run_main(&module).expect("run").returns;
+            // An int and a num compare by value: `int(2)` equals `2`.
+            let flags = [
+                true, true, false, true, false, true, false, false, true, true, true, true,
+            ]
+            .map(Value::Bool);
+            assert_eq!(returns, flags);
+        }
+    }
+
+    #[test]
+    fn host_handles_round_trip_through_imp_code() {
+        struct Conn {
+            rows: f64,
+            dropped: Arc<std::sync::atomic::AtomicBool>,
+        }
+        impl Drop for Conn {
+            fn drop(&mut self) {
+                self.dropped
+                    .store(true, std::sync::atomic::Ordering::SeqCst);
+            }
+        }
+
+        let module = compile_source(
+            r#"
+#call core::const out=local::rows value=3;
+#call core::host::call name="db::open" args="local::rows" out=local::conn;
+#call core::host::call name="db::count" args="local::conn" out=return::value;
+#call core::try::push handler="bad";
+#call core::host::call name="db::count" args="local::rows" out=local::unused;
+#call core::try::pop;
+#call core::exit;
+#call core::label name="bad";
+#call core::mov from=err::0 to=return::err;
+#call core::exit;
+"#,
+        );
+
+        for enable_jit in [true, false] {
+            let dropped = Arc::new(std::sync::atomic::AtomicBool::new(false));
+            let mut host_fns = HostFunctions::default();
+            let flag = Arc::clone(&dropped);
+            host_fns.register("db::open", move |args| {
+                Ok(Value::Handle(HostHandle::new(Conn {
+                    rows: args[0].as_num().unwrap_or(0.0),
+                    dropped: Arc::clone(&flag),
+                })))
+            });
+            host_fns.register("db::count", |args| match &args[0] {
+                Value::Handle(handle) if handle.is::<Conn>() => Ok(Value::Num(
+                    handle.downcast_ref::<Conn>().expect("conn").rows,
+                )),
+                other => Err(HostFnError::bad_arg("db::count", 0, "a connection", other)),
+            });
+            let mut vm = Vm::new(VmConfig {
+                enable_jit,
+                host_fns,
+                ..VmConfig::default()
+            });
+            let returns = vm.run_main(&module).expect("run").returns;
+            assert_eq!(returns[0], Value::Num(3.0));
+            let Value::Error { code, msg, .. } = &returns[1] else {
+                panic!("expected host error, got {returns:?}");
+            };
+            assert_eq!(code.as_ref(), "host_arg");
+            assert!(msg.contains("expects a connection, found num"));
+            assert!(dropped.load(std::sync::atomic::Ordering::SeqCst));
+        }
+    }
+
+    #[test]
+    fn sandbox_capabilities_throw_instead_of_opening_side_channels() {
+        let module = compile_source(
+            r#"
+#call core::const out=local::x value=1;
+#call core::try::push handler="print";
+#call core::host::print value=local::x;
+#call core::try::pop;
+#call core::label name="print";
+#call core::error::code err=err::0 out=return::print;
+#call core::try::push handler="now";
+#call core::host::call name="time::now" args="" out=return::now;
+#call core::try::pop;
+#call core::label name="now";
+#call core::error::code err=err::0 out=return::now_err;
+#call core::host::call name="math::id" args="local::x" out=return::id;
+#call core::exit;
+"#,
+        );
+
+        for enable_jit in [true, false] {
+            let mut host_fns = HostFunctions::default();
+            host_fns.register_with("time::now", Capability::Time, |_| Ok(Value::Num(42.0)));
+            host_fns.register("math::id", |args| Ok(args[0].clone()));
+            let output = Arc::new(OutputBuffer::default());
+            let cfg = VmConfig {
+                enable_jit,
+                writer: Some(output.clone()),
+                host_fns,
+                ..VmConfig::default()
+            };
+
+            let mut vm = Vm::new(VmConfig {
+                capabilities: Capabilities::sandbox(),
+                ..cfg.clone()
+            });
+            let returns = vm.run_main(&module).expect("run").returns;
+            let denied = Value::Str(Arc::from("cap_denied"));
+            assert_eq!(
+                returns,
+                vec![denied.clone(), Value::Null, denied, Value::Num(1.0)],
+                "jit={enable_jit}"
+            );
+            assert!(output.lines().is_empty());
+
+            let mut vm = Vm::new(cfg);
+            let returns = vm.run_main(&module).expect("run").returns;
+            assert_eq!(
+                returns,
+                vec![Value::Null, Value::Num(42.0), Value::Null, Value::Num(1.0)]
+            );
+            assert_eq!(output.lines().len(), 1);
+        }
+    }
+
+    #[test]
+    fn host_policy_denial_is_catchable() {
+        let module = compile_source(
+            r#"
+#call core::try::push handler="denied";
+#call core::const out=local::x value=1;
+#call core::host::print value=local::x;
+#call core::try::pop;
+#call core::const out=return::value value="printed";
+#call core::exit;
+#call core::label name="denied";
+#call core::mov from=err::0 to=return::value;
+#call core::exit;
+"#,
+        );
+
+        for enable_jit in [true, false] {
+            let mut vm = Vm::new(VmConfig {
+                enable_host_print: false,
+                enable_jit,
+                host_policy: HostPolicy {
+                    deny: vec!["core::host::*".to_owned()],
+                    ..HostPolicy::default()
+                },
+                ..VmConfig::default()
+            });
+            let result = vm.run_main(&module).expect("run");
+            assert!(matches!(
+                &result.returns[0],
+                Value::Error { code, .. } if code.as_ref() == "cap_denied"
+            ));
+        }
+
+        let mut vm = Vm::new(VmConfig {
+            enable_host_print: false,
+            host_policy: HostPolicy::allow_only(["core::host::print"]),
+            ..VmConfig::default()
+        });
+        let result = vm.run_main(&module).expect("run allowed");
+        assert_eq!(result.returns, vec![Value::Str(Arc::from("printed"))]);
+    }
+
+    #[test]
+    fn host_policy_hook_overrides_lists() {
+        let policy = HostPolicy {
+            deny: vec!["core::host".to_owned()],
+            hook: Some(Arc::new(|op, listed| listed || op == "core::host::print")),
+            ..HostPolicy::default()
+        };
+        assert!(policy.permits("core::host::print"));
+        assert!(!policy.permits("core::host::eprint"));
+        assert!(!HostPolicy::allow_only(["core::hostile"]).permits("core::host::print"));
+    }
+
+    #[test]
+    fn audit_log_records_host_ops() {
+        let module = compile_source(
+            r#"
+#call core::fn::begin name=main::shout args="x" retshape="scalar";
+#call core::host::print value=arg::x;
+#call core::mov from=arg::x to=return::value;
+#call core::exit;
+#call core::fn::end;
+#call core::const out=local::x value="hi";
+#call main::shout args="local::x" out=return::value;
+#call core::exit;
+"#,
+        );
+        let log = Arc::new(AuditLog::default());
+        let mut vm = Vm::new(VmConfig {
+            enable_host_print: false,
+            audit: Some(log.clone()),
+            ..VmConfig::default()
+        });
+        vm.run_main(&module).expect("run");
+
+        let entries = log.entries();
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].op.as_ref(), "core::host::print");
+        assert_eq!(entries[0].function.as_ref(), "main::shout");
+        assert_eq!(entries[0].pc, 0);
+        assert!(entries[0].allowed);
+        assert_eq!(entries[0].args, vec!["\"hi\"".to_owned()]);
+    }
+
+    #[test]
+    fn host_env_stubs_clocks_for_audit_and_profile() {
+        struct SteppingEnv {
+            base: std::time::Instant,
+            reads: Mutex<u32>,
+        }
+        impl HostEnv for SteppingEnv {
+            fn now(&self) -> SystemTime {
+                SystemTime::UNIX_EPOCH + Duration::from_secs(1_000)
+            }
+            fn monotonic(&self) -> std::time::Instant {
+                let mut reads = self.reads.lock().expect("reads");
+                *reads += 1;
+                self.base + Duration::from_millis(5) * *reads
+            }
+            fn random_seed(&self) -> u64 {
+                7
+            }
+        }
+
+        let module = compile_source(
+            r#"
+#call core::const out=local::x value="hi";
+#call core::host::print value=local::x;
+#call core::exit;
+"#,
+        );
+        let log = Arc::new(AuditLog::default());
+        let mut vm = Vm::new(VmConfig {
+            enable_host_print: false,
+            audit: Some(log.clone()),
+            profile: true,
+            env: Some(Arc::new(SteppingEnv {
+                base: std::time::Instant::now(),
+                reads: Mutex::new(0),
+            })),
+            ..VmConfig::default()
+        });
+        let stats = vm.run_main(&module).expect("run").stats.expect("stats");
+        // Reads: run start, `<init>` entry and return, run end.
+        assert_eq!(stats.wall_time, Duration::from_millis(15));
+        assert_eq!(stats.functions[0].inclusive_time, Duration::from_millis(5));
+        assert_eq!(
+            log.entries()[0].timestamp,
+            SystemTime::UNIX_EPOCH + Duration::from_secs(1_000)
+        );
+    }
+
+    #[test]
+    fn host_clocks_read_the_host_env_and_need_the_time_capability() {
+        struct TickingEnv {
+            base: std::time::Instant,
+            reads: Mutex<u32>,
+        }
+        impl HostEnv for TickingEnv {
+            fn now(&self) -> SystemTime {
+                SystemTime::UNIX_EPOCH + Duration::from_secs(1_000)
+            }
+            fn monotonic(&self) -> std::time::Instant {
+                let mut reads = self.reads.lock().expect("reads");
+                *reads += 1;
+                self.base + Duration::from_millis(5) * *reads
+            }
+            fn random_seed(&self) -> u64 {
+                7
+            }
+        }
+
+        let module = compile_source(
+            r#"
+#call core::try::push handler="denied";
+#call core::host::now out=return::now;
+#call core::host::monotonic out=local::start;
+#call core::host::monotonic out=local::end;
+#call core::sub a=local::end b=local::start out=return::elapsed;
+#call core::try::pop;
+#call core::exit;
+#call core::label name="denied";
+#call core::error::code err=err::0 out=return::now;
+#call core::exit;
+"#,
+        );
 
-    let target = frame.get(*fn_slot, globals)?;
-    let mut values = Vec::with_capacity(args.len());
-    for slot in args {
-        values.push(frame.get(*slot, globals)?);
+        for enable_jit in [true, false] {
+            let cfg = VmConfig {
+                enable_jit,
+                env: Some(Arc::new(TickingEnv {
+                    base: std::time::Instant::now(),
+                    reads: Mutex::new(0),
+                })),
+                ..VmConfig::default()
+            };
+            let mut vm = Vm::new(cfg.clone());
+            let returns = vm.run_main(&module).expect("run").returns;
+            assert_eq!(returns, vec![Value::Num(1_000_000.0), Value::Num(5.0)]);
+
+            let mut vm = Vm::new(VmConfig {
+                capabilities: Capabilities {
+                    allow_time: false,
+                    ..Capabilities::default()
+                },
+                ..cfg
+            });
+            let returns = vm.run_main(&module).expect("run").returns;
+            assert_eq!(returns[0], Value::Str(Arc::from("cap_denied")));
+        }
     }
 
-    let Value::Func(target_func) = target else {
-        return Err(VmError::Runtime(
-            "invoke target is not a function".to_owned(),
-        ));
-    };
+    #[test]
+    fn host_args_returns_program_args_and_needs_the_env_capability() {
+        let module = compile_source(
+            r#"
+#call core::try::push handler="denied";
+#call core::host::args out=return::args;
+#call core::try::pop;
+#call core::exit;
+#call core::label name="denied";
+#call core::error::code err=err::0 out=return::args;
+#call core::exit;
+"#,
+        );
+        let args = vec![Value::Str(Arc::from("a")), Value::Str(Arc::from("b c"))];
 
-    match vm.execute_function(module, target_func, &values, globals) {
-        Ok(return_values) => {
-            let value = return_values.into_iter().next().unwrap_or(Value::Null);
-            frame.set(*out, value, globals);
-            Ok(StepControl::Next(pc + 1))
+        for enable_jit in [true, false] {
+            let cfg = VmConfig {
+                enable_jit,
+                program_args: vec!["a".to_owned(), "b c".to_owned()],
+                ..VmConfig::default()
+            };
+            let mut vm = Vm::new(cfg.clone());
+            let returns = vm.run_main(&module).expect("run").returns;
+            assert_eq!(returns, vec![Value::List(args.clone())], "jit={enable_jit}");
+
+            let mut vm = Vm::new(VmConfig {
+                capabilities: Capabilities::sandbox(),
+                ..cfg
+            });
+            let returns = vm.run_main(&module).expect("run").returns;
+            assert_eq!(returns, vec![Value::Str(Arc::from("cap_denied"))]);
+        }
+    }
+
+    #[test]
+    fn vm_config_can_move_to_another_thread() {
+        fn assert_send<T: Send>() {}
+        assert_send::<VmConfig>();
+    }
+
+    #[test]
+    fn host_read_takes_input_from_the_reader_once_per_read() {
+        let module = compile_source(
+            r#"
+#call core::fn::begin name=main::next_line args="" retshape="scalar";
+#call core::host::read_line out=return::value;
+#call core::exit;
+#call core::fn::end;
+
+#call core::invoke fn=main::next_line args="" out=return::first;
+#call core::invoke fn=main::next_line args="" out=return::second;
+#call core::host::read_all out=return::rest;
+#call core::host::read_line out=return::done;
+#call core::const out=return::end value="end";
+#call core::exit;
+"#,
+        );
+
+        for enable_jit in [true, false] {
+            let cfg = VmConfig {
+                enable_jit,
+                verify_jit: enable_jit,
+                reader: Some(Arc::new(InputBuffer::new("a\r\nb\nc\nd\n"))),
+                ..VmConfig::default()
+            };
+            let returns = Vm::new(cfg.clone()).run_main(&module).expect("run").returns;
+            assert_eq!(
+                returns,
+                vec![
+                    Value::Str(Arc::from("a")),
+                    Value::Str(Arc::from("b")),
+                    Value::Str(Arc::from("c\nd\n")),
+                    Value::Null,
+                    Value::Str(Arc::from("end")),
+                ],
+                "jit={enable_jit}"
+            );
+
+            let err = Vm::new(VmConfig {
+                capabilities: Capabilities::sandbox(),
+                ..cfg
+            })
+            .run_main(&module)
+            .expect_err("sandboxed");
+            assert!(matches!(err, VmError::Thrown { code, .. } if code.as_ref() == "cap_denied"));
         }
-        Err(VmError::Thrown { code, msg }) => {
-            let handled = frame.handle_throw(&code, &msg, globals);
-            if handled {
-                Ok(StepControl::Next(frame.pc))
-            } else {
-                Err(VmError::Thrown { code, msg })
+    }
+
+    #[test]
+    fn host_env_reads_allowed_vars_through_the_host_env() {
+        struct VarsEnv;
+        impl HostEnv for VarsEnv {
+            fn now(&self) -> SystemTime {
+                SystemTime::UNIX_EPOCH
+            }
+            fn monotonic(&self) -> std::time::Instant {
+                std::time::Instant::now()
+            }
+            fn random_seed(&self) -> u64 {
+                0
+            }
+            fn var(&self, name: &str) -> Option<String> {
+                (name == "IMP_MODE").then(|| "prod".to_owned())
             }
         }
-        Err(err) => Err(err),
+
+        let module = compile_source(
+            r#"
+#call core::host::env key="IMP_MODE" out=return::mode;
+#call core::host::env key="IMP_UNSET" out=return::unset;
+#call core::const out=return::secret value="kept";
+#call core::try::push handler="denied";
+#call core::host::env key="SECRET" out=return::secret;
+#call core::try::pop;
+#call core::exit;
+#call core::label name="denied";
+#call core::error::code err=err::0 out=return::secret;
+#call core::exit;
+"#,
+        );
+
+        for enable_jit in [true, false] {
+            let cfg = VmConfig {
+                enable_jit,
+                env: Some(Arc::new(VarsEnv)),
+                ..VmConfig::default()
+            };
+            let mut vm = Vm::new(cfg.clone());
+            let returns = vm.run_main(&module).expect("run").returns;
+            assert_eq!(
+                returns,
+                vec![Value::Str(Arc::from("prod")), Value::Null, Value::Null,],
+                "jit={enable_jit}"
+            );
+
+            let audit = Arc::new(AuditLog::default());
+            let mut vm = Vm::new(VmConfig {
+                env_allowlist: Some(vec!["IMP_MODE".to_owned(), "IMP_UNSET".to_owned()]),
+                audit: Some(Arc::clone(&audit) as Arc<dyn AuditSink>),
+                ..cfg.clone()
+            });
+            let returns = vm.run_main(&module).expect("run").returns;
+            assert_eq!(returns[2], Value::Str(Arc::from("cap_denied")));
+            let reads = audit
+                .entries()
+                .into_iter()
+                .map(|entry| (entry.args, entry.allowed))
+                .collect::<Vec<_>>();
+            assert_eq!(
+                reads,
+                [
+                    (vec![r#""IMP_MODE""#.to_owned()], true),
+                    (vec![r#""IMP_UNSET""#.to_owned()], true),
+                    (vec![r#""SECRET""#.to_owned()], false),
+                ]
+            );
+
+            let err = Vm::new(VmConfig {
+                capabilities: Capabilities::sandbox(),
+                ..cfg
+            })
+            .run_main(&module)
+            .expect_err("sandboxed");
+            assert!(matches!(err, VmError::Thrown { code, .. } if code.as_ref() == "cap_denied"));
+        }
     }
-}
 
-fn step_return_set(
-    _vm: &mut Vm,
-    _module: &CompiledModule,
-    frame: &mut Frame,
-    globals: &mut [Value],
-    operands: &JitOperands,
-    pc: usize,
-) -> Result<StepControl, VmError> {
-    let JitOperands::ReturnSet { slot_id, value } = operands else {
-        return Err(VmError::Runtime(
-            "jit operand mismatch for return_set".to_owned(),
-        ));
-    };
-    let value = frame.get(*value, globals)?;
-    frame.set_ret(*slot_id as usize, value);
-    Ok(StepControl::Next(pc + 1))
-}
+    #[test]
+    fn host_random_is_reproducible_from_rng_seed() {
+        let module = compile_source(
+            r#"
+#call core::host::random out=return::unit;
+#call core::host::random_int lo=-3 hi=4 out=return::die;
+#call core::try::push handler="empty";
+#call core::host::random_int lo=2 hi=2 out=local::unused;
+#call core::try::pop;
+#call core::label name="empty";
+#call core::error::code err=err::0 out=return::empty;
+#call core::exit;
+"#,
+        );
 
-fn step_exit(
-    _vm: &mut Vm,
-    _module: &CompiledModule,
-    _frame: &mut Frame,
-    _globals: &mut [Value],
-    operands: &JitOperands,
-    _pc: usize,
-) -> Result<StepControl, VmError> {
-    if !matches!(operands, JitOperands::None) {
-        return Err(VmError::Runtime("jit operand mismatch for exit".to_owned()));
+        let run = |enable_jit, rng_seed| {
+            let mut vm = Vm::new(VmConfig {
+                enable_jit,
+                verify_jit: enable_jit,
+                rng_seed: Some(rng_seed),
+                ..VmConfig::default()
+            });
+            vm.run_main(&module).expect("run").returns
+        };
+        let first = run(true, 42);
+        assert_eq!(first, run(false, 42));
+        assert_ne!(first, run(true, 43));
+        let Value::Num(unit) = first[0] else {
+            panic!("expected a num, got {first:?}");
+        };
+        assert!((0.0..1.0).contains(&unit));
+        let Value::Int(die) = first[1] else {
+            panic!("expected an int, got {first:?}");
+        };
+        assert!((-3..4).contains(&die));
+        assert_eq!(first[2], Value::Str(Arc::from("random_range")));
+
+        let mut vm = Vm::new(VmConfig {
+            capabilities: Capabilities::sandbox(),
+            ..VmConfig::default()
+        });
+        let err = vm.run_main(&module).expect_err("denied");
+        assert!(matches!(err, VmError::Thrown { code, .. } if code.as_ref() == "cap_denied"));
     }
-    Ok(StepControl::Exit)
-}
 
-fn step_throw(
-    _vm: &mut Vm,
-    _module: &CompiledModule,
-    frame: &mut Frame,
-    globals: &mut [Value],
-    operands: &JitOperands,
-    _pc: usize,
-) -> Result<StepControl, VmError> {
-    let JitOperands::Throw { code, msg } = operands else {
-        return Err(VmError::Runtime(
-            "jit operand mismatch for throw".to_owned(),
+    #[test]
+    fn host_fs_stays_inside_its_roots() {
+        let root = std::env::temp_dir().join("imp_vm_host_fs");
+        let _ = std::fs::remove_dir_all(&root);
+        std::fs::create_dir_all(&root).expect("temp dir");
+        let file = root.join("note.txt");
+        let source = format!(
+            r#"
+#call core::const out=local::path value="{file}";
+#call core::const out=local::text value="hello";
+#call core::host::fs::exists path=local::path out=return::before;
+#call core::host::fs::write path=local::path data=local::text;
+#call core::host::fs::read path=local::path out=return::text;
+#call core::const out=local::missing value="{missing}";
+#call core::try::push handler="missing";
+#call core::host::fs::read path=local::missing out=local::unused;
+#call core::try::pop;
+#call core::label name="missing";
+#call core::error::code err=err::0 out=return::missing;
+#call core::const out=local::outside value="{outside}";
+#call core::try::push handler="outside";
+#call core::host::fs::exists path=local::outside out=local::unused;
+#call core::try::pop;
+#call core::label name="outside";
+#call core::error::code err=err::0 out=return::outside;
+#call core::exit;
+"#,
+            file = file.display(),
+            missing = root.join("missing.txt").display(),
+            outside = root.join("..").join("escape.txt").display(),
+        );
+        let module = compile_source(&source);
+
+        for enable_jit in [true, false] {
+            let _ = std::fs::remove_file(&file);
+            let mut vm = Vm::new(VmConfig {
+                enable_jit,
+                fs_roots: vec![root.clone()],
+                ..VmConfig::default()
+            });
+            let returns = vm.run_main(&module).expect("run").returns;
+            assert_eq!(
+                returns,
+                vec![
+                    Value::Bool(false),
+                    Value::Str(Arc::from("hello")),
+                    Value::Str(Arc::from("io_error")),
+                    Value::Str(Arc::from("cap_denied")),
+                ],
+                "jit={enable_jit}"
+            );
+        }
+
+        // Without roots, file I/O stays off.
+        let err = Vm::new(VmConfig::default())
+            .run_main(&module)
+            .expect_err("no roots");
+        assert!(matches!(err, VmError::Thrown { code, .. } if code.as_ref() == "cap_denied"));
+
+        // Reads are charged by the file's size before they happen.
+        let path = file.display().to_string();
+        let module = compile_source(&format!(
+            r#"
+#call core::const out=local::path value="{path}";
+#call core::host::fs::read path=local::path out=return::text;
+#call core::exit;
+"#
         ));
-    };
-    if frame.handle_throw(code, msg, globals) {
-        return Ok(StepControl::Next(frame.pc));
+        let read = |budget| {
+            Vm::new(VmConfig {
+                fs_roots: vec![root.clone()],
+                max_memory: Some(budget),
+                ..VmConfig::default()
+            })
+            .run_main(&module)
+        };
+        let err = read(path.len() + 4).expect_err("over budget");
+        assert!(matches!(err, VmError::Thrown { code, .. } if code.as_ref() == "oom"));
+        let returns = read(path.len() + 5).expect("within budget").returns;
+        assert_eq!(returns, vec![Value::Str(Arc::from("hello"))]);
+        let _ = std::fs::remove_dir_all(&root);
+    }
+
+    #[test]
+    fn json_roundtrips_and_throws_json_error() {
+        let module = compile_source(
+            r#"
+#call core::fn::begin name=main::id args="x" retshape="scalar";
+#call core::exit;
+#call core::fn::end;
+
+#call core::const out=local::text value="{\"b\": [1, 2.5, true, null], \"a\": \"x\\ny\"}";
+#call core::json::parse value=local::text out=local::data;
+#call core::json::stringify value=local::data out=return::text;
+#call core::obj::get obj=local::data key="b" out=local::list;
+#call core::list::len list=local::list out=return::len;
+#call core::const out=local::bad value="[1,";
+#call core::try::push handler="bad";
+#call core::json::parse value=local::bad out=local::unused;
+#call core::try::pop;
+#call core::label name="bad";
+#call core::error::code err=err::0 out=return::bad;
+#call core::try::push handler="func";
+#call core::json::stringify value=main::id out=local::unused;
+#call core::try::pop;
+#call core::label name="func";
+#call core::error::code err=err::0 out=return::func;
+#call core::exit;
+"#,
+        );
+
+        for enable_jit in [true, false] {
+            let mut vm = Vm::new(VmConfig {
+                enable_jit,
+                ..VmConfig::default()
+            });
+            let returns = vm.run_main(&module).expect("run").returns;
+            assert_eq!(
+                returns,
+                vec![
+                    Value::Str(Arc::from(r#"{"a":"x\ny","b":[1,2.5,true,null]}"#)),
+                    Value::Num(4.0),
+                    Value::Str(Arc::from("json_error")),
+                    Value::Str(Arc::from("json_error")),
+                ],
+                "jit={enable_jit}"
+            );
+        }
+    }
+
+    #[test]
+    fn json_parse_counts_the_containers_it_creates() {
+        let module = compile_source(
+            r#"
+#call core::const out=local::scalar value="5";
+#call core::json::parse value=local::scalar out=local::five;
+#call core::const out=local::text value="{\"a\": [1, {}]}";
+#call core::json::parse value=local::text out=local::data;
+#call core::exit;
+"#,
+        );
+
+        for enable_jit in [true, false] {
+            let mut vm = Vm::new(VmConfig {
+                enable_jit,
+                profile: true,
+                ..VmConfig::default()
+            });
+            let stats = vm.run_main(&module).expect("run").stats.expect("stats");
+            assert_eq!(stats.objects_allocated, 3, "jit={enable_jit}");
+        }
+    }
+
+    #[test]
+    fn invoke_dynamic_spreads_a_runtime_arg_list() {
+        let apply = compile_source(
+            r#"
+#call core::fn::begin name=main::sub args="a,b" retshape="scalar";
+#call core::sub a=arg::a b=arg::b out=return::value;
+#call core::exit;
+#call core::fn::end;
+#call core::fn::begin name=main::apply args="f,xs" retshape="scalar";
+#call core::invoke::dynamic fn=arg::f arglist=arg::xs out=return::value;
+#call core::exit;
+#call core::fn::end;
+#call core::list::new out=local::xs;
+#call core::list::push list=local::xs value=10 out=local::xs;
+#call core::list::push list=local::xs value=4 out=local::xs;
+#call main::apply f=main::sub xs=local::xs out=return::value;
+#call core::exit;
+"#,
+        );
+        let not_a_list = compile_source(
+            r#"
+#call core::fn::begin name=main::sub args="a,b" retshape="scalar";
+#call core::sub a=arg::a b=arg::b out=return::value;
+#call core::exit;
+#call core::fn::end;
+#call core::const out=local::xs value=3;
+#call core::invoke::dynamic fn=main::sub arglist=local::xs out=return::value;
+#call core::exit;
+"#,
+        );
+        for enable_jit in [true, false] {
+            let mut vm = Vm::new(VmConfig {
+                enable_jit,
+                ..VmConfig::default()
+            });
+            let returns = vm.run_main(&apply).expect("run").returns;
+            assert_eq!(returns, vec![Value::Num(6.0)]);
+            let err = vm.run_main(&not_a_list).expect_err("non-list arglist");
+            assert!(
+                err.to_string()
+                    .contains("core::invoke::dynamic arglist is not a list in <init>"),
+                "{err}"
+            );
+        }
+    }
+
+    #[test]
+    fn timeout_annotation_throws_catchable_timeout() {
+        let module = compile_source(
+            r#"
+#call core::fn::begin name=main::forever args="" retshape="scalar";
+#call core::label name="spin";
+#call core::jump target="spin";
+#call core::fn::end;
+#call @timeout(ms=20) core::fn::begin name=main::bounded args="" retshape="scalar";
+#call main::forever out=return::value;
+#call core::exit;
+#call core::fn::end;
+#call core::try::push handler="late";
+#call main::bounded out=return::value;
+#call core::try::pop;
+#call core::exit;
+#call core::label name="late";
+#call core::mov from=err::0 to=return::value;
+#call core::exit;
+"#,
+        );
+        let bounded = module
+            .functions
+            .iter()
+            .find(|function| function.meta.name.as_ref() == "main::bounded")
+            .expect("bounded");
+        assert_eq!(bounded.meta.timeout_ms, Some(20));
+
+        for enable_jit in [true, false] {
+            let mut vm = Vm::new(VmConfig {
+                enable_jit,
+                ..VmConfig::default()
+            });
+            // The callee inherits the deadline, so its loop is cut short too.
+            let returns = vm.run_main(&module).expect("run").returns;
+            let Value::Error { code, msg, .. } = &returns[0] else {
+                panic!("expected a caught timeout, got {:?}", returns[0]);
+            };
+            assert_eq!(code.as_ref(), "timeout");
+            assert_eq!(msg.as_ref(), "main::bounded exceeded its 20ms timeout");
+        }
+
+        let err = compile_program(
+            "#call @timeout(ms=0) core::fn::begin name=main::f args=\"\";\n#call core::fn::end;\n",
+            CompileOpts::default(),
+        )
+        .expect_err("zero timeout");
+        assert!(
+            err.to_string()
+                .contains("expected @timeout(ms=<positive integer>)")
+        );
+    }
+
+    #[test]
+    fn host_print_routes_streams_and_levels_to_writer() {
+        let module = compile_source(
+            r#"
+#call core::const out=local::x value="data";
+#call core::host::print value=local::x;
+#call core::host::eprint value=local::x level="warn";
+#call core::host::print slot=local::x level="debug";
+#call core::exit;
+"#,
+        );
+        let line = |stream, level| OutputLine {
+            stream,
+            level,
+            text: "\"data\"".to_owned(),
+        };
+
+        for enable_jit in [true, false] {
+            let output = Arc::new(OutputBuffer::default());
+            let mut vm = Vm::new(VmConfig {
+                enable_jit,
+                writer: Some(output.clone()),
+                ..VmConfig::default()
+            });
+            vm.run_main(&module).expect("run");
+            assert_eq!(
+                output.lines(),
+                vec![
+                    line(PrintStream::Stdout, PrintLevel::Info),
+                    line(PrintStream::Stderr, PrintLevel::Warn),
+                    line(PrintStream::Stdout, PrintLevel::Debug),
+                ]
+            );
+
+            let output = Arc::new(OutputBuffer::default());
+            let mut vm = Vm::new(VmConfig {
+                enable_jit,
+                writer: Some(output.clone()),
+                host_policy: HostPolicy {
+                    deny: vec!["core::host::eprint".to_owned()],
+                    ..HostPolicy::default()
+                },
+                ..VmConfig::default()
+            });
+            let err = vm.run_main(&module).expect_err("eprint denied");
+            assert!(matches!(err, VmError::Thrown { code, .. } if code.as_ref() == "cap_denied"));
+            assert_eq!(
+                output.lines(),
+                vec![line(PrintStream::Stdout, PrintLevel::Info)]
+            );
+        }
+    }
+
+    #[test]
+    fn invoke_outs_receive_every_return_value() {
+        let module = compile_source(
+            r#"
+#call core::fn::begin name=main::divmod args="a,b" retshape="any" retcount=2;
+#call core::int::parse value=arg::a out=local::a;
+#call core::int::parse value=arg::b out=local::b;
+#call core::div a=local::a b=local::b out=return::0;
+#call core::mod a=local::a b=local::b out=return::1;
+#call core::exit;
+#call core::fn::end;
+#call core::const out=local::a value=17;
+#call core::const out=local::b value=5;
+#call main::divmod args="local::a,local::b" outs="local::q,local::r";
+#call core::fn::bind fn=main::divmod args="local::q" out=local::bound;
+#call core::const out=local::two value=2;
+#call core::invoke fn=local::bound args="local::two" outs="local::q2,local::r2,local::extra";
+#call core::add a=local::q b=local::r out=return::sum;
+#call core::mul a=local::q2 b=local::r2 out=return::product;
+#call core::is::null value=local::extra out=return::extra;
+#call core::exit;
+"#,
+        );
+
+        for enable_jit in [true, false] {
+            let mut vm = Vm::new(VmConfig {
+                enable_jit,
+                ..VmConfig::default()
+            });
+            let returns = vm.run_main(&module).expect("run").returns;
+            assert_eq!(
+                returns,
+                vec![Value::Int(5), Value::Int(1), Value::Bool(true)]
+            );
+        }
     }
-    Err(VmError::Thrown {
-        code: Arc::clone(code),
-        msg: Arc::clone(msg),
-    })
-}
 
-fn step_try_push(
-    _vm: &mut Vm,
-    _module: &CompiledModule,
-    frame: &mut Frame,
-    _globals: &mut [Value],
-    operands: &JitOperands,
-    pc: usize,
-) -> Result<StepControl, VmError> {
-    let JitOperands::TryPush { handler_pc } = operands else {
-        return Err(VmError::Runtime(
-            "jit operand mismatch for try_push".to_owned(),
-        ));
-    };
-    frame.try_stack.push(*handler_pc);
-    Ok(StepControl::Next(pc + 1))
-}
+    #[test]
+    fn reassigned_functions_are_not_inlined() {
+        let module = compile_source(
+            r#"
+#call @inline core::fn::begin name=main::f args="" retshape="scalar";
+#call core::const out=return::value value=2;
+#call core::exit;
+#call core::fn::end;
+#call core::fn::begin name=main::g args="" retshape="scalar";
+#call core::const out=return::value value=101;
+#call core::exit;
+#call core::fn::end;
+#call core::mov from=main::g to=main::f;
+#call main::f args="" out=return::value;
+#call core::exit;
+"#,
+        );
 
-fn step_try_pop(
-    _vm: &mut Vm,
-    _module: &CompiledModule,
-    frame: &mut Frame,
-    _globals: &mut [Value],
-    operands: &JitOperands,
-    pc: usize,
-) -> Result<StepControl, VmError> {
-    if !matches!(operands, JitOperands::None) {
-        return Err(VmError::Runtime(
-            "jit operand mismatch for try_pop".to_owned(),
-        ));
+        for enable_jit in [true, false] {
+            let mut vm = Vm::new(VmConfig {
+                enable_jit,
+                ..VmConfig::default()
+            });
+            let returns = vm.run_main(&module).expect("run").returns;
+            assert_eq!(returns, vec![Value::Num(101.0)], "jit={enable_jit}");
+        }
     }
-    frame.try_stack.pop();
-    Ok(StepControl::Next(pc + 1))
-}
-
-fn step_obj_new(
-    _vm: &mut Vm,
-    _module: &CompiledModule,
-    frame: &mut Frame,
-    globals: &mut [Value],
-    operands: &JitOperands,
-    pc: usize,
-) -> Result<StepControl, VmError> {
-    let JitOperands::UnarySlot { slot } = operands else {
-        return Err(VmError::Runtime(
-            "jit operand mismatch for obj_new".to_owned(),
-        ));
-    };
-    frame.set(*slot, Value::Obj(HashMap::new()), globals);
-    Ok(StepControl::Next(pc + 1))
-}
 
-fn step_obj_set(
-    _vm: &mut Vm,
-    _module: &CompiledModule,
-    frame: &mut Frame,
-    globals: &mut [Value],
-    operands: &JitOperands,
-    pc: usize,
-) -> Result<StepControl, VmError> {
-    let JitOperands::ObjSet {
-        obj,
-        key,
-        value,
-        out,
-    } = operands
-    else {
-        return Err(VmError::Runtime(
-            "jit operand mismatch for obj_set".to_owned(),
-        ));
-    };
+    #[test]
+    fn inlined_calls_start_from_a_fresh_frame_each_time() {
+        let src = r#"
+#call @inline core::fn::begin name=main::tag args="x,y" retshape="any" retcount=2;
+#call core::br cond=arg::x then="set" else="done";
+#call core::label name="set";
+#call core::const out=local::mark value="set";
+#call core::label name="done";
+#call core::mov from=local::mark to=return::0;
+#call core::mov from=arg::y to=return::1;
+#call core::exit;
+#call core::fn::end;
+#call @inline core::fn::begin name=main::parse args="text" retshape="scalar";
+#call core::int::parse value=arg::text out=return::value;
+#call core::exit;
+#call core::fn::end;
+#call core::const out=local::yes value=true;
+#call core::const out=local::no value=false;
+#call main::tag args="local::yes,local::yes" outs="local::first,local::y1";
+#call main::tag args="local::no" outs="local::second,local::y2,local::extra";
+#call core::mov from=local::first to=return::first;
+#call core::type_of value=local::second out=return::second;
+#call core::type_of value=local::y2 out=return::y2;
+#call core::type_of value=local::extra out=return::extra;
+#call core::const out=local::bad value="nope";
+#call core::try::push handler="caught";
+#call main::parse args="local::bad" out=return::parsed;
+#call core::try::pop;
+#call core::exit;
+#call core::label name="caught";
+#call core::error::code err=err::0 out=return::parsed;
+#call core::exit;
+"#;
 
-    let mut object = match frame.get(*obj, globals)? {
-        Value::Obj(map) => map,
-        _ => {
-            return Err(VmError::Runtime(
-                "core::obj::set target is not an object".to_owned(),
-            ));
+        for opt_level in [0, 1] {
+            let module = compile_program(
+                src,
+                CompileOpts {
+                    opt_level,
+                    ..CompileOpts::default()
+                },
+            )
+            .expect("compile")
+            .module;
+            let init = module.function(0).expect("init");
+            assert!(
+                !init
+                    .code
+                    .iter()
+                    .any(|instr| matches!(instr, Instr::Invoke { .. } | Instr::InvokeMulti { .. }))
+            );
+            for enable_jit in [true, false] {
+                let mut vm = Vm::new(VmConfig {
+                    enable_jit,
+                    ..VmConfig::default()
+                });
+                let returns = vm.run_main(&module).expect("run").returns;
+                assert_eq!(
+                    returns,
+                    vec![
+                        Value::Str("set".into()),
+                        Value::Str("null".into()),
+                        Value::Str("null".into()),
+                        Value::Str("null".into()),
+                        Value::Str("int_parse".into()),
+                    ]
+                );
+            }
         }
-    };
-    let key_text = value_to_text(&frame.get(*key, globals)?)?;
-    object.insert(key_text, frame.get(*value, globals)?);
-    frame.set(*out, Value::Obj(object), globals);
-    Ok(StepControl::Next(pc + 1))
-}
+    }
 
-fn step_obj_get(
-    _vm: &mut Vm,
-    _module: &CompiledModule,
-    frame: &mut Frame,
-    globals: &mut [Value],
-    operands: &JitOperands,
-    pc: usize,
-) -> Result<StepControl, VmError> {
-    let JitOperands::ObjLookup {
-        kind,
-        obj,
-        key,
-        out,
-    } = operands
-    else {
-        return Err(VmError::Runtime(
-            "jit operand mismatch for obj_lookup".to_owned(),
-        ));
-    };
+    #[test]
+    fn fn_meta_reflects_function_metadata() {
+        let module = compile_source(
+            r#"
+#call core::fn::begin name=main::pair args="a,b" retshape="record(left,right)";
+#call core::exit;
+#call core::fn::end;
+#call core::fn::meta fn=main::pair out=return::value;
+#call core::exit;
+"#,
+        );
 
-    let object = frame.get(*obj, globals)?;
-    let key_text = value_to_text(&frame.get(*key, globals)?)?;
-    let value = object_lookup(&object, &key_text)?;
-    match kind {
-        ObjLookupKind::Get => frame.set(*out, value.unwrap_or(Value::Null), globals),
-        ObjLookupKind::Has => frame.set(*out, Value::Bool(value.is_some()), globals),
+        for enable_jit in [true, false] {
+            let mut vm = Vm::new(VmConfig {
+                enable_jit,
+                ..VmConfig::default()
+            });
+            let returns = vm.run_main(&module).expect("run").returns;
+            assert_eq!(
+                returns[0].to_string(),
+                r#"{arg_count: 2, name: "main::pair", ret_count: 1, retshape: "record(left,right)"}"#
+            );
+        }
     }
-    Ok(StepControl::Next(pc + 1))
-}
 
-fn step_str(
-    _vm: &mut Vm,
-    _module: &CompiledModule,
-    frame: &mut Frame,
-    globals: &mut [Value],
-    operands: &JitOperands,
-    pc: usize,
-) -> Result<StepControl, VmError> {
-    let JitOperands::StrOp { kind, a, b, out } = operands else {
-        return Err(VmError::Runtime(
-            "jit operand mismatch for str op".to_owned(),
-        ));
-    };
+    #[test]
+    fn fn_bind_prefills_leading_args() {
+        let module = compile_source(
+            r#"
+#call core::fn::begin name=main::sub3 args="a,b,c" retshape="scalar";
+#call core::sub a=arg::a b=arg::b out=local::t;
+#call core::sub a=local::t b=arg::c out=return::value;
+#call core::exit;
+#call core::fn::end;
+#call core::fn::begin name=main::apply args="f,x" retshape="scalar";
+#call arg::f args="arg::x" out=return::value;
+#call core::exit;
+#call core::fn::end;
+#call core::const out=local::ten value=10;
+#call core::const out=local::three value=3;
+#call core::const out=local::two value=2;
+#call core::fn::bind fn=main::sub3 args="local::ten" out=local::from_ten;
+#call core::fn::bind fn=local::from_ten args="local::three" out=local::f;
+#call main::apply args="local::f,local::two" out=return::value;
+#call core::fn::meta fn=local::f out=local::meta;
+#call core::const out=local::key value="arg_count";
+#call core::obj::get obj=local::meta key=local::key out=return::arity;
+#call core::exit;
+"#,
+        );
 
-    match kind {
-        StrOpKind::Concat => {
-            let a_slot = a.ok_or_else(|| VmError::Runtime("str concat missing a".to_owned()))?;
-            let b_slot = b.ok_or_else(|| VmError::Runtime("str concat missing b".to_owned()))?;
-            let av = value_to_text(&frame.get(a_slot, globals)?)?;
-            let bv = value_to_text(&frame.get(b_slot, globals)?)?;
-            frame.set(*out, Value::Str(Arc::from(format!("{av}{bv}"))), globals);
-        }
-        StrOpKind::Len => {
-            let value_slot =
-                a.ok_or_else(|| VmError::Runtime("str len missing value".to_owned()))?;
-            let text = value_to_text(&frame.get(value_slot, globals)?)?;
-            frame.set(*out, Value::Num(text.chars().count() as f64), globals);
+        for enable_jit in [true, false] {
+            let mut vm = Vm::new(VmConfig {
+                enable_jit,
+                ..VmConfig::default()
+            });
+            let returns = vm.run_main(&module).expect("run").returns;
+            assert_eq!(returns, vec![Value::Num(5.0), Value::Num(1.0)]);
         }
     }
 
-    Ok(StepControl::Next(pc + 1))
-}
+    #[test]
+    fn on_uncaught_can_recover_or_reraise() {
+        let module = compile_source(
+            r#"
+#call core::fn::begin name=main::fail args="code" retshape="scalar";
+#call core::const out=local::bad value="bad";
+#call core::eq a=arg::code b=local::bad out=local::is_bad;
+#call core::br cond=local::is_bad then="bad" else="runtime";
+#call core::label name="bad";
+#call core::throw code="bad" msg="bad input";
+#call core::label name="runtime";
+#call core::div a=arg::code b=local::bad out=return::value;
+#call core::exit;
+#call core::fn::end;
+"#,
+        );
+        let seen = Arc::new(Mutex::new(Vec::new()));
+        let log = Arc::clone(&seen);
+        let mut vm = Vm::new(VmConfig {
+            on_uncaught: Some(Arc::new(move |code, msg, trace| {
+                log.lock()
+                    .unwrap()
+                    .push(format!("{code}: {msg} @ {}", trace[0].function));
+                if code == "bad" {
+                    ErrorDisposition::Return(vec![Value::Num(-1.0)])
+                } else {
+                    ErrorDisposition::Raise
+                }
+            })),
+            ..VmConfig::default()
+        });
+        vm.run_main(&module).expect("init");
+        let fail = module.function_globals[0].1;
 
-fn step_host_print(
-    vm: &mut Vm,
-    _module: &CompiledModule,
-    frame: &mut Frame,
-    globals: &mut [Value],
-    operands: &JitOperands,
-    pc: usize,
-) -> Result<StepControl, VmError> {
-    let JitOperands::UnarySlot { slot } = operands else {
-        return Err(VmError::Runtime(
-            "jit operand mismatch for host_print".to_owned(),
+        let bad = Value::Str(Arc::from("bad"));
+        assert_eq!(
+            vm.invoke(fail, &[bad]).expect("recovered"),
+            vec![Value::Num(-1.0)]
+        );
+        let other = Value::Str(Arc::from("other"));
+        assert!(matches!(
+            vm.invoke(fail, &[other]),
+            Err(VmError::Runtime { .. })
         ));
-    };
-    if vm.cfg.enable_host_print {
-        println!("{:?}", frame.get(*slot, globals)?);
+        let seen = seen.lock().unwrap();
+        assert_eq!(seen.len(), 2);
+        assert_eq!(seen[0], "bad: bad input @ main::fail");
+        assert!(seen[1].starts_with("runtime: "), "{}", seen[1]);
     }
-    Ok(StepControl::Next(pc + 1))
-}
 
-fn object_lookup(object: &Value, key: &str) -> Result<Option<Value>, VmError> {
-    match object {
-        Value::Obj(map) => Ok(map.get(key).cloned()),
-        _ => Err(VmError::Runtime(
-            "object lookup target is not an object".to_owned(),
-        )),
-    }
-}
+    #[test]
+    fn global_deltas_name_the_globals_a_run_changed() {
+        let module = compile_source(
+            r#"
+#call core::fn::begin name=main::double args="x" retshape="scalar";
+#call core::add a=arg::x b=arg::x out=return::value;
+#call core::exit;
+#call core::fn::end;
+#call core::const out=mod::count value=3;
+#call core::const out=mod::same value=null;
+"#,
+        );
+        let mut vm = Vm::new(VmConfig {
+            track_global_deltas: true,
+            ..VmConfig::default()
+        });
+        let deltas = vm
+            .run_main(&module)
+            .expect("run")
+            .global_deltas
+            .expect("deltas");
+        assert_eq!(
+            deltas,
+            vec![GlobalDelta {
+                name: "mod::count".to_owned(),
+                before: Value::Null,
+                after: Value::Num(3.0),
+            }]
+        );
 
-fn value_to_text(value: &Value) -> Result<String, VmError> {
-    match value {
-        Value::Null => Ok("null".to_owned()),
-        Value::Bool(v) => Ok(v.to_string()),
-        Value::Num(v) => Ok(v.to_string()),
-        Value::Str(v) => Ok(v.to_string()),
-        Value::Error { code, msg } => Ok(format!("error({code}): {msg}")),
-        Value::Obj(_) | Value::Func(_) => Err(VmError::Runtime(
-            "cannot convert complex value to string".to_owned(),
-        )),
+        let mut vm = Vm::new(VmConfig::default());
+        assert!(vm.run_main(&module).expect("run").global_deltas.is_none());
     }
-}
 
-fn validate_retshape(meta: &FnMeta, values: &[Value]) -> Result<(), VmError> {
-    match &meta.retshape {
-        RetShape::Scalar => {
-            if values.len() != 1 {
-                return Err(VmError::Runtime(format!(
-                    "{} expects scalar return with 1 slot, got {}",
-                    meta.name,
-                    values.len()
-                )));
-            }
-        }
-        RetShape::Either(allowed) => {
-            if values.len() != 1 {
-                return Err(VmError::Runtime(format!(
-                    "{} expects single either slot",
-                    meta.name
-                )));
-            }
-            if let Value::Str(value) = &values[0]
-                && allowed.iter().any(|item| item == value.as_ref())
-            {
-                return Ok(());
-            }
-            return Err(VmError::Runtime(format!(
-                "{} return is not in either(...) set",
-                meta.name
-            )));
-        }
-        RetShape::Record(fields) => {
-            if values.len() != 1 {
-                return Err(VmError::Runtime(format!(
-                    "{} expects single record slot",
-                    meta.name
-                )));
-            }
-            let Value::Obj(map) = &values[0] else {
-                return Err(VmError::Runtime(format!(
-                    "{} return is not an object for record shape",
-                    meta.name
-                )));
-            };
-            for field in fields {
-                if !map.contains_key(field) {
-                    return Err(VmError::Runtime(format!(
-                        "{} missing record field '{field}'",
-                        meta.name
-                    )));
-                }
-            }
+    #[test]
+    fn lambdas_capture_locals_by_value() {
+        let module = compile_source(
+            r#"
+#call core::fn::begin name=main::make_adder args="n" retshape="scalar";
+#call core::fn::lambda out=return::value captures="arg::n" args="x" retshape="scalar";
+#call core::add a=local::n b=arg::x out=return::value;
+#call core::exit;
+#call core::fn::end;
+#call core::exit;
+#call core::fn::end;
+#call core::fn::begin name=main::apply args="f,x" retshape="scalar";
+#call arg::f args="arg::x" out=return::value;
+#call core::exit;
+#call core::fn::end;
+#call core::const out=local::five value=5;
+#call core::const out=local::two value=2;
+#call main::make_adder args="local::five" out=local::add5;
+#call main::apply args="local::add5,local::two" out=return::sum;
+#call core::const out=local::scale value=10;
+#call core::fn::lambda out=local::scaled captures="local::scale,local::add5" args="x";
+#call local::add5 args="arg::x" out=local::y;
+#call core::mul a=local::y b=local::scale out=return::value;
+#call core::const out=local::scale value=0;
+#call core::exit;
+#call core::fn::end;
+#call core::const out=local::scale value=3;
+#call local::scaled args="local::two" out=return::scaled;
+#call core::fn::bind fn=local::scaled args="local::five" out=local::bound;
+#call core::invoke fn=local::bound out=return::bound;
+#call core::fn::meta fn=local::add5 out=local::meta;
+#call core::const out=local::key value="arg_count";
+#call core::obj::get obj=local::meta key=local::key out=return::arity;
+#call core::exit;
+"#,
+        );
+
+        for enable_jit in [true, false] {
+            let mut vm = Vm::new(VmConfig {
+                enable_jit,
+                ..VmConfig::default()
+            });
+            let returns = vm.run_main(&module).expect("run").returns;
+            assert_eq!(
+                returns,
+                vec![
+                    Value::Num(7.0),
+                    Value::Num(70.0),
+                    Value::Num(100.0),
+                    Value::Num(1.0)
+                ]
+            );
         }
-        RetShape::Any => {}
     }
-    Ok(())
-}
 
-#[derive(Debug, Clone)]
-struct Frame {
-    code: Arc<[Instr]>,
-    pc: usize,
-    locals: Vec<Value>,
-    args: Vec<Value>,
-    ret: Vec<Value>,
-    err: Vec<Value>,
-    try_stack: Vec<usize>,
-    meta: FnMeta,
-}
+    #[test]
+    fn module_consts_are_folded_and_exported() {
+        let module = compile_source(
+            r#"
+#call core::mod::const name="SCALE" value=4;
+#call core::fn::begin name=main::scale args="x" retshape="scalar";
+#call core::mul a=arg::x b=mod::SCALE out=return::value;
+#call core::exit;
+#call core::fn::end;
+#call core::const out=local::x value=2;
+#call main::scale args="local::x" out=return::value;
+#call main::scale args="mod::SCALE" out=return::squared;
+#call core::exit;
+"#,
+        );
 
-impl Frame {
-    fn new(function: &CompiledFunction, args: &[Value]) -> Self {
-        let mut frame_args = vec![Value::Null; function.arg_count as usize];
-        for (index, value) in args.iter().enumerate() {
-            if index >= frame_args.len() {
-                break;
-            }
-            frame_args[index] = value.clone();
-        }
+        let mut vm = Vm::new(VmConfig::default());
+        let result = vm.run_main(&module).expect("run");
+        assert_eq!(result.returns, vec![Value::Num(8.0), Value::Num(16.0)]);
+        assert_eq!(result.export::<f64>("SCALE"), Ok(4.0));
+    }
 
-        Self {
-            code: Arc::clone(&function.code),
-            pc: 0,
-            locals: vec![Value::Null; function.local_count as usize],
-            args: frame_args,
-            ret: vec![Value::Null; function.ret_count as usize],
-            err: vec![Value::Null; function.err_count.max(1) as usize],
-            try_stack: Vec::new(),
-            meta: function.meta.clone(),
+    #[test]
+    fn coverage_counts_instruction_hits() {
+        let module = compile_source(
+            r#"
+#call core::fn::begin name=main::never args="" retshape="scalar";
+#call core::exit;
+#call core::fn::end;
+#call core::const out=local::flag value=false;
+#call core::br cond=local::flag then="skipped" else="done";
+#call core::label name="skipped";
+#call core::const out=return::value value=1;
+#call core::label name="done";
+#call core::exit;
+"#,
+        );
+
+        for enable_jit in [true, false] {
+            let mut vm = Vm::new(VmConfig {
+                enable_jit,
+                coverage: true,
+                ..VmConfig::default()
+            });
+            vm.run_main(&module).expect("run");
+            vm.run_main(&module).expect("run again");
+
+            let coverage = vm.coverage();
+            let [init, never] = coverage.functions() else {
+                panic!("expected two functions");
+            };
+            assert_eq!(init.function.as_ref(), "<init>");
+            assert_eq!(init.hits, vec![2, 2, 0, 2]);
+            assert_eq!(never.covered(), 0);
+            assert_eq!((coverage.covered(), coverage.total()), (3, 5));
+            assert!(
+                coverage
+                    .report()
+                    .ends_with("coverage: 3/5 instructions (60.0%)\n")
+            );
+            assert_eq!(
+                coverage.lcov(),
+                "TN:\nSF:main\n\
+                 FN:5,<init>\nFNDA:2,<init>\nFN:3,main::never\nFNDA:0,main::never\n\
+                 FNF:2\nFNH:1\n\
+                 DA:3,0\nDA:5,2\nDA:6,2\nDA:8,0\nDA:10,2\n\
+                 LF:5\nLH:3\nend_of_record\n"
+            );
         }
     }
 
-    fn get(&self, slot: Slot, globals: &[Value]) -> Result<Value, VmError> {
-        match slot {
-            Slot::Local(index) => self
-                .locals
-                .get(index as usize)
-                .cloned()
-                .ok_or_else(|| VmError::Runtime(format!("local slot {index} out of range"))),
-            Slot::Global(index) => globals
-                .get(index as usize)
-                .cloned()
-                .ok_or_else(|| VmError::Runtime(format!("global slot {index} out of range"))),
-            Slot::Arg(index) => self
-                .args
-                .get(index as usize)
-                .cloned()
-                .ok_or_else(|| VmError::Runtime(format!("arg slot {index} out of range"))),
-            Slot::Ret(index) => self
-                .ret
-                .get(index as usize)
-                .cloned()
-                .ok_or_else(|| VmError::Runtime(format!("ret slot {index} out of range"))),
-            Slot::Err(index) => self
-                .err
-                .get(index as usize)
-                .cloned()
-                .ok_or_else(|| VmError::Runtime(format!("err slot {index} out of range"))),
+    #[test]
+    fn trace_reports_each_instruction_with_operands() {
+        let module = compile_source(
+            r"
+#call core::const out=local::a value=2;
+#call core::add a=local::a b=local::a out=return::sum;
+#call core::exit;
+",
+        );
+
+        for (enable_jit, verify_jit) in [(true, true), (false, false)] {
+            let log = Arc::new(TraceLog::default());
+            let mut vm = Vm::new(VmConfig {
+                enable_jit,
+                verify_jit,
+                trace: Some(log.clone()),
+                ..VmConfig::default()
+            });
+            vm.run_main(&module).expect("run");
+
+            let lines = log.lines();
+            assert_eq!(lines.len(), 3, "{lines:#?}");
+            assert_eq!(
+                lines[1],
+                format!(
+                    "{}/<init>@0001: {:?} | L0=2",
+                    module.name, module.functions[0].code[1]
+                )
+            );
+            assert!(lines[2].ends_with("@0002: Exit"), "{}", lines[2]);
         }
     }
 
-    fn set(&mut self, slot: Slot, value: Value, globals: &mut [Value]) {
-        match slot {
-            Slot::Local(index) => set_vec_slot(&mut self.locals, index as usize, value),
-            Slot::Global(index) => {
-                if (index as usize) < globals.len() {
-                    globals[index as usize] = value;
-                }
-            }
-            Slot::Arg(index) => set_vec_slot(&mut self.args, index as usize, value),
-            Slot::Ret(index) => set_vec_slot(&mut self.ret, index as usize, value),
-            Slot::Err(index) => set_vec_slot(&mut self.err, index as usize, value),
+    #[test]
+    fn error_wrap_keeps_cause_chain() {
+        let module = compile_source(
+            r#"
+#call core::try::push handler="caught";
+#call core::throw code="not_found" msg="config.toml missing";
+#call core::label name="caught";
+#call core::error::wrap err=err::0 code="io_error" msg="while reading config" out=local::wrapped;
+#call core::throw code="startup_failed" msg="cannot start" cause=local::wrapped;
+"#,
+        );
+
+        for enable_jit in [true, false] {
+            let mut vm = Vm::new(VmConfig {
+                enable_jit,
+                ..VmConfig::default()
+            });
+            let err = vm.run_main(&module).expect_err("rethrown");
+            assert_eq!(
+                err.to_string(),
+                "uncaught throw (startup_failed): cannot start\n\
+                 caused by (io_error): while reading config\n\
+                 caused by (not_found): config.toml missing"
+            );
         }
-    }
 
-    fn set_ret(&mut self, index: usize, value: Value) {
-        set_vec_slot(&mut self.ret, index, value);
+        let module = compile_source(
+            r#"
+#call core::try::push handler="caught";
+#call core::throw code="not_found" msg="missing";
+#call core::label name="caught";
+#call core::error::wrap err=err::0 code="io_error" msg="reading" out=return::wrapped;
+#call core::error::cause err=return::wrapped out=return::cause;
+#call core::error::cause err=return::cause out=return::root;
+#call core::exit;
+"#,
+        );
+        let returns = Vm::new(VmConfig::default())
+            .run_main(&module)
+            .expect("run")
+            .returns;
+        assert_eq!(
+            returns[0].to_string(),
+            "error(io_error): reading; caused by: error(not_found): missing"
+        );
+        assert_eq!(returns[1].to_string(), "error(not_found): missing");
+        assert_eq!(returns[2], Value::Null);
     }
 
-    fn handle_throw(&mut self, code: &str, msg: &str, globals: &mut [Value]) -> bool {
-        if let Some(handler_pc) = self.try_stack.pop() {
-            self.set(
-                Slot::Err(0),
-                Value::Error {
-                    code: Arc::from(code),
-                    msg: Arc::from(msg),
-                },
-                globals,
+    #[test]
+    fn thrown_data_reaches_handlers_across_calls() {
+        let module = compile_source(
+            r#"
+#call core::fn::begin name=main::load args="path" retshape="scalar";
+#call core::obj::new out=local::info;
+#call core::obj::set obj=local::info key="path" value=arg::path;
+#call core::const out=local::line value=3;
+#call core::obj::set obj=local::info key="line" value=local::line;
+#call core::throw code="parse_failed" msg="bad config" data=local::info;
+#call core::fn::end;
+#call core::const out=local::path value="app.toml";
+#call core::try::push handler="caught";
+#call main::load args="local::path" out=local::unused;
+#call core::label name="caught";
+#call core::error::code err=err::0 out=return::code;
+#call core::err::code err=err::0 out=return::short_code;
+#call core::err::msg err=err::0 out=return::msg;
+#call core::err::data err=err::0 out=local::data;
+#call core::obj::get obj=local::data key="line" out=return::line;
+#call core::error::wrap err=err::0 code="startup" msg="cannot start" out=local::wrapped;
+#call core::error::data err=local::wrapped out=return::wrapped_data;
+#call core::error::data err=local::path out=return::not_error;
+#call core::exit;
+"#,
+        );
+
+        for enable_jit in [true, false] {
+            let mut vm = Vm::new(VmConfig {
+                enable_jit,
+                ..VmConfig::default()
+            });
+            let returns = vm.run_main(&module).expect("run").returns;
+            assert_eq!(
+                returns,
+                vec![
+                    Value::Str(Arc::from("parse_failed")),
+                    Value::Str(Arc::from("parse_failed")),
+                    Value::Str(Arc::from("bad config")),
+                    Value::Num(3.0),
+                    Value::Null,
+                    Value::Null,
+                ]
             );
-            self.pc = handler_pc;
-            return true;
         }
-        false
+
+        let module = compile_source(
+            r#"
+#call core::obj::new out=local::info;
+#call core::const out=local::id value=7;
+#call core::obj::set obj=local::info key="id" value=local::id;
+#call core::throw code="missing" msg="no such user" data=local::info;
+"#,
+        );
+        let err = Vm::new(VmConfig::default())
+            .run_main(&module)
+            .expect_err("uncaught");
+        assert_eq!(
+            err.to_string(),
+            "uncaught throw (missing): no such user\ndata: {id: 7}"
+        );
+        let VmError::Thrown {
+            data: Some(data), ..
+        } = err
+        else {
+            panic!("data lost");
+        };
+        assert!(matches!(data.as_ref(), Value::Obj(_)));
     }
-}
 
-fn set_vec_slot(vec: &mut Vec<Value>, index: usize, value: Value) {
-    if index >= vec.len() {
-        vec.resize(index + 1, Value::Null);
+    #[test]
+    fn rethrow_and_finally_keep_the_original_error() {
+        let module = compile_source(
+            r#"
+#call core::fn::begin name=main::risky args="log,fail" retshape="scalar";
+#call core::try::begin;
+#call core::br cond=arg::fail then="fail" else="ok";
+#call core::label name="fail";
+#call core::obj::new out=local::info;
+#call core::throw code="disk_full" msg="no space" data=local::info;
+#call core::label name="ok";
+#call core::const out=return::value value="done";
+#call core::try::finally;
+#call core::obj::set obj=arg::log key="cleaned" value=arg::fail;
+#call core::try::end;
+#call core::exit;
+#call core::fn::end;
+#call core::obj::new out=local::log;
+#call core::const out=local::no value=false;
+#call main::risky args="local::log,local::no" out=return::value;
+#call core::obj::get obj=local::log key="cleaned" out=return::after_ok;
+#call core::const out=local::yes value=true;
+#call core::try::push handler="caught";
+#call main::risky args="local::log,local::yes" out=local::unused;
+#call core::try::pop;
+#call core::exit;
+#call core::label name="caught";
+#call core::obj::get obj=local::log key="cleaned" out=return::after_fail;
+#call core::try::push handler="again";
+#call core::rethrow;
+#call core::label name="again";
+#call core::error::code err=err::0 out=return::code;
+#call core::error::data err=err::0 out=local::data;
+#call core::is::obj value=local::data out=return::kept_data;
+#call core::try::push handler="not_error";
+#call core::rethrow err=local::data;
+#call core::label name="not_error";
+#call core::error::code err=err::0 out=return::not_error;
+#call core::exit;
+"#,
+        );
+
+        for enable_jit in [true, false] {
+            let mut vm = Vm::new(VmConfig {
+                enable_jit,
+                ..VmConfig::default()
+            });
+            let returns = vm.run_main(&module).expect("run").returns;
+            assert_eq!(
+                returns,
+                vec![
+                    Value::Str(Arc::from("done")),
+                    Value::Bool(false),
+                    Value::Bool(true),
+                    Value::Str(Arc::from("disk_full")),
+                    Value::Bool(true),
+                    Value::Str(Arc::from("type_error")),
+                ]
+            );
+        }
     }
-    vec[index] = value;
-}
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use imp_compiler::{FsModuleLoader, compile_module};
-    use imp_ir::{CompiledFunction, CompiledModule, ConstValue, FnMeta, Instr, RetShape, Slot};
-    use std::fs;
-    use std::path::PathBuf;
+    #[test]
+    fn exit_from_a_finally_body_runs_every_cleanup() {
+        let module = compile_source(
+            r#"
+#call core::fn::begin name=main::early args="log" retshape="scalar";
+#call core::try::begin;
+#call core::try::begin;
+#call core::const out=return::value value="early";
+#call core::exit;
+#call core::try::finally;
+#call core::const out=local::yes value=true;
+#call core::obj::set obj=arg::log key="inner" value=local::yes;
+#call core::try::end;
+#call core::const out=return::value value="late";
+#call core::try::finally;
+#call core::const out=local::yes value=true;
+#call core::obj::set obj=arg::log key="outer" value=local::yes;
+#call core::try::end;
+#call core::exit;
+#call core::fn::end;
+#call core::obj::new out=local::log;
+#call main::early args="local::log" out=return::value;
+#call core::obj::get obj=local::log key="inner" out=return::inner;
+#call core::obj::get obj=local::log key="outer" out=return::outer;
+#call core::try::begin;
+#call core::exit;
+#call core::try::finally;
+#call core::const out=return::top value="cleaned";
+#call core::try::end;
+#call core::exit;
+"#,
+        );
 
-    fn scalar_meta(name: &str) -> FnMeta {
-        FnMeta {
-            name: Arc::from(name),
-            arg_count: 0,
-            ret_count: 1,
-            retshape: RetShape::Scalar,
+        for enable_jit in [true, false] {
+            let mut vm = Vm::new(VmConfig {
+                enable_jit,
+                ..VmConfig::default()
+            });
+            let returns = vm.run_main(&module).expect("run").returns;
+            assert_eq!(
+                returns,
+                vec![
+                    Value::Str(Arc::from("early")),
+                    Value::Bool(true),
+                    Value::Bool(true),
+                    Value::Str(Arc::from("cleaned")),
+                ]
+            );
         }
     }
 
     #[test]
-    fn executes_add_and_return_jit() {
-        let function = CompiledFunction {
-            id: 0,
-            code: Arc::from([
-                Instr::StoreConst {
-                    slot: Slot::Local(0),
-                    value: ConstValue::Num(2.0),
-                },
-                Instr::StoreConst {
-                    slot: Slot::Local(1),
-                    value: ConstValue::Num(3.0),
-                },
-                Instr::Add {
-                    a: Slot::Local(0),
-                    b: Slot::Local(1),
-                    out: Slot::Ret(0),
-                },
-                Instr::Exit,
-            ]),
-            local_count: 2,
-            arg_count: 0,
-            ret_count: 1,
-            err_count: 1,
-            meta: scalar_meta("main"),
-        };
+    fn step_runs_a_bounded_number_of_instructions() {
+        let module = compile_source(
+            r#"
+#call core::fn::begin name=main::inc args="x" retshape="scalar";
+#call core::const out=local::one value=1;
+#call core::add a=arg::x b=local::one out=return::value;
+#call core::exit;
+#call core::fn::end;
+#call core::const out=local::n value=0;
+#call main::inc args="local::n" out=local::n;
+#call main::inc args="local::n" out=return::value;
+#call core::exit;
+"#,
+        );
+        for enable_jit in [true, false] {
+            let mut vm = Vm::new(VmConfig {
+                enable_jit,
+                ..VmConfig::default()
+            });
+            assert!(vm.step(1).is_err());
+            vm.start(&module).expect("start");
 
-        let module = CompiledModule {
-            name: Arc::from("main"),
-            init_func: 0,
-            functions: vec![function],
-            function_globals: vec![],
-            exports: vec![],
-            imports: vec![],
-            global_count: 0,
-        };
+            let StepOutcome::Paused { frames } = vm.step(2).expect("step") else {
+                panic!("finished early");
+            };
+            assert_eq!(frames.len(), 2);
+            assert_eq!(
+                (frames[0].function.as_ref(), frames[0].pc),
+                ("main::inc", 0)
+            );
+            assert_eq!((frames[1].function.as_ref(), frames[1].pc), ("<init>", 1));
 
-        let mut vm = Vm::new(VmConfig {
-            enable_host_print: false,
-            enable_jit: true,
-        });
-        let result = vm.run_main(&module).expect("run");
-        assert_eq!(result.returns, vec![Value::Num(5.0)]);
+            let StepOutcome::Paused { frames } = vm.step(3).expect("step") else {
+                panic!("finished early");
+            };
+            assert_eq!((frames[0].function.as_ref(), frames[0].pc), ("<init>", 2));
+
+            // The second call (4 instructions) and the init's exit.
+            let StepOutcome::Paused { .. } = vm.step(4).expect("step") else {
+                panic!("finished early");
+            };
+            let StepOutcome::Finished(result) = vm.step(1).expect("step") else {
+                panic!("still paused");
+            };
+            assert_eq!(result.returns, vec![Value::Num(2.0)]);
+            assert!(!vm.is_stepping());
+        }
     }
 
     #[test]
-    fn catches_divide_by_zero_with_try_handler_jit() {
-        let function = CompiledFunction {
-            id: 0,
-            code: Arc::from([
-                Instr::StoreConst {
-                    slot: Slot::Local(0),
-                    value: ConstValue::Num(1.0),
-                },
-                Instr::StoreConst {
-                    slot: Slot::Local(1),
-                    value: ConstValue::Num(0.0),
-                },
-                Instr::TryPush { handler_pc: 5 },
-                Instr::Div {
-                    a: Slot::Local(0),
-                    b: Slot::Local(1),
-                    out: Slot::Ret(0),
-                },
-                Instr::Jump { target: 7 },
-                Instr::StoreConst {
-                    slot: Slot::Ret(0),
-                    value: ConstValue::Num(99.0),
-                },
-                Instr::TryPop,
-                Instr::Exit,
-            ]),
-            local_count: 2,
-            arg_count: 0,
-            ret_count: 1,
-            err_count: 1,
-            meta: scalar_meta("main"),
-        };
+    fn breakpoints_pause_a_stepped_run_for_inspection() {
+        let module = compile_source(
+            r#"
+#call core::fn::begin name=main::inc args="x" retshape="scalar";
+#call core::const out=local::one value=1;
+#call core::add a=arg::x b=local::one out=return::value;
+#call core::exit;
+#call core::fn::end;
+#call core::const out=local::n value=0;
+#call main::inc args="local::n" out=local::n;
+#call main::inc args="local::n" out=return::value;
+#call core::exit;
+"#,
+        );
+        for enable_jit in [true, false] {
+            let mut vm = Vm::new(VmConfig {
+                enable_jit,
+                ..VmConfig::default()
+            });
+            vm.set_breakpoints(vec![Breakpoint::At {
+                function: Arc::from("main::inc"),
+                pc: 1,
+            }]);
+            vm.start(&module).expect("start");
+            let StepOutcome::Paused { .. } = vm.step(u64::MAX).expect("step") else {
+                panic!("ran past the breakpoint");
+            };
+            assert!(vm.at_breakpoint());
+            let frames = vm.paused_frames();
+            assert_eq!(frames.len(), 2);
+            assert_eq!(frames[0].frame.function.as_ref(), "main::inc");
+            assert_eq!(frames[0].frame.pc, 1);
+            assert_eq!(frames[0].frame.line, Some(4));
+            assert!(matches!(frames[0].instr, Some(Instr::Add { .. })));
+            assert_eq!(frames[0].params, vec![Arc::<str>::from("x")]);
+            assert!(frames[0].slots.contains(&(Slot::Local(0), Value::Num(1.0))));
+            assert_eq!(vm.paused_slot(0, Slot::Arg(0)), Some(Value::Num(0.0)));
+            assert_eq!(vm.paused_slot(1, Slot::Local(0)), Some(Value::Num(0.0)));
+            assert_eq!(vm.paused_slot(2, Slot::Local(0)), None);
+
+            // Resuming moves past the breakpoint it is paused at.
+            vm.set_breakpoints(vec![Breakpoint::Line {
+                source: None,
+                line: 4,
+            }]);
+            let StepOutcome::Paused { frames } = vm.step(u64::MAX).expect("step") else {
+                panic!("ran past the breakpoint");
+            };
+            assert_eq!(
+                (frames[0].function.as_ref(), frames[0].pc),
+                ("main::inc", 1)
+            );
+            assert_eq!(vm.paused_slot(0, Slot::Arg(0)), Some(Value::Num(1.0)));
+
+            let StepOutcome::Finished(result) = vm.step(u64::MAX).expect("step") else {
+                panic!("still paused");
+            };
+            assert_eq!(result.returns, vec![Value::Num(2.0)]);
+            assert!(vm.paused_frames().is_empty());
+        }
+    }
 
-        let module = CompiledModule {
-            name: Arc::from("main"),
-            init_func: 0,
-            functions: vec![function],
-            function_globals: vec![],
-            exports: vec![],
-            imports: vec![],
-            global_count: 0,
+    #[test]
+    fn throw_breakpoint_pauses_before_the_error_is_raised() {
+        let module = compile_source(
+            r#"
+#call core::try::push handler="caught";
+#call core::throw code="boom" msg="nope";
+#call core::label name="caught";
+#call core::const out=return::value value=1;
+#call core::exit;
+"#,
+        );
+        let mut vm = Vm::new(VmConfig::default());
+        vm.set_breakpoints(vec![Breakpoint::Throw]);
+        vm.start(&module).expect("start");
+        let StepOutcome::Paused { frames } = vm.step(u64::MAX).expect("step") else {
+            panic!("ran past the throw");
         };
+        assert_eq!(frames[0].pc, 1);
+        assert!(vm.at_breakpoint());
+        let StepOutcome::Finished(result) = vm.step(u64::MAX).expect("step") else {
+            panic!("still paused");
+        };
+        assert_eq!(result.returns, vec![Value::Num(1.0)]);
+    }
 
+    #[test]
+    fn run_for_suspends_each_tick_and_finishes_with_fuel_left() {
+        let module = compile_source(
+            r#"
+#call core::const out=local::n value=0;
+#call core::const out=local::one value=1;
+#call core::const out=local::limit value=10;
+#call core::label name="loop";
+#call core::lt a=local::n b=local::limit out=local::more;
+#call core::br cond=local::more then="body" else="done";
+#call core::label name="body";
+#call core::add a=local::n b=local::one out=local::n;
+#call core::jump target="loop";
+#call core::label name="done";
+#call core::mov from=local::n to=return::value;
+#call core::exit;
+"#,
+        );
         let mut vm = Vm::new(VmConfig {
-            enable_host_print: false,
-            enable_jit: true,
+            max_steps: Some(1000),
+            ..VmConfig::default()
         });
-        let result = vm.run_main(&module).expect("run");
-        assert_eq!(result.returns, vec![Value::Num(99.0)]);
+        vm.start(&module).expect("start");
+        let mut ticks = 0;
+        let result = loop {
+            ticks += 1;
+            match vm.run_for(Steps(5)).expect("tick") {
+                RunState::Suspended => {}
+                RunState::Done(result) => break result,
+            }
+        };
+        // 3 constants, 10 iterations of 4, the final check and branch, mov
+        // and exit: 47 instructions.
+        assert_eq!(ticks, 10);
+        assert_eq!(result.returns, vec![Value::Num(10.0)]);
+        assert_eq!(vm.remaining_fuel(), Some(1000 - 47));
     }
 
     #[test]
-    fn invoke_uses_function_global_slot_jit() {
-        let init = CompiledFunction {
-            id: 0,
-            code: Arc::from([
-                Instr::Invoke {
-                    fn_slot: Slot::Global(0),
-                    args: vec![],
-                    out: Slot::Ret(0),
-                },
-                Instr::Exit,
-            ]),
-            local_count: 0,
-            arg_count: 0,
-            ret_count: 1,
-            err_count: 1,
-            meta: scalar_meta("main"),
-        };
+    fn error_code_and_msg_let_handlers_branch_on_the_code() {
+        let module = compile_source(
+            r#"
+#call core::try::push handler="caught";
+#call core::throw code="not_found" msg="config.toml missing";
+#call core::label name="caught";
+#call core::error::code err=err::0 out=local::code;
+#call core::error::msg err=err::0 out=return::msg;
+#call core::const out=local::expected value="not_found";
+#call core::eq a=local::code b=local::expected out=local::is_missing;
+#call core::br cond=local::is_missing then="missing" else="other";
+#call core::label name="missing";
+#call core::const out=return::kind value="missing";
+#call core::jump target="done";
+#call core::label name="other";
+#call core::const out=return::kind value="other";
+#call core::label name="done";
+#call core::const out=local::plain value=1;
+#call core::error::code err=local::plain out=return::none;
+#call core::exit;
+"#,
+        );
+        for enable_jit in [true, false] {
+            let returns = Vm::new(VmConfig {
+                enable_jit,
+                ..VmConfig::default()
+            })
+            .run_main(&module)
+            .expect("run")
+            .returns;
+            assert_eq!(
+                returns,
+                vec![
+                    Value::Str(Arc::from("config.toml missing")),
+                    Value::Str(Arc::from("missing")),
+                    Value::Null,
+                ]
+            );
+        }
+    }
 
-        let callee = CompiledFunction {
-            id: 1,
-            code: Arc::from([
-                Instr::StoreConst {
-                    slot: Slot::Ret(0),
-                    value: ConstValue::Num(7.0),
-                },
-                Instr::Exit,
-            ]),
-            local_count: 0,
-            arg_count: 0,
-            ret_count: 1,
-            err_count: 1,
-            meta: scalar_meta("main::f"),
-        };
+    #[test]
+    fn profile_fills_run_stats() {
+        let module = compile_source(
+            r#"
+#call core::fn::begin name=main::fail args="" retshape="scalar";
+#call core::throw code="boom" msg="nope";
+#call core::fn::end;
+#call core::obj::new out=local::o;
+#call core::const out=local::a value="a";
+#call core::str::concat a=local::a b="b" out=local::s;
+#call core::try::push handler="caught";
+#call main::fail out=local::ignored;
+#call core::label name="caught";
+#call core::const out=return::value value=1;
+#call core::exit;
+"#,
+        );
 
-        let module = CompiledModule {
-            name: Arc::from("main"),
-            init_func: 0,
-            functions: vec![init, callee],
-            function_globals: vec![(0, 1)],
-            exports: vec![],
-            imports: vec![],
-            global_count: 1,
-        };
+        for enable_jit in [true, false] {
+            let mut vm = Vm::new(VmConfig {
+                enable_host_print: false,
+                enable_jit,
+                profile: true,
+                ..VmConfig::default()
+            });
+            let stats = vm.run_main(&module).expect("run").stats.expect("stats");
+            assert_eq!(stats.peak_call_depth, 2);
+            assert_eq!(stats.throws, 1);
+            assert_eq!(stats.objects_allocated, 1);
+            assert_eq!(stats.strings_allocated, 1);
+            assert!(stats.instructions >= 8);
+        }
 
         let mut vm = Vm::new(VmConfig {
             enable_host_print: false,
-            enable_jit: true,
+            ..VmConfig::default()
         });
-        let result = vm.run_main(&module).expect("run");
-        assert_eq!(result.returns, vec![Value::Num(7.0)]);
+        assert!(vm.run_main(&module).expect("run").stats.is_none());
     }
 
     #[test]
-    fn interpreter_fallback_matches_behavior() {
-        let function = CompiledFunction {
-            id: 0,
-            code: Arc::from([
-                Instr::StoreConst {
-                    slot: Slot::Local(0),
-                    value: ConstValue::Num(10.0),
-                },
-                Instr::StoreConst {
-                    slot: Slot::Local(1),
-                    value: ConstValue::Num(4.0),
-                },
-                Instr::Sub {
-                    a: Slot::Local(0),
-                    b: Slot::Local(1),
-                    out: Slot::Ret(0),
-                },
-                Instr::Exit,
-            ]),
-            local_count: 2,
-            arg_count: 0,
-            ret_count: 1,
-            err_count: 1,
-            meta: scalar_meta("main"),
-        };
+    fn profile_counts_calls_and_self_instructions_per_function() {
+        let module = compile_source(
+            r#"
+#call core::fn::begin name=main::inc args="x" retshape="scalar";
+#call core::const out=local::one value=1;
+#call core::add a=arg::x b=local::one out=return::value;
+#call core::exit;
+#call core::fn::end;
+#call core::const out=local::zero value=0;
+#call main::inc x=local::zero out=local::a;
+#call main::inc x=local::a out=local::b;
+#call main::inc x=local::b out=return::value;
+#call core::exit;
+"#,
+        );
 
-        let module = CompiledModule {
-            name: Arc::from("main"),
-            init_func: 0,
-            functions: vec![function],
-            function_globals: vec![],
-            exports: vec![],
-            imports: vec![],
-            global_count: 0,
-        };
+        for enable_jit in [true, false] {
+            let mut vm = Vm::new(VmConfig {
+                enable_host_print: false,
+                enable_jit,
+                profile: true,
+                ..VmConfig::default()
+            });
+            let stats = vm.run_main(&module).expect("run").stats.expect("stats");
+            let inc = stats
+                .functions
+                .iter()
+                .find(|function| function.function.as_ref() == "main::inc")
+                .expect("inc profiled");
+            assert_eq!(inc.calls, 3);
+            assert_eq!(inc.self_instructions, 9);
+            let total: u64 = stats
+                .functions
+                .iter()
+                .map(|function| function.self_instructions)
+                .sum();
+            assert_eq!(total, stats.instructions);
+            assert_eq!(vm.stats(), stats);
+            let report = stats.report();
+            assert!(report.contains("main::inc"), "{report}");
+            assert!(report.contains("total:"), "{report}");
+        }
+    }
 
-        let mut vm = Vm::new(VmConfig {
-            enable_host_print: false,
-            enable_jit: false,
-        });
-        let result = vm.run_main(&module).expect("run");
-        assert_eq!(result.returns, vec![Value::Num(6.0)]);
+    #[test]
+    fn max_call_depth_throws_catchable_stack_overflow() {
+        let module = compile_source(
+            r#"
+#call core::fn::begin name=main::down args="" retshape="scalar";
+#call main::down out=local::ignored;
+#call core::exit;
+#call core::fn::end;
+#call core::try::push handler="caught";
+#call main::down out=local::ignored;
+#call core::label name="caught";
+#call core::mov from=err::0 to=return::value;
+#call core::exit;
+"#,
+        );
+
+        for enable_jit in [true, false] {
+            let mut vm = Vm::new(VmConfig {
+                enable_host_print: false,
+                enable_jit,
+                max_call_depth: Some(32),
+                ..VmConfig::default()
+            });
+            let returns = vm.run_main(&module).expect("run").returns;
+            let [Value::Error { code, msg, .. }] = returns.as_slice() else {
+                panic!("expected caught error, got {returns:?}");
+            };
+            assert_eq!(code.as_ref(), "stack_overflow");
+            assert_eq!(msg.as_ref(), "call depth 32 exceeds max_call_depth 32");
+        }
     }
 
     #[test]
-    fn new_core_ops_match_between_jit_and_interpreter() {
-        let function = CompiledFunction {
-            id: 0,
-            code: Arc::from([
-                Instr::ObjNew {
-                    out: Slot::Local(0),
-                },
-                Instr::StoreConst {
-                    slot: Slot::Local(1),
-                    value: ConstValue::Str(Arc::from("neo")),
-                },
-                Instr::StoreConst {
-                    slot: Slot::Local(2),
-                    value: ConstValue::Str(Arc::from("name")),
-                },
-                Instr::ObjSet {
-                    obj: Slot::Local(0),
-                    key: Slot::Local(2),
-                    value: Slot::Local(1),
-                    out: Slot::Local(0),
-                },
-                Instr::StoreConst {
-                    slot: Slot::Local(5),
-                    value: ConstValue::Str(Arc::from("name")),
-                },
-                Instr::ObjHas {
-                    obj: Slot::Local(0),
-                    key: Slot::Local(5),
-                    out: Slot::Local(3),
-                },
-                Instr::ObjGet {
-                    obj: Slot::Local(0),
-                    key: Slot::Local(5),
-                    out: Slot::Local(4),
-                },
-                Instr::StoreConst {
-                    slot: Slot::Local(6),
-                    value: ConstValue::Str(Arc::from("!")),
-                },
-                Instr::StrConcat {
-                    a: Slot::Local(4),
-                    b: Slot::Local(6),
-                    out: Slot::Local(7),
-                },
-                Instr::StrLen {
-                    value: Slot::Local(7),
-                    out: Slot::Ret(0),
-                },
-                Instr::Exit,
-            ]),
-            local_count: 8,
-            arg_count: 0,
-            ret_count: 1,
-            err_count: 1,
-            meta: scalar_meta("main"),
-        };
+    fn tail_calls_reuse_the_frame() {
+        let module = compile_source(
+            r#"
+#call core::fn::begin name=main::sum args="n,acc" retshape="scalar";
+#call core::const out=local::zero value=0;
+#call core::eq a=arg::n b=local::zero out=local::done;
+#call core::br cond=local::done then="base" else="step";
+#call core::label name="base";
+#call core::mov from=arg::acc to=return::value;
+#call core::exit;
+#call core::label name="step";
+#call core::const out=local::one value=1;
+#call core::sub a=arg::n b=local::one out=local::next;
+#call core::add a=arg::acc b=arg::n out=local::total;
+#call main::sum args="local::next,local::total" out=return::value;
+#call core::exit;
+#call core::fn::end;
+#call core::fn::begin name=main::fail args="" retshape="scalar";
+#call core::throw code="boom" msg="from the callee";
+#call core::fn::end;
+#call core::fn::begin name=main::guarded args="" retshape="scalar";
+#call core::try::push handler="caught";
+#call main::fail out=return::value;
+#call core::exit;
+#call core::label name="caught";
+#call core::error::code err=err::0 out=return::value;
+#call core::exit;
+#call core::fn::end;
+#call core::const out=local::n value=1000;
+#call core::const out=local::zero value=0;
+#call main::sum args="local::n,local::zero" out=return::sum;
+#call main::guarded out=return::guarded;
+#call core::exit;
+"#,
+        );
+        let sum = module.function(1).expect("sum");
+        assert!(
+            sum.code
+                .iter()
+                .any(|instr| matches!(instr, Instr::TailInvoke { .. }))
+        );
 
-        let module = CompiledModule {
-            name: Arc::from("main"),
-            init_func: 0,
-            functions: vec![function],
-            function_globals: vec![],
-            exports: vec![],
-            imports: vec![],
-            global_count: 0,
-        };
+        for enable_jit in [true, false] {
+            let mut vm = Vm::new(VmConfig {
+                enable_jit,
+                max_call_depth: Some(16),
+                ..VmConfig::default()
+            });
+            let returns = vm.run_main(&module).expect("run").returns;
+            // The handler in `guarded` keeps its frame, so it still catches.
+            assert_eq!(
+                returns,
+                vec![Value::Num(500_500.0), Value::Str("boom".into())]
+            );
+        }
+    }
+
+    #[test]
+    fn deep_recursion_runs_on_the_vm_call_stack() {
+        let module = compile_source(
+            r#"
+#call core::fn::begin name=main::count args="n" retshape="scalar";
+#call core::const out=local::zero value=0;
+#call core::eq a=arg::n b=local::zero out=local::done;
+#call core::br cond=local::done then="base" else="step";
+#call core::label name="base";
+#call core::mov from=arg::n to=return::value;
+#call core::exit;
+#call core::label name="step";
+#call core::const out=local::one value=1;
+#call core::sub a=arg::n b=local::one out=local::next;
+#call main::count args="local::next" out=local::inner;
+#call core::add a=local::inner b=local::one out=return::value;
+#call core::exit;
+#call core::fn::end;
+#call core::const out=local::n value=50000;
+#call main::count args="local::n" out=return::value;
+#call core::exit;
+"#,
+        );
+
+        for enable_jit in [true, false] {
+            let mut vm = Vm::new(VmConfig {
+                enable_host_print: false,
+                enable_jit,
+                profile: true,
+                ..VmConfig::default()
+            });
+            let result = vm.run_main(&module).expect("run");
+            assert_eq!(result.returns, vec![Value::Num(50000.0)]);
+            assert_eq!(result.stats.expect("stats").peak_call_depth, 50002);
+            assert_eq!(vm.call_depth, 0);
+        }
+    }
+
+    #[test]
+    fn max_steps_bounds_a_run_and_is_not_catchable() {
+        let module = compile_source(
+            r#"
+#call core::fn::begin name=main::spin args="" retshape="scalar";
+#call core::try::push handler="caught";
+#call core::label name="spin";
+#call core::jump target="spin";
+#call core::label name="caught";
+#call core::exit;
+#call core::fn::end;
+#call core::fn::begin name=main::double args="x" retshape="scalar";
+#call core::add a=arg::x b=arg::x out=return::value;
+#call core::exit;
+#call core::fn::end;
+#call core::mod::export name="spin" value=main::spin;
+#call core::mod::export name="double" value=main::double;
+#call core::exit;
+"#,
+        );
 
         for enable_jit in [true, false] {
             let mut vm = Vm::new(VmConfig {
                 enable_host_print: false,
                 enable_jit,
+                max_steps: Some(1000),
+                ..VmConfig::default()
             });
-            let result = vm.run_main(&module).expect("run");
-            assert_eq!(result.returns, vec![Value::Num(4.0)]);
+            let exports = vm.run_main(&module).expect("run").exports;
+            let (Some(Value::Func(spin)), Some(Value::Func(double))) =
+                (exports.get("spin"), exports.get("double"))
+            else {
+                panic!("expected exported functions, got {exports:?}");
+            };
+
+            let err = vm.invoke(*spin, &[]).expect_err("fuel runs out");
+            assert!(
+                matches!(
+                    err,
+                    VmError::FuelExhausted {
+                        max_steps: 1000,
+                        ..
+                    }
+                ),
+                "jit={enable_jit}: {err:?}"
+            );
+            assert_eq!(vm.remaining_fuel(), Some(0));
+
+            // Each top-level call starts with a full tank.
+            let returns = vm.invoke(*double, &[Value::Num(2.0)]).expect("double");
+            assert_eq!(returns, vec![Value::Num(4.0)]);
+            assert_eq!(vm.remaining_fuel(), Some(998));
         }
+        assert_eq!(Vm::new(VmConfig::default()).remaining_fuel(), None);
     }
 
     #[test]
-    fn stdlib_prelude_module_runs() {
-        let prelude = std::path::Path::new(env!("CARGO_MANIFEST_DIR"))
-            .join("../../stdlib/prelude.imp")
-            .canonicalize()
-            .expect("canonicalize prelude path");
+    fn max_alloc_bytes_throws_a_catchable_oom() {
+        let module = compile_source(
+            r#"
+#call core::const out=local::text value="ab";
+#call core::try::push handler="full";
+#call core::label name="grow";
+#call core::str::concat a=local::text b=local::text out=local::text;
+#call core::jump target="grow";
+#call core::label name="full";
+#call core::error::code err=err::0 out=return::code;
+#call core::str::len value=local::text out=return::len;
+#call core::exit;
+"#,
+        );
 
-        let program = format!(
-            r#"#call core::import alias="std" path="{}";
-#call core::const out=local::x value=-3;
-#call std::abs args="local::x" out=local::absx;
-#call core::const out=local::low value=0;
-#call core::const out=local::high value=2;
-#call std::clamp args="local::absx,local::low,local::high" out=local::clamped;
-#call core::mov from=local::clamped to=return::value;
+        for enable_jit in [true, false] {
+            let mut vm = Vm::new(VmConfig {
+                enable_jit,
+                max_alloc_bytes: Some(4096),
+                ..VmConfig::default()
+            });
+            for _ in 0..2 {
+                let returns = vm.run_main(&module).expect("run").returns;
+                assert_eq!(
+                    returns,
+                    vec![Value::Str(Arc::from("oom")), Value::Num(2048.0)],
+                    "jit={enable_jit}"
+                );
+                // "ab" and each doubling up to 2048 chars; the budget
+                // starts over with every run.
+                assert_eq!(vm.allocated_bytes(), 4094);
+            }
+        }
+    }
+
+    #[test]
+    fn max_memory_credits_dropped_values_and_counts_callers() {
+        let module = compile_source(
+            r#"
+#call core::fn::begin name=main::churn args="" retshape="scalar";
+#call core::const out=local::n value=0;
+#call core::const out=local::rounds value=10;
+#call core::const out=local::one value=1;
+#call core::const out=local::size value=2048;
+#call core::label name="round";
+#call core::lt a=local::n b=local::rounds out=local::more;
+#call core::br cond=local::more then="fresh" else="done";
+#call core::label name="fresh";
+#call core::const out=local::s value="ab";
+#call core::label name="grow";
+#call core::str::concat a=local::s b=local::s out=local::s;
+#call core::str::len value=local::s out=local::len;
+#call core::lt a=local::len b=local::size out=local::small;
+#call core::br cond=local::small then="grow" else="next";
+#call core::label name="next";
+#call core::add a=local::n b=local::one out=local::n;
+#call core::jump target="round";
+#call core::label name="done";
+#call core::exit;
+#call core::fn::end;
+#call core::try::push handler="full";
+#call core::const out=local::size value=2048;
+#call core::const out=local::held value="ab";
+#call core::label name="build";
+#call core::str::concat a=local::held b=local::held out=local::held;
+#call core::str::len value=local::held out=local::len;
+#call core::lt a=local::len b=local::size out=local::small;
+#call core::br cond=local::small then="build" else="call";
+#call core::label name="call";
+#call main::churn out=local::unused;
+#call core::const out=return::code value="done";
+#call core::exit;
+#call core::label name="full";
+#call core::error::code err=err::0 out=return::code;
 #call core::exit;
 "#,
-            prelude.display()
         );
+        let run = |cfg: VmConfig| {
+            let mut vm = Vm::new(cfg);
+            let returns = vm.run_main(&module).expect("run").returns;
+            (returns, vm.live_bytes())
+        };
+        let done = vec![Value::Str(Arc::from("done"))];
+        let oom = vec![Value::Str(Arc::from("oom"))];
 
-        let main_path = std::env::temp_dir().join("imp_stdlib_prelude_test.imp");
-        fs::write(&main_path, program).expect("write main");
+        for enable_jit in [true, false] {
+            // Every round's string is dropped before the next one grows, so
+            // far more than the budget is allocated over the run.
+            let (returns, live) = run(VmConfig {
+                enable_jit,
+                max_memory: Some(6000),
+                ..VmConfig::default()
+            });
+            assert_eq!(returns, done, "jit={enable_jit}");
+            assert!(live <= 6000, "{live}");
+            let (returns, _) = run(VmConfig {
+                enable_jit,
+                max_alloc_bytes: Some(6000),
+                ..VmConfig::default()
+            });
+            assert_eq!(returns, oom, "jit={enable_jit}");
+            // The caller's 2048 chars count while `churn` runs.
+            let (returns, _) = run(VmConfig {
+                enable_jit,
+                max_memory: Some(4500),
+                ..VmConfig::default()
+            });
+            assert_eq!(returns, oom, "jit={enable_jit}");
+        }
+    }
 
-        let module = compile_module(&main_path, &FsModuleLoader).expect("compile module");
+    #[test]
+    fn self_referencing_objects_are_sized_once() {
+        let module = compile_source(
+            r#"
+#call core::obj::new out=local::o;
+#call core::obj::set obj=local::o key="self" value=local::o;
+#call core::obj::entries obj=local::o out=local::entries len=return::n;
+#call core::exit;
+"#,
+        );
+
+        for enable_jit in [true, false] {
+            for max_alloc_bytes in [None, Some(1 << 20)] {
+                let mut vm = Vm::new(VmConfig {
+                    enable_jit,
+                    max_alloc_bytes,
+                    ..VmConfig::default()
+                });
+                let returns = vm.run_main(&module).expect("run").returns;
+                assert_eq!(returns, vec![Value::Num(1.0)], "jit={enable_jit}");
+                if max_alloc_bytes.is_none() {
+                    assert_eq!(vm.allocated_bytes(), 0);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn timeout_and_cancel_stop_runaway_runs() {
+        let module = compile_source(
+            r#"
+#call core::fn::begin name=main::spin args="" retshape="scalar";
+#call core::try::push handler="caught";
+#call core::label name="spin";
+#call core::jump target="spin";
+#call core::label name="caught";
+#call core::exit;
+#call core::fn::end;
+#call core::mod::export name="spin" value=main::spin;
+#call core::exit;
+"#,
+        );
+
+        for enable_jit in [true, false] {
+            let cancel = CancelToken::new();
+            let mut vm = Vm::new(VmConfig {
+                enable_host_print: false,
+                enable_jit,
+                timeout: Some(Duration::from_millis(20)),
+                cancel: Some(cancel.clone()),
+                ..VmConfig::default()
+            });
+            let exports = vm.run_main(&module).expect("run").exports;
+            let Some(Value::Func(spin)) = exports.get("spin") else {
+                panic!("expected exported function, got {exports:?}");
+            };
+
+            let err = vm.invoke(*spin, &[]).expect_err("timeout");
+            assert!(
+                matches!(err, VmError::Timeout { ref function, .. } if &**function == "main::spin"),
+                "jit={enable_jit}: {err:?}"
+            );
+
+            let tripper = cancel.clone();
+            let handle = std::thread::spawn(move || {
+                std::thread::sleep(Duration::from_millis(5));
+                tripper.cancel();
+            });
+            vm.cfg.timeout = None;
+            let err = vm.invoke(*spin, &[]).expect_err("cancelled");
+            handle.join().expect("join");
+            assert!(
+                matches!(err, VmError::Cancelled { .. }),
+                "jit={enable_jit}: {err:?}"
+            );
+            assert!(matches!(
+                vm.run_main(&module),
+                Err(VmError::Cancelled { .. })
+            ));
+
+            cancel.reset();
+            vm.run_main(&module).expect("runs again once reset");
+        }
+    }
+
+    #[test]
+    fn value_display_is_stable_and_truncatable() {
+        let mut inner = HashMap::new();
+        inner.insert("port".to_owned(), Value::Num(8080.0));
+        let mut map = HashMap::new();
+        map.insert("name".to_owned(), Value::Str(Arc::from("a \"b\"")));
+        map.insert("server".to_owned(), Value::Obj(inner.into()));
+        map.insert("on".to_owned(), Value::Bool(true));
+        map.insert("odd key".to_owned(), Value::Null);
+        let value = Value::Obj(map.into());
+
+        assert_eq!(
+            value.to_string(),
+            r#"{name: "a \"b\"", "odd key": null, on: true, server: {port: 8080}}"#
+        );
+        assert_eq!(
+            value
+                .display_with(DisplayOptions {
+                    max_depth: Some(1),
+                    max_items: Some(2),
+                    ..DisplayOptions::default()
+                })
+                .to_string(),
+            r#"{name: "a \"b\"", "odd key": null, ... (+2 more)}"#
+        );
+        assert_eq!(
+            Value::Str(Arc::from("abcdef"))
+                .display_with(DisplayOptions {
+                    max_str_chars: Some(3),
+                    ..DisplayOptions::default()
+                })
+                .to_string(),
+            "\"abc...\""
+        );
+
+        let mut nested = HashMap::new();
+        nested.insert("a".to_owned(), Value::Num(1.5));
+        let mut outer = HashMap::new();
+        outer.insert("inner".to_owned(), Value::Obj(nested.into()));
+        assert_eq!(
+            Value::Obj(outer.into()).pretty(2),
+            "{\n  inner: {\n    a: 1.5\n  }\n}"
+        );
+    }
+
+    #[test]
+    fn typed_export_helpers() {
+        let module = compile_source(
+            r#"
+#call core::obj::new out=main::server;
+#call core::const out=local::port value=8080;
+#call core::obj::set obj=main::server key="port" value=local::port;
+#call core::obj::new out=main::config;
+#call core::obj::set obj=main::config key="server" value=main::server;
+#call core::const out=main::name value="imp";
+#call core::mod::export name="config" value=main::config;
+#call core::mod::export name="name" value=main::name;
+#call core::exit;
+"#,
+        );
         let mut vm = Vm::new(VmConfig {
             enable_host_print: false,
-            enable_jit: true,
+            ..VmConfig::default()
         });
         let result = vm.run_main(&module).expect("run");
-        assert_eq!(result.returns, vec![Value::Num(2.0)]);
+
+        assert_eq!(result.export::<String>("name"), Ok("imp".to_owned()));
+        assert_eq!(
+            result.exports_path("config.server.port"),
+            Some(Value::Num(8080.0))
+        );
+        assert_eq!(result.exports_path("config.server.host"), None);
+        assert_eq!(
+            result.export::<f64>("name"),
+            Err(ExportError::Type {
+                path: "name".to_owned(),
+                source: ValueTypeError {
+                    expected: "num",
+                    found: "str",
+                },
+            })
+        );
+        assert_eq!(
+            result.export::<bool>("missing"),
+            Err(ExportError::Missing("missing".to_owned()))
+        );
+        assert_eq!(
+            result.to_snapshot(),
+            "returns:\nexports:\n  config: {server: {port: 8080}}\n  name: \"imp\"\n"
+        );
+        assert_eq!(i64::try_from(Value::Num(42.0)), Ok(42));
+        assert!(i64::try_from(Value::Num(4.5)).is_err());
     }
 
     #[test]
-    fn namespaced_stdlib_modules_run_together() {
-        let stdlib_root = std::path::Path::new(env!("CARGO_MANIFEST_DIR"))
-            .join("../../stdlib")
+    fn verify_jit_accepts_matching_paths() {
+        let path = PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+            .join("../../examples/collections_algo_demo.imp")
             .canonicalize()
-            .expect("canonicalize stdlib root");
-        let map = stdlib_root.join("map.imp");
-        let string = stdlib_root.join("string.imp");
-        let result_mod = stdlib_root.join("result.imp");
+            .expect("canonicalize example path");
+        let module = compile_module(&path, &FsModuleLoader).expect("compile example");
+        let mut vm = Vm::new(VmConfig {
+            enable_host_print: false,
+            verify_jit: true,
+            ..VmConfig::default()
+        });
+        let result = vm.run_main(&module).expect("verified run");
+        assert_eq!(
+            result.returns,
+            vec![Value::Str(Arc::from(
+                "idx=2 has7=false sum=19 rev=[3,5,3,8]"
+            ))]
+        );
+    }
 
-        let program = format!(
-            r#"#call core::import alias="std_map" path="{}";
-#call core::import alias="std_str" path="{}";
-#call core::import alias="std_res" path="{}";
+    #[test]
+    fn verify_jit_shadow_runs_do_not_mutate_shared_objects() {
+        let module = compile_source(
+            r#"
+#call core::fn::begin name=main::bump args="counter" retshape="scalar";
+#call core::obj::get obj=arg::counter key="n" out=local::n;
+#call core::const out=local::one value=1;
+#call core::add a=local::n b=local::one out=local::n;
+#call core::obj::set obj=arg::counter key="n" value=local::n;
+#call core::obj::get obj=main::state key="n" out=local::seen;
+#call core::add a=local::seen b=local::one out=local::seen;
+#call core::obj::set obj=main::state key="n" value=local::seen;
+#call core::mov from=local::n to=return::value;
+#call core::exit;
+#call core::fn::end;
 
-#call std_map::new out=local::m;
-#call core::const out=local::name value="imp";
-#call core::obj::set obj=local::m key="name" value=local::name out=local::m;
-#call core::const out=local::key value="name";
-#call core::const out=local::msg value="missing name";
-#call std_map::require args="local::m,local::key,local::msg" out=local::got;
-#call core::const out=local::suffix value="!";
-#call std_str::concat args="local::got,local::suffix" out=local::text;
-#call std_res::ok args="local::text" out=local::r;
-#call core::const out=local::fallback value="fallback";
-#call std_res::unwrap_or args="local::r,local::fallback" out=return::value;
+#call core::obj::new out=main::state;
+#call core::const out=local::zero value=0;
+#call core::obj::set obj=main::state key="n" value=local::zero;
+#call core::obj::new out=local::counter;
+#call core::obj::set obj=local::counter key="n" value=local::zero;
+#call core::invoke fn=main::bump args="local::counter" out=return::first;
+#call core::invoke fn=main::bump args="local::counter" out=return::second;
+#call core::obj::get obj=main::state key="n" out=return::state;
 #call core::exit;
 "#,
-            map.display(),
-            string.display(),
-            result_mod.display()
         );
 
-        let main_path = std::env::temp_dir().join("imp_stdlib_namespaced_test.imp");
-        fs::write(&main_path, program).expect("write main");
+        let mut vm = Vm::new(VmConfig {
+            verify_jit: true,
+            ..VmConfig::default()
+        });
+        let returns = vm.run_main(&module).expect("verified run").returns;
+        assert_eq!(
+            returns,
+            vec![Value::Num(1.0), Value::Num(2.0), Value::Num(2.0)]
+        );
+    }
 
-        let module = compile_module(&main_path, &FsModuleLoader).expect("compile module");
+    #[test]
+    fn divergence_reports_returns_and_globals() {
+        let shadow = ShadowRun {
+            result: Ok(vec![Value::Num(1.0)]),
+            globals: vec![Value::Null, Value::Num(2.0)],
+            pc: 4,
+        };
+        let detail = describe_divergence(&Ok(vec![Value::Num(2.0)]), &shadow.globals, &shadow)
+            .expect("returns diverge");
+        assert!(detail.starts_with("returns differ"));
+        let detail = describe_divergence(
+            &Ok(vec![Value::Num(1.0)]),
+            &[Value::Null, Value::Num(3.0)],
+            &shadow,
+        )
+        .expect("globals diverge");
+        assert!(detail.starts_with("global slot 1 differs"));
+        assert_eq!(
+            describe_divergence(&Ok(vec![Value::Num(1.0)]), &shadow.globals, &shadow),
+            None
+        );
+    }
+
+    #[test]
+    fn import_init_failure_names_the_import() {
+        let temp = std::env::temp_dir();
+        let dep_path = temp.join("imp_failing_init_dep.imp");
+        let main_path = temp.join("imp_failing_init_main.imp");
+        fs::write(
+            &dep_path,
+            "#call core::throw code=\"bad_config\" msg=\"missing key\";\n",
+        )
+        .expect("write dep");
+        fs::write(
+            &main_path,
+            format!(
+                "#call core::import alias=\"cfg\" path=\"{}\";\n#call core::exit;\n",
+                dep_path.display()
+            ),
+        )
+        .expect("write main");
+
+        let module = compile_module(&main_path, &FsModuleLoader).expect("compile");
         let mut vm = Vm::new(VmConfig {
             enable_host_print: false,
-            enable_jit: true,
+            ..VmConfig::default()
         });
-        let result = vm.run_main(&module).expect("run");
-        assert_eq!(result.returns, vec![Value::Str(Arc::from("imp!"))]);
+        let err = vm.run_main(&module).expect_err("init throws");
+        let VmError::ImportInit { alias, source, .. } = &err else {
+            panic!("expected ImportInit, got {err:?}");
+        };
+        assert_eq!(alias, "cfg");
+        assert!(
+            matches!(source.as_ref(), VmError::Thrown { code, .. } if code.as_ref() == "bad_config")
+        );
+        assert!(err.to_string().contains("while initializing import 'cfg'"));
     }
 
     #[test]
-    fn imported_object_function_is_callable_across_modules() {
+    fn function_handles_are_symbolicated() {
         let temp = std::env::temp_dir();
-        let provider_path = temp.join("imp_provider_fn_holder.imp");
-        let consumer_path = temp.join("imp_consumer_fn_holder.imp");
+        let dep_path = temp.join("imp_symbol_dep.imp");
+        let main_path = temp.join("imp_symbol_main.imp");
+        fs::write(
+            &dep_path,
+            "#call core::fn::begin name=mod::double args=\"x\" retshape=\"scalar\";\n\
+             #call core::add a=arg::x b=arg::x out=return::value;\n\
+             #call core::exit;\n\
+             #call core::fn::end;\n\
+             #call core::mod::export name=\"double\" value=mod::double;\n",
+        )
+        .expect("write dep");
+        fs::write(
+            &main_path,
+            format!(
+                "#call core::import alias=\"math\" path=\"{}\";\n\
+                 #call core::mod::export name=\"twice\" value=math::double;\n\
+                 #call core::exit;\n",
+                dep_path.display()
+            ),
+        )
+        .expect("write main");
 
-        let provider_src = r#"
-#call core::fn::begin name=main::double args="x" retshape="scalar";
-#call core::const out=local::two value=2;
-#call core::mul a=arg::x b=local::two out=return::value;
-#call core::exit;
-#call core::fn::end;
+        let module = compile_module(&main_path, &FsModuleLoader).expect("compile");
+        let mut vm = Vm::new(VmConfig {
+            enable_host_print: false,
+            ..VmConfig::default()
+        });
+        let result = vm.run_main(&module).expect("run");
+        let Some(Value::Func(handle)) = result.exports.get("twice") else {
+            panic!("expected exported function, got {:?}", result.exports);
+        };
+        let symbol = vm.symbol(*handle).expect("handle has a symbol");
+        assert_eq!(symbol.name.as_ref(), "mod::double");
+        assert_eq!(symbol.alias.as_deref(), Some("math"));
+        assert!(symbol.to_string().contains("imported as 'math'"));
 
-#call core::obj::new out=main::holder;
-#call core::obj::set obj=main::holder key="fn" value=main::double out=main::holder;
-#call core::mod::export name="holder" value=main::holder;
-#call core::exit;
-"#;
-        fs::write(&provider_path, provider_src).expect("write provider");
+        let err = vm.invoke(424_242, &[]).expect_err("no such function");
+        assert!(matches!(
+            err,
+            VmError::UnknownFunction {
+                id: 424_242,
+                symbol: None
+            }
+        ));
+    }
 
-        let consumer_src = format!(
-            r#"#call core::import alias="prov" path="{}";
-#call core::obj::get obj=prov::holder key="fn" out=local::fn_ref;
-#call core::const out=local::x value=4;
-#call core::invoke fn=local::fn_ref args="local::x" out=return::value;
-#call core::exit;
-"#,
-            provider_path.display()
-        );
-        fs::write(&consumer_path, consumer_src).expect("write consumer");
+    #[test]
+    fn reentrant_import_init_raises_import_cycle() {
+        let temp = std::env::temp_dir();
+        let dep_path = temp.join("imp_cycle_dep.imp");
+        let main_path = temp.join("imp_cycle_main.imp");
+        fs::write(&dep_path, "#call core::exit;\n").expect("write dep");
+        fs::write(
+            &main_path,
+            format!(
+                "#call core::import alias=\"dep\" path=\"{}\";\n#call core::exit;\n",
+                dep_path.display()
+            ),
+        )
+        .expect("write main");
+
+        // The compiler rejects cyclic sources, so close the loop by hand the
+        // way a hand-assembled bytecode graph could: dep imports its own path.
+        let mut module = compile_module(&main_path, &FsModuleLoader).expect("compile");
+        let binding = module.imports[0].clone();
+        let mut dep = (*binding.module).clone();
+        dep.imports.push(ImportBinding {
+            alias: "again".to_owned(),
+            export_to_global: Vec::new(),
+            ..binding.clone()
+        });
+        module.imports[0].module = Arc::new(dep);
 
-        let module = compile_module(&consumer_path, &FsModuleLoader).expect("compile consumer");
         let mut vm = Vm::new(VmConfig {
             enable_host_print: false,
-            enable_jit: true,
+            ..VmConfig::default()
         });
-        let result = vm.run_main(&module).expect("run consumer");
-        assert_eq!(result.returns, vec![Value::Num(8.0)]);
+        let err = vm.run_main(&module).expect_err("cycle detected");
+        let VmError::ImportInit { source, .. } = &err else {
+            panic!("expected ImportInit, got {err:?}");
+        };
+        let VmError::Thrown { code, msg, .. } = source.as_ref() else {
+            panic!("expected import_cycle throw, got {source:?}");
+        };
+        assert_eq!(code.as_ref(), "import_cycle");
+        assert!(msg.contains(&format!("{0} -> {0}", binding.path)));
+        assert!(vm.initializing.is_empty());
+    }
+
+    #[test]
+    fn bad_branch_targets_fail_before_running() {
+        let mut module = compile_source("#call core::exit;\n");
+        let main = &mut module.functions[0];
+        let mut code = main.code.to_vec();
+        let pc = code.len();
+        // Unreachable after the exit, so only an up-front check can see it.
+        code.push(Instr::Jump { target: 99 });
+        main.code = Arc::from(code);
+
+        for enable_jit in [true, false] {
+            let mut vm = Vm::new(VmConfig {
+                enable_host_print: false,
+                enable_jit,
+                ..VmConfig::default()
+            });
+            let err = vm.run_main(&module).expect_err("bad target rejected");
+            assert!(
+                matches!(
+                    err,
+                    VmError::BadBranchTarget { pc: at, target: 99, len, .. }
+                        if at == pc && len == pc + 1
+                ),
+                "jit={enable_jit}: {err:?}"
+            );
+            assert_eq!(vm.jit_cache_len(), 0);
+        }
+    }
+
+    #[test]
+    fn imports_initialize_once_and_keep_their_state() {
+        let temp = std::env::temp_dir();
+        let counter_path = temp.join("imp_instance_counter.imp");
+        let mid_path = temp.join("imp_instance_mid.imp");
+        let main_path = temp.join("imp_instance_main.imp");
+        fs::write(
+            &counter_path,
+            r#"
+#call core::fn::begin name=mod::bump args="" retshape="scalar";
+#call core::const out=local::one value=1;
+#call core::add a=mod::count b=local::one out=mod::count;
+#call core::mov from=mod::count to=return::value;
+#call core::exit;
+#call core::fn::end;
+#call core::const out=mod::count value=0;
+#call core::host::print value=mod::count;
+#call core::mod::export name="bump" value=mod::bump;
+#call core::exit;
+"#,
+        )
+        .expect("write counter");
+        fs::write(
+            &mid_path,
+            format!(
+                "#call core::import alias=\"counter\" path=\"{}\";\n\
+                 #call core::mod::export name=\"bump\" value=counter::bump;\n\
+                 #call core::exit;\n",
+                counter_path.display()
+            ),
+        )
+        .expect("write mid");
+        fs::write(
+            &main_path,
+            format!(
+                "#call core::import alias=\"counter\" path=\"{}\";\n\
+                 #call core::import alias=\"mid\" path=\"{}\";\n\
+                 #call counter::bump out=local::first;\n\
+                 #call mid::bump out=return::value;\n\
+                 #call core::exit;\n",
+                counter_path.display(),
+                mid_path.display()
+            ),
+        )
+        .expect("write main");
+        let module = compile_module(&main_path, &FsModuleLoader).expect("compile");
+
+        for enable_jit in [true, false] {
+            let log = Arc::new(AuditLog::default());
+            let mut vm = Vm::new(VmConfig {
+                enable_host_print: false,
+                enable_jit,
+                audit: Some(log.clone()),
+                ..VmConfig::default()
+            });
+            let returns = vm.run_main(&module).expect("run").returns;
+            // Both paths reach the same instance, so the count carries over.
+            assert_eq!(returns, vec![Value::Num(2.0)], "jit={enable_jit}");
+            assert_eq!(log.entries().len(), 1, "counter init ran once");
+        }
     }
 
     fn run_example(name: &str) -> Vec<Value> {
@@ -1814,6 +10976,7 @@ mod tests {
         let mut vm = Vm::new(VmConfig {
             enable_host_print: false,
             enable_jit: true,
+            ..VmConfig::default()
         });
         vm.run_main(&module).expect("run example").returns
     }
@@ -0,0 +1,151 @@
+//! Shared object storage: `Value::Obj` holds a reference, so every slot a
+//! object is copied into sees the same fields. Objects are reference
+//! counted with no cycle collector: an object that reaches itself, directly
+//! or through other objects, is leaked when the VM drops it.
+
+use crate::Value;
+use std::cell::{Ref, RefCell, RefMut};
+use std::collections::HashMap;
+use std::fmt;
+use std::rc::Rc;
+
+thread_local! {
+    /// Pairs of objects being compared further up the stack.
+    static COMPARING: RefCell<Vec<(usize, usize)>> = const { RefCell::new(Vec::new()) };
+}
+
+/// A reference to a mutable string-keyed map. Cloning shares the map;
+/// equality compares fields, so two distinct objects with equal fields are
+/// equal.
+#[derive(Clone, Default)]
+pub struct ObjRef(Rc<RefCell<HashMap<String, Value>>>);
+
+impl ObjRef {
+    #[must_use]
+    pub fn new(map: HashMap<String, Value>) -> Self {
+        Self(Rc::new(RefCell::new(map)))
+    }
+
+    /// Borrows the fields. Panics while the same object is borrowed
+    /// mutably, which the VM never holds across other object accesses.
+    #[must_use]
+    pub fn borrow(&self) -> Ref<'_, HashMap<String, Value>> {
+        self.0.borrow()
+    }
+
+    #[must_use]
+    pub fn borrow_mut(&self) -> RefMut<'_, HashMap<String, Value>> {
+        self.0.borrow_mut()
+    }
+
+    /// Whether both refer to the same object.
+    #[must_use]
+    pub fn ptr_eq(&self, other: &Self) -> bool {
+        Rc::ptr_eq(&self.0, &other.0)
+    }
+
+    /// Identifies the object for cycle checks while walking nested values.
+    pub(crate) fn addr(&self) -> usize {
+        Rc::as_ptr(&self.0) as usize
+    }
+
+    /// Runs `compare` on both objects' fields, unless the same pair is
+    /// already being compared further up the stack; then the pair is part
+    /// of a reference cycle and `on_cycle` is the answer, so objects that
+    /// contain themselves compare without recursing forever.
+    pub(crate) fn walk_pair<T>(
+        &self,
+        other: &Self,
+        on_cycle: T,
+        compare: impl FnOnce(&HashMap<String, Value>, &HashMap<String, Value>) -> T,
+    ) -> T {
+        let pair = (self.addr(), other.addr());
+        if COMPARING.with_borrow(|pairs| pairs.contains(&pair)) {
+            return on_cycle;
+        }
+        COMPARING.with_borrow_mut(|pairs| pairs.push(pair));
+        let result = compare(&self.borrow(), &other.borrow());
+        COMPARING.with_borrow_mut(Vec::pop);
+        result
+    }
+
+    /// A copy of the fields, detached from this object.
+    #[must_use]
+    pub fn to_map(&self) -> HashMap<String, Value> {
+        self.borrow().clone()
+    }
+}
+
+impl Value {
+    /// A copy sharing no objects with `self`, through lists and nested
+    /// objects; objects reached twice are copied once, so sharing and
+    /// cycles carry over to the copy. Closure captures and error causes
+    /// and data are still shared.
+    #[must_use]
+    pub fn deep_copy(&self) -> Self {
+        self.deep_copy_with(&mut HashMap::new())
+    }
+
+    /// [`Self::deep_copy`] of every value in `groups`, sharing one copy of
+    /// any object reached from more than one of them.
+    pub(crate) fn deep_copy_all<const N: usize>(groups: [&[Self]; N]) -> [Vec<Self>; N] {
+        let mut copies = HashMap::new();
+        groups.map(|values| {
+            values
+                .iter()
+                .map(|value| value.deep_copy_with(&mut copies))
+                .collect()
+        })
+    }
+
+    fn deep_copy_with(&self, copies: &mut HashMap<usize, ObjRef>) -> Self {
+        match self {
+            Self::List(items) => Self::List(
+                items
+                    .iter()
+                    .map(|item| item.deep_copy_with(copies))
+                    .collect(),
+            ),
+            Self::Obj(obj) => {
+                if let Some(copy) = copies.get(&obj.addr()) {
+                    return Self::Obj(copy.clone());
+                }
+                let copy = ObjRef::default();
+                copies.insert(obj.addr(), copy.clone());
+                let fields = obj
+                    .borrow()
+                    .iter()
+                    .map(|(key, value)| (key.clone(), value.deep_copy_with(copies)))
+                    .collect::<Vec<_>>();
+                copy.borrow_mut().extend(fields);
+                Self::Obj(copy)
+            }
+            other => other.clone(),
+        }
+    }
+}
+
+impl From<HashMap<String, Value>> for ObjRef {
+    fn from(map: HashMap<String, Value>) -> Self {
+        Self::new(map)
+    }
+}
+
+impl FromIterator<(String, Value)> for ObjRef {
+    fn from_iter<I: IntoIterator<Item = (String, Value)>>(iter: I) -> Self {
+        Self::new(iter.into_iter().collect())
+    }
+}
+
+impl PartialEq for ObjRef {
+    fn eq(&self, other: &Self) -> bool {
+        self.ptr_eq(other) || self.walk_pair(other, true, |a, b| *a == *b)
+    }
+}
+
+impl fmt::Debug for ObjRef {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // Through `Display`, which cuts reference cycles short.
+        write!(f, "Obj({})", Value::Obj(self.clone()))
+    }
+}
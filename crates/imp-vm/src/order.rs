@@ -0,0 +1,119 @@
+use crate::{Value, int_num};
+use std::cmp::Ordering;
+use std::rc::Rc;
+
+impl Value {
+    /// Total order used by `core::cmp`.
+    ///
+    /// Kinds order as null < bool < num < str < bytes < list < obj < func <
+    /// error < handle. Within a kind: `false < true`; numbers numerically with
+    /// `-0 == 0` and NaN above every other number, where ints count as numbers
+    /// and sort just before an equal num; strings and bytes
+    /// lexicographically by byte; lists element-wise, shorter first on a tie;
+    /// objects by their key-sorted `(key, value)` sequences; functions by id;
+    /// errors by code, message, cause, then data; handles by type name, then
+    /// identity (stable only within one run).
+    #[must_use]
+    pub fn total_cmp(&self, other: &Self) -> Ordering {
+        match (self, other) {
+            (Self::Null, Self::Null) => Ordering::Equal,
+            (Self::Bool(a), Self::Bool(b)) => a.cmp(b),
+            (Self::Num(a), Self::Num(b)) => cmp_num(*a, *b),
+            (Self::Int(a), Self::Int(b)) => a.cmp(b),
+            (Self::Int(a), Self::Num(b)) => cmp_num(int_num(*a), *b).then(Ordering::Less),
+            (Self::Num(a), Self::Int(b)) => cmp_num(*a, int_num(*b)).then(Ordering::Greater),
+            (Self::Str(a), Self::Str(b)) => a.cmp(b),
+            (Self::Bytes(a), Self::Bytes(b)) => a.cmp(b),
+            (Self::List(a), Self::List(b)) => a
+                .iter()
+                .zip(b)
+                .map(|(x, y)| x.total_cmp(y))
+                .find(|ordering| ordering.is_ne())
+                .unwrap_or_else(|| a.len().cmp(&b.len())),
+            (Self::Obj(a), Self::Obj(b)) if a.ptr_eq(b) => Ordering::Equal,
+            (Self::Obj(a), Self::Obj(b)) => a.walk_pair(b, Ordering::Equal, |a, b| {
+                let mut a = a.iter().collect::<Vec<_>>();
+                let mut b = b.iter().collect::<Vec<_>>();
+                a.sort_by(|x, y| x.0.cmp(y.0));
+                b.sort_by(|x, y| x.0.cmp(y.0));
+                a.iter()
+                    .zip(&b)
+                    .map(|((ka, va), (kb, vb))| ka.cmp(kb).then_with(|| va.total_cmp(vb)))
+                    .find(|ordering| ordering.is_ne())
+                    .unwrap_or_else(|| a.len().cmp(&b.len()))
+            }),
+            (Self::Func(a), Self::Func(b)) => a.cmp(b),
+            (
+                Self::Closure {
+                    func: fa,
+                    captures: ca,
+                },
+                Self::Closure {
+                    func: fb,
+                    captures: cb,
+                },
+            ) => fa.cmp(fb).then_with(|| {
+                ca.iter()
+                    .zip(cb.iter())
+                    .map(|(x, y)| x.total_cmp(y))
+                    .find(|ordering| ordering.is_ne())
+                    .unwrap_or_else(|| ca.len().cmp(&cb.len()))
+            }),
+            (
+                Self::Error {
+                    code: ca,
+                    msg: ma,
+                    cause: xa,
+                    data: da,
+                },
+                Self::Error {
+                    code: cb,
+                    msg: mb,
+                    cause: xb,
+                    data: db,
+                },
+            ) => ca
+                .cmp(cb)
+                .then_with(|| ma.cmp(mb))
+                .then_with(|| cmp_cause(xa.as_ref(), xb.as_ref()))
+                .then_with(|| cmp_cause(da.as_ref(), db.as_ref())),
+            (Self::Handle(a), Self::Handle(b)) => a
+                .type_name()
+                .cmp(b.type_name())
+                .then_with(|| a.addr().cmp(&b.addr())),
+            _ => self.kind_rank().cmp(&other.kind_rank()),
+        }
+    }
+
+    fn kind_rank(&self) -> u8 {
+        match self {
+            Self::Null => 0,
+            Self::Bool(_) => 1,
+            Self::Num(_) | Self::Int(_) => 2,
+            Self::Str(_) => 3,
+            Self::Bytes(_) => 4,
+            Self::List(_) => 5,
+            Self::Obj(_) => 6,
+            Self::Func(_) => 7,
+            Self::Closure { .. } => 8,
+            Self::Error { .. } => 9,
+            Self::Handle(_) => 10,
+        }
+    }
+}
+
+fn cmp_num(a: f64, b: f64) -> Ordering {
+    match (a.is_nan(), b.is_nan()) {
+        (true, true) => Ordering::Equal,
+        (true, false) => Ordering::Greater,
+        (false, true) => Ordering::Less,
+        (false, false) => a.partial_cmp(&b).unwrap_or(Ordering::Equal),
+    }
+}
+
+fn cmp_cause(a: Option<&Rc<Value>>, b: Option<&Rc<Value>>) -> Ordering {
+    match (a, b) {
+        (Some(a), Some(b)) => a.total_cmp(b),
+        (a, b) => a.is_some().cmp(&b.is_some()),
+    }
+}
@@ -0,0 +1,133 @@
+use crate::RunStats;
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// Counters for one function, gathered with `VmConfig.profile`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FunctionStats {
+    pub module: Arc<str>,
+    pub function: Arc<str>,
+    /// Activations, tail calls included.
+    pub calls: u64,
+    /// Instructions the function executed itself, not counting its callees.
+    pub self_instructions: u64,
+    /// Time from entry to return, callees included. Recursive activations
+    /// count once, from the outermost entry.
+    pub inclusive_time: Duration,
+}
+
+impl RunStats {
+    /// One line per function, hottest (most inclusive time, then most
+    /// instructions) first, then the run's totals.
+    #[must_use]
+    pub fn report(&self) -> String {
+        let mut out = String::new();
+        let _ = writeln!(
+            out,
+            "{:<40} {:>8} {:>12} {:>12}",
+            "function", "calls", "self instrs", "inclusive"
+        );
+        for function in &self.functions {
+            let name = format!("{}/{}", function.module, function.function);
+            let _ = writeln!(
+                out,
+                "{name:<40} {:>8} {:>12} {:>10.3}ms",
+                function.calls,
+                function.self_instructions,
+                function.inclusive_time.as_secs_f64() * 1000.0
+            );
+        }
+        let _ = writeln!(
+            out,
+            "total: {} instructions in {:.3}ms",
+            self.instructions,
+            self.wall_time.as_secs_f64() * 1000.0
+        );
+        out
+    }
+}
+
+/// Per-function counters behind `RunStats.functions`; frames hold their
+/// function's slot from entry to return.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Profiler {
+    functions: Vec<FunctionStats>,
+    index: HashMap<Arc<str>, HashMap<Arc<str>, usize>>,
+    /// Per slot: activations on the stack, and when the outermost began.
+    open: Vec<(u32, Option<Instant>)>,
+}
+
+impl Profiler {
+    /// Counts a call to `module`'s `function`; returns its slot.
+    pub(crate) fn enter(&mut self, module: &Arc<str>, function: &Arc<str>, now: Instant) -> usize {
+        let known = self
+            .index
+            .get(module.as_ref())
+            .and_then(|by_name| by_name.get(function.as_ref()))
+            .copied();
+        let slot = if let Some(slot) = known {
+            slot
+        } else {
+            let slot = self.functions.len();
+            self.index
+                .entry(Arc::clone(module))
+                .or_default()
+                .insert(Arc::clone(function), slot);
+            self.functions.push(FunctionStats {
+                module: Arc::clone(module),
+                function: Arc::clone(function),
+                calls: 0,
+                self_instructions: 0,
+                inclusive_time: Duration::ZERO,
+            });
+            self.open.push((0, None));
+            slot
+        };
+        self.functions[slot].calls += 1;
+        let (depth, since) = &mut self.open[slot];
+        if *depth == 0 {
+            *since = Some(now);
+        }
+        *depth += 1;
+        slot
+    }
+
+    /// Ends an activation begun by [`Self::enter`].
+    pub(crate) fn leave(&mut self, slot: usize, now: Instant) {
+        let Some((depth, since)) = self.open.get_mut(slot) else {
+            return;
+        };
+        *depth = depth.saturating_sub(1);
+        if *depth == 0
+            && let Some(since) = since.take()
+        {
+            self.functions[slot].inclusive_time += now.saturating_duration_since(since);
+        }
+    }
+
+    pub(crate) fn count_instruction(&mut self, slot: usize) {
+        if let Some(function) = self.functions.get_mut(slot) {
+            function.self_instructions += 1;
+        }
+    }
+
+    /// The counters so far, hottest first. Functions still running report
+    /// the time up to `now`.
+    pub(crate) fn snapshot(&self, now: Instant) -> Vec<FunctionStats> {
+        let mut functions = self.functions.clone();
+        for (function, (_, since)) in functions.iter_mut().zip(&self.open) {
+            if let Some(since) = since {
+                function.inclusive_time += now.saturating_duration_since(*since);
+            }
+        }
+        functions.sort_by(|a, b| {
+            b.inclusive_time
+                .cmp(&a.inclusive_time)
+                .then(b.self_instructions.cmp(&a.self_instructions))
+                .then_with(|| (&a.module, &a.function).cmp(&(&b.module, &b.function)))
+        });
+        functions
+    }
+}
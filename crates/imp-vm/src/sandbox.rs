@@ -0,0 +1,81 @@
+use std::fmt;
+
+/// A side channel a host op can open onto the outside world.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Capability {
+    Print,
+    Time,
+    Random,
+    Fs,
+    Env,
+    Input,
+}
+
+impl fmt::Display for Capability {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Print => "print",
+            Self::Time => "time",
+            Self::Random => "random",
+            Self::Fs => "fs",
+            Self::Env => "env",
+            Self::Input => "input",
+        })
+    }
+}
+
+/// Which side channels `VmConfig.capabilities` leaves open. Every host op
+/// consults it before `VmConfig.host_policy`: `core::host::print`/`eprint`
+/// need `allow_print`, and `core::host::call` whatever capability the
+/// function was registered with (`HostFunctions::register_with`). A closed
+/// channel throws a catchable `cap_denied`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[allow(clippy::struct_excessive_bools)] // one switch per host channel
+pub struct Capabilities {
+    pub allow_print: bool,
+    pub allow_time: bool,
+    pub allow_random: bool,
+    pub allow_fs: bool,
+    pub allow_env: bool,
+    pub allow_input: bool,
+}
+
+impl Capabilities {
+    /// Everything closed, for running untrusted code deterministically.
+    #[must_use]
+    pub const fn sandbox() -> Self {
+        Self {
+            allow_print: false,
+            allow_time: false,
+            allow_random: false,
+            allow_fs: false,
+            allow_env: false,
+            allow_input: false,
+        }
+    }
+
+    #[must_use]
+    pub fn permits(&self, capability: Capability) -> bool {
+        match capability {
+            Capability::Print => self.allow_print,
+            Capability::Time => self.allow_time,
+            Capability::Random => self.allow_random,
+            Capability::Fs => self.allow_fs,
+            Capability::Env => self.allow_env,
+            Capability::Input => self.allow_input,
+        }
+    }
+}
+
+impl Default for Capabilities {
+    fn default() -> Self {
+        Self {
+            allow_print: true,
+            allow_time: true,
+            allow_random: true,
+            allow_fs: true,
+            allow_env: true,
+            allow_input: true,
+        }
+    }
+}
@@ -0,0 +1,268 @@
+//! Host-driven execution: [`Vm::start`] prepares a module's init and
+//! [`Vm::step`] or [`Vm::run_for`] runs it a bounded number of instructions
+//! at a time, so an embedder can spread a script over frames of its own loop.
+//! Breakpoints and [`Vm::paused_frames`] build a debugger on the same runs.
+
+use crate::call_stack::CallStack;
+use crate::profile::Profiler;
+use crate::{
+    Frame, RunResult, RunStats, TraceFrame, Value, Vm, VmError, global_deltas, module_exports,
+};
+use imp_ir::{CompiledModule, Instr, Slot};
+use std::fmt;
+use std::sync::Arc;
+use std::time::Instant;
+
+/// Where a [`Vm::step`] call left the run.
+#[derive(Debug, Clone)]
+pub enum StepOutcome {
+    /// The budget ran out first. `frames` is the call stack, innermost
+    /// first, each at the instruction it runs next.
+    Paused { frames: Vec<TraceFrame> },
+    /// The init returned and the run is over.
+    Finished(RunResult),
+}
+
+/// Instruction budget for one [`Vm::run_for`] call.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Steps(pub u64);
+
+/// Where a [`Vm::run_for`] call left the run.
+#[derive(Debug, Clone)]
+pub enum RunState {
+    Done(RunResult),
+    /// The budget ran out between two instructions; call `run_for` again
+    /// to continue.
+    Suspended,
+}
+
+/// Where [`Vm::step`] pauses a run, before the instruction there runs.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Breakpoint {
+    /// `pc` of the function named `function`.
+    At { function: Arc<str>, pc: usize },
+    /// The first instruction of `line` in functions compiled from `source`
+    /// (`None` for source compiled from a string).
+    Line { source: Option<Arc<str>>, line: u32 },
+    /// Every `Throw` and `Rethrow`, before the error is raised.
+    Throw,
+}
+
+impl Breakpoint {
+    fn hit(&self, frame: &Frame) -> bool {
+        let (meta, pc) = (&frame.meta, frame.pc);
+        match self {
+            Self::At { function, pc: at } => *at == pc && *function == meta.name,
+            Self::Throw => matches!(
+                frame.code.get(pc),
+                Some(Instr::Throw { .. } | Instr::Rethrow { .. })
+            ),
+            Self::Line { source, line } => meta.debug.as_deref().is_some_and(|debug| {
+                debug.source == *source
+                    && debug.line(pc) == Some(*line)
+                    && (pc == 0 || debug.line(pc - 1) != Some(*line))
+            }),
+        }
+    }
+}
+
+/// One frame of a paused run, as a debugger shows it.
+#[derive(Debug, Clone)]
+pub struct FrameInfo {
+    pub module: Arc<str>,
+    pub frame: TraceFrame,
+    /// The instruction at `frame.pc`, which runs next.
+    pub instr: Option<Instr>,
+    /// Parameter names; `params[i]` is `Slot::Arg(i)`.
+    pub params: Vec<Arc<str>>,
+    /// Every local, argument, error and return slot, in that order.
+    pub slots: Vec<(Slot, Value)>,
+}
+
+#[derive(Clone)]
+pub(crate) struct SteppedRun {
+    module: Arc<CompiledModule>,
+    stack: CallStack,
+    globals: Vec<Value>,
+    /// Linked globals, kept for `RunResult.global_deltas`.
+    before: Option<Vec<Value>>,
+    started: Instant,
+}
+
+impl fmt::Debug for SteppedRun {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SteppedRun")
+            .field("module", &self.module.name)
+            .field("frames", &self.stack.trace())
+            .finish_non_exhaustive()
+    }
+}
+
+impl Vm {
+    /// Links `module` (running its imports' inits to completion) and stops
+    /// before the first instruction of its init; advance it with
+    /// [`Self::step`]. A run started earlier and not yet finished is
+    /// dropped.
+    ///
+    /// # Errors
+    ///
+    /// A link failure or a failing import init.
+    pub fn start(&mut self, module: &CompiledModule) -> Result<(), VmError> {
+        if let Some(previous) = self.stepped.take() {
+            self.abandon_call_stack(previous.stack);
+        }
+        let started = self.env().monotonic();
+        self.stats = RunStats::default();
+        self.profiler = Profiler::default();
+        self.start_budget();
+        self.active_module = Some(module.clone());
+        let globals = self.build_module_globals(module)?;
+        let before = self.snapshot_globals(&globals);
+        let mut stack = CallStack::default();
+        self.push_call(
+            &mut stack,
+            module,
+            &globals,
+            module.init_func,
+            Vec::new(),
+            None,
+        )?;
+        self.stepped = Some(SteppedRun {
+            module: Arc::new(module.clone()),
+            stack,
+            globals,
+            before,
+            started,
+        });
+        Ok(())
+    }
+
+    /// Runs at most `n` instructions of the run begun by [`Self::start`],
+    /// counting every frame's instructions alike. An error ends the run as
+    /// it would end [`Self::run_main`].
+    ///
+    /// # Errors
+    ///
+    /// The error that ends the run, as [`Self::run_main`] returns it.
+    pub fn step(&mut self, n: u64) -> Result<StepOutcome, VmError> {
+        Ok(match self.advance(n)? {
+            Some(result) => StepOutcome::Finished(result),
+            None => StepOutcome::Paused {
+                frames: self
+                    .stepped
+                    .as_ref()
+                    .map(|run| run.stack.trace())
+                    .unwrap_or_default(),
+            },
+        })
+    }
+
+    /// Like [`Self::step`], without collecting the call stack on suspension;
+    /// meant to be called once per tick of a game loop, for every script.
+    ///
+    /// # Errors
+    ///
+    /// As [`Self::step`].
+    pub fn run_for(&mut self, budget: Steps) -> Result<RunState, VmError> {
+        Ok(match self.advance(budget.0)? {
+            Some(result) => RunState::Done(result),
+            None => RunState::Suspended,
+        })
+    }
+
+    /// Runs up to `n` instructions; `None` while the run is still going.
+    fn advance(&mut self, n: u64) -> Result<Option<RunResult>, VmError> {
+        let mut run = self
+            .stepped
+            .take()
+            .ok_or_else(|| VmError::runtime("no stepped run; call Vm::start first"))?;
+        self.step_budget = Some(n);
+        self.resuming = true;
+        self.run_depth += 1;
+        let outcome = self.resume_call_stack(&mut run.stack, &run.module, &mut run.globals);
+        self.run_depth -= 1;
+        self.step_budget = None;
+
+        let Some(returns) = outcome? else {
+            self.stepped = Some(run);
+            return Ok(None);
+        };
+        self.stats.wall_time = self
+            .env()
+            .monotonic()
+            .saturating_duration_since(run.started);
+        let stats = self.cfg.profile.then(|| self.stats());
+        Ok(Some(RunResult {
+            returns,
+            exports: module_exports(&run.module, &run.globals),
+            stats,
+            global_deltas: run
+                .before
+                .map(|before| global_deltas(&run.module, &before, &run.globals)),
+        }))
+    }
+
+    /// Whether a run begun by [`Self::start`] is waiting for [`Self::step`].
+    #[must_use]
+    pub fn is_stepping(&self) -> bool {
+        self.stepped.is_some()
+    }
+
+    /// Makes [`Self::step`] and [`Self::run_for`] pause before any
+    /// instruction a breakpoint names, even with budget left. The
+    /// instruction a run is paused at never stops it again, so stepping
+    /// on resumes past it. Nested runs (import inits, `invoke` from host
+    /// code) do not stop.
+    pub fn set_breakpoints(&mut self, breakpoints: Vec<Breakpoint>) {
+        self.breakpoints = breakpoints;
+    }
+
+    #[must_use]
+    pub fn breakpoints(&self) -> &[Breakpoint] {
+        &self.breakpoints
+    }
+
+    /// Whether the paused run's innermost frame is at a breakpoint.
+    #[must_use]
+    pub fn at_breakpoint(&self) -> bool {
+        self.stepped
+            .as_ref()
+            .and_then(|run| run.stack.activation(0, &run.module, &run.globals))
+            .is_some_and(|(frame, _, _)| self.at_breakpoint_in(frame))
+    }
+
+    pub(crate) fn at_breakpoint_in(&self, frame: &Frame) -> bool {
+        self.breakpoints
+            .iter()
+            .any(|breakpoint| breakpoint.hit(frame))
+    }
+
+    /// The paused run's call stack, innermost first; empty when no run is
+    /// waiting for [`Self::step`].
+    #[must_use]
+    pub fn paused_frames(&self) -> Vec<FrameInfo> {
+        let Some(run) = &self.stepped else {
+            return Vec::new();
+        };
+        (0..)
+            .map_while(|depth| run.stack.activation(depth, &run.module, &run.globals))
+            .map(|(frame, module, _)| FrameInfo {
+                module: Arc::clone(&module.name),
+                frame: TraceFrame::of(frame),
+                instr: frame.code.get(frame.pc).cloned(),
+                params: frame.meta.params.clone(),
+                slots: frame.slots(),
+            })
+            .collect()
+    }
+
+    /// `slot` of the paused frame `depth` below the innermost; globals are
+    /// those of the frame's module. `None` when there is no such frame or
+    /// slot.
+    #[must_use]
+    pub fn paused_slot(&self, depth: usize, slot: Slot) -> Option<Value> {
+        let run = self.stepped.as_ref()?;
+        let (frame, _, globals) = run.stack.activation(depth, &run.module, &run.globals)?;
+        frame.get(slot, globals).ok()
+    }
+}
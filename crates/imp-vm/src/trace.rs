@@ -0,0 +1,113 @@
+use crate::{DisplayOptions, Value};
+use imp_ir::{Instr, Slot};
+use std::fmt;
+use std::io::{self, Write};
+use std::sync::Mutex;
+
+/// One instruction about to run, as `VmConfig.trace` sees it.
+#[derive(Debug, Clone, Copy)]
+pub struct TraceEvent<'a> {
+    pub module: &'a str,
+    pub function: &'a str,
+    pub pc: usize,
+    pub instr: &'a Instr,
+    /// Each distinct slot `instr` names, with its value before it runs.
+    pub operands: &'a [(Slot, Value)],
+}
+
+/// Long values are cut short so one event stays one readable line.
+const OPERAND_DISPLAY: DisplayOptions = DisplayOptions {
+    max_depth: Some(2),
+    max_items: Some(8),
+    max_str_chars: Some(40),
+    indent: None,
+};
+
+/// `module/function@pc: Instr | L0=1 A0="x"`.
+impl fmt::Display for TraceEvent<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}/{}@{:04}: {:?}",
+            self.module, self.function, self.pc, self.instr
+        )?;
+        for (index, (slot, value)) in self.operands.iter().enumerate() {
+            let sep = if index == 0 { " | " } else { " " };
+            let (prefix, slot) = match slot {
+                Slot::Local(index) => ('L', index),
+                Slot::Global(index) => ('G', index),
+                Slot::Arg(index) => ('A', index),
+                Slot::Ret(index) => ('R', index),
+                Slot::Err(index) => ('E', index),
+            };
+            write!(
+                f,
+                "{sep}{prefix}{slot}={}",
+                value.display_with(OPERAND_DISPLAY)
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// Receives every instruction a run executes, interpreted or JIT-compiled.
+pub trait TraceSink: Send + Sync {
+    fn instruction(&self, event: &TraceEvent<'_>);
+}
+
+/// Writes each event as one line to any [`io::Write`].
+pub struct TraceWriter {
+    out: Mutex<Box<dyn Write + Send>>,
+}
+
+impl TraceWriter {
+    pub fn new(out: impl Write + Send + 'static) -> Self {
+        Self {
+            out: Mutex::new(Box::new(out)),
+        }
+    }
+
+    /// What `imp run --trace` uses.
+    #[must_use]
+    pub fn stderr() -> Self {
+        Self::new(io::stderr())
+    }
+}
+
+impl fmt::Debug for TraceWriter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TraceWriter").finish_non_exhaustive()
+    }
+}
+
+impl TraceSink for TraceWriter {
+    fn instruction(&self, event: &TraceEvent<'_>) {
+        if let Ok(mut out) = self.out.lock() {
+            // Tracing is best-effort; a closed pipe must not abort the program.
+            let _ = writeln!(out, "{event}");
+        }
+    }
+}
+
+/// In-memory sink; share it via `Arc` and read the lines back after a run.
+#[derive(Debug, Default)]
+pub struct TraceLog {
+    lines: Mutex<Vec<String>>,
+}
+
+impl TraceLog {
+    pub fn lines(&self) -> Vec<String> {
+        self.lines
+            .lock()
+            .map(|lines| lines.clone())
+            .unwrap_or_default()
+    }
+}
+
+impl TraceSink for TraceLog {
+    fn instruction(&self, event: &TraceEvent<'_>) {
+        if let Ok(mut lines) = self.lines.lock() {
+            lines.push(event.to_string());
+        }
+    }
+}
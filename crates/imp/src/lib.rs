@@ -0,0 +1,223 @@
+//! Embedding facade: compile, load and run imp modules from one crate.
+//!
+//! [`Module`] hides the compiled IR, so changes to instructions or module
+//! layout in the underlying crates do not break code written against this
+//! one. Values, configuration and errors are re-exported as they are;
+//! [`Value`], [`VmError`] and [`VmConfig`] are `#[non_exhaustive]`, so new
+//! variants and settings are not breaking changes. Start a config from
+//! [`VmConfig::default`] and set the fields you need.
+
+use imp_ir::CompiledModule;
+use std::fmt;
+use std::path::Path;
+use std::sync::Arc;
+
+pub use imp_bytecode::BytecodeError;
+pub use imp_compiler::{CompileError, CompileWarning};
+pub use imp_vm::{
+    CancelToken, Capabilities, Capability, ErrorDisposition, ExportError, GlobalDelta, HostFn,
+    HostFnError, HostFunctions, HostHandle, HostPolicy, ObjRef, RunResult, RunStats, TraceFrame,
+    Value, ValueKind, ValueTypeError, VmConfig, VmError,
+};
+
+/// A compiled module, ready to run or to save as `.impc` bytes.
+#[derive(Debug, Clone)]
+pub struct Module {
+    inner: Arc<CompiledModule>,
+}
+
+impl Module {
+    /// Compiles the `.imp` file at `path` and everything it imports.
+    ///
+    /// # Errors
+    ///
+    /// [`Error::Compile`] when the file, or one it imports, cannot be read or
+    /// does not compile.
+    pub fn compile_file(path: impl AsRef<Path>) -> Result<Self, Error> {
+        let module = imp_compiler::compile_module(path.as_ref(), &imp_compiler::FsModuleLoader)?;
+        Ok(Self::from(module))
+    }
+
+    /// Compiles a single source text named `main`; it cannot import files.
+    ///
+    /// # Errors
+    ///
+    /// [`Error::Compile`] when `src` does not compile.
+    pub fn compile_source(src: &str) -> Result<Self, Error> {
+        let program = imp_compiler::compile_program(src, imp_compiler::CompileOpts::default())?;
+        Ok(Self::from(program.module))
+    }
+
+    /// Loads `.impc` bytes written by [`Module::to_bytes`] or `imp build`.
+    ///
+    /// # Errors
+    ///
+    /// [`Error::Bytecode`] when `bytes` are not a module this version reads.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, Error> {
+        Ok(Self::from(imp_bytecode::decode_module(bytes)?))
+    }
+
+    /// The module as `.impc` bytes, imports embedded.
+    ///
+    /// # Errors
+    ///
+    /// [`Error::Bytecode`] when the module exceeds a limit of the format.
+    pub fn to_bytes(&self) -> Result<Vec<u8>, Error> {
+        Ok(imp_bytecode::encode_module(&self.inner)?)
+    }
+
+    #[must_use]
+    pub fn name(&self) -> &str {
+        &self.inner.name
+    }
+
+    /// Names of the module's exports, in declaration order.
+    pub fn exports(&self) -> impl Iterator<Item = &str> {
+        self.inner.exports.iter().map(|(name, _)| name.as_str())
+    }
+}
+
+impl From<CompiledModule> for Module {
+    fn from(module: CompiledModule) -> Self {
+        Self {
+            inner: Arc::new(module),
+        }
+    }
+}
+
+/// A VM that runs [`Module`]s.
+pub struct Runtime {
+    vm: imp_vm::Vm,
+}
+
+impl Runtime {
+    #[must_use]
+    pub fn new(config: VmConfig) -> Self {
+        Self {
+            vm: imp_vm::Vm::new(config),
+        }
+    }
+
+    /// Runs `module`'s init, returning its returns and exports.
+    ///
+    /// # Errors
+    ///
+    /// [`Error::Runtime`] when the init fails at runtime or throws uncaught.
+    pub fn run(&mut self, module: &Module) -> Result<RunResult, Error> {
+        Ok(self.vm.run_main(&module.inner)?)
+    }
+
+    /// Calls a function or closure value from the last [`Runtime::run`],
+    /// such as one of its exports.
+    ///
+    /// # Errors
+    ///
+    /// [`Error::NotCallable`] for any other value, and [`Error::Runtime`] when
+    /// the call fails or throws uncaught.
+    pub fn call(&mut self, func: &Value, args: &[Value]) -> Result<Vec<Value>, Error> {
+        let returns = match func {
+            Value::Func(id) => self.vm.invoke(*id, args)?,
+            Value::Closure { func, captures } => {
+                let args = captures.iter().chain(args).cloned().collect::<Vec<_>>();
+                self.vm.invoke(*func, &args)?
+            }
+            other => return Err(Error::NotCallable(other.type_name())),
+        };
+        Ok(returns)
+    }
+}
+
+impl Default for Runtime {
+    fn default() -> Self {
+        Self::new(VmConfig::default())
+    }
+}
+
+/// Anything that can go wrong between source text and a finished run.
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum Error {
+    Compile(CompileError),
+    Bytecode(BytecodeError),
+    Runtime(VmError),
+    /// [`Runtime::call`] was given a value of this type.
+    NotCallable(&'static str),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Compile(err) => write!(f, "{err}"),
+            Self::Bytecode(err) => write!(f, "{err}"),
+            Self::Runtime(err) => write!(f, "{err}"),
+            Self::NotCallable(type_name) => write!(f, "cannot call a {type_name}"),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Compile(err) => Some(err),
+            Self::Bytecode(err) => Some(err),
+            Self::Runtime(err) => Some(err),
+            Self::NotCallable(_) => None,
+        }
+    }
+}
+
+impl From<CompileError> for Error {
+    fn from(err: CompileError) -> Self {
+        Self::Compile(err)
+    }
+}
+
+impl From<BytecodeError> for Error {
+    fn from(err: BytecodeError) -> Self {
+        Self::Bytecode(err)
+    }
+}
+
+impl From<VmError> for Error {
+    fn from(err: VmError) -> Self {
+        Self::Runtime(err)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn compiles_round_trips_and_calls_exports() {
+        let module = Module::compile_source(
+            r#"
+#call core::fn::begin name=mod::double args="x" retshape="scalar";
+#call core::add a=arg::x b=arg::x out=return::value;
+#call core::exit;
+#call core::fn::end;
+#call core::mod::export name="double" value=mod::double;
+#call core::exit;
+"#,
+        )
+        .expect("compile");
+        assert_eq!(module.exports().collect::<Vec<_>>(), ["double"]);
+
+        let loaded = Module::from_bytes(&module.to_bytes().expect("encode")).expect("decode");
+        let mut runtime = Runtime::default();
+        let result = runtime.run(&loaded).expect("run");
+        let returns = runtime
+            .call(&result.exports["double"], &[Value::Num(21.0)])
+            .expect("call");
+        assert_eq!(returns, vec![Value::Num(42.0)]);
+
+        assert!(matches!(
+            runtime.call(&Value::Null, &[]),
+            Err(Error::NotCallable("null"))
+        ));
+        assert!(matches!(
+            Module::compile_source("#call core::jump target=\"nowhere\";\n"),
+            Err(Error::Compile(_))
+        ));
+    }
+}